@@ -0,0 +1,141 @@
+//! Alerts which flash the whole bar when a block enters a state
+//!
+//! When a block listed in `flash_on` enters the given state, the colors of the whole bar are
+//! inverted `flashes` times over about a second, and `command` is run. An alert is only triggered
+//! by the transition: while the block stays in the state nothing happens, and the alert is armed
+//! again once the block leaves it. A block which is hidden or shows an error is in no state.
+//!
+//! # Configuration
+//!
+//! Key        | Values                                                                    | Default
+//! -----------|---------------------------------------------------------------------------|--------
+//! `flash_on` | List of `{ block, state }` entries. `block` is a block name (e.g. `"battery"`) or identifier (e.g. `"temperature-0"`), `state` is `"idle"`, `"info"`, `"good"`, `"warning"` or `"critical"` | `[]`
+//! `flashes`  | How many times the bar flashes, at most 12                                | `3`
+//! `command`  | A shell command to run on every alert. `$I3RS_ALERT_BLOCK` is set to the identifier of the block. | None
+//!
+//! # Example
+//!
+//! ```toml
+//! [alerts]
+//! flash_on = [
+//!     { block = "temperature-0", state = "critical" },
+//!     { block = "battery", state = "critical" },
+//! ]
+//! command = "notify-send -u critical \"$I3RS_ALERT_BLOCK\""
+//! ```
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use smart_default::SmartDefault;
+
+use crate::widget::State;
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub flash_on: Vec<Trigger>,
+    #[default(3)]
+    pub flashes: u8,
+    pub command: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Trigger {
+    block: String,
+    state: State,
+}
+
+/// Detects the transitions which trigger alerts
+#[derive(Debug, Default)]
+pub struct Alerts {
+    /// The block ID and state of each trigger, and whether the block is in that state
+    triggers: Vec<(usize, State, bool)>,
+}
+
+impl Alerts {
+    /// `names` are the names of all blocks and `block_ids` maps their identifiers to block IDs
+    pub fn new(config: &Config, names: &[&str], block_ids: &HashMap<String, usize>) -> Self {
+        let mut triggers = Vec::new();
+        for trigger in &config.flash_on {
+            let len = triggers.len();
+            for (id, name) in names.iter().enumerate() {
+                if *name == trigger.block || block_ids.get(&trigger.block) == Some(&id) {
+                    triggers.push((id, trigger.state, false));
+                }
+            }
+            if triggers.len() == len {
+                log::warn!("Cannot alert on block '{}': no such block", trigger.block);
+            }
+        }
+        Self { triggers }
+    }
+
+    /// Handle the state of block `id` after it was rendered, `None` if it is hidden or shows an
+    /// error. Returns whether an alert is triggered.
+    pub fn block_rendered(&mut self, id: usize, state: Option<State>) -> bool {
+        let mut triggered = false;
+        for (block, alert_state, active) in &mut self.triggers {
+            if *block != id {
+                continue;
+            }
+            let matches = state == Some(*alert_state);
+            triggered |= matches && !*active;
+            *active = matches;
+        }
+        triggered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alerts(flash_on: &str) -> Alerts {
+        let config: Config = toml::from_str(flash_on).unwrap();
+        let names = ["temperature", "battery", "temperature"];
+        Alerts::new(&config, &names, &crate::imports::block_ids(&names))
+    }
+
+    #[test]
+    fn transitions() {
+        let mut alerts = alerts(r#"flash_on = [{ block = "temperature-1", state = "critical" }]"#);
+        assert!(!alerts.block_rendered(2, Some(State::Warning)));
+        assert!(alerts.block_rendered(2, Some(State::Critical)));
+        // Other blocks with the same name are not affected
+        assert!(!alerts.block_rendered(0, Some(State::Critical)));
+        assert!(!alerts.block_rendered(1, Some(State::Critical)));
+    }
+
+    #[test]
+    fn suppression() {
+        let mut alerts = alerts(r#"flash_on = [{ block = "battery", state = "critical" }]"#);
+        assert!(alerts.block_rendered(1, Some(State::Critical)));
+        // The condition persists
+        assert!(!alerts.block_rendered(1, Some(State::Critical)));
+        assert!(!alerts.block_rendered(1, Some(State::Critical)));
+        // It clears and triggers again
+        assert!(!alerts.block_rendered(1, Some(State::Warning)));
+        assert!(alerts.block_rendered(1, Some(State::Critical)));
+        // Errors and hidden blocks clear it as well
+        assert!(!alerts.block_rendered(1, None));
+        assert!(alerts.block_rendered(1, Some(State::Critical)));
+    }
+
+    #[test]
+    fn names() {
+        let mut alerts = alerts(
+            r#"flash_on = [
+                { block = "temperature", state = "critical" },
+                { block = "temperature-0", state = "warning" },
+                { block = "cpu", state = "critical" },
+            ]"#,
+        );
+        assert!(alerts.block_rendered(0, Some(State::Critical)));
+        assert!(alerts.block_rendered(2, Some(State::Critical)));
+        assert!(alerts.block_rendered(0, Some(State::Warning)));
+        assert!(!alerts.block_rendered(2, Some(State::Warning)));
+        assert!(toml::from_str::<Config>(r#"flash_on = [{ block = "cpu" }]"#).is_err());
+    }
+}
@@ -0,0 +1,323 @@
+//! Transitions which are shown when a block appears or disappears, and flashes of the whole bar
+//!
+//! A transition is a few intermediate frames which are printed in between the bar's regular
+//! updates. The render cache always holds the true state of each block, the frames only replace it
+//! while printing. A block whose output changes again stops its transition right away.
+//!
+//! A flash inverts the colors of the whole bar on some frames, on top of any transitions.
+
+use serde::Deserialize;
+use smart_default::SmartDefault;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::themes::color::Color;
+
+/// The number of intermediate frames of a transition
+const FRAMES: usize = 4;
+/// The time between two frames, so that a transition takes about 150ms
+const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+/// The number of frames of a flash, so that it takes a second
+const FLASH_FRAMES: usize = 25;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, SmartDefault)]
+#[serde(rename_all = "lowercase")]
+pub enum Transition {
+    #[default]
+    None,
+    /// Blend the block's colors from or to the background
+    Fade,
+    /// Grow or shrink the block's text
+    Collapse,
+}
+
+impl Transition {
+    /// The intermediate frames of a block which appears or disappears, in the order they are
+    /// shown. The block's final state is not included.
+    fn frames(
+        self,
+        segments: &[I3BarBlock],
+        appearing: bool,
+        background: Color,
+    ) -> VecDeque<Vec<I3BarBlock>> {
+        if self == Self::None || segments.is_empty() {
+            return VecDeque::new();
+        }
+        (1..=FRAMES)
+            .map(|i| {
+                // How much of the block is visible, never 0 or 1
+                let visible =
+                    if appearing { i } else { FRAMES + 1 - i } as f64 / (FRAMES + 1) as f64;
+                segments
+                    .iter()
+                    .map(|segment| self.frame(segment, visible, background))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn frame(self, segment: &I3BarBlock, visible: f64, background: Color) -> I3BarBlock {
+        let mut segment = segment.clone();
+        match self {
+            Self::None => (),
+            Self::Fade => {
+                let text_background = match segment.background {
+                    Color::None | Color::Auto => background,
+                    color => color,
+                };
+                segment.color = text_background.blend(segment.color, visible);
+                segment.background = background.blend(segment.background, visible);
+            }
+            Self::Collapse => {
+                let markup = segment.markup.as_deref() == Some("pango");
+                segment.full_text = truncate(&segment.full_text, visible, markup);
+                segment.short_text = truncate(&segment.short_text, visible, markup);
+            }
+        }
+        segment
+    }
+}
+
+/// Keep the `visible` fraction of `text`. Text with markup is kept as is, since cutting it could
+/// break the markup.
+fn truncate(text: &str, visible: f64, markup: bool) -> String {
+    if markup && text.contains(['<', '&']) {
+        return text.into();
+    }
+    let keep = (text.chars().count() as f64 * visible).round() as usize;
+    text.chars().take(keep).collect()
+}
+
+/// Swap the text and background colors of `segment`. Unset colors are taken from the theme's
+/// `fg` and `bg`.
+pub fn invert(segment: &mut I3BarBlock, fg: Color, bg: Color) {
+    let or = |color, default| match color {
+        Color::None | Color::Auto => default,
+        color => color,
+    };
+    let color = or(segment.color, fg);
+    segment.color = or(segment.background, bg);
+    segment.background = color;
+}
+
+/// Whether each frame of a flash is inverted
+fn flash_frames(flashes: u8) -> VecDeque<bool> {
+    // Each flash needs at least one inverted and one regular frame
+    let flashes = usize::from(flashes).min(FLASH_FRAMES / 2);
+    if flashes == 0 {
+        return VecDeque::new();
+    }
+    // `usize::is_multiple_of` needs a newer compiler than the rest of the tree
+    #[allow(clippy::manual_is_multiple_of)]
+    (0..FLASH_FRAMES)
+        .map(|i| i * 2 * flashes / FLASH_FRAMES % 2 == 0)
+        .collect()
+}
+
+/// The transitions and flashes which are currently shown
+#[derive(Debug)]
+pub struct Animations {
+    transition: Transition,
+    /// The remaining frames of each animated block. The first frame is the one shown.
+    blocks: Vec<(usize, VecDeque<Vec<I3BarBlock>>)>,
+    /// The remaining frames of the flash
+    flash: VecDeque<bool>,
+    next_frame: Option<Instant>,
+}
+
+impl Animations {
+    /// Transitions are disabled if their frames would be printed faster than `max_fps`
+    pub fn new(transition: Transition, max_fps: Option<f64>) -> Self {
+        let fps = 1.0 / FRAME_INTERVAL.as_secs_f64();
+        Self {
+            transition: match max_fps {
+                Some(max_fps) if max_fps < fps => Transition::None,
+                _ => transition,
+            },
+            blocks: Vec::new(),
+            flash: VecDeque::new(),
+            next_frame: None,
+        }
+    }
+
+    /// Handle a change of block `id`'s output from `old` to `new`
+    pub fn block_changed(
+        &mut self,
+        id: usize,
+        old: &[I3BarBlock],
+        new: &[I3BarBlock],
+        background: Color,
+    ) {
+        self.blocks.retain(|(block, _)| *block != id);
+        let frames = match (old.is_empty(), new.is_empty()) {
+            (true, false) => self.transition.frames(new, true, background),
+            (false, true) => self.transition.frames(old, false, background),
+            _ => return,
+        };
+        if !frames.is_empty() {
+            self.blocks.push((id, frames));
+            self.next_frame
+                .get_or_insert_with(|| Instant::now() + FRAME_INTERVAL);
+        }
+    }
+
+    /// Flash the whole bar `flashes` times, replacing a flash which is still running
+    pub fn flash(&mut self, flashes: u8) {
+        self.flash = flash_frames(flashes);
+        if !self.flash.is_empty() {
+            self.next_frame
+                .get_or_insert_with(|| Instant::now() + FRAME_INTERVAL);
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.blocks.is_empty() || !self.flash.is_empty()
+    }
+
+    /// Whether the colors of the whole bar are inverted in the current frame
+    pub fn is_inverted(&self) -> bool {
+        self.flash.front() == Some(&true)
+    }
+
+    /// The frame which replaces block `id`'s output, if it is animated
+    pub fn frame(&self, id: usize) -> Option<&[I3BarBlock]> {
+        self.blocks
+            .iter()
+            .find(|(block, _)| *block == id)
+            .and_then(|(_, frames)| frames.front())
+            .map(Vec::as_slice)
+    }
+
+    /// Wait until the next frame is due and advance to it
+    pub async fn next_frame(&mut self) {
+        if let Some(next_frame) = self.next_frame {
+            tokio::time::sleep_until(next_frame.into()).await;
+        }
+        self.advance();
+    }
+
+    fn advance(&mut self) {
+        for (_, frames) in &mut self.blocks {
+            frames.pop_front();
+        }
+        self.blocks.retain(|(_, frames)| !frames.is_empty());
+        self.flash.pop_front();
+        self.next_frame = self.is_running().then(|| Instant::now() + FRAME_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::themes::color::Rgba;
+
+    fn segment(text: &str) -> I3BarBlock {
+        I3BarBlock {
+            full_text: text.into(),
+            color: Color::Rgba(Rgba::from_hex(0xffffffff)),
+            ..Default::default()
+        }
+    }
+
+    fn texts(frames: &VecDeque<Vec<I3BarBlock>>) -> Vec<&str> {
+        frames.iter().map(|f| f[0].full_text.as_str()).collect()
+    }
+
+    #[test]
+    fn collapse() {
+        let block = [segment("abcdefghij")];
+        let frames = Transition::Collapse.frames(&block, true, Color::None);
+        assert_eq!(texts(&frames), ["ab", "abcd", "abcdef", "abcdefgh"]);
+        let frames = Transition::Collapse.frames(&block, false, Color::None);
+        assert_eq!(texts(&frames), ["abcdefgh", "abcdef", "abcd", "ab"]);
+
+        let mut markup = segment("<b>bold</b>");
+        markup.markup = Some("pango".into());
+        let frames = Transition::Collapse.frames(&[markup], false, Color::None);
+        assert!(texts(&frames).iter().all(|t| *t == "<b>bold</b>"));
+    }
+
+    #[test]
+    fn fade() {
+        let black = Color::Rgba(Rgba::from_hex(0x000000ff));
+        let frames = Transition::Fade.frames(&[segment("x")], false, black);
+        let reds: Vec<_> = frames
+            .iter()
+            .map(|f| match f[0].color {
+                Color::Rgba(c) => c.r,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(reds, [204, 153, 102, 51]);
+        assert!(frames.iter().all(|f| f[0].background == Color::None));
+
+        // Without a background color there is nothing to blend to
+        let frames = Transition::Fade.frames(&[segment("x")], true, Color::None);
+        assert!(frames.iter().all(|f| f[0] == segment("x")));
+
+        assert!(Transition::None
+            .frames(&[segment("x")], true, black)
+            .is_empty());
+    }
+
+    #[test]
+    fn animations() {
+        let block = [segment("abcdefghij")];
+        let mut animations = Animations::new(Transition::Collapse, None);
+        assert!(!animations.is_running());
+
+        animations.block_changed(1, &[], &block, Color::None);
+        assert_eq!(animations.frame(1).unwrap()[0].full_text, "ab");
+        assert_eq!(animations.frame(0), None);
+        for _ in 1..FRAMES {
+            animations.advance();
+        }
+        assert_eq!(animations.frame(1).unwrap()[0].full_text, "abcdefgh");
+        animations.advance();
+        assert!(!animations.is_running());
+        assert_eq!(animations.frame(1), None);
+
+        // Other changes of the output end the transition right away
+        animations.block_changed(1, &block, &[], Color::None);
+        assert!(animations.is_running());
+        animations.block_changed(1, &[], &[segment("x")], Color::None);
+        animations.block_changed(1, &[segment("x")], &block, Color::None);
+        assert!(!animations.is_running());
+
+        let mut animations = Animations::new(Transition::Collapse, Some(10.0));
+        animations.block_changed(1, &[], &block, Color::None);
+        assert!(!animations.is_running());
+    }
+
+    #[test]
+    fn flash() {
+        let frames = flash_frames(3);
+        assert_eq!(frames.len(), FLASH_FRAMES);
+        let flashes = frames
+            .iter()
+            .zip(frames.iter().skip(1))
+            .filter(|(a, b)| !**a && **b)
+            .count();
+        assert_eq!(flashes + 1, 3);
+        assert!(frames[0] && !frames[FLASH_FRAMES - 1]);
+        assert_eq!(flash_frames(255).iter().filter(|f| **f).count(), 13);
+        assert!(flash_frames(0).is_empty());
+
+        let mut animations = Animations::new(Transition::None, None);
+        animations.flash(1);
+        assert!(animations.is_running() && animations.is_inverted());
+        for _ in 0..FLASH_FRAMES - 1 {
+            animations.advance();
+        }
+        assert!(animations.is_running() && !animations.is_inverted());
+        animations.advance();
+        assert!(!animations.is_running());
+
+        let white = Color::Rgba(Rgba::from_hex(0xffffffff));
+        let black = Color::Rgba(Rgba::from_hex(0x000000ff));
+        let mut inverted = segment("x");
+        invert(&mut inverted, Color::None, black);
+        assert_eq!((inverted.color, inverted.background), (black, white));
+    }
+}
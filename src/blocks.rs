@@ -1,21 +1,26 @@
 //! The collection of blocks
 
+mod packages_util;
 pub mod prelude;
 
 use crate::BoxedFuture;
 use futures::future::FutureExt;
 use serde::Deserialize;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
 use std::borrow::Cow;
 use std::future::Future;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::click::MouseButton;
 use crate::config::SharedConfig;
 use crate::errors::*;
+use crate::icons::Icon;
+use crate::subprocess::CommandContext;
 use crate::widget::Widget;
-use crate::{Request, RequestCmd};
+use crate::{BlockStatus, Request, RequestCmd};
 
 macro_rules! define_blocks {
     {
@@ -51,12 +56,12 @@ macro_rules! define_blocks {
             }
 
             pub fn run(self, api: CommonApi) -> BlockFuture {
-                let id = api.id;
+                let id = api.id.clone();
                 match self {
                     $(
                         $(#[cfg($attr)])?
                         Self::$block { config } => {
-                            $block::run(config, api).map(move |e| e.in_block(stringify!($block), id)).boxed_local()
+                            $block::run(config, api).map(move |e| e.in_block(stringify!($block), id.get())).boxed_local()
                         }
                     )*
                 }
@@ -66,21 +71,30 @@ macro_rules! define_blocks {
 }
 
 define_blocks!(
+    acpi,
     apt,
     backlight,
     battery,
     bluetooth,
+    calendar,
+    ci,
+    clipboard,
     cpu,
     custom,
     custom_dbus,
     disk_space,
+    display_scale,
+    dns_blocker,
     dnf,
     docker,
     external_ip,
     focused_window,
     github,
+    hosts,
     hueshift,
+    inhibitors,
     kdeconnect,
+    keylocks,
     load,
     #[cfg(feature = "maildir")]
     maildir,
@@ -88,27 +102,52 @@ define_blocks!(
     memory,
     music,
     net,
+    netmount,
     notify,
     #[cfg(feature = "notmuch")]
     notmuch,
     nvidia_gpu,
     pacman,
+    peripherals,
     pomodoro,
+    removable,
     rofication,
+    scratchpad,
+    screen_time,
     sound,
+    spacer,
     speedtest,
+    summary,
+    sway_mode,
     keyboard_layout,
     taskwarrior,
     temperature,
     time,
     tea_timer,
     toggle,
+    torrents,
+    #[cfg(feature = "totp")]
+    totp,
     uptime,
     watson,
     weather,
     xrandr,
 );
 
+impl BlockConfig {
+    /// A resource which the block controls, such as a sound device. Two blocks which control the
+    /// same resource fight each other, which is detected at startup. Blocks which only read what
+    /// they show return `None`.
+    pub fn resource(&self) -> Option<String> {
+        match self {
+            Self::backlight { config } => Some(config.resource()),
+            Self::hueshift { config } => Some(config.resource()),
+            Self::sound { config } => Some(config.resource()),
+            _ => None,
+        }
+    }
+}
+
 pub type BlockFuture = BoxedFuture<Result<()>>;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -117,14 +156,127 @@ pub enum BlockEvent {
     UpdateRequest,
 }
 
+/// The sending half of a block's event queue.
+///
+/// Update requests are coalesced: while one is waiting to be received by the block, further
+/// requests are no-ops. This way a burst of signals or clicks results in a single update.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    /// Events, along with how many times an action was triggered at once
+    sender: mpsc::UnboundedSender<(BlockEvent, u32)>,
+    update_pending: Arc<AtomicBool>,
+    activity: Activity,
+}
+
+impl EventSender {
+    pub fn new(
+        sender: mpsc::UnboundedSender<(BlockEvent, u32)>,
+        update_pending: Arc<AtomicBool>,
+        activity: Activity,
+    ) -> Self {
+        Self {
+            sender,
+            update_pending,
+            activity,
+        }
+    }
+
+    /// Trigger `action` `count` times at once, e.g. for a burst of scrolling
+    pub fn send_action(&self, action: Cow<'static, str>, count: u32) {
+        let _ = self.sender.send((BlockEvent::Action(action), count));
+    }
+
+    pub fn request_update(&self) {
+        if !self.update_pending.swap(true, Ordering::SeqCst) {
+            let _ = self.sender.send((BlockEvent::UpdateRequest, 1));
+        }
+    }
+
+    /// How long the block has not been waiting for events, e.g. because it is updating. Blocks
+    /// which never waited for an event are not considered busy.
+    pub fn busy_for(&self) -> Option<Duration> {
+        self.activity.busy_since().map(|since| since.elapsed())
+    }
+}
+
+/// Whether a block is waiting for events, shared by the bar and the block's future to detect
+/// blocks which hang, e.g. in an update
+#[derive(Debug, Clone, Default)]
+pub struct Activity(Arc<Mutex<Option<Instant>>>);
+
+impl Activity {
+    fn busy_since(&self) -> Option<Instant> {
+        *self.0.lock().unwrap()
+    }
+
+    fn set_waiting(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    fn set_busy(&self) {
+        self.0.lock().unwrap().get_or_insert_with(Instant::now);
+    }
+}
+
+/// Marks the block as busy when the future waiting for an event completes or is cancelled
+struct BusyOnDrop(Activity);
+
+impl Drop for BusyOnDrop {
+    fn drop(&mut self) {
+        self.0.set_busy();
+    }
+}
+
+/// The ID of a removed block. It differs from [`power_warnings::BLOCK_ID`], so that requests of
+/// removed blocks are not mistaken for the power warning's.
+///
+/// [`power_warnings::BLOCK_ID`]: crate::power_warnings::BLOCK_ID
+const REMOVED_ID: usize = usize::MAX - 1;
+
+/// The ID of a block, i.e. its position in the bar. It is shared by the bar and the block's future,
+/// because reloading the configuration moves the blocks which are kept.
+#[derive(Debug, Clone)]
+pub struct BlockId(Arc<AtomicUsize>);
+
+impl BlockId {
+    pub fn new(id: usize) -> Self {
+        Self(Arc::new(AtomicUsize::new(id)))
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, id: usize) {
+        self.0.store(id, Ordering::Relaxed);
+    }
+
+    /// Mark the block as removed. Its ID no longer refers to any block.
+    pub fn remove(&self) {
+        self.set(REMOVED_ID);
+    }
+}
+
 pub struct CommonApi {
-    pub id: usize,
+    pub id: BlockId,
     pub shared_config: SharedConfig,
-    pub event_receiver: mpsc::Receiver<BlockEvent>,
+    pub event_receiver: mpsc::UnboundedReceiver<(BlockEvent, u32)>,
+    pub update_pending: Arc<AtomicBool>,
+    pub activity: Activity,
+    /// How many times the action last received by [`CommonApi::event`] was triggered at once.
+    /// This is always `1` unless the block called [`CommonApi::coalesce_actions`].
+    pub action_count: u32,
+    /// Whether a burst of the same action is received once, see [`CommonApi::coalesce_actions`]
+    pub coalesce_actions: bool,
+    /// An action of a burst which is yet to be received, and how many times
+    pub repeated_action: Option<(Cow<'static, str>, u32)>,
 
     pub request_sender: mpsc::Sender<Request>,
+    /// The name and status of every block, updated whenever one is rendered
+    pub block_statuses: watch::Receiver<Vec<(&'static str, BlockStatus)>>,
 
     pub error_interval: Duration,
+    pub command_context: CommandContext,
 }
 
 impl CommonApi {
@@ -132,7 +284,7 @@ impl CommonApi {
     pub async fn set_widget(&self, widget: &Widget) -> Result<()> {
         self.request_sender
             .send(Request {
-                block_id: self.id,
+                block_id: self.id.get(),
                 cmd: RequestCmd::SetWidget(widget.clone()),
             })
             .await
@@ -143,7 +295,7 @@ impl CommonApi {
     pub async fn hide(&self) -> Result<()> {
         self.request_sender
             .send(Request {
-                block_id: self.id,
+                block_id: self.id.get(),
                 cmd: RequestCmd::UnsetWidget,
             })
             .await
@@ -154,7 +306,7 @@ impl CommonApi {
     pub async fn set_error(&self, error: Error) -> Result<()> {
         self.request_sender
             .send(Request {
-                block_id: self.id,
+                block_id: self.id.get(),
                 cmd: RequestCmd::SetError(error),
             })
             .await
@@ -167,17 +319,62 @@ impl CommonApi {
     ) -> Result<()> {
         self.request_sender
             .send(Request {
-                block_id: self.id,
+                block_id: self.id.get(),
                 cmd: RequestCmd::SetDefaultActions(actions),
             })
             .await
             .error("Failed to send Request")
     }
 
+    /// Don't update the block when all blocks are asked to with SIGUSR1, e.g. because updating
+    /// is expensive. The block's own `signal` still asks it to update.
+    pub async fn ignore_usr1(&self) -> Result<()> {
+        self.request_sender
+            .send(Request {
+                block_id: self.id.get(),
+                cmd: RequestCmd::IgnoreUsr1,
+            })
+            .await
+            .error("Failed to send Request")
+    }
+
+    /// Request other blocks to update. Blocks are referenced either by their name (e.g. `sound`),
+    /// which refers to all blocks of this type, or by their identifier (e.g. `sound-1`, see
+    /// [`imports`](crate::imports)).
+    pub async fn update_blocks(&self, blocks: Vec<String>) -> Result<()> {
+        self.request_sender
+            .send(Request {
+                block_id: self.id.get(),
+                cmd: RequestCmd::UpdateBlocks(blocks),
+            })
+            .await
+            .error("Failed to send Request")
+    }
+
+    /// Show only the given blocks for `duration`, e.g. blocks which may be scrolled out of view on
+    /// narrow screens
+    pub async fn reveal(&self, blocks: Vec<usize>, duration: Duration) -> Result<()> {
+        self.request_sender
+            .send(Request {
+                block_id: self.id.get(),
+                cmd: RequestCmd::Reveal(blocks, duration),
+            })
+            .await
+            .error("Failed to send Request")
+    }
+
+    /// Receive a burst of the same action (e.g. from scrolling fast) once, with
+    /// [`CommonApi::action_count`] set to its length, instead of once for each click. Blocks which
+    /// step a value by a lot at once should call this, and step it `action_count` times.
+    pub fn coalesce_actions(&mut self) {
+        self.coalesce_actions = true;
+    }
+
     /// Receive the next event, such as click notification or update request.
     ///
-    /// This method should be called regularly to avoid sender blocking. Currently, the runtime is
-    /// single threaded, so full channel buffer will cause a deadlock. If receiving events is
+    /// Events are queued until they are received, so clicks made while the block is busy (e.g.
+    /// waiting for a slow update) are not lost and never block the bar. Update requests are not
+    /// queued more than once. If receiving events is
     /// impossible / meaningless, call `event_receiver.close()`.
     ///
     /// # Cancel safety
@@ -200,8 +397,30 @@ impl CommonApi {
     /// }
     /// ```
     pub async fn event(&mut self) -> BlockEvent {
+        self.activity.set_waiting();
+        let _busy = BusyOnDrop(self.activity.clone());
+        if let Some((action, remaining)) = self.repeated_action.take() {
+            if remaining > 1 {
+                self.repeated_action = Some((action.clone(), remaining - 1));
+            }
+            self.action_count = 1;
+            return BlockEvent::Action(action);
+        }
         match self.event_receiver.recv().await {
-            Some(event) => event,
+            Some((BlockEvent::UpdateRequest, _)) => {
+                // Requests made from now on must be delivered again
+                self.update_pending.store(false, Ordering::SeqCst);
+                BlockEvent::UpdateRequest
+            }
+            Some((BlockEvent::Action(action), count)) if !self.coalesce_actions && count > 1 => {
+                self.repeated_action = Some((action.clone(), count - 1));
+                self.action_count = 1;
+                BlockEvent::Action(action)
+            }
+            Some((event, count)) => {
+                self.action_count = count;
+                event
+            }
             None => panic!("events stream ended"),
         }
     }
@@ -231,9 +450,11 @@ impl CommonApi {
         while self.event().await != BlockEvent::UpdateRequest {}
     }
 
-    pub fn get_icon(&self, icon: &str) -> Result<String> {
+    /// An icon for [`Value::icon`](crate::formatting::value::Value::icon). If the icon has
+    /// variants for some states, the widget shows the variant for its state.
+    pub fn get_icon(&self, icon: &str) -> Result<Icon> {
         self.shared_config
-            .get_icon(icon)
+            .get_stateful_icon(icon)
             .or_error(|| format!("Icon '{icon}' not found"))
     }
 
@@ -267,3 +488,198 @@ impl CommonApi {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_api() -> (EventSender, CommonApi) {
+        let (event_sender, event_receiver) = mpsc::unbounded_channel();
+        let (request_sender, _request_receiver) = mpsc::channel(1);
+        let update_pending = Arc::new(AtomicBool::new(false));
+        let activity = Activity::default();
+        let api = CommonApi {
+            id: BlockId::new(0),
+            shared_config: SharedConfig::default(),
+            event_receiver,
+            update_pending: update_pending.clone(),
+            activity: activity.clone(),
+            action_count: 1,
+            coalesce_actions: false,
+            repeated_action: None,
+            request_sender,
+            block_statuses: watch::channel(Vec::new()).1,
+            error_interval: Duration::from_secs(5),
+            command_context: CommandContext::default(),
+        };
+        (
+            EventSender::new(event_sender, update_pending, activity),
+            api,
+        )
+    }
+
+    #[test]
+    fn burst() {
+        let (event_sender, mut api) = test_api();
+        tokio_test::block_on(async {
+            // Blocks receive each action of a burst
+            event_sender.send_action(Cow::Borrowed("up"), 3);
+            event_sender.request_update();
+            for _ in 0..3 {
+                assert_eq!(api.event().await, BlockEvent::Action("up".into()));
+                assert_eq!(api.action_count, 1);
+            }
+            assert_eq!(api.event().await, BlockEvent::UpdateRequest);
+
+            // Unless they step by the whole burst at once
+            api.coalesce_actions();
+            event_sender.send_action(Cow::Borrowed("up"), 3);
+            event_sender.request_update();
+            assert_eq!(api.event().await, BlockEvent::Action("up".into()));
+            assert_eq!(api.action_count, 3);
+            assert_eq!(api.event().await, BlockEvent::UpdateRequest);
+        });
+    }
+
+    #[test]
+    fn removed_id() {
+        let id = BlockId::new(3);
+        id.remove();
+        assert_ne!(id.get(), crate::power_warnings::BLOCK_ID);
+    }
+
+    #[test]
+    fn events_are_queued_while_busy() {
+        let (event_sender, mut api) = test_api();
+
+        // The block does not receive events while these are sent, e.g. because it is in the
+        // middle of an update. Sending must neither block nor drop events.
+        for i in 0..1000 {
+            event_sender.send_action(Cow::Owned(i.to_string()), 1);
+        }
+        event_sender.request_update();
+
+        tokio_test::block_on(async {
+            for i in 0..1000 {
+                assert_eq!(
+                    api.event().await,
+                    BlockEvent::Action(Cow::Owned(i.to_string()))
+                );
+            }
+            assert_eq!(api.event().await, BlockEvent::UpdateRequest);
+        });
+    }
+
+    #[test]
+    fn update_requests_are_coalesced() {
+        let (event_sender, mut api) = test_api();
+
+        for _ in 0..10_000 {
+            event_sender.request_update();
+        }
+        event_sender.send_action(Cow::Borrowed("click"), 1);
+        for _ in 0..10_000 {
+            event_sender.request_update();
+        }
+
+        tokio_test::block_on(async {
+            assert_eq!(api.event().await, BlockEvent::UpdateRequest);
+            assert_eq!(
+                api.event().await,
+                BlockEvent::Action(Cow::Borrowed("click"))
+            );
+            assert!(api.event_receiver.try_recv().is_err());
+
+            // Once the pending request is received, the next one is delivered again
+            event_sender.request_update();
+            event_sender.request_update();
+            assert_eq!(api.event().await, BlockEvent::UpdateRequest);
+            assert!(api.event_receiver.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn signal_and_click_during_update() {
+        let (event_sender, mut api) = test_api();
+
+        tokio_test::block_on(async {
+            // A block which takes a while to update, and counts updates and clicks
+            let block = async {
+                let mut updates = 0;
+                let mut clicks = 0;
+                loop {
+                    updates += 1;
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    loop {
+                        tokio::select! {
+                            event = api.event() => match event {
+                                BlockEvent::UpdateRequest => break,
+                                BlockEvent::Action(_) => clicks += 1,
+                            },
+                            _ = tokio::time::sleep(Duration::from_millis(100)) => {
+                                return (updates, clicks);
+                            }
+                        }
+                    }
+                }
+            };
+
+            // Signals and a click while the first update is in flight
+            let bar = async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                event_sender.request_update();
+                event_sender.send_action(Cow::Borrowed("click"), 1);
+                event_sender.request_update();
+                event_sender.request_update();
+            };
+
+            let ((updates, clicks), ()) = tokio::join!(block, bar);
+            // Exactly one follow-up update, and the click is not lost
+            assert_eq!(updates, 2);
+            assert_eq!(clicks, 1);
+        });
+    }
+
+    #[test]
+    fn hung_update_is_detected() {
+        let (event_sender, mut api) = test_api();
+        // Blocks which never waited for an event, e.g. while starting, are not busy
+        assert_eq!(event_sender.busy_for(), None);
+
+        tokio_test::block_on(async {
+            // A block whose update never finishes
+            let block = async {
+                loop {
+                    if api.event().await == BlockEvent::UpdateRequest {
+                        std::future::pending::<()>().await;
+                    }
+                }
+            };
+
+            let bar = async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                assert_eq!(event_sender.busy_for(), None);
+
+                // Clicks are handled right away
+                event_sender.send_action(Cow::Borrowed("click"), 1);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                assert_eq!(event_sender.busy_for(), None);
+
+                event_sender.request_update();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let busy = event_sender.busy_for().unwrap();
+                assert!(busy >= Duration::from_millis(50));
+
+                // The block stays busy however many events are queued
+                event_sender.send_action(Cow::Borrowed("click"), 1);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                assert!(event_sender.busy_for().unwrap() > busy);
+            };
+
+            tokio::select! {
+                () = block => unreachable!(),
+                () = bar => (),
+            }
+        });
+    }
+}
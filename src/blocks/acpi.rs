@@ -0,0 +1,420 @@
+//! ACPI events
+//!
+//! Shows the state of the laptop lid and the AC adapter, and updates other blocks when ACPI
+//! events arrive.
+//!
+//! Events are read from the socket of [acpid](https://sourceforge.net/projects/acpid2/). If acpid
+//! is not running, the kernel's netlink ACPI event source is used instead. Note that some events,
+//! e.g. headphone jack events, are only reported by acpid. You can check which events your
+//! machine reports with `acpi_listen`. If the event source is lost, the block reconnects with an
+//! increasing delay of up to one minute.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $ac_icon $lid_icon "`
+//! `socket_path` | Path to the acpid socket. Supports path expansions e.g. `~`. | `"/run/acpid.socket"`
+//! `triggers` | A list of triggers, see below | `[]`
+//!
+//! Each trigger is a table with the following keys:
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `event` | The event to react to. Matches all events which start with these space separated fields, e.g. `"jack/headphone"` matches both `"jack/headphone HEADPHONE plug"` and `"jack/headphone HEADPHONE unplug"`. | **Required**
+//! `update_blocks` | The blocks to update. A block is referenced either by its name (e.g. `"sound"`), which refers to all blocks of this type, or by its name and its index among the blocks of the same type (e.g. `"sound-1"` for the second `sound` block). | **Required**
+//!
+//! Placeholder  | Value                                            | Type | Unit
+//! -------------|--------------------------------------------------|------|-----
+//! `ac_icon`    | An icon showing whether the AC adapter is online | Icon | -
+//! `ac`         | Present if the AC adapter is online              | Flag | -
+//! `lid_icon`   | An icon showing whether the lid is closed        | Icon | -
+//! `lid`        | `open` or `closed`                               | Text | -
+//! `lid_closed` | Present if the lid is closed                     | Flag | -
+//!
+//! The `ac` placeholders are absent if the machine has no AC adapter, and the `lid` placeholders
+//! are absent if it has no lid. Set `format = ""` to use this block for its triggers only.
+//!
+//! # Examples
+//!
+//! Update the `sound` block as soon as headphones are plugged in or out:
+//!
+//! ```toml
+//! [[block]]
+//! block = "acpi"
+//! format = ""
+//! triggers = [
+//!   { event = "jack/headphone", update_blocks = ["sound"] },
+//! ]
+//! ```
+//!
+//! Show when the laptop runs on battery:
+//!
+//! ```toml
+//! [[block]]
+//! block = "acpi"
+//! format = " $ac_icon {$ac online|on battery} "
+//! triggers = [
+//!   { event = "ac_adapter", update_blocks = ["battery-0"] },
+//! ]
+//! ```
+//!
+//! # Icons Used
+//! - `ac_online`, `ac_offline`
+//! - `lid_open`, `lid_closed`
+
+use super::prelude::*;
+use crate::util::read_file;
+use neli::consts::socket::NlFamily;
+use neli::genl::Genlmsghdr;
+use neli::nl::NlPayload;
+use neli::socket::{tokio::NlSocket, NlSocketHandle};
+use std::path::Path;
+use tokio::fs::read_dir;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::net::UnixStream;
+
+const LID_PATH: &str = "/proc/acpi/button/lid";
+const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+
+/// The generic netlink family and multicast group of ACPI events
+const ACPI_FAMILY: &str = "acpi_event";
+const ACPI_MCAST_GROUP: &str = "acpi_mc_group";
+const ACPI_GENL_ATTR_EVENT: u16 = 1;
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    format: FormatConfig,
+    #[default("/run/acpid.socket".into())]
+    socket_path: ShellString,
+    triggers: Vec<Trigger>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Trigger {
+    event: String,
+    update_blocks: Vec<String>,
+}
+
+impl Trigger {
+    fn matches(&self, event: &Event) -> bool {
+        let mut fields = self.event.split_whitespace();
+        let first = match fields.next() {
+            Some(first) => first,
+            None => return false,
+        };
+        let (class, subclass) = split_class(first);
+        let mut values = event.values.iter();
+        class == event.class
+            && subclass == event.subclass
+            && fields.all(|field| values.next() == Some(&field))
+    }
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    let mut widget = Widget::new().with_format(config.format.with_default(" $ac_icon $lid_icon ")?);
+    let socket_path = config.socket_path.expand()?;
+    let socket_path = Path::new(&*socket_path);
+
+    let mut source = None;
+    let mut reconnect_delay = MIN_RECONNECT_DELAY;
+    let mut state = Status::current().await;
+
+    loop {
+        let ac_icon = match state.ac {
+            Some(true) => Some(api.get_icon("ac_online")?),
+            Some(false) => Some(api.get_icon("ac_offline")?),
+            None => None,
+        };
+        let lid_icon = match state.lid_closed {
+            Some(true) => Some(api.get_icon("lid_closed")?),
+            Some(false) => Some(api.get_icon("lid_open")?),
+            None => None,
+        };
+        widget.set_values(map! {
+            [if let Some(icon) = ac_icon] "ac_icon" => Value::icon(icon),
+            [if state.ac == Some(true)] "ac" => Value::flag(),
+            [if let Some(icon) = lid_icon] "lid_icon" => Value::icon(icon),
+            [if let Some(closed) = state.lid_closed]
+                "lid" => Value::text(if closed { "closed" } else { "open" }.into()),
+            [if state.lid_closed == Some(true)] "lid_closed" => Value::flag(),
+        });
+        api.set_widget(&widget).await?;
+
+        select! {
+            lines = next_events(&mut source, socket_path, &mut reconnect_delay) => {
+                let mut update_blocks = Vec::new();
+                let mut update_state = false;
+                for line in &lines {
+                    let event = match Event::parse(line) {
+                        Some(event) => event,
+                        None => continue,
+                    };
+                    update_state |= matches!(event.class, "button" | "ac_adapter");
+                    for trigger in config.triggers.iter().filter(|t| t.matches(&event)) {
+                        update_blocks.extend(trigger.update_blocks.iter().cloned());
+                    }
+                }
+                if !update_blocks.is_empty() {
+                    api.update_blocks(update_blocks).await?;
+                }
+                if update_state {
+                    state = Status::current().await;
+                }
+            }
+            _ = api.wait_for_update_request() => state = Status::current().await,
+        }
+    }
+}
+
+/// An ACPI event, e.g. `button/lid LID close` or `ac_adapter ACPI0003:00 00000080 00000001`
+#[derive(Debug, PartialEq, Eq)]
+struct Event<'a> {
+    /// e.g. `button`
+    class: &'a str,
+    /// e.g. `lid`
+    subclass: Option<&'a str>,
+    /// The remaining space separated fields
+    values: Vec<&'a str>,
+}
+
+impl<'a> Event<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let (class, subclass) = split_class(fields.next()?);
+        Some(Self {
+            class,
+            subclass,
+            values: fields.collect(),
+        })
+    }
+}
+
+enum EventSource {
+    Acpid(Lines<BufReader<UnixStream>>),
+    Netlink(NlSocket, Vec<u8>),
+}
+
+impl EventSource {
+    async fn connect(socket_path: &Path) -> Result<Self> {
+        match UnixStream::connect(socket_path).await {
+            Ok(stream) => Ok(Self::Acpid(BufReader::new(stream).lines())),
+            Err(_) => {
+                let mut handle = NlSocketHandle::connect(NlFamily::Generic, None, &[])
+                    .error("Failed to open netlink socket")?;
+                let group = handle
+                    .resolve_nl_mcast_group(ACPI_FAMILY, ACPI_MCAST_GROUP)
+                    .error("Neither acpid nor the netlink ACPI event source are available")?;
+                handle
+                    .add_mcast_membership(&[group])
+                    .error("Failed to subscribe to ACPI events")?;
+                let socket = NlSocket::new(handle).error("Failed to open netlink socket")?;
+                Ok(Self::Netlink(socket, Vec::new()))
+            }
+        }
+    }
+
+    /// Receive the next events. Returns `None` if the source was lost.
+    async fn next(&mut self) -> Option<Vec<String>> {
+        match self {
+            Self::Acpid(lines) => lines.next_line().await.ok().flatten().map(|l| vec![l]),
+            Self::Netlink(socket, buf) => {
+                let msgs = socket.recv::<u16, Genlmsghdr<u8, u16>>(buf).await.ok()?;
+                Some(
+                    msgs.into_iter()
+                        .filter_map(|msg| match msg.nl_payload {
+                            NlPayload::Payload(genl) => genl
+                                .get_attr_handle()
+                                .get_attribute(ACPI_GENL_ATTR_EVENT)
+                                .and_then(|attr| parse_netlink_event(attr.nla_payload.as_ref())),
+                            _ => None,
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+/// Wait for the next events, (re)connecting to the event source if needed
+///
+/// # Cancel safety
+///
+/// This function is cancel safe.
+async fn next_events(
+    source: &mut Option<EventSource>,
+    socket_path: &Path,
+    reconnect_delay: &mut Duration,
+) -> Vec<String> {
+    loop {
+        match source {
+            Some(s) => match s.next().await {
+                Some(lines) => {
+                    *reconnect_delay = MIN_RECONNECT_DELAY;
+                    return lines;
+                }
+                None => *source = None,
+            },
+            None => match EventSource::connect(socket_path).await {
+                Ok(s) => *source = Some(s),
+                Err(_) => {
+                    sleep(*reconnect_delay).await;
+                    *reconnect_delay = (*reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            },
+        }
+    }
+}
+
+/// Split `<class>/<subclass>`
+fn split_class(field: &str) -> (&str, Option<&str>) {
+    match field.split_once('/') {
+        Some((class, subclass)) => (class, Some(subclass)),
+        None => (field, None),
+    }
+}
+
+/// Convert a `struct acpi_genl_event` to a line in the format used by acpid
+fn parse_netlink_event(payload: &[u8]) -> Option<String> {
+    // struct acpi_genl_event {
+    //     char device_class[20];
+    //     char bus_id[15];
+    //     u32 type;
+    //     u32 data;
+    // };
+    fn c_str(bytes: &[u8]) -> Option<&str> {
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        std::str::from_utf8(&bytes[..len]).ok()
+    }
+    let u32_at = |offset: usize| -> Option<u32> {
+        Some(u32::from_ne_bytes(
+            payload.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    let device_class = c_str(payload.get(..20)?)?;
+    let bus_id = c_str(payload.get(20..35)?)?;
+    let event_type = u32_at(36)?;
+    let data = u32_at(40)?;
+    Some(format!(
+        "{device_class} {bus_id} {event_type:08x} {data:08x}"
+    ))
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Status {
+    /// `None` if there is no AC adapter
+    ac: Option<bool>,
+    /// `None` if there is no lid
+    lid_closed: Option<bool>,
+}
+
+impl Status {
+    async fn current() -> Self {
+        Self {
+            ac: read_ac().await,
+            lid_closed: read_lid().await,
+        }
+    }
+}
+
+async fn read_ac() -> Option<bool> {
+    let mut entries = read_dir(POWER_SUPPLY_PATH).await.ok()?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if read_file(path.join("type")).await.ok().as_deref() == Some("Mains") {
+            return Some(read_file(path.join("online")).await.ok()? == "1");
+        }
+    }
+    None
+}
+
+async fn read_lid() -> Option<bool> {
+    let mut entries = read_dir(LID_PATH).await.ok()?;
+    let entry = entries.next_entry().await.ok()??;
+    parse_lid_state(&read_file(entry.path().join("state")).await.ok()?)
+}
+
+/// Parse the contents of `/proc/acpi/button/lid/*/state`. Returns whether the lid is closed.
+fn parse_lid_state(text: &str) -> Option<bool> {
+    match text.strip_prefix("state:")?.trim() {
+        "open" => Some(false),
+        "closed" => Some(true),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events() {
+        assert_eq!(
+            Event::parse("button/lid LID close"),
+            Some(Event {
+                class: "button",
+                subclass: Some("lid"),
+                values: vec!["LID", "close"],
+            })
+        );
+        assert_eq!(
+            Event::parse("ac_adapter ACPI0003:00 00000080 00000001"),
+            Some(Event {
+                class: "ac_adapter",
+                subclass: None,
+                values: vec!["ACPI0003:00", "00000080", "00000001"],
+            })
+        );
+        assert_eq!(
+            Event::parse("jack/headphone HEADPHONE plug\n"),
+            Some(Event {
+                class: "jack",
+                subclass: Some("headphone"),
+                values: vec!["HEADPHONE", "plug"],
+            })
+        );
+        assert_eq!(Event::parse(""), None);
+    }
+
+    #[test]
+    fn triggers() {
+        let trigger = |event: &str| Trigger {
+            event: event.into(),
+            update_blocks: vec!["sound".into()],
+        };
+        let plug = Event::parse("jack/headphone HEADPHONE plug").unwrap();
+        let lid = Event::parse("button/lid LID close").unwrap();
+        assert!(trigger("jack/headphone HEADPHONE plug").matches(&plug));
+        assert!(trigger("jack/headphone").matches(&plug));
+        assert!(!trigger("jack/headphone HEADPHONE unplug").matches(&plug));
+        assert!(!trigger("jack").matches(&plug));
+        assert!(!trigger("jack/headphone").matches(&lid));
+        assert!(!trigger("button/lid LID close now").matches(&lid));
+    }
+
+    #[test]
+    fn netlink_event() {
+        let mut payload = vec![0; 44];
+        payload[..10].copy_from_slice(b"ac_adapter");
+        payload[20..31].copy_from_slice(b"ACPI0003:00");
+        payload[36..40].copy_from_slice(&0x80u32.to_ne_bytes());
+        payload[40..44].copy_from_slice(&1u32.to_ne_bytes());
+        assert_eq!(
+            parse_netlink_event(&payload).as_deref(),
+            Some("ac_adapter ACPI0003:00 00000080 00000001")
+        );
+        assert_eq!(parse_netlink_event(&payload[..40]), None);
+    }
+
+    #[test]
+    fn lid_state() {
+        assert_eq!(parse_lid_state("state:      open"), Some(false));
+        assert_eq!(parse_lid_state("state:      closed"), Some(true));
+        assert_eq!(parse_lid_state("foo"), None);
+    }
+}
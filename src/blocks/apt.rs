@@ -14,13 +14,18 @@
 //! `format_up_to_date` | Same as `format`, but for when no updates are available. | `" $icon $count.eng(w:1) "`
 //! `warning_updates_regex` | Display block as warning if updates matching regex are available. | `None`
 //! `critical_updates_regex` | Display block as critical if updates matching regex are available. | `None`
-//! `ignore_phased_updates` | Doesn't include potentially held back phased updates in the count. | `false`
+//! `ignore_updates_regex` | Updates matching regex are neither counted nor matched against `warning_updates_regex` and `critical_updates_regex`, e.g. for held packages. | `None`
+//! `ignore_phased_updates` | Doesn't include Ubuntu phased updates which `apt-get upgrade` defers, in the count or when matching the regexes. | `false`
 //!
 //! Placeholder | Value                       | Type   | Unit
 //! ------------|-----------------------------|--------|------
 //! `icon`      | A static icon               | Icon   | -
 //! `count`     | Number of updates available | Number | -
 //!
+//! Action   | Description                                       | Default button
+//! ---------|---------------------------------------------------|---------------
+//! `update` | Check for updates now, showing `…` while it runs  | Left
+//!
 //! # Example
 //!
 //! Update the list of pending updates every thirty minutes (1800 seconds):
@@ -53,8 +58,8 @@ use std::process::Stdio;
 use regex::Regex;
 
 use tokio::fs::{create_dir_all, File};
-use tokio::process::Command;
 
+use super::packages_util::UpdateRegexes;
 use super::prelude::*;
 
 #[derive(Deserialize, Debug, SmartDefault)]
@@ -67,10 +72,14 @@ pub struct Config {
     format_up_to_date: FormatConfig,
     warning_updates_regex: Option<String>,
     critical_updates_regex: Option<String>,
+    ignore_updates_regex: Option<String>,
     ignore_phased_updates: bool,
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[(MouseButton::Left, None, "update")])
+        .await?;
+
     let mut widget = Widget::new();
 
     let format = config.format.with_default(" $icon $count.eng(w:1) ")?;
@@ -81,18 +90,18 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         .format_up_to_date
         .with_default(" $icon $count.eng(w:1) ")?;
 
-    let warning_updates_regex = config
-        .warning_updates_regex
-        .as_deref()
-        .map(Regex::new)
-        .transpose()
-        .error("invalid warning updates regex")?;
-    let critical_updates_regex = config
-        .critical_updates_regex
+    let regexes = UpdateRegexes::new(
+        config.warning_updates_regex.as_deref(),
+        config.critical_updates_regex.as_deref(),
+    )?;
+    let ignore_updates_regex = config
+        .ignore_updates_regex
         .as_deref()
         .map(Regex::new)
         .transpose()
-        .error("invalid critical updates regex")?;
+        .error("invalid ignore updates regex")?;
+
+    let commands = apt_context(&api.command_context);
 
     let mut cache_dir = env::temp_dir();
     cache_dir.push("i3rs-apt");
@@ -124,8 +133,23 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         .error("Failed to write to config file")?;
 
     loop {
-        let updates = get_updates_list(config_file).await?;
-        let count = get_update_count(config_file, config.ignore_phased_updates, &updates).await?;
+        // Checking takes a while, so show that it is running
+        api.set_widget(
+            &Widget::new()
+                .with_text(" \u{2026} ".into())
+                .with_state(widget.state),
+        )
+        .await?;
+
+        let updates = get_updates_list(&commands, config_file).await?;
+        let simulation = if config.ignore_phased_updates {
+            simulate_upgrade(&commands, config_file).await?
+        } else {
+            String::new()
+        };
+        let phased = phased_updates(&simulation);
+        let updates = relevant_updates(&updates, ignore_updates_regex.as_ref(), &phased);
+        let count = updates.len();
 
         widget.set_format(match count {
             0 => format_up_to_date.clone(),
@@ -137,36 +161,31 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             "icon" => Value::icon(api.get_icon("update")?)
         ));
 
-        let warning = warning_updates_regex
-            .as_ref()
-            .map_or(false, |regex| has_matching_update(&updates, regex));
-        let critical = critical_updates_regex
-            .as_ref()
-            .map_or(false, |regex| has_matching_update(&updates, regex));
-        widget.state = match count {
-            0 => State::Idle,
-            _ => {
-                if critical {
-                    State::Critical
-                } else if warning {
-                    State::Warning
-                } else {
-                    State::Info
-                }
-            }
-        };
+        widget.state = regexes.state(count, updates.iter().copied());
 
         api.set_widget(&widget).await?;
 
-        select! {
-            _ = sleep(config.interval.0) => (),
-            _ = api.wait_for_update_request() => (),
+        loop {
+            select! {
+                _ = sleep(config.interval.0) => break,
+                event = api.event() => match event {
+                    UpdateRequest => break,
+                    Action(a) if a == "update" => break,
+                    _ => (),
+                }
+            }
         }
     }
 }
 
-async fn get_updates_list(config_path: &str) -> Result<String> {
-    Command::new("apt")
+/// The output of apt is only parsed correctly if it is not localized
+fn apt_context(context: &CommandContext) -> CommandContext {
+    context.with_default_env("LC_ALL", "C")
+}
+
+async fn get_updates_list(commands: &CommandContext, config_path: &str) -> Result<String> {
+    commands
+        .command("apt")
         .env("APT_CONFIG", config_path)
         .args(["update"])
         .stdout(Stdio::null())
@@ -176,7 +195,8 @@ async fn get_updates_list(config_path: &str) -> Result<String> {
         .wait()
         .await
         .error("Failed to run `apt update`")?;
-    let stdout = Command::new("apt")
+    let stdout = commands
+        .command("apt")
         .env("APT_CONFIG", config_path)
         .args(["list", "--upgradable"])
         .output()
@@ -186,45 +206,123 @@ async fn get_updates_list(config_path: &str) -> Result<String> {
     String::from_utf8(stdout).error("apt produced non-UTF8 output")
 }
 
-async fn get_update_count(
-    config_path: &str,
-    ignore_phased_updates: bool,
-    updates: &str,
-) -> Result<usize> {
-    let mut cnt = 0;
+/// Simulate `apt-get upgrade`, which lists the phased updates it defers
+async fn simulate_upgrade(commands: &CommandContext, config_path: &str) -> Result<String> {
+    let stdout = commands
+        .command("apt-get")
+        .env("APT_CONFIG", config_path)
+        .args(["-s", "upgrade"])
+        .output()
+        .await
+        .error("Problem running apt-get command")?
+        .stdout;
+    String::from_utf8(stdout).error("apt-get produced non-UTF8 output")
+}
 
-    for update_line in updates.lines().filter(|line| line.contains("[upgradable")) {
-        if !ignore_phased_updates || !is_phased_update(config_path, update_line).await? {
-            cnt += 1;
-        }
-    }
+/// The packages whose updates are deferred due to phasing in the output of `apt-get -s upgrade`
+fn phased_updates(simulation: &str) -> Vec<&str> {
+    simulation
+        .lines()
+        .skip_while(|line| !line.starts_with("The following upgrades have been deferred"))
+        .skip(1)
+        .take_while(|line| line.starts_with(' '))
+        .flat_map(str::split_whitespace)
+        .collect()
+}
 
-    Ok(cnt)
+/// The lines of `apt list --upgradable` which are neither ignored nor phased
+fn relevant_updates<'a>(updates: &'a str, ignore: Option<&Regex>, phased: &[&str]) -> Vec<&'a str> {
+    upgradable(updates)
+        .filter(|line| !ignore.is_some_and(|regex| regex.is_match(line)))
+        .filter(|line| {
+            let package = line.split('/').next().unwrap_or_default();
+            !phased.contains(&package)
+        })
+        .collect()
 }
 
-fn has_matching_update(updates: &str, regex: &Regex) -> bool {
-    updates.lines().any(|line| regex.is_match(line))
+fn upgradable(updates: &str) -> impl Iterator<Item = &str> {
+    updates.lines().filter(|line| line.contains("[upgradable"))
 }
 
-async fn is_phased_update(config_path: &str, package_line: &str) -> Result<bool> {
-    let package_name_regex = regex!(r#"(.*)/.*"#);
-    let package_name = &package_name_regex
-        .captures(package_line)
-        .error("Couldn't find package name")?[1];
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::packages_util::has_matching_update;
 
-    let output = String::from_utf8(
-        Command::new("apt-cache")
-            .args(["-c", config_path, "policy", package_name])
-            .output()
-            .await
-            .error("Problem running apt-cache command")?
-            .stdout,
-    )
-    .error("Problem capturing apt-cache command output")?;
-
-    let phased_regex = regex!(r#".*\(phased (\d+)%\).*"#);
-    Ok(match phased_regex.captures(&output) {
-        Some(matches) => &matches[1] != "100",
-        None => false,
-    })
+    #[test]
+    fn localized_output() {
+        // `apt list --upgradable` with `LANG=de_DE.UTF-8`
+        let localized = "Auflistung… Fertig
+firefox/jammy-updates 120.0+build2-0ubuntu0.22.04.1 amd64 [aktualisierbar von: 119.0+build2-0ubuntu0.22.04.1]
+";
+        assert_eq!(upgradable(localized).count(), 0);
+
+        let c = "Listing... Done
+firefox/jammy-updates 120.0+build2-0ubuntu0.22.04.1 amd64 [upgradable from: 119.0+build2-0ubuntu0.22.04.1]
+";
+        assert_eq!(upgradable(c).count(), 1);
+
+        let lc_all = |context: &CommandContext| {
+            let apt = apt_context(context).command("apt");
+            apt.as_std()
+                .get_envs()
+                .find(|(key, _)| *key == "LC_ALL")
+                .and_then(|(_, value)| value.map(|v| v.to_string_lossy().into_owned()))
+        };
+        assert_eq!(lc_all(&CommandContext::default()).as_deref(), Some("C"));
+        // `command_env` takes precedence
+        let context =
+            CommandContext::new(HashMap::from([("LC_ALL".into(), "C.UTF-8".into())]), None);
+        assert_eq!(lc_all(&context).as_deref(), Some("C.UTF-8"));
+    }
+
+    #[test]
+    fn phased() {
+        let updates = "Listing... Done
+firefox/jammy-updates 120.0+build2-0ubuntu0.22.04.1 amd64 [upgradable from: 119.0+build2-0ubuntu0.22.04.1]
+libsystemd0/jammy-updates 249.11-0ubuntu3.12 amd64 [upgradable from: 249.11-0ubuntu3.11]
+systemd/jammy-updates 249.11-0ubuntu3.12 amd64 [upgradable from: 249.11-0ubuntu3.11]
+linux-generic/jammy-updates 5.15.0.91.88 amd64 [upgradable from: 5.15.0.89.86]
+";
+        let simulation = "Reading package lists... Done
+Building dependency tree... Done
+Reading state information... Done
+Calculating upgrade... Done
+The following packages have been kept back:
+  linux-generic
+The following upgrades have been deferred due to phasing:
+  libsystemd0 systemd
+The following packages will be upgraded:
+  firefox
+1 upgraded, 0 newly installed, 0 to remove and 3 not upgraded.
+Inst firefox [119.0+build2-0ubuntu0.22.04.1] (120.0+build2-0ubuntu0.22.04.1 Ubuntu:22.04/jammy-updates [amd64])
+";
+        let phased = phased_updates(simulation);
+        assert_eq!(phased, ["libsystemd0", "systemd"]);
+        assert_eq!(relevant_updates(updates, None, &[]).len(), 4);
+
+        let relevant = relevant_updates(updates, None, &phased);
+        assert_eq!(relevant.len(), 2);
+        // Phased updates don't set the state either
+        assert!(!has_matching_update(
+            relevant.iter().copied(),
+            &Regex::new("systemd").unwrap()
+        ));
+
+        let held = Regex::new("^linux-").unwrap();
+        let relevant = relevant_updates(updates, Some(&held), &phased);
+        assert_eq!(relevant.len(), 1);
+        assert!(relevant[0].starts_with("firefox/"));
+        assert!(!has_matching_update(
+            relevant.iter().copied(),
+            &Regex::new("linux").unwrap()
+        ));
+
+        // Without phasing, `apt-get` says nothing about it
+        assert!(
+            phased_updates("0 upgraded, 0 newly installed, 0 to remove and 0 not upgraded.\n")
+                .is_empty()
+        );
+    }
 }
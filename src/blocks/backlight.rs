@@ -21,6 +21,7 @@
 //! `cycle` | The brightnesses to cycle through on each click | `[minimum, maximum]`
 //! `root_scaling` | Scaling exponent reciprocal (ie. root) | `1.0`
 //! `invert_icons` | Invert icons' ordering, useful if you have colorful emoji | `false`
+//! `invert_brightness_control` | Swap the `brightness_up` and `brightness_down` actions, useful if scrolling feels backwards | `false`
 //!
 //! Placeholder  | Value                                     | Type   | Unit
 //! -------------|-------------------------------------------|--------|---------------
@@ -130,9 +131,22 @@ pub struct Config {
     #[default(1.0)]
     root_scaling: f64,
     invert_icons: bool,
+    invert_brightness_control: bool,
+}
+
+impl Config {
+    /// The device controlled by the block, see [`BlockConfig::resource`](super::BlockConfig::resource)
+    pub fn resource(&self) -> String {
+        match &self.device {
+            Some(device) => format!("{DEVICES_PATH}/{device}"),
+            // The first device, whichever it is
+            None => format!("{DEVICES_PATH}/*"),
+        }
+    }
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.coalesce_actions();
     api.set_default_actions(&[
         (MouseButton::Left, None, "cycle"),
         (MouseButton::WheelUp, None, "brightness_up"),
@@ -164,13 +178,10 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
 
     loop {
         let brightness = device.brightness().await?;
-        let mut icon_index = (usize::from(brightness) * BACKLIGHT_ICONS.len()) / 101;
-        if config.invert_icons {
-            icon_index = BACKLIGHT_ICONS.len() - icon_index - 1;
-        }
+        let icon = BACKLIGHT_ICONS[icon_index(brightness, config.invert_icons)];
 
         widget.set_values(map! {
-            "icon" => Value::icon(api.get_icon(BACKLIGHT_ICONS[icon_index])?),
+            "icon" => Value::icon(api.get_icon(icon)?),
             "brightness" => Value::percents(brightness)
         });
         api.set_widget(&widget).await?;
@@ -184,16 +195,18 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                             device.set_brightness(brightness).await?;
                         }
                     }
-                    Action(a) if a == "brightness_up" => {
-                        device.set_brightness(
-                            (brightness + config.step_width) .clamp(config.minimum, config.maximum)
-                        ).await?;
-                    }
-                    Action(a) if a == "brightness_down" => {
+                    Action(a) if a == "brightness_up" || a == "brightness_down" => {
+                        let up = (a == "brightness_up") != config.invert_brightness_control;
+                        let step = config
+                            .step_width
+                            .saturating_mul(api.action_count.try_into().unwrap_or(u8::MAX));
+                        let brightness = if up {
+                            brightness.saturating_add(step)
+                        } else {
+                            brightness.saturating_sub(step)
+                        };
                         device.set_brightness(
-                            brightness
-                                .saturating_sub(config.step_width)
-                                .clamp(config.minimum, config.maximum)
+                            brightness.clamp(config.minimum, config.maximum)
                         ).await?;
                     }
                     _ => (),
@@ -203,6 +216,17 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     }
 }
 
+/// The index of the icon in `BACKLIGHT_ICONS` for a brightness in percent
+fn icon_index(brightness: u8, invert: bool) -> usize {
+    let index =
+        (usize::from(brightness) * BACKLIGHT_ICONS.len() / 101).min(BACKLIGHT_ICONS.len() - 1);
+    if invert {
+        BACKLIGHT_ICONS.len() - index - 1
+    } else {
+        index
+    }
+}
+
 /// Read a brightness value from the given path.
 async fn read_brightness_raw(device_file: &Path) -> Result<u64> {
     let val = match read_file(device_file).await {
@@ -295,3 +319,25 @@ impl BacklightDevice {
             .error("Failed to send D-Bus message")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn icons() {
+        let last = BACKLIGHT_ICONS.len() - 1;
+        assert_eq!(icon_index(0, false), 0);
+        assert_eq!(icon_index(6, false), 0);
+        assert_eq!(icon_index(7, false), 1);
+        assert_eq!(icon_index(94, false), last - 1);
+        assert_eq!(icon_index(95, false), last);
+        assert_eq!(icon_index(100, false), last);
+        assert_eq!(icon_index(255, false), last);
+
+        assert_eq!(icon_index(0, true), last);
+        assert_eq!(icon_index(100, true), 0);
+        assert_eq!(icon_index(255, true), 0);
+        assert_eq!(icon_index(50, true), last - icon_index(50, false));
+    }
+}
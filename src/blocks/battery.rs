@@ -78,7 +78,7 @@ use crate::util::battery_level_icon;
 
 mod apc_ups;
 mod sysfs;
-mod upower;
+pub(crate) mod upower;
 
 // make_log_macro!(debug, "battery");
 
@@ -81,9 +81,16 @@ impl Device {
 
         let mut matching_battery = None;
 
-        let mut sysfs_dir = read_dir(POWER_SUPPLY_DEVICES_PATH)
-            .await
-            .error("failed to read /sys/class/power_supply direcory")?;
+        let mut sysfs_dir = match read_dir(POWER_SUPPLY_DEVICES_PATH).await {
+            Ok(dir) => dir,
+            // Some systems (e.g. desktops and containers) have no power supply class at all,
+            // which simply means that there is no battery
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                debug!("{} does not exist", POWER_SUPPLY_DEVICES_PATH);
+                return Ok(None);
+            }
+            Err(err) => return Err(err).error("failed to read /sys/class/power_supply direcory"),
+        };
         while let Some(dir) = sysfs_dir
             .next_entry()
             .await
@@ -6,6 +6,9 @@ use super::{BatteryDevice, BatteryInfo, BatteryStatus, DeviceName};
 use crate::blocks::prelude::*;
 use crate::util::new_system_dbus_connection;
 
+/// The device which represents the whole power supply of the machine
+pub(crate) const DISPLAY_DEVICE: &str = "/org/freedesktop/UPower/devices/DisplayDevice";
+
 pub(super) struct Device {
     device_proxy: DeviceProxy<'static>,
     changes: PropertiesChangedStream<'static>,
@@ -16,9 +19,7 @@ impl Device {
         let dbus_conn = new_system_dbus_connection().await?;
 
         let (device_path, device_proxy) = if device.exact() == Some("DisplayDevice") {
-            let path: ObjectPath = "/org/freedesktop/UPower/devices/DisplayDevice"
-                .try_into()
-                .unwrap();
+            let path: ObjectPath = DISPLAY_DEVICE.try_into().unwrap();
             let proxy = DeviceProxy::builder(&dbus_conn)
                 .path(path.clone())
                 .unwrap()
@@ -145,13 +146,16 @@ impl BatteryDevice for Device {
     interface = "org.freedesktop.UPower.Device",
     default_service = "org.freedesktop.UPower"
 )]
-trait Device {
+pub(crate) trait Device {
     #[dbus_proxy(property)]
     fn energy_rate(&self) -> zbus::Result<f64>;
 
     #[dbus_proxy(property)]
     fn is_present(&self) -> zbus::Result<bool>;
 
+    #[dbus_proxy(property)]
+    fn model(&self) -> zbus::Result<String>;
+
     #[dbus_proxy(property)]
     fn native_path(&self) -> zbus::Result<String>;
 
@@ -172,6 +176,9 @@ trait Device {
 
     #[dbus_proxy(property, name = "Type")]
     fn type_(&self) -> zbus::Result<u32>;
+
+    #[dbus_proxy(property)]
+    fn warning_level(&self) -> zbus::Result<u32>;
 }
 
 #[zbus::dbus_proxy(
@@ -179,7 +186,7 @@ trait Device {
     default_service = "org.freedesktop.UPower",
     default_path = "/org/freedesktop/UPower"
 )]
-trait UPower {
+pub(crate) trait UPower {
     fn enumerate_devices(&self) -> zbus::Result<Vec<zvariant::OwnedObjectPath>>;
 
     fn get_display_device(&self) -> zbus::Result<zvariant::OwnedObjectPath>;
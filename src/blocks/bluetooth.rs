@@ -6,7 +6,9 @@
 //! When the device can be identified as an audio headset, a keyboard, joystick, or mouse, use the
 //! relevant icon. Otherwise, fall back on the generic Bluetooth symbol.
 //!
-//! Right-clicking the block will attempt to connect (or disconnect) the device.
+//! Clicking the block will attempt to connect (or disconnect) the device. If the device is not
+//! known to BlueZ (e.g. it is not paired or the adapter is off), the block is shown in idle state
+//! with the `bluetooth_absent` icon.
 //!
 //! # Configuration
 //!
@@ -26,7 +28,7 @@
 //!
 //! Action   | Default button
 //! ---------|---------------
-//! `toggle` | Right
+//! `toggle` | Left or Right
 //!
 //! # Examples
 //!
@@ -46,6 +48,7 @@
 //! - `keyboard` for bluetooth devices identifying as "input-keyboard"
 //! - `mouse` for bluetooth devices identifying as "input-mouse"
 //! - `bluetooth` for all other devices
+//! - `bluetooth_absent` if the device is not available
 
 use super::prelude::*;
 use zbus::fdo::{DBusProxy, ObjectManagerProxy, PropertiesProxy};
@@ -64,8 +67,11 @@ pub struct Config {
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
-    api.set_default_actions(&[(MouseButton::Right, None, "toggle")])
-        .await?;
+    api.set_default_actions(&[
+        (MouseButton::Left, None, "toggle"),
+        (MouseButton::Right, None, "toggle"),
+    ])
+    .await?;
 
     let format = config.format.with_default(" $icon $name{ $percentage|} ")?;
     let disconnected_format = config
@@ -105,7 +111,7 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                 debug!("Showing device as unavailable");
                 widget.state = State::Idle;
                 widget.set_format(disconnected_format.clone());
-                widget.set_values(map!("icon" => Value::icon(api.get_icon("bluetooth")?)));
+                widget.set_values(map!("icon" => Value::icon(api.get_icon("bluetooth_absent")?)));
                 api.set_widget(&widget).await?;
             }
         }
@@ -256,9 +262,8 @@ impl DeviceMonitor {
     async fn get_device_info(&mut self) -> Option<DeviceInfo> {
         let device = self.device.as_ref()?;
 
-        let Ok((connected, icon, name)) = tokio::try_join!(
+        let Ok((connected, name)) = tokio::try_join!(
             device.device.connected(),
-            device.device.icon(),
             device.device.name(),
         ) else {
             debug!("failed to fetch device info, assuming device or bluez disappeared");
@@ -266,6 +271,9 @@ impl DeviceMonitor {
             return None;
         };
 
+        // The icon property is optional
+        let icon = device.device.icon().await.unwrap_or_default();
+
         Some(DeviceInfo {
             connected,
             icon: match icon.as_str() {
@@ -0,0 +1,358 @@
+//! The next calendar event
+//!
+//! Shows the next (or currently ongoing) event from local iCalendar (`.ics`) files or from
+//! [khal](https://lostpackets.de/khal/).
+//!
+//! The `ics` driver reads the files listed in `files`. Directories are searched for `.ics` files,
+//! so vdirsyncer collections can be used directly. Recurring events are supported for daily,
+//! weekly and monthly rules (`INTERVAL`, `COUNT`, `UNTIL`, `BYDAY` and `WKST`). Events with other
+//! rules are skipped and logged as a warning. The `khal` driver runs `khal list` and reads its
+//! JSON output. Set `khal_datetime_format` and `khal_date_format` to khal's `longdatetimeformat`
+//! and `longdateformat` if they are not the defaults.
+//!
+//! The block is hidden if there is no event within `lookahead`.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `driver` | One of `"ics"` or `"khal"` | `"ics"`
+//! `files` | List of `.ics` files or directories containing them (`ics` only). Supports path expansions e.g. `~`. | `[]`
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $start $title.str(max_w:30) "`
+//! `interval` | How often to re-read the calendars, in seconds | `300`
+//! `lookahead` | How far ahead to look for events, in seconds | `86400`
+//! `info_before` | Set the block's state to info this many seconds before an event starts | `1800`
+//! `warning_before` | Set the block's state to warning this many seconds before an event starts | `300`
+//! `show_all_day` | Whether to show all-day events | `false`
+//! `khal_datetime_format` | khal's `longdatetimeformat` (`khal` only) | `"%Y-%m-%d %H:%M"`
+//! `khal_date_format` | khal's `longdateformat` (`khal` only) | `"%Y-%m-%d"`
+//!
+//! Placeholder | Value                                                         | Type | Unit
+//! ------------|---------------------------------------------------------------|------|-----
+//! `icon`      | A static icon                                                 | Icon | -
+//! `title`     | The event's title                                             | Text | -
+//! `start`     | Time until the event starts, e.g. `in 1h 05m`, or `now`       | Text | -
+//! `time`      | The event's start time, e.g. `14:30`, `Tue 09:00` or `all day` | Text | -
+//! `location`  | The event's location. Absent if not set                       | Text | -
+//! `remaining` | Time until an ongoing event ends. Absent if it has not started | Text | -
+//!
+//! The block's state is set to info while an event is ongoing.
+//!
+//! Action   | Default button
+//! ---------|---------------
+//! `update` | Left
+//!
+//! # Examples
+//!
+//! ```toml
+//! [[block]]
+//! block = "calendar"
+//! files = ["~/.calendars/work", "~/.calendars/personal/holidays.ics"]
+//! format = " $icon $time $title{ @ $location|} "
+//! ```
+//!
+//! ```toml
+//! [[block]]
+//! block = "calendar"
+//! driver = "khal"
+//! lookahead = 3600
+//! ```
+//!
+//! # Icons Used
+//! - `calendar`
+
+mod ics;
+mod khal;
+
+use super::prelude::*;
+use crate::localization::{Localization, Text};
+use crate::util::format_duration;
+use chrono::{DateTime, Local, Utc};
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    driver: Driver,
+    files: Vec<ShellString>,
+    format: FormatConfig,
+    #[default(300.into())]
+    interval: Seconds,
+    #[default(86400.into())]
+    lookahead: Seconds,
+    #[default(1800.into())]
+    info_before: Seconds,
+    #[default(300.into())]
+    warning_before: Seconds,
+    show_all_day: bool,
+    #[default("%Y-%m-%d %H:%M".into())]
+    khal_datetime_format: String,
+    #[default("%Y-%m-%d".into())]
+    khal_date_format: String,
+}
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(rename_all = "lowercase")]
+enum Driver {
+    #[default]
+    Ics,
+    Khal,
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[(MouseButton::Left, None, "update")])
+        .await?;
+
+    let mut widget = Widget::new().with_format(
+        config
+            .format
+            .with_default(" $icon $start $title.str(max_w:30) ")?,
+    );
+
+    let source: Box<dyn CalendarSource + Send + Sync> = match config.driver {
+        Driver::Ics => Box::new(IcsFiles {
+            paths: config
+                .files
+                .iter()
+                .map(|path| path.expand().map(|p| PathBuf::from(&*p)))
+                .collect::<Result<_>>()?,
+        }),
+        Driver::Khal => Box::new(khal::Khal {
+            datetime_format: config.khal_datetime_format.clone(),
+            date_format: config.khal_date_format.clone(),
+            commands: api.command_context.clone(),
+        }),
+    };
+    let lookahead = chrono_duration(config.lookahead.0);
+    let info_before = chrono_duration(config.info_before.0);
+    let warning_before = chrono_duration(config.warning_before.0);
+
+    loop {
+        let now = Utc::now();
+        let mut events = api
+            .recoverable(|| source.events(now, now + lookahead))
+            .await?;
+        events.retain(|e| config.show_all_day || !e.all_day);
+        events.sort_by_key(|e| (e.start, e.end));
+        let next_poll = now + chrono_duration(config.interval.0);
+
+        // Update the relative times every minute and when the state changes
+        loop {
+            let now = Utc::now();
+            let event = events.iter().find(|e| e.end > now);
+            let wake = match event {
+                None => {
+                    api.hide().await?;
+                    next_poll
+                }
+                Some(event) => {
+                    widget.state = event.state(now, info_before, warning_before);
+                    widget.set_values(map! {
+                        "icon" => Value::icon(api.get_icon("calendar")?),
+                        "title" => Value::text(event.title.clone()),
+                        "start" => Value::text(event.relative_start(now, &api.shared_config.localization)),
+                        "time" => Value::text(event.start_time(now)),
+                        [if let Some(location) = &event.location]
+                            "location" => Value::text(location.clone()),
+                        [if event.start <= now]
+                            "remaining" => Value::text(seconds_text(event.end - now, &api.shared_config.localization)),
+                    });
+                    api.set_widget(&widget).await?;
+
+                    let next_minute = now + chrono::Duration::seconds(60 - now.timestamp() % 60);
+                    event
+                        .state_changes(info_before, warning_before)
+                        .into_iter()
+                        .filter(|t| *t > now)
+                        .chain([next_minute, next_poll])
+                        .min()
+                        .unwrap_or(next_poll)
+                }
+            };
+
+            let timeout = (wake - now).to_std().unwrap_or_default();
+            select! {
+                _ = sleep(timeout) => if wake >= next_poll { break },
+                _ = api.event() => break,
+            }
+        }
+    }
+}
+
+fn chrono_duration(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::max_value())
+}
+
+fn seconds_text(duration: chrono::Duration, localization: &Localization) -> String {
+    format_duration(duration.num_seconds() as f64, localization)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Event {
+    title: String,
+    location: Option<String>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    all_day: bool,
+}
+
+impl Event {
+    fn state(
+        &self,
+        now: DateTime<Utc>,
+        info_before: chrono::Duration,
+        warning_before: chrono::Duration,
+    ) -> State {
+        if self.start <= now {
+            State::Info
+        } else if self.start - now <= warning_before {
+            State::Warning
+        } else if self.start - now <= info_before {
+            State::Info
+        } else {
+            State::Idle
+        }
+    }
+
+    /// The times at which the result of [`Event::state`] changes
+    fn state_changes(
+        &self,
+        info_before: chrono::Duration,
+        warning_before: chrono::Duration,
+    ) -> [DateTime<Utc>; 4] {
+        [
+            self.start - info_before,
+            self.start - warning_before,
+            self.start,
+            self.end,
+        ]
+    }
+
+    fn relative_start(&self, now: DateTime<Utc>, localization: &Localization) -> String {
+        if self.start <= now {
+            localization.get(Text::Now).into()
+        } else {
+            // Round up, so that an event starting in 30 seconds is not shown as "in 0m"
+            let duration = seconds_text(
+                self.start - now + chrono::Duration::seconds(59),
+                localization,
+            );
+            localization.relative(Text::In, &duration)
+        }
+    }
+
+    fn start_time(&self, now: DateTime<Utc>) -> String {
+        let start = self.start.with_timezone(&Local);
+        if self.all_day {
+            "all day".into()
+        } else if start.date_naive() == now.with_timezone(&Local).date_naive() {
+            start.format("%H:%M").to_string()
+        } else {
+            start.format("%a %H:%M").to_string()
+        }
+    }
+}
+
+#[async_trait]
+trait CalendarSource {
+    /// The events which end after `from` and start before `to`, in any order
+    async fn events(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Event>>;
+}
+
+struct IcsFiles {
+    paths: Vec<PathBuf>,
+}
+
+#[async_trait]
+impl CalendarSource for IcsFiles {
+    async fn events(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Event>> {
+        let mut files = Vec::new();
+        for path in &self.paths {
+            ics_files(path, &mut files).await?;
+        }
+        let mut events = Vec::new();
+        for file in files {
+            let text = tokio::fs::read_to_string(&file)
+                .await
+                .or_error(|| format!("Failed to read {}", file.display()))?;
+            events.extend(ics::events_between(&text, from, to));
+        }
+        Ok(events)
+    }
+}
+
+/// Collect `path` if it is a file, or the `.ics` files in it if it is a directory
+async fn ics_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .or_error(|| format!("Failed to access {}", path.display()))?;
+    if !metadata.is_dir() {
+        files.push(path.into());
+        return Ok(());
+    }
+    let mut entries = tokio::fs::read_dir(path)
+        .await
+        .or_error(|| format!("Failed to read {}", path.display()))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .error("Failed to read directory")?
+    {
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "ics") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(start: i64, end: i64) -> Event {
+        Event {
+            title: "Meeting".into(),
+            location: None,
+            start: DateTime::from_utc(
+                chrono::NaiveDateTime::from_timestamp_opt(start, 0).unwrap(),
+                Utc,
+            ),
+            end: DateTime::from_utc(
+                chrono::NaiveDateTime::from_timestamp_opt(end, 0).unwrap(),
+                Utc,
+            ),
+            all_day: false,
+        }
+    }
+
+    #[test]
+    fn state() {
+        let event = event(10_000, 13_600);
+        let info = chrono::Duration::minutes(30);
+        let warning = chrono::Duration::minutes(5);
+        let at = |t| {
+            DateTime::from_utc(
+                chrono::NaiveDateTime::from_timestamp_opt(t, 0).unwrap(),
+                Utc,
+            )
+        };
+
+        assert_eq!(event.state(at(0), info, warning), State::Idle);
+        assert_eq!(event.state(at(10_000 - 1800), info, warning), State::Info);
+        assert_eq!(event.state(at(10_000 - 300), info, warning), State::Warning);
+        assert_eq!(event.state(at(10_000), info, warning), State::Info);
+
+        let english = Localization::default();
+        assert_eq!(event.relative_start(at(10_000 - 30), &english), "in 1m");
+        assert_eq!(
+            event.relative_start(at(10_000 - 3900), &english),
+            "in 1h 05m"
+        );
+        assert_eq!(event.relative_start(at(10_001), &english), "now");
+
+        let changes = event.state_changes(info, warning);
+        assert_eq!(changes[0], at(10_000 - 1800));
+        assert_eq!(changes[3], at(13_600));
+    }
+}
@@ -0,0 +1,901 @@
+//! A small iCalendar (RFC 5545) parser
+//!
+//! Only `VEVENT` components are read. Recurring events are expanded with a subset of `RRULE`:
+//! `FREQ` (`DAILY`, `WEEKLY` or `MONTHLY`), `INTERVAL`, `COUNT`, `UNTIL`, `BYDAY` and `WKST`.
+//! Instances are computed in the event's local time and then converted to UTC, so recurring events
+//! keep their local time across DST transitions.
+
+use std::collections::HashSet;
+
+use chrono::{
+    DateTime, Datelike, Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone,
+    Utc, Weekday,
+};
+use chrono_tz::Tz;
+
+use super::Event;
+use crate::blocks::prelude::*;
+
+/// Stop expanding a recurring event after this many periods (e.g. days for `FREQ=DAILY`)
+const MAX_PERIODS: i64 = 100_000;
+
+/// Parse the events of an iCalendar file and expand recurring events. Only events which end after
+/// `from` and start before `to` are returned. Malformed events are skipped with a warning.
+pub(super) fn events_between(text: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Event> {
+    let mut components = Vec::new();
+    for (index, properties) in vevents(text).into_iter().enumerate() {
+        match Component::new(&properties) {
+            Ok(Some(component)) => components.push(component),
+            Ok(None) => (),
+            Err(error) => log::warn!("Skipping event #{index} of calendar: {error}"),
+        }
+    }
+
+    // Instances of recurring events which are replaced by another component
+    let overridden: HashSet<(&str, DateTime<Utc>)> = components
+        .iter()
+        .filter_map(|c| Some((c.uid.as_str(), c.recurrence_id?)))
+        .collect();
+
+    let mut events = Vec::new();
+    for component in &components {
+        let duration = component.end_utc() - component.start_utc();
+        for start in component.instances(to) {
+            let end = start + duration;
+            let is_overridden = component.recurrence_id.is_none()
+                && overridden.contains(&(component.uid.as_str(), start));
+            if end > from && !is_overridden && !component.exdates.contains(&start) {
+                events.push(Event {
+                    title: component.summary.clone(),
+                    location: component.location.clone(),
+                    start,
+                    end,
+                    all_day: component.all_day,
+                });
+            }
+        }
+    }
+    events
+}
+
+/// A content line, e.g. `DTSTART;TZID=Europe/Berlin:20230105T093000`
+#[derive(Debug, PartialEq, Eq)]
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        // The value starts after the first colon which is not inside a quoted parameter value
+        let mut in_quotes = false;
+        let colon = line.char_indices().find_map(|(i, c)| match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                None
+            }
+            ':' if !in_quotes => Some(i),
+            _ => None,
+        })?;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+        let mut parts = head.split(';');
+        let name = parts.next()?.to_ascii_uppercase();
+        let params = parts
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.to_ascii_uppercase(), v.trim_matches('"').to_string()))
+            .collect();
+        Some(Self {
+            name,
+            params,
+            value: value.to_string(),
+        })
+    }
+}
+
+/// Split the file into `VEVENT` components. Properties of nested components (e.g. `VALARM`) are
+/// left out.
+fn vevents(text: &str) -> Vec<Vec<Property>> {
+    // Long lines are folded by inserting a line break followed by a space or a tab
+    let unfolded = text
+        .replace("\r\n", "\n")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+
+    let mut events = Vec::new();
+    let mut current: Option<Vec<Property>> = None;
+    let mut depth = 0;
+    for line in unfolded.lines() {
+        let property = match Property::parse(line) {
+            Some(property) => property,
+            None => continue,
+        };
+        match (
+            property.name.as_str(),
+            property.value.to_ascii_uppercase().as_str(),
+        ) {
+            ("BEGIN", "VEVENT") if current.is_none() => {
+                current = Some(Vec::new());
+                depth = 0;
+            }
+            ("BEGIN", _) => depth += 1,
+            ("END", "VEVENT") if depth == 0 => events.extend(current.take()),
+            ("END", _) => depth -= 1,
+            _ => {
+                if let (Some(current), 0) = (&mut current, depth) {
+                    current.push(property);
+                }
+            }
+        }
+    }
+    events
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n' | 'N') => result.push(' '),
+                Some(c) => result.push(c),
+                None => (),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// The time zone of a date-time value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EventTz {
+    Utc,
+    /// Floating time or a date
+    Local,
+    Zone(Tz),
+}
+
+impl EventTz {
+    /// Convert a local time to UTC. Times which are skipped by a DST transition are shifted by
+    /// the length of the gap, and ambiguous times refer to their first occurrence, as specified
+    /// by RFC 5545.
+    fn to_utc(self, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+        fn convert<T: TimeZone>(tz: &T, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+            match tz.from_local_datetime(&time) {
+                LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => {
+                    Some(t.with_timezone(&Utc))
+                }
+                LocalResult::None => {
+                    // Use the offset before the gap
+                    let before = tz.from_local_datetime(&(time - chrono::Duration::hours(3)));
+                    let offset = before.earliest()?.offset().fix();
+                    Some(DateTime::from_utc(time - offset, Utc))
+                }
+            }
+        }
+        match self {
+            Self::Utc => Some(DateTime::from_utc(time, Utc)),
+            Self::Local => convert(&Local, time),
+            Self::Zone(tz) => convert(&tz, time),
+        }
+    }
+}
+
+/// A date or date-time value. Returns the local time, its time zone and whether it is a date.
+fn parse_date_time(property: &Property) -> Result<(NaiveDateTime, EventTz, bool)> {
+    let value = property.value.trim();
+    if property.param("VALUE") == Some("DATE") || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d")
+            .or_error(|| format!("invalid date '{value}'"))?;
+        return Ok((date.and_time(NaiveTime::default()), EventTz::Local, true));
+    }
+    let (value, tz) = match value.strip_suffix('Z') {
+        Some(value) => (value, EventTz::Utc),
+        None => match property.param("TZID") {
+            Some(tzid) => match tzid.trim_start_matches('/').parse() {
+                Ok(tz) => (value, EventTz::Zone(tz)),
+                Err(_) => {
+                    log::warn!("Unknown time zone '{tzid}', using the local time zone");
+                    (value, EventTz::Local)
+                }
+            },
+            None => (value, EventTz::Local),
+        },
+    };
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .or_error(|| format!("invalid date-time '{value}'"))?;
+    Ok((time, tz, false))
+}
+
+/// Parse a duration like `PT1H30M`, `P1D` or `-P1W`
+fn parse_duration(value: &str) -> Result<chrono::Duration> {
+    let invalid = || format!("invalid duration '{value}'");
+    let (negative, rest) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut rest = rest.strip_prefix('P').or_error(invalid)?;
+    let mut seconds = 0;
+    let mut number = String::new();
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            'T' => (),
+            '0'..='9' => number.push(c),
+            'W' | 'D' | 'H' | 'M' | 'S' => {
+                let n: i64 = number.parse().or_error(invalid)?;
+                number.clear();
+                seconds += n * match c {
+                    'W' => 7 * 86400,
+                    'D' => 86400,
+                    'H' => 3600,
+                    'M' => 60,
+                    _ => 1,
+                };
+            }
+            _ => return Err(Error::new(invalid())),
+        }
+    }
+    if !number.is_empty() {
+        return Err(Error::new(invalid()));
+    }
+    Ok(chrono::Duration::seconds(if negative {
+        -seconds
+    } else {
+        seconds
+    }))
+}
+
+#[derive(Debug)]
+struct Component {
+    uid: String,
+    summary: String,
+    location: Option<String>,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    tz: EventTz,
+    all_day: bool,
+    rule: Option<Rule>,
+    exdates: HashSet<DateTime<Utc>>,
+    recurrence_id: Option<DateTime<Utc>>,
+}
+
+impl Component {
+    /// Returns `None` for cancelled events
+    fn new(properties: &[Property]) -> Result<Option<Self>> {
+        let get = |name: &str| properties.iter().find(|p| p.name == name);
+
+        if get("STATUS").map(|p| p.value.as_str()) == Some("CANCELLED") {
+            return Ok(None);
+        }
+
+        let dtstart = get("DTSTART").error("DTSTART is missing")?;
+        let (start, tz, all_day) = parse_date_time(dtstart)?;
+        let end = match (get("DTEND"), get("DURATION")) {
+            (Some(dtend), _) => {
+                let (end, end_tz, _) = parse_date_time(dtend)?;
+                if end_tz == tz {
+                    end
+                } else {
+                    // Express the end in the time zone of the start
+                    let duration = end_tz.to_utc(end).error("invalid DTEND")?
+                        - tz.to_utc(start).error("invalid DTSTART")?;
+                    start + duration
+                }
+            }
+            (None, Some(duration)) => start + parse_duration(&duration.value)?,
+            (None, None) if all_day => start + chrono::Duration::days(1),
+            (None, None) => start,
+        };
+
+        let mut exdates = HashSet::new();
+        for exdate in properties.iter().filter(|p| p.name == "EXDATE") {
+            for value in exdate.value.split(',') {
+                let property = Property {
+                    name: exdate.name.clone(),
+                    params: exdate.params.clone(),
+                    value: value.into(),
+                };
+                let (time, tz, _) = parse_date_time(&property)?;
+                exdates.extend(tz.to_utc(time));
+            }
+        }
+
+        let recurrence_id = match get("RECURRENCE-ID") {
+            Some(property) => {
+                let (time, tz, _) = parse_date_time(property)?;
+                tz.to_utc(time)
+            }
+            None => None,
+        };
+
+        Ok(Some(Self {
+            uid: get("UID").map(|p| p.value.clone()).unwrap_or_default(),
+            summary: get("SUMMARY")
+                .map(|p| unescape(&p.value))
+                .unwrap_or_default(),
+            location: get("LOCATION")
+                .map(|p| unescape(&p.value))
+                .filter(|l| !l.is_empty()),
+            start,
+            end,
+            tz,
+            all_day,
+            rule: get("RRULE").map(|p| p.value.parse()).transpose()?,
+            exdates,
+            recurrence_id,
+        }))
+    }
+
+    fn start_utc(&self) -> DateTime<Utc> {
+        self.tz.to_utc(self.start).unwrap_or_else(Utc::now)
+    }
+
+    fn end_utc(&self) -> DateTime<Utc> {
+        self.tz.to_utc(self.end).unwrap_or_else(|| self.start_utc())
+    }
+
+    /// The start times of all instances which start before `to`
+    fn instances(&self, to: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        match &self.rule {
+            Some(rule) => rule.expand(self.start, self.tz, to),
+            None => self.tz.to_utc(self.start).into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// An entry of `BYDAY`, e.g. `MO` or `-1FR` (the last Friday)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ByDay {
+    ordinal: Option<i8>,
+    weekday: Weekday,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Until {
+    Utc(DateTime<Utc>),
+    Local(NaiveDateTime),
+}
+
+/// A recurrence rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<Until>,
+    by_day: Vec<ByDay>,
+    week_start: Weekday,
+}
+
+fn parse_weekday(s: &str) -> Result<Weekday> {
+    Ok(match s {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return Err(Error::new(format!("invalid weekday '{s}'"))),
+    })
+}
+
+impl std::str::FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut frequency = None;
+        let mut rule = Self {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            week_start: Weekday::Mon,
+        };
+        for part in s.split(';').filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .or_error(|| format!("invalid RRULE part '{part}'"))?;
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        _ => return Err(Error::new(format!("unsupported FREQ '{value}'"))),
+                    });
+                }
+                "INTERVAL" => {
+                    rule.interval = value.parse().error("invalid INTERVAL")?;
+                    if rule.interval == 0 {
+                        return Err(Error::new("invalid INTERVAL"));
+                    }
+                }
+                "COUNT" => rule.count = Some(value.parse().error("invalid COUNT")?),
+                "UNTIL" => {
+                    let property = Property {
+                        name: "UNTIL".into(),
+                        params: Vec::new(),
+                        value: value.into(),
+                    };
+                    rule.until = Some(match parse_date_time(&property)? {
+                        (time, EventTz::Utc, _) => Until::Utc(DateTime::from_utc(time, Utc)),
+                        // A date includes the whole day
+                        (time, _, true) => Until::Local(time + chrono::Duration::days(1)),
+                        (time, _, false) => Until::Local(time),
+                    });
+                }
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        // The weekday is the last two characters, which may not be ASCII
+                        let split = day.char_indices().rev().nth(1).error("invalid BYDAY")?.0;
+                        let (ordinal, weekday) = day.split_at(split);
+                        rule.by_day.push(ByDay {
+                            ordinal: match ordinal {
+                                "" => None,
+                                n => match n.parse::<i8>().error("invalid BYDAY")? {
+                                    // There are at most five of each weekday in a month
+                                    n @ (-5..=-1 | 1..=5) => Some(n),
+                                    _ => return Err(Error::new("invalid BYDAY")),
+                                },
+                            },
+                            weekday: parse_weekday(weekday)?,
+                        });
+                    }
+                }
+                "WKST" => rule.week_start = parse_weekday(value)?,
+                _ => return Err(Error::new(format!("unsupported RRULE part '{key}'"))),
+            }
+        }
+        rule.frequency = frequency.error("FREQ is missing")?;
+        Ok(rule)
+    }
+}
+
+impl Rule {
+    /// The start times of the instances which start before `to`, beginning with `start`
+    fn expand(&self, start: NaiveDateTime, tz: EventTz, to: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        let mut instances = Vec::new();
+        // Instances are compared in local time, so allow for any UTC offset
+        let last_date = (to + chrono::Duration::days(2)).naive_utc().date();
+
+        // The start always is the first instance
+        let mut count = 0;
+        for period in 0..MAX_PERIODS {
+            let candidates = self.candidates(start, period * self.interval as i64);
+            if candidates.is_empty() && self.period_start(start, period) > last_date {
+                break;
+            }
+            let first = (period == 0).then_some(start);
+            for time in first
+                .into_iter()
+                .chain(candidates.into_iter().filter(|t| *t > start))
+            {
+                let utc = match tz.to_utc(time) {
+                    Some(utc) => utc,
+                    None => continue,
+                };
+                let past_until = match self.until {
+                    Some(Until::Utc(until)) => utc > until,
+                    Some(Until::Local(until)) => time > until,
+                    None => false,
+                };
+                if past_until || utc >= to || self.count.is_some_and(|c| count >= c) {
+                    return instances;
+                }
+                instances.push(utc);
+                count += 1;
+            }
+        }
+        instances
+    }
+
+    /// The first day of the `n`th period
+    fn period_start(&self, start: NaiveDateTime, n: i64) -> NaiveDate {
+        let n = n * self.interval as i64;
+        match self.frequency {
+            Frequency::Daily => start.date() + chrono::Duration::days(n),
+            Frequency::Weekly => self.week_of(start.date()) + chrono::Duration::weeks(n),
+            Frequency::Monthly => add_months(start.date().with_day(1).unwrap(), n),
+        }
+    }
+
+    /// The first day of the week containing `date`
+    fn week_of(&self, date: NaiveDate) -> NaiveDate {
+        let offset = (date.weekday().num_days_from_monday() + 7
+            - self.week_start.num_days_from_monday())
+            % 7;
+        date - chrono::Duration::days(offset as i64)
+    }
+
+    /// The candidate instances in the period which is `offset` periods after the first one, in
+    /// chronological order
+    fn candidates(&self, start: NaiveDateTime, offset: i64) -> Vec<NaiveDateTime> {
+        let time = start.time();
+        let mut dates = Vec::new();
+        match self.frequency {
+            Frequency::Daily => {
+                let date = start.date() + chrono::Duration::days(offset);
+                if self.by_day.is_empty() || self.by_day.iter().any(|d| d.weekday == date.weekday())
+                {
+                    dates.push(date);
+                }
+            }
+            Frequency::Weekly => {
+                let week = self.week_of(start.date()) + chrono::Duration::weeks(offset);
+                let weekdays: Vec<Weekday> = if self.by_day.is_empty() {
+                    vec![start.weekday()]
+                } else {
+                    self.by_day.iter().map(|d| d.weekday).collect()
+                };
+                for weekday in weekdays {
+                    let days = (weekday.num_days_from_monday() + 7
+                        - self.week_start.num_days_from_monday())
+                        % 7;
+                    dates.push(week + chrono::Duration::days(days as i64));
+                }
+            }
+            Frequency::Monthly => {
+                let month = add_months(start.date().with_day(1).unwrap(), offset);
+                if self.by_day.is_empty() {
+                    // Months without this day are skipped
+                    dates.extend(month.with_day(start.day()));
+                } else {
+                    let days_in_month: Vec<NaiveDate> = month
+                        .iter_days()
+                        .take_while(|d| d.month() == month.month())
+                        .collect();
+                    for by_day in &self.by_day {
+                        let matching = days_in_month
+                            .iter()
+                            .filter(|d| d.weekday() == by_day.weekday);
+                        match by_day.ordinal {
+                            None => dates.extend(matching),
+                            Some(n) if n > 0 => dates.extend(matching.clone().nth(n as usize - 1)),
+                            Some(n) => dates.extend(matching.rev().nth((-n) as usize - 1)),
+                        }
+                    }
+                }
+            }
+        }
+        dates.sort_unstable();
+        dates.dedup();
+        dates.into_iter().map(|d| d.and_time(time)).collect()
+    }
+}
+
+fn add_months(date: NaiveDate, months: i64) -> NaiveDate {
+    let total = date.year() as i64 * 12 + date.month0() as i64 + months;
+    NaiveDate::from_ymd_opt((total / 12) as i32, (total % 12) as u32 + 1, date.day())
+        .unwrap_or(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naive(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::from_utc(naive(s), Utc)
+    }
+
+    fn expand(rule: &str, start: &str, tz: EventTz, to: &str) -> Vec<String> {
+        rule.parse::<Rule>()
+            .unwrap()
+            .expand(naive(start), tz, utc(to))
+            .iter()
+            .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+            .collect()
+    }
+
+    const BERLIN: EventTz = EventTz::Zone(chrono_tz::Europe::Berlin);
+
+    #[test]
+    fn properties() {
+        assert_eq!(
+            Property::parse("DTSTART;TZID=\"Europe/Berlin\";VALUE=DATE-TIME:20230105T093000"),
+            Some(Property {
+                name: "DTSTART".into(),
+                params: vec![
+                    ("TZID".into(), "Europe/Berlin".into()),
+                    ("VALUE".into(), "DATE-TIME".into())
+                ],
+                value: "20230105T093000".into(),
+            })
+        );
+        let p = Property::parse("ATTENDEE;CN=\"Doe: John\":mailto:john@example.org").unwrap();
+        assert_eq!(p.param("cn"), Some("Doe: John"));
+        assert_eq!(p.value, "mailto:john@example.org");
+        assert_eq!(unescape(r"a\, b\; c\nd\\e"), r"a, b; c d\e");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("PT1H30M").unwrap().num_minutes(), 90);
+        assert_eq!(parse_duration("P1DT2H").unwrap().num_hours(), 26);
+        assert_eq!(parse_duration("-P1W").unwrap().num_days(), -7);
+        assert!(parse_duration("PT1").is_err());
+        assert!(parse_duration("1H").is_err());
+    }
+
+    #[test]
+    fn daily() {
+        assert_eq!(
+            expand(
+                "FREQ=DAILY;COUNT=3",
+                "2023-01-30 09:00",
+                EventTz::Utc,
+                "2024-01-01 00:00"
+            ),
+            ["2023-01-30 09:00", "2023-01-31 09:00", "2023-02-01 09:00"]
+        );
+        assert_eq!(
+            expand(
+                "FREQ=DAILY;INTERVAL=2;UNTIL=20230105T090000Z",
+                "2023-01-01 09:00",
+                EventTz::Utc,
+                "2024-01-01 00:00"
+            ),
+            ["2023-01-01 09:00", "2023-01-03 09:00", "2023-01-05 09:00"]
+        );
+        // Weekdays only, limited by the window
+        assert_eq!(
+            expand(
+                "FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR",
+                "2023-01-05 09:00",
+                EventTz::Utc,
+                "2023-01-10 00:00"
+            ),
+            ["2023-01-05 09:00", "2023-01-06 09:00", "2023-01-09 09:00"]
+        );
+    }
+
+    #[test]
+    fn weekly() {
+        // 2023-01-02 is a Monday
+        assert_eq!(
+            expand(
+                "FREQ=WEEKLY;BYDAY=MO,FR;COUNT=5",
+                "2023-01-02 10:00",
+                EventTz::Utc,
+                "2024-01-01 00:00"
+            ),
+            [
+                "2023-01-02 10:00",
+                "2023-01-06 10:00",
+                "2023-01-09 10:00",
+                "2023-01-13 10:00",
+                "2023-01-16 10:00"
+            ]
+        );
+        assert_eq!(
+            expand(
+                "FREQ=WEEKLY;INTERVAL=2;UNTIL=20230201",
+                "2023-01-04 10:00",
+                EventTz::Utc,
+                "2024-01-01 00:00"
+            ),
+            ["2023-01-04 10:00", "2023-01-18 10:00", "2023-02-01 10:00"]
+        );
+        // The week start matters for INTERVAL > 1 (RFC 5545 section 3.3.10)
+        assert_eq!(
+            expand(
+                "FREQ=WEEKLY;INTERVAL=2;COUNT=4;BYDAY=TU,SU;WKST=MO",
+                "1997-08-05 09:00",
+                EventTz::Utc,
+                "1998-01-01 00:00"
+            ),
+            [
+                "1997-08-05 09:00",
+                "1997-08-10 09:00",
+                "1997-08-19 09:00",
+                "1997-08-24 09:00"
+            ]
+        );
+        assert_eq!(
+            expand(
+                "FREQ=WEEKLY;INTERVAL=2;COUNT=4;BYDAY=TU,SU;WKST=SU",
+                "1997-08-05 09:00",
+                EventTz::Utc,
+                "1998-01-01 00:00"
+            ),
+            [
+                "1997-08-05 09:00",
+                "1997-08-17 09:00",
+                "1997-08-19 09:00",
+                "1997-08-31 09:00"
+            ]
+        );
+    }
+
+    #[test]
+    fn monthly() {
+        // Months without a 31st are skipped
+        assert_eq!(
+            expand(
+                "FREQ=MONTHLY;COUNT=4",
+                "2023-01-31 08:00",
+                EventTz::Utc,
+                "2024-01-01 00:00"
+            ),
+            [
+                "2023-01-31 08:00",
+                "2023-03-31 08:00",
+                "2023-05-31 08:00",
+                "2023-07-31 08:00"
+            ]
+        );
+        // The first Friday and the last Monday
+        assert_eq!(
+            expand(
+                "FREQ=MONTHLY;BYDAY=1FR,-1MO;COUNT=5",
+                "2023-01-06 12:00",
+                EventTz::Utc,
+                "2024-01-01 00:00"
+            ),
+            [
+                "2023-01-06 12:00",
+                "2023-01-30 12:00",
+                "2023-02-03 12:00",
+                "2023-02-27 12:00",
+                "2023-03-03 12:00"
+            ]
+        );
+    }
+
+    #[test]
+    fn dst() {
+        // Berlin switches to summer time on 2023-03-26 and back on 2023-10-29
+        assert_eq!(
+            expand(
+                "FREQ=WEEKLY;COUNT=3",
+                "2023-03-19 09:00",
+                BERLIN,
+                "2024-01-01 00:00"
+            ),
+            ["2023-03-19 08:00", "2023-03-26 07:00", "2023-04-02 07:00"]
+        );
+        assert_eq!(
+            expand(
+                "FREQ=DAILY;COUNT=2",
+                "2023-10-28 09:00",
+                BERLIN,
+                "2024-01-01 00:00"
+            ),
+            ["2023-10-28 07:00", "2023-10-29 08:00"]
+        );
+        // 02:30 does not exist on 2023-03-26 and is shifted to 03:30 summer time
+        assert_eq!(
+            expand(
+                "FREQ=DAILY;COUNT=3",
+                "2023-03-25 02:30",
+                BERLIN,
+                "2024-01-01 00:00"
+            ),
+            ["2023-03-25 01:30", "2023-03-26 01:30", "2023-03-27 00:30"]
+        );
+        // 02:30 happens twice on 2023-10-29, the first one is used
+        assert_eq!(
+            expand(
+                "FREQ=DAILY;COUNT=1",
+                "2023-10-29 02:30",
+                BERLIN,
+                "2024-01-01 00:00"
+            ),
+            ["2023-10-29 00:30"]
+        );
+        // UNTIL in UTC is compared with the instant, not the local time
+        assert_eq!(
+            expand(
+                "FREQ=DAILY;UNTIL=20230326T070000Z",
+                "2023-03-25 09:00",
+                BERLIN,
+                "2024-01-01 00:00"
+            ),
+            ["2023-03-25 08:00", "2023-03-26 07:00"]
+        );
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!("FREQ=YEARLY".parse::<Rule>().is_err());
+        assert!("FREQ=DAILY;BYMONTHDAY=1".parse::<Rule>().is_err());
+        assert!("INTERVAL=2".parse::<Rule>().is_err());
+        assert!("FREQ=WEEKLY;BYDAY=XX".parse::<Rule>().is_err());
+        assert!("FREQ=MONTHLY;BYDAY=1MÖ".parse::<Rule>().is_err());
+        assert!("FREQ=MONTHLY;BYDAY=Ö".parse::<Rule>().is_err());
+        assert!("FREQ=MONTHLY;BYDAY=0MO".parse::<Rule>().is_err());
+        assert!("FREQ=MONTHLY;BYDAY=6MO".parse::<Rule>().is_err());
+        assert!("FREQ=MONTHLY;BYDAY=-6MO".parse::<Rule>().is_err());
+        assert!("FREQ=MONTHLY;BYDAY=-5MO".parse::<Rule>().is_ok());
+        assert!("FREQ=DAILY;INTERVAL=0".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn calendar() {
+        let text = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:standup\r
+SUMMARY:Stand \r
+ up\r
+LOCATION:Room 1\\, 2nd floor\r
+DTSTART;TZID=Europe/Berlin:20230102T093000\r
+DURATION:PT15M\r
+RRULE:FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR\r
+EXDATE;TZID=Europe/Berlin:20230104T093000\r
+BEGIN:VALARM\r
+TRIGGER:-PT5M\r
+SUMMARY:Alarm\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:standup\r
+RECURRENCE-ID;TZID=Europe/Berlin:20230103T093000\r
+SUMMARY:Late stand up\r
+DTSTART;TZID=Europe/Berlin:20230103T110000\r
+DTEND;TZID=Europe/Berlin:20230103T111500\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:broken\r
+SUMMARY:Broken\r
+DTSTART:2023-01-03\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:cancelled\r
+STATUS:CANCELLED\r
+DTSTART:20230103T100000Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:holiday\r
+SUMMARY:Holiday\r
+DTSTART;VALUE=DATE:20230105\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+        let events = events_between(text, utc("2023-01-02 12:00"), utc("2023-01-05 12:00"));
+        let summary: Vec<_> = events
+            .iter()
+            .filter(|e| !e.all_day)
+            .map(|e| {
+                (
+                    e.title.as_str(),
+                    e.start.format("%d %H:%M").to_string(),
+                    (e.end - e.start).num_minutes(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("Stand up", "05 08:30".to_string(), 15),
+                ("Late stand up", "03 10:00".to_string(), 15)
+            ]
+        );
+        assert_eq!(events[0].location.as_deref(), Some("Room 1, 2nd floor"));
+        let holiday = events.iter().find(|e| e.all_day).unwrap();
+        assert_eq!(holiday.title, "Holiday");
+        assert_eq!((holiday.end - holiday.start).num_hours(), 24);
+    }
+}
@@ -0,0 +1,142 @@
+//! Events from `khal list`
+
+use super::{CalendarSource, Event};
+use crate::blocks::prelude::*;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+
+pub(super) struct Khal {
+    pub(super) datetime_format: String,
+    pub(super) date_format: String,
+    pub(super) commands: CommandContext,
+}
+
+#[derive(Deserialize, Debug)]
+struct KhalEvent {
+    title: String,
+    #[serde(rename = "start-long")]
+    start: String,
+    #[serde(rename = "end-long")]
+    end: String,
+    location: String,
+    #[serde(rename = "all-day")]
+    all_day: serde_json::Value,
+}
+
+#[async_trait]
+impl CalendarSource for Khal {
+    async fn events(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Event>> {
+        let from = from.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+        let to = to.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+        let output = self
+            .commands
+            .command("khal")
+            .args(["list", "--once", "--day-format", ""])
+            .args([
+                "--json",
+                "title",
+                "--json",
+                "start-long",
+                "--json",
+                "end-long",
+            ])
+            .args(["--json", "location", "--json", "all-day"])
+            .arg(from.to_string())
+            .arg(to.to_string())
+            .output()
+            .await
+            .error("Failed to run khal")?;
+        if !output.status.success() {
+            return Err(Error::new(format!(
+                "khal exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let output = String::from_utf8(output.stdout).error("khal produced non-UTF8 output")?;
+        self.parse(&output)
+    }
+}
+
+impl Khal {
+    /// Parse the output of `khal list --json ...`, which is a JSON array of events per day
+    fn parse(&self, output: &str) -> Result<Vec<Event>> {
+        let mut events = Vec::new();
+        for line in output.lines().map(str::trim).filter(|l| l.starts_with('[')) {
+            let day: Vec<KhalEvent> =
+                serde_json::from_str(line).error("Failed to parse khal output")?;
+            for event in day {
+                let all_day = match &event.all_day {
+                    serde_json::Value::Bool(b) => *b,
+                    serde_json::Value::String(s) => s.eq_ignore_ascii_case("true"),
+                    _ => false,
+                };
+                let (start, end) = if all_day {
+                    // The end of all-day events is the last day of the event
+                    (
+                        self.date(&event.start)?,
+                        self.date(&event.end)? + chrono::Duration::days(1),
+                    )
+                } else {
+                    (self.datetime(&event.start)?, self.datetime(&event.end)?)
+                };
+                events.push(Event {
+                    title: event.title,
+                    location: Some(event.location).filter(|l| !l.is_empty()),
+                    start,
+                    end,
+                    all_day,
+                });
+            }
+        }
+        Ok(events)
+    }
+
+    fn datetime(&self, s: &str) -> Result<DateTime<Utc>> {
+        let time = NaiveDateTime::parse_from_str(s, &self.datetime_format)
+            .or_error(|| format!("Failed to parse '{s}' with khal_datetime_format"))?;
+        local_to_utc(time)
+    }
+
+    fn date(&self, s: &str) -> Result<DateTime<Utc>> {
+        let date = NaiveDate::parse_from_str(s, &self.date_format)
+            .or_error(|| format!("Failed to parse '{s}' with khal_date_format"))?;
+        local_to_utc(date.and_time(NaiveTime::default()))
+    }
+}
+
+fn local_to_utc(time: NaiveDateTime) -> Result<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+        .or_error(|| format!("{time} does not exist in the local time zone"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let khal = Khal {
+            datetime_format: "%d.%m.%Y %H:%M".into(),
+            date_format: "%d.%m.%Y".into(),
+            commands: CommandContext::default(),
+        };
+        let output = r#"[{"title": "Dentist", "start-long": "05.01.2023 14:00", "end-long": "05.01.2023 15:00", "location": "", "all-day": "False"}]
+
+[{"title": "Holiday", "start-long": "06.01.2023", "end-long": "06.01.2023", "location": "Home", "all-day": "True"}]
+"#;
+        let events = khal.parse(output).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].title, "Dentist");
+        assert_eq!(events[0].location, None);
+        assert_eq!((events[0].end - events[0].start).num_minutes(), 60);
+        assert!(events[1].all_day);
+        assert_eq!(events[1].location.as_deref(), Some("Home"));
+        assert_eq!((events[1].end - events[1].start).num_hours(), 24);
+
+        assert!(khal.parse(r#"[{"title": "x"}]"#).is_err());
+        assert!(khal.parse("").unwrap().is_empty());
+    }
+}
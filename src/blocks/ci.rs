@@ -0,0 +1,311 @@
+//! Status of CI jobs
+//!
+//! Shows one glyph per job: the result of its latest completed run, or a spinner while it is
+//! running. Jobs can be [Jenkins](https://www.jenkins.io/) jobs or GitHub Actions workflows, and
+//! both kinds can be mixed in one block. Every `interval` seconds all jobs are polled
+//! concurrently. A job which cannot be polled is shown with the `unknown` glyph and does not
+//! affect the other jobs; the error is logged.
+//!
+//! GitHub requests are conditional (`If-None-Match`), so polling a workflow whose runs did not
+//! change does not count against the API rate limit. Public repositories can be polled without a
+//! token, but unauthenticated requests are limited to 60 per hour.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $jobs.pango-str() "`
+//! `interval` | Update interval in seconds | `60`
+//! `github_token` | A GitHub personal access token, used for `github_actions` jobs. Falls back to the `I3RS_GITHUB_TOKEN` environment variable. | `None`
+//! `success` | A string displayed for a job whose latest run succeeded. May contain pango markup. | `"✔"`
+//! `failure` | A string displayed for a job whose latest run failed. May contain pango markup. | `"✘"`
+//! `unknown` | A string displayed for a job which was never run, was cancelled or could not be polled. May contain pango markup. | `"?"`
+//! `spinner` | The frames of the spinner displayed for running jobs, one character each | `"◐◓◑◒"`
+//! `jobs` | A list of jobs to watch. See below. | `[]`
+//!
+//! Each entry of `jobs` has a `driver` key and the following keys:
+//!
+//! Driver | Key | Values | Default
+//! -------|-----|--------|--------
+//! `"jenkins"` | `url` | URL of the job, e.g. `"https://ci.example.com/job/app"` | Required
+//! `"jenkins"` | `username` | Username used to authenticate | `None`
+//! `"jenkins"` | `password` | Password or API token used to authenticate | `None`
+//! `"github_actions"` | `repo` | The repository, e.g. `"owner/name"` | Required
+//! `"github_actions"` | `workflow` | The workflow's file name or ID, e.g. `"ci.yml"` | Required
+//! `"github_actions"` | `branch` | Only watch runs on this branch | `None`
+//!
+//! Placeholder     | Value                                             | Type   | Unit
+//! ----------------|---------------------------------------------------|--------|-----
+//! `icon`          | A static icon                                     | Icon   | -
+//! `jobs`          | A glyph for each job, in order                    | Text   | -
+//! `failed_count`  | Number of jobs whose latest run failed            | Number | -
+//! `running_count` | Number of jobs which are running                  | Number | -
+//!
+//! The block's state is set to critical if any job's latest run failed, and to info while any job
+//! is running.
+//!
+//! Action        | Description                                         | Default button
+//! --------------|-----------------------------------------------------|---------------
+//! `open_failed` | Open the first failed run with `xdg-open`           | Left
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "ci"
+//! format = " $icon $jobs.pango-str(){ $failed_count failed|} "
+//! failure = "<span color='red'>✘</span>"
+//! [[block.jobs]]
+//! driver = "jenkins"
+//! url = "https://ci.example.com/job/app"
+//! username = "me"
+//! password = "..."
+//! [[block.jobs]]
+//! driver = "github_actions"
+//! repo = "greshake/i3status-rust"
+//! workflow = "ci.yml"
+//! branch = "master"
+//! ```
+//!
+//! # Icons Used
+//! - `ci`
+
+mod github_actions;
+mod jenkins;
+
+use super::prelude::*;
+use tokio::time::MissedTickBehavior;
+
+/// How often the spinner advances
+const SPINNER_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    format: FormatConfig,
+    #[default(60.into())]
+    interval: Seconds,
+    github_token: Option<String>,
+    #[default("✔".into())]
+    success: String,
+    #[default("✘".into())]
+    failure: String,
+    #[default("?".into())]
+    unknown: String,
+    #[default("◐◓◑◒".into())]
+    spinner: String,
+    jobs: Vec<JobConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "driver", rename_all = "snake_case", deny_unknown_fields)]
+enum JobConfig {
+    Jenkins {
+        url: String,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    GithubActions {
+        repo: String,
+        workflow: String,
+        branch: Option<String>,
+    },
+}
+
+pub async fn run(mut config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[(MouseButton::Left, None, "open_failed")])
+        .await?;
+
+    let format = std::mem::take(&mut config.format);
+    let mut widget = Widget::new().with_format(format.with_default(" $icon $jobs.pango-str() ")?);
+
+    let token = super::github::token(config.github_token.clone());
+    let jobs: Vec<Box<dyn Job + Send + Sync>> = config
+        .jobs
+        .iter()
+        .map(|job| -> Box<dyn Job + Send + Sync> {
+            match job {
+                JobConfig::Jenkins {
+                    url,
+                    username,
+                    password,
+                } => Box::new(jenkins::Client::new(
+                    url,
+                    username.clone(),
+                    password.clone(),
+                )),
+                JobConfig::GithubActions {
+                    repo,
+                    workflow,
+                    branch,
+                } => Box::new(github_actions::Client::new(
+                    repo,
+                    workflow,
+                    branch.clone(),
+                    token.clone(),
+                )),
+            }
+        })
+        .collect();
+
+    let mut timer = config.interval.timer();
+    timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        let statuses = futures::future::join_all(jobs.iter().map(|job| job.status())).await;
+        for (job, status) in jobs.iter().zip(&statuses) {
+            if let Err(error) = status {
+                log::warn!("Failed to get the status of {}: {error}", job.name());
+            }
+        }
+        let ok_statuses = || statuses.iter().filter_map(|s| s.as_ref().ok());
+        let failed_count = ok_statuses()
+            .filter(|s| s.outcome == Outcome::Failure)
+            .count();
+        let running_count = ok_statuses().filter(|s| s.running).count();
+        let failed_url = ok_statuses()
+            .find(|s| s.outcome == Outcome::Failure)
+            .and_then(|s| s.url.clone());
+
+        widget.state = if failed_count > 0 {
+            State::Critical
+        } else if running_count > 0 {
+            State::Info
+        } else {
+            State::Idle
+        };
+
+        // Redraw the spinner until the next poll
+        let mut frame = 0;
+        loop {
+            widget.set_values(map! {
+                "icon" => Value::icon(api.get_icon("ci")?),
+                "jobs" => Value::text(config.glyphs(&statuses, frame)),
+                "failed_count" => Value::number(failed_count),
+                "running_count" => Value::number(running_count),
+            });
+            api.set_widget(&widget).await?;
+
+            select! {
+                _ = timer.tick() => break,
+                _ = sleep(SPINNER_INTERVAL), if running_count > 0 => frame += 1,
+                event = api.event() => match event {
+                    UpdateRequest => break,
+                    Action(a) if a == "open_failed" => {
+                        if let Some(url) = &failed_url {
+                            api.command_context
+                                .spawn_process("xdg-open", &[url])
+                                .error("Failed to run xdg-open")?;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+impl Config {
+    /// The glyphs of all jobs, with the spinner at `frame` for running jobs
+    fn glyphs(&self, statuses: &[Result<JobStatus>], frame: usize) -> String {
+        let spinner: Vec<char> = self.spinner.chars().collect();
+        let mut glyphs = String::new();
+        for status in statuses {
+            match status {
+                Ok(status) if status.running && !spinner.is_empty() => {
+                    glyphs.push(spinner[frame % spinner.len()]);
+                }
+                Ok(status) => glyphs.push_str(match status.outcome {
+                    Outcome::Success => &self.success,
+                    Outcome::Failure => &self.failure,
+                    Outcome::Unknown => &self.unknown,
+                }),
+                Err(_) => glyphs.push_str(&self.unknown),
+            }
+        }
+        glyphs
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Success,
+    Failure,
+    /// Never run, cancelled, or another result
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct JobStatus {
+    /// The result of the latest completed run
+    outcome: Outcome,
+    running: bool,
+    /// The page of the latest completed run
+    url: Option<String>,
+}
+
+#[async_trait]
+trait Job {
+    /// A name of the job used in log messages
+    fn name(&self) -> String;
+
+    async fn status(&self) -> Result<JobStatus>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyphs() {
+        let config = Config::default();
+        let status = |outcome, running| {
+            Ok(JobStatus {
+                outcome,
+                running,
+                url: None,
+            })
+        };
+        let statuses = [
+            status(Outcome::Success, false),
+            status(Outcome::Failure, false),
+            status(Outcome::Failure, true),
+            Err(Error::new("unreachable")),
+            status(Outcome::Unknown, false),
+        ];
+        assert_eq!(config.glyphs(&statuses, 0), "✔✘◐??");
+        assert_eq!(config.glyphs(&statuses, 5), "✔✘◓??");
+
+        let config = Config {
+            spinner: String::new(),
+            ..Config::default()
+        };
+        assert_eq!(config.glyphs(&statuses, 1), "✔✘✘??");
+    }
+
+    #[test]
+    fn jobs_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [[jobs]]
+            driver = "jenkins"
+            url = "https://ci.example.com/job/app"
+            [[jobs]]
+            driver = "github_actions"
+            repo = "owner/name"
+            workflow = "ci.yml"
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(config.jobs[0], JobConfig::Jenkins { .. }));
+        assert!(matches!(config.jobs[1], JobConfig::GithubActions { .. }));
+        assert!(toml::from_str::<Config>(
+            r#"
+            [[jobs]]
+            driver = "jenkins"
+            repo = "owner/name"
+            "#,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! GitHub Actions workflow runs API client
+//!
+//! See <https://docs.github.com/en/rest/actions/workflow-runs#list-workflow-runs-for-a-workflow>
+
+use super::{Job, JobStatus, Outcome};
+use crate::blocks::prelude::*;
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+use std::sync::Mutex;
+
+pub(super) struct Client {
+    repo: String,
+    workflow: String,
+    branch: Option<String>,
+    token: Option<String>,
+    /// The ETag of the last response and the status parsed from it
+    cache: Mutex<Option<(String, JobStatus)>>,
+}
+
+impl Client {
+    pub(super) fn new(
+        repo: &str,
+        workflow: &str,
+        branch: Option<String>,
+        token: Option<String>,
+    ) -> Self {
+        Self {
+            repo: repo.into(),
+            workflow: workflow.into(),
+            branch,
+            token,
+            cache: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl Job for Client {
+    fn name(&self) -> String {
+        format!("{}/{}", self.repo, self.workflow)
+    }
+
+    async fn status(&self) -> Result<JobStatus> {
+        let mut request = REQWEST_CLIENT
+            .get(format!(
+                "https://api.github.com/repos/{}/actions/workflows/{}/runs",
+                self.repo, self.workflow
+            ))
+            .query(&[("per_page", "10")])
+            .header("Accept", "application/vnd.github+json");
+        if let Some(branch) = &self.branch {
+            request = request.query(&[("branch", branch)]);
+        }
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("token {token}"));
+        }
+        let cached = self.cache.lock().unwrap().clone();
+        if let Some((etag, _)) = &cached {
+            // Responses with 304 Not Modified do not count against the rate limit
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        let response = request
+            .send()
+            .await
+            .error("Failed to send request to GitHub")?;
+        match response.status() {
+            StatusCode::NOT_MODIFIED => cached
+                .map(|(_, status)| status)
+                .error("GitHub returned 304 Not Modified for a request which was not cached"),
+            status if status.is_success() => {
+                let etag = response
+                    .headers()
+                    .get(ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(String::from);
+                let text = response
+                    .text()
+                    .await
+                    .error("Failed to read GitHub response")?;
+                let status = parse(&text)?;
+                *self.cache.lock().unwrap() = etag.map(|etag| (etag, status.clone()));
+                Ok(status)
+            }
+            status => {
+                let rate_limited = response
+                    .headers()
+                    .get("x-ratelimit-remaining")
+                    .is_some_and(|remaining| remaining == "0");
+                if rate_limited {
+                    return Err(Error::new("GitHub API rate limit exceeded"));
+                }
+                let message = response
+                    .json::<ErrorMessage>()
+                    .await
+                    .map(|e| e.message)
+                    .unwrap_or_default();
+                Err(Error::new(format!(
+                    "GitHub returned HTTP status {status}: {message}"
+                )))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorMessage {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Runs {
+    workflow_runs: Vec<Run>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Run {
+    status: String,
+    conclusion: Option<String>,
+    html_url: String,
+}
+
+/// Parse a list of runs, ordered from newest to oldest
+fn parse(text: &str) -> Result<JobStatus> {
+    let runs: Runs = serde_json::from_str(text).error("Failed to parse GitHub response")?;
+    let runs = runs.workflow_runs;
+    let completed = runs.iter().find(|run| run.status == "completed");
+    let outcome = match completed.and_then(|run| run.conclusion.as_deref()) {
+        Some("success") => Outcome::Success,
+        Some("failure" | "timed_out" | "startup_failure") => Outcome::Failure,
+        _ => Outcome::Unknown,
+    };
+    Ok(JobStatus {
+        outcome,
+        running: runs.first().is_some_and(|run| run.status != "completed"),
+        url: completed.map(|run| run.html_url.clone()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs() {
+        let status = parse(
+            r#"{
+                "total_count": 3,
+                "workflow_runs": [
+                    {"id": 3, "status": "in_progress", "conclusion": null, "html_url": "https://github.com/o/r/actions/runs/3"},
+                    {"id": 2, "status": "completed", "conclusion": "failure", "html_url": "https://github.com/o/r/actions/runs/2"},
+                    {"id": 1, "status": "completed", "conclusion": "success", "html_url": "https://github.com/o/r/actions/runs/1"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            status,
+            JobStatus {
+                outcome: Outcome::Failure,
+                running: true,
+                url: Some("https://github.com/o/r/actions/runs/2".into()),
+            }
+        );
+
+        let status = parse(
+            r#"{"total_count": 1, "workflow_runs": [
+                {"status": "completed", "conclusion": "success", "html_url": "https://github.com/o/r/actions/runs/1"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(status.outcome, Outcome::Success);
+        assert!(!status.running);
+
+        let status = parse(
+            r#"{"total_count": 1, "workflow_runs": [
+                {"status": "completed", "conclusion": "cancelled", "html_url": "x"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(status.outcome, Outcome::Unknown);
+
+        let status = parse(r#"{"total_count": 0, "workflow_runs": []}"#).unwrap();
+        assert_eq!(status.outcome, Outcome::Unknown);
+        assert_eq!(status.url, None);
+
+        assert!(parse(r#"{"message": "Not Found"}"#).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Jenkins JSON API client
+//!
+//! See <https://www.jenkins.io/doc/book/using/remote-access-api/>
+
+use super::{Job, JobStatus, Outcome};
+use crate::blocks::prelude::*;
+
+/// Only request the fields which are used, which keeps the responses small
+const TREE: &str = "url,color,lastBuild[building,url],lastCompletedBuild[result,url]";
+
+pub(super) struct Client {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl Client {
+    pub(super) fn new(url: &str, username: Option<String>, password: Option<String>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').into(),
+            username,
+            password,
+        }
+    }
+}
+
+#[async_trait]
+impl Job for Client {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    async fn status(&self) -> Result<JobStatus> {
+        let mut request = REQWEST_CLIENT
+            .get(format!("{}/api/json", self.url))
+            .query(&[("tree", TREE)]);
+        if let Some(username) = &self.username {
+            request = request.basic_auth(username, self.password.as_ref());
+        }
+        let response = request
+            .send()
+            .await
+            .error("Failed to send request to Jenkins")?;
+        if !response.status().is_success() {
+            return Err(Error::new(format!(
+                "Jenkins returned HTTP status {}",
+                response.status()
+            )));
+        }
+        let text = response
+            .text()
+            .await
+            .error("Failed to read Jenkins response")?;
+        parse(&text)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JenkinsJob {
+    #[serde(default)]
+    color: String,
+    last_build: Option<Build>,
+    last_completed_build: Option<Build>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Build {
+    #[serde(default)]
+    building: bool,
+    result: Option<String>,
+    url: String,
+}
+
+fn parse(text: &str) -> Result<JobStatus> {
+    let job: JenkinsJob = serde_json::from_str(text).error("Failed to parse Jenkins response")?;
+    // Building jobs have an animated color, e.g. `red_anime`
+    let (color, animated) = match job.color.strip_suffix("_anime") {
+        Some(color) => (color, true),
+        None => (job.color.as_str(), false),
+    };
+    let result = job
+        .last_completed_build
+        .as_ref()
+        .and_then(|b| b.result.as_deref());
+    let outcome = match (result, color) {
+        (Some("SUCCESS"), _) | (None, "blue") => Outcome::Success,
+        (Some("FAILURE" | "UNSTABLE"), _) | (None, "red" | "yellow") => Outcome::Failure,
+        _ => Outcome::Unknown,
+    };
+    Ok(JobStatus {
+        outcome,
+        running: animated || job.last_build.is_some_and(|b| b.building),
+        url: job.last_completed_build.map(|b| b.url),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job() {
+        let status = parse(
+            r#"{
+                "_class": "hudson.model.FreeStyleProject",
+                "color": "red_anime",
+                "url": "https://ci.example.com/job/app/",
+                "lastBuild": {"_class": "hudson.model.FreeStyleBuild", "building": true, "url": "https://ci.example.com/job/app/42/"},
+                "lastCompletedBuild": {"_class": "hudson.model.FreeStyleBuild", "result": "FAILURE", "url": "https://ci.example.com/job/app/41/"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            status,
+            JobStatus {
+                outcome: Outcome::Failure,
+                running: true,
+                url: Some("https://ci.example.com/job/app/41/".into()),
+            }
+        );
+
+        let status = parse(
+            r#"{
+                "color": "blue",
+                "url": "https://ci.example.com/job/app/",
+                "lastBuild": {"building": false, "result": "SUCCESS", "url": "https://ci.example.com/job/app/7/"},
+                "lastCompletedBuild": {"result": "SUCCESS", "url": "https://ci.example.com/job/app/7/"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(status.outcome, Outcome::Success);
+        assert!(!status.running);
+
+        // A job which was never built
+        let status = parse(
+            r#"{"color": "notbuilt", "url": "https://ci.example.com/job/new/", "lastBuild": null, "lastCompletedBuild": null}"#,
+        )
+        .unwrap();
+        assert_eq!(status.outcome, Outcome::Unknown);
+        assert_eq!(status.url, None);
+
+        // Aborted builds are not failures
+        let status = parse(
+            r#"{"color": "aborted", "lastCompletedBuild": {"result": "ABORTED", "url": "x"}}"#,
+        )
+        .unwrap();
+        assert_eq!(status.outcome, Outcome::Unknown);
+
+        assert!(parse("<html>Login</html>").is_err());
+    }
+}
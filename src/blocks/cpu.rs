@@ -7,6 +7,8 @@
 //! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $utilization "`
 //! `format_alt` | If set, block will switch between `format` and `format_alt` on every click | `None`
 //! `interval` | Update interval in seconds | `5`
+//! `steal_warning` | Percentage of steal time above which the state is set to warning, if it is not already worse | `None`
+//! `steal_critical` | Percentage of steal time above which the state is set to critical | `None`
 //!
 //! Placeholder      | Value                                                          | Type   | Unit
 //! -----------------|----------------------------------------------------------------|--------|---------------
@@ -14,9 +16,22 @@
 //! `utilization`    | Average CPU utilization                                        | Number | %
 //! `utilization<N>` | Utilization of Nth logical CPU                                 | Number | %
 //! `barchart`       | Utilization of all logical CPUs presented as a barchart        | Text   | -
-//! `frequency`      | Average CPU frequency                                          | Number | Hz
+//! `frequency`      | Average CPU frequency (may be absent if not reported)          | Number | Hz
 //! `frequency<N>`   | Frequency of Nth logical CPU                                   | Number | Hz
 //! `boost`          | CPU turbo boost status (may be absent if CPU is not supported) | Text   | -
+//! `user_percents`  | Share of CPU time spent in user space, including niced processes | Number | %
+//! `system_percents`| Share of CPU time spent in the kernel                          | Number | %
+//! `irq_percents`   | Share of CPU time spent serving interrupts                     | Number | %
+//! `iowait_percents`| Share of CPU time spent idle while waiting for I/O             | Number | %
+//! `steal_percents` | Share of CPU time taken by the hypervisor for other guests     | Number | %
+//! `in_vm`          | Present if running in a virtual machine                        | Flag   | -
+//!
+//! The shares of CPU time are of all logical CPUs, since the previous update. Steal time is only
+//! reported in virtual machines. `in_vm` is detected once, from `/sys/class/dmi/id` or else with
+//! `systemd-detect-virt`.
+//!
+//! `utilization1` is the utilization of `cpu0` in `/proc/stat`. Offline CPUs are left out of
+//! `barchart` and their `utilization<N>` placeholders are absent.
 //!
 //! Action          | Description                               | Default button
 //! ----------------|-------------------------------------------|---------------
@@ -32,6 +47,16 @@
 //! format_alt = " $icon $frequency{ $boost|} "
 //! ```
 //!
+//! On a VPS, show steal time and warn when the hypervisor takes more than 10% of the CPU time:
+//!
+//! ```toml
+//! [[block]]
+//! block = "cpu"
+//! format = " $icon $utilization{ steal $steal_percents$in_vm|} "
+//! steal_warning = 10
+//! steal_critical = 25
+//! ```
+//!
 //! # Icons Used
 //! - `cpu_low`
 //! - `cpu_med`
@@ -57,6 +82,8 @@ pub struct Config {
     format_alt: Option<FormatConfig>,
     #[default(5.into())]
     interval: Seconds,
+    steal_warning: Option<f64>,
+    steal_critical: Option<f64>,
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
@@ -73,33 +100,28 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
 
     let boost_icon_on = api.get_icon("cpu_boost_on")?;
     let boost_icon_off = api.get_icon("cpu_boost_off")?;
+    let in_vm = detect_vm(&api.command_context).await;
 
     // Store previous /proc/stat state
     let mut cputime = read_proc_stat().await?;
-    let cores = cputime.1.len();
 
     let mut timer = config.interval.timer();
 
     loop {
         let freqs = read_frequencies().await?;
-        let freq_avg = freqs.iter().sum::<f64>() / (freqs.len() as f64);
+        let freq_avg = (!freqs.is_empty()).then(|| freqs.iter().sum::<f64>() / freqs.len() as f64);
 
         // Compute utilizations
         let new_cputime = read_proc_stat().await?;
         let utilization_avg = new_cputime.0.utilization(cputime.0);
-        let mut utilizations = Vec::new();
-        if new_cputime.1.len() != cores {
-            return Err(Error::new("new cputime length is incorrect"));
-        }
-        for i in 0..cores {
-            utilizations.push(new_cputime.1[i].utilization(cputime.1[i]));
-        }
+        let breakdown = new_cputime.0.breakdown(cputime.0);
+        let utilizations = per_cpu_utilization(&cputime.1, &new_cputime.1);
         cputime = new_cputime;
 
         // Create barchart indicating per-core utilization
         let mut barchart = String::new();
         const BOXCHARS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
-        for utilization in &utilizations {
+        for (_, utilization) in &utilizations {
             barchart.push(BOXCHARS[(7.5 * utilization) as usize]);
         }
 
@@ -118,14 +140,20 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         let mut values = map!(
             "icon" => Value::icon(api.get_icon(icon)?),
             "barchart" => Value::text(barchart),
-            "frequency" => Value::hertz(freq_avg),
+            [if let Some(freq_avg) = freq_avg] "frequency" => Value::hertz(freq_avg),
             "utilization" => Value::percents(utilization_avg * 100.),
+            "user_percents" => Value::percents(breakdown.user * 100.),
+            "system_percents" => Value::percents(breakdown.system * 100.),
+            "irq_percents" => Value::percents(breakdown.irq * 100.),
+            "iowait_percents" => Value::percents(breakdown.iowait * 100.),
+            "steal_percents" => Value::percents(breakdown.steal * 100.),
+            [if in_vm] "in_vm" => Value::flag(),
         );
         boost.map(|b| values.insert("boost".into(), Value::icon(b)));
         for (i, freq) in freqs.iter().enumerate() {
             values.insert(format!("frequency{}", i + 1).into(), Value::hertz(*freq));
         }
-        for (i, utilization) in utilizations.iter().enumerate() {
+        for (i, utilization) in &utilizations {
             values.insert(
                 format!("utilization{}", i + 1).into(),
                 Value::percents(utilization * 100.),
@@ -133,8 +161,12 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         }
 
         widget.set_values(values);
+        let steal = breakdown.steal * 100.;
+        let above = |threshold: Option<f64>| threshold.is_some_and(|t| steal > t);
         widget.state = match utilization_avg {
+            _ if above(config.steal_critical) => State::Critical,
             x if x > 0.9 => State::Critical,
+            _ if above(config.steal_warning) => State::Warning,
             x if x > 0.6 => State::Warning,
             x if x > 0.3 => State::Info,
             _ => State::Idle,
@@ -188,64 +220,175 @@ async fn read_frequencies() -> Result<Vec<f64>> {
     Ok(freqs)
 }
 
-#[derive(Debug, Clone, Copy)]
+/// CPU time in clock ticks, as listed in `/proc/stat`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct CpuTime {
+    /// Including niced processes
+    user: u64,
+    system: u64,
     idle: u64,
-    non_idle: u64,
+    iowait: u64,
+    /// Including softirqs
+    irq: u64,
+    /// Absent before Linux 2.6.11
+    steal: u64,
+}
+
+/// The shares of each kind of CPU time between two samples, between 0 and 1
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Breakdown {
+    user: f64,
+    system: f64,
+    iowait: f64,
+    irq: f64,
+    steal: f64,
 }
 
 impl CpuTime {
     fn from_str(s: &str) -> Option<Self> {
         let mut s = s.trim().split_ascii_whitespace();
-        let user = u64::from_str(s.next()?).ok()?;
-        let nice = u64::from_str(s.next()?).ok()?;
-        let system = u64::from_str(s.next()?).ok()?;
-        let idle = u64::from_str(s.next()?).ok()?;
-        let iowait = u64::from_str(s.next()?).ok()?;
-        let irq = u64::from_str(s.next()?).ok()?;
-        let softirq = u64::from_str(s.next()?).ok()?;
+        let mut next = || u64::from_str(s.next()?).ok();
+        let user = next()?;
+        let nice = next()?;
+        let system = next()?;
+        let idle = next()?;
+        let iowait = next()?;
+        let irq = next()?;
+        let softirq = next()?;
+        let steal = next().unwrap_or(0);
 
         Some(Self {
-            idle: idle + iowait,
-            non_idle: user + nice + system + irq + softirq,
+            user: user + nice,
+            system,
+            idle,
+            iowait,
+            irq: irq + softirq,
+            steal,
         })
     }
 
+    fn idle(&self) -> u64 {
+        self.idle + self.iowait
+    }
+
+    fn non_idle(&self) -> u64 {
+        self.user + self.system + self.irq
+    }
+
     fn utilization(&self, old: Self) -> f64 {
-        let elapsed = (self.idle + self.non_idle) as f64 - (old.idle + old.non_idle) as f64;
-        ((self.non_idle - old.non_idle) as f64 / elapsed).clamp(0., 1.)
+        // The counters of a CPU are reset when it goes offline
+        let elapsed = (self.idle() + self.non_idle()).saturating_sub(old.idle() + old.non_idle());
+        if elapsed == 0 {
+            return 0.;
+        }
+        (self.non_idle().saturating_sub(old.non_idle()) as f64 / elapsed as f64).clamp(0., 1.)
+    }
+
+    /// The shares of the time elapsed since `old`, including steal time. Counters which went back
+    /// (e.g. because they were reset or wrapped around) count as zero.
+    fn breakdown(&self, old: Self) -> Breakdown {
+        let user = self.user.saturating_sub(old.user);
+        let system = self.system.saturating_sub(old.system);
+        let idle = self.idle.saturating_sub(old.idle);
+        let iowait = self.iowait.saturating_sub(old.iowait);
+        let irq = self.irq.saturating_sub(old.irq);
+        let steal = self.steal.saturating_sub(old.steal);
+        let elapsed = user + system + idle + iowait + irq + steal;
+        if elapsed == 0 {
+            return Breakdown::default();
+        }
+        let share = |x: u64| x as f64 / elapsed as f64;
+        Breakdown {
+            user: share(user),
+            system: share(system),
+            iowait: share(iowait),
+            irq: share(irq),
+            steal: share(steal),
+        }
     }
 }
 
-async fn read_proc_stat() -> Result<(CpuTime, Vec<CpuTime>)> {
-    let mut utilizations = Vec::with_capacity(32);
-    let mut total = None;
+/// Utilization of each logical CPU which is online in both samples, by the CPU's number
+fn per_cpu_utilization(old: &[(usize, CpuTime)], new: &[(usize, CpuTime)]) -> Vec<(usize, f64)> {
+    new.iter()
+        .filter_map(|(cpu, time)| {
+            let (_, old_time) = old.iter().find(|(old_cpu, _)| old_cpu == cpu)?;
+            Some((*cpu, time.utilization(*old_time)))
+        })
+        .collect()
+}
 
-    let file = File::open("/proc/stat")
+/// Read the total CPU time and the CPU time of each logical CPU, along with the CPU's number.
+/// Offline CPUs are not listed.
+async fn read_proc_stat() -> Result<(CpuTime, Vec<(usize, CpuTime)>)> {
+    let text = read_file("/proc/stat")
         .await
         .error("failed to read /proc/stat")?;
-    let mut file = BufReader::new(file);
+    parse_proc_stat(&text)
+}
 
-    let mut line = String::new();
-    while file
-        .read_line(&mut line)
-        .await
-        .error("failed to read /proc/sta")?
-        != 0
-    {
-        // Total time
-        let data = line.trim_start_matches(|c: char| !c.is_ascii_whitespace());
-        if line.starts_with("cpu ") {
+fn parse_proc_stat(text: &str) -> Result<(CpuTime, Vec<(usize, CpuTime)>)> {
+    let mut utilizations = Vec::with_capacity(32);
+    let mut total = None;
+
+    for line in text.lines() {
+        let (name, data) = match line.split_once(|c: char| c.is_ascii_whitespace()) {
+            Some(split) => split,
+            None => continue,
+        };
+        if name == "cpu" {
+            // Total time
             total = Some(CpuTime::from_str(data).error("failed to parse /proc/stat")?);
-        } else if line.starts_with("cpu") {
-            utilizations.push(CpuTime::from_str(data).error("failed to parse /proc/stat")?);
+        } else if let Some(cpu) = name.strip_prefix("cpu") {
+            let cpu = cpu.parse().error("failed to parse /proc/stat")?;
+            let time = CpuTime::from_str(data).error("failed to parse /proc/stat")?;
+            utilizations.push((cpu, time));
         }
-        line.clear();
     }
 
     Ok((total.error("failed to parse /proc/stat")?, utilizations))
 }
 
+/// Whether the system runs in a virtual machine, judging by the DMI product and vendor names, or
+/// else by `systemd-detect-virt`
+async fn detect_vm(command_context: &CommandContext) -> bool {
+    let mut dmi = String::new();
+    for file in ["product_name", "sys_vendor"] {
+        if let Ok(name) = read_file(format!("/sys/class/dmi/id/{file}")).await {
+            dmi.push_str(&name);
+            dmi.push('\n');
+        }
+    }
+    if !dmi.is_empty() && is_vm_dmi(&dmi) {
+        return true;
+    }
+    command_context
+        .command("systemd-detect-virt")
+        .args(["--vm", "--quiet"])
+        .status()
+        .await
+        .is_ok_and(|status| status.success())
+}
+
+/// Whether DMI product or vendor names are those of a hypervisor
+fn is_vm_dmi(dmi: &str) -> bool {
+    const HYPERVISORS: &[&str] = &[
+        "KVM",
+        "QEMU",
+        "VirtualBox",
+        "VMware",
+        "Xen",
+        "Bochs",
+        "Parallels",
+        "Virtual Machine",
+        "Google Compute Engine",
+        "DigitalOcean",
+        "OpenStack",
+        "Amazon EC2",
+    ];
+    HYPERVISORS.iter().any(|name| dmi.contains(name))
+}
+
 /// Read the cpu turbo boost status from kernel sys interface
 /// or intel pstate interface
 async fn boost_status() -> Option<bool> {
@@ -257,3 +400,92 @@ async fn boost_status() -> Option<bool> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_goes_offline() {
+        let old = parse_proc_stat(
+            "cpu  300 0 100 600 0 0 0 0 0 0
+cpu0 100 0 50 350 0 0 0 0 0 0
+cpu1 200 0 50 250 0 0 0 0 0 0
+intr 12345 0 0",
+        )
+        .unwrap();
+        // cpu1 went offline and cpu2 came online
+        let new = parse_proc_stat(
+            "cpu  400 0 100 700 0 0 0 0 0 0
+cpu0 200 0 50 450 0 0 0 0 0 0
+cpu2 10 0 0 10 0 0 0 0 0 0",
+        )
+        .unwrap();
+        assert_eq!(
+            old.1.iter().map(|(cpu, _)| *cpu).collect::<Vec<_>>(),
+            [0, 1]
+        );
+        assert_eq!(new.0.utilization(old.0), 0.5);
+        assert_eq!(per_cpu_utilization(&old.1, &new.1), [(0, 0.5)]);
+    }
+
+    #[test]
+    fn breakdown() {
+        let sample = |line: &str| parse_proc_stat(line).unwrap().0;
+        let base = sample("cpu  100 0 100 700 50 25 25 0 0 0");
+        // (description, new sample, expected shares of user, system, iowait, irq and steal)
+        let cases = [
+            (
+                "all kinds of time",
+                "cpu  160 20 120 920 100 30 30 20 0 0",
+                [0.2, 0.05, 0.125, 0.025, 0.05],
+            ),
+            ("first sample", "cpu  100 0 100 700 50 25 25 0 0 0", [0.; 5]),
+            (
+                "only steal",
+                "cpu  100 0 100 700 50 25 25 100 0 0",
+                [0., 0., 0., 0., 1.],
+            ),
+            (
+                "no steal column",
+                "cpu  150 0 100 700 50 25 25",
+                [1., 0., 0., 0., 0.],
+            ),
+            (
+                "user counter wrapped around",
+                "cpu  10 0 150 750 50 25 25 0 0 0",
+                [0., 0.5, 0., 0., 0.],
+            ),
+            ("all counters reset", "cpu  1 0 1 1 0 0 0 0 0 0", [0.; 5]),
+        ];
+        for (description, line, expected) in cases {
+            let b = sample(line).breakdown(base);
+            assert_eq!(
+                [b.user, b.system, b.iowait, b.irq, b.steal],
+                expected,
+                "{description}"
+            );
+        }
+        // Steal time does not count as utilization
+        assert_eq!(
+            sample("cpu  100 0 100 700 50 25 25 100 0 0").utilization(base),
+            0.
+        );
+    }
+
+    #[test]
+    fn vm_detection() {
+        assert!(is_vm_dmi("Standard PC (Q35 + ICH9, 2009)\nQEMU\n"));
+        assert!(is_vm_dmi("VirtualBox\ninnotek GmbH\n"));
+        assert!(is_vm_dmi("Virtual Machine\nMicrosoft Corporation\n"));
+        assert!(!is_vm_dmi("ThinkPad X1 Carbon Gen 9\nLENOVO\n"));
+    }
+
+    #[test]
+    fn counters_reset() {
+        let old = CpuTime::from_str("100 0 50 350 0 0 0").unwrap();
+        let new = CpuTime::from_str("10 0 0 10 0 0 0").unwrap();
+        assert_eq!(new.utilization(old), 0.);
+        assert_eq!(old.utilization(old), 0.);
+    }
+}
@@ -22,6 +22,9 @@
 //! `hide_when_empty` | Hides the block when the command output (or json text field) is empty | `false`
 //! `shell` | Specify the shell to use when running commands | `$SHELL` if set, otherwise fallback to `sh`
 //!
+//! If the command exits with a non-zero status and writes something to stderr, the block's state
+//! is set to critical and the first line of stderr is displayed as `text`.
+//!
 //! Placeholder      | Value                                                      | Type   | Unit
 //! -----------------|------------------------------------------------------------|--------|---------------
 //! `icon`           | Value of icon field from JSON output when it's non-empty   | Icon   | -
@@ -96,10 +99,10 @@
 //! - Use `shellexpand`
 
 use super::prelude::*;
+use crate::escape::CollectEscaped;
 use inotify::{Inotify, WatchMask};
 use std::process::Stdio;
 use tokio::io::{self, AsyncBufReadExt, BufReader};
-use tokio::process::Command;
 
 #[derive(Deserialize, Debug, SmartDefault)]
 #[serde(default)]
@@ -141,6 +144,7 @@ async fn update_bar(
     } else {
         text_empty = stdout.is_empty();
         widget.set_values(map!("text" => Value::text(stdout.into())));
+        widget.state = State::Idle;
     }
 
     if text_empty && hide_when_empty {
@@ -189,7 +193,9 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         .unwrap_or_else(|| "sh".to_string());
 
     if config.persistent {
-        let mut process = Command::new(&shell)
+        let mut process = api
+            .command_context
+            .command(&shell)
             .args([
                 "-c",
                 config
@@ -232,7 +238,9 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
 
         loop {
             // Run command
-            let output = Command::new(&shell)
+            let output = api
+                .command_context
+                .command(&shell)
                 .args(["-c", &cmd])
                 .output()
                 .await
@@ -240,15 +248,24 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             let stdout = std::str::from_utf8(&output.stdout)
                 .error("the output of command is invalid UTF-8")?
                 .trim();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = stderr.lines().map(str::trim).find(|l| !l.is_empty());
 
-            update_bar(
-                stdout,
-                config.hide_when_empty,
-                config.json,
-                &mut api,
-                &mut widget,
-            )
-            .await?;
+            if let (false, Some(stderr)) = (output.status.success(), stderr) {
+                let text = stderr.chars().collect_pango_escaped();
+                widget.set_values(map!("text" => Value::text(text)));
+                widget.state = State::Critical;
+                api.set_widget(&widget).await?;
+            } else {
+                update_bar(
+                    stdout,
+                    config.hide_when_empty,
+                    config.json,
+                    &mut api,
+                    &mut widget,
+                )
+                .await?;
+            }
 
             loop {
                 select! {
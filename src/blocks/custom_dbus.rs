@@ -45,23 +45,18 @@
 //!
 //! Because it's impossible to publish objects to the same name from different
 //! processes, having multiple dbus blocks in different bars won't work. As a workaround,
-//! you can set the env var `I3RS_DBUS_NAME` to set the interface a bar works on to
-//! differentiate between different processes. For example, setting this to 'top', will allow you
-//! to use `rs.i3status.top`.
+//! you can set the env var `I3RS_DBUS_NAME` or pass `--dbus-name` to set the interface a bar works
+//! on to differentiate between different processes. For example, setting this to 'top', will allow
+//! you to use `rs.i3status.top`. The bar itself can be controlled with the same name, see
+//! [`control`](crate::control).
 //!
 //! # TODO
 //! - Send a signal on click?
 
 use super::prelude::*;
-use std::env;
+use crate::control::dbus_connection;
 use zbus::{dbus_interface, fdo};
 
-// Share DBus connection between multiple block instances
-static DBUS_CONNECTION: async_once_cell::OnceCell<Result<zbus::Connection>> =
-    async_once_cell::OnceCell::new();
-
-const DBUS_NAME: &str = "rs.i3status";
-
 #[derive(Deserialize, Debug)]
 pub struct Config {
     #[serde(default)]
@@ -77,7 +72,7 @@ struct Block {
     short_text: Option<String>,
 }
 
-fn block_values(block: &Block, api: &CommonApi) -> Result<HashMap<Cow<'static, str>, Value>> {
+fn block_values(block: &Block, api: &CommonApi) -> Result<Values> {
     Ok(map! {
         [if let Some(icon) = &block.icon] "icon" => Value::icon(api.get_icon(icon)?),
         [if let Some(text) = &block.text] "text" => Value::text(text.to_string()),
@@ -131,12 +126,8 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         "{ $icon|} $short_text.str(pango:true) |",
     )?);
 
-    let dbus_conn = DBUS_CONNECTION
-        .get_or_init(dbus_conn())
-        .await
-        .as_ref()
-        .map_err(Clone::clone)?;
-    dbus_conn
+    dbus_connection()
+        .await?
         .object_server()
         .at(
             config.path,
@@ -152,16 +143,3 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         .error("Failed to setup DBus server")?;
     Ok(())
 }
-
-async fn dbus_conn() -> Result<zbus::Connection> {
-    let dbus_interface_name = match env::var("I3RS_DBUS_NAME") {
-        Ok(v) => format!("{DBUS_NAME}.{v}"),
-        Err(_) => DBUS_NAME.to_string(),
-    };
-
-    let conn = new_dbus_connection().await?;
-    conn.request_name(dbus_interface_name)
-        .await
-        .error("Failed to request DBus name")?;
-    Ok(conn)
-}
@@ -1,17 +1,26 @@
 //! Disk usage statistics
 //!
+//! `path` may be a list of paths, in which case `format` is shown once for each path, in order.
+//! Each path has its own state, and clicks on a path can be configured with `widget` set to the
+//! path, as given in the config.
+//!
+//! If the filesystem does not respond within `interval` (e.g. an unreachable network share), the
+//! block shows an error instead of hanging.
+//!
 //! # Configuration
 //!
 //! Key | Values | Default
 //! ----|--------|--------
-//! `path` | Path to collect information from. Supports path expansions e.g. `~`. | `"/"`
+//! `path` | Path to collect information from, or a list of paths. Supports path expansions e.g. `~`. | `"/"`
 //! `interval` | Update time in seconds | `20`
 //! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $available "`
 //! `format_alt` | If set, block will switch between `format` and `format_alt` on every click | `None`
-//! `warning` | A value which will trigger warning block state | `20.0`
-//! `alert` | A value which will trigger critical block state | `10.0`
+//! `warning` | A value which will trigger warning block state. Either a number in `alert_unit` or a string with a unit, e.g. `"15%"` or `"20GiB"` | `20.0`, or `80.0` if `info_type` is `"used"`
+//! `alert` | A value which will trigger critical block state. Either a number in `alert_unit` or a string with a unit, e.g. `"10%"` or `"10GiB"` | `10.0`, or `90.0` if `info_type` is `"used"`
 //! `info_type` | Determines which information will affect the block state. Possible values are `"available"`, `"free"` and `"used"` | `"available"`
-//! `alert_unit` | The unit of `alert` and `warning` options. If not set, percents are uesd. Possible values are `"B"`, `"KB"`, `"MB"`, `"GB"` and `"TB"` | `None`
+//! `alert_unit` | The unit of `alert` and `warning` options given as bare numbers. Possible values are `"%"`, `"B"`, `"KB"`, `"KiB"`, `"MB"`, `"MiB"`, `"GB"`, `"GiB"`, `"TB"` and `"TiB"`. It does not affect how values are displayed. | `"%"`
+//! `inode_warning` | A percentage of free inodes below which the state is set to warning, if it is not already worse | `None`
+//! `inode_alert` | A percentage of free inodes below which the state is set to critical | `None`
 //!
 //! Placeholder  | Value                                                              | Type   | Unit
 //! -------------|--------------------------------------------------------------------|--------|-------
@@ -22,6 +31,9 @@
 //! `used`       | Used disk space                                                    | Number | Bytes
 //! `free`       | Free disk space                                                    | Number | Bytes
 //! `available`  | Available disk space (free disk space minus reserved system space) | Number | Bytes
+//! `inodes_total` | Total number of inodes                                           | Number | -
+//! `inodes_free`  | Number of free inodes                                            | Number | -
+//! `inodes_free_percentage` | Percentage of free inodes. `100` if the filesystem does not have a fixed number of inodes, e.g. btrfs | Number | %
 //!
 //! Action          | Description                               | Default button
 //! ----------------|-------------------------------------------|---------------
@@ -40,6 +52,34 @@
 //! format_alt = " $icon $available / $total "
 //! ```
 //!
+//! The same thresholds with units:
+//!
+//! ```toml
+//! [[block]]
+//! block = "disk_space"
+//! alert = "10GB"
+//! warning = "15GB"
+//! ```
+//!
+//! With `info_type = "used"` the state is set when the value reaches or exceeds the thresholds,
+//! otherwise when it reaches or falls below them. `warning` must therefore not be above `alert`
+//! for `"used"`, and not below it otherwise.
+//!
+//! Several paths in one block, also warning when inodes run out:
+//!
+//! ```toml
+//! [[block]]
+//! block = "disk_space"
+//! path = ["/", "/home", "/var"]
+//! format = " $path $available "
+//! inode_warning = 10.0
+//! inode_alert = 5.0
+//! [[block.click]]
+//! button = "right"
+//! widget = "/var"
+//! cmd = "baobab /var"
+//! ```
+//!
 //! Update block on right click:
 //!
 //! ```toml
@@ -56,8 +96,9 @@
 // make_log_macro!(debug, "disk_space");
 
 use super::prelude::*;
-use crate::formatting::prefix::Prefix;
-use nix::sys::statvfs::statvfs;
+use crate::util::statvfs_timeout;
+use crate::widget::WidgetPart;
+use nix::sys::statvfs::Statvfs;
 
 #[derive(Copy, Clone, Debug, Deserialize, SmartDefault)]
 #[serde(rename_all = "lowercase")]
@@ -71,102 +112,86 @@ pub enum InfoType {
 #[derive(Deserialize, Debug, SmartDefault)]
 #[serde(default)]
 pub struct Config {
-    #[default("/".into())]
-    path: ShellString,
+    #[default(Paths::Single("/".into()))]
+    path: Paths,
     info_type: InfoType,
     format: FormatConfig,
     format_alt: Option<FormatConfig>,
     alert_unit: Option<String>,
     #[default(20.into())]
     interval: Seconds,
-    #[default(20.0)]
-    warning: f64,
-    #[default(10.0)]
-    alert: f64,
+    warning: Option<Threshold>,
+    alert: Option<Threshold>,
+    inode_warning: Option<f64>,
+    inode_alert: Option<f64>,
 }
 
-pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Paths {
+    Single(ShellString),
+    Multiple(Vec<ShellString>),
+}
+
+pub async fn run(mut config: Config, mut api: CommonApi) -> Result<()> {
     api.set_default_actions(&[(MouseButton::Left, None, "toggle_format")])
         .await?;
 
-    let mut format = config.format.with_default(" $icon $available ")?;
-    let mut format_alt = match config.format_alt {
+    let mut format = std::mem::take(&mut config.format).with_default(" $icon $available ")?;
+    let mut format_alt = match config.format_alt.take() {
         Some(f) => Some(f.with_default("")?),
         None => None,
     };
 
     let mut widget = Widget::new().with_format(format.clone());
 
-    let unit = match config.alert_unit.as_deref() {
-        Some("TB") => Some(Prefix::Tera),
-        Some("GB") => Some(Prefix::Giga),
-        Some("MB") => Some(Prefix::Mega),
-        Some("KB") => Some(Prefix::Kilo),
-        Some("B") => Some(Prefix::One),
-        Some(x) => return Err(Error::new(format!("Unknown unit: '{x}'"))),
-        None => None,
-    };
+    let thresholds = Thresholds::new(&config)?;
 
-    let path = config.path.expand()?;
+    let paths = match &config.path {
+        Paths::Single(path) => vec![path],
+        Paths::Multiple(paths) => paths.iter().collect(),
+    };
+    let paths = paths
+        .into_iter()
+        .map(|path| Ok((path.0.to_string(), path.expand()?.into_owned())))
+        .collect::<Result<Vec<_>>>()?;
 
     let mut timer = config.interval.timer();
 
     loop {
-        let statvfs = statvfs(&*path).error("failed to retrieve statvfs")?;
-
-        let total = statvfs.blocks() * statvfs.fragment_size();
-        let used = (statvfs.blocks() - statvfs.blocks_free()) * statvfs.fragment_size();
-        let available = statvfs.blocks_available() * statvfs.block_size();
-        let free = statvfs.blocks_free() * statvfs.block_size();
-
-        let result = match config.info_type {
-            InfoType::Available => available,
-            InfoType::Free => free,
-            InfoType::Used => used,
-        } as f64;
-
-        let percentage = result / (total as f64) * 100.;
-        widget.set_values(map! {
-            "icon" => Value::icon(api.get_icon("disk_drive")?),
-            "path" => Value::text(path.to_string()),
-            "percentage" => Value::percents(percentage),
-            "total" => Value::bytes(total as f64),
-            "used" => Value::bytes(used as f64),
-            "available" => Value::bytes(available as f64),
-            "free" => Value::bytes(free as f64),
-        });
-
-        // Send percentage to alert check if we don't want absolute alerts
-        let alert_val_in_config_units = match unit {
-            Some(Prefix::Tera) => result * 1e-12,
-            Some(Prefix::Giga) => result * 1e-9,
-            Some(Prefix::Mega) => result * 1e-6,
-            Some(Prefix::Kilo) => result * 1e-3,
-            Some(_) => result,
-            None => percentage,
-        };
+        let mut parts = Vec::new();
+        for (name, path) in &paths {
+            let statvfs = statvfs_timeout(path.into(), config.interval.0)
+                .await?
+                .or_error(|| format!("statvfs of {path} did not return in time"))?;
+            let (mut values, state) = usage(&config, &thresholds, path, &statvfs);
+            values.insert("icon".into(), Value::icon(api.get_icon("disk_drive")?));
+            parts.push(WidgetPart {
+                name: name.clone(),
+                state,
+                values,
+            });
+        }
 
-        // Compute state
-        widget.state = match config.info_type {
-            InfoType::Used => {
-                if alert_val_in_config_units >= config.alert {
-                    State::Critical
-                } else if alert_val_in_config_units >= config.warning {
-                    State::Warning
-                } else {
-                    State::Idle
-                }
+        match config.path {
+            Paths::Single(_) => {
+                let part = parts.pop().unwrap();
+                widget.state = part.state;
+                widget.set_values(part.values);
             }
-            InfoType::Free | InfoType::Available => {
-                if alert_val_in_config_units <= config.alert {
-                    State::Critical
-                } else if alert_val_in_config_units <= config.warning {
-                    State::Warning
-                } else {
-                    State::Idle
-                }
+            Paths::Multiple(_) => {
+                widget.state = parts
+                    .iter()
+                    .map(|part| part.state)
+                    .max_by_key(|state| match state {
+                        State::Critical => 2,
+                        State::Warning => 1,
+                        _ => 0,
+                    })
+                    .unwrap_or_default();
+                widget.set_parts(parts);
             }
-        };
+        }
 
         api.set_widget(&widget).await?;
 
@@ -188,3 +213,253 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    /// The state is set when the value reaches or exceeds the thresholds
+    Above,
+    /// The state is set when the value reaches or falls below the thresholds
+    Below,
+}
+
+/// `warning` and `alert`, each in percents or bytes
+#[derive(Debug, Clone, Copy)]
+struct Thresholds {
+    direction: Direction,
+    warning: Threshold,
+    alert: Threshold,
+}
+
+impl Thresholds {
+    fn new(config: &Config) -> Result<Self> {
+        let (direction, default_warning, default_alert) = match config.info_type {
+            InfoType::Used => (Direction::Above, 80.0, 90.0),
+            InfoType::Available | InfoType::Free => (Direction::Below, 20.0, 10.0),
+        };
+        let unit = config.alert_unit.as_deref().unwrap_or("%");
+        // Bare numbers are in `alert_unit`
+        let resolve = |threshold: Threshold| match threshold {
+            Threshold::Number(x) => format!("{x}{unit}")
+                .parse()
+                .or_error(|| format!("Unknown unit: '{unit}'")),
+            threshold => Ok(threshold),
+        };
+        let warning = resolve(config.warning.unwrap_or(Threshold::Number(default_warning)))?;
+        let alert = resolve(config.alert.unwrap_or(Threshold::Number(default_alert)))?;
+
+        for threshold in [warning, alert] {
+            if !matches!(threshold, Threshold::Percents(_) | Threshold::Bytes(_)) {
+                return Err(Error::new("Thresholds must be percents or sizes"));
+            }
+        }
+        // Thresholds in different units cannot be compared
+        if std::mem::discriminant(&warning) == std::mem::discriminant(&alert) {
+            let consistent = match direction {
+                Direction::Above => warning.value() <= alert.value(),
+                Direction::Below => warning.value() >= alert.value(),
+            };
+            if !consistent {
+                return Err(Error::new(match direction {
+                    Direction::Above => {
+                        "With info_type = \"used\", warning must not be above alert"
+                    }
+                    Direction::Below => "warning must not be below alert",
+                }));
+            }
+        }
+
+        Ok(Self {
+            direction,
+            warning,
+            alert,
+        })
+    }
+
+    /// The state for a value of `percentage` percents or `bytes` bytes
+    fn state(&self, percentage: f64, bytes: f64) -> State {
+        let current = |threshold: Threshold| match threshold {
+            Threshold::Percents(_) => percentage,
+            _ => bytes,
+        };
+        let warning = compute_state(
+            self.direction,
+            current(self.warning),
+            self.warning.value(),
+            f64::NAN,
+        );
+        let alert = compute_state(
+            self.direction,
+            current(self.alert),
+            f64::NAN,
+            self.alert.value(),
+        );
+        if alert == State::Critical {
+            State::Critical
+        } else {
+            warning
+        }
+    }
+}
+
+/// The state of `value`, which is critical once it reaches `alert` and warning once it reaches
+/// `warning`, in `direction`. Comparisons with NaN thresholds are always false.
+fn compute_state(direction: Direction, value: f64, warning: f64, alert: f64) -> State {
+    let reaches = |threshold: f64| match direction {
+        Direction::Above => value >= threshold,
+        Direction::Below => value <= threshold,
+    };
+    if reaches(alert) {
+        State::Critical
+    } else if reaches(warning) {
+        State::Warning
+    } else {
+        State::Idle
+    }
+}
+
+/// The values and the state of the filesystem at `path`
+fn usage(
+    config: &Config,
+    thresholds: &Thresholds,
+    path: &str,
+    statvfs: &Statvfs,
+) -> (Values, State) {
+    let total = statvfs.blocks() * statvfs.fragment_size();
+    let used = (statvfs.blocks() - statvfs.blocks_free()) * statvfs.fragment_size();
+    let available = statvfs.blocks_available() * statvfs.block_size();
+    let free = statvfs.blocks_free() * statvfs.block_size();
+
+    let result = match config.info_type {
+        InfoType::Available => available,
+        InfoType::Free => free,
+        InfoType::Used => used,
+    } as f64;
+
+    let percentage = result / (total as f64) * 100.;
+
+    // Some filesystems allocate inodes dynamically and report none
+    let inodes_free_percentage = match statvfs.files() {
+        0 => 100.,
+        files => statvfs.files_free() as f64 / files as f64 * 100.,
+    };
+
+    let values = map! {
+        "path" => Value::text(path.into()),
+        "percentage" => Value::percents(percentage),
+        "total" => Value::bytes(total as f64),
+        "used" => Value::bytes(used as f64),
+        "available" => Value::bytes(available as f64),
+        "free" => Value::bytes(free as f64),
+        "inodes_total" => Value::number(statvfs.files()),
+        "inodes_free" => Value::number(statvfs.files_free()),
+        "inodes_free_percentage" => Value::percents(inodes_free_percentage),
+    };
+
+    let state = thresholds.state(percentage, result);
+
+    let below = |threshold: Option<f64>| threshold.is_some_and(|t| inodes_free_percentage <= t);
+    let state = if state == State::Critical || below(config.inode_alert) {
+        State::Critical
+    } else if state == State::Warning || below(config.inode_warning) {
+        State::Warning
+    } else {
+        State::Idle
+    };
+
+    (values, state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        let config: Config = toml::from_str(r#"path = "~/data""#).unwrap();
+        assert!(matches!(config.path, Paths::Single(p) if p.0 == "~/data"));
+        let config: Config = toml::from_str(r#"path = ["/", "/home"]"#).unwrap();
+        assert!(matches!(config.path, Paths::Multiple(p) if p.len() == 2));
+        let config: Config = toml::from_str("").unwrap();
+        assert!(matches!(config.path, Paths::Single(p) if p.0 == "/"));
+    }
+
+    #[test]
+    fn usage_of_root() {
+        let config = Config {
+            inode_alert: Some(101.0),
+            ..Config::default()
+        };
+        let thresholds = Thresholds::new(&config).unwrap();
+        let (values, state) = usage(
+            &config,
+            &thresholds,
+            "/",
+            &nix::sys::statvfs::statvfs("/").unwrap(),
+        );
+        assert!(values.contains_key("inodes_free_percentage"));
+        assert_eq!(state, State::Critical);
+    }
+
+    #[test]
+    fn states() {
+        use Direction::*;
+        assert_eq!(compute_state(Below, 30.0, 20.0, 10.0), State::Idle);
+        assert_eq!(compute_state(Below, 20.0, 20.0, 10.0), State::Warning);
+        assert_eq!(compute_state(Below, 15.0, 20.0, 10.0), State::Warning);
+        assert_eq!(compute_state(Below, 10.0, 20.0, 10.0), State::Critical);
+        assert_eq!(compute_state(Below, 0.0, 20.0, 10.0), State::Critical);
+
+        assert_eq!(compute_state(Above, 50.0, 80.0, 90.0), State::Idle);
+        assert_eq!(compute_state(Above, 80.0, 80.0, 90.0), State::Warning);
+        assert_eq!(compute_state(Above, 85.0, 80.0, 90.0), State::Warning);
+        assert_eq!(compute_state(Above, 90.0, 80.0, 90.0), State::Critical);
+        assert_eq!(compute_state(Above, 100.0, 80.0, 90.0), State::Critical);
+    }
+
+    #[test]
+    fn thresholds() {
+        let thresholds = |toml: &str| Thresholds::new(&toml::from_str(toml).unwrap());
+
+        // The default thresholds of "used" can be reached
+        let used = thresholds(r#"info_type = "used""#).unwrap();
+        assert_eq!(used.state(50.0, 0.0), State::Idle);
+        assert_eq!(used.state(85.0, 0.0), State::Warning);
+        assert_eq!(used.state(90.0, 0.0), State::Critical);
+
+        let available = thresholds("").unwrap();
+        assert_eq!(available.state(20.0, 0.0), State::Warning);
+        assert_eq!(available.state(5.0, 0.0), State::Critical);
+
+        // Bare numbers are in `alert_unit`
+        let gib = thresholds(
+            r#"alert_unit = "GiB"
+            warning = 20
+            alert = "5GB""#,
+        )
+        .unwrap();
+        assert_eq!(gib.state(0.0, 20.0 * 1024f64.powi(3)), State::Warning);
+        assert_eq!(gib.state(0.0, 5e9), State::Critical);
+        assert_eq!(gib.state(0.0, 6e9), State::Warning);
+        assert!(matches!(
+            thresholds(r#"alert_unit = "%""#).unwrap().alert,
+            Threshold::Percents(x) if x == 10.0
+        ));
+
+        // Inconsistent thresholds
+        assert!(thresholds(
+            r#"info_type = "used"
+            warning = 20
+            alert = 10"#
+        )
+        .is_err());
+        assert!(thresholds("warning = 10\nalert = 20").is_err());
+        // Thresholds in different units are not compared
+        assert!(thresholds(
+            r#"warning = "10%"
+            alert = "20GB""#
+        )
+        .is_ok());
+        assert!(thresholds(r#"alert_unit = "h""#).is_err());
+        assert!(thresholds(r#"alert_unit = "parsecs""#).is_err());
+    }
+}
@@ -0,0 +1,592 @@
+//! Scale and resolution of a Wayland output
+//!
+//! Shows the current scale and mode of an output and cycles through a list of presets on click,
+//! e.g. to switch between 1x and 1.5x scale when connecting a HiDPI laptop to a projector. After a
+//! preset is applied the actual state is read back from the compositor (which may clamp the
+//! values), and the block is shown in warning state if it does not match the requested preset.
+//!
+//! Only `sway` is currently supported.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `driver` | Which driver to use. Available values: `sway_ipc` | `"sway_ipc"`
+//! `output` | Name of the output, e.g. `"eDP-1"`. If not set, the focused output is used. | `None`
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $scale $resolution "`
+//! `interval` | Update interval in seconds | `5`
+//! `presets` | A list of presets to cycle through. See below. | `[]`
+//!
+//! Each entry of `presets` has the following keys:
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `scale` | Scale factor | Required
+//! `mode` | Mode in `<width>x<height>` or `<width>x<height>@<refresh>Hz` form. If not set, the mode is not changed. | `None`
+//!
+//! Placeholder  | Value                                    | Type | Unit
+//! -------------|------------------------------------------|------|-----
+//! `icon`       | A static icon                            | Icon | -
+//! `output`     | Name of the output                       | Text | -
+//! `scale`      | Current scale, e.g. `1.5x`               | Text | -
+//! `resolution` | Current resolution, e.g. `2256x1504`     | Text | -
+//!
+//! Action        | Default button
+//! --------------|---------------
+//! `next_preset` | Left
+//! `prev_preset` | Right
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "display_scale"
+//! output = "eDP-1"
+//! [[block.presets]]
+//! scale = 1.5
+//! mode = "2256x1504"
+//! [[block.presets]]
+//! scale = 1
+//! mode = "1920x1080@60Hz"
+//! ```
+//!
+//! # Icons Used
+//! - `resolution`
+
+use super::prelude::*;
+use std::str::FromStr;
+use swayipc_async::{Connection, Output};
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    driver: Driver,
+    output: Option<String>,
+    format: FormatConfig,
+    #[default(5.into())]
+    interval: Seconds,
+    presets: Vec<Preset>,
+}
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(rename_all = "snake_case")]
+enum Driver {
+    #[default]
+    SwayIpc,
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[
+        (MouseButton::Left, None, "next_preset"),
+        (MouseButton::Right, None, "prev_preset"),
+    ])
+    .await?;
+
+    let mut widget =
+        Widget::new().with_format(config.format.with_default(" $icon $scale $resolution ")?);
+
+    let mut backend: Box<dyn Backend + Send> = match config.driver {
+        Driver::SwayIpc => Box::new(SwayIpc::new().await?),
+    };
+
+    let mut timer = config.interval.timer();
+    // The last preset applied by the block, if any
+    let mut requested: Option<usize> = None;
+
+    loop {
+        let state = current_state(&mut *backend, config.output.as_deref()).await?;
+
+        match &state {
+            Some(state) => {
+                widget.state = match requested {
+                    Some(i) if !config.presets[i].matches(state) => State::Warning,
+                    _ => State::Idle,
+                };
+                widget.set_values(map! {
+                    "icon" => Value::icon(api.get_icon("resolution")?),
+                    "output" => Value::text(state.name.clone()),
+                    "scale" => Value::text(format!("{}x", state.scale)),
+                    "resolution" => Value::text(format!("{}x{}", state.width, state.height)),
+                });
+                api.set_widget(&widget).await?;
+            }
+            None => api.hide().await?,
+        }
+
+        loop {
+            select! {
+                _ = timer.tick() => break,
+                event = api.event() => match event {
+                    UpdateRequest => break,
+                    Action(a) if a == "next_preset" || a == "prev_preset" => {
+                        if let Some(state) = &state {
+                            let forward = a == "next_preset";
+                            if let Some(i) = next_preset(&config.presets, requested, state, forward) {
+                                requested = Some(i);
+                                apply_preset(&mut *backend, &state.name, &config.presets[i]).await?;
+                                break;
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Preset {
+    scale: f64,
+    #[serde(default)]
+    mode: Option<Mode>,
+}
+
+impl Preset {
+    /// The command which applies this preset to `output`
+    fn command(&self, output: &str) -> String {
+        match &self.mode {
+            Some(mode) => format!("output \"{output}\" mode {mode} scale {}", self.scale),
+            None => format!("output \"{output}\" scale {}", self.scale),
+        }
+    }
+
+    fn matches(&self, state: &OutputState) -> bool {
+        if (self.scale - state.scale).abs() >= 0.001 {
+            return false;
+        }
+        match &self.mode {
+            Some(mode) => {
+                mode.width == state.width
+                    && mode.height == state.height
+                    // Refresh rates are in mHz and compositors round them
+                    && match mode.refresh {
+                        Some(refresh) => (refresh - state.refresh).abs() <= 1000,
+                        None => true,
+                    }
+            }
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mode {
+    width: i32,
+    height: i32,
+    /// mHz
+    refresh: Option<i32>,
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let err =
+            || format!("'{s}' is not a valid mode, expected '<width>x<height>[@<refresh>Hz]'");
+        let (size, refresh) = match s.split_once('@') {
+            Some((size, refresh)) => (size, Some(refresh)),
+            None => (s, None),
+        };
+        let (width, height) = size.split_once('x').or_error(err)?;
+        let refresh = match refresh {
+            Some(refresh) => {
+                let hz: f64 = refresh.trim_end_matches("Hz").parse().or_error(err)?;
+                Some((hz * 1000.0).round() as i32)
+            }
+            None => None,
+        };
+        Ok(Self {
+            width: width.parse().or_error(err)?,
+            height: height.parse().or_error(err)?,
+            refresh,
+        })
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)?;
+        if let Some(refresh) = self.refresh {
+            write!(f, "@{}Hz", refresh as f64 / 1000.0)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for Mode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().serde_error()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct OutputState {
+    name: String,
+    scale: f64,
+    width: i32,
+    height: i32,
+    /// mHz
+    refresh: i32,
+}
+
+impl OutputState {
+    /// Find the configured output, or the focused one if `name` is `None`. Disabled outputs are
+    /// ignored.
+    fn find(outputs: &[Output], name: Option<&str>) -> Option<Self> {
+        let output = outputs.iter().find(|o| {
+            o.active
+                && match name {
+                    Some(name) => o.name == name,
+                    None => o.focused,
+                }
+        })?;
+        let mode = output.current_mode?;
+        Some(Self {
+            name: output.name.clone(),
+            scale: output.scale.unwrap_or(1.0),
+            width: mode.width,
+            height: mode.height,
+            refresh: mode.refresh,
+        })
+    }
+}
+
+/// The preset to apply when cycling from the current state. Starts from the last requested preset
+/// or from the preset matching the current state.
+fn next_preset(
+    presets: &[Preset],
+    requested: Option<usize>,
+    state: &OutputState,
+    forward: bool,
+) -> Option<usize> {
+    if presets.is_empty() {
+        return None;
+    }
+    let current = requested.or_else(|| presets.iter().position(|p| p.matches(state)));
+    Some(match (current, forward) {
+        (Some(i), true) => (i + 1) % presets.len(),
+        (Some(i), false) => (i + presets.len() - 1) % presets.len(),
+        (None, true) => 0,
+        (None, false) => presets.len() - 1,
+    })
+}
+
+async fn current_state(
+    backend: &mut (dyn Backend + Send),
+    output: Option<&str>,
+) -> Result<Option<OutputState>> {
+    Ok(OutputState::find(&backend.outputs().await?, output))
+}
+
+/// Apply the preset and read back the resulting state
+async fn apply_preset(
+    backend: &mut (dyn Backend + Send),
+    output: &str,
+    preset: &Preset,
+) -> Result<Option<OutputState>> {
+    backend.run_command(&preset.command(output)).await?;
+    current_state(backend, Some(output)).await
+}
+
+#[async_trait]
+trait Backend {
+    async fn outputs(&mut self) -> Result<Vec<Output>>;
+    async fn run_command(&mut self, command: &str) -> Result<()>;
+}
+
+struct SwayIpc {
+    connection: Connection,
+}
+
+impl SwayIpc {
+    async fn new() -> Result<Self> {
+        Ok(Self {
+            connection: Connection::new()
+                .await
+                .error("failed to open connection with swayipc")?,
+        })
+    }
+}
+
+#[async_trait]
+impl Backend for SwayIpc {
+    async fn outputs(&mut self) -> Result<Vec<Output>> {
+        self.connection
+            .get_outputs()
+            .await
+            .error("failed to get outputs")
+    }
+
+    async fn run_command(&mut self, command: &str) -> Result<()> {
+        for outcome in self
+            .connection
+            .run_command(command)
+            .await
+            .error("failed to run command")?
+        {
+            outcome.error("sway rejected the output command")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Captured with `swaymsg -t get_outputs -r`
+    const OUTPUTS: &str = r#"[
+      {
+        "id": 3,
+        "type": "output",
+        "orientation": "none",
+        "percent": 1.0,
+        "urgent": false,
+        "marks": [],
+        "layout": "output",
+        "border": "none",
+        "current_border_width": 0,
+        "rect": { "x": 0, "y": 0, "width": 1504, "height": 1003 },
+        "deco_rect": { "x": 0, "y": 0, "width": 0, "height": 0 },
+        "window_rect": { "x": 0, "y": 0, "width": 0, "height": 0 },
+        "geometry": { "x": 0, "y": 0, "width": 0, "height": 0 },
+        "name": "eDP-1",
+        "window": null,
+        "nodes": [],
+        "floating_nodes": [],
+        "focus": [4],
+        "fullscreen_mode": 0,
+        "sticky": false,
+        "primary": false,
+        "make": "BOE",
+        "model": "0x095F",
+        "serial": "Unknown",
+        "modes": [
+          { "width": 2256, "height": 1504, "refresh": 59999 },
+          { "width": 1920, "height": 1280, "refresh": 59999 }
+        ],
+        "non_desktop": false,
+        "active": true,
+        "dpms": true,
+        "power": true,
+        "scale": 1.5,
+        "scale_filter": "smart",
+        "transform": "normal",
+        "adaptive_sync_status": "disabled",
+        "current_workspace": "1",
+        "current_mode": { "width": 2256, "height": 1504, "refresh": 59999 },
+        "max_render_time": 0,
+        "focused": true,
+        "subpixel_hinting": "unknown"
+      },
+      {
+        "id": 5,
+        "type": "output",
+        "orientation": "none",
+        "percent": 1.0,
+        "urgent": false,
+        "marks": [],
+        "layout": "output",
+        "border": "none",
+        "current_border_width": 0,
+        "rect": { "x": 1504, "y": 0, "width": 1920, "height": 1080 },
+        "deco_rect": { "x": 0, "y": 0, "width": 0, "height": 0 },
+        "window_rect": { "x": 0, "y": 0, "width": 0, "height": 0 },
+        "geometry": { "x": 0, "y": 0, "width": 0, "height": 0 },
+        "name": "HDMI-A-1",
+        "window": null,
+        "nodes": [],
+        "floating_nodes": [],
+        "focus": [6],
+        "fullscreen_mode": 0,
+        "sticky": false,
+        "primary": false,
+        "make": "Epson",
+        "model": "EPSON PJ",
+        "serial": "0x01010101",
+        "modes": [
+          { "width": 1920, "height": 1080, "refresh": 60000 },
+          { "width": 1280, "height": 720, "refresh": 60000 }
+        ],
+        "non_desktop": false,
+        "active": true,
+        "dpms": true,
+        "power": true,
+        "scale": 1.0,
+        "scale_filter": "nearest",
+        "transform": "normal",
+        "adaptive_sync_status": "disabled",
+        "current_workspace": "2",
+        "current_mode": { "width": 1920, "height": 1080, "refresh": 60000 },
+        "max_render_time": 0,
+        "focused": false,
+        "subpixel_hinting": "unknown"
+      },
+      {
+        "type": "output",
+        "name": "DP-1",
+        "make": "Unknown",
+        "model": "Unknown",
+        "serial": "Unknown",
+        "active": false,
+        "dpms": false,
+        "power": false,
+        "primary": false,
+        "scale": -1.0,
+        "transform": "normal",
+        "current_workspace": null,
+        "modes": [],
+        "rect": { "x": 0, "y": 0, "width": 0, "height": 0 }
+      }
+    ]"#;
+
+    fn outputs() -> Vec<Output> {
+        serde_json::from_str(OUTPUTS).unwrap()
+    }
+
+    fn preset(scale: f64, mode: Option<&str>) -> Preset {
+        Preset {
+            scale,
+            mode: mode.map(|m| m.parse().unwrap()),
+        }
+    }
+
+    /// Applies `scale` and `mode` commands to the captured outputs, clamping the scale to
+    /// `max_scale` like a compositor might
+    struct FakeBackend {
+        outputs: Vec<Output>,
+        max_scale: f64,
+        commands: Vec<String>,
+    }
+
+    #[async_trait]
+    impl Backend for FakeBackend {
+        async fn outputs(&mut self) -> Result<Vec<Output>> {
+            Ok(self.outputs.clone())
+        }
+
+        async fn run_command(&mut self, command: &str) -> Result<()> {
+            self.commands.push(command.into());
+            let words: Vec<&str> = command.split(' ').collect();
+            let name = words[1].trim_matches('"');
+            let output = self.outputs.iter_mut().find(|o| o.name == name).unwrap();
+            for arg in words[2..].chunks(2) {
+                match arg[0] {
+                    "scale" => {
+                        output.scale = Some(arg[1].parse::<f64>().unwrap().min(self.max_scale));
+                    }
+                    "mode" => {
+                        let mode: Mode = arg[1].parse().unwrap();
+                        let current = output.current_mode.as_mut().unwrap();
+                        current.width = mode.width;
+                        current.height = mode.height;
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parse_outputs() {
+        let outputs = outputs();
+        let state = OutputState::find(&outputs, None).unwrap();
+        assert_eq!(
+            state,
+            OutputState {
+                name: "eDP-1".into(),
+                scale: 1.5,
+                width: 2256,
+                height: 1504,
+                refresh: 59999,
+            }
+        );
+        let state = OutputState::find(&outputs, Some("HDMI-A-1")).unwrap();
+        assert_eq!(state.scale, 1.0);
+        assert_eq!((state.width, state.height), (1920, 1080));
+        assert_eq!(OutputState::find(&outputs, Some("DP-1")), None);
+        assert_eq!(OutputState::find(&outputs, Some("DP-2")), None);
+    }
+
+    #[test]
+    fn parse_mode() {
+        let mode: Mode = "2256x1504".parse().unwrap();
+        assert_eq!(
+            mode,
+            Mode {
+                width: 2256,
+                height: 1504,
+                refresh: None
+            }
+        );
+        let mode: Mode = "1920x1080@59.94Hz".parse().unwrap();
+        assert_eq!(mode.refresh, Some(59940));
+        assert_eq!(mode.to_string(), "1920x1080@59.94Hz");
+        assert!("1920".parse::<Mode>().is_err());
+        assert!("1920x1080@fast".parse::<Mode>().is_err());
+    }
+
+    #[test]
+    fn preset_matches() {
+        let state = OutputState::find(&outputs(), None).unwrap();
+        assert!(preset(1.5, None).matches(&state));
+        assert!(preset(1.5, Some("2256x1504")).matches(&state));
+        assert!(preset(1.5, Some("2256x1504@60Hz")).matches(&state));
+        assert!(!preset(1.5, Some("2256x1504@30Hz")).matches(&state));
+        assert!(!preset(1.0, Some("2256x1504")).matches(&state));
+        assert!(!preset(1.5, Some("1920x1280")).matches(&state));
+    }
+
+    #[test]
+    fn cycle_presets() {
+        let state = OutputState::find(&outputs(), None).unwrap();
+        let presets = [preset(1.0, None), preset(1.5, None), preset(2.0, None)];
+        // Starts from the preset matching the current state
+        assert_eq!(next_preset(&presets, None, &state, true), Some(2));
+        assert_eq!(next_preset(&presets, None, &state, false), Some(0));
+        // Continues from the last requested preset
+        assert_eq!(next_preset(&presets, Some(2), &state, true), Some(0));
+        assert_eq!(next_preset(&presets, Some(0), &state, false), Some(2));
+        // No preset matches
+        let presets = [preset(3.0, None), preset(4.0, None)];
+        assert_eq!(next_preset(&presets, None, &state, true), Some(0));
+        assert_eq!(next_preset(&presets, None, &state, false), Some(1));
+        assert_eq!(next_preset(&[], None, &state, true), None);
+    }
+
+    #[test]
+    fn apply_and_verify() {
+        let mut backend = FakeBackend {
+            outputs: outputs(),
+            max_scale: 2.0,
+            commands: Vec::new(),
+        };
+
+        let requested = preset(1.0, Some("1920x1280"));
+        let state = tokio_test::block_on(apply_preset(&mut backend, "eDP-1", &requested))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            backend.commands,
+            ["output \"eDP-1\" mode 1920x1280 scale 1"]
+        );
+        assert_eq!(state.scale, 1.0);
+        assert_eq!((state.width, state.height), (1920, 1280));
+        assert!(requested.matches(&state));
+
+        // The compositor clamps the scale
+        let requested = preset(3.0, None);
+        let state = tokio_test::block_on(apply_preset(&mut backend, "eDP-1", &requested))
+            .unwrap()
+            .unwrap();
+        assert_eq!(backend.commands[1], "output \"eDP-1\" scale 3");
+        assert_eq!(state.scale, 2.0);
+        assert!(!requested.matches(&state));
+    }
+}
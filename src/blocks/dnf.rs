@@ -1,10 +1,14 @@
 //! Pending updates available for your Fedora system
 //!
+//! Behind the scenes this uses `dnf check-update`, or `rpm-ostree upgrade --check` on Fedora
+//! Silverblue and other rpm-ostree based systems. If a check fails, e.g. without network, the
+//! previous count is kept and the block is in warning state until a check succeeds.
 //!
 //! # Configuration
 //!
 //! Key | Values | Default
 //! ----|--------|--------
+//! `driver` | `"dnf"` or `"rpm_ostree"` | `"dnf"`
 //! `interval` | Update interval in seconds. | `600`
 //! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $count.eng(w:1) "`
 //! `format_singular` | Same as `format`, but for when exactly one update is available. | `" $icon $count.eng(w:1) "`
@@ -12,6 +16,10 @@
 //! `warning_updates_regex` | Display block as warning if updates matching regex are available. | `None`
 //! `critical_updates_regex` | Display block as critical if updates matching regex are available. | `None`
 //!
+//! With `rpm_ostree`, `count` is the number of packages which the new deployment changes, and the
+//! regexes are matched against the lines of the `rpm-ostree` output, such as the security
+//! advisories.
+//!
 //! Placeholder | Value                       | Type | Unit
 //! ------------|-----------------------------|--------|-----
 //! `icon`      | A static icon               | Icon   | -
@@ -35,17 +43,25 @@
 //! cmd = "dnf list -q --upgrades | tail -n +2 | rofi -dmenu"
 //! ```
 //!
+//! ```toml
+//! [[block]]
+//! block = "dnf"
+//! driver = "rpm_ostree"
+//! interval = 3600
+//! critical_updates_regex = "Critical|Important"
+//! ```
+//!
 //! # Icons Used
 //!
 //! - `update`
 
+use super::packages_util::UpdateRegexes;
 use super::prelude::*;
-use regex::Regex;
-use tokio::process::Command;
 
 #[derive(Deserialize, Debug, SmartDefault)]
 #[serde(default)]
 pub struct Config {
+    driver: Driver,
     #[default(600.into())]
     interval: Seconds,
     format: FormatConfig,
@@ -55,6 +71,14 @@ pub struct Config {
     critical_updates_regex: Option<String>,
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum Driver {
+    #[default]
+    Dnf,
+    RpmOstree,
+}
+
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     let mut widget = Widget::new();
 
@@ -66,50 +90,40 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         .format_up_to_date
         .with_default(" $icon $count.eng(w:1) ")?;
 
-    let warning_updates_regex = config
-        .warning_updates_regex
-        .as_deref()
-        .map(Regex::new)
-        .transpose()
-        .error("invalid warning updates regex")?;
-    let critical_updates_regex = config
-        .critical_updates_regex
-        .as_deref()
-        .map(Regex::new)
-        .transpose()
-        .error("invalid critical updates regex")?;
+    let regexes = UpdateRegexes::new(
+        config.warning_updates_regex.as_deref(),
+        config.critical_updates_regex.as_deref(),
+    )?;
+
+    let commands = api.command_context.with_default_env("LC_ALL", "C");
+    let mut updates = Updates::default();
 
     loop {
-        let updates = get_updates_list().await?;
-        let count = get_update_count(&updates);
+        // Keep the previous updates if the check fails
+        let failed = match check(config.driver, &commands).await {
+            Ok(new) => {
+                updates = new;
+                false
+            }
+            Err(error) => {
+                log::warn!("Failed to check for updates: {error}");
+                true
+            }
+        };
 
-        widget.set_format(match count {
+        widget.set_format(match updates.count {
             0 => format_up_to_date.clone(),
             1 => format_singular.clone(),
             _ => format.clone(),
         });
         widget.set_values(map!(
             "icon" => Value::icon(api.get_icon("update")?),
-            "count" => Value::number(count)
+            "count" => Value::number(updates.count)
         ));
-
-        let warning = warning_updates_regex
-            .as_ref()
-            .map_or(false, |regex| has_matching_update(&updates, regex));
-        let critical = critical_updates_regex
-            .as_ref()
-            .map_or(false, |regex| has_matching_update(&updates, regex));
-        widget.state = match count {
-            0 => State::Idle,
-            _ => {
-                if critical {
-                    State::Critical
-                } else if warning {
-                    State::Warning
-                } else {
-                    State::Info
-                }
-            }
+        widget.state = if failed {
+            State::Warning
+        } else {
+            regexes.state(updates.count, updates.lines.iter().map(String::as_str))
         };
 
         api.set_widget(&widget).await?;
@@ -121,21 +135,119 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     }
 }
 
-async fn get_updates_list() -> Result<String> {
-    let stdout = Command::new("sh")
-        .env("LC_LANG", "C")
-        .args(["-c", "dnf check-update -q --skip-broken"])
+/// The number of pending updates, and the lines of output which describe them
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Updates {
+    count: usize,
+    lines: Vec<String>,
+}
+
+async fn check(driver: Driver, commands: &CommandContext) -> Result<Updates> {
+    let (program, args) = match driver {
+        Driver::Dnf => ("dnf", &["check-update", "--refresh", "-q"][..]),
+        Driver::RpmOstree => ("rpm-ostree", &["upgrade", "--check"][..]),
+    };
+    let output = commands
+        .command(program)
+        .args(args)
         .output()
         .await
-        .error("Failed to run dnf check-update")?
-        .stdout;
-    String::from_utf8(stdout).error("dnf produced non-UTF8 output")
+        .or_error(|| format!("Failed to run {program}"))?;
+    let stdout = String::from_utf8(output.stdout)
+        .or_error(|| format!("{program} produced non-UTF8 output"))?;
+    // dnf exits with 100 if there are updates, rpm-ostree with 77 if there are none
+    match (driver, output.status.code()) {
+        (Driver::Dnf, Some(0)) | (Driver::RpmOstree, Some(77)) => Ok(Updates::default()),
+        (Driver::Dnf, Some(100)) => Ok(dnf_updates(&stdout)),
+        (Driver::RpmOstree, Some(0)) => Ok(rpm_ostree_updates(&stdout)),
+        _ => Err(Error::new(format!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
+
+/// Parse the output of `dnf check-update`, one package per line, followed by a list of the
+/// packages which are obsoleted
+fn dnf_updates(output: &str) -> Updates {
+    let lines: Vec<String> = output
+        .lines()
+        .take_while(|line| !line.starts_with("Obsoleting Packages"))
+        .filter(|line| !line.trim().is_empty() && !line.starts_with(' '))
+        .map(Into::into)
+        .collect();
+    Updates {
+        count: lines.len(),
+        lines,
+    }
 }
 
-fn get_update_count(updates: &str) -> usize {
-    updates.lines().filter(|line| line.len() > 1).count()
+/// Parse the output of `rpm-ostree upgrade --check`, which counts the changed packages in its
+/// `Diff:` line
+fn rpm_ostree_updates(output: &str) -> Updates {
+    let count = output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("Diff:"))
+        .flat_map(|diff| diff.split(','))
+        .filter_map(|change| change.split_whitespace().next()?.parse::<usize>().ok())
+        .sum();
+    let lines = output
+        .lines()
+        .skip_while(|line| !line.starts_with("AvailableUpdate:"))
+        .skip(1)
+        .map(|line| line.trim().into())
+        .collect();
+    Updates { count, lines }
 }
 
-fn has_matching_update(updates: &str, regex: &Regex) -> bool {
-    updates.lines().any(|line| regex.is_match(line))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dnf() {
+        let output = "
+firefox.x86_64                      120.0-2.fc39                updates
+kernel.x86_64                       6.6.2-201.fc39              updates
+kernel-core.x86_64                  6.6.2-201.fc39              updates
+Obsoleting Packages
+grub2-tools-efi.x86_64              1:2.06-108.fc39             updates
+    grub2-tools-efi.x86_64          1:2.06-100.fc39             @updates
+";
+        let updates = dnf_updates(output);
+        assert_eq!(updates.count, 3);
+        assert!(updates.lines[1].starts_with("kernel.x86_64"));
+        let regexes = UpdateRegexes::new(None, Some("^kernel")).unwrap();
+        assert_eq!(
+            regexes.state(updates.count, updates.lines.iter().map(String::as_str)),
+            State::Critical
+        );
+        assert_eq!(dnf_updates(""), Updates::default());
+    }
+
+    #[test]
+    fn rpm_ostree() {
+        let output = "1 metadata, 0 images, 0 repos updated
+Note: --check and --preview may be unreliable.  See https://github.com/coreos/rpm-ostree/issues/1579
+AvailableUpdate:
+        Version: 39.20231204.0 (2023-12-04T00:43:29Z)
+         Commit: 3f9f6d3b8f1e1c0c0b4a2d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1d2e3f4a
+   GPGSignature: Valid signature by 8C8F9B2D4E1A6B3C5D7E9F0A1B2C3D4E5F6A7B8C
+  SecAdvisories: FEDORA-2023-1a2b3c4d5e  Important  kernel-6.6.4-200.fc39.x86_64
+           Diff: 47 upgraded, 1 removed, 2 added
+";
+        let updates = rpm_ostree_updates(output);
+        assert_eq!(updates.count, 50);
+        let regexes = UpdateRegexes::new(Some("Moderate"), Some("Important")).unwrap();
+        assert_eq!(
+            regexes.state(updates.count, updates.lines.iter().map(String::as_str)),
+            State::Critical
+        );
+        // The notes before the update are not matched
+        let regexes = UpdateRegexes::new(Some("unreliable"), None).unwrap();
+        assert_eq!(
+            regexes.state(updates.count, updates.lines.iter().map(String::as_str)),
+            State::Info
+        );
+    }
 }
@@ -0,0 +1,215 @@
+//! DNS blocker statistics
+//!
+//! Shows the statistics of a [Pi-hole](https://pi-hole.net/) or an
+//! [AdGuard Home](https://adguard.com/adguard-home/overview.html) instance and toggles blocking on
+//! click.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `driver` | One of `"pihole"` or `"adguard"` | `"pihole"`
+//! `url` | URL of the web interface | `"http://pi.hole"` for Pi-hole, `"http://localhost:3000"` for AdGuard Home
+//! `token` | Pi-hole API token (Settings → API → Show API token) | `None`
+//! `token_file` | File containing the Pi-hole API token (used if `token` is not set). Supports path expansions e.g. `~`. | `None`
+//! `token_command` | Shell command which outputs the Pi-hole API token (used if `token` and `token_file` are not set) | `None`
+//! `username` | AdGuard Home username | `None`
+//! `password` | AdGuard Home password | `None`
+//! `password_command` | Shell command which outputs the AdGuard Home password (used if `password` is not set) | `None`
+//! `disable_duration` | When blocking is disabled on click, re-enable it after this many seconds. Blocking stays disabled if not set. | `None`
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $blocked_percents.eng(w:2) "`
+//! `interval` | Update interval in seconds | `60`
+//!
+//! Placeholder        | Value                                      | Type   | Unit
+//! -------------------|--------------------------------------------|--------|-----
+//! `icon`             | A static icon                              | Icon   | -
+//! `blocked_percents` | Percentage of queries blocked today        | Number | %
+//! `blocked`          | Number of queries blocked today            | Number | -
+//! `queries`          | Number of queries today                    | Number | -
+//! `status`           | `enabled` or `disabled`                    | Text   | -
+//! `stale`            | Present if the last request failed         | Flag   | -
+//!
+//! The block's state is set to good if blocking is enabled and to warning if it is disabled. If an
+//! update or toggling blocking fails, the last values are shown in warning state (with `stale`
+//! set) until the next successful update. AdGuard Home reports statistics for its configured
+//! retention period instead of today only.
+//!
+//! Action            | Description                | Default button
+//! ------------------|----------------------------|---------------
+//! `toggle_blocking` | Enable or disable blocking | Left
+//!
+//! # Examples
+//!
+//! ```toml
+//! [[block]]
+//! block = "dns_blocker"
+//! url = "http://192.168.1.2"
+//! token_file = "~/.config/pihole-token"
+//! disable_duration = 300
+//! ```
+//!
+//! ```toml
+//! [[block]]
+//! block = "dns_blocker"
+//! driver = "adguard"
+//! username = "admin"
+//! password_command = "pass show adguard"
+//! format = " $icon $status $queries.eng(w:1) "
+//! ```
+//!
+//! # Icons Used
+//! - `dns_blocker`
+
+mod adguard;
+mod pihole;
+
+use super::prelude::*;
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    driver: Driver,
+    url: Option<String>,
+    token: Option<String>,
+    token_file: Option<ShellString>,
+    token_command: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    password_command: Option<String>,
+    disable_duration: Option<Seconds<false>>,
+    format: FormatConfig,
+    #[default(60.into())]
+    interval: Seconds,
+}
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(rename_all = "lowercase")]
+enum Driver {
+    #[default]
+    Pihole,
+    Adguard,
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[(MouseButton::Left, None, "toggle_blocking")])
+        .await?;
+
+    let mut widget = Widget::new().with_format(
+        config
+            .format
+            .with_default(" $icon $blocked_percents.eng(w:2) ")?,
+    );
+
+    let client: Box<dyn DnsBlocker + Send + Sync> = match config.driver {
+        Driver::Pihole => {
+            let token = match (config.token, config.token_file, config.token_command) {
+                (Some(token), _, _) => Some(token),
+                (None, Some(path), _) => Some(
+                    tokio::fs::read_to_string(&*path.expand()?)
+                        .await
+                        .error("Failed to read token_file")?
+                        .trim()
+                        .to_string(),
+                ),
+                (None, None, Some(cmd)) => {
+                    Some(run_secret_command(&api.command_context, "token_command", &cmd).await?)
+                }
+                (None, None, None) => None,
+            };
+            Box::new(pihole::Client::new(config.url, token))
+        }
+        Driver::Adguard => {
+            let password = match (config.password, config.password_command) {
+                (Some(password), _) => Some(password),
+                (None, Some(cmd)) => {
+                    Some(run_secret_command(&api.command_context, "password_command", &cmd).await?)
+                }
+                (None, None) => None,
+            };
+            let credentials = config.username.map(|u| (u, password.unwrap_or_default()));
+            Box::new(adguard::Client::new(config.url, credentials))
+        }
+    };
+    let disable_duration = config.disable_duration.map(|d| d.0);
+
+    let mut status = api.recoverable(|| client.status()).await?;
+    let mut stale = false;
+
+    loop {
+        widget.state = if status.enabled && !stale {
+            State::Good
+        } else {
+            State::Warning
+        };
+        widget.set_values(map! {
+            "icon" => Value::icon(api.get_icon("dns_blocker")?),
+            "blocked_percents" => Value::percents(status.blocked_percents()),
+            "blocked" => Value::number(status.blocked),
+            "queries" => Value::number(status.queries),
+            "status" => Value::text(if status.enabled { "enabled" } else { "disabled" }.into()),
+            [if stale] "stale" => Value::flag(),
+        });
+        api.set_widget(&widget).await?;
+
+        let mut toggle_failed = false;
+        select! {
+            _ = sleep(config.interval.0) => (),
+            event = api.event() => match event {
+                Action(a) if a == "toggle_blocking" => {
+                    let enable = !status.enabled;
+                    let duration = if enable { None } else { disable_duration };
+                    toggle_failed = client.set_blocking(enable, duration).await.is_err();
+                }
+                _ => (),
+            }
+        }
+
+        // Poll right after toggling too, to confirm the new status
+        match client.status().await {
+            Ok(new_status) => {
+                status = new_status;
+                stale = toggle_failed;
+            }
+            Err(_) => stale = true,
+        }
+    }
+}
+
+async fn run_secret_command(commands: &CommandContext, key: &str, cmd: &str) -> Result<String> {
+    let output = commands
+        .shell(cmd)
+        .output()
+        .await
+        .or_error(|| format!("Failed to run {key}"))?;
+    if !output.status.success() {
+        return Err(Error::new(format!("{key} failed")));
+    }
+    let secret =
+        String::from_utf8(output.stdout).or_error(|| format!("{key} produced non-UTF8 output"))?;
+    Ok(secret.trim_end_matches('\n').to_string())
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Status {
+    enabled: bool,
+    queries: f64,
+    blocked: f64,
+}
+
+impl Status {
+    fn blocked_percents(&self) -> f64 {
+        if self.queries > 0.0 {
+            self.blocked / self.queries * 100.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[async_trait]
+trait DnsBlocker {
+    async fn status(&self) -> Result<Status>;
+
+    /// Enable or disable blocking. If `duration` is set, blocking is re-enabled after it.
+    async fn set_blocking(&self, enabled: bool, duration: Option<Duration>) -> Result<()>;
+}
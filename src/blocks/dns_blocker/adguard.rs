@@ -0,0 +1,139 @@
+//! AdGuard Home API client
+//!
+//! See <https://github.com/AdguardTeam/AdGuardHome/blob/master/openapi/openapi.yaml>
+
+use super::{DnsBlocker, Status};
+use crate::blocks::prelude::*;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+const DEFAULT_URL: &str = "http://localhost:3000";
+
+pub(super) struct Client {
+    url: String,
+    credentials: Option<(String, String)>,
+}
+
+impl Client {
+    pub(super) fn new(url: Option<String>, credentials: Option<(String, String)>) -> Self {
+        let url = url.unwrap_or_else(|| DEFAULT_URL.into());
+        Self {
+            url: url.trim_end_matches('/').into(),
+            credentials,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = REQWEST_CLIENT.request(method, format!("{}/control/{path}", self.url));
+        match &self.credentials {
+            Some((username, password)) => request.basic_auth(username, Some(password)),
+            None => request,
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.request(reqwest::Method::GET, path)
+            .send()
+            .await
+            .error("Failed to send request to AdGuard Home")?
+            .error_for_status()
+            .error("AdGuard Home returned an error")?
+            .json()
+            .await
+            .error("Failed to parse AdGuard Home response")
+    }
+}
+
+#[async_trait]
+impl DnsBlocker for Client {
+    async fn status(&self) -> Result<Status> {
+        let status: ServerStatus = self.get("status").await?;
+        let stats: Stats = self.get("stats").await?;
+        Ok(Status {
+            enabled: status.protection_enabled,
+            queries: stats.num_dns_queries,
+            blocked: stats.blocked(),
+        })
+    }
+
+    async fn set_blocking(&self, enabled: bool, duration: Option<Duration>) -> Result<()> {
+        let mut body = json!({ "enabled": enabled });
+        if let Some(duration) = duration {
+            body["duration"] = json!(duration.as_millis() as u64);
+        }
+        self.request(reqwest::Method::POST, "protection")
+            .json(&body)
+            .send()
+            .await
+            .error("Failed to send request to AdGuard Home")?
+            .error_for_status()
+            .error("AdGuard Home returned an error")?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ServerStatus {
+    protection_enabled: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Stats {
+    num_dns_queries: f64,
+    num_blocked_filtering: f64,
+    #[serde(default)]
+    num_replaced_safebrowsing: f64,
+    #[serde(default)]
+    num_replaced_parental: f64,
+}
+
+impl Stats {
+    fn blocked(&self) -> f64 {
+        self.num_blocked_filtering + self.num_replaced_safebrowsing + self.num_replaced_parental
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_status() {
+        let text = r#"{
+            "dns_addresses": ["127.0.0.1"],
+            "dns_port": 53,
+            "http_port": 3000,
+            "protection_enabled": true,
+            "protection_disabled_duration": 0,
+            "dhcp_available": true,
+            "running": true,
+            "version": "v0.107.20",
+            "language": "en"
+        }"#;
+        let status: ServerStatus = serde_json::from_str(text).unwrap();
+        assert!(status.protection_enabled);
+    }
+
+    #[test]
+    fn stats() {
+        let text = r#"{
+            "time_units": "hours",
+            "top_queried_domains": [{"example.org": 123}],
+            "top_clients": [{"127.0.0.1": 456}],
+            "top_blocked_domains": [{"ads.example.org": 78}],
+            "dns_queries": [1, 2, 3],
+            "blocked_filtering": [0, 1, 0],
+            "replaced_safebrowsing": [0, 0, 0],
+            "replaced_parental": [0, 0, 0],
+            "num_dns_queries": 2000,
+            "num_blocked_filtering": 150,
+            "num_replaced_safebrowsing": 10,
+            "num_replaced_safesearch": 0,
+            "num_replaced_parental": 40,
+            "avg_processing_time": 0.012
+        }"#;
+        let stats: Stats = serde_json::from_str(text).unwrap();
+        assert_eq!(stats.num_dns_queries, 2000.);
+        assert_eq!(stats.blocked(), 200.);
+    }
+}
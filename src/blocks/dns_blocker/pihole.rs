@@ -0,0 +1,143 @@
+//! Pi-hole API client
+//!
+//! See <https://discourse.pi-hole.net/t/pi-hole-api/1863>
+
+use super::{DnsBlocker, Status};
+use crate::blocks::prelude::*;
+
+const DEFAULT_URL: &str = "http://pi.hole";
+
+pub(super) struct Client {
+    url: String,
+    token: Option<String>,
+}
+
+impl Client {
+    pub(super) fn new(url: Option<String>, token: Option<String>) -> Self {
+        let url = url.unwrap_or_else(|| DEFAULT_URL.into());
+        Self {
+            url: format!("{}/admin/api.php", url.trim_end_matches('/')),
+            token,
+        }
+    }
+
+    async fn request(&self, query: &[(&str, &str)]) -> Result<String> {
+        let mut request = REQWEST_CLIENT.get(&self.url).query(query);
+        if let Some(token) = &self.token {
+            request = request.query(&[("auth", token)]);
+        }
+        request
+            .send()
+            .await
+            .error("Failed to send request to Pi-hole")?
+            .error_for_status()
+            .error("Pi-hole returned an error")?
+            .text()
+            .await
+            .error("Failed to read Pi-hole response")
+    }
+}
+
+#[async_trait]
+impl DnsBlocker for Client {
+    async fn status(&self) -> Result<Status> {
+        let text = self.request(&[("summaryRaw", "")]).await?;
+        parse_summary(&text)
+    }
+
+    async fn set_blocking(&self, enabled: bool, duration: Option<Duration>) -> Result<()> {
+        let duration = duration.map_or(0, |d| d.as_secs()).to_string();
+        let query = if enabled {
+            [("enable", "")]
+        } else {
+            [("disable", duration.as_str())]
+        };
+        let text = self.request(&query).await?;
+        // Pi-hole replies with an empty array if the token is wrong
+        let reply: StatusReply =
+            serde_json::from_str(&text).error("Pi-hole rejected the API token")?;
+        if reply.enabled() != enabled {
+            return Err(Error::new("Pi-hole did not change the blocking status"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct StatusReply {
+    status: String,
+}
+
+impl StatusReply {
+    fn enabled(&self) -> bool {
+        self.status == "enabled"
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Summary {
+    dns_queries_today: f64,
+    ads_blocked_today: f64,
+    /// Only present if the API token is valid
+    status: Option<String>,
+}
+
+fn parse_summary(text: &str) -> Result<Status> {
+    let summary: Summary = serde_json::from_str(text).error("Failed to parse Pi-hole summary")?;
+    let status = summary
+        .status
+        .error("Pi-hole did not report the blocking status, is the API token set?")?;
+    Ok(Status {
+        enabled: status == "enabled",
+        queries: summary.dns_queries_today,
+        blocked: summary.ads_blocked_today,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let text = r#"{
+            "domains_being_blocked": 142387,
+            "dns_queries_today": 28000,
+            "ads_blocked_today": 2100,
+            "ads_percentage_today": 7.5,
+            "unique_domains": 1855,
+            "queries_forwarded": 15342,
+            "queries_cached": 10470,
+            "clients_ever_seen": 12,
+            "unique_clients": 9,
+            "dns_queries_all_types": 28000,
+            "reply_NODATA": 402,
+            "privacy_level": 0,
+            "status": "disabled",
+            "gravity_last_updated": {"file_exists": true, "absolute": 1667700000, "relative": {"days": 2, "hours": 3, "minutes": 4}}
+        }"#;
+        let status = parse_summary(text).unwrap();
+        assert_eq!(
+            status,
+            Status {
+                enabled: false,
+                queries: 28000.,
+                blocked: 2100.,
+            }
+        );
+        assert_eq!(status.blocked_percents(), 7.5);
+    }
+
+    #[test]
+    fn summary_without_token() {
+        let text = r#"{"domains_being_blocked": 142387, "dns_queries_today": 28000, "ads_blocked_today": 2100, "ads_percentage_today": 7.5}"#;
+        assert!(parse_summary(text).is_err());
+    }
+
+    #[test]
+    fn toggle_reply() {
+        let reply: StatusReply = serde_json::from_str(r#"{"status":"enabled"}"#).unwrap();
+        assert!(reply.enabled());
+        assert!(serde_json::from_str::<StatusReply>("[]").is_err());
+    }
+}
@@ -6,7 +6,8 @@
 //! ----|--------|--------
 //! `interval` | Update interval, in seconds. | `5`
 //! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $running.eng(w:1) "`
-//! `socket_path` | The path to the docker socket. Supports path expansions e.g. `~`. | `"/var/run/docker.sock"`
+//! `format_disconnected` | A string to customise the output of this block when the daemon is not running. | `" $icon down "`
+//! `socket_path` | The path to the docker socket. Supports path expansions e.g. `~`. Can point to a rootless docker or podman socket. | `"/var/run/docker.sock"`
 //!
 //! Key       | Value                          | Type   | Unit
 //! ----------|--------------------------------|--------|-----
@@ -17,7 +18,10 @@
 //! `paused`  | Containers paused on the host  | Number | -
 //! `images`  | Total images on the host       | Number | -
 //!
-//! # Example
+//! If the socket does not exist or nobody is listening on it, `format_disconnected` is displayed in
+//! warning state until the daemon becomes available.
+//!
+//! # Examples
 //!
 //! ```toml
 //! [[block]]
@@ -26,6 +30,15 @@
 //! format = " $icon $running/$total "
 //! ```
 //!
+//! Podman (rootless):
+//!
+//! ```toml
+//! [[block]]
+//! block = "docker"
+//! socket_path = "$XDG_RUNTIME_DIR/podman/podman.sock"
+//! format_disconnected = ""
+//! ```
+//!
 //! # Icons Used
 //!
 //! - `docker`
@@ -40,26 +53,37 @@ pub struct Config {
     #[default(5.into())]
     interval: Seconds,
     format: FormatConfig,
+    format_disconnected: FormatConfig,
     #[default("/var/run/docker.sock".into())]
     socket_path: ShellString,
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
-    let mut widget =
-        Widget::new().with_format(config.format.with_default(" $icon $running.eng(w:1) ")?);
+    let format = config.format.with_default(" $icon $running.eng(w:1) ")?;
+    let format_disconnected = config.format_disconnected.with_default(" $icon down ")?;
+    let mut widget = Widget::new();
     let socket_path = config.socket_path.expand()?;
 
     loop {
-        let status = api.recoverable(|| Status::new(&*socket_path)).await?;
-
-        widget.set_values(map! {
-            "icon" => Value::icon(api.get_icon("docker")?),
-            "total" =>   Value::number(status.total),
-            "running" => Value::number(status.running),
-            "paused" =>  Value::number(status.paused),
-            "stopped" => Value::number(status.stopped),
-            "images" =>  Value::number(status.images),
-        });
+        match api.recoverable(|| Status::new(&*socket_path)).await? {
+            Some(status) => {
+                widget.set_format(format.clone());
+                widget.state = State::Idle;
+                widget.set_values(map! {
+                    "icon" => Value::icon(api.get_icon("docker")?),
+                    "total" =>   Value::number(status.total),
+                    "running" => Value::number(status.running),
+                    "paused" =>  Value::number(status.paused),
+                    "stopped" => Value::number(status.stopped),
+                    "images" =>  Value::number(status.images),
+                });
+            }
+            None => {
+                widget.set_format(format_disconnected.clone());
+                widget.state = State::Warning;
+                widget.set_values(map!("icon" => Value::icon(api.get_icon("docker")?)));
+            }
+        }
         api.set_widget(&widget).await?;
 
         select! {
@@ -84,10 +108,13 @@ struct Status {
 }
 
 impl Status {
-    async fn new(socket_path: impl AsRef<Path>) -> Result<Self> {
-        let socket = UnixStream::connect(socket_path)
-            .await
-            .error("Failed to connect to socket")?;
+    /// Returns `None` if the daemon is not running
+    async fn new(socket_path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let socket = match UnixStream::connect(socket_path).await {
+            Ok(socket) => socket,
+            Err(e) if is_disconnected(&e) => return Ok(None),
+            Err(e) => return Err(e).error("Failed to connect to socket"),
+        };
         let (mut request_sender, connection) = hyper::client::conn::handshake(socket)
             .await
             .error("Failed to create request sender")?;
@@ -105,6 +132,13 @@ impl Status {
         let bytes = hyper::body::to_bytes(response.into_body())
             .await
             .error("Failed to get response bytes")?;
-        serde_json::from_slice::<Self>(&bytes).error("Failed to deserialize JSON")
+        serde_json::from_slice(&bytes)
+            .map(Some)
+            .error("Failed to deserialize JSON")
     }
 }
+
+fn is_disconnected(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(error.kind(), NotFound | ConnectionRefused)
+}
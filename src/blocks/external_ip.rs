@@ -5,13 +5,16 @@
 //! Key | Values | Default
 //! ----|--------|--------
 //! `format` | A string to customise the output of this block. See below for available placeholders. | `" $ip $country_flag "`
+//! `url` | The service to query. It must answer with JSON like <https://ipapi.co/json/>, of which only `ip` is required, e.g. `"https://api64.ipify.org?format=json"`. | `"https://ipapi.co/json/"`
 //! `interval` | Interval in seconds for automatic updates | `300`
 //! `with_network_manager` | If 'true', listen for NetworkManager events and update the IP immediately if there was a change | `true`
+//! `use_ipv4_only` | Query the service over IPv4, to get the external IPv4 address on a dual-stack network | `false`
 //!
 //!  Key | Value | Type | Unit
 //! -----|-------|------|------
 //! `ip` | The external IP address, as seen from a remote server | Text | -
 //! `version` | IPv4 or IPv6 | Text | -
+//! `stale` | Present if the last lookup failed and the values are from an earlier one | Flag | -
 //! `city` | City name, such as "San Francisco" | Text | -
 //! `region` | Region name, such as "California" | Text | -
 //! `region_code` | Region code, such as "CA" for California | Text | -
@@ -36,6 +39,7 @@
 //! `country_population` | Population of the country | Number | -
 //! `timezone` | Time zone | Text | -
 //! `org` | Organization | Text | -
+//! `isp` | Internet service provider, same as `org` | Text | -
 //! `asn` | Autonomous system (AS) | Text | -
 //! `country_flag` | Flag of the country | Text (glyph) | -
 //!
@@ -47,33 +51,56 @@
 //! format = " $ip $country_code "
 //! ```
 //!
+//! Only the address, from ipify, over IPv4:
+//!
+//! ```toml
+//! [[block]]
+//! block = "external_ip"
+//! format = " $ip{ (old)|} "
+//! url = "https://api64.ipify.org?format=json"
+//! use_ipv4_only = true
+//! ```
+//!
 //! # Notes
-//! All the information comes from <https://ipapi.co/json/>
+//! By default, all the information comes from <https://ipapi.co/json/>
 //! Check their documentation here: <https://ipapi.co/api/#complete-location5>
+//! Other services, such as ipify, only provide the address.
 //!
-//! The IP is queried, 1) When i3status-rs starts, 2) When a signal is received
-//! on D-Bus about a network configuration change, 3) Every 5 minutes. This
+//! The IP is queried, 1) When i3status-rs starts, 2) When the kernel reports
+//! a change of an address or of the default route, 3) When a signal is received
+//! on D-Bus about a network configuration change, 4) Every 5 minutes. This
 //! periodic refresh exists to catch IP updates that don't trigger a notification,
 //! for example due to a IP refresh at the router.
 //!
+//! If a lookup fails after an earlier one succeeded, the last values are kept,
+//! the block is shown in the warning state and `stale` is set.
+//!
 //! Flags: They are not icons but unicode glyphs. You will need a font that
 //! includes them. Tested with: <https://www.babelstone.co.uk/Fonts/Flags.html>
 
+use std::net::IpAddr;
+
 use zbus::MatchRule;
 
 use super::prelude::*;
+use crate::netlink::AddressChanges;
 use crate::util::{country_flag_from_iso_code, new_system_dbus_connection};
+use crate::REQWEST_CLIENT_IPV4;
 
-const API_ENDPOINT: &str = "https://ipapi.co/json/";
+/// Addresses and routes change several times while connecting, so wait for them to settle
+const SETTLE_DURATION: Duration = Duration::from_secs(1);
 
 #[derive(Deserialize, Debug, SmartDefault)]
 #[serde(default)]
 pub struct Config {
     format: FormatConfig,
+    #[default("https://ipapi.co/json/".into())]
+    url: String,
     #[default(300.into())]
     interval: Seconds,
     #[default(true)]
     with_network_manager: bool,
+    use_ipv4_only: bool,
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
@@ -127,8 +154,24 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         Box::pin(futures::stream::empty())
     };
 
+    let mut changes = match AddressChanges::new() {
+        Ok(changes) => Some(changes),
+        Err(error) => {
+            log::warn!("Cannot watch for address changes: {error}");
+            None
+        }
+    };
+
+    let client = if config.use_ipv4_only {
+        &*REQWEST_CLIENT_IPV4
+    } else {
+        &*REQWEST_CLIENT
+    };
+    let mut info = api
+        .recoverable(|| IPAddressInfo::new(client, &config.url))
+        .await?;
+
     loop {
-        let info = api.recoverable(IPAddressInfo::new).await?;
         let mut values = map! {
             "ip" => Value::text(info.ip),
             "version" => Value::text(info.version),
@@ -154,6 +197,7 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             "country_area" => Value::number(info.country_area),
             "country_population" => Value::number(info.country_population),
             "asn" => Value::text(info.asn),
+            "isp" => Value::text(info.org.clone()),
             "org" => Value::text(info.org),
         };
         info.postal
@@ -162,18 +206,50 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             values.insert("in_eu".into(), Value::flag());
         }
         widget.set_values(values);
+        widget.state = State::Idle;
         api.set_widget(&widget).await?;
 
-        select! {
-            _ = sleep(config.interval.0) => (),
-            _ = api.wait_for_update_request() => (),
-            _ = stream.next() => {
-                // avoid too frequent updates
-                let _ = tokio::time::timeout(Duration::from_millis(100), async {
-                    loop { let _ = stream.next().await; }
-                }).await;
+        let mut retry = config.interval.0;
+        info = loop {
+            select! {
+                _ = sleep(retry) => (),
+                _ = api.wait_for_update_request() => (),
+                _ = stream.next() => {
+                    // avoid too frequent updates
+                    let _ = tokio::time::timeout(Duration::from_millis(100), async {
+                        loop { let _ = stream.next().await; }
+                    }).await;
+                }
+                result = settled(&mut changes) => {
+                    if let Err(error) = result {
+                        log::warn!("Stopped watching for address changes: {error}");
+                        changes = None;
+                    }
+                }
             }
-        }
+            match IPAddressInfo::new(client, &config.url).await {
+                Ok(info) => break info,
+                Err(error) => {
+                    log::debug!("External IP lookup failed: {error}");
+                    if let Some(values) = widget.values() {
+                        let mut values = values.clone();
+                        values.insert("stale".into(), Value::flag());
+                        widget.set_values(values);
+                    }
+                    widget.state = State::Warning;
+                    api.set_widget(&widget).await?;
+                    retry = api.error_interval;
+                }
+            }
+        };
+    }
+}
+
+/// Wait until the addresses have changed and settled, or forever if they cannot be watched
+async fn settled(changes: &mut Option<AddressChanges>) -> Result<()> {
+    match changes {
+        Some(changes) => changes.settled(SETTLE_DURATION).await,
+        None => std::future::pending().await,
     }
 }
 
@@ -211,19 +287,58 @@ struct IPAddressInfo {
 }
 
 impl IPAddressInfo {
-    async fn new() -> Result<Self> {
-        let info: Self = REQWEST_CLIENT
-            .get(API_ENDPOINT)
+    async fn new(client: &reqwest::Client, url: &str) -> Result<Self> {
+        let text = client
+            .get(url)
             .send()
             .await
             .error("Failed to request current location")?
-            .json::<Self>()
+            .text()
             .await
-            .error("Failed to parse JSON")?;
+            .error("Failed to read the response")?;
+        Self::parse(&text)
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut info: Self = serde_json::from_str(text).error("Failed to parse JSON")?;
         if info.error {
-            Err(Error::new(info.reason))
-        } else {
-            Ok(info)
+            return Err(Error::new(info.reason));
         }
+        let ip: IpAddr = info
+            .ip
+            .parse()
+            .error("The service did not return an IP address")?;
+        if info.version.is_empty() {
+            info.version = match ip {
+                IpAddr::V4(_) => "IPv4",
+                IpAddr::V6(_) => "IPv6",
+            }
+            .into();
+        }
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let info = IPAddressInfo::parse(r#"{"ip":"2001:db8::1"}"#).unwrap();
+        assert_eq!(info.ip, "2001:db8::1");
+        assert_eq!(info.version, "IPv6");
+
+        let info = IPAddressInfo::parse(
+            r#"{"ip":"192.0.2.1","version":"IPv4","country_code":"NL","org":"Example BV"}"#,
+        )
+        .unwrap();
+        assert_eq!(info.version, "IPv4");
+        assert_eq!(info.country_code, "NL");
+        assert_eq!(info.org, "Example BV");
+
+        assert!(IPAddressInfo::parse(r#"{"error":true,"reason":"RateLimited"}"#).is_err());
+        assert!(IPAddressInfo::parse(r#"{"ip":"unknown"}"#).is_err());
+        assert!(IPAddressInfo::parse("<html>").is_err());
     }
 }
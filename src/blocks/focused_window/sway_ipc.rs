@@ -1,29 +1,59 @@
 use super::{Backend, Info};
 use crate::blocks::prelude::*;
-use swayipc_async::{Connection, Event, EventStream, EventType, WindowChange, WorkspaceChange};
+use swayipc_async::{
+    Connection, Event, EventStream, EventType, Node, NodeType, WindowChange, WorkspaceChange,
+};
 
 pub(super) struct SwayIpc {
     events: EventStream,
     info: Info,
+    /// `info` was read from the tree and has not been reported yet
+    initial: bool,
 }
 
 impl SwayIpc {
     pub(super) async fn new() -> Result<Self> {
+        let mut connection = Connection::new()
+            .await
+            .error("failed to open connection with swayipc")?;
+
+        // Events only report changes, so get the currently focused window from the tree
+        let tree = connection
+            .get_tree()
+            .await
+            .error("failed to get the tree from swayipc")?;
+        let info = tree
+            .find_focused(|n| n.focused)
+            .filter(is_window)
+            .map(|n| Info {
+                title: n.name.unwrap_or_default(),
+                marks: n.marks,
+            })
+            .unwrap_or_default();
+
         Ok(Self {
-            events: Connection::new()
-                .await
-                .error("failed to open connection with swayipc")?
+            events: connection
                 .subscribe(&[EventType::Window, EventType::Workspace])
                 .await
                 .error("could not subscribe to window events")?,
-            info: default(),
+            info,
+            initial: true,
         })
     }
 }
 
+fn is_window(node: &Node) -> bool {
+    matches!(node.node_type, NodeType::Con | NodeType::FloatingCon)
+}
+
 #[async_trait]
 impl Backend for SwayIpc {
     async fn get_info(&mut self) -> Result<Info> {
+        if self.initial {
+            self.initial = false;
+            return Ok(self.info.clone());
+        }
+
         loop {
             let event = self
                 .events
@@ -34,7 +64,11 @@ impl Backend for SwayIpc {
             match event {
                 Event::Window(e) => match e.change {
                     WindowChange::Mark => {
-                        self.info.marks = e.container.marks;
+                        if e.container.focused {
+                            self.info.marks = e.container.marks;
+                        } else {
+                            continue;
+                        }
                     }
                     WindowChange::Focus => {
                         self.info.title.clear();
@@ -63,6 +97,14 @@ impl Backend for SwayIpc {
                     self.info.title.clear();
                     self.info.marks.clear();
                 }
+                // Switching to an empty workspace does not emit a window event
+                Event::Workspace(e)
+                    if e.change == WorkspaceChange::Focus
+                        && matches!(&e.current, Some(ws) if ws.nodes.is_empty() && ws.floating_nodes.is_empty()) =>
+                {
+                    self.info.title.clear();
+                    self.info.marks.clear();
+                }
                 _ => continue,
             }
 
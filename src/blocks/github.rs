@@ -1,6 +1,6 @@
 //! The number of GitHub notifications
 //!
-//! This block shows the unread notification count for a GitHub account. A GitHub [personal access token](https://github.com/settings/tokens/new) with the "notifications" scope is required, and must be passed using the `I3RS_GITHUB_TOKEN` environment variable or `token` configuration option. Optionally the colour of the block is determined by the highest notification in the following lists from highest to lowest: `critical`,`warning`,`info`,`good`
+//! This block shows the unread notification count for a GitHub account. A GitHub [personal access token](https://github.com/settings/tokens/new) with the "notifications" scope is required, and must be passed using the `I3RS_GITHUB_TOKEN` environment variable, the `token` configuration option or a file given by `token_file`. Optionally the colour of the block is determined by the highest notification in the following lists from highest to lowest: `critical`,`warning`,`info`,`good`
 //!
 //! # Configuration
 //!
@@ -9,18 +9,21 @@
 //! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $total.eng(w:1) "`
 //! `interval` | Update interval in seconds | `30`
 //! `token` | A GitHub personal access token with the "notifications" scope | `None`
+//! `token_file` | A file containing the token, which keeps it out of the environment and the config. Supports path expansions e.g. `~`. | `None`
+//! `api_url` | The URL of the API, e.g. `"https://github.example.com/api/v3"` for GitHub Enterprise Server | `"https://api.github.com"`
 //! `hide_if_total_is_zero` | Hide this block if the total count of notifications is zero | `false`
-//! `critical` | List of notification types that change the block to the critical colour | `None`
-//! `warning` | List of notification types that change the block to the warning colour | `None`
-//! `info` | List of notification types that change the block to the info colour | `None`
-//! `good` | List of notification types that change the block to the good colour | `None`
+//! `critical` | List of notification types that change the block to the critical colour, or a total number of notifications at which it does | `None`
+//! `warning` | List of notification types that change the block to the warning colour, or a total number of notifications at which it does | `None`
+//! `info` | List of notification types that change the block to the info colour, or a total number of notifications at which it does | `None`
+//! `good` | List of notification types that change the block to the good colour, or a total number of notifications at which it does | `None`
 //!
 //!
-//! All the placeholders are numbers without a unit.
+//! All the placeholders except `icon` and `notifications_url` are numbers without a unit.
 //!
 //! Placeholder        | Value
 //! -------------------|------
 //! `icon`             | A static icon
+//! `notifications_url`| The URL of the notifications page
 //! `total`            | The total number of notifications
 //! `assign`           | You were assigned to the issue
 //! `author`           | You created the thread
@@ -35,6 +38,11 @@
 //! `subscribed`       | You're watching the repository
 //! `team_mention`     | You were on a team that was mentioned
 //!
+//! Action      | Description                                                        | Default button
+//! ------------|--------------------------------------------------------------------|---------------
+//! `open`      | Open the notifications page with `$BROWSER`, or `xdg-open` if it is not set | Left
+//! `mark_read` | Mark all notifications as read. If this fails, the block is in warning state until the next update. | Middle
+//!
 //! # Examples
 //!
 //! ```toml
@@ -55,6 +63,17 @@
 //! hide_if_total_is_zero = true
 //! ```
 //!
+//! GitHub Enterprise Server, with the token in a file and warning from 10 notifications:
+//!
+//! ```toml
+//! [[block]]
+//! block = "github"
+//! api_url = "https://github.example.com/api/v3"
+//! token_file = "~/.config/github-token"
+//! warning = 10
+//! critical = ["security_alert"]
+//! ```
+//!
 //! # Icons Used
 //! - `github`
 
@@ -67,47 +86,90 @@ pub struct Config {
     interval: Seconds,
     format: FormatConfig,
     token: Option<String>,
+    token_file: Option<ShellString>,
+    #[default("https://api.github.com".into())]
+    api_url: String,
     hide_if_total_is_zero: bool,
-    good: Option<Vec<String>>,
-    info: Option<Vec<String>>,
-    warning: Option<Vec<String>>,
-    critical: Option<Vec<String>>,
+    good: Option<Trigger>,
+    info: Option<Trigger>,
+    warning: Option<Trigger>,
+    critical: Option<Trigger>,
+}
+
+/// What sets a state
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Trigger {
+    /// Notifications with one of these reasons
+    Reasons(Vec<String>),
+    /// At least this many notifications in total
+    Total(usize),
+}
+
+impl Trigger {
+    fn matches(&self, stats: &HashMap<String, usize>) -> bool {
+        match self {
+            Self::Reasons(reasons) => reasons
+                .iter()
+                .any(|reason| stats.get(reason).is_some_and(|x| *x > 0)),
+            Self::Total(total) => stats.get("total").is_some_and(|x| x >= total),
+        }
+    }
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[
+        (MouseButton::Left, None, "open"),
+        (MouseButton::Middle, None, "mark_read"),
+    ])
+    .await?;
+
     let mut widget =
         Widget::new().with_format(config.format.with_default(" $icon $total.eng(w:1) ")?);
 
     let mut interval = config.interval.timer();
-    let token = config
-        .token
-        .or_else(|| std::env::var("I3RS_GITHUB_TOKEN").ok())
-        .error("Github token not found")?;
+    let token = match (config.token, &config.token_file) {
+        (Some(token), _) => token,
+        (None, Some(path)) => {
+            let path = path.expand()?;
+            let token = tokio::fs::read_to_string(&*path)
+                .await
+                .or_error(|| format!("Failed to read the Github token from {path}"))?;
+            token.trim().to_string()
+        }
+        (None, None) => token(None).error("Github token not found")?,
+    };
+    let api_url = config.api_url.trim_end_matches('/');
+    let notifications_url = notifications_url(api_url);
+    let mut mark_read_failed = false;
 
     loop {
-        let stats = api.recoverable(|| get_stats(&token)).await?;
-        if stats.get("total").map_or(false, |x| *x > 0) || !config.hide_if_total_is_zero {
-            let mut state = State::Idle;
-            'outer: for (list_opt, ret) in [
+        let stats = api.recoverable(|| get_stats(api_url, &token)).await?;
+        if stats.get("total").is_some_and(|x| *x > 0) || !config.hide_if_total_is_zero {
+            let state = [
                 (&config.critical, State::Critical),
                 (&config.warning, State::Warning),
                 (&config.info, State::Info),
                 (&config.good, State::Good),
-            ] {
-                if let Some(list) = list_opt {
-                    for val in list {
-                        if stats.get(val).map_or(false, |x| *x > 0) {
-                            state = ret;
-                            break 'outer;
-                        }
-                    }
-                }
-            }
-            let mut values: HashMap<_, _> = stats
+            ]
+            .into_iter()
+            .find(|(trigger, _)| trigger.as_ref().is_some_and(|t| t.matches(&stats)))
+            .map_or(State::Idle, |(_, state)| state);
+            // Show that the notifications could not be marked as read until the next update
+            let state = if mark_read_failed {
+                State::Warning
+            } else {
+                state
+            };
+            let mut values: Values = stats
                 .into_iter()
                 .map(|(k, v)| (k.into(), Value::number(v)))
                 .collect();
             values.insert("icon".into(), Value::icon(api.get_icon("github")?));
+            values.insert(
+                "notifications_url".into(),
+                Value::text(notifications_url.clone()),
+            );
             widget.set_values(values);
             widget.state = state;
             api.set_widget(&widget).await?;
@@ -115,30 +177,75 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             api.hide().await?;
         }
 
-        select! {
-            _ = interval.tick() => (),
-            _ = api.wait_for_update_request() => (),
+        mark_read_failed = false;
+
+        loop {
+            select! {
+                _ = interval.tick() => break,
+                event = api.event() => match event {
+                    UpdateRequest => break,
+                    Action(a) if a == "open" => {
+                        let browser = std::env::var("BROWSER")
+                            .ok()
+                            .filter(|browser| !browser.is_empty());
+                        let browser = browser.as_deref().unwrap_or("xdg-open");
+                        if let Err(e) = api
+                            .command_context
+                            .spawn_process(browser, &[&notifications_url])
+                        {
+                            log::warn!("Failed to run '{browser}': {e}");
+                        }
+                    }
+                    Action(a) if a == "mark_read" => {
+                        if let Err(error) = mark_read(api_url, &token).await {
+                            log::warn!("Failed to mark the notifications as read: {error}");
+                            mark_read_failed = true;
+                        }
+                        // Show the new count right away
+                        break;
+                    }
+                    _ => (),
+                }
+            }
         }
     }
 }
 
+/// The configured token, or the one from the `I3RS_GITHUB_TOKEN` environment variable
+pub(super) fn token(token: Option<String>) -> Option<String> {
+    token.or_else(|| std::env::var("I3RS_GITHUB_TOKEN").ok())
+}
+
 #[derive(Deserialize, Debug)]
 struct Notification {
     reason: String,
 }
 
-async fn get_stats(token: &str) -> Result<HashMap<String, usize>> {
+/// The web page of the notifications of the GitHub instance with the API at `api_url`. GitHub
+/// Enterprise Server serves its API at `<host>/api/v3`.
+fn notifications_url(api_url: &str) -> String {
+    match api_url.strip_suffix("/api/v3") {
+        Some(host) => format!("{host}/notifications"),
+        None => "https://github.com/notifications".into(),
+    }
+}
+
+async fn get_stats(api_url: &str, token: &str) -> Result<HashMap<String, usize>> {
     let mut stats = HashMap::new();
     let mut total = 0;
-    for page in 1..100 {
-        let on_page = get_on_page(token, page).await?;
-        if on_page.is_empty() {
-            break;
-        }
+    let mut url = Some(format!("{api_url}/notifications?per_page=100"));
+    // Follow the pages until there is no next one, but never too many
+    for _ in 0..100 {
+        let page_url = match url.take() {
+            Some(url) => url,
+            None => break,
+        };
+        let (on_page, next) = get_page(&page_url, token).await?;
         total += on_page.len();
         for n in on_page {
             stats.entry(n.reason).and_modify(|x| *x += 1).or_insert(1);
         }
+        url = next;
     }
     stats.insert("total".into(), total);
     stats.entry("total".into()).or_insert(0);
@@ -157,7 +264,8 @@ async fn get_stats(token: &str) -> Result<HashMap<String, usize>> {
     Ok(stats)
 }
 
-async fn get_on_page(token: &str, page: usize) -> Result<Vec<Notification>> {
+/// The notifications on a page, and the URL of the next page if there is one
+async fn get_page(url: &str, token: &str) -> Result<(Vec<Notification>, Option<String>)> {
     #[derive(Deserialize)]
     #[serde(untagged)]
     enum Response {
@@ -167,20 +275,103 @@ async fn get_on_page(token: &str, page: usize) -> Result<Vec<Notification>> {
 
     // https://docs.github.com/en/rest/reference/activity#notifications
     let request = REQWEST_CLIENT
-        .get(format!(
-            "https://api.github.com/notifications?per_page=100&page={page}",
-        ))
+        .get(url)
         .header("Authorization", format!("token {token}"));
-    let responce = request
-        .send()
-        .await
-        .error("Failed to send request")?
+    let response = request.send().await.error("Failed to send request")?;
+    let next = response
+        .headers()
+        .get(reqwest::header::LINK)
+        .and_then(|link| link.to_str().ok())
+        .and_then(next_link);
+    let response = response
         .json::<Response>()
         .await
         .error("Failed to get JSON")?;
 
-    match responce {
-        Response::Notifications(n) => Ok(n),
+    match response {
+        Response::Notifications(n) => Ok((n, next)),
         Response::ErrorMessage { message } => Err(Error::new(format!("API error: {message}"))),
     }
 }
+
+/// Mark all notifications as read
+async fn mark_read(api_url: &str, token: &str) -> Result<()> {
+    // https://docs.github.com/en/rest/activity/notifications#mark-notifications-as-read
+    let response = REQWEST_CLIENT
+        .put(format!("{api_url}/notifications"))
+        .header("Authorization", format!("token {token}"))
+        .json(&serde_json::json!({ "read": true }))
+        .send()
+        .await
+        .error("Failed to send request")?;
+    // 202 if the notifications are marked in the background, 205 if they are marked already
+    if !response.status().is_success() {
+        return Err(Error::new(format!("API error: {}", response.status())));
+    }
+    Ok(())
+}
+
+/// The URL of the next page in an RFC 8288 `Link` header, e.g.
+/// `<https://api.github.com/notifications?page=2>; rel="next", <...>; rel="last"`
+fn next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let (url, params) = link.split_once(';')?;
+        let url = url.trim().strip_prefix('<')?.strip_suffix('>')?;
+        params
+            .split(';')
+            .filter_map(|param| param.trim().strip_prefix("rel="))
+            .any(|rel| rel.trim_matches('"').split(' ').any(|rel| rel == "next"))
+            .then(|| url.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_header() {
+        assert_eq!(
+            next_link(
+                r#"<https://api.github.com/notifications?per_page=100&page=2>; rel="next", <https://api.github.com/notifications?per_page=100&page=5>; rel="last""#
+            )
+            .as_deref(),
+            Some("https://api.github.com/notifications?per_page=100&page=2")
+        );
+        // The last page
+        assert_eq!(
+            next_link(
+                r#"<https://github.example.com/api/v3/notifications?page=1>; rel="first", <https://github.example.com/api/v3/notifications?page=4>; rel="prev""#
+            ),
+            None
+        );
+        assert_eq!(
+            next_link("<https://x/?page=3>;rel=next").as_deref(),
+            Some("https://x/?page=3")
+        );
+        assert_eq!(next_link(""), None);
+    }
+
+    #[test]
+    fn triggers() {
+        let stats = HashMap::from([("total".to_string(), 12), ("mention".to_string(), 0)]);
+        let trigger = |toml: &str| toml::from_str::<Config>(toml).unwrap().warning.unwrap();
+        assert!(trigger("warning = 10").matches(&stats));
+        assert!(trigger("warning = 12").matches(&stats));
+        assert!(!trigger("warning = 13").matches(&stats));
+        assert!(trigger(r#"warning = ["total"]"#).matches(&stats));
+        assert!(!trigger(r#"warning = ["mention", "author"]"#).matches(&stats));
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            notifications_url("https://api.github.com"),
+            "https://github.com/notifications"
+        );
+        assert_eq!(
+            notifications_url("https://github.example.com/api/v3"),
+            "https://github.example.com/notifications"
+        );
+    }
+}
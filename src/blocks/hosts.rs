@@ -0,0 +1,464 @@
+//! Reachability of a list of hosts
+//!
+//! Every `interval` seconds all hosts are probed concurrently. Each probe has its own `timeout`. If
+//! probing takes longer than `interval`, the next round is skipped rather than queued.
+//!
+//! `format` is shown once for each host, in order. Its state is warning (or critical, see below)
+//! if the host is unreachable. Only hosts whose reachability changed are rendered again. If
+//! `format` uses `hosts`, `down_count` or `down_names`, it is shown once for all hosts instead.
+//!
+//! The state of the block is critical if a host with `critical = true` is down, and warning if
+//! any other host is down.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `format` | A string to customise the output of each host. See below for available placeholders. | `" $name $status.pango-str() "`
+//! `interval` | Update interval in seconds | `60`
+//! `timeout` | Timeout of a single probe in seconds | `5`
+//! `up` | A string displayed for a reachable host. May contain pango markup. | `"●"`
+//! `down` | A string displayed for an unreachable host. May contain pango markup. | `"○"`
+//! `hosts` | A list of hosts to probe. See below. | `[]`
+//!
+//! Each entry of `hosts` has the following keys:
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `name` | Name of the host displayed in `format` | Required
+//! `check` | How to probe the host: `"tcp://<host>:<port>"` (TCP connect), `"ping://<host>"` (runs `ping`) or `"cmd:<command>"` (a shell command which exits with `0` if the host is up) | Required
+//! `critical` | Set the host's and the block's state to critical (instead of warning) when it is down | `false`
+//!
+//! Placeholders shown once for each host:
+//!
+//! Placeholder | Value                                      | Type | Unit
+//! ------------|--------------------------------------------|------|-----
+//! `name`      | The `name` of the host                     | Text | -
+//! `status`    | The `up` or `down` string                  | Text | -
+//! `up`        | Present if the host is reachable           | Flag | -
+//!
+//! Placeholders shown once for all hosts:
+//!
+//! Placeholder  | Value                                              | Type   | Unit
+//! -------------|----------------------------------------------------|--------|-----
+//! `hosts`      | `up` or `down` string for each host, in order      | Text   | -
+//! `down_count` | Number of unreachable hosts                        | Number | -
+//! `down_names` | Comma separated names of unreachable hosts         | Text   | -
+//!
+//! # Examples
+//!
+//! ```toml
+//! [[block]]
+//! block = "hosts"
+//! interval = 30
+//! format = " $hosts.pango-str(){ $down_names|} "
+//! down = "<span color='red'>●</span>"
+//! [[block.hosts]]
+//! name = "server"
+//! check = "tcp://server.lan:22"
+//! critical = true
+//! [[block.hosts]]
+//! name = "router"
+//! check = "ping://192.168.1.1"
+//! ```
+//!
+//! ```toml
+//! [[block]]
+//! block = "hosts"
+//! interval = 30
+//! format = "{ $up|$name }$status.pango-str() "
+//! down = "<span color='red'>●</span>"
+//! [[block.hosts]]
+//! name = "server"
+//! check = "tcp://server.lan:22"
+//! critical = true
+//! [[block.hosts]]
+//! name = "router"
+//! check = "ping://192.168.1.1"
+//! [[block.hosts]]
+//! name = "nas"
+//! check = "cmd:ssh -o BatchMode=yes nas.lan true"
+//! ```
+
+use super::prelude::*;
+use crate::widget::{PartsView, WidgetPart, WidgetsDiff};
+use std::future::Future;
+use std::str::FromStr;
+use tokio::net::TcpStream;
+use tokio::time::{timeout, MissedTickBehavior};
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    format: FormatConfig,
+    #[default(60.into())]
+    interval: Seconds,
+    #[default(5.into())]
+    timeout: Seconds<false>,
+    #[default("●".into())]
+    up: String,
+    #[default("○".into())]
+    down: String,
+    hosts: Vec<Host>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Host {
+    name: String,
+    check: Check,
+    #[serde(default)]
+    critical: bool,
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    let format = config.format.with_default(" $name $status.pango-str() ")?;
+    let summary = ["hosts", "down_count", "down_names"]
+        .iter()
+        .any(|key| format.contains_key(key));
+    let mut widget = Widget::new().with_format(format);
+
+    let mut timer = config.interval.timer();
+    timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let commands = api.command_context.clone();
+    let mut view = HostsView {
+        hosts: &config.hosts,
+        up: &config.up,
+        down: &config.down,
+        results: Vec::new(),
+        previous: None,
+    };
+
+    loop {
+        let results = futures::future::join_all(
+            config
+                .hosts
+                .iter()
+                .map(|host| host.check.probe(&commands, config.timeout.0)),
+        )
+        .await;
+
+        view.update(results);
+        widget.state = view.state();
+        if summary {
+            widget.set_values(view.summary());
+        } else {
+            widget.set_view(&view);
+        }
+        api.set_widget(&widget).await?;
+
+        select! {
+            _ = timer.tick() => (),
+            _ = api.wait_for_update_request() => (),
+        }
+    }
+}
+
+/// The probe results of the hosts
+struct HostsView<'a> {
+    hosts: &'a [Host],
+    up: &'a str,
+    down: &'a str,
+    results: Vec<bool>,
+    /// The results of the previous round, if there was one
+    previous: Option<Vec<bool>>,
+}
+
+impl HostsView<'_> {
+    fn update(&mut self, results: Vec<bool>) {
+        let previous = std::mem::replace(&mut self.results, results);
+        self.previous = (!previous.is_empty()).then_some(previous);
+    }
+
+    fn results(&self) -> impl Iterator<Item = (&Host, bool)> {
+        self.hosts.iter().zip(self.results.iter().copied())
+    }
+
+    /// Critical if a critical host is down, warning if any other host is down
+    fn state(&self) -> State {
+        let states = self.results().map(|(host, up)| host_state(host, up));
+        states.fold(State::Idle, |state, host| match (state, host) {
+            (State::Critical, _) | (_, State::Critical) => State::Critical,
+            (State::Warning, _) | (_, State::Warning) => State::Warning,
+            _ => State::Idle,
+        })
+    }
+
+    /// The values of `format` if it is shown once for all hosts
+    fn summary(&self) -> Values {
+        let mut hosts = String::new();
+        let mut down_names = Vec::new();
+        for (host, up) in self.results() {
+            if up {
+                hosts.push_str(self.up);
+            } else {
+                hosts.push_str(self.down);
+                down_names.push(host.name.as_str());
+            }
+        }
+        map! {
+            "hosts" => Value::text(hosts),
+            "down_count" => Value::number(down_names.len()),
+            [if !down_names.is_empty()] "down_names" => Value::text(down_names.join(", ")),
+        }
+    }
+}
+
+fn host_state(host: &Host, up: bool) -> State {
+    match (up, host.critical) {
+        (true, _) => State::Idle,
+        (false, true) => State::Critical,
+        (false, false) => State::Warning,
+    }
+}
+
+impl PartsView for HostsView<'_> {
+    fn view(&self) -> Vec<WidgetPart> {
+        self.results()
+            .enumerate()
+            .map(|(index, (host, up))| WidgetPart {
+                name: index.to_string(),
+                state: host_state(host, up),
+                values: map! {
+                    "name" => Value::text(host.name.clone()),
+                    "status" => Value::text(if up { self.up } else { self.down }.into()),
+                    [if up] "up" => Value::flag(),
+                },
+            })
+            .collect()
+    }
+
+    fn view_diff(&self) -> WidgetsDiff {
+        match &self.previous {
+            Some(previous) => WidgetsDiff::Changed(
+                (0..self.results.len())
+                    .filter(|&index| previous.get(index) != self.results.get(index))
+                    .collect(),
+            ),
+            None => WidgetsDiff::All,
+        }
+    }
+}
+
+/// A way to check whether a host is reachable
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Check {
+    /// Try to open a TCP connection to `host:port`
+    Tcp(String),
+    /// Send a single ICMP echo request using `ping`
+    Ping(String),
+    /// Run a shell command
+    Cmd(String),
+}
+
+impl FromStr for Check {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            Ok(Self::Tcp(addr.into()))
+        } else if let Some(host) = s.strip_prefix("ping://") {
+            Ok(Self::Ping(host.into()))
+        } else if let Some(cmd) = s.strip_prefix("cmd:") {
+            Ok(Self::Cmd(cmd.into()))
+        } else {
+            Err(Error::new(format!(
+                "'{s}' is not a valid check, expected 'tcp://', 'ping://' or 'cmd:'"
+            )))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Check {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().serde_error()
+    }
+}
+
+impl Check {
+    /// Returns `true` if the host is reachable
+    async fn probe(&self, commands: &CommandContext, probe_timeout: Duration) -> bool {
+        match self {
+            Self::Tcp(addr) => with_timeout(probe_timeout, probe_tcp(addr)).await,
+            Self::Ping(host) => {
+                with_timeout(probe_timeout, probe_ping(commands, host, probe_timeout)).await
+            }
+            Self::Cmd(cmd) => with_timeout(probe_timeout, probe_cmd(commands, cmd)).await,
+        }
+    }
+}
+
+/// A probe which does not finish within `probe_timeout` counts as unreachable
+async fn with_timeout(probe_timeout: Duration, probe: impl Future<Output = bool>) -> bool {
+    matches!(timeout(probe_timeout, probe).await, Ok(true))
+}
+
+async fn probe_tcp(addr: &str) -> bool {
+    TcpStream::connect(addr).await.is_ok()
+}
+
+async fn probe_ping(commands: &CommandContext, host: &str, probe_timeout: Duration) -> bool {
+    let status = commands
+        .command("ping")
+        .args(["-c", "1", "-W"])
+        .arg(probe_timeout.as_secs().max(1).to_string())
+        .arg(host)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await;
+    matches!(status, Ok(status) if status.success())
+}
+
+async fn probe_cmd(commands: &CommandContext, cmd: &str) -> bool {
+    let status = commands
+        .shell(cmd)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await;
+    matches!(status, Ok(status) if status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatting::value::ValueInner;
+
+    #[test]
+    fn parse_check() {
+        assert_eq!(
+            "tcp://example.com:22".parse::<Check>().unwrap(),
+            Check::Tcp("example.com:22".into())
+        );
+        assert_eq!(
+            "ping://10.0.0.1".parse::<Check>().unwrap(),
+            Check::Ping("10.0.0.1".into())
+        );
+        assert_eq!(
+            "cmd:true".parse::<Check>().unwrap(),
+            Check::Cmd("true".into())
+        );
+        assert!("example.com".parse::<Check>().is_err());
+    }
+
+    fn host(name: &str, critical: bool) -> Host {
+        Host {
+            name: name.into(),
+            check: Check::Cmd("true".into()),
+            critical,
+        }
+    }
+
+    #[test]
+    fn view() {
+        let hosts = [host("a", false), host("b", true), host("c", false)];
+        let mut view = HostsView {
+            hosts: &hosts,
+            up: "U",
+            down: "D",
+            results: Vec::new(),
+            previous: None,
+        };
+
+        view.update(vec![true, false, false]);
+        assert_eq!(view.view_diff(), WidgetsDiff::All);
+        assert_eq!(view.state(), State::Critical);
+        let parts = view.view();
+        assert_eq!(
+            parts.iter().map(|part| part.state).collect::<Vec<_>>(),
+            [State::Idle, State::Critical, State::Warning]
+        );
+        let status = |part: &WidgetPart| match &part.values.get("status").unwrap().inner {
+            ValueInner::Text(text) => text.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(status(&parts[0]), "U");
+        assert_eq!(status(&parts[1]), "D");
+        assert!(parts[0].values.contains_key("up"));
+        assert!(!parts[1].values.contains_key("up"));
+
+        // Only the hosts whose reachability changed are rendered again
+        view.update(vec![true, true, false]);
+        assert_eq!(view.view_diff(), WidgetsDiff::Changed(vec![1]));
+        view.update(vec![true, true, false]);
+        assert_eq!(view.view_diff(), WidgetsDiff::Changed(vec![]));
+    }
+
+    #[test]
+    fn summary() {
+        let hosts = [host("a", false), host("b", true), host("c", false)];
+        let mut view = HostsView {
+            hosts: &hosts,
+            up: "U",
+            down: "D",
+            results: Vec::new(),
+            previous: None,
+        };
+        let text = |values: &Values, key| match values.get(key).map(|value| &value.inner) {
+            Some(ValueInner::Text(text)) => Some(text.clone()),
+            Some(ValueInner::Number { val, .. }) => Some(val.to_string()),
+            _ => None,
+        };
+
+        view.update(vec![true, true, true]);
+        let summary = view.summary();
+        assert_eq!(text(&summary, "hosts").as_deref(), Some("UUU"));
+        assert_eq!(text(&summary, "down_count").as_deref(), Some("0"));
+        assert_eq!(text(&summary, "down_names"), None);
+        assert_eq!(view.state(), State::Idle);
+
+        view.update(vec![false, true, false]);
+        let summary = view.summary();
+        assert_eq!(text(&summary, "hosts").as_deref(), Some("DUD"));
+        assert_eq!(text(&summary, "down_count").as_deref(), Some("2"));
+        assert_eq!(text(&summary, "down_names").as_deref(), Some("a, c"));
+        assert_eq!(view.state(), State::Warning);
+
+        // A critical host stays critical regardless of the hosts after it
+        view.update(vec![true, false, false]);
+        assert_eq!(view.state(), State::Critical);
+    }
+
+    #[test]
+    fn tcp_local_listener() {
+        let commands = CommandContext::default();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let check = Check::Tcp(listener.local_addr().unwrap().to_string());
+        assert!(tokio_test::block_on(
+            check.probe(&commands, Duration::from_secs(1))
+        ));
+
+        drop(listener);
+        assert!(!tokio_test::block_on(
+            check.probe(&commands, Duration::from_secs(1))
+        ));
+    }
+
+    #[test]
+    fn cmd_exit_status() {
+        let commands = CommandContext::default();
+        let timeout = Duration::from_secs(1);
+        assert!(tokio_test::block_on(
+            Check::Cmd("true".into()).probe(&commands, timeout)
+        ));
+        assert!(!tokio_test::block_on(
+            Check::Cmd("false".into()).probe(&commands, timeout)
+        ));
+    }
+
+    #[test]
+    fn probe_timeout() {
+        let timeout = Duration::from_millis(10);
+        tokio_test::block_on(async {
+            assert!(with_timeout(timeout, async { true }).await);
+            assert!(!with_timeout(timeout, async { false }).await);
+            assert!(!with_timeout(timeout, std::future::pending()).await);
+        });
+    }
+}
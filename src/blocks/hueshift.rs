@@ -14,6 +14,7 @@
 //! `max_temp`    | Max color temperature in Kelvin. | `10000`
 //! `min_temp`    | Min color temperature in Kelvin. | `1000`
 //! `click_temp`  | Left click color temperature in Kelvin. | `6500`
+//! `interval`    | How often to check for temperature changes made outside of the bar, in seconds. Only used by `redshift` and `gammastep`, see below. | `None`
 //!
 //! Placeholder           | Value                        | Type   | Unit
 //! ----------------------|------------------------------|--------|---------------
@@ -37,10 +38,17 @@
 //! `"wl_gammarelay_rs"` | Wayland
 //! `"wlsunset"`         | Wayland
 //!
-//! Note that at the moment, only [`wl_gammarelay`](https://github.com/jeremija/wl-gammarelay) and
+//! [`wl_gammarelay`](https://github.com/jeremija/wl-gammarelay) and
 //! [`wl_gammarelay_rs`](https://github.com/MaxVerevkin/wl-gammarelay-rs)
-//! subscribe to the events and update the bar when the temperature is modified extenrally. Also,
-//! these are the only drivers at the moment that work under Wayland without flickering.
+//! subscribe to the events and update the bar when the temperature is modified externally, so
+//! `interval` is ignored for them. Also, these are the only drivers at the moment that work under
+//! Wayland without flickering.
+//!
+//! With `redshift` and `gammastep`, the temperature is read back from a running instance every
+//! `interval` seconds: the temperature passed with `-O` to an instance which keeps running
+//! (`gammastep` does so on Wayland), or the output of `-p` for an instance in continuous mode. If
+//! no instance is running, or with `sct` and `wlsunset`, which cannot report the temperature, the
+//! block shows the last temperature it set itself.
 //!
 //! # Example
 //!
@@ -56,17 +64,14 @@
 //! The `step` has a hard limit as well, defined to `500K` to avoid too brutal changes.
 
 use super::prelude::*;
-use crate::subprocess::{spawn_process, spawn_shell};
-use crate::util::has_command;
 use futures::future::pending;
+use tokio::fs::{read_dir, read_to_string};
 
 #[derive(Deserialize, Debug, SmartDefault)]
 #[serde(default)]
 pub struct Config {
     format: FormatConfig,
-    // TODO: Document once this option becomes usefull
-    #[default(5.into())]
-    interval: Seconds,
+    interval: Option<Seconds>,
     #[default(10_000)]
     max_temp: u16,
     #[default(1_000)]
@@ -81,7 +86,25 @@ pub struct Config {
     click_temp: u16,
 }
 
+impl Config {
+    /// The color temperature of the screens is controlled through the driver, see
+    /// [`BlockConfig::resource`](super::BlockConfig::resource)
+    pub fn resource(&self) -> String {
+        let driver = match self.hue_shifter {
+            None => "auto",
+            Some(HueShifter::Redshift) => "redshift",
+            Some(HueShifter::Sct) => "sct",
+            Some(HueShifter::Gammastep) => "gammastep",
+            Some(HueShifter::Wlsunset) => "wlsunset",
+            Some(HueShifter::WlGammarelay) => "wl_gammarelay",
+            Some(HueShifter::WlGammarelayRs) => "wl_gammarelay_rs",
+        };
+        format!("hueshift:{driver}")
+    }
+}
+
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.coalesce_actions();
     api.set_default_actions(&[
         (MouseButton::Left, None, "set_click_temp"),
         (MouseButton::Right, None, "reset"),
@@ -97,20 +120,21 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     let max_temp = config.max_temp.min(10_000);
     let min_temp = config.min_temp.clamp(1_000, max_temp);
 
+    let commands = api.command_context.clone();
     let hue_shifter = match config.hue_shifter {
         Some(driver) => driver,
         None => {
-            if has_command("wl-gammarelay-rs").await? {
+            if commands.has_command("wl-gammarelay-rs").await? {
                 HueShifter::WlGammarelayRs
-            } else if has_command("wl-gammarelay").await? {
+            } else if commands.has_command("wl-gammarelay").await? {
                 HueShifter::WlGammarelay
-            } else if has_command("redshift").await? {
+            } else if commands.has_command("redshift").await? {
                 HueShifter::Redshift
-            } else if has_command("sct").await? {
+            } else if commands.has_command("sct").await? {
                 HueShifter::Sct
-            } else if has_command("gammastep").await? {
+            } else if commands.has_command("gammastep").await? {
                 HueShifter::Gammastep
-            } else if has_command("wlsunset").await? {
+            } else if commands.has_command("wlsunset").await? {
                 HueShifter::Wlsunset
             } else {
                 return Err(Error::new("Cound not detect driver program"));
@@ -119,12 +143,16 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     };
 
     let mut driver: Box<dyn HueShiftDriver> = match hue_shifter {
-        HueShifter::Redshift => Box::new(Redshift::new(config.interval)),
-        HueShifter::Sct => Box::new(Sct::new(config.interval)),
-        HueShifter::Gammastep => Box::new(Gammastep::new(config.interval)),
-        HueShifter::Wlsunset => Box::new(Wlsunset::new(config.interval)),
-        HueShifter::WlGammarelay => Box::new(WlGammarelayRs::new("wl-gammarelay").await?),
-        HueShifter::WlGammarelayRs => Box::new(WlGammarelayRs::new("wl-gammarelay-rs").await?),
+        HueShifter::Redshift => Box::new(Redshift::new(config.interval, commands)),
+        HueShifter::Sct => Box::new(Sct::new(commands)),
+        HueShifter::Gammastep => Box::new(Gammastep::new(config.interval, commands)),
+        HueShifter::Wlsunset => Box::new(Wlsunset::new(commands)),
+        HueShifter::WlGammarelay => {
+            Box::new(WlGammarelayRs::new("wl-gammarelay", &commands).await?)
+        }
+        HueShifter::WlGammarelayRs => {
+            Box::new(WlGammarelayRs::new("wl-gammarelay-rs", &commands).await?)
+        }
     };
 
     let mut current_temp = driver.get().await?.unwrap_or(config.current_temp);
@@ -158,10 +186,14 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                         }
                     }
                     Action(a) if a == "temperature_up" => {
-                        current_temp = (current_temp + step).min(max_temp);
+                        let step = step
+                            .saturating_mul(api.action_count.try_into().unwrap_or(u16::MAX));
+                        current_temp = current_temp.saturating_add(step).min(max_temp);
                         driver.update(current_temp).await?;
                     }
                     Action(a) if a == "temperature_down" => {
+                        let step = step
+                            .saturating_mul(api.action_count.try_into().unwrap_or(u16::MAX));
                         current_temp = current_temp.saturating_sub(step).max(min_temp);
                         driver.update(current_temp).await?;
                     }
@@ -188,127 +220,220 @@ trait HueShiftDriver {
     async fn get(&mut self) -> Result<Option<u16>>;
     async fn update(&mut self, temp: u16) -> Result<()>;
     async fn reset(&mut self) -> Result<()>;
+    /// Wait until the temperature is changed outside of the bar
     async fn receive_update(&mut self) -> Result<u16>;
 }
 
+/// Wait `interval` and read the temperature from a running instance of `program`, until it
+/// reports one
+async fn poll_process(
+    interval: Option<Seconds>,
+    commands: &CommandContext,
+    program: &str,
+) -> Result<u16> {
+    let interval = match interval {
+        Some(interval) => interval,
+        None => return pending().await,
+    };
+    loop {
+        sleep(interval.0).await;
+        if let Some(temp) = get_from_process(commands, program).await? {
+            return Ok(temp);
+        }
+    }
+}
+
+/// The temperature set by a running instance of `program`, if there is one
+async fn get_from_process(commands: &CommandContext, program: &str) -> Result<Option<u16>> {
+    let args = match find_process(program).await {
+        Some(args) => args,
+        None => return Ok(None),
+    };
+    if let Some(temp) = oneshot_temperature(&args) {
+        return Ok(Some(temp));
+    }
+    if args
+        .iter()
+        .any(|arg| arg.starts_with("-x") || arg.starts_with("-p"))
+    {
+        return Ok(None);
+    }
+    // Continuous mode: ask for the temperature of the current period, using the same location
+    // and temperatures as the running instance
+    let output = commands
+        .command(program)
+        .args(&args)
+        .arg("-p")
+        .env("LC_ALL", "C")
+        .output()
+        .await
+        .or_error(|| format!("Failed to run {program} -p"))?;
+    Ok(parse_print_mode(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The arguments of the first running process named `program`
+async fn find_process(program: &str) -> Option<Vec<String>> {
+    let mut entries = read_dir("/proc").await.ok()?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        match read_to_string(path.join("comm")).await {
+            Ok(comm) if comm.trim_end() == program => (),
+            _ => continue,
+        }
+        if let Ok(cmdline) = read_to_string(path.join("cmdline")).await {
+            return Some(
+                cmdline
+                    .split_terminator('\0')
+                    .skip(1)
+                    .map(String::from)
+                    .collect(),
+            );
+        }
+    }
+    None
+}
+
+/// The temperature passed with `-O`, either as `-O 4000` or `-O4000`
+fn oneshot_temperature(args: &[String]) -> Option<u16> {
+    let pos = args.iter().position(|arg| arg.starts_with("-O"))?;
+    match &args[pos][2..] {
+        "" => args.get(pos + 1)?.parse().ok(),
+        temp => temp.parse().ok(),
+    }
+}
+
+/// Parse the output of `redshift -p` or `gammastep -p`, which contains a line like
+/// `Color temperature: 6500K`
+fn parse_print_mode(output: &str) -> Option<u16> {
+    output.lines().find_map(|line| {
+        line.strip_prefix("Color temperature: ")?
+            .trim_end()
+            .strip_suffix('K')?
+            .parse()
+            .ok()
+    })
+}
+
 struct Redshift {
-    interval: Seconds,
+    interval: Option<Seconds>,
+    commands: CommandContext,
 }
 
 impl Redshift {
-    fn new(interval: Seconds) -> Self {
-        Self { interval }
+    fn new(interval: Option<Seconds>, commands: CommandContext) -> Self {
+        Self { interval, commands }
     }
 }
 
 #[async_trait]
 impl HueShiftDriver for Redshift {
     async fn get(&mut self) -> Result<Option<u16>> {
-        // TODO
-        Ok(None)
+        get_from_process(&self.commands, "redshift").await
     }
     async fn update(&mut self, temp: u16) -> Result<()> {
-        spawn_process("redshift", &["-O", &temp.to_string(), "-P"])
+        self.commands
+            .spawn_process("redshift", &["-O", &temp.to_string(), "-P"])
             .error("Failed to set new color temperature using redshift.")
     }
     async fn reset(&mut self) -> Result<()> {
-        spawn_process("redshift", &["-x"])
+        self.commands
+            .spawn_process("redshift", &["-x"])
             .error("Failed to set new color temperature using redshift.")
     }
     async fn receive_update(&mut self) -> Result<u16> {
-        sleep(self.interval.0).await;
-        // self.get().await
-        pending().await
+        poll_process(self.interval, &self.commands, "redshift").await
     }
 }
 
 struct Sct {
-    interval: Seconds,
+    commands: CommandContext,
 }
 
 impl Sct {
-    fn new(interval: Seconds) -> Self {
-        Self { interval }
+    fn new(commands: CommandContext) -> Self {
+        Self { commands }
     }
 }
 
 #[async_trait]
 impl HueShiftDriver for Sct {
     async fn get(&mut self) -> Result<Option<u16>> {
-        // TODO
+        // sct cannot report the temperature
         Ok(None)
     }
     async fn update(&mut self, temp: u16) -> Result<()> {
-        spawn_shell(&format!("sct {temp} >/dev/null 2>&1"))
+        self.commands
+            .spawn_shell(&format!("sct {temp} >/dev/null 2>&1"))
             .error("Failed to set new color temperature using sct.")
     }
     async fn reset(&mut self) -> Result<()> {
-        spawn_process("sct", &[]).error("Failed to set new color temperature using sct.")
+        self.commands
+            .spawn_process("sct", &[])
+            .error("Failed to set new color temperature using sct.")
     }
     async fn receive_update(&mut self) -> Result<u16> {
-        sleep(self.interval.0).await;
-        // self.get().await
         pending().await
     }
 }
 
 struct Gammastep {
-    interval: Seconds,
+    interval: Option<Seconds>,
+    commands: CommandContext,
 }
 
 impl Gammastep {
-    fn new(interval: Seconds) -> Self {
-        Self { interval }
+    fn new(interval: Option<Seconds>, commands: CommandContext) -> Self {
+        Self { interval, commands }
     }
 }
 
 #[async_trait]
 impl HueShiftDriver for Gammastep {
     async fn get(&mut self) -> Result<Option<u16>> {
-        // TODO
-        Ok(None)
+        get_from_process(&self.commands, "gammastep").await
     }
     async fn update(&mut self, temp: u16) -> Result<()> {
-        spawn_shell(&format!("killall gammastep; gammastep -O {temp} -P &",))
+        self.commands
+            .spawn_shell(&format!("killall gammastep; gammastep -O {temp} -P &",))
             .error("Failed to set new color temperature using gammastep.")
     }
     async fn reset(&mut self) -> Result<()> {
-        spawn_process("gammastep", &["-x"])
+        self.commands
+            .spawn_process("gammastep", &["-x"])
             .error("Failed to set new color temperature using gammastep.")
     }
     async fn receive_update(&mut self) -> Result<u16> {
-        sleep(self.interval.0).await;
-        // self.get().await
-        pending().await
+        poll_process(self.interval, &self.commands, "gammastep").await
     }
 }
 
 struct Wlsunset {
-    interval: Seconds,
+    commands: CommandContext,
 }
 
 impl Wlsunset {
-    fn new(interval: Seconds) -> Self {
-        Self { interval }
+    fn new(commands: CommandContext) -> Self {
+        Self { commands }
     }
 }
 
 #[async_trait]
 impl HueShiftDriver for Wlsunset {
     async fn get(&mut self) -> Result<Option<u16>> {
-        // TODO
+        // wlsunset cannot report the temperature
         Ok(None)
     }
     async fn update(&mut self, temp: u16) -> Result<()> {
         // wlsunset does not have a oneshot option, so set both day and
         // night temperature. wlsunset dose not allow for day and night
         // temperatures to be the same, so increment the day temperature.
-        spawn_shell(&format!(
-            "killall wlsunset; wlsunset -T {} -t {} &",
-            temp + 1,
-            temp
-        ))
-        .error("Failed to set new color temperature using wlsunset.")
+        self.commands
+            .spawn_shell(&format!(
+                "killall wlsunset; wlsunset -T {} -t {} &",
+                temp + 1,
+                temp
+            ))
+            .error("Failed to set new color temperature using wlsunset.")
     }
     async fn reset(&mut self) -> Result<()> {
         // wlsunset does not have a reset option, so just kill the process.
@@ -319,12 +444,11 @@ impl HueShiftDriver for Wlsunset {
         //     ^ results in sun_condition == POLAR_NIGHT at time of testing
         // With these defaults, this results in the the color temperature
         // getting set to 4000K.
-        spawn_process("killall", &["wlsunset"])
+        self.commands
+            .spawn_process("killall", &["wlsunset"])
             .error("Failed to set new color temperature using wlsunset.")
     }
     async fn receive_update(&mut self) -> Result<u16> {
-        sleep(self.interval.0).await;
-        // self.get().await
         pending().await
     }
 }
@@ -335,9 +459,11 @@ struct WlGammarelayRs {
 }
 
 impl WlGammarelayRs {
-    async fn new(cmd: &str) -> Result<Self> {
+    async fn new(cmd: &str, commands: &CommandContext) -> Result<Self> {
         // Make sure the daemon is running
-        spawn_process(cmd, &[]).error("Failed to start wl-gammarelay daemon")?;
+        commands
+            .spawn_process(cmd, &[])
+            .error("Failed to start wl-gammarelay daemon")?;
         sleep(Duration::from_millis(100)).await;
 
         let conn = crate::util::new_dbus_connection().await?;
@@ -392,3 +518,30 @@ trait WlGammarelayRsBus {
     #[dbus_proxy(property)]
     fn set_temperature(&self, value: u16) -> zbus::Result<()>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oneshot() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            oneshot_temperature(&args(&["-O", "4000", "-P"])),
+            Some(4000)
+        );
+        assert_eq!(oneshot_temperature(&args(&["-P", "-O3500"])), Some(3500));
+        assert_eq!(oneshot_temperature(&args(&["-l", "55.7:12.6"])), None);
+        assert_eq!(oneshot_temperature(&args(&["-O"])), None);
+    }
+
+    #[test]
+    fn print_mode() {
+        let output = "Period: Night\nColor temperature: 4500K\nBrightness: 1.00\n";
+        assert_eq!(parse_print_mode(output), Some(4500));
+        // Older versions of redshift print the location first
+        let output = "Location: 55.70 N, 12.60 E\nPeriod: Daytime\nColor temperature: 6500K\n";
+        assert_eq!(parse_print_mode(output), Some(6500));
+        assert_eq!(parse_print_mode("Unable to get location\n"), None);
+    }
+}
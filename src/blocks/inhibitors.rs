@@ -0,0 +1,304 @@
+//! Applications which keep the machine awake
+//!
+//! Shows when an application, e.g. a video player or a video call, inhibits the screen from
+//! locking or the machine from sleeping, which explains why it does not. The inhibitors are read
+//! from logind, counting those which block `idle` or `sleep`. The block is hidden while there are
+//! none.
+//!
+//! Applications which use the idle inhibit protocol of Wayland are not known to logind. Under
+//! sway, set `sway_ipc = true` to count the windows which inhibit idle as well.
+//!
+//! The inhibitors are read every `interval`, before the machine goes to sleep and when the session
+//! is locked or unlocked.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $count $who "`
+//! `interval` | Update interval in seconds | `30`
+//! `sway_ipc` | Whether to count the windows which inhibit idle over sway's IPC as well | `false`
+//!
+//! Placeholder | Value                                                   | Type   | Unit
+//! ------------|---------------------------------------------------------|--------|-----
+//! `icon`      | A static icon                                           | Icon   | -
+//! `count`     | The number of inhibitors                                | Number | -
+//! `who`       | The application holding the first inhibitor             | Text   | -
+//! `why`       | The reason given for the first inhibitor, if any        | Text   | -
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "inhibitors"
+//! format = " $icon $who.str(max_w:15) {($why.str(max_w:20))|} "
+//! sway_ipc = true
+//! ```
+//!
+//! # Icons Used
+//! - `inhibitor`
+
+use super::prelude::*;
+use swayipc_async::{Connection, Node};
+use zbus::dbus_proxy;
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    format: FormatConfig,
+    #[default(30.into())]
+    interval: Seconds,
+    sway_ipc: bool,
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Manager {
+    /// `(what, who, why, mode, uid, pid)` of each inhibitor
+    fn list_inhibitors(&self) -> zbus::Result<Vec<(String, String, String, String, u32, u32)>>;
+
+    #[dbus_proxy(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1/session/auto"
+)]
+trait Session {
+    #[dbus_proxy(signal)]
+    fn lock(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn unlock(&self) -> zbus::Result<()>;
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    let mut widget = Widget::new().with_format(config.format.with_default(" $icon $count $who ")?);
+
+    let dbus_conn = new_system_dbus_connection().await?;
+    let manager = ManagerProxy::new(&dbus_conn)
+        .await
+        .error("Failed to create ManagerProxy")?;
+    let session = SessionProxy::new(&dbus_conn)
+        .await
+        .error("Failed to create SessionProxy")?;
+    let mut sleeps = manager
+        .receive_prepare_for_sleep()
+        .await
+        .error("Failed to receive PrepareForSleep")?;
+    let mut locks = session
+        .receive_lock()
+        .await
+        .error("Failed to receive Lock")?;
+    let mut unlocks = session
+        .receive_unlock()
+        .await
+        .error("Failed to receive Unlock")?;
+
+    let mut sway = if config.sway_ipc {
+        Some(
+            Connection::new()
+                .await
+                .error("failed to open connection with swayipc")?,
+        )
+    } else {
+        None
+    };
+
+    let mut timer = config.interval.timer();
+
+    loop {
+        let logind = manager
+            .list_inhibitors()
+            .await
+            .error("Failed to list inhibitors")?
+            .into_iter()
+            .map(Inhibitor::from_logind)
+            .filter(Inhibitor::keeps_awake)
+            .collect();
+        let compositor = match &mut sway {
+            Some(connection) => {
+                let tree = connection
+                    .get_tree()
+                    .await
+                    .error("failed to get the tree from swayipc")?;
+                let mut inhibitors = Vec::new();
+                compositor_inhibitors(&tree, &mut inhibitors);
+                inhibitors
+            }
+            None => Vec::new(),
+        };
+        let inhibitors = merge(logind, compositor);
+
+        match inhibitors.first() {
+            Some(first) => {
+                widget.set_values(map! {
+                    "icon" => Value::icon(api.get_icon("inhibitor")?),
+                    "count" => Value::number(inhibitors.len()),
+                    "who" => Value::text(first.who.clone()),
+                    [if !first.why.is_empty()] "why" => Value::text(first.why.clone()),
+                });
+                api.set_widget(&widget).await?;
+            }
+            None => api.hide().await?,
+        }
+
+        select! {
+            _ = timer.tick() => (),
+            _ = sleeps.next() => (),
+            _ = locks.next() => (),
+            _ = unlocks.next() => (),
+            _ = api.wait_for_update_request() => (),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Inhibitor {
+    /// Colon separated list of what is inhibited, e.g. `sleep:idle`
+    what: String,
+    who: String,
+    why: String,
+    /// `block` or `delay`
+    mode: String,
+}
+
+impl Inhibitor {
+    fn from_logind(
+        (what, who, why, mode, _uid, _pid): (String, String, String, String, u32, u32),
+    ) -> Self {
+        Self {
+            what,
+            who,
+            why,
+            mode,
+        }
+    }
+
+    /// Whether the screen cannot lock or the machine cannot sleep because of this inhibitor.
+    /// Inhibitors which only delay sleep, e.g. to lock the screen first, don't count.
+    fn keeps_awake(&self) -> bool {
+        self.mode == "block"
+            && self
+                .what
+                .split(':')
+                .any(|what| what == "idle" || what == "sleep")
+    }
+
+    fn blocks_idle(&self) -> bool {
+        self.what.split(':').any(|what| what == "idle")
+    }
+}
+
+/// The windows which inhibit idle, according to sway
+fn compositor_inhibitors(node: &Node, inhibitors: &mut Vec<Inhibitor>) {
+    if node.inhibit_idle == Some(true) {
+        let who = node
+            .app_id
+            .clone()
+            .or_else(|| node.window_properties.as_ref()?.class.clone())
+            .or_else(|| node.name.clone())
+            .unwrap_or_default();
+        inhibitors.push(Inhibitor {
+            what: "idle".into(),
+            who,
+            why: String::new(),
+            mode: "block".into(),
+        });
+    }
+    for child in node.nodes.iter().chain(&node.floating_nodes) {
+        compositor_inhibitors(child, inhibitors);
+    }
+}
+
+/// The inhibitors of logind, followed by those of the compositor. Applications which inhibit
+/// idle with logind as well are only counted once.
+fn merge(logind: Vec<Inhibitor>, compositor: Vec<Inhibitor>) -> Vec<Inhibitor> {
+    let mut inhibitors = logind;
+    for inhibitor in compositor {
+        let known = inhibitors
+            .iter()
+            .any(|i| i.blocks_idle() && i.who.eq_ignore_ascii_case(&inhibitor.who));
+        if !known {
+            inhibitors.push(inhibitor);
+        }
+    }
+    inhibitors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inhibitor(what: &str, who: &str, mode: &str) -> Inhibitor {
+        Inhibitor {
+            what: what.into(),
+            who: who.into(),
+            why: String::new(),
+            mode: mode.into(),
+        }
+    }
+
+    #[test]
+    fn logind() {
+        // As listed by `systemd-inhibit --list`
+        let listed = [
+            (
+                "handle-power-key:handle-suspend-key:handle-hibernate-key",
+                "GNOME Settings Daemon",
+                "GNOME handling keypresses",
+                "block",
+            ),
+            (
+                "sleep",
+                "NetworkManager",
+                "NetworkManager needs to turn off networks",
+                "delay",
+            ),
+            ("idle", "mpv", "Playing video", "block"),
+            ("sleep:idle", "zoom", "", "block"),
+        ];
+        let inhibitors: Vec<_> = listed
+            .into_iter()
+            .map(|(what, who, why, mode)| {
+                Inhibitor::from_logind((what.into(), who.into(), why.into(), mode.into(), 1000, 42))
+            })
+            .filter(Inhibitor::keeps_awake)
+            .collect();
+        assert_eq!(
+            inhibitors,
+            [
+                Inhibitor {
+                    why: "Playing video".into(),
+                    ..inhibitor("idle", "mpv", "block")
+                },
+                inhibitor("sleep:idle", "zoom", "block"),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_sources() {
+        let logind = vec![
+            inhibitor("idle", "mpv", "block"),
+            inhibitor("sleep", "firefox", "block"),
+        ];
+        let compositor = vec![
+            inhibitor("idle", "MPV", "block"),
+            inhibitor("idle", "firefox", "block"),
+            inhibitor("idle", "org.gnome.Totem", "block"),
+        ];
+        let who: Vec<_> = merge(logind, compositor)
+            .into_iter()
+            .map(|i| i.who)
+            .collect();
+        // Firefox only inhibits sleep with logind, so its window inhibiting idle counts as well
+        assert_eq!(who, ["mpv", "firefox", "firefox", "org.gnome.Totem"]);
+        assert!(merge(Vec::new(), Vec::new()).is_empty());
+    }
+}
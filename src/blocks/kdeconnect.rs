@@ -143,7 +143,7 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                     values.insert("notif_count".into(), Value::number(notif_count));
                     values.insert(
                         "notif_icon".into(),
-                        Value::icon(api.get_icon("notification")?.trim().into()),
+                        Value::icon(api.get_icon("notification")?.map(|icon| icon.trim().into())),
                     );
                 }
                 if !battery_state {
@@ -21,7 +21,7 @@
 //!  Key     | Value | Type
 //! ---------|-------|-----
 //! `layout` | Keyboard layout name | String
-//! `variant`| Keyboard variant. Only `localebus`, `sway` and `kbddbus` are supported so far. | String
+//! `variant`| Keyboard variant, `N/A` if not set | String
 //!
 //! # Examples
 //!
@@ -80,8 +80,8 @@
 //! ```
 
 use super::prelude::*;
+use crate::localization::Text;
 use swayipc_async::{Connection, Event, EventType};
-use tokio::process::Command;
 use zbus::dbus_proxy;
 
 #[derive(Deserialize, Debug, SmartDefault)]
@@ -109,7 +109,10 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     let mut widget = Widget::new().with_format(config.format.with_default(" $layout ")?);
 
     let mut backend: Box<dyn Backend> = match config.driver {
-        KeyboardLayoutDriver::SetXkbMap => Box::new(SetXkbMap(config.interval)),
+        KeyboardLayoutDriver::SetXkbMap => Box::new(SetXkbMap {
+            interval: config.interval,
+            commands: api.command_context.clone(),
+        }),
         KeyboardLayoutDriver::LocaleBus => Box::new(LocaleBus::new().await?),
         KeyboardLayoutDriver::KbddBus => Box::new(KbddBus::new().await?),
         KeyboardLayoutDriver::Sway => Box::new(Sway::new(config.sway_kb_identifier).await?),
@@ -121,12 +124,19 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             variant,
         } = backend.get_info().await?;
 
-        let variant = variant.unwrap_or_else(|| "N/A".into());
+        // Mappings always use the English "N/A", so that they do not depend on the translation
         if let Some(mappings) = &config.mappings {
-            if let Some(mapped) = mappings.get(&format!("{layout} ({variant})")) {
+            let key_variant = variant.as_deref().unwrap_or("N/A");
+            if let Some(mapped) = mappings.get(&format!("{layout} ({key_variant})")) {
                 layout = mapped.clone();
             }
         }
+        let variant = variant.unwrap_or_else(|| {
+            api.shared_config
+                .localization
+                .get(Text::NotAvailable)
+                .into()
+        });
 
         widget.set_values(map! {
             "layout" => Value::text(layout),
@@ -147,40 +157,34 @@ trait Backend {
     async fn wait_for_change(&mut self) -> Result<()>;
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 struct Info {
     layout: String,
     variant: Option<String>,
 }
 
-struct SetXkbMap(Seconds);
+struct SetXkbMap {
+    interval: Seconds,
+    commands: CommandContext,
+}
 
 #[async_trait]
 impl Backend for SetXkbMap {
     async fn get_info(&mut self) -> Result<Info> {
-        let output = Command::new("setxkbmap")
+        let output = self
+            .commands
+            .command("setxkbmap")
             .arg("-query")
             .output()
             .await
             .error("Failed to execute setxkbmap")?;
         let output =
             String::from_utf8(output.stdout).error("setxkbmap produced a non-UTF8 output")?;
-        let layout = output
-            .lines()
-            // Find the "layout:    xxxx" entry.
-            .find(|line| line.starts_with("layout"))
-            .error("Could not find the layout entry from setxkbmap")?
-            .split_ascii_whitespace()
-            .last()
-            .error("Could not read the layout entry from setxkbmap.")?;
-        Ok(Info {
-            layout: layout.into(),
-            variant: None,
-        })
+        parse_setxkbmap_query(&output)
     }
 
     async fn wait_for_change(&mut self) -> Result<()> {
-        sleep(self.0 .0).await;
+        sleep(self.interval.0).await;
         Ok(())
     }
 }
@@ -198,7 +202,7 @@ impl LocaleBus {
             .await
             .error("Failed to create LocaleBusProxy")?;
         let layout_updates = proxy.receive_layout_changed().await;
-        let variant_updates = proxy.receive_layout_changed().await;
+        let variant_updates = proxy.receive_variant_changed().await;
         Ok(Self {
             proxy,
             stream1: layout_updates,
@@ -298,6 +302,28 @@ impl Backend for Sway {
     }
 }
 
+/// Parse the output of `setxkbmap -query`
+fn parse_setxkbmap_query(output: &str) -> Result<Info> {
+    let entry = |name: &str| {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .map(str::trim)
+    };
+    let layout = entry("layout").error("Could not find the layout entry from setxkbmap")?;
+    if layout.is_empty() {
+        return Err(Error::new(
+            "Could not read the layout entry from setxkbmap.",
+        ));
+    }
+    Ok(Info {
+        layout: layout.into(),
+        variant: entry("variant")
+            .filter(|variant| !variant.is_empty())
+            .map(Into::into),
+    })
+}
+
 fn parse_layout(layout: &str) -> Info {
     if let Some(i) = layout.find('(') {
         Info {
@@ -390,3 +416,50 @@ impl Backend for KbddBus {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn setxkbmap_query() {
+        let output = "rules:      evdev\nmodel:      pc105\nlayout:     us\n";
+        assert_eq!(
+            parse_setxkbmap_query(output).unwrap(),
+            Info {
+                layout: "us".into(),
+                variant: None,
+            }
+        );
+
+        let output = "rules:      evdev\nmodel:      pc105\nlayout:     de\nvariant:    nodeadkeys\noptions:    caps:escape\n";
+        assert_eq!(
+            parse_setxkbmap_query(output).unwrap(),
+            Info {
+                layout: "de".into(),
+                variant: Some("nodeadkeys".into()),
+            }
+        );
+
+        assert!(parse_setxkbmap_query("rules:      evdev\n").is_err());
+        assert!(parse_setxkbmap_query("layout:\n").is_err());
+    }
+
+    #[test]
+    fn layout_names() {
+        assert_eq!(
+            parse_layout("English (US)"),
+            Info {
+                layout: "English".into(),
+                variant: Some("US".into()),
+            }
+        );
+        assert_eq!(
+            parse_layout("German"),
+            Info {
+                layout: "German".into(),
+                variant: None,
+            }
+        );
+    }
+}
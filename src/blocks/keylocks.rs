@@ -0,0 +1,352 @@
+//! Caps Lock, Num Lock and Scroll Lock
+//!
+//! Shows the state of the lock keys, which is handy if the keyboard has no LEDs for them. The
+//! state is read from the LEDs which the kernel keeps for each keyboard in `/sys/class/leds`,
+//! e.g. `input3::capslock`. With several keyboards, a lock is on if it is on for any of them.
+//! Keyboards which are plugged in later are picked up.
+//!
+//! The kernel does not notify about LEDs which change because of a key press, so they are read
+//! every `interval`. Changes written to the LEDs by programs are shown at once.
+//!
+//! `format` is shown once for each lock in `locks`, in order. Its state is info if the lock is on
+//! and idle if it is off.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `driver` | The source of the lock states. Only `"sysfs"` is supported. | `"sysfs"`
+//! `locks` | The locks to show, out of `"caps_lock"`, `"num_lock"` and `"scroll_lock"` | `["caps_lock", "num_lock"]`
+//! `format` | A string to customise the output of each lock. See below for available placeholders. | `" $text "`
+//! `caps_lock` | The texts of Caps Lock, as `{ on = "...", off = "..." }` | `{ on = "CAPS", off = "caps" }`
+//! `num_lock` | The texts of Num Lock | `{ on = "NUM", off = "num" }`
+//! `scroll_lock` | The texts of Scroll Lock | `{ on = "SCRL", off = "scrl" }`
+//! `hide_inactive` | Whether to hide locks which are off. The block is hidden if all of them are. | `false`
+//! `interval` | Update interval in seconds | `1`
+//!
+//! Placeholder | Value                                   | Type | Unit
+//! ------------|-----------------------------------------|------|-----
+//! `text`      | The `on` or `off` text of the lock      | Text | -
+//! `on`        | Present if the lock is on               | Flag | -
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "keylocks"
+//! locks = ["caps_lock"]
+//! hide_inactive = true
+//! caps_lock = { on = "⇪", off = "" }
+//! ```
+
+use super::prelude::*;
+use crate::util::read_file;
+use crate::widget::WidgetPart;
+use inotify::{Inotify, WatchMask};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+const LEDS_DIR: &str = "/sys/class/leds";
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    driver: Driver,
+    #[default(vec![Lock::Caps, Lock::Num])]
+    locks: Vec<Lock>,
+    format: FormatConfig,
+    #[default(LockTexts::new("CAPS", "caps"))]
+    caps_lock: LockTexts,
+    #[default(LockTexts::new("NUM", "num"))]
+    num_lock: LockTexts,
+    #[default(LockTexts::new("SCRL", "scrl"))]
+    scroll_lock: LockTexts,
+    hide_inactive: bool,
+    #[default(1.into())]
+    interval: Seconds,
+}
+
+#[derive(Deserialize, Debug, SmartDefault, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Driver {
+    #[default]
+    Sysfs,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Lock {
+    #[serde(rename = "caps_lock")]
+    Caps,
+    #[serde(rename = "num_lock")]
+    Num,
+    #[serde(rename = "scroll_lock")]
+    Scroll,
+}
+
+impl Lock {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Caps => "caps_lock",
+            Self::Num => "num_lock",
+            Self::Scroll => "scroll_lock",
+        }
+    }
+
+    /// The function of the LED, which its name ends with
+    fn led_function(self) -> &'static str {
+        match self {
+            Self::Caps => "capslock",
+            Self::Num => "numlock",
+            Self::Scroll => "scrolllock",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct LockTexts {
+    on: String,
+    off: String,
+}
+
+impl LockTexts {
+    fn new(on: &str, off: &str) -> Self {
+        Self {
+            on: on.into(),
+            off: off.into(),
+        }
+    }
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    let mut widget = Widget::new().with_format(config.format.with_default(" $text ")?);
+    let leds_dir = match config.driver {
+        Driver::Sysfs => Path::new(LEDS_DIR),
+    };
+
+    let mut notify = Inotify::init().error("Failed to start inotify")?;
+    let mut led_changes = notify
+        .event_stream([0; 1024])
+        .error("Failed to create event stream")?;
+    // The LEDs whose brightness is watched. Those of unplugged keyboards are dropped.
+    let mut watched = HashMap::new();
+    let mut timer = config.interval.timer();
+
+    loop {
+        let leds = discover(leds_dir, &config.locks).await?;
+        for watch in prune(&mut watched, &leds) {
+            // The kernel removes the watch of a deleted file by itself, so this may fail
+            let _ = notify.rm_watch(watch);
+        }
+        for led in leds.iter().flat_map(|(_, leds)| leds) {
+            if watched.contains_key(led) {
+                continue;
+            }
+            // The LED may be gone already, in which case polling picks up the change
+            if let Ok(watch) = notify.add_watch(led.join("brightness"), WatchMask::MODIFY) {
+                watched.insert(led.clone(), watch);
+            }
+        }
+
+        let mut parts = Vec::new();
+        for (lock, leds) in &leds {
+            let on = is_on(leds).await;
+            if config.hide_inactive && !on {
+                continue;
+            }
+            let texts = match lock {
+                Lock::Caps => &config.caps_lock,
+                Lock::Num => &config.num_lock,
+                Lock::Scroll => &config.scroll_lock,
+            };
+            let text = if on { &texts.on } else { &texts.off };
+            parts.push(WidgetPart {
+                name: lock.name().into(),
+                state: if on { State::Info } else { State::Idle },
+                values: map! {
+                    "text" => Value::text(text.clone()),
+                    [if on] "on" => Value::flag(),
+                },
+            });
+        }
+
+        if parts.is_empty() {
+            api.hide().await?;
+        } else {
+            widget.state = if parts.iter().any(|p| p.state == State::Info) {
+                State::Info
+            } else {
+                State::Idle
+            };
+            widget.set_parts(parts);
+            api.set_widget(&widget).await?;
+        }
+
+        select! {
+            _ = timer.tick() => (),
+            _ = led_changes.next() => (),
+            _ = api.wait_for_update_request() => (),
+        }
+    }
+}
+
+/// Forgets the watched LEDs which are not in `leds` anymore, e.g. because their keyboard was
+/// unplugged. Returns their watches.
+fn prune<W>(watched: &mut HashMap<PathBuf, W>, leds: &[(Lock, Vec<PathBuf>)]) -> Vec<W> {
+    let gone: Vec<PathBuf> = watched
+        .keys()
+        .filter(|led| !leds.iter().any(|(_, leds)| leds.contains(led)))
+        .cloned()
+        .collect();
+    gone.iter().filter_map(|led| watched.remove(led)).collect()
+}
+
+/// The LEDs in `leds_dir` of each of `locks`, in order. Each keyboard has its own LEDs, e.g.
+/// `input3::capslock` and `input15::capslock`. LEDs which are listed more than once are only
+/// returned once.
+async fn discover(leds_dir: &Path, locks: &[Lock]) -> Result<Vec<(Lock, Vec<PathBuf>)>> {
+    let mut entries = tokio::fs::read_dir(leds_dir)
+        .await
+        .or_error(|| format!("Failed to read {}", leds_dir.display()))?;
+    let mut names = Vec::new();
+    while let Some(entry) = entries.next_entry().await.error("Failed to read LED")? {
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+
+    let mut result: Vec<(Lock, Vec<PathBuf>)> = Vec::new();
+    for &lock in locks {
+        if result.iter().any(|(l, _)| *l == lock) {
+            continue;
+        }
+        let mut leds = Vec::new();
+        let mut seen = HashSet::new();
+        for name in &names {
+            let is_led = name
+                .rsplit_once("::")
+                .is_some_and(|(_, function)| function == lock.led_function());
+            if !is_led {
+                continue;
+            }
+            let path = leds_dir.join(name);
+            // The entries are symlinks to the devices
+            let device = tokio::fs::canonicalize(&path)
+                .await
+                .unwrap_or_else(|_| path.clone());
+            if seen.insert(device) {
+                leds.push(path);
+            }
+        }
+        result.push((lock, leds));
+    }
+    Ok(result)
+}
+
+/// Whether any of `leds` is on. LEDs which can't be read, e.g. of a keyboard which was just
+/// unplugged, count as off.
+async fn is_on(leds: &[PathBuf]) -> bool {
+    for led in leds {
+        if let Ok(brightness) = read_file(led.join("brightness")).await {
+            if brightness.parse::<u32>().is_ok_and(|b| b > 0) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    /// A fake `/sys` with LEDs in `devices`, linked to from `class/leds`
+    fn fake_sys(name: &str, leds: &[(&str, u32)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("keylocks-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let class = root.join("class/leds");
+        fs::create_dir_all(&class).unwrap();
+        for (led, brightness) in leds {
+            let device = root.join("devices").join(led);
+            fs::create_dir_all(&device).unwrap();
+            fs::write(device.join("brightness"), format!("{brightness}\n")).unwrap();
+            symlink(&device, class.join(led)).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn discovery() {
+        let root = fake_sys(
+            "discovery",
+            &[
+                ("input3::capslock", 0),
+                ("input3::numlock", 1),
+                ("input15::capslock", 1),
+                ("input15::numlock", 0),
+                ("input3::kana", 1),
+                ("phy0-led", 1),
+            ],
+        );
+        let class = root.join("class/leds");
+        // The same LED linked twice
+        symlink(
+            root.join("devices/input3::capslock"),
+            class.join("input3-alias::capslock"),
+        )
+        .unwrap();
+
+        tokio_test::block_on(async {
+            let locks = [Lock::Caps, Lock::Scroll, Lock::Num, Lock::Caps];
+            let leds = discover(&class, &locks).await.unwrap();
+            let names: Vec<(Lock, Vec<String>)> = leds
+                .iter()
+                .map(|(lock, leds)| {
+                    let names = leds
+                        .iter()
+                        .map(|led| led.file_name().unwrap().to_string_lossy().into_owned())
+                        .collect();
+                    (*lock, names)
+                })
+                .collect();
+            assert_eq!(
+                names,
+                [
+                    (
+                        Lock::Caps,
+                        vec!["input15::capslock".into(), "input3-alias::capslock".into()]
+                    ),
+                    (Lock::Scroll, vec![]),
+                    (
+                        Lock::Num,
+                        vec!["input15::numlock".into(), "input3::numlock".into()]
+                    ),
+                ]
+            );
+
+            // A lock is on if it is on for any keyboard
+            assert!(is_on(&leds[0].1).await);
+            assert!(!is_on(&leds[1].1).await);
+            assert!(is_on(&leds[2].1).await);
+            fs::write(root.join("devices/input15::capslock/brightness"), "0\n").unwrap();
+            assert!(!is_on(&leds[0].1).await);
+
+            // A keyboard which is unplugged counts as off
+            fs::remove_dir_all(root.join("devices/input3::numlock")).unwrap();
+            assert!(!is_on(&leds[2].1).await);
+        });
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn pruning() {
+        let caps = |input: &str| PathBuf::from(format!("/sys/class/leds/{input}::capslock"));
+        let mut watched = HashMap::from([(caps("input3"), 3), (caps("input15"), 15)]);
+        // The second keyboard was unplugged
+        let leds = [(Lock::Caps, vec![caps("input3")])];
+        assert_eq!(prune(&mut watched, &leds), [15]);
+        assert_eq!(watched, HashMap::from([(caps("input3"), 3)]));
+        assert!(prune(&mut watched, &leds).is_empty());
+    }
+}
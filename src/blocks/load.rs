@@ -4,18 +4,31 @@
 //!
 //! Key        | Values                                                                                | Default
 //! -----------|---------------------------------------------------------------------------------------|--------
-//! `format`   | A string to customise the output of this block. See below for available placeholders. | `" $icon $1m "`
+//! `format`   | A string to customise the output of this block. See below for available placeholders. | `" $icon $avg "`
 //! `interval` | Update interval in seconds                                                            | `3`
 //! `info`     | Minimum load, where state is set to info                                              | `0.3`
 //! `warning`  | Minimum load, where state is set to warning                                           | `0.6`
 //! `critical` | Minimum load, where state is set to critical                                          | `0.9`
+//! `per_core` | Whether to divide `1m`, `5m`, `15m` and `avg` by the number of logical cores          | `false`
 //!
-//! Placeholder  | Value                  | Type   | Unit
-//! -------------|------------------------|--------|-----
-//! `icon`       | A static icon          | Icon   | -
-//! `1m`         | 1 minute load average  | Number | -
-//! `5m`         | 5 minute load average  | Number | -
-//! `15m`        | 15 minute load average | Number | -
+//! The state is always set by the 1 minute load average divided by the number of logical cores, so
+//! `info`, `warning` and `critical` apply to a single core.
+//!
+//! Placeholder    | Value                                                   | Type   | Unit
+//! ---------------|---------------------------------------------------------|--------|-----
+//! `icon`         | A static icon                                           | Icon   | -
+//! `1m`           | 1 minute load average                                   | Number | -
+//! `5m`           | 5 minute load average                                   | Number | -
+//! `15m`          | 15 minute load average                                  | Number | -
+//! `1m_per_core`  | 1 minute load average divided by the number of cores    | Number | -
+//! `5m_per_core`  | 5 minute load average divided by the number of cores    | Number | -
+//! `15m_per_core` | 15 minute load average divided by the number of cores   | Number | -
+//! `avg`          | The load average selected by clicking, initially `1m`   | Number | -
+//! `avg_period`   | The period of `avg`: `1m`, `5m` or `15m`                | Text   | -
+//!
+//! Action          | Description                                        | Default button
+//! ----------------|----------------------------------------------------|---------------
+//! `cycle_average` | Switch `avg` between the 1, 5 and 15 minute averages | Left
 //!
 //! # Example
 //!
@@ -26,6 +39,15 @@
 //! interval = 1
 //! ```
 //!
+//! Show the load per core, and which average is shown:
+//!
+//! ```toml
+//! [[block]]
+//! block = "load"
+//! format = " $icon $avg_period $avg "
+//! per_core = true
+//! ```
+//!
 //! # Icons Used
 //! - `cogs`
 
@@ -44,10 +66,16 @@ pub struct Config {
     warning: f64,
     #[default(0.9)]
     critical: f64,
+    per_core: bool,
 }
 
+const PERIODS: [&str; 3] = ["1m", "5m", "15m"];
+
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
-    let mut widget = Widget::new().with_format(config.format.with_default(" $icon $1m ")?);
+    api.set_default_actions(&[(MouseButton::Left, None, "cycle_average")])
+        .await?;
+
+    let mut widget = Widget::new().with_format(config.format.with_default(" $icon $avg ")?);
 
     // borrowed from https://docs.rs/cpuinfo/0.1.1/src/cpuinfo/count/logical.rs.html#4-6
     let logical_cores = util::read_file("/proc/cpuinfo")
@@ -55,43 +83,79 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         .error("Your system doesn't support /proc/cpuinfo")?
         .lines()
         .filter(|l| l.starts_with("processor"))
-        .count();
+        .count()
+        .max(1) as f64;
+
+    // The index of the average shown as `avg`
+    let mut selected = 0;
 
     loop {
         let loadavg = util::read_file("/proc/loadavg")
             .await
             .error("Your system does not support reading the load average from /proc/loadavg")?;
-        let mut values = loadavg.split(' ');
-        let m1: f64 = values
-            .next()
-            .and_then(|x| x.parse().ok())
-            .error("bad /proc/loadavg file")?;
-        let m5: f64 = values
-            .next()
-            .and_then(|x| x.parse().ok())
-            .error("bad /proc/loadavg file")?;
-        let m15: f64 = values
-            .next()
-            .and_then(|x| x.parse().ok())
-            .error("bad /proc/loadavg file")?;
+        let averages = parse_loadavg(&loadavg)?;
+        let per_core = averages.map(|x| x / logical_cores);
 
-        widget.state = match m1 / logical_cores as f64 {
+        widget.state = match per_core[0] {
             x if x > config.critical => State::Critical,
             x if x > config.warning => State::Warning,
             x if x > config.info => State::Info,
             _ => State::Idle,
         };
+        let shown = if config.per_core { per_core } else { averages };
         widget.set_values(map! {
             "icon" => Value::icon(api.get_icon("cogs")?),
-            "1m" => Value::number(m1),
-            "5m" => Value::number(m5),
-            "15m" => Value::number(m15),
+            "1m" => Value::number(shown[0]),
+            "5m" => Value::number(shown[1]),
+            "15m" => Value::number(shown[2]),
+            "1m_per_core" => Value::number(per_core[0]),
+            "5m_per_core" => Value::number(per_core[1]),
+            "15m_per_core" => Value::number(per_core[2]),
+            "avg" => Value::number(shown[selected]),
+            "avg_period" => Value::text(PERIODS[selected].into()),
         });
         api.set_widget(&widget).await?;
 
-        select! {
-            _ = sleep(config.interval.0) => (),
-            _ = api.wait_for_update_request() => (),
+        loop {
+            select! {
+                _ = sleep(config.interval.0) => break,
+                event = api.event() => match event {
+                    UpdateRequest => break,
+                    Action(a) if a == "cycle_average" => {
+                        selected = (selected + 1) % PERIODS.len();
+                        break;
+                    }
+                    _ => (),
+                }
+            }
         }
     }
 }
+
+/// The 1, 5 and 15 minute load averages from the contents of `/proc/loadavg`
+fn parse_loadavg(loadavg: &str) -> Result<[f64; 3]> {
+    let mut values = loadavg.split_ascii_whitespace().map(str::parse);
+    let mut next = || {
+        values
+            .next()
+            .and_then(|x| x.ok())
+            .or_error(|| format!("bad /proc/loadavg file: '{}'", loadavg.trim()))
+    };
+    Ok([next()?, next()?, next()?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loadavg() {
+        assert_eq!(
+            parse_loadavg("0.52 1.07 0.98 2/1203 45311\n").unwrap(),
+            [0.52, 1.07, 0.98]
+        );
+        assert!(parse_loadavg("0.52 1.07\n").is_err());
+        assert!(parse_loadavg("0.52 x 0.98 2/1203 45311").is_err());
+        assert!(parse_loadavg("").is_err());
+    }
+}
@@ -4,13 +4,13 @@
 //!
 //! Key | Values | Default
 //! ----|--------|--------
-//! `format` | A string to customise the output of this block when in "Memory" view. See below for available placeholders. | `" $icon $mem_avail.eng(prefix:M)/$mem_total.eng(prefix:M)($mem_total_used_percents.eng(w:2)) "`
+//! `format` | A string to customise the output of this block. It may show memory and swap together. See below for available placeholders. | `" $icon $mem_avail.eng(prefix:M)/$mem_total.eng(prefix:M)($mem_total_used_percents.eng(w:2)) "`, with the short text `" $icon $mem_total_used_percents.eng(w:2) "` if `format` is not set
 //! `format_alt` | If set, block will switch between `format` and `format_alt` on every click | `None`
 //! `interval` | Update interval in seconds | `5`
-//! `warning_mem` | Percentage of memory usage, where state is set to warning | `80.0`
-//! `warning_swap` | Percentage of swap usage, where state is set to warning | `80.0`
-//! `critical_mem` | Percentage of memory usage, where state is set to critical | `95.0`
-//! `critical_swap` | Percentage of swap usage, where state is set to critical | `95.0`
+//! `warning_mem` | Percentage of memory usage, where state is set to warning. May also be a string with a unit, e.g. `"80%"` or `"12GiB"` | `80.0`
+//! `warning_swap` | Percentage of swap usage, where state is set to warning. May also be a string with a unit, e.g. `"80%"` or `"2GiB"` | `80.0`
+//! `critical_mem` | Percentage of memory usage, where state is set to critical. May also be a string with a unit, e.g. `"95%"` or `"14GiB"` | `95.0`
+//! `critical_swap` | Percentage of swap usage, where state is set to critical. May also be a string with a unit, e.g. `"95%"` or `"4GiB"` | `95.0`
 //!
 //! Placeholder               | Value                                                                           | Type   | Unit
 //! --------------------------|---------------------------------------------------------------------------------|--------|-------
@@ -34,6 +34,19 @@
 //! `swap_free_percents`      | as above but as a percentage of total memory                                    | Number | Percents
 //! `swap_used`               | Swap used                                                                       | Number | Bytes
 //! `swap_used_percents`      | as above but as a percentage of total memory                                    | Number | Percents
+//! `swap_used_zram_adjusted` | Swap used, counting the swap on zram devices by the memory it takes up          | Number | Bytes
+//! `zram_compressed`         | Size of the data stored in zram devices and zswap after compression             | Number | Bytes
+//! `zram_uncompressed`       | Size of the data stored in zram devices and zswap before compression            | Number | Bytes
+//! `zram_ratio`              | zram_uncompressed / zram_compressed                                             | Number | -
+//!
+//! The percentages of swap are `0` if there is no swap. The `zram` placeholders are `0` if there
+//! are no zram devices and zswap is off. zswap is only accounted for if `/sys/kernel/debug/zswap`
+//! is readable, which usually requires root.
+//!
+//! The state of the block is the worse of the states of memory and swap.
+//!
+//! `format_mem` and `format_swap` of older versions, which were toggled by a click, are migrated
+//! to `format` and `format_alt`. They are ignored if `format` is set as well.
 //!
 //! Action          | Description                               | Default button
 //! ----------------|-------------------------------------------|---------------
@@ -48,7 +61,15 @@
 //! format_alt = " $icon_swap $swap_free.eng(w:3,u:B,p:M)/$swap_total.eng(w:3,u:B,p:M)($swap_used_percents.eng(w:2)) "
 //! interval = 30
 //! warning_mem = 70
-//! critical_mem = 90
+//! critical_mem = "14GiB"
+//! ```
+//!
+//! Memory and swap usage at once, e.g. `MEM 43% SWP 2%`:
+//!
+//! ```toml
+//! [[block]]
+//! block = "memory"
+//! format = " MEM $mem_used_percents.eng(w:1) SWP $swap_used_percents.eng(w:1) "
 //! ```
 //!
 //! # Icons Used
@@ -56,6 +77,7 @@
 //! - `memory_swap`
 
 use std::cmp::min;
+use std::path::Path;
 use std::str::FromStr;
 use tokio::fs::File;
 use tokio::io::{AsyncBufReadExt, BufReader};
@@ -70,14 +92,14 @@ pub struct Config {
     format_alt: Option<FormatConfig>,
     #[default(5.into())]
     interval: Seconds,
-    #[default(80.0)]
-    warning_mem: f64,
-    #[default(80.0)]
-    warning_swap: f64,
-    #[default(95.0)]
-    critical_mem: f64,
-    #[default(95.0)]
-    critical_swap: f64,
+    #[default(Threshold::Number(80.0))]
+    warning_mem: Threshold,
+    #[default(Threshold::Number(80.0))]
+    warning_swap: Threshold,
+    #[default(Threshold::Number(95.0))]
+    critical_mem: Threshold,
+    #[default(Threshold::Number(95.0))]
+    critical_swap: Threshold,
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
@@ -86,14 +108,31 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
 
     let mut widget = Widget::new();
 
-    let mut format = config.format.with_default(
+    // A custom `format` has no short text unless it sets one
+    let default_short = match config.format.full {
+        Some(_) => "",
+        None => " $icon $mem_total_used_percents.eng(w:2) ",
+    };
+    let mut format = config.format.with_defaults(
         " $icon $mem_avail.eng(prefix:M)/$mem_total.eng(prefix:M)($mem_total_used_percents.eng(w:2)) ",
+        default_short,
     )?;
     let mut format_alt = match config.format_alt {
         Some(f) => Some(f.with_default("")?),
         None => None,
     };
 
+    for threshold in [
+        config.warning_mem,
+        config.warning_swap,
+        config.critical_mem,
+        config.critical_swap,
+    ] {
+        if let Threshold::Seconds(_) = threshold {
+            return Err(Error::new("Thresholds must be numbers, percents or sizes"));
+        }
+    }
+
     let mut timer = config.interval.timer();
 
     loop {
@@ -144,41 +183,54 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         let swap_cached = mem_state.swap_cached as f64 * 1024.;
         let swap_used = swap_total - swap_free - swap_cached;
 
+        let zram_compressed = (mem_state.zram_compressed + mem_state.zswap_compressed) as f64;
+        let zram_uncompressed = (mem_state.zram_uncompressed + mem_state.zswap_uncompressed) as f64;
+        let zram_ratio = if zram_compressed > 0. {
+            zram_uncompressed / zram_compressed
+        } else {
+            0.
+        };
+        // Swapped out pages on zram take up memory rather than their size. Pages in zswap are
+        // still counted as swap, since they are written to the swap device when the pool is full.
+        let swap_used_zram_adjusted = (swap_used - mem_state.zram_uncompressed as f64
+            + mem_state.zram_mem_used as f64)
+            .max(0.);
+
         widget.set_format(format.clone());
         widget.set_values(map! {
             "icon" => Value::icon(api.get_icon("memory_mem")?),
             "icon_swap" => Value::icon(api.get_icon("memory_swap")?),
             "mem_total" => Value::bytes(mem_total),
             "mem_free" => Value::bytes(mem_free),
-            "mem_free_percents" => Value::percents(mem_free / mem_total * 100.),
+            "mem_free_percents" => Value::percents(percents(mem_free, mem_total)),
             "mem_total_used" => Value::bytes(mem_total_used),
-            "mem_total_used_percents" => Value::percents(mem_total_used / mem_total * 100.),
+            "mem_total_used_percents" => Value::percents(percents(mem_total_used, mem_total)),
             "mem_used" => Value::bytes(mem_used),
-            "mem_used_percents" => Value::percents(mem_used / mem_total * 100.),
+            "mem_used_percents" => Value::percents(percents(mem_used, mem_total)),
             "mem_avail" => Value::bytes(mem_avail),
-            "mem_avail_percents" => Value::percents(mem_avail / mem_total * 100.),
+            "mem_avail_percents" => Value::percents(percents(mem_avail, mem_total)),
             "swap_total" => Value::bytes(swap_total),
             "swap_free" => Value::bytes(swap_free),
-            "swap_free_percents" => Value::percents(swap_free / swap_total * 100.),
+            "swap_free_percents" => Value::percents(percents(swap_free, swap_total)),
             "swap_used" => Value::bytes(swap_used),
-            "swap_used_percents" => Value::percents(swap_used / swap_total * 100.),
+            "swap_used_percents" => Value::percents(percents(swap_used, swap_total)),
+            "swap_used_zram_adjusted" => Value::bytes(swap_used_zram_adjusted),
+            "zram_compressed" => Value::bytes(zram_compressed),
+            "zram_uncompressed" => Value::bytes(zram_uncompressed),
+            "zram_ratio" => Value::number(zram_ratio),
             "buffers" => Value::bytes(buffers),
-            "buffers_percent" => Value::percents(buffers / mem_total * 100.),
+            "buffers_percent" => Value::percents(percents(buffers, mem_total)),
             "cached" => Value::bytes(cached),
-            "cached_percent" => Value::percents(cached / mem_total * 100.)
+            "cached_percent" => Value::percents(percents(cached, mem_total))
         });
 
-        let mem_state = match mem_used / mem_total * 100. {
-            x if x > config.critical_mem => State::Critical,
-            x if x > config.warning_mem => State::Warning,
-            _ => State::Idle,
-        };
-
-        let swap_state = match swap_used / swap_total * 100. {
-            x if x > config.critical_swap => State::Critical,
-            x if x > config.warning_swap => State::Warning,
-            _ => State::Idle,
-        };
+        let mem_state = usage_state(mem_used, mem_total, config.warning_mem, config.critical_mem);
+        let swap_state = usage_state(
+            swap_used,
+            swap_total,
+            config.warning_swap,
+            config.critical_swap,
+        );
 
         widget.state = if mem_state == State::Critical || swap_state == State::Critical {
             State::Critical
@@ -209,6 +261,30 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     }
 }
 
+/// `part` as a percentage of `total`, or `0` if `total` is `0`, e.g. if there is no swap
+fn percents(part: f64, total: f64) -> f64 {
+    if total > 0. {
+        part / total * 100.
+    } else {
+        0.
+    }
+}
+
+/// Bare numbers and percents are compared with the percentage of `used`, sizes with `used` itself
+fn usage_state(used: f64, total: f64, warning: Threshold, critical: Threshold) -> State {
+    let exceeds = |threshold: Threshold| match threshold {
+        Threshold::Bytes(bytes) => used > bytes,
+        other => percents(used, total) > other.value(),
+    };
+    if exceeds(critical) {
+        State::Critical
+    } else if exceeds(warning) {
+        State::Warning
+    } else {
+        State::Idle
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 struct Memstate {
     mem_total: u64,
@@ -222,6 +298,13 @@ struct Memstate {
     swap_free: u64,
     swap_cached: u64,
     zfs_arc_cache: u64,
+    /// Totals of the zram devices, in bytes
+    zram_uncompressed: u64,
+    zram_compressed: u64,
+    zram_mem_used: u64,
+    /// Totals of zswap, in bytes
+    zswap_uncompressed: u64,
+    zswap_compressed: u64,
 }
 
 impl Memstate {
@@ -283,6 +366,90 @@ impl Memstate {
             mem_state.zfs_arc_cache = size.parse().error("failed to parse zfs_arc_cache size")?;
         }
 
+        // Devices which are missing or unreadable count as empty
+        if let Ok(mut devices) = tokio::fs::read_dir("/sys/block").await {
+            while let Ok(Some(device)) = devices.next_entry().await {
+                if !device.file_name().to_string_lossy().starts_with("zram") {
+                    continue;
+                }
+                let mm_stat = read_file(device.path().join("mm_stat")).await;
+                if let Some(stat) = mm_stat.ok().as_deref().and_then(ZramStat::parse) {
+                    mem_state.zram_uncompressed += stat.orig_data_size;
+                    mem_state.zram_compressed += stat.compr_data_size;
+                    mem_state.zram_mem_used += stat.mem_used_total;
+                }
+            }
+        }
+
+        let zswap = |name| read_file(Path::new("/sys/kernel/debug/zswap").join(name));
+        if let (Ok(pool), Ok(pages)) = (zswap("pool_total_size").await, zswap("stored_pages").await)
+        {
+            let page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)
+                .ok()
+                .flatten()
+                .unwrap_or(4096) as u64;
+            mem_state.zswap_compressed = pool.trim().parse().unwrap_or(0);
+            mem_state.zswap_uncompressed = pages.trim().parse().unwrap_or(0) * page_size;
+        }
+
         Ok(mem_state)
     }
 }
+
+/// The sizes of `/sys/block/zram*/mm_stat`, see
+/// https://www.kernel.org/doc/html/latest/admin-guide/blockdev/zram.html
+#[derive(Debug, PartialEq, Eq)]
+struct ZramStat {
+    orig_data_size: u64,
+    compr_data_size: u64,
+    mem_used_total: u64,
+}
+
+impl ZramStat {
+    fn parse(mm_stat: &str) -> Option<Self> {
+        let mut fields = mm_stat.split_whitespace().map(|x| x.parse().ok());
+        Some(Self {
+            orig_data_size: fields.next()??,
+            compr_data_size: fields.next()??,
+            mem_used_total: fields.next()??,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zram_stat() {
+        assert_eq!(
+            ZramStat::parse(
+                "1174749184 326472451 340606976        0 341008384     9866     3316     1010     1207\n"
+            ),
+            Some(ZramStat {
+                orig_data_size: 1174749184,
+                compr_data_size: 326472451,
+                mem_used_total: 340606976,
+            })
+        );
+        // An unused device
+        assert_eq!(
+            ZramStat::parse("0 0 0 0 0 0 0 0 0"),
+            Some(ZramStat {
+                orig_data_size: 0,
+                compr_data_size: 0,
+                mem_used_total: 0,
+            })
+        );
+        assert_eq!(ZramStat::parse("4096 1024"), None);
+        assert_eq!(ZramStat::parse(""), None);
+    }
+
+    #[test]
+    fn no_swap() {
+        assert_eq!(percents(0., 0.), 0.);
+        assert_eq!(percents(1., 4.), 25.);
+        let threshold = Threshold::Number(80.);
+        assert_eq!(usage_state(0., 0., threshold, threshold), State::Idle);
+    }
+}
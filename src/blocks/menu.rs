@@ -32,7 +32,6 @@
 //! ```
 
 use super::prelude::*;
-use crate::subprocess::spawn_shell;
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
@@ -127,7 +126,10 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                     continue;
                 }
             }
-            spawn_shell(&res.cmd).or_error(|| format!("Failed to run '{}'", res.cmd))?;
+            let commands = &block.api.command_context;
+            commands
+                .spawn_shell(&res.cmd)
+                .or_error(|| format!("Failed to run '{}'", res.cmd))?;
         }
     }
 }
@@ -12,7 +12,8 @@
 //!
 //! By default the block tracks all players available on the MPRIS bus. Right clicking on the block
 //! will cycle it to the next player. You can pin the widget to a given player via the "player"
-//! setting.
+//! setting. When no player is available, the block shows `no_player_format`, or is hidden if
+//! `hide_when_empty` is set.
 //!
 //! # Configuration
 //!
@@ -23,6 +24,8 @@
 //! `interface_name_exclude` | A list of regex patterns for player MPRIS interface names to ignore. | `["playerctld"]`
 //! `separator` | String to insert between artist and title. | `" - "`
 //! `seek_step_secs` | Positive number of seconds to seek forward/backward when scrolling on the bar. Does not need to be an integer. | `1`
+//! `no_player_format` | A string to customise the output of this block when no player is available. Only `icon` placeholder is available. | `" $icon "`
+//! `hide_when_empty` | Hide the block when no player is available | `false`
 //!
 //! Note: All placeholders exctpt `icon` can be absent. See the examples below to learn how to handle this.
 //!
@@ -46,6 +49,7 @@
 //! `next`          | Left on `$next`
 //! `prev`          | Left on `$prev`
 //! `next_player`   | Right
+//! `prev_player`   | -
 //! `seek_forward`  | Wheel Up
 //! `seek_backward` | Wheel Down
 //!
@@ -79,6 +83,20 @@
 //! interface_name_exclude = [".*kdeconnect.*", "mpd"]
 //! ```
 //!
+//! Cycle between players by scrolling instead of seeking, and hide the block when no player is running:
+//!
+//! ```toml
+//! [[block]]
+//! block = "music"
+//! hide_when_empty = true
+//! [[block.click]]
+//! button = "up"
+//! action = "next_player"
+//! [[block.click]]
+//! button = "down"
+//! action = "prev_player"
+//! ```
+//!
 //! Click anywhere to paly/pause:
 //!
 //! ```toml
@@ -123,6 +141,8 @@ pub struct Config {
     separator: String,
     #[default(1.into())]
     seek_step_secs: Seconds<false>,
+    no_player_format: FormatConfig,
+    hide_when_empty: bool,
 }
 
 #[derive(Deserialize, Debug, Clone, SmartDefault)]
@@ -145,11 +165,11 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     .await?;
 
     let dbus_conn = new_dbus_connection().await?;
-    let mut widget = Widget::new().with_format(
-        config
-            .format
-            .with_default(" $icon {$combo.str(max_w:25,rot_interval:0.5) $play |}")?,
-    );
+    let format = config
+        .format
+        .with_default(" $icon {$combo.str(max_w:25,rot_interval:0.5) $play |}")?;
+    let no_player_format = config.no_player_format.with_default(" $icon ")?;
+    let mut widget = Widget::new();
 
     let new_btn = |icon: &str, instance: &'static str, api: &mut CommonApi| -> Result<Value> {
         Ok(Value::icon(api.get_icon(icon)?).with_instance(instance))
@@ -290,11 +310,16 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                     }
                     _ => (),
                 }
+                widget.set_format(format.clone());
                 widget.set_values(values);
                 widget.state = state;
                 api.set_widget(&widget).await?;
             }
+            None if config.hide_when_empty => {
+                api.hide().await?;
+            }
             None => {
+                widget.set_format(no_player_format.clone());
                 widget.set_values(map!("icon" => Value::icon(api.get_icon("music")?)));
                 widget.state = State::Idle;
                 api.set_widget(&widget).await?;
@@ -397,6 +422,13 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                                     }
                                     break;
                                 }
+                                "prev_player" => {
+                                    cur_player = Some((i + players.len() - 1) % players.len());
+                                    if let Err(e) = playerctrld_proxy.unshift().await{
+                                        debug!("{e}");
+                                    }
+                                    break;
+                                }
                                 "seek_forward" => {
                                     player.seek(config.seek_step_secs.0.as_micros() as i64).await?;
                                 }
@@ -99,6 +99,7 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
 
     // Stats
     let mut stats = None;
+    let mut stats_device = String::new();
     let mut stats_timer = Instant::now();
     let mut tx_hist = [0f64; 8];
     let mut rx_hist = [0f64; 8];
@@ -106,11 +107,13 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     loop {
         match NetDevice::new(device_re.as_ref()).await? {
             None => {
+                stats = None;
                 widget.set_format(missing_format.clone());
                 widget.set_values(default());
                 api.set_widget(&widget).await?;
             }
             Some(device) if !device.is_up() => {
+                stats = None;
                 widget.set_format(missing_format.clone());
                 widget.set_values(default());
                 api.set_widget(&widget).await?;
@@ -118,13 +121,22 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             Some(device) => {
                 widget.set_format(format.clone());
 
+                // Counters of different devices cannot be compared
+                if device.iface.name != stats_device {
+                    stats = None;
+                    stats_device.clone_from(&device.iface.name);
+                }
+
                 let mut speed_down: f64 = 0.0;
                 let mut speed_up: f64 = 0.0;
 
                 // Calculate speed
                 match (stats, device.iface.stats) {
                     // No previous stats available
-                    (None, new_stats) => stats = new_stats,
+                    (None, new_stats) => {
+                        stats = new_stats;
+                        stats_timer = Instant::now();
+                    }
                     // No new stats available
                     (Some(_), None) => stats = None,
                     // All stats available
@@ -0,0 +1,369 @@
+//! Network filesystems
+//!
+//! Shows whether each of a list of mount points (e.g. NFS or SMB shares) is mounted. Mounts are
+//! looked up in `/proc/self/mountinfo`, which never blocks. Optionally, the available space of
+//! each mounted filesystem is read with `statvfs`, in a separate thread and with a `timeout`: a
+//! share whose server is unreachable may hang such calls for minutes. If the call does not return
+//! in time, the share is shown as stale, and no new call is made for it until the hanging one
+//! returns.
+//!
+//! `format` is shown once for each mount point, in order. Its state is critical if it is stale and
+//! warning if it is not mounted. Clicking a mount point mounts or unmounts it.
+//!
+//! At most eight mount points are shown.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `format` | A string to customise the output of each mount point. See below for available placeholders. | `" $icon $name{ $available.eng(w:3)\|}{$stale stale\|} "`
+//! `interval` | Update interval in seconds | `10`
+//! `timeout` | How long to wait for `statvfs`, in seconds | `2`
+//! `check_space` | Whether to read the available space with `statvfs`. If `false`, stale shares are not detected. | `true`
+//! `mounts` | A list of mount points. See below. | `[]`
+//!
+//! Each entry of `mounts` has the following keys:
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `path` | The mount point. Supports path expansions e.g. `~`. | Required
+//! `name` | Name displayed in `format` | The last component of `path`
+//! `mount_cmd` | Shell command run when a mount point which is not mounted is clicked | `"mount <path>"`
+//! `umount_cmd` | Shell command run when a mounted mount point is clicked | `"umount <path>"`
+//!
+//! Placeholder  | Value                                                   | Type   | Unit
+//! -------------|---------------------------------------------------------|--------|------
+//! `icon`       | A static icon                                           | Icon   | -
+//! `name`       | The `name` of the mount point                           | Text   | -
+//! `path`       | The `path` of the mount point                           | Text   | -
+//! `mounted`    | Present if it is mounted                                | Flag   | -
+//! `stale`      | Present if `statvfs` did not return in time             | Flag   | -
+//! `available`  | Available space. Absent if it is not mounted or stale.  | Number | Bytes
+//! `latency_ms` | How long `statvfs` took. Absent if it is not mounted or stale. | Number | -
+//!
+//! Action                     | Default button
+//! ---------------------------|---------------
+//! `toggle_0` ... `toggle_7`  | Left on the first ... eighth mount point
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "netmount"
+//! timeout = 0.5
+//! [[block.mounts]]
+//! path = "/mnt/nas"
+//! [[block.mounts]]
+//! path = "~/work"
+//! name = "work"
+//! mount_cmd = "sshfs work:/srv ~/work"
+//! umount_cmd = "fusermount -u ~/work"
+//! ```
+//!
+//! # Icons Used
+//! - `disk_drive`
+
+use super::prelude::*;
+use crate::util::{read_file, spawn_blocking_timeout};
+use crate::widget::WidgetPart;
+use futures::stream::FuturesUnordered;
+use nix::sys::statvfs::statvfs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+const MAX_MOUNTS: usize = 8;
+const INSTANCES: [&str; MAX_MOUNTS] = ["0", "1", "2", "3", "4", "5", "6", "7"];
+const ACTIONS: [&str; MAX_MOUNTS] = [
+    "toggle_0", "toggle_1", "toggle_2", "toggle_3", "toggle_4", "toggle_5", "toggle_6", "toggle_7",
+];
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    format: FormatConfig,
+    #[default(10.into())]
+    interval: Seconds,
+    #[default(2.into())]
+    timeout: Seconds<false>,
+    #[default(true)]
+    check_space: bool,
+    mounts: Vec<MountConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct MountConfig {
+    path: ShellString,
+    name: Option<String>,
+    mount_cmd: Option<String>,
+    umount_cmd: Option<String>,
+}
+
+struct Mount {
+    path: String,
+    name: String,
+    mount_cmd: String,
+    umount_cmd: String,
+    /// Whether a `statvfs` call which timed out is still running
+    hanging: Arc<AtomicBool>,
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[
+        (MouseButton::Left, Some(INSTANCES[0]), ACTIONS[0]),
+        (MouseButton::Left, Some(INSTANCES[1]), ACTIONS[1]),
+        (MouseButton::Left, Some(INSTANCES[2]), ACTIONS[2]),
+        (MouseButton::Left, Some(INSTANCES[3]), ACTIONS[3]),
+        (MouseButton::Left, Some(INSTANCES[4]), ACTIONS[4]),
+        (MouseButton::Left, Some(INSTANCES[5]), ACTIONS[5]),
+        (MouseButton::Left, Some(INSTANCES[6]), ACTIONS[6]),
+        (MouseButton::Left, Some(INSTANCES[7]), ACTIONS[7]),
+    ])
+    .await?;
+
+    let mut widget = Widget::new().with_format(
+        config
+            .format
+            .with_default(" $icon $name{ $available.eng(w:3)|}{$stale stale|} ")?,
+    );
+
+    if config.mounts.len() > MAX_MOUNTS {
+        return Err(Error::new(format!(
+            "At most {MAX_MOUNTS} mount points are supported"
+        )));
+    }
+    let mounts = config
+        .mounts
+        .iter()
+        .map(|mount| {
+            let path = normalize(&mount.path.expand()?).to_string();
+            let name = mount.name.clone().unwrap_or_else(|| {
+                path.rsplit('/')
+                    .next()
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or("/")
+                    .into()
+            });
+            Ok(Mount {
+                mount_cmd: mount
+                    .mount_cmd
+                    .clone()
+                    .unwrap_or_else(|| format!("mount '{path}'")),
+                umount_cmd: mount
+                    .umount_cmd
+                    .clone()
+                    .unwrap_or_else(|| format!("umount '{path}'")),
+                path,
+                name,
+                hanging: Arc::new(AtomicBool::new(false)),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut timer = config.interval.timer();
+    // Mount and unmount commands started by clicks
+    let mut commands = FuturesUnordered::new();
+
+    loop {
+        let mountinfo = read_file("/proc/self/mountinfo")
+            .await
+            .error("Failed to read /proc/self/mountinfo")?;
+        let mount_points = mount_points(&mountinfo);
+
+        let mut parts = Vec::new();
+        let mut mounted = Vec::new();
+        for (mount, instance) in mounts.iter().zip(INSTANCES) {
+            let is_mounted = mount_points.contains(&mount.path);
+            mounted.push(is_mounted);
+            let mut values = map! {
+                "icon" => Value::icon(api.get_icon("disk_drive")?),
+                "name" => Value::text(mount.name.clone()),
+                "path" => Value::text(mount.path.clone()),
+                [if is_mounted] "mounted" => Value::flag(),
+            };
+            let state = if !is_mounted {
+                State::Warning
+            } else if !config.check_space {
+                State::Idle
+            } else {
+                match check(mount, config.timeout.0).await? {
+                    Some((available, latency)) => {
+                        values.insert("available".into(), Value::bytes(available));
+                        values.insert(
+                            "latency_ms".into(),
+                            Value::number(latency.as_millis() as f64),
+                        );
+                        State::Idle
+                    }
+                    None => {
+                        values.insert("stale".into(), Value::flag());
+                        State::Critical
+                    }
+                }
+            };
+            parts.push(WidgetPart {
+                name: instance.into(),
+                state,
+                values,
+            });
+        }
+
+        widget.state = if parts.iter().any(|p| p.state == State::Critical) {
+            State::Critical
+        } else if parts.iter().any(|p| p.state == State::Warning) {
+            State::Warning
+        } else {
+            State::Idle
+        };
+        widget.set_parts(parts);
+        api.set_widget(&widget).await?;
+
+        loop {
+            select! {
+                _ = timer.tick() => break,
+                Some(result) = commands.next() => {
+                    let (cmd, result): (String, std::io::Result<std::process::ExitStatus>) = result;
+                    match result {
+                        Ok(status) if status.success() => (),
+                        Ok(status) => log::warn!("'{cmd}' exited with {status}"),
+                        Err(e) => log::warn!("Failed to run '{cmd}': {e}"),
+                    }
+                    break;
+                }
+                event = api.event() => match event {
+                    UpdateRequest => break,
+                    Action(a) => {
+                        if let Some(i) = ACTIONS.iter().position(|x| *x == a).filter(|i| *i < mounts.len()) {
+                            let cmd = if mounted[i] {
+                                mounts[i].umount_cmd.clone()
+                            } else {
+                                mounts[i].mount_cmd.clone()
+                            };
+                            let mut child = api.command_context.shell(&cmd);
+                            commands.push(async move { let status = child.status().await; (cmd, status) });
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The available space in bytes and how long it took to get it, or `None` if the filesystem did
+/// not respond in time
+async fn check(mount: &Mount, timeout: Duration) -> Result<Option<(f64, Duration)>> {
+    // Don't pile up threads which hang on the same filesystem
+    if mount.hanging.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+    let hanging = mount.hanging.clone();
+    let path = mount.path.clone();
+    let start = Instant::now();
+    let stat = spawn_blocking_timeout(timeout, move || {
+        let stat = statvfs(&*path);
+        hanging.store(false, Ordering::SeqCst);
+        stat
+    })
+    .await?;
+    let latency = start.elapsed();
+    match stat {
+        Some(stat) => {
+            let stat = stat.or_error(|| format!("failed to retrieve statvfs of {}", mount.path))?;
+            let available = (stat.blocks_available() * stat.block_size()) as f64;
+            Ok(Some((available, latency)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Strips trailing slashes, which mount points in `/proc/self/mountinfo` do not have, except for
+/// the one of the root
+fn normalize(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" if path.starts_with('/') => "/",
+        path => path,
+    }
+}
+
+/// The mount points listed in the contents of a `mountinfo` file
+fn mount_points(mountinfo: &str) -> Vec<String> {
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(unescape)
+        .collect()
+}
+
+/// Undo the octal escapes of spaces, tabs, newlines and backslashes in `mountinfo`
+fn unescape(path: &str) -> String {
+    let mut result = String::new();
+    let mut rest = path;
+    while let Some(pos) = rest.find('\\') {
+        result.push_str(&rest[..pos]);
+        let code = rest
+            .get(pos + 1..pos + 4)
+            .and_then(|code| u8::from_str_radix(code, 8).ok());
+        match code {
+            Some(code) => {
+                result.push(code as char);
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                result.push('\\');
+                rest = &rest[pos + 1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mountinfo() {
+        let mountinfo = "\
+22 1 8:2 / / rw,relatime shared:1 - ext4 /dev/sda2 rw
+61 22 0:52 / /mnt/nas rw,relatime shared:30 - nfs4 nas:/export rw,vers=4.2
+62 22 0:53 / /mnt/my\\040share rw,relatime shared:31 - cifs //srv/share rw";
+        assert_eq!(mount_points(mountinfo), ["/", "/mnt/nas", "/mnt/my share"]);
+        assert_eq!(unescape("a\\134b\\011c\\"), "a\\b\tc\\");
+    }
+
+    #[test]
+    fn normalized_path() {
+        assert_eq!(normalize("/mnt/nas/"), "/mnt/nas");
+        assert_eq!(normalize("/mnt/nas"), "/mnt/nas");
+        assert_eq!(normalize("/"), "/");
+        assert_eq!(normalize("//"), "/");
+    }
+
+    #[test]
+    fn stale() {
+        tokio_test::block_on(async {
+            let mount = Mount {
+                path: "/".into(),
+                name: "root".into(),
+                mount_cmd: String::new(),
+                umount_cmd: String::new(),
+                hanging: Arc::new(AtomicBool::new(false)),
+            };
+            let (available, _) = check(&mount, Duration::from_secs(5))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(available > 0.0);
+            assert!(!mount.hanging.load(Ordering::SeqCst));
+
+            // A call which is still hanging is not repeated
+            mount.hanging.store(true, Ordering::SeqCst);
+            assert!(check(&mount, Duration::from_secs(5))
+                .await
+                .unwrap()
+                .is_none());
+        });
+    }
+}
@@ -2,6 +2,10 @@
 //!
 //! Left-clicking on this block will enable/disable notifications.
 //!
+//! If the notification daemon cannot be reached, e.g. because it is restarting, the last state
+//! is kept and the block turns to warning. The block connects to the daemon again on the next
+//! click or update request.
+//!
 //! # Configuration
 //!
 //! Key | Values | Default
@@ -85,42 +89,104 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
 
     let mut widget = Widget::new().with_format(config.format.with_default(" $icon ")?);
 
-    let mut driver: Box<dyn Driver> = match config.driver {
-        DriverType::Dunst => Box::new(DunstDriver::new().await?),
-        DriverType::SwayNC => Box::new(SwayNCDriver::new().await?),
-    };
+    // `None` until connected, and again after the daemon could not be reached
+    let mut driver: Option<Box<dyn Driver>> = None;
 
     loop {
-        let (is_paused, notification_count) =
-            try_join!(driver.is_paused(), driver.notification_count())?;
-
-        widget.set_values(map!(
-            "icon" => Value::icon(api.get_icon(if is_paused { ICON_OFF } else { ICON_ON })?),
-            [if notification_count != 0] "notification_count" => Value::number(notification_count),
-            [if is_paused] "paused" => Value::flag(),
-        ));
-        widget.state = if notification_count == 0 {
-            State::Idle
-        } else {
-            State::Info
+        let status = match connected(&config.driver, &mut driver).await {
+            Ok(driver) => try_join!(driver.is_paused(), driver.notification_count()),
+            Err(error) => Err(error),
         };
-        api.set_widget(&widget).await?;
+        match status {
+            Ok((is_paused, notification_count)) => {
+                widget.set_values(map!(
+                    "icon" => Value::icon(api.get_icon(if is_paused { ICON_OFF } else { ICON_ON })?),
+                    [if notification_count != 0] "notification_count" => Value::number(notification_count),
+                    [if is_paused] "paused" => Value::flag(),
+                ));
+                widget.state = if notification_count == 0 {
+                    State::Idle
+                } else {
+                    State::Info
+                };
+                api.set_widget(&widget).await?;
+            }
+            Err(error) => {
+                driver = None;
+                disconnected(&mut api, &mut widget, error).await?;
+            }
+        }
 
-        select! {
-            x = driver.wait_for_change() => x?,
-            event = api.event() => match event {
-                Action(a) if a == "toggle_paused" => {
-                    driver.set_paused(!is_paused).await?;
+        // Failures while waiting do not reconnect right away
+        loop {
+            select! {
+                x = wait_for_change(&mut driver) => match x {
+                    Ok(()) => break,
+                    Err(error) => {
+                        driver = None;
+                        disconnected(&mut api, &mut widget, error).await?;
+                    }
+                },
+                // Nothing was shown yet, so retry like `CommonApi::recoverable`
+                _ = sleep(api.error_interval), if widget.values().is_none() => break,
+                event = api.event() => {
+                    let result = match event {
+                        UpdateRequest => break,
+                        Action(a) if a == "toggle_paused" => async {
+                            let driver = connected(&config.driver, &mut driver).await?;
+                            let is_paused = driver.is_paused().await?;
+                            driver.set_paused(!is_paused).await
+                        }.await,
+                        Action(a) if a == "show" => async {
+                            connected(&config.driver, &mut driver).await?.notification_show().await
+                        }.await,
+                        _ => continue,
+                    };
+                    match result {
+                        Ok(()) => break,
+                        Err(error) => {
+                            driver = None;
+                            disconnected(&mut api, &mut widget, error).await?;
+                        }
+                    }
                 }
-                Action(a) if a == "show" => {
-                    driver.notification_show().await?;
-                }
-                _ => (),
             }
         }
     }
 }
 
+/// The driver, connecting to the daemon first if needed
+async fn connected<'a>(
+    driver_type: &DriverType,
+    driver: &'a mut Option<Box<dyn Driver>>,
+) -> Result<&'a mut Box<dyn Driver>> {
+    if driver.is_none() {
+        *driver = Some(match driver_type {
+            DriverType::Dunst => Box::new(DunstDriver::new().await?),
+            DriverType::SwayNC => Box::new(SwayNCDriver::new().await?),
+        });
+    }
+    Ok(driver.as_mut().unwrap())
+}
+
+/// Wait for a change, or forever while not connected
+async fn wait_for_change(driver: &mut Option<Box<dyn Driver>>) -> Result<()> {
+    match driver {
+        Some(driver) => driver.wait_for_change().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Keep showing the last state as a warning, or the error if there is none
+async fn disconnected(api: &mut CommonApi, widget: &mut Widget, error: Error) -> Result<()> {
+    if widget.values().is_none() {
+        return api.set_error(error).await;
+    }
+    log::warn!("Lost connection to the notification daemon: {error}");
+    widget.state = State::Warning;
+    api.set_widget(widget).await
+}
+
 #[async_trait]
 trait Driver {
     async fn is_paused(&self) -> Result<bool>;
@@ -181,12 +247,13 @@ impl Driver for DunstDriver {
     }
 
     async fn wait_for_change(&mut self) -> Result<()> {
-        select! {
-            _ = self.paused_changes.next() => {}
-            _ = self.displayed_length_changes.next() => {}
-            _ = self.waiting_length_changes.next() => {}
-        }
-        Ok(())
+        // The streams end when the connection is lost
+        let change = select! {
+            x = self.paused_changes.next() => x.map(drop),
+            x = self.displayed_length_changes.next() => x.map(drop),
+            x = self.waiting_length_changes.next() => x.map(drop),
+        };
+        change.error("Lost connection to dunst")
     }
 }
 
@@ -255,8 +322,11 @@ impl Driver for SwayNCDriver {
     }
 
     async fn wait_for_change(&mut self) -> Result<()> {
-        self.changes.next().await;
-        Ok(())
+        self.changes
+            .next()
+            .await
+            .map(drop)
+            .error("Lost connection to swaync")
     }
 }
 
@@ -1,26 +1,30 @@
 //! Display the stats of your NVidia GPU
 //!
-//! By default `show_temperature` shows the used memory. Clicking the left mouse on the
-//! "temperature" part of the block will alternate it between showing used or total available
+//! By default the block shows the utilization of the GPU, and the used memory after a left click.
+//! Clicking the left mouse on `$memory` will alternate it between showing used or total available
 //! memory.
 //!
-//! Clicking the left mouse button on the "fan speed" part of the block will cause it to enter into
-//! a fan speed setting mode. In this mode you can scroll the mouse wheel over the block to change
-//! the fan speeds, and left click to exit the mode.
+//! With `fan_control = true`, clicking the left mouse button on the "fan speed" part of the block
+//! will cause it to enter into a fan speed setting mode. In this mode you can scroll the mouse
+//! wheel over the block to change the fan speeds, and left click to exit the mode.
 //!
-//! Requires `nvidia-smi` for displaying info and `nvidia_settings` for setting fan speed.
+//! Requires the NVIDIA driver, whose NVML library (`libnvidia-ml.so.1`) is loaded when the block
+//! starts. The fan speed is set through NVML if it is allowed to (which needs root), and through
+//! `nvidia-settings` otherwise.
 //!
 //! # Configuration
 //!
 //! Key | Values | Default
 //! ----|--------|--------
-//! `gpu_id` | GPU id in system. | `0`
-//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $utilization $memory $temperature "`
+//! `gpu_id` | The index of the GPU in the system, or its UUID, e.g. `"GPU-5a3e..."`, as listed by `nvidia-smi -L` | `0`
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $utilization $temperature "`
+//! `format_alt` | If set, block will switch between `format` and `format_alt` on every click | `" $icon $memory $temperature "` if `format` is not set, `None` otherwise
 //! `interval` | Update interval in seconds. | `1`
 //! `idle` | Maximum temperature, below which state is set to idle | `50`
 //! `good` | Maximum temperature, below which state is set to good | `70`
 //! `info` | Maximum temperature, below which state is set to info | `75`
 //! `warning` | Maximum temperature, below which state is set to warning | `80`
+//! `fan_control` | Whether the fan speed can be set by clicking and scrolling on `$fan_speed` | `false`
 //!
 //! Placeholder   | Type   | Unit
 //! --------------|--------|---------------
@@ -28,18 +32,27 @@
 //! `name`        | Text   | -
 //! `utilization` | Number | Percents
 //! `memory`      | Number | Bytes
+//! `memory_used` | Number | Bytes
+//! `memory_total`| Number | Bytes
 //! `temperature` | Number | Degrees
 //! `fan_speed`   | Number | Percents
 //! `clocks`      | Number | Hertz
 //! `power`       | Number | Watts
+//! `power_draw`  | Number | Watts
+//!
+//! `memory` is the used or total memory, depending on `toggle_mem_total`. `power` is the same as
+//! `power_draw`.
 //!
 //! Action                  | Default button
 //! ------------------------|----------------
+//! `toggle_format`         | Left
 //! `toggle_mem_total`      | Left on `$memory`
 //! `toggle_fan_controlled` | Left on `$fan_speed`
 //! `fan_speed_up`          | Wheel Up on `$fan_speed`
 //! `fan_speed_down`        | Wheel Down on `$fan_speed`
 //!
+//! The fan actions do nothing unless `fan_control` is set.
+//!
 //! # Example
 //!
 //! ```toml
@@ -49,22 +62,29 @@
 //! format = " $icon GT 1030 $utilization $temperature $clocks "
 //! ```
 //!
+//! ```toml
+//! [[block]]
+//! block = "nvidia_gpu"
+//! gpu_id = "GPU-5a3e7c2b-1f0d-4c8e-9b6a-2d4f8e1c7a90"
+//! format = " $icon $memory_used.eng(prefix:Mi)/$memory_total.eng(prefix:Mi) $power_draw "
+//! ```
+//!
 //! # Icons Used
 //! - `gpu`
 //!
 //! # TODO
 //! - Provide a `mappings` option similar to `keyboard_layout`'s  to map GPU names to labels?
 
-use std::process::Stdio;
-use std::str::FromStr;
+mod nvml;
+
+use std::fmt;
 
-use tokio::io::{BufReader, Lines};
-use tokio::process::Command;
+pub(super) use nvml::Gpu;
 
 const MEM_BTN: &str = "mem_btn";
 const FAN_BTN: &str = "fan_btn";
-const QUERY: &str = "--query-gpu=name,memory.total,utilization.gpu,memory.used,temperature.gpu,fan.speed,clocks.current.graphics,power.draw,";
-const FORMAT: &str = "--format=csv,noheader,nounits";
+/// Exists if the NVIDIA driver is loaded
+pub(super) const NVIDIA_DRIVER: &str = "/proc/driver/nvidia/version";
 
 use super::prelude::*;
 
@@ -72,10 +92,10 @@ use super::prelude::*;
 #[serde(default)]
 pub struct Config {
     format: FormatConfig,
+    format_alt: Option<FormatConfig>,
     #[default(1.into())]
     interval: Seconds,
-    #[default(0)]
-    gpu_id: u64,
+    gpu_id: GpuId,
     #[default(50)]
     idle: u32,
     #[default(70)]
@@ -84,41 +104,65 @@ pub struct Config {
     info: u32,
     #[default(80)]
     warning: u32,
+    fan_control: bool,
+}
+
+/// A GPU, as accepted by NVML and `nvidia-smi -i`
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub(super) enum GpuId {
+    Index(u64),
+    Uuid(String),
+}
+
+impl Default for GpuId {
+    fn default() -> Self {
+        Self::Index(0)
+    }
+}
+
+impl fmt::Display for GpuId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "{index}"),
+            Self::Uuid(uuid) => f.write_str(uuid),
+        }
+    }
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    let gpu = Gpu::shared(&config.gpu_id)?;
+    // The index for `nvidia-settings`, if NVML may not set the fan speed
+    let fan_id = match config.fan_control {
+        true => Some(gpu.index()?),
+        false => None,
+    };
+
     api.set_default_actions(&[
-        (MouseButton::Left, Some(MEM_BTN), "toggle_mem_totoal"),
+        (MouseButton::Left, None, "toggle_format"),
+        (MouseButton::Left, Some(MEM_BTN), "toggle_mem_total"),
         (MouseButton::Left, Some(FAN_BTN), "toggle_fan_controlled"),
         (MouseButton::WheelUp, Some(FAN_BTN), "fan_speed_up"),
         (MouseButton::WheelDown, Some(FAN_BTN), "fan_speed_down"),
     ])
     .await?;
+    api.coalesce_actions();
 
-    let mut widget = Widget::new().with_format(
-        config
-            .format
-            .with_default(" $icon $utilization $memory $temperature ")?,
-    );
+    let mut format_alt = match (config.format_alt, config.format.full.is_none()) {
+        (Some(format_alt), _) => Some(format_alt.with_default("")?),
+        (None, true) => Some(FormatConfig::default().with_default(" $icon $memory $temperature ")?),
+        (None, false) => None,
+    };
+    let mut format = config
+        .format
+        .with_default(" $icon $utilization $temperature ")?;
+    let mut widget = Widget::new().with_format(format.clone());
 
-    // Run `nvidia-smi` command
-    let mut child = Command::new("nvidia-smi")
-        .args([
-            "-l",
-            &config.interval.seconds().to_string(),
-            "-i",
-            &config.gpu_id.to_string(),
-            QUERY,
-            FORMAT,
-        ])
-        .stdout(Stdio::piped())
-        .kill_on_drop(true)
-        .spawn()
-        .error("Failed to execute nvidia-smi")?;
-    let mut reader = BufReader::new(child.stdout.take().unwrap()).lines();
+    let commands = api.command_context.clone();
 
-    // Read the initial info
-    let mut info = GpuInfo::from_reader(&mut reader).await?;
+    let mut timer = config.interval.timer();
+
+    let mut info = gpu.info()?;
     let mut show_mem_total = false;
     let mut fan_controlled = false;
 
@@ -136,10 +180,13 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             "name" => Value::text(info.name.clone()),
             "utilization" => Value::percents(info.utilization),
             "memory" => Value::bytes(if show_mem_total {info.mem_total} else {info.mem_used}).with_instance(MEM_BTN),
+            "memory_used" => Value::bytes(info.mem_used),
+            "memory_total" => Value::bytes(info.mem_total),
             "temperature" => Value::degrees(info.temperature),
             "fan_speed" => Value::percents(info.fan_speed).with_instance(FAN_BTN).underline(fan_controlled).italic(fan_controlled),
             "clocks" => Value::hertz(info.clocks),
             "power" => Value::watts(info.power_draw),
+            "power_draw" => Value::watts(info.power_draw),
         });
 
         api.set_widget(&widget).await?;
@@ -148,35 +195,38 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             select! {
                 event = api.event() => match event {
                     UpdateRequest => break,
+                    Action(a) if a == "toggle_format" => {
+                        if let Some(ref mut format_alt) = format_alt {
+                            std::mem::swap(format_alt, &mut format);
+                            widget.set_format(format.clone());
+                            break;
+                        }
+                    }
                     Action(a) if a == "toggle_mem_total" => {
                         show_mem_total = !show_mem_total;
                         break;
                     }
                     Action(a) if a == "toggle_fan_controlled" => {
-                        fan_controlled = !fan_controlled;
-                        set_fan_speed(config.gpu_id, fan_controlled.then_some(info.fan_speed)).await?;
-                        break;
+                        if let Some(id) = fan_id {
+                            fan_controlled = !fan_controlled;
+                            set_fan_speed(&gpu, &commands, id, fan_controlled.then_some(info.fan_speed)).await?;
+                            break;
+                        }
                     }
-                    Action(a) if a == "fan_speed_up" && fan_controlled && info.fan_speed < 100 => {
-                        info.fan_speed += 1;
-                        set_fan_speed(config.gpu_id, Some(info.fan_speed)).await?;
-                        break;
-                    }
-                    Action(a) if a == "fan_speed_down" && fan_controlled && info.fan_speed > 0 => {
-                        info.fan_speed -= 1;
-                        set_fan_speed(config.gpu_id, Some(info.fan_speed)).await?;
-                        break;
+                    Action(a) if (a == "fan_speed_up" || a == "fan_speed_down") && fan_controlled => {
+                        let speed = step_fan_speed(info.fan_speed, a == "fan_speed_up", api.action_count);
+                        if speed != info.fan_speed {
+                            info.fan_speed = speed;
+                            set_fan_speed(&gpu, &commands, fan_id.unwrap(), Some(speed)).await?;
+                            break;
+                        }
                     }
                     _ => (),
                 },
-                new_info = GpuInfo::from_reader(&mut reader) => {
-                    info = new_info?;
+                _ = timer.tick() => {
+                    info = gpu.info()?;
                     break;
                 }
-                code = child.wait() => {
-                    let code = code.error("failed to check nvidia-smi exit code")?;
-                    return Err(Error::new(format!("nvidia-smi exited with code {code}")));
-                }
             }
         }
     }
@@ -194,55 +244,27 @@ struct GpuInfo {
     power_draw: f64,  // watts
 }
 
-impl GpuInfo {
-    /// Read a line from provided reader and parse it
-    ///
-    /// # Cancel safety
-    ///
-    /// This method should be cancellation safe, because it has only one `.await` and it is on `next_line`, which is cancellation safe.
-    async fn from_reader<B: AsyncBufRead + Unpin>(reader: &mut Lines<B>) -> Result<Self> {
-        const ERR_MSG: &str = "failed to read from nvidia-smi";
-        reader
-            .next_line()
-            .await
-            .error(ERR_MSG)?
-            .error(ERR_MSG)?
-            .parse::<GpuInfo>()
-            .error("failed to parse nvidia-smi output")
+/// The fan speed after `steps` scroll steps up or down, in percents
+fn step_fan_speed(speed: u32, up: bool, steps: u32) -> u32 {
+    if up {
+        speed.saturating_add(steps).min(100)
+    } else {
+        speed.saturating_sub(steps)
     }
 }
 
-impl FromStr for GpuInfo {
-    type Err = Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        macro_rules! parse {
-            ($s:ident -> $($part:ident : $t:ident $(* $mul:expr)?),*) => {{
-                let mut parts = $s.trim().split(", ");
-                let info = GpuInfo {
-                    $(
-                    $part: {
-                        let $part = parts
-                            .next()
-                            .error(concat!("missing property: ", stringify!($part)))?
-                            .parse::<$t>()
-                            .error(concat!("bad property: ", stringify!($part)))?;
-                        $(let $part = $part * $mul;)?
-                        $part
-                    },
-                    )*
-                };
-                Ok(info)
-            }}
-        }
-        // `memory` and `clocks` are initially in MB and MHz, so we have to multiply them by 1_000_000
-        parse!(s -> name: String, mem_total: f64 * 1e6, utilization: f64, mem_used: f64 * 1e6, temperature: u32, fan_speed: u32, clocks: f64 * 1e6, power_draw: f64)
+/// Set the fan speed through NVML, or `nvidia-settings` if NVML may not
+async fn set_fan_speed(
+    gpu: &Gpu,
+    commands: &CommandContext,
+    id: u32,
+    speed: Option<u32>,
+) -> Result<()> {
+    if gpu.set_fan_speed(speed)? {
+        return Ok(());
     }
-}
-
-async fn set_fan_speed(id: u64, speed: Option<u32>) -> Result<()> {
     const ERR_MSG: &str = "Failed to execute nvidia-settings";
-    let mut cmd = Command::new("nvidia-settings");
+    let mut cmd = commands.command("nvidia-settings");
     if let Some(speed) = speed {
         cmd.args([
             "-a",
@@ -266,3 +288,31 @@ async fn set_fan_speed(id: u64, speed: Option<u32>) -> Result<()> {
         Err(Error::new(ERR_MSG))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpu_id() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.gpu_id, GpuId::Index(0));
+        let config: Config = toml::from_str("gpu_id = 1").unwrap();
+        assert_eq!(config.gpu_id.to_string(), "1");
+        let config: Config = toml::from_str(r#"gpu_id = "GPU-5a3e7c2b""#).unwrap();
+        assert_eq!(config.gpu_id, GpuId::Uuid("GPU-5a3e7c2b".into()));
+        assert_eq!(config.gpu_id.to_string(), "GPU-5a3e7c2b");
+    }
+
+    #[test]
+    fn fan_speed_steps() {
+        assert_eq!(step_fan_speed(40, true, 1), 41);
+        assert_eq!(step_fan_speed(40, true, 5), 45);
+        assert_eq!(step_fan_speed(40, false, 5), 35);
+        assert_eq!(step_fan_speed(98, true, 5), 100);
+        assert_eq!(step_fan_speed(100, true, 1), 100);
+        assert_eq!(step_fan_speed(3, false, 5), 0);
+        assert_eq!(step_fan_speed(0, false, 1), 0);
+        assert_eq!(step_fan_speed(50, true, u32::MAX), 100);
+    }
+}
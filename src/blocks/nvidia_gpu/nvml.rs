@@ -0,0 +1,360 @@
+//! NVML, the NVIDIA Management Library. It is loaded at runtime from the driver's
+//! `libnvidia-ml.so.1`, so that the bar does not require the driver unless a block shows an
+//! NVIDIA GPU.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_uint, c_void, CStr, CString};
+use std::sync::{Arc, Mutex, Weak};
+
+use once_cell::sync::Lazy;
+
+use super::{GpuId, GpuInfo};
+use crate::errors::*;
+use crate::util::default;
+
+const LIBRARY: &CStr = c"libnvidia-ml.so.1";
+
+const SUCCESS: c_int = 0;
+const ERROR_NOT_SUPPORTED: c_int = 3;
+const ERROR_NO_PERMISSION: c_int = 4;
+const TEMPERATURE_GPU: c_uint = 0;
+const CLOCK_GRAPHICS: c_uint = 0;
+/// `NVML_DEVICE_NAME_V2_BUFFER_SIZE`
+const NAME_LEN: usize = 96;
+
+type Device = *mut c_void;
+
+#[repr(C)]
+#[derive(Default)]
+struct Utilization {
+    gpu: c_uint,
+    memory: c_uint,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Memory {
+    total: u64,
+    free: u64,
+    used: u64,
+}
+
+struct Functions {
+    shutdown: unsafe extern "C" fn() -> c_int,
+    error_string: unsafe extern "C" fn(c_int) -> *const c_char,
+    index: unsafe extern "C" fn(Device, *mut c_uint) -> c_int,
+    name: unsafe extern "C" fn(Device, *mut c_char, c_uint) -> c_int,
+    utilization: unsafe extern "C" fn(Device, *mut Utilization) -> c_int,
+    memory: unsafe extern "C" fn(Device, *mut Memory) -> c_int,
+    temperature: unsafe extern "C" fn(Device, c_uint, *mut c_uint) -> c_int,
+    fan_speed: unsafe extern "C" fn(Device, *mut c_uint) -> c_int,
+    clock: unsafe extern "C" fn(Device, c_uint, *mut c_uint) -> c_int,
+    power_usage: unsafe extern "C" fn(Device, *mut c_uint) -> c_int,
+    /// Only in drivers since R520
+    num_fans: Option<unsafe extern "C" fn(Device, *mut c_uint) -> c_int>,
+    set_fan_speed: Option<unsafe extern "C" fn(Device, c_uint, c_uint) -> c_int>,
+    set_default_fan_speed: Option<unsafe extern "C" fn(Device, c_uint) -> c_int>,
+}
+
+/// The readings of a GPU, in the units of NVML
+#[derive(Default)]
+struct Readings {
+    name: String,
+    utilization: Utilization,
+    memory: Memory,
+    /// Degrees
+    temperature: c_uint,
+    /// Percents
+    fan_speed: c_uint,
+    /// MHz
+    clocks: c_uint,
+    /// mW
+    power_usage: c_uint,
+}
+
+impl From<Readings> for GpuInfo {
+    fn from(readings: Readings) -> Self {
+        Self {
+            name: readings.name,
+            mem_total: readings.memory.total as f64,
+            mem_used: readings.memory.used as f64,
+            utilization: readings.utilization.gpu as f64,
+            temperature: readings.temperature,
+            fan_speed: readings.fan_speed,
+            clocks: readings.clocks as f64 * 1e6,
+            power_draw: readings.power_usage as f64 / 1e3,
+        }
+    }
+}
+
+/// The open GPUs, by `gpu_id`
+static GPUS: Lazy<Mutex<HashMap<String, Weak<Gpu>>>> = Lazy::new(default);
+
+/// A GPU, opened through NVML
+pub(crate) struct Gpu {
+    lib: *mut c_void,
+    f: Functions,
+    device: Device,
+}
+
+// Safety: NVML is thread-safe, and the library and the device handle stay valid until the GPU is
+// dropped
+unsafe impl Send for Gpu {}
+unsafe impl Sync for Gpu {}
+
+impl Gpu {
+    /// Open the GPU `gpu_id`, which is shared by all blocks that show it. NVML is loaded the
+    /// first time and unloaded once the last block which uses it is dropped.
+    pub fn shared(gpu_id: &GpuId) -> Result<Arc<Self>> {
+        let mut gpus = GPUS.lock().unwrap();
+        let key = gpu_id.to_string();
+        if let Some(gpu) = gpus.get(&key).and_then(Weak::upgrade) {
+            return Ok(gpu);
+        }
+        let gpu = Arc::new(Self::open(gpu_id)?);
+        gpus.insert(key, Arc::downgrade(&gpu));
+        Ok(gpu)
+    }
+
+    /// Load NVML and open the GPU `gpu_id`
+    fn open(gpu_id: &GpuId) -> Result<Self> {
+        // Safety: dlopen() is given a valid C string
+        let lib = unsafe { libc::dlopen(LIBRARY.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if lib.is_null() {
+            return Err(Error::new(
+                "Failed to load NVML (libnvidia-ml.so.1), is the NVIDIA driver installed?",
+            ));
+        }
+        let gpu = Self::open_in(lib, gpu_id);
+        if gpu.is_err() {
+            // Safety: nothing from the library is used anymore
+            unsafe { libc::dlclose(lib) };
+        }
+        gpu
+    }
+
+    fn open_in(lib: *mut c_void, gpu_id: &GpuId) -> Result<Self> {
+        macro_rules! symbol {
+            ($name:literal) => {{
+                // Safety: the symbol has the signature of the field it is assigned to, as
+                // declared in nvml.h
+                unsafe {
+                    let ptr = libc::dlsym(lib, concat!($name, "\0").as_ptr().cast());
+                    (!ptr.is_null()).then(|| std::mem::transmute::<*mut c_void, _>(ptr))
+                }
+            }};
+            ($name:literal!) => {
+                symbol!($name).or_error(|| format!("NVML does not provide {}", $name))?
+            };
+        }
+
+        let init: unsafe extern "C" fn() -> c_int = symbol!("nvmlInit_v2"!);
+        let by_index: unsafe extern "C" fn(c_uint, *mut Device) -> c_int =
+            symbol!("nvmlDeviceGetHandleByIndex_v2"!);
+        let by_uuid: unsafe extern "C" fn(*const c_char, *mut Device) -> c_int =
+            symbol!("nvmlDeviceGetHandleByUUID"!);
+        let f = Functions {
+            shutdown: symbol!("nvmlShutdown"!),
+            error_string: symbol!("nvmlErrorString"!),
+            index: symbol!("nvmlDeviceGetIndex"!),
+            name: symbol!("nvmlDeviceGetName"!),
+            utilization: symbol!("nvmlDeviceGetUtilizationRates"!),
+            memory: symbol!("nvmlDeviceGetMemoryInfo"!),
+            temperature: symbol!("nvmlDeviceGetTemperature"!),
+            fan_speed: symbol!("nvmlDeviceGetFanSpeed"!),
+            clock: symbol!("nvmlDeviceGetClockInfo"!),
+            power_usage: symbol!("nvmlDeviceGetPowerUsage"!),
+            num_fans: symbol!("nvmlDeviceGetNumFans"),
+            set_fan_speed: symbol!("nvmlDeviceSetFanSpeed_v2"),
+            set_default_fan_speed: symbol!("nvmlDeviceSetDefaultFanSpeed_v2"),
+        };
+
+        // Safety: the functions are called as declared in nvml.h, with valid pointers
+        let ret = unsafe { init() };
+        if ret != SUCCESS {
+            return Err(Error::new(format!(
+                "Failed to initialize NVML: {}",
+                error_string(&f, ret)
+            )));
+        }
+        let mut device = std::ptr::null_mut();
+        let ret = match gpu_id {
+            GpuId::Index(index) => {
+                let index = c_uint::try_from(*index).error("gpu_id is out of range")?;
+                unsafe { by_index(index, &mut device) }
+            }
+            GpuId::Uuid(uuid) => {
+                let uuid = CString::new(uuid.as_str()).error("gpu_id contains a NUL byte")?;
+                unsafe { by_uuid(uuid.as_ptr(), &mut device) }
+            }
+        };
+        if ret != SUCCESS {
+            let err = error_string(&f, ret);
+            unsafe { (f.shutdown)() };
+            return Err(Error::new(format!("NVML cannot open GPU {gpu_id}: {err}")));
+        }
+        Ok(Self { lib, f, device })
+    }
+
+    fn check(&self, ret: c_int, what: &str) -> Result<()> {
+        if ret == SUCCESS {
+            Ok(())
+        } else {
+            Err(Error::new(format!(
+                "NVML failed to get {what}: {}",
+                error_string(&self.f, ret)
+            )))
+        }
+    }
+
+    /// Like [`Self::check`], but a value the GPU does not support (e.g. the fan speed of a GPU
+    /// without fans) reads as zero
+    fn check_supported(&self, ret: c_int, what: &str) -> Result<bool> {
+        match ret {
+            ERROR_NOT_SUPPORTED => Ok(false),
+            ret => self.check(ret, what).map(|()| true),
+        }
+    }
+
+    /// The index of the GPU, as used by `nvidia-settings`
+    pub fn index(&self) -> Result<u32> {
+        let mut index = 0;
+        self.check(
+            unsafe { (self.f.index)(self.device, &mut index) },
+            "the index",
+        )?;
+        Ok(index)
+    }
+
+    /// The temperature in degrees
+    pub fn temperature(&self) -> Result<u32> {
+        let mut temperature = 0;
+        // Safety: the function is called as declared in nvml.h, with a valid pointer
+        self.check(
+            unsafe { (self.f.temperature)(self.device, TEMPERATURE_GPU, &mut temperature) },
+            "the temperature",
+        )?;
+        Ok(temperature)
+    }
+
+    pub(super) fn info(&self) -> Result<GpuInfo> {
+        let mut readings = Readings {
+            temperature: self.temperature()?,
+            ..default()
+        };
+        // Safety: the functions are called as declared in nvml.h, with valid pointers
+        unsafe {
+            let mut name = [0 as c_char; NAME_LEN];
+            self.check(
+                (self.f.name)(self.device, name.as_mut_ptr(), NAME_LEN as c_uint),
+                "the name",
+            )?;
+            readings.name = CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+            self.check(
+                (self.f.utilization)(self.device, &mut readings.utilization),
+                "the utilization",
+            )?;
+            self.check(
+                (self.f.memory)(self.device, &mut readings.memory),
+                "the memory",
+            )?;
+            self.check_supported(
+                (self.f.fan_speed)(self.device, &mut readings.fan_speed),
+                "the fan speed",
+            )?;
+            self.check_supported(
+                (self.f.clock)(self.device, CLOCK_GRAPHICS, &mut readings.clocks),
+                "the clocks",
+            )?;
+            self.check_supported(
+                (self.f.power_usage)(self.device, &mut readings.power_usage),
+                "the power usage",
+            )?;
+        }
+        Ok(readings.into())
+    }
+
+    /// Set the speed of all fans, or give control back to the driver if `speed` is `None`.
+    /// Returns `false` if NVML cannot do that, which needs root and a recent driver.
+    pub fn set_fan_speed(&self, speed: Option<u32>) -> Result<bool> {
+        let (num_fans, set_fan_speed, set_default_fan_speed) = match (
+            self.f.num_fans,
+            self.f.set_fan_speed,
+            self.f.set_default_fan_speed,
+        ) {
+            (Some(num_fans), Some(set), Some(set_default)) => (num_fans, set, set_default),
+            _ => return Ok(false),
+        };
+        // Safety: the functions are called as declared in nvml.h, with valid pointers
+        unsafe {
+            let mut fans = 0;
+            if !self.check_supported(num_fans(self.device, &mut fans), "the fans")? {
+                return Ok(false);
+            }
+            for fan in 0..fans {
+                let ret = match speed {
+                    Some(speed) => set_fan_speed(self.device, fan, speed),
+                    None => set_default_fan_speed(self.device, fan),
+                };
+                match ret {
+                    ERROR_NO_PERMISSION | ERROR_NOT_SUPPORTED => return Ok(false),
+                    SUCCESS => (),
+                    ret => {
+                        return Err(Error::new(format!(
+                            "NVML failed to set the fan speed: {}",
+                            error_string(&self.f, ret)
+                        )))
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Drop for Gpu {
+    fn drop(&mut self) {
+        // Safety: `device` is not used after NVML is shut down
+        unsafe {
+            (self.f.shutdown)();
+            libc::dlclose(self.lib);
+        }
+    }
+}
+
+fn error_string(f: &Functions, ret: c_int) -> String {
+    // Safety: nvmlErrorString() returns a static string
+    unsafe { CStr::from_ptr((f.error_string)(ret)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readings() {
+        let info = GpuInfo::from(Readings {
+            name: "NVIDIA GeForce GT 1030".into(),
+            utilization: Utilization { gpu: 7, memory: 3 },
+            memory: Memory {
+                total: 2048 << 20,
+                free: 1736 << 20,
+                used: 312 << 20,
+            },
+            temperature: 41,
+            fan_speed: 35,
+            clocks: 1227,
+            power_usage: 9610,
+        });
+        assert_eq!(info.name, "NVIDIA GeForce GT 1030");
+        assert_eq!(info.utilization, 7.0);
+        assert_eq!(info.mem_total, (2048u64 << 20) as f64);
+        assert_eq!(info.mem_used, (312u64 << 20) as f64);
+        assert_eq!(info.temperature, 41);
+        assert_eq!(info.fan_speed, 35);
+        assert_eq!(info.clocks, 1227e6);
+        assert_eq!(info.power_draw, 9.61);
+    }
+}
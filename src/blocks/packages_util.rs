@@ -0,0 +1,74 @@
+//! Helpers shared by the blocks which show pending package updates
+
+use regex::Regex;
+
+use crate::errors::*;
+use crate::widget::State;
+
+/// The `warning_updates_regex` and `critical_updates_regex` options
+#[derive(Debug, Default)]
+pub struct UpdateRegexes {
+    warning: Option<Regex>,
+    critical: Option<Regex>,
+}
+
+impl UpdateRegexes {
+    pub fn new(warning: Option<&str>, critical: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            warning: warning
+                .map(Regex::new)
+                .transpose()
+                .error("invalid warning updates regex")?,
+            critical: critical
+                .map(Regex::new)
+                .transpose()
+                .error("invalid critical updates regex")?,
+        })
+    }
+
+    /// The state of a block which shows `count` updates, described by the lines `updates`
+    pub fn state<'a, I>(&self, count: usize, updates: I) -> State
+    where
+        I: IntoIterator<Item = &'a str> + Clone,
+    {
+        let matches = |regex: &Option<Regex>| {
+            regex
+                .as_ref()
+                .is_some_and(|regex| has_matching_update(updates.clone(), regex))
+        };
+        if count == 0 {
+            State::Idle
+        } else if matches(&self.critical) {
+            State::Critical
+        } else if matches(&self.warning) {
+            State::Warning
+        } else {
+            State::Info
+        }
+    }
+}
+
+pub fn has_matching_update<'a>(updates: impl IntoIterator<Item = &'a str>, regex: &Regex) -> bool {
+    updates.into_iter().any(|line| regex.is_match(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn states() {
+        let regexes = UpdateRegexes::new(Some("^firefox"), Some("^linux")).unwrap();
+        let updates = "firefox 120.0-1\nlinux 6.6.1-1\nvim 9.0-1";
+        assert_eq!(regexes.state(3, updates.lines()), State::Critical);
+        assert_eq!(regexes.state(2, updates.lines().take(1)), State::Warning);
+        assert_eq!(regexes.state(1, updates.lines().skip(2)), State::Info);
+        // The regexes are not matched without updates
+        assert_eq!(regexes.state(0, updates.lines()), State::Idle);
+        assert_eq!(
+            UpdateRegexes::default().state(3, updates.lines()),
+            State::Info
+        );
+        assert!(UpdateRegexes::new(Some("("), None).is_err());
+    }
+}
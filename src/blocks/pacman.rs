@@ -115,13 +115,10 @@ use std::env;
 use std::path::PathBuf;
 use std::process::Stdio;
 
-use regex::Regex;
-
 use tokio::fs::{create_dir_all, symlink};
-use tokio::process::Command;
 
+use super::packages_util::UpdateRegexes;
 use super::prelude::*;
-use crate::util::has_command;
 
 make_log_macro!(debug, "pacman");
 
@@ -198,59 +195,42 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         Watched::None
     };
 
+    let commands = api.command_context.clone();
     if matches!(watched, Watched::Pacman | Watched::Both(_)) {
-        check_fakeroot_command_exists().await?;
+        check_fakeroot_command_exists(&commands).await?;
     }
 
-    let warning_updates_regex = config
-        .warning_updates_regex
-        .as_deref()
-        .map(Regex::new)
-        .transpose()
-        .error("invalid warning updates regex")?;
-    let critical_updates_regex = config
-        .critical_updates_regex
-        .as_deref()
-        .map(Regex::new)
-        .transpose()
-        .error("invalid critical updates regex")?;
+    let regexes = UpdateRegexes::new(
+        config.warning_updates_regex.as_deref(),
+        config.critical_updates_regex.as_deref(),
+    )?;
 
     loop {
-        let (mut values, warning, critical, total) = match &watched {
+        let (mut values, updates, total) = match &watched {
             Watched::Pacman => {
-                let updates = api.recoverable(get_pacman_available_updates).await?;
+                let updates = api
+                    .recoverable(|| get_pacman_available_updates(&commands))
+                    .await?;
                 let count = get_update_count(&updates);
                 let values = map!("pacman" => Value::number(count));
-                let warning = warning_updates_regex
-                    .as_ref()
-                    .map_or(false, |regex| has_matching_update(&updates, regex));
-                let critical = critical_updates_regex
-                    .as_ref()
-                    .map_or(false, |regex| has_matching_update(&updates, regex));
-                (values, warning, critical, count)
+                (values, updates, count)
             }
             Watched::Aur(aur_command) => {
                 let updates = api
-                    .recoverable(|| get_aur_available_updates(aur_command))
+                    .recoverable(|| get_aur_available_updates(&commands, aur_command))
                     .await?;
                 let count = get_update_count(&updates);
                 let values = map!(
                     "aur" => Value::number(count)
                 );
-                let warning = warning_updates_regex
-                    .as_ref()
-                    .map_or(false, |regex| has_matching_update(&updates, regex));
-                let critical = critical_updates_regex
-                    .as_ref()
-                    .map_or(false, |regex| has_matching_update(&updates, regex));
-                (values, warning, critical, count)
+                (values, updates, count)
             }
             Watched::Both(aur_command) => {
                 let (pacman_updates, aur_updates) = api
                     .recoverable(|| async {
                         tokio::try_join!(
-                            get_pacman_available_updates(),
-                            get_aur_available_updates(aur_command)
+                            get_pacman_available_updates(&commands),
+                            get_aur_available_updates(&commands, aur_command)
                         )
                     })
                     .await?;
@@ -261,17 +241,10 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                     "aur" =>    Value::number(aur_count),
                     "both" =>   Value::number(pacman_count + aur_count),
                 };
-                let warning = warning_updates_regex.as_ref().map_or(false, |regex| {
-                    has_matching_update(&aur_updates, regex)
-                        || has_matching_update(&pacman_updates, regex)
-                });
-                let critical = critical_updates_regex.as_ref().map_or(false, |regex| {
-                    has_matching_update(&aur_updates, regex)
-                        || has_matching_update(&pacman_updates, regex)
-                });
-                (values, warning, critical, pacman_count + aur_count)
+                let updates = format!("{pacman_updates}\n{aur_updates}");
+                (values, updates, pacman_count + aur_count)
             }
-            Watched::None => (HashMap::new(), false, false, 0),
+            Watched::None => (Values::new(), String::new(), 0),
         };
         values.insert("icon".into(), Value::icon(api.get_icon("update")?));
 
@@ -281,18 +254,7 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             _ => format.clone(),
         });
         widget.set_values(values);
-        widget.state = match total {
-            0 => State::Idle,
-            _ => {
-                if critical {
-                    State::Critical
-                } else if warning {
-                    State::Warning
-                } else {
-                    State::Info
-                }
-            }
-        };
+        widget.state = regexes.state(total, updates.lines());
         api.set_widget(&widget).await?;
 
         select! {
@@ -310,15 +272,15 @@ enum Watched {
     Both(String),
 }
 
-async fn check_fakeroot_command_exists() -> Result<()> {
-    if !has_command("fakeroot").await? {
+async fn check_fakeroot_command_exists(commands: &CommandContext) -> Result<()> {
+    if !commands.has_command("fakeroot").await? {
         Err(Error::new("fakeroot not found"))
     } else {
         Ok(())
     }
 }
 
-async fn get_pacman_available_updates() -> Result<String> {
+async fn get_pacman_available_updates(commands: &CommandContext) -> Result<String> {
     // Create the determined `checkup-db` path recursively
     create_dir_all(&*PACMAN_UPDATES_DB).await.or_error(|| {
         format!(
@@ -336,7 +298,8 @@ async fn get_pacman_available_updates() -> Result<String> {
     }
 
     // Update database
-    let status = Command::new("fakeroot")
+    let status = commands
+        .command("fakeroot")
         .env("LC_ALL", "C")
         .args([
             "--".as_ref(),
@@ -356,7 +319,8 @@ async fn get_pacman_available_updates() -> Result<String> {
         return Err(Error::new("pacman -Sy exited with non zero exit status"));
     }
 
-    let stdout = Command::new("fakeroot")
+    let stdout = commands
+        .command("fakeroot")
         .env("LC_ALL", "C")
         .args([
             "--".as_ref(),
@@ -373,9 +337,9 @@ async fn get_pacman_available_updates() -> Result<String> {
     String::from_utf8(stdout).error("Pacman produced non-UTF8 output")
 }
 
-async fn get_aur_available_updates(aur_command: &str) -> Result<String> {
-    let stdout = Command::new("sh")
-        .args(["-c", aur_command])
+async fn get_aur_available_updates(commands: &CommandContext, aur_command: &str) -> Result<String> {
+    let stdout = commands
+        .shell(aur_command)
         .output()
         .await
         .or_error(|| format!("aur command: {aur_command} failed"))?
@@ -390,7 +354,3 @@ fn get_update_count(updates: &str) -> usize {
         .filter(|line| !line.contains("[ignored]"))
         .count()
 }
-
-fn has_matching_update(updates: &str, regex: &Regex) -> bool {
-    updates.lines().any(|line| regex.is_match(line))
-}
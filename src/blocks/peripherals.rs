@@ -0,0 +1,480 @@
+//! Batteries of wireless mice, keyboards, game controllers and headsets
+//!
+//! Shows the charge of the peripherals which UPower knows about, e.g. over Bluetooth or a
+//! Logitech receiver. `format` is shown once for each device, ordered by the kinds in `show` and
+//! then by name. Devices are picked up when they connect and removed when they disconnect, and
+//! their charge is shown as soon as UPower reports a change. A device is not shown while UPower
+//! does not know its charge. The block is hidden if no device is shown.
+//!
+//! The state of a device is warning if its charge is at most `warning` percent and critical if it
+//! is at most `critical` percent. Both can be set for each kind in `thresholds`.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `format` | A string to customise the output of each device. See below for available placeholders. | `" $icon $percentage "`
+//! `show` | The kinds of devices to show, out of `"mouse"`, `"keyboard"`, `"gaming_input"` and `"headset"` | All of them
+//! `exclude` | A list of regular expressions. Devices whose name or native path matches one of them are not shown. | `[]`
+//! `warning` | The charge in percent at which the state is warning | `20`
+//! `critical` | The charge in percent at which the state is critical | `10`
+//! `thresholds` | `warning` and `critical` of single kinds, e.g. `{ mouse = { warning = 30, critical = 15 } }` | `{}`
+//!
+//! Placeholder  | Value                                           | Type   | Unit
+//! -------------|-------------------------------------------------|--------|-----
+//! `icon`       | An icon of the kind of the device               | Icon   | -
+//! `name`       | The model of the device, e.g. `MX Master 3`     | Text   | -
+//! `kind`       | The kind of the device, e.g. `mouse`            | Text   | -
+//! `percentage` | The charge of the device                        | Number | %
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "peripherals"
+//! show = ["mouse", "keyboard"]
+//! exclude = ["^Receiver"]
+//! thresholds = { mouse = { warning = 30, critical = 15 } }
+//! ```
+//!
+//! # Icons Used
+//! - `mouse`
+//! - `keyboard`
+//! - `joystick`
+//! - `headphones`
+
+use super::battery::upower::{DeviceProxy, UPowerProxy};
+use super::prelude::*;
+use crate::icons::Icon;
+use crate::widget::WidgetPart;
+use regex::Regex;
+use zbus::fdo::{PropertiesChangedStream, PropertiesProxy};
+use zbus::zvariant::OwnedObjectPath;
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    format: FormatConfig,
+    #[default(vec![Kind::Mouse, Kind::Keyboard, Kind::GamingInput, Kind::Headset])]
+    show: Vec<Kind>,
+    exclude: Vec<String>,
+    #[default(20.0)]
+    warning: f64,
+    #[default(10.0)]
+    critical: f64,
+    thresholds: HashMap<Kind, Thresholds>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+enum Kind {
+    Mouse,
+    Keyboard,
+    GamingInput,
+    Headset,
+}
+
+impl Kind {
+    /// https://upower.freedesktop.org/docs/Device.html#Device:Type
+    fn from_upower(device_type: u32) -> Option<Self> {
+        match device_type {
+            5 => Some(Self::Mouse),
+            6 => Some(Self::Keyboard),
+            12 => Some(Self::GamingInput),
+            // Headsets and headphones
+            17 | 19 => Some(Self::Headset),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Mouse => "mouse",
+            Self::Keyboard => "keyboard",
+            Self::GamingInput => "gaming_input",
+            Self::Headset => "headset",
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            Self::Mouse => "mouse",
+            Self::Keyboard => "keyboard",
+            Self::GamingInput => "joystick",
+            Self::Headset => "headphones",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+struct Thresholds {
+    warning: f64,
+    critical: f64,
+}
+
+/// The properties of a UPower device which the block uses
+#[derive(Debug, Clone, PartialEq)]
+struct Device {
+    /// The last component of the object path, which is unique
+    id: String,
+    kind: Kind,
+    name: String,
+    native_path: String,
+    percentage: f64,
+    /// https://upower.freedesktop.org/docs/Device.html#Device:State
+    state: u32,
+    is_present: bool,
+}
+
+impl Device {
+    /// Whether UPower knows the charge of the device
+    fn is_known(&self) -> bool {
+        self.is_present && !(self.state == 0 && self.percentage == 0.0)
+    }
+}
+
+/// Which devices are shown, and in which state
+struct Filter {
+    show: Vec<Kind>,
+    exclude: Vec<Regex>,
+    default_thresholds: Thresholds,
+    thresholds: HashMap<Kind, Thresholds>,
+}
+
+impl Filter {
+    fn new(config: &Config) -> Result<Self> {
+        let exclude = config
+            .exclude
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()
+            .error("Invalid pattern in 'exclude'")?;
+        Ok(Self {
+            show: config.show.clone(),
+            exclude,
+            default_thresholds: Thresholds {
+                warning: config.warning,
+                critical: config.critical,
+            },
+            thresholds: config.thresholds.clone(),
+        })
+    }
+
+    fn state(&self, kind: Kind, percentage: f64) -> State {
+        let thresholds = self
+            .thresholds
+            .get(&kind)
+            .unwrap_or(&self.default_thresholds);
+        if percentage <= thresholds.critical {
+            State::Critical
+        } else if percentage <= thresholds.warning {
+            State::Warning
+        } else {
+            State::Idle
+        }
+    }
+
+    /// One part for each device which is shown, in order
+    fn parts(
+        &self,
+        devices: &[Device],
+        get_icon: impl Fn(&str) -> Result<Icon>,
+    ) -> Result<Vec<WidgetPart>> {
+        let mut shown: Vec<(usize, &Device)> = devices
+            .iter()
+            .filter(|device| device.is_known())
+            .filter(|device| {
+                !self.exclude.iter().any(|pattern| {
+                    pattern.is_match(&device.name) || pattern.is_match(&device.native_path)
+                })
+            })
+            .filter_map(|device| {
+                let rank = self.show.iter().position(|kind| *kind == device.kind)?;
+                Some((rank, device))
+            })
+            .collect();
+        shown.sort_by(|(a_rank, a), (b_rank, b)| {
+            (a_rank, &a.name, &a.id).cmp(&(b_rank, &b.name, &b.id))
+        });
+        shown
+            .into_iter()
+            .map(|(_, device)| {
+                Ok(WidgetPart {
+                    name: device.id.clone(),
+                    state: self.state(device.kind, device.percentage),
+                    values: map! {
+                        "icon" => Value::icon(get_icon(device.kind.icon())?),
+                        "name" => Value::text(device.name.clone()),
+                        "kind" => Value::text(device.kind.name().into()),
+                        "percentage" => Value::percents(device.percentage),
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    let filter = Filter::new(&config)?;
+    let mut widget = Widget::new().with_format(config.format.with_default(" $icon $percentage ")?);
+
+    let dbus_conn = new_system_dbus_connection().await?;
+    let upower = UPowerProxy::new(&dbus_conn)
+        .await
+        .error("Failed to create UPowerProxy")?;
+    let mut added = upower
+        .receive_device_added()
+        .await
+        .error("Failed to monitor devices")?;
+    let mut removed = upower
+        .receive_device_removed()
+        .await
+        .error("Failed to monitor devices")?;
+
+    loop {
+        let mut proxies = Vec::new();
+        let mut changes: Vec<PropertiesChangedStream> = Vec::new();
+        for path in upower
+            .enumerate_devices()
+            .await
+            .error("Failed to retrieve UPower devices")?
+        {
+            let proxy = DeviceProxy::builder(&dbus_conn)
+                .path(path.clone())
+                .unwrap()
+                .build()
+                .await
+                .error("Failed to create DeviceProxy")?;
+            let device_type = proxy.type_().await.error("Failed to get device's type")?;
+            if let Some(kind) = Kind::from_upower(device_type) {
+                changes.push(
+                    PropertiesProxy::builder(&dbus_conn)
+                        .destination("org.freedesktop.UPower")
+                        .and_then(|x| x.path(path.clone()))
+                        .unwrap()
+                        .build()
+                        .await
+                        .error("Failed to create PropertiesProxy")?
+                        .receive_properties_changed()
+                        .await
+                        .error("Failed to create PropertiesChangedStream")?,
+                );
+                proxies.push((path, kind, proxy));
+            }
+        }
+        let mut changes = futures::stream::select_all(changes);
+
+        'devices: loop {
+            let mut devices = Vec::new();
+            for (path, kind, proxy) in &proxies {
+                // The device may have disconnected in the meantime
+                if let Ok(device) = read_device(path, *kind, proxy).await {
+                    devices.push(device);
+                }
+            }
+            let parts = filter.parts(&devices, |icon| api.get_icon(icon))?;
+            if parts.is_empty() {
+                api.hide().await?;
+            } else {
+                widget.state = block_state(&parts);
+                widget.set_parts(parts);
+                api.set_widget(&widget).await?;
+            }
+
+            // Enumerate the devices again when one connects or disconnects
+            select! {
+                _ = added.next() => break 'devices,
+                _ = removed.next() => break 'devices,
+                Some(_) = changes.next() => (),
+                _ = api.wait_for_update_request() => (),
+            }
+        }
+    }
+}
+
+/// The state of the device with the lowest charge, relative to its thresholds
+fn block_state(parts: &[WidgetPart]) -> State {
+    if parts.iter().any(|part| part.state == State::Critical) {
+        State::Critical
+    } else if parts.iter().any(|part| part.state == State::Warning) {
+        State::Warning
+    } else {
+        State::Idle
+    }
+}
+
+async fn read_device(
+    path: &OwnedObjectPath,
+    kind: Kind,
+    proxy: &DeviceProxy<'_>,
+) -> Result<Device> {
+    Ok(Device {
+        id: path.as_str().rsplit('/').next().unwrap_or_default().into(),
+        kind,
+        name: proxy.model().await.error("Failed to get device's model")?,
+        native_path: proxy
+            .native_path()
+            .await
+            .error("Failed to get device's native path")?,
+        percentage: proxy.percentage().await.error("Failed to get percentage")?,
+        state: proxy.state().await.error("Failed to get state")?,
+        is_present: proxy
+            .is_present()
+            .await
+            .error("Failed to get whether the device is present")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatting::value::ValueInner;
+
+    fn device(id: &str, kind: Kind, name: &str, percentage: f64) -> Device {
+        Device {
+            id: id.into(),
+            kind,
+            name: name.into(),
+            native_path: format!("hidpp_battery_{id}"),
+            percentage,
+            state: 2,
+            is_present: true,
+        }
+    }
+
+    fn parts(filter: &Filter, devices: &[Device]) -> Vec<WidgetPart> {
+        filter
+            .parts(devices, |icon| Ok(Icon::Plain(icon.into())))
+            .unwrap()
+    }
+
+    fn shown(filter: &Filter, devices: &[Device]) -> Vec<(String, State, String)> {
+        parts(filter, devices)
+            .into_iter()
+            .map(|part| {
+                let icon = match &part.values.get("icon").unwrap().inner {
+                    ValueInner::Icon(icon) => icon.text().to_owned(),
+                    _ => unreachable!(),
+                };
+                (part.name, part.state, icon)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kinds() {
+        let kinds: Vec<_> = [5, 6, 12, 17, 19, 2, 1, 0]
+            .into_iter()
+            .map(Kind::from_upower)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                Some(Kind::Mouse),
+                Some(Kind::Keyboard),
+                Some(Kind::GamingInput),
+                Some(Kind::Headset),
+                Some(Kind::Headset),
+                None,
+                None,
+                None
+            ]
+        );
+        let icons: Vec<_> = [
+            Kind::Mouse,
+            Kind::Keyboard,
+            Kind::GamingInput,
+            Kind::Headset,
+        ]
+        .into_iter()
+        .map(Kind::icon)
+        .collect();
+        assert_eq!(icons, ["mouse", "keyboard", "joystick", "headphones"]);
+    }
+
+    #[test]
+    fn devices() {
+        let config: Config = toml::from_str(
+            r#"
+            show = ["mouse", "keyboard", "headset"]
+            exclude = ["^Receiver", "usb-0000:00:14"]
+            thresholds = { mouse = { warning = 30, critical = 15 } }
+            "#,
+        )
+        .unwrap();
+        let filter = Filter::new(&config).unwrap();
+        let mut devices = vec![
+            device("keyboard_0", Kind::Keyboard, "K380", 80.0),
+            device("mouse_1", Kind::Mouse, "MX Master 3", 25.0),
+            device("gaming_input_2", Kind::GamingInput, "Xbox Controller", 5.0),
+            device("mouse_3", Kind::Mouse, "Receiver mouse", 50.0),
+        ];
+        // Mice first, with the thresholds of mice
+        assert_eq!(
+            shown(&filter, &devices),
+            [
+                ("mouse_1".into(), State::Warning, "mouse".into()),
+                ("keyboard_0".into(), State::Idle, "keyboard".into()),
+            ]
+        );
+
+        // A headset connects
+        devices.push(device("headset_4", Kind::Headset, "WH-1000XM4", 9.0));
+        // And another mouse, on an excluded port
+        let mut wired = device("mouse_5", Kind::Mouse, "G502", 90.0);
+        wired.native_path = "usb-0000:00:14.0-1".into();
+        devices.push(wired);
+        assert_eq!(
+            shown(&filter, &devices),
+            [
+                ("mouse_1".into(), State::Warning, "mouse".into()),
+                ("keyboard_0".into(), State::Idle, "keyboard".into()),
+                ("headset_4".into(), State::Critical, "headphones".into()),
+            ]
+        );
+
+        // The mouse disconnects, and the keyboard no longer knows its charge
+        devices.remove(1);
+        devices[0].state = 0;
+        devices[0].percentage = 0.0;
+        assert_eq!(
+            shown(&filter, &devices),
+            [("headset_4".into(), State::Critical, "headphones".into())]
+        );
+        devices[0].state = 2;
+        devices[0].is_present = false;
+        assert_eq!(shown(&filter, &devices).len(), 1);
+    }
+
+    #[test]
+    fn thresholds() {
+        let filter = Filter::new(&Config::default()).unwrap();
+        assert_eq!(filter.state(Kind::Keyboard, 21.0), State::Idle);
+        assert_eq!(filter.state(Kind::Keyboard, 20.0), State::Warning);
+        assert_eq!(filter.state(Kind::Keyboard, 10.5), State::Warning);
+        assert_eq!(filter.state(Kind::Keyboard, 10.0), State::Critical);
+        assert_eq!(filter.state(Kind::Headset, 0.0), State::Critical);
+
+        let mut devices = vec![
+            device("mouse_0", Kind::Mouse, "A", 15.0),
+            device("mouse_1", Kind::Mouse, "B", 80.0),
+        ];
+        assert_eq!(block_state(&parts(&filter, &devices)), State::Warning);
+        // A critical device makes the whole block critical
+        devices[1].percentage = 8.0;
+        let parts = parts(&filter, &devices);
+        let states: Vec<_> = parts.iter().map(|part| part.state).collect();
+        assert_eq!(states, [State::Warning, State::Critical]);
+        assert_eq!(block_state(&parts), State::Critical);
+    }
+
+    #[test]
+    fn bad_pattern() {
+        let config = Config {
+            exclude: vec!["(".into()],
+            ..Default::default()
+        };
+        assert!(Filter::new(&config).is_err());
+    }
+}
@@ -10,244 +10,339 @@
 //! 5) If you have fewer than four checkmarks, take a short break (3–5 minutes) and then return to step 2.
 //! 6) After four pomodoros, take a longer break (15–30 minutes), reset your checkmark count to zero, then go to step 1.
 //!
+//! # Usage
+//!
+//! A left click starts the timer, and pauses or resumes it while it runs. When a work period is
+//! over, a break starts right away, and when the break is over, the next work period starts. A
+//! middle click stops the timer. Scrolling changes the length of the next work period, by a minute
+//! per step. The block is good during breaks and critical in the last minute of a work period.
 //!
 //! # Configuration
 //!
 //! Key | Values | Default
 //! ----|--------|--------
-//! `format` | A string to customise the output of this block. | <code>" $icon{ $message&vert;} "</code>
-//! `message` | Message when timer expires | `"Pomodoro over! Take a break!"`
-//! `break_message` | Message when break is over | `"Break over! Time to work!"`
-//! `notify_cmd` | A shell command to run as a notifier. `{msg}` will be substituted with either `message` or `break_message`. | `None`
-//! `blocking_cmd` | Is `notify_cmd` blocking? If it is, then pomodoro block will wait until the command finishes before proceeding. Otherwise, you will have to click on the block in order to proceed. | `false`
+//! `format` | A string to customise the output of this block. See below for available placeholders. | <code>" $icon $time "</code>
+//! `length` | Length of a work period in minutes | `25`
+//! `break_length` | Length of a break in minutes | `5`
+//! `message` | Message when a work period is over | `"Pomodoro over! Take a break!"`
+//! `break_message` | Message when a break is over | `"Break over! Time to work!"`
+//! `notifier` | How to show the messages: `"none"`, `"i3nag"` (with `i3-nagbar`) or `"notify-send"` | `"none"`
+//! `notify_cmd` | A shell command to show the messages instead of `notifier`. `{msg}` will be substituted with either `message` or `break_message`. | `None`
 //!
-//! Placeholder | Value                               | Type
-//! ------------|-------------------------------------|------
-//! `icon`      | A static icon                       | Icon
-//! `message`   | Current message                     | Text
+//! Placeholder | Value                                                               | Type
+//! ------------|---------------------------------------------------------------------|------
+//! `icon`      | An icon which depends on the state of the timer                     | Icon
+//! `time`      | The time left, as `{minutes}:{seconds}`. While stopped, the length of the next work period. | Text
 //!
-//! # Example
+//! Action   | Default button
+//! ---------|---------------
+//! `toggle` | Left
+//! `reset`  | Middle
+//! `longer` | Wheel Up
+//! `shorter`| Wheel Down
 //!
-//! Use `swaynag` as a notifier:
+//! # Examples
+//!
+//! Use `notify-send` as a notifier:
 //!
 //! ```toml
 //! [[block]]
 //! block = "pomodoro"
-//! notify_cmd = "swaynag -m '{msg}'"
-//! blocking_cmd = true
+//! length = 50
+//! break_length = 10
+//! notifier = "notify-send"
 //! ```
 //!
-//! Use `notify-send` as a notifier:
+//! Use `swaynag` as a notifier:
 //!
 //! ```toml
 //! [[block]]
 //! block = "pomodoro"
-//! notify_cmd = "notify-send '{msg}'"
-//! blocking_cmd = false
+//! notify_cmd = "swaynag -m '{msg}'"
 //! ```
 //!
 //! # Icons Used
-//! - `pomodoro`
 //! - `pomodoro_started`
 //! - `pomodoro_stopped`
 //! - `pomodoro_paused`
 //! - `pomodoro_break`
-//!
-//! # TODO
-//! - Use different icons.
-//! - Use format strings.
 
 use super::prelude::*;
-use crate::subprocess::{spawn_shell, spawn_shell_sync};
-use std::time::Instant;
+use tokio::time::{interval_at, Instant};
 
 #[derive(Deserialize, Debug, SmartDefault)]
 #[serde(default)]
 pub struct Config {
     format: FormatConfig,
+    #[default(25)]
+    length: u64,
+    #[default(5)]
+    break_length: u64,
     #[default("Pomodoro over! Take a break!".into())]
     message: String,
     #[default("Break over! Time to work!".into())]
     break_message: String,
+    notifier: Notifier,
     notify_cmd: Option<String>,
-    blocking_cmd: bool,
 }
 
-struct Block {
-    widget: Widget,
-    api: CommonApi,
-    block_config: Config,
+#[derive(Deserialize, Debug, SmartDefault, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Notifier {
+    #[default]
+    None,
+    I3nag,
+    #[serde(rename = "notify-send")]
+    NotifySend,
 }
 
-impl Block {
-    async fn set_text(&mut self, text: String) -> Result<()> {
-        let mut values = map!(
-            "icon" => Value::icon(self.api.get_icon("pomodoro")?),
-        );
-        if !text.is_empty() {
-            values.insert("message".into(), Value::text(text));
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Work,
+    Break,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Timer {
+    Stopped,
+    /// The end is kept rather than the time left, so that the timer does not drift
+    Running {
+        phase: Phase,
+        ends_at: Instant,
+    },
+    Paused {
+        phase: Phase,
+        left: Duration,
+    },
+}
+
+impl Timer {
+    /// Start, pause or resume the timer
+    fn toggle(self, length: Duration, now: Instant) -> Self {
+        match self {
+            Self::Stopped => Self::Running {
+                phase: Phase::Work,
+                ends_at: now + length,
+            },
+            Self::Running { phase, ends_at } => Self::Paused {
+                phase,
+                left: ends_at.saturating_duration_since(now),
+            },
+            Self::Paused { phase, left } => Self::Running {
+                phase,
+                ends_at: now + left,
+            },
         }
-        self.widget.set_values(values);
-        self.api.set_widget(&self.widget).await
     }
 
-    async fn wait_for_click(&mut self, button: &str) {
-        loop {
-            if let Action(action) = self.api.event().await {
-                if action == button {
-                    break;
-                }
+    /// Move to the next phase if the current one is over, and return the phase which ended
+    fn advance(&mut self, length: Duration, break_length: Duration, now: Instant) -> Option<Phase> {
+        match *self {
+            Self::Running { phase, ends_at } if ends_at <= now => {
+                // The next phase starts when the last one ended, not when this was noticed
+                *self = match phase {
+                    Phase::Work => Self::Running {
+                        phase: Phase::Break,
+                        ends_at: ends_at + break_length,
+                    },
+                    Phase::Break => Self::Running {
+                        phase: Phase::Work,
+                        ends_at: ends_at + length,
+                    },
+                };
+                Some(phase)
             }
+            _ => None,
         }
     }
 
-    async fn read_params(&mut self) -> Result<(Duration, Duration, u64)> {
-        let task_len = self.read_u64(25, "Task length:").await?;
-        let break_len = self.read_u64(5, "Break length:").await?;
-        let pomodoros = self.read_u64(4, "Pomodoros:").await?;
-        Ok((
-            Duration::from_secs(task_len * 60),
-            Duration::from_secs(break_len * 60),
-            pomodoros,
-        ))
-    }
-
-    async fn read_u64(&mut self, mut number: u64, msg: &str) -> Result<u64> {
-        loop {
-            self.set_text(format!("{msg} {number}")).await?;
-            if let Action(action) = self.api.event().await {
-                match action.as_ref() {
-                    "_left" => break,
-                    "_up" => number += 1,
-                    "_down" => number = number.saturating_sub(1),
-                    _ => (),
-                }
+    /// The phase and the time left, `None` while stopped
+    fn left(self, now: Instant) -> Option<(Phase, Duration)> {
+        match self {
+            Self::Stopped => None,
+            Self::Running { phase, ends_at } => {
+                Some((phase, ends_at.saturating_duration_since(now)))
             }
+            Self::Paused { phase, left } => Some((phase, left)),
         }
-        Ok(number)
     }
+}
 
-    async fn run_pomodoro(
-        &mut self,
-        task_len: Duration,
-        break_len: Duration,
-        pomodoros: u64,
-    ) -> Result<()> {
-        for pomodoro in 0..pomodoros {
-            // Task timer
-            self.widget.state = State::Idle;
-            let timer = Instant::now();
-            loop {
-                let elapsed = timer.elapsed();
-                if elapsed >= task_len {
-                    break;
-                }
-                let left = task_len - elapsed;
-                let text = if pomodoro == 0 {
-                    format!("{} min", (left.as_secs() + 59) / 60,)
-                } else {
-                    format!(
-                        "{} {} min",
-                        "|".repeat(pomodoro as usize),
-                        (left.as_secs() + 59) / 60,
-                    )
-                };
-                self.set_text(text).await?;
-                select! {
-                    _ = sleep(Duration::from_secs(10)) => (),
-                    event = self.api.event() => match event {
-                        Action(a) if a == "_middle" => {
-                            return Ok(());
-                        }
-                        _ => (),
-                    }
-                }
-            }
+/// Format a duration as `{minutes}:{seconds:02}`, rounding up, so that a timer shows `0:00` only
+/// when it is over
+fn format_time(duration: Duration) -> String {
+    let seconds = duration.as_millis().div_ceil(1000);
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
 
-            // Show break message
-            self.widget.state = State::Good;
-            self.set_text(self.block_config.message.clone()).await?;
-            if let Some(cmd) = &self.block_config.notify_cmd {
-                let cmd = cmd.replace("{msg}", &self.block_config.message);
-                if self.block_config.blocking_cmd {
-                    spawn_shell_sync(&cmd)
-                        .await
-                        .error("failed to run notify_cmd")?;
-                } else {
-                    spawn_shell(&cmd).error("failed to run notify_cmd")?;
-                    self.wait_for_click("_left").await;
-                }
-            } else {
-                self.wait_for_click("_left").await;
-            }
+fn widget_state(phase: Phase, left: Duration) -> State {
+    match phase {
+        Phase::Break => State::Good,
+        Phase::Work if left <= Duration::from_secs(60) => State::Critical,
+        Phase::Work => State::Idle,
+    }
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[
+        (MouseButton::Left, None, "toggle"),
+        (MouseButton::Middle, None, "reset"),
+        (MouseButton::WheelUp, None, "longer"),
+        (MouseButton::WheelDown, None, "shorter"),
+    ])
+    .await?;
 
-            // No break after the last pomodoro
-            if pomodoro == pomodoros - 1 {
-                break;
+    let mut widget = Widget::new().with_format(config.format.with_default(" $icon $time ")?);
+
+    let minute = Duration::from_secs(60);
+    let break_length = minute * config.break_length as u32;
+    let mut length = config.length.max(1);
+    let mut timer = Timer::Stopped;
+    // Only used to wake up while the timer is running, the time left is computed from `timer`
+    let mut ticks = interval_at(Instant::now(), Duration::from_secs(1));
+
+    loop {
+        let now = Instant::now();
+        if let Some(ended) = timer.advance(minute * length as u32, break_length, now) {
+            let message = match ended {
+                Phase::Work => &config.message,
+                Phase::Break => &config.break_message,
+            };
+            // A broken notifier must not reset the timer
+            if let Err(error) = notify(
+                &api.command_context,
+                config.notifier,
+                config.notify_cmd.as_deref(),
+                message,
+            ) {
+                log::warn!("Failed to run the notifier: {error}");
             }
+        }
 
-            // Break timer
-            let timer = Instant::now();
-            loop {
-                let elapsed = timer.elapsed();
-                if elapsed >= break_len {
-                    break;
-                }
-                let left = break_len - elapsed;
-                self.set_text(format!("Break: {} min", (left.as_secs() + 59) / 60,))
-                    .await?;
-                select! {
-                    _ = sleep(Duration::from_secs(10)) => (),
-                    event = self.api.event() => match event {
-                        Action(a) if a == "_middle" => {
-                            return Ok(());
-                        }
-                        _ => (),
-                    }
-                }
+        let (icon, time) = match timer.left(now) {
+            None => {
+                widget.state = State::Idle;
+                ("pomodoro_stopped", format_time(minute * length as u32))
+            }
+            Some((phase, left)) => {
+                widget.state = widget_state(phase, left);
+                let icon = match (timer, phase) {
+                    (Timer::Paused { .. }, _) => "pomodoro_paused",
+                    (_, Phase::Break) => "pomodoro_break",
+                    (_, Phase::Work) => "pomodoro_started",
+                };
+                (icon, format_time(left))
             }
+        };
+        widget.set_values(map! {
+            "icon" => Value::icon(api.get_icon(icon)?),
+            "time" => Value::text(time),
+        });
+        api.set_widget(&widget).await?;
 
-            // Show task message
-            self.widget.state = State::Good;
-            self.set_text(self.block_config.break_message.clone())
-                .await?;
-            if let Some(cmd) = &self.block_config.notify_cmd {
-                let cmd = cmd.replace("{msg}", &self.block_config.break_message);
-                if self.block_config.blocking_cmd {
-                    spawn_shell_sync(&cmd)
-                        .await
-                        .error("failed to run notify_cmd")?;
-                } else {
-                    spawn_shell(&cmd).error("failed to run notify_cmd")?;
-                    self.wait_for_click("_left").await;
+        let running = matches!(timer, Timer::Running { .. });
+        select! {
+            _ = ticks.tick(), if running => (),
+            event = api.event() => match event {
+                Action(a) if a == "toggle" => {
+                    let now = Instant::now();
+                    timer = timer.toggle(minute * length as u32, now);
+                    if let Timer::Running { ends_at, .. } = timer {
+                        // Tick whenever the shown number of seconds changes
+                        let left = ends_at - now;
+                        ticks = interval_at(
+                            now + Duration::from_nanos(left.subsec_nanos().into()),
+                            Duration::from_secs(1),
+                        );
+                    }
                 }
-            } else {
-                self.wait_for_click("_left").await;
+                Action(a) if a == "reset" => timer = Timer::Stopped,
+                Action(a) if a == "longer" => length += 1,
+                Action(a) if a == "shorter" => length = length.saturating_sub(1).max(1),
+                _ => (),
             }
         }
+    }
+}
 
-        Ok(())
+fn notify(
+    commands: &CommandContext,
+    notifier: Notifier,
+    notify_cmd: Option<&str>,
+    message: &str,
+) -> std::io::Result<()> {
+    match (notify_cmd, notifier) {
+        (Some(cmd), _) => commands.spawn_shell(&cmd.replace("{msg}", message)),
+        (None, Notifier::None) => Ok(()),
+        (None, Notifier::I3nag) => {
+            commands.spawn_process("i3-nagbar", &["-t", "warning", "-m", message])
+        }
+        (None, Notifier::NotifySend) => commands.spawn_process("notify-send", &[message]),
     }
 }
 
-pub async fn run(block_config: Config, api: CommonApi) -> Result<()> {
-    let format = FormatConfig::default().with_default(" $icon{ $message|} ")?;
-    let widget = Widget::new().with_format(format);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut block = Block {
-        widget,
-        api,
-        block_config,
-    };
+    const MINUTE: Duration = Duration::from_secs(60);
 
-    loop {
-        // Send collaped block
-        block.widget.state = State::Idle;
-        block.set_text(String::new()).await?;
+    #[test]
+    fn state_machine() {
+        let (length, break_length) = (MINUTE * 25, MINUTE * 5);
+        let start = Instant::now();
+        let mut timer = Timer::Stopped.toggle(length, start);
+        assert_eq!(timer.left(start), Some((Phase::Work, length)));
 
-        block.wait_for_click("_left").await;
+        // Pausing keeps the time left
+        let paused_at = start + MINUTE * 10;
+        timer = timer.toggle(length, paused_at);
+        assert_eq!(
+            timer,
+            Timer::Paused {
+                phase: Phase::Work,
+                left: MINUTE * 15
+            }
+        );
+        let resumed_at = paused_at + MINUTE * 60;
+        timer = timer.toggle(length, resumed_at);
+        assert_eq!(timer.left(resumed_at), Some((Phase::Work, MINUTE * 15)));
+
+        // A break follows work and work follows a break
+        let now = resumed_at + MINUTE * 15;
+        assert_eq!(timer.advance(length, break_length, now - MINUTE), None);
+        assert_eq!(timer.advance(length, break_length, now), Some(Phase::Work));
+        assert_eq!(timer.left(now), Some((Phase::Break, break_length)));
+        let now = now + break_length;
+        assert_eq!(timer.advance(length, break_length, now), Some(Phase::Break));
+        assert_eq!(timer.left(now), Some((Phase::Work, length)));
+    }
+
+    #[test]
+    fn no_drift() {
+        let (length, break_length) = (MINUTE * 25, MINUTE * 5);
+        let start = Instant::now();
+        let mut timer = Timer::Stopped.toggle(length, start);
+        // The end of a phase is noticed late, e.g. after the machine was busy
+        let late = start + length + Duration::from_millis(1500);
+        assert_eq!(timer.advance(length, break_length, late), Some(Phase::Work));
+        assert_eq!(
+            timer,
+            Timer::Running {
+                phase: Phase::Break,
+                ends_at: start + length + break_length
+            }
+        );
+    }
 
-        let (task_len, break_len, pomodoros) = block.read_params().await?;
-        block.run_pomodoro(task_len, break_len, pomodoros).await?;
+    #[test]
+    fn display() {
+        assert_eq!(format_time(MINUTE * 25), "25:00");
+        assert_eq!(format_time(Duration::from_millis(61_500)), "1:02");
+        assert_eq!(format_time(Duration::from_millis(100)), "0:01");
+        assert_eq!(format_time(Duration::ZERO), "0:00");
+        assert_eq!(widget_state(Phase::Work, MINUTE * 2), State::Idle);
+        assert_eq!(widget_state(Phase::Work, MINUTE), State::Critical);
+        assert_eq!(widget_state(Phase::Break, Duration::ZERO), State::Good);
     }
 }
@@ -3,20 +3,20 @@ pub use super::{BlockEvent::*, CommonApi};
 pub use crate::click::MouseButton;
 pub use crate::errors::*;
 pub use crate::formatting::{config::Config as FormatConfig, value::Value, Values};
+pub use crate::subprocess::CommandContext;
 pub use crate::util::{default, new_dbus_connection, new_system_dbus_connection};
 pub use crate::widget::{State, Widget};
-pub use crate::wrappers::{Seconds, ShellString};
+pub use crate::wrappers::{Seconds, ShellString, Threshold};
 pub use crate::REQWEST_CLIENT;
 
 pub use serde::Deserialize;
 
-pub use std::borrow::Cow;
 pub use std::collections::HashMap;
 pub use std::fmt::Write;
 pub use std::pin::Pin;
 pub use std::time::Duration;
 
-pub use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+pub use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 pub use tokio::select;
 pub use tokio::time::sleep;
 
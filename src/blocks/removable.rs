@@ -0,0 +1,610 @@
+//! Mounted removable drives
+//!
+//! Shows every mounted filesystem on a removable drive, as reported by
+//! [UDisks2](https://www.freedesktop.org/wiki/Software/udisks/). The block is hidden when nothing
+//! removable is mounted. Clicking a device unmounts its filesystem and powers off the drive; the
+//! block is in warning state while this happens and in critical state, showing the error, if it
+//! fails (e.g. because the filesystem is busy).
+//!
+//! At most eight devices are shown.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `format` | A string to customise the output of each device. See below for available placeholders. | `" $icon $label $percentage.eng(w:2) "`
+//! `interval` | Update interval of the used space, in seconds. Mounts and unmounts are shown immediately. | `30`
+//! `exclude` | A list of regex patterns. Filesystems with a matching label or UUID are ignored. | `[]`
+//! `show_internal` | Show filesystems on non-removable drives too | `false`
+//!
+//! Placeholder   | Value                                              | Type   | Unit
+//! --------------|----------------------------------------------------|--------|------
+//! `icon`        | A static icon                                      | Icon   | -
+//! `label`       | Filesystem label, or the device name if it has none | Text   | -
+//! `device`      | Device name, e.g. `sdb1`                           | Text   | -
+//! `mount_point` | Where the filesystem is mounted                    | Text   | -
+//! `percentage`  | Used space                                         | Number | %
+//! `used`        | Used space                                         | Number | Bytes
+//! `total`       | Total space                                        | Number | Bytes
+//!
+//! Action                   | Default button
+//! -------------------------|---------------
+//! `eject_0` ... `eject_7` | Left on the first ... eighth device
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "removable"
+//! format = " $icon $label "
+//! exclude = ["^backup$"]
+//! ```
+//!
+//! # Icons Used
+//! - `disk_drive`
+
+use super::prelude::*;
+use crate::escape::CollectEscaped;
+use crate::formatting::Format;
+use nix::sys::statvfs::statvfs;
+use regex::Regex;
+use zbus::fdo::ObjectManagerProxy;
+use zbus::zvariant::{self, ObjectPath, OwnedObjectPath, OwnedValue};
+use zbus::{MatchRule, MessageStream};
+
+make_log_macro!(debug, "removable");
+
+const MAX_DEVICES: usize = 8;
+const INSTANCES: [&str; MAX_DEVICES] = ["0", "1", "2", "3", "4", "5", "6", "7"];
+const ACTIONS: [&str; MAX_DEVICES] = [
+    "eject_0", "eject_1", "eject_2", "eject_3", "eject_4", "eject_5", "eject_6", "eject_7",
+];
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    format: FormatConfig,
+    #[default(30.into())]
+    interval: Seconds,
+    exclude: Vec<String>,
+    show_internal: bool,
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[
+        (MouseButton::Left, Some(INSTANCES[0]), ACTIONS[0]),
+        (MouseButton::Left, Some(INSTANCES[1]), ACTIONS[1]),
+        (MouseButton::Left, Some(INSTANCES[2]), ACTIONS[2]),
+        (MouseButton::Left, Some(INSTANCES[3]), ACTIONS[3]),
+        (MouseButton::Left, Some(INSTANCES[4]), ACTIONS[4]),
+        (MouseButton::Left, Some(INSTANCES[5]), ACTIONS[5]),
+        (MouseButton::Left, Some(INSTANCES[6]), ACTIONS[6]),
+        (MouseButton::Left, Some(INSTANCES[7]), ACTIONS[7]),
+    ])
+    .await?;
+
+    let format = config
+        .format
+        .with_default(" $icon $label $percentage.eng(w:2) ")?;
+    let exclude = config
+        .exclude
+        .iter()
+        .map(|r| Regex::new(r))
+        .collect::<Result<Vec<_>, _>>()
+        .error("Invalid regex")?;
+
+    let dbus_conn = new_system_dbus_connection().await?;
+    let manager = ObjectManagerProxy::builder(&dbus_conn)
+        .destination("org.freedesktop.UDisks2")
+        .and_then(|x| x.path("/org/freedesktop/UDisks2"))
+        .unwrap()
+        .build()
+        .await
+        .error("Failed to create ObjectManagerProxy")?;
+
+    // Interfaces added/removed and property changes (e.g. mount points) of all UDisks2 objects
+    let mut changes = MessageStream::for_match_rule(
+        MatchRule::builder()
+            .msg_type(zbus::MessageType::Signal)
+            .sender("org.freedesktop.UDisks2")
+            .and_then(|x| x.path_namespace("/org/freedesktop/UDisks2"))
+            .unwrap()
+            .build(),
+        &dbus_conn,
+        None,
+    )
+    .await
+    .error("Failed to add match rule")?;
+
+    let mut widget = Widget::new();
+    let mut timer = config.interval.timer();
+    // The device which failed to be ejected and the error
+    let mut failure: Option<(String, String)> = None;
+
+    loop {
+        let objects = manager
+            .get_managed_objects()
+            .await
+            .error("Failed to get UDisks2 objects")?;
+        let (blocks, drives) = parse_objects(objects);
+        let mut devices = mounted_devices(&blocks, &drives, config.show_internal, &exclude);
+        devices.truncate(MAX_DEVICES);
+
+        if devices.is_empty() {
+            api.hide().await?;
+        } else {
+            let mut values = Values::new();
+            let mut outer = String::new();
+            widget.state = State::Idle;
+            for (i, device) in devices.iter().enumerate() {
+                let text = match &failure {
+                    Some((path, error)) if *path == device.block_path => {
+                        widget.state = State::Critical;
+                        format!(" {} ", error.chars().collect_pango_escaped::<String>())
+                    }
+                    _ => render_device(device, &format, &api)?,
+                };
+                values.insert(
+                    format!("d{i}").into(),
+                    Value::text(text).with_instance(INSTANCES[i]),
+                );
+                let _ = write!(outer, "$d{i}.pango-str()");
+            }
+            widget.set_format(FormatConfig::default().with_default(&outer)?);
+            widget.set_values(values);
+            api.set_widget(&widget).await?;
+        }
+        failure = None;
+
+        loop {
+            select! {
+                _ = timer.tick() => break,
+                Some(_) = changes.next() => {
+                    // Many signals are sent at once when a drive is plugged in
+                    let _ = tokio::time::timeout(Duration::from_millis(100), async {
+                        loop { let _ = changes.next().await; }
+                    }).await;
+                    break;
+                }
+                event = api.event() => match event {
+                    UpdateRequest => break,
+                    Action(a) => {
+                        if let Some(device) = ACTIONS.iter().position(|x| *x == a).and_then(|i| devices.get(i)) {
+                            widget.state = State::Warning;
+                            api.set_widget(&widget).await?;
+                            if let Err(e) = eject(&dbus_conn, device).await {
+                                debug!("{e}");
+                                failure = Some((device.block_path.clone(), e.to_string()));
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn render_device(device: &Device, format: &Format, api: &CommonApi) -> Result<String> {
+    let mut values = map! {
+        "icon" => Value::icon(api.get_icon("disk_drive")?),
+        "label" => Value::text(device.label.clone()),
+        "device" => Value::text(device.device.clone()),
+        "mount_point" => Value::text(device.mount_point.clone()),
+    };
+    if let Ok(stat) = statvfs(&*device.mount_point) {
+        let total = (stat.blocks() * stat.fragment_size()) as f64;
+        let used = ((stat.blocks() - stat.blocks_free()) * stat.fragment_size()) as f64;
+        values.insert("total".into(), Value::bytes(total));
+        values.insert("used".into(), Value::bytes(used));
+        if total > 0.0 {
+            values.insert("percentage".into(), Value::percents(used / total * 100.));
+        }
+    }
+    let (full, _) = format.render(&values, &api.shared_config)?;
+    Ok(full.iter().map(|f| f.formated_text()).collect())
+}
+
+async fn eject(dbus_conn: &zbus::Connection, device: &Device) -> Result<()> {
+    FilesystemProxy::builder(dbus_conn)
+        .path(device.block_path.as_str())
+        .error("Invalid object path")?
+        .build()
+        .await
+        .error("Failed to create FilesystemProxy")?
+        .unmount(HashMap::new())
+        .await
+        .error("Failed to unmount")?;
+    if device.can_power_off {
+        DriveProxy::builder(dbus_conn)
+            .path(device.drive_path.as_str())
+            .error("Invalid object path")?
+            .build()
+            .await
+            .error("Failed to create DriveProxy")?
+            .power_off(HashMap::new())
+            .await
+            .error("Failed to power off")?;
+    }
+    Ok(())
+}
+
+/// A block device with a filesystem (`org.freedesktop.UDisks2.Block` and `Filesystem` interfaces)
+#[derive(Debug, Default, Clone)]
+struct BlockInfo {
+    path: String,
+    /// Device file, e.g. `/dev/sdb1`, NUL-terminated
+    device: Vec<u8>,
+    label: String,
+    uuid: String,
+    /// Object path of the drive
+    drive: String,
+    hint_system: bool,
+    hint_ignore: bool,
+    /// NUL-terminated mount points
+    mount_points: Vec<Vec<u8>>,
+}
+
+/// `org.freedesktop.UDisks2.Drive` interface
+#[derive(Debug, Default, Clone, Copy)]
+struct DriveInfo {
+    removable: bool,
+    can_power_off: bool,
+}
+
+/// A mounted filesystem to be displayed
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Device {
+    block_path: String,
+    drive_path: String,
+    label: String,
+    device: String,
+    mount_point: String,
+    can_power_off: bool,
+}
+
+type ManagedObjects =
+    HashMap<OwnedObjectPath, HashMap<zbus::names::OwnedInterfaceName, HashMap<String, OwnedValue>>>;
+
+/// Split UDisks2 objects into filesystems and drives
+fn parse_objects(objects: ManagedObjects) -> (Vec<BlockInfo>, HashMap<String, DriveInfo>) {
+    let mut blocks = Vec::new();
+    let mut drives = HashMap::new();
+
+    for (path, interfaces) in objects {
+        let path = path.as_str().to_string();
+        let interface = |name: &str| interfaces.iter().find(|(i, _)| i.as_str() == name);
+
+        if let Some((_, props)) = interface("org.freedesktop.UDisks2.Drive") {
+            drives.insert(
+                path.clone(),
+                DriveInfo {
+                    removable: get_bool(props, "Removable") || get_bool(props, "MediaRemovable"),
+                    can_power_off: get_bool(props, "CanPowerOff"),
+                },
+            );
+        }
+
+        if let (Some((_, block)), Some((_, fs))) = (
+            interface("org.freedesktop.UDisks2.Block"),
+            interface("org.freedesktop.UDisks2.Filesystem"),
+        ) {
+            blocks.push(BlockInfo {
+                path,
+                device: get(block, "Device").unwrap_or_default(),
+                label: get_str(block, "IdLabel"),
+                uuid: get_str(block, "IdUUID"),
+                drive: block
+                    .get("Drive")
+                    .and_then(|v| v.downcast_ref::<ObjectPath>())
+                    .map(|p| p.as_str().to_string())
+                    .unwrap_or_default(),
+                hint_system: get_bool(block, "HintSystem"),
+                hint_ignore: get_bool(block, "HintIgnore"),
+                mount_points: get(fs, "MountPoints").unwrap_or_default(),
+            });
+        }
+    }
+
+    (blocks, drives)
+}
+
+fn get<T>(props: &HashMap<String, OwnedValue>, name: &str) -> Option<T>
+where
+    T: TryFrom<OwnedValue>,
+{
+    T::try_from(props.get(name)?.clone()).ok()
+}
+
+fn get_bool(props: &HashMap<String, OwnedValue>, name: &str) -> bool {
+    props
+        .get(name)
+        .and_then(|v| v.downcast_ref::<bool>())
+        .copied()
+        .unwrap_or(false)
+}
+
+fn get_str(props: &HashMap<String, OwnedValue>, name: &str) -> String {
+    props
+        .get(name)
+        .and_then(|v| v.downcast_ref::<str>())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Decode a NUL-terminated byte string as used by UDisks2 for paths
+fn decode_bytestring(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Mounted filesystems which should be displayed, sorted by device name
+fn mounted_devices(
+    blocks: &[BlockInfo],
+    drives: &HashMap<String, DriveInfo>,
+    show_internal: bool,
+    exclude: &[Regex],
+) -> Vec<Device> {
+    let mut devices: Vec<Device> = blocks
+        .iter()
+        .filter(|block| !block.hint_ignore)
+        .filter_map(|block| {
+            let mount_point = decode_bytestring(block.mount_points.first()?);
+            let drive = drives.get(&block.drive).copied().unwrap_or_default();
+            let removable = drive.removable || !block.hint_system;
+            if !show_internal && !removable {
+                return None;
+            }
+            if exclude
+                .iter()
+                .any(|r| r.is_match(&block.label) || r.is_match(&block.uuid))
+            {
+                return None;
+            }
+            let device = decode_bytestring(&block.device);
+            let device = device.rsplit('/').next().unwrap_or_default().to_string();
+            Some(Device {
+                block_path: block.path.clone(),
+                drive_path: block.drive.clone(),
+                label: if block.label.is_empty() {
+                    device.clone()
+                } else {
+                    block.label.clone()
+                },
+                device,
+                mount_point,
+                can_power_off: drive.can_power_off,
+            })
+        })
+        .collect();
+    devices.sort_by(|a, b| a.device.cmp(&b.device));
+    devices
+}
+
+#[zbus::dbus_proxy(
+    interface = "org.freedesktop.UDisks2.Filesystem",
+    default_service = "org.freedesktop.UDisks2"
+)]
+trait Filesystem {
+    fn unmount(&self, options: HashMap<&str, zvariant::Value<'_>>) -> zbus::Result<()>;
+}
+
+#[zbus::dbus_proxy(
+    interface = "org.freedesktop.UDisks2.Drive",
+    default_service = "org.freedesktop.UDisks2"
+)]
+trait Drive {
+    fn power_off(&self, options: HashMap<&str, zvariant::Value<'_>>) -> zbus::Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::names::OwnedInterfaceName;
+
+    fn props(props: Vec<(&str, zvariant::Value<'static>)>) -> HashMap<String, OwnedValue> {
+        props
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.into()))
+            .collect()
+    }
+
+    fn object(
+        path: &str,
+        interfaces: Vec<(&str, HashMap<String, OwnedValue>)>,
+    ) -> (
+        OwnedObjectPath,
+        HashMap<OwnedInterfaceName, HashMap<String, OwnedValue>>,
+    ) {
+        (
+            ObjectPath::try_from(path.to_string()).unwrap().into(),
+            interfaces
+                .into_iter()
+                .map(|(i, p)| (OwnedInterfaceName::try_from(i).unwrap(), p))
+                .collect(),
+        )
+    }
+
+    fn fs_object(
+        name: &str,
+        label: &str,
+        drive: &str,
+        hint_system: bool,
+        mount_point: Option<&str>,
+    ) -> (
+        OwnedObjectPath,
+        HashMap<OwnedInterfaceName, HashMap<String, OwnedValue>>,
+    ) {
+        let nul_terminated = |s: &str| {
+            let mut bytes = s.as_bytes().to_vec();
+            bytes.push(0);
+            bytes
+        };
+        object(
+            &format!("/org/freedesktop/UDisks2/block_devices/{name}"),
+            vec![
+                (
+                    "org.freedesktop.UDisks2.Block",
+                    props(vec![
+                        ("Device", nul_terminated(&format!("/dev/{name}")).into()),
+                        ("IdLabel", label.to_string().into()),
+                        ("IdUUID", format!("uuid-{name}").into()),
+                        (
+                            "Drive",
+                            ObjectPath::try_from(drive.to_string()).unwrap().into(),
+                        ),
+                        ("HintSystem", hint_system.into()),
+                        ("HintIgnore", false.into()),
+                    ]),
+                ),
+                (
+                    "org.freedesktop.UDisks2.Filesystem",
+                    props(vec![(
+                        "MountPoints",
+                        mount_point
+                            .into_iter()
+                            .map(nul_terminated)
+                            .collect::<Vec<_>>()
+                            .into(),
+                    )]),
+                ),
+            ],
+        )
+    }
+
+    fn objects() -> ManagedObjects {
+        [
+            object(
+                "/org/freedesktop/UDisks2/drives/Kingston_DataTraveler",
+                vec![(
+                    "org.freedesktop.UDisks2.Drive",
+                    props(vec![
+                        ("Removable", true.into()),
+                        ("CanPowerOff", true.into()),
+                    ]),
+                )],
+            ),
+            object(
+                "/org/freedesktop/UDisks2/drives/Samsung_SSD",
+                vec![(
+                    "org.freedesktop.UDisks2.Drive",
+                    props(vec![
+                        ("Removable", false.into()),
+                        ("CanPowerOff", false.into()),
+                    ]),
+                )],
+            ),
+            fs_object(
+                "sdb1",
+                "STICK",
+                "/org/freedesktop/UDisks2/drives/Kingston_DataTraveler",
+                false,
+                Some("/run/media/user/STICK"),
+            ),
+            fs_object(
+                "sdb2",
+                "",
+                "/org/freedesktop/UDisks2/drives/Kingston_DataTraveler",
+                false,
+                Some("/run/media/user/1234-ABCD"),
+            ),
+            fs_object(
+                "sdb3",
+                "unmounted",
+                "/org/freedesktop/UDisks2/drives/Kingston_DataTraveler",
+                false,
+                None,
+            ),
+            fs_object(
+                "nvme0n1p2",
+                "root",
+                "/org/freedesktop/UDisks2/drives/Samsung_SSD",
+                true,
+                Some("/"),
+            ),
+            // The root object only has an interface we are not interested in
+            object(
+                "/org/freedesktop/UDisks2/Manager",
+                vec![("org.freedesktop.UDisks2.Manager", props(vec![]))],
+            ),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn bytestrings() {
+        assert_eq!(decode_bytestring(b"/dev/sdb1\0"), "/dev/sdb1");
+        assert_eq!(decode_bytestring(b"/dev/sdb1"), "/dev/sdb1");
+        assert_eq!(decode_bytestring(b""), "");
+    }
+
+    #[test]
+    fn parse() {
+        let (blocks, drives) = parse_objects(objects());
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(drives.len(), 2);
+        let sdb1 = blocks
+            .iter()
+            .find(|b| b.path == "/org/freedesktop/UDisks2/block_devices/sdb1")
+            .unwrap();
+        assert_eq!(decode_bytestring(&sdb1.device), "/dev/sdb1");
+        assert_eq!(sdb1.label, "STICK");
+        assert_eq!(sdb1.uuid, "uuid-sdb1");
+        assert_eq!(
+            sdb1.drive,
+            "/org/freedesktop/UDisks2/drives/Kingston_DataTraveler"
+        );
+        assert_eq!(sdb1.mount_points.len(), 1);
+        assert!(drives["/org/freedesktop/UDisks2/drives/Kingston_DataTraveler"].removable);
+        assert!(drives["/org/freedesktop/UDisks2/drives/Kingston_DataTraveler"].can_power_off);
+    }
+
+    #[test]
+    fn removable_only() {
+        let (blocks, drives) = parse_objects(objects());
+        let devices = mounted_devices(&blocks, &drives, false, &[]);
+        assert_eq!(
+            devices,
+            [
+                Device {
+                    block_path: "/org/freedesktop/UDisks2/block_devices/sdb1".into(),
+                    drive_path: "/org/freedesktop/UDisks2/drives/Kingston_DataTraveler".into(),
+                    label: "STICK".into(),
+                    device: "sdb1".into(),
+                    mount_point: "/run/media/user/STICK".into(),
+                    can_power_off: true,
+                },
+                Device {
+                    block_path: "/org/freedesktop/UDisks2/block_devices/sdb2".into(),
+                    drive_path: "/org/freedesktop/UDisks2/drives/Kingston_DataTraveler".into(),
+                    label: "sdb2".into(),
+                    device: "sdb2".into(),
+                    mount_point: "/run/media/user/1234-ABCD".into(),
+                    can_power_off: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn internal_and_exclude() {
+        let (blocks, drives) = parse_objects(objects());
+        let devices = mounted_devices(&blocks, &drives, true, &[]);
+        let names: Vec<_> = devices.iter().map(|d| d.device.as_str()).collect();
+        assert_eq!(names, ["nvme0n1p2", "sdb1", "sdb2"]);
+
+        let exclude = [
+            Regex::new("^STICK$").unwrap(),
+            Regex::new("^root$").unwrap(),
+        ];
+        let devices = mounted_devices(&blocks, &drives, true, &exclude);
+        let names: Vec<_> = devices.iter().map(|d| d.device.as_str()).collect();
+        assert_eq!(names, ["sdb2"]);
+
+        let exclude = [Regex::new("uuid-sdb2").unwrap()];
+        let devices = mounted_devices(&blocks, &drives, false, &exclude);
+        let names: Vec<_> = devices.iter().map(|d| d.device.as_str()).collect();
+        assert_eq!(names, ["sdb1"]);
+    }
+}
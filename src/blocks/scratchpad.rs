@@ -0,0 +1,435 @@
+//! Windows in the scratchpad of i3 or sway
+//!
+//! This block shows how many windows are hidden in the scratchpad. A left click shows one of them
+//! with `scratchpad show`, and further clicks cycle through them the way i3 and sway do. The block
+//! is hidden while the scratchpad is empty, unless `show_empty` is set.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $count "`
+//! `show_empty` | Show the block even if the scratchpad is empty | `false`
+//!
+//! Placeholder | Value                                              | Type   | Unit
+//! ------------|----------------------------------------------------|--------|-----
+//! `icon`      | A static icon                                      | Icon   | -
+//! `count`     | The number of windows in the scratchpad            | Number | -
+//! `titles`    | The titles of the windows, separated by `;`        | Text   | -
+//!
+//! Action | Default button
+//! -------|---------------
+//! `show` | Left
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "scratchpad"
+//! format = " $icon $count $titles.str(max_w:30) "
+//! ```
+//!
+//! # Icons Used
+//! - `scratchpad`
+
+use super::prelude::*;
+use swayipc_async::{Connection, EventType, Node, NodeType};
+
+/// The name of the workspace which holds the hidden scratchpad windows, in both i3 and sway
+const SCRATCHPAD_WORKSPACE: &str = "__i3_scratch";
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    format: FormatConfig,
+    show_empty: bool,
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[(MouseButton::Left, None, "show")])
+        .await?;
+
+    let mut widget = Widget::new().with_format(config.format.with_default(" $icon $count ")?);
+
+    let mut connection = Connection::new()
+        .await
+        .error("failed to open connection with swayipc")?;
+    // Windows move to and from the scratchpad with "move" events
+    let mut events = Connection::new()
+        .await
+        .error("failed to open connection with swayipc")?
+        .subscribe(&[EventType::Window])
+        .await
+        .error("could not subscribe to window events")?;
+
+    loop {
+        let tree = connection
+            .get_tree()
+            .await
+            .error("failed to get the tree from swayipc")?;
+        let windows = scratchpad_windows(&tree);
+
+        if windows.is_empty() && !config.show_empty {
+            api.hide().await?;
+        } else {
+            let titles: Vec<&str> = windows
+                .iter()
+                .map(|w| w.name.as_deref().unwrap_or_default())
+                .collect();
+            widget.set_values(map! {
+                "icon" => Value::icon(api.get_icon("scratchpad")?),
+                "count" => Value::number(windows.len()),
+                "titles" => Value::text(titles.join("; ")),
+            });
+            api.set_widget(&widget).await?;
+        }
+
+        select! {
+            event = events.next() => {
+                event.error("swayipc channel closed")?.error("bad event")?;
+            }
+            event = api.event() => match event {
+                Action(a) if a == "show" => {
+                    // The command itself fails if the scratchpad is empty, which is ignored
+                    connection
+                        .run_command("scratchpad show")
+                        .await
+                        .error("failed to run command")?;
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// The windows which are hidden in the scratchpad
+fn scratchpad_windows(tree: &Node) -> Vec<&Node> {
+    let mut windows = Vec::new();
+    if let Some(scratchpad) = find_scratchpad(tree) {
+        for node in scratchpad.nodes.iter().chain(&scratchpad.floating_nodes) {
+            collect_windows(node, &mut windows);
+        }
+    }
+    windows
+}
+
+/// In i3 the workspace is in a content container of the `__i3` output, in sway it is a child of
+/// the output itself
+fn find_scratchpad(node: &Node) -> Option<&Node> {
+    if node.node_type == NodeType::Workspace && node.name.as_deref() == Some(SCRATCHPAD_WORKSPACE) {
+        return Some(node);
+    }
+    node.nodes.iter().find_map(find_scratchpad)
+}
+
+/// i3 wraps each floating window in a container, while in sway the floating node is the window.
+/// Empty containers, e.g. a split whose windows were closed, are not windows.
+fn collect_windows<'a>(node: &'a Node, windows: &mut Vec<&'a Node>) {
+    if node.nodes.is_empty() && node.floating_nodes.is_empty() {
+        // X11 windows have an ID, Wayland windows an app ID
+        if node.window.is_some() || node.app_id.is_some() {
+            windows.push(node);
+        }
+    } else {
+        for child in node.nodes.iter().chain(&node.floating_nodes) {
+            collect_windows(child, windows);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parts of `i3-msg -t get_tree` with two windows in the scratchpad, one of them in a split
+    const I3_TREE: &str = r#"{
+        "id": 1, "type": "root", "name": "root", "border": "normal", "current_border_width": -1,
+        "layout": "splith", "orientation": "horizontal", "percent": null,
+        "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+        "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+        "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+        "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+        "window": null, "window_type": null, "urgent": false, "marks": [], "focused": false,
+        "focus": [2, 10], "fullscreen_mode": 0, "sticky": false, "floating": "auto_off",
+        "swallows": [], "floating_nodes": [],
+        "nodes": [
+            {
+                "id": 2, "type": "output", "name": "__i3", "border": "normal",
+                "current_border_width": -1, "layout": "output", "percent": null,
+                "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "window": null, "urgent": false, "focused": false, "focus": [3],
+                "sticky": false, "floating_nodes": [],
+                "nodes": [
+                    {
+                        "id": 3, "type": "con", "name": "content", "border": "normal",
+                        "current_border_width": -1, "layout": "splith", "percent": null,
+                        "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                        "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "window": null, "urgent": false, "focused": false, "focus": [4],
+                        "sticky": false, "floating_nodes": [],
+                        "nodes": [
+                            {
+                                "id": 4, "type": "workspace", "name": "__i3_scratch",
+                                "num": -1, "border": "normal", "current_border_width": -1,
+                                "layout": "splith", "percent": null, "scratchpad_state": "none",
+                                "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                                "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                "window": null, "urgent": false, "focused": false,
+                                "focus": [5, 7], "sticky": false, "nodes": [],
+                                "floating_nodes": [
+                                    {
+                                        "id": 5, "type": "floating_con", "name": null,
+                                        "border": "normal", "current_border_width": -1,
+                                        "layout": "splith", "percent": null,
+                                        "scratchpad_state": "changed",
+                                        "rect": {"x": 480, "y": 270, "width": 960, "height": 540},
+                                        "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                        "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                        "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                        "window": null, "urgent": false, "focused": false,
+                                        "focus": [6], "sticky": false, "floating_nodes": [],
+                                        "nodes": [
+                                            {
+                                                "id": 6, "type": "con", "name": "htop",
+                                                "border": "normal", "current_border_width": 2,
+                                                "layout": "splith", "percent": 1.0,
+                                                "rect": {"x": 480, "y": 270, "width": 960, "height": 540},
+                                                "window_rect": {"x": 2, "y": 0, "width": 956, "height": 538},
+                                                "deco_rect": {"x": 0, "y": 0, "width": 960, "height": 22},
+                                                "geometry": {"x": 0, "y": 0, "width": 724, "height": 316},
+                                                "window": 10485763, "window_type": "normal",
+                                                "window_properties": {
+                                                    "class": "Alacritty", "instance": "Alacritty",
+                                                    "title": "htop", "transient_for": null
+                                                },
+                                                "urgent": false, "marks": [], "focused": false,
+                                                "focus": [], "sticky": false,
+                                                "floating_nodes": [], "nodes": []
+                                            }
+                                        ]
+                                    },
+                                    {
+                                        "id": 7, "type": "floating_con", "name": null,
+                                        "border": "normal", "current_border_width": -1,
+                                        "layout": "splith", "percent": null,
+                                        "scratchpad_state": "fresh",
+                                        "rect": {"x": 480, "y": 270, "width": 960, "height": 540},
+                                        "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                        "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                        "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                        "window": null, "urgent": false, "focused": false,
+                                        "focus": [8], "sticky": false, "floating_nodes": [],
+                                        "nodes": [
+                                            {
+                                                "id": 8, "type": "con", "name": null,
+                                                "border": "normal", "current_border_width": -1,
+                                                "layout": "splitv", "percent": 1.0,
+                                                "rect": {"x": 480, "y": 270, "width": 960, "height": 540},
+                                                "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                                "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                                "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                                "window": null, "urgent": false, "focused": false,
+                                                "focus": [9], "sticky": false, "floating_nodes": [],
+                                                "nodes": [
+                                                    {
+                                                        "id": 9, "type": "con", "name": "Notes - vim",
+                                                        "border": "normal", "current_border_width": 2,
+                                                        "layout": "splith", "percent": 1.0,
+                                                        "rect": {"x": 480, "y": 270, "width": 960, "height": 540},
+                                                        "window_rect": {"x": 2, "y": 0, "width": 956, "height": 538},
+                                                        "deco_rect": {"x": 0, "y": 0, "width": 960, "height": 22},
+                                                        "geometry": {"x": 0, "y": 0, "width": 724, "height": 316},
+                                                        "window": 10485790,
+                                                        "urgent": false, "marks": ["notes"],
+                                                        "focused": false, "focus": [], "sticky": false,
+                                                        "floating_nodes": [], "nodes": []
+                                                    }
+                                                ]
+                                            }
+                                        ]
+                                    }
+                                ]
+                            }
+                        ]
+                    }
+                ]
+            },
+            {
+                "id": 10, "type": "output", "name": "eDP-1", "border": "normal",
+                "current_border_width": -1, "layout": "output", "percent": 1.0,
+                "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "window": null, "urgent": false, "focused": false, "focus": [11],
+                "sticky": false, "floating_nodes": [],
+                "nodes": [
+                    {
+                        "id": 11, "type": "con", "name": "content", "border": "normal",
+                        "current_border_width": -1, "layout": "splith", "percent": null,
+                        "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                        "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "window": null, "urgent": false, "focused": false, "focus": [12],
+                        "sticky": false, "floating_nodes": [],
+                        "nodes": [
+                            {
+                                "id": 12, "type": "workspace", "name": "1", "num": 1,
+                                "border": "normal", "current_border_width": -1,
+                                "layout": "splith", "percent": null,
+                                "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                                "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                "window": null, "urgent": false, "focused": true, "focus": [],
+                                "sticky": false, "floating_nodes": [], "nodes": []
+                            }
+                        ]
+                    }
+                ]
+            }
+        ]
+    }"#;
+
+    /// Parts of `swaymsg -t get_tree` with one window in the scratchpad
+    const SWAY_TREE: &str = r#"{
+        "id": 1, "type": "root", "name": "root", "orientation": "horizontal",
+        "border": "none", "current_border_width": 0, "layout": "splith", "percent": null,
+        "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+        "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+        "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+        "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+        "urgent": false, "sticky": false, "marks": [], "focused": false, "focus": [3, 2],
+        "floating_nodes": [],
+        "nodes": [
+            {
+                "id": 2, "type": "output", "name": "__i3", "orientation": "horizontal",
+                "border": "none", "current_border_width": 0, "layout": "output",
+                "percent": null,
+                "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "urgent": false, "sticky": false, "marks": [], "focused": false,
+                "focus": [4], "floating_nodes": [],
+                "nodes": [
+                    {
+                        "id": 4, "type": "workspace", "name": "__i3_scratch",
+                        "orientation": "horizontal", "border": "none",
+                        "current_border_width": 0, "layout": "splith", "percent": null,
+                        "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                        "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "urgent": false, "sticky": false, "marks": [], "focused": false,
+                        "focus": [6], "nodes": [],
+                        "floating_nodes": [
+                            {
+                                "id": 6, "type": "floating_con", "name": "Music",
+                                "border": "normal", "current_border_width": 2,
+                                "layout": "none", "orientation": "none", "percent": null,
+                                "rect": {"x": 480, "y": 270, "width": 960, "height": 540},
+                                "window_rect": {"x": 2, "y": 0, "width": 956, "height": 538},
+                                "deco_rect": {"x": 0, "y": 0, "width": 960, "height": 22},
+                                "geometry": {"x": 0, "y": 0, "width": 800, "height": 600},
+                                "urgent": false, "sticky": false, "marks": [], "focused": false,
+                                "focus": [], "nodes": [], "floating_nodes": [],
+                                "fullscreen_mode": 0, "pid": 4242, "app_id": "spotify",
+                                "visible": false, "shell": "xdg_shell", "inhibit_idle": false,
+                                "idle_inhibitors": {"user": "none", "application": "none"}
+                            }
+                        ]
+                    }
+                ]
+            },
+            {
+                "id": 3, "type": "output", "name": "eDP-1", "orientation": "none",
+                "border": "none", "current_border_width": 0, "layout": "output",
+                "percent": 1.0, "active": true, "primary": false, "make": "Unknown",
+                "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                "urgent": false, "sticky": false, "marks": [], "focused": false,
+                "focus": [5], "floating_nodes": [],
+                "nodes": [
+                    {
+                        "id": 5, "type": "workspace", "name": "1", "num": 1,
+                        "orientation": "horizontal", "border": "none",
+                        "current_border_width": 0, "layout": "splith", "percent": null,
+                        "representation": "H[firefox]", "output": "eDP-1",
+                        "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                        "window_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "geometry": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        "urgent": false, "sticky": false, "marks": [], "focused": false,
+                        "focus": [7], "floating_nodes": [],
+                        "nodes": [
+                            {
+                                "id": 7, "type": "con", "name": "Mozilla Firefox",
+                                "border": "none", "current_border_width": 0,
+                                "layout": "none", "orientation": "none", "percent": 1.0,
+                                "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                                "window_rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                                "deco_rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+                                "geometry": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+                                "urgent": false, "sticky": false, "marks": [], "focused": true,
+                                "focus": [], "nodes": [], "floating_nodes": [],
+                                "fullscreen_mode": 0, "pid": 1234, "app_id": "firefox",
+                                "visible": true, "shell": "xdg_shell", "inhibit_idle": false,
+                                "idle_inhibitors": {"user": "none", "application": "none"}
+                            }
+                        ]
+                    }
+                ]
+            }
+        ]
+    }"#;
+
+    fn titles(tree: &str) -> Vec<String> {
+        let tree: Node = serde_json::from_str(tree).unwrap();
+        scratchpad_windows(&tree)
+            .iter()
+            .map(|w| w.name.clone().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn i3_tree() {
+        assert_eq!(titles(I3_TREE), ["htop", "Notes - vim"]);
+    }
+
+    #[test]
+    fn sway_tree() {
+        assert_eq!(titles(SWAY_TREE), ["Music"]);
+    }
+
+    #[test]
+    fn no_scratchpad() {
+        // The visible windows are not counted
+        let mut tree: Node = serde_json::from_str(SWAY_TREE).unwrap();
+        tree.nodes.remove(0);
+        assert!(scratchpad_windows(&tree).is_empty());
+    }
+
+    #[test]
+    fn empty_container() {
+        let mut tree: Node = serde_json::from_str(I3_TREE).unwrap();
+        // Close the window of the split, leaving the split itself
+        let scratchpad = &mut tree.nodes[0].nodes[0].nodes[0];
+        scratchpad.floating_nodes[1].nodes[0].nodes.clear();
+        let windows: Vec<_> = scratchpad_windows(&tree)
+            .iter()
+            .map(|w| w.name.clone().unwrap_or_default())
+            .collect();
+        assert_eq!(windows, ["htop"]);
+    }
+}
@@ -0,0 +1,390 @@
+//! Daily screen time summary
+//!
+//! Shows how long the computer has been actively used today, as reported by
+//! [ActivityWatch](https://activitywatch.net/) or [Timewarrior](https://timewarrior.net/).
+//!
+//! The `activitywatch` driver queries a local `aw-server`. It counts the time recorded by the window
+//! watcher while the AFK watcher reports the user as active (if the AFK watcher is running) and
+//! additionally reports the application used the most. The `timewarrior` driver runs
+//! `timew summary :day` and only reports the total tracked time.
+//!
+//! If the respective daemon is not running (or `timew` is not installed) the block is hidden until
+//! it becomes available.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `driver` | One of `"activitywatch"` or `"timewarrior"` | `"activitywatch"`
+//! `url` | URL of the aw-server (`activitywatch` only) | `"http://localhost:5600"`
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $total{ ($remaining left)\|} "`
+//! `interval` | Update interval in seconds | `300`
+//! `goal` | Daily screen time limit. Either a number of minutes or a string with a unit, e.g. `"4h"` or `"90min"` | `None`
+//!
+//! Placeholder    | Value                                                  | Type | Unit
+//! ---------------|--------------------------------------------------------|------|-----
+//! `icon`         | A static icon                                          | Icon | -
+//! `total`        | Active time today, e.g. `2h 05m`                       | Text | -
+//! `remaining`    | Time left until `goal`. Absent if `goal` is not set    | Text | -
+//! `top_app`      | The application used the most today (`activitywatch`)  | Text | -
+//! `top_app_time` | Time spent in `top_app`                                | Text | -
+//!
+//! If `goal` is set, the block's state is set to warning when less than 10% of the goal is left
+//! and to critical once the goal is exceeded.
+//!
+//! Action   | Default button
+//! ---------|---------------
+//! `update` | Left
+//!
+//! # Examples
+//!
+//! ```toml
+//! [[block]]
+//! block = "screen_time"
+//! goal = "4h"
+//! format = " $icon $total{ - $top_app|} "
+//! ```
+//!
+//! ```toml
+//! [[block]]
+//! block = "screen_time"
+//! driver = "timewarrior"
+//! interval = 60
+//! ```
+//!
+//! # Icons Used
+//! - `time`
+
+use super::prelude::*;
+use crate::util::format_duration;
+use chrono::Local;
+use serde_json::json;
+
+const DEFAULT_URL: &str = "http://localhost:5600";
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    driver: Driver,
+    url: Option<String>,
+    format: FormatConfig,
+    #[default(300.into())]
+    interval: Seconds,
+    goal: Option<Threshold>,
+}
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(rename_all = "lowercase")]
+enum Driver {
+    #[default]
+    ActivityWatch,
+    TimeWarrior,
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[(MouseButton::Left, None, "update")])
+        .await?;
+
+    let mut widget = Widget::new().with_format(
+        config
+            .format
+            .with_default(" $icon $total{ ($remaining left)|} ")?,
+    );
+
+    let goal = match config.goal {
+        None => None,
+        Some(Threshold::Number(minutes)) => Some(minutes * 60.),
+        Some(Threshold::Seconds(seconds)) => Some(seconds),
+        Some(_) => return Err(Error::new("`goal` must be a duration, e.g. \"4h\"")),
+    };
+
+    let source: Box<dyn ScreenTimeSource + Send + Sync> = match config.driver {
+        Driver::ActivityWatch => Box::new(ActivityWatch {
+            url: config
+                .url
+                .as_deref()
+                .unwrap_or(DEFAULT_URL)
+                .trim_end_matches('/')
+                .into(),
+        }),
+        Driver::TimeWarrior => Box::new(TimeWarrior {
+            commands: api.command_context.clone(),
+        }),
+    };
+
+    loop {
+        match api.recoverable(|| source.summary()).await? {
+            None => api.hide().await?,
+            Some(summary) => {
+                widget.state = match goal {
+                    Some(goal) if summary.total > goal => State::Critical,
+                    Some(goal) if summary.total > goal * 0.9 => State::Warning,
+                    _ => State::Idle,
+                };
+                widget.set_values(map! {
+                    "icon" => Value::icon(api.get_icon("time")?),
+                    "total" => Value::text(format_duration(summary.total, &api.shared_config.localization)),
+                    [if let Some(goal) = goal]
+                        "remaining" => Value::text(format_duration(goal - summary.total, &api.shared_config.localization)),
+                    [if let Some((app, _)) = &summary.top_app] "top_app" => Value::text(app.clone()),
+                    [if let Some((_, time)) = summary.top_app]
+                        "top_app_time" => Value::text(format_duration(time, &api.shared_config.localization)),
+                });
+                api.set_widget(&widget).await?;
+            }
+        }
+
+        select! {
+            _ = sleep(config.interval.0) => (),
+            // Both the `update` action and update requests refresh the block
+            _ = api.event() => (),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Summary {
+    /// Active time today in seconds
+    total: f64,
+    /// The most used application and the time spent in it in seconds
+    top_app: Option<(String, f64)>,
+}
+
+#[async_trait]
+trait ScreenTimeSource {
+    /// Returns `None` if the daemon is not available
+    async fn summary(&self) -> Result<Option<Summary>>;
+}
+
+struct ActivityWatch {
+    url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Bucket {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Event {
+    /// Seconds
+    duration: f64,
+    data: EventData,
+}
+
+#[derive(Deserialize, Debug)]
+struct EventData {
+    app: String,
+}
+
+#[async_trait]
+impl ScreenTimeSource for ActivityWatch {
+    async fn summary(&self) -> Result<Option<Summary>> {
+        let buckets = match REQWEST_CLIENT
+            .get(format!("{}/api/0/buckets/", self.url))
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) if e.is_connect() => return Ok(None),
+            Err(e) => return Err(e).error("Failed to request the list of buckets"),
+        };
+        let buckets: HashMap<String, Bucket> = buckets
+            .json()
+            .await
+            .error("Failed to parse the list of buckets")?;
+        let query = match build_query(&buckets) {
+            Some(query) => query,
+            // The window watcher has not reported anything yet
+            None => return Ok(Some(Summary::default())),
+        };
+
+        let midnight = Local::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+            .error("Failed to get the start of the day")?;
+        let period = format!(
+            "{}/{}",
+            midnight.to_rfc3339(),
+            (midnight + chrono::Duration::days(1)).to_rfc3339()
+        );
+
+        let events: Vec<Vec<Event>> = REQWEST_CLIENT
+            .post(format!("{}/api/0/query/", self.url))
+            .json(&json!({ "timeperiods": [period], "query": query }))
+            .send()
+            .await
+            .error("Failed to query aw-server")?
+            .json()
+            .await
+            .error("Failed to parse the query result")?;
+        Ok(Some(summarize_events(
+            events.into_iter().next().unwrap_or_default(),
+        )))
+    }
+}
+
+/// Build an ActivityWatch query which returns today's window events merged by application and
+/// sorted by duration. Returns `None` if there is no window watcher bucket.
+fn build_query(buckets: &HashMap<String, Bucket>) -> Option<Vec<String>> {
+    let find_bucket = |kind: &str| {
+        let mut ids: Vec<&str> = buckets
+            .iter()
+            .filter(|(_, bucket)| bucket.kind == kind)
+            .map(|(id, _)| id.as_str())
+            .collect();
+        ids.sort_unstable();
+        ids.first().copied()
+    };
+
+    let mut query = vec![format!(
+        "events = flood(query_bucket({:?}));",
+        find_bucket("currentwindow")?
+    )];
+    if let Some(afk) = find_bucket("afkstatus") {
+        query.push(format!("not_afk = flood(query_bucket({afk:?}));"));
+        query.push(r#"not_afk = filter_keyvals(not_afk, "status", ["not-afk"]);"#.into());
+        query.push("events = filter_period_intersect(events, not_afk);".into());
+    }
+    query.push(r#"events = merge_events_by_keys(events, ["app"]);"#.into());
+    query.push("RETURN = sort_by_duration(events);".into());
+    Some(query)
+}
+
+fn summarize_events(events: Vec<Event>) -> Summary {
+    let total = events.iter().map(|e| e.duration).sum();
+    let top_app = events
+        .into_iter()
+        .max_by(|a, b| a.duration.total_cmp(&b.duration))
+        .map(|e| (e.data.app, e.duration));
+    Summary { total, top_app }
+}
+
+struct TimeWarrior {
+    commands: CommandContext,
+}
+
+#[async_trait]
+impl ScreenTimeSource for TimeWarrior {
+    async fn summary(&self) -> Result<Option<Summary>> {
+        let output = match self
+            .commands
+            .command("timew")
+            .args(["summary", ":day"])
+            .output()
+            .await
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).error("Failed to run timew"),
+        };
+        if !output.status.success() {
+            return Err(Error::new(format!(
+                "timew exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let output = String::from_utf8(output.stdout).error("timew produced non-UTF8 output")?;
+        parse_timew_summary(&output).map(|total| {
+            Some(Summary {
+                total,
+                top_app: None,
+            })
+        })
+    }
+}
+
+/// Parse the total (in seconds) from the output of `timew summary`
+fn parse_timew_summary(output: &str) -> Result<f64> {
+    if output.trim_start().starts_with("No filtered data found") {
+        return Ok(0.);
+    }
+    // The total is the last non-empty line, e.g. "  1:45:00"
+    let total = output
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .error("timew summary is empty")?;
+    let mut seconds = 0;
+    for part in total.split(':') {
+        seconds = seconds * 60
+            + part
+                .parse::<u64>()
+                .or_error(|| format!("Failed to parse timew total '{total}'"))?;
+    }
+    Ok(seconds as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aw_query() {
+        let buckets: HashMap<String, Bucket> = serde_json::from_str(
+            r#"{
+                "aw-watcher-window_laptop": {"id": "aw-watcher-window_laptop", "type": "currentwindow", "client": "aw-watcher-window", "hostname": "laptop"},
+                "aw-watcher-afk_laptop": {"id": "aw-watcher-afk_laptop", "type": "afkstatus", "client": "aw-watcher-afk", "hostname": "laptop"},
+                "aw-watcher-web-firefox": {"id": "aw-watcher-web-firefox", "type": "web.tab.current", "client": "aw-client-web", "hostname": "unknown"}
+            }"#,
+        )
+        .unwrap();
+        let query = build_query(&buckets).unwrap();
+        assert_eq!(
+            query[0],
+            r#"events = flood(query_bucket("aw-watcher-window_laptop"));"#
+        );
+        assert_eq!(
+            query[1],
+            r#"not_afk = flood(query_bucket("aw-watcher-afk_laptop"));"#
+        );
+        assert_eq!(query.last().unwrap(), "RETURN = sort_by_duration(events);");
+
+        let buckets: HashMap<String, Bucket> =
+            serde_json::from_str(r#"{"aw-watcher-window_laptop": {"type": "currentwindow"}}"#)
+                .unwrap();
+        let query = build_query(&buckets).unwrap();
+        assert!(!query.iter().any(|line| line.contains("afk")));
+
+        assert!(build_query(&HashMap::new()).is_none());
+    }
+
+    #[test]
+    fn aw_events() {
+        let events: Vec<Vec<Event>> = serde_json::from_str(
+            r#"[[
+                {"id": null, "timestamp": "2022-11-07T08:00:00+00:00", "duration": 5400.5, "data": {"app": "firefox"}},
+                {"id": null, "timestamp": "2022-11-07T09:00:00+00:00", "duration": 1800.0, "data": {"app": "Alacritty"}}
+            ]]"#,
+        )
+        .unwrap();
+        let summary = summarize_events(events.into_iter().next().unwrap());
+        assert_eq!(summary.total, 7200.5);
+        assert_eq!(summary.top_app, Some(("firefox".into(), 5400.5)));
+
+        assert_eq!(summarize_events(Vec::new()), Summary::default());
+    }
+
+    #[test]
+    fn timew_summary() {
+        let output = "
+Wk  Date       Day Tags     Start      End    Time   Total
+W45 2022-11-07 Mon foo    9:00:00 10:00:00 1:00:00
+                   bar   10:30:00 11:15:00 0:45:00 1:45:00
+
+                                                   1:45:00
+
+";
+        assert_eq!(parse_timew_summary(output).unwrap(), 6300.);
+
+        let output =
+            "\nNo filtered data found in the range 2022-11-07T00:00:00 - 2022-11-08T00:00:00.\n";
+        assert_eq!(parse_timew_summary(output).unwrap(), 0.);
+
+        assert!(parse_timew_summary("").is_err());
+    }
+}
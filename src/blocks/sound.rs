@@ -19,7 +19,7 @@
 //! ```toml
 //! [[block]]
 //! block = "sound"
-//! format = " $icon $output_description{ $volume|} "
+//! format = " $icon ${output_description|output_name}{ $volume|} "
 //! ```
 //!
 //! ```toml
@@ -41,34 +41,53 @@
 //! device_kind = "source"
 //! ```
 //!
+//! The same works with ALSA, which then uses the capture volume and switch of the `Capture`
+//! control:
+//!
+//! ```toml
+//! [[block]]
+//! block = "sound"
+//! driver = "alsa"
+//! device_kind = "source"
+//! ```
+//!
 //! # Configuration
 //!
 //! Key | Values | Default
 //! ----|--------|--------
 //! `driver` | `"auto"`, `"pulseaudio"`, `"alsa"`. | `"auto"` (Pulseaudio with ALSA fallback)
 //! `format` | A string to customise the output of this block. See below for available placeholders. | <code> $icon {$volume.eng(w:2) &vert;}</code>
-//! `name` | PulseAudio device name, or the ALSA control name as found in the output of `amixer -D yourdevice scontrols`. | PulseAudio: `@DEFAULT_SINK@` / ALSA: `Master`
+//! `name` | PulseAudio device name, or the ALSA control name as found in the output of `amixer -D yourdevice scontrols`. | PulseAudio: `@DEFAULT_SINK@` / ALSA: `Master`, or `Capture` for sources
 //! `device` | ALSA device name, usually in the form "hw:X" or "hw:X,Y" where `X` is the card number and `Y` is the device number as found in the output of `aplay -l`. | `default`
-//! `device_kind` | PulseAudio device kind: `source` or `sink`. | `"sink"`
+//! `device_kind` | Device kind: `source` or `sink`. With ALSA, this selects the capture or playback part of the control. | `"sink"`
 //! `natural_mapping` | When using the ALSA driver, display the "mapped volume" as given by `alsamixer`/`amixer -M`, which represents the volume level more naturally with respect for the human ear. | `false`
 //! `step_width` | The percent volume level is increased/decreased for the selected audio device when scrolling. Capped automatically at 50. | `5`
-//! `max_vol` | Max volume in percent that can be set via scrolling. Note it can still be set above this value if changed by another application. | `None`
+//! `max_vol` | Max volume in percent that can be set via scrolling. Note it can still be set above this value if changed by another application, unless `cap_external` is set. | `None`
+//! `cap_external` | Also lower the volume to `max_vol` when another application sets it higher. | `false`
 //! `show_volume_when_muted` | Show the volume even if it is currently muted. | `false`
 //! `headphones_indicator` | Change icon when headphones are plugged in (pulseaudio only) | `false`
 //! `mappings` | Map `output_name` to custom name. | `None`
+//! `device_cycle` | PulseAudio device names to switch between with `cycle_device`, in this order. Devices which are not available are skipped. | All devices of `device_kind`, sorted by name
 //!
 //! Placeholder          | Value                             | Type   | Unit
 //! ---------------------|-----------------------------------|--------|---------------
 //! `icon`               | Icon based on volume              | Icon   | -
 //! `volume`             | Current volume. Missing if muted. | Number | %
 //! `output_name`        | PulseAudio or ALSA device name    | Text   | -
-//! `output_description` | PulseAudio device description, will fallback to `output_name` if no description is available and will be overwritten by mappings (mappings will still use `output_name`) | Text | -
+//! `output_description` | PulseAudio device description or ALSA card and control name. Missing if the device has no description, use `${output_description|output_name}` to show the name then. If the format uses `$output_description` outside of such a fallback chain, it falls back to `output_name` as before. | Text | -
+//!
+//! The block's state is set to warning if the device is muted, or if the last request to PulseAudio
+//! failed (e.g. timed out).
+//!
+//! Action         | Default button
+//! ---------------|---------------
+//! `toggle_mute`  | Rigth
+//! `volume_up`    | Wheel Up
+//! `volume_down`  | Wheel Down
+//! `cycle_device` | Middle
 //!
-//! Action        | Default button
-//! --------------|---------------
-//! `toggle_mute` | Rigth
-//! `volume_up`   | Wheel Up
-//! `volume_down` | Wheel Down
+//! `cycle_device` makes the next device the default one (pulseaudio only). The block follows the
+//! default device unless `name` is set.
 //!
 //! #  Icons Used
 //!
@@ -103,18 +122,52 @@ pub struct Config {
     show_volume_when_muted: bool,
     mappings: Option<HashMap<String, String>>,
     max_vol: Option<u32>,
+    cap_external: bool,
+    device_cycle: Option<Vec<String>>,
+}
+
+impl Config {
+    /// The device controlled by the block, see [`BlockConfig::resource`](super::BlockConfig::resource)
+    pub fn resource(&self) -> String {
+        let alsa = || {
+            let name = self.name.as_deref().unwrap_or(match self.device_kind {
+                DeviceKind::Sink => "Master",
+                DeviceKind::Source => "Capture",
+            });
+            let device = self.device.as_deref().unwrap_or("default");
+            format!("alsa:{device}:{name}")
+        };
+        match self.driver {
+            SoundDriver::Alsa => alsa(),
+            #[cfg(feature = "pulseaudio")]
+            SoundDriver::PulseAudio | SoundDriver::Auto => {
+                let (kind, default) = match self.device_kind {
+                    DeviceKind::Sink => ("sink", "@DEFAULT_SINK@"),
+                    DeviceKind::Source => ("source", "@DEFAULT_SOURCE@"),
+                };
+                let name = self.name.as_deref().unwrap_or(default);
+                format!("pulse:{kind}:{name}")
+            }
+            #[cfg(not(feature = "pulseaudio"))]
+            SoundDriver::Auto => alsa(),
+        }
+    }
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.coalesce_actions();
     api.set_default_actions(&[
         (MouseButton::Right, None, "toggle_mute"),
         (MouseButton::WheelUp, None, "volume_up"),
         (MouseButton::WheelDown, None, "volume_down"),
+        (MouseButton::Middle, None, "cycle_device"),
     ])
     .await?;
 
-    let mut widget =
-        Widget::new().with_format(config.format.with_default(" $icon {$volume.eng(w:2)|} ")?);
+    let format = config.format.with_default(" $icon {$volume.eng(w:2)|} ")?;
+    // Formats written before fallback chains expect the description to fall back to the name
+    let describe_by_name = format.contains_plain_key("output_description");
+    let mut widget = Widget::new().with_format(format);
 
     let device_kind = config.device_kind;
     let step_width = config.step_width.clamp(0, 50) as i32;
@@ -158,9 +211,11 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     type DeviceType = Box<dyn SoundDevice>;
     let mut device: DeviceType = match config.driver {
         SoundDriver::Alsa => Box::new(alsa::Device::new(
-            config.name.clone().unwrap_or_else(|| "Master".into()),
-            config.device.unwrap_or_else(|| "default".into()),
+            config.name.clone(),
+            config.device.clone(),
+            config.device_kind,
             config.natural_mapping,
+            api.command_context.clone(),
         )?),
         #[cfg(feature = "pulseaudio")]
         SoundDriver::PulseAudio => {
@@ -172,22 +227,33 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                 Box::new(pulse)
             } else {
                 Box::new(alsa::Device::new(
-                    config.name.unwrap_or_else(|| "Master".into()),
-                    config.device.unwrap_or_else(|| "default".into()),
+                    config.name.clone(),
+                    config.device.clone(),
+                    config.device_kind,
                     config.natural_mapping,
+                    api.command_context.clone(),
                 )?)
             }
         }
         #[cfg(not(feature = "pulseaudio"))]
         SoundDriver::Auto => Box::new(alsa::Device::new(
-            config.name.clone().unwrap_or_else(|| "Master".into()),
-            config.device.unwrap_or_else(|| "default".into()),
+            config.name.clone(),
+            config.device.clone(),
+            config.device_kind,
             config.natural_mapping,
+            api.command_context.clone(),
         )?),
     };
 
+    let mut cap = Cap::default();
+
     loop {
         device.get_info().await?;
+        if let (true, Some(max_vol)) = (config.cap_external, config.max_vol) {
+            if cap.exceeded(device.volume(), max_vol) {
+                device.set_volume(0, Some(max_vol)).await?;
+            }
+        }
         let volume = device.volume();
 
         let mut output_name = device.output_name();
@@ -197,14 +263,15 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             }
         }
 
-        let output_description = device
-            .output_description()
-            .unwrap_or_else(|| output_name.clone());
+        let output_description = match device.output_description() {
+            None if describe_by_name => Some(output_name.clone()),
+            description => description,
+        };
 
         let mut values = map! {
             "volume" => Value::percents(volume),
             "output_name" => Value::text(output_name),
-            "output_description" => Value::text(output_description),
+            [if let Some(description) = output_description] "output_description" => Value::text(description),
         };
 
         if device.muted() {
@@ -221,7 +288,11 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                 "icon".into(),
                 Value::icon(api.get_icon(&icon(volume, &*device))?),
             );
-            widget.state = State::Idle;
+            widget.state = if device.request_error().is_some() {
+                State::Warning
+            } else {
+                State::Idle
+            };
         }
 
         widget.set_values(values);
@@ -239,10 +310,23 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                         device.toggle().await?;
                     }
                     Action(a) if a == "volume_up" => {
-                        device.set_volume(step_width, config.max_vol).await?;
+                        let steps = api.action_count as i32;
+                        device.set_volume(step_width * steps, config.max_vol).await?;
                     }
                     Action(a) if a == "volume_down" => {
-                        device.set_volume(-step_width, config.max_vol).await?;
+                        let steps = api.action_count as i32;
+                        device.set_volume(-step_width * steps, config.max_vol).await?;
+                    }
+                    Action(a) if a == "cycle_device" => {
+                        let names = device.device_names();
+                        let next = next_device(
+                            &device.output_name(),
+                            &names,
+                            config.device_cycle.as_deref(),
+                        );
+                        if let Some(next) = next {
+                            device.set_default_device(next).await?;
+                        }
                     }
                     _ => (),
                 }
@@ -251,6 +335,57 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     }
 }
 
+/// Whether `cap_external` lowered the volume
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Cap {
+    #[default]
+    Uncapped,
+    /// The volume was just lowered to `max_vol`
+    Lowered,
+    /// The volume which the device reported after it was lowered. Because of rounding, it may be
+    /// slightly above `max_vol`, which must not be capped again.
+    Settled(u32),
+}
+
+impl Cap {
+    /// Whether the volume has to be lowered to `max_vol`
+    fn exceeded(&mut self, volume: u32, max_vol: u32) -> bool {
+        *self = match *self {
+            Self::Settled(settled) if volume == settled => return false,
+            Self::Lowered if volume <= max_vol + 1 => Self::Settled(volume),
+            _ if volume > max_vol => Self::Lowered,
+            _ => Self::Uncapped,
+        };
+        *self == Self::Lowered
+    }
+}
+
+/// The device after `current` in `cycle`, or in `available` sorted by name if there is no
+/// `cycle`. Devices which are not in `available` are skipped.
+fn next_device<'a>(
+    current: &str,
+    available: &'a [String],
+    cycle: Option<&'a [String]>,
+) -> Option<&'a str> {
+    let candidates: Vec<&str> = match cycle {
+        Some(cycle) => cycle
+            .iter()
+            .filter(|name| available.contains(name))
+            .map(String::as_str)
+            .collect(),
+        None => {
+            let mut names: Vec<&str> = available.iter().map(String::as_str).collect();
+            names.sort_unstable();
+            names
+        }
+    };
+    let next = match candidates.iter().position(|name| *name == current) {
+        Some(i) => candidates[(i + 1) % candidates.len()],
+        None => *candidates.first()?,
+    };
+    (next != current).then_some(next)
+}
+
 #[derive(Deserialize, Debug, SmartDefault, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 enum SoundDriver {
@@ -287,9 +422,61 @@ trait SoundDevice {
     fn output_description(&self) -> Option<String>;
     fn active_port(&self) -> Option<&str>;
     fn form_factor(&self) -> Option<&str>;
+    /// The error of the last request which failed without breaking the device, e.g. a timeout
+    fn request_error(&self) -> Option<&str> {
+        None
+    }
+
+    /// The names of the devices which can be made the default one, in any order
+    fn device_names(&self) -> Vec<String> {
+        Vec::new()
+    }
 
     async fn get_info(&mut self) -> Result<()>;
     async fn set_volume(&mut self, step: i32, max_vol: Option<u32>) -> Result<()>;
     async fn toggle(&mut self) -> Result<()>;
+    async fn set_default_device(&mut self, name: &str) -> Result<()>;
     async fn wait_for_update(&mut self) -> Result<()>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap() {
+        let mut cap = Cap::default();
+        assert!(!cap.exceeded(100, 100));
+        assert!(cap.exceeded(101, 100));
+
+        let mut cap = Cap::default();
+        assert!(cap.exceeded(130, 100));
+        // The volume set by the cap is reported with a rounding error, on every update until it
+        // changes
+        assert!(!cap.exceeded(101, 100));
+        assert!(!cap.exceeded(101, 100));
+        assert!(!cap.exceeded(101, 100));
+        // Another application raised the volume again
+        assert!(cap.exceeded(130, 100));
+        assert!(!cap.exceeded(101, 100));
+
+        // Once the volume was changed otherwise, there is no tolerance
+        assert!(!cap.exceeded(90, 100));
+        assert!(cap.exceeded(101, 100));
+    }
+
+    #[test]
+    fn cycle() {
+        let available = ["hdmi".to_string(), "analog".into(), "usb".into()];
+        assert_eq!(next_device("analog", &available, None), Some("hdmi"));
+        assert_eq!(next_device("usb", &available, None), Some("analog"));
+        assert_eq!(next_device("gone", &available, None), Some("analog"));
+        assert_eq!(next_device("analog", &available[1..2], None), None);
+        assert_eq!(next_device("analog", &[], None), None);
+
+        let cycle = ["usb".to_string(), "bluetooth".into(), "analog".into()];
+        assert_eq!(next_device("usb", &available, Some(&cycle)), Some("analog"));
+        assert_eq!(next_device("analog", &available, Some(&cycle)), Some("usb"));
+        assert_eq!(next_device("hdmi", &available, Some(&cycle)), Some("usb"));
+    }
+}
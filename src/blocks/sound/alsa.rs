@@ -1,36 +1,129 @@
 use std::cmp::{max, min};
 use std::process::Stdio;
-use tokio::process::{ChildStdout, Command};
+use tokio::process::ChildStdout;
 
 use super::super::prelude::*;
-use super::SoundDevice;
+use super::{DeviceKind, SoundDevice};
 
 pub(super) struct Device {
     name: String,
     device: String,
+    device_kind: DeviceKind,
     natural_mapping: bool,
+    commands: CommandContext,
     volume: u32,
     muted: bool,
+    /// The card's name, `None` until it was queried
+    card_name: Option<Option<String>>,
     monitor: ChildStdout,
 }
 
 impl Device {
-    pub(super) fn new(name: String, device: String, natural_mapping: bool) -> Result<Self> {
+    pub(super) fn new(
+        name: Option<String>,
+        device: Option<String>,
+        device_kind: DeviceKind,
+        natural_mapping: bool,
+        commands: CommandContext,
+    ) -> Result<Self> {
+        let monitor = commands
+            .command("alsactl")
+            .arg("monitor")
+            .stdout(Stdio::piped())
+            .spawn()
+            .error("Failed to start alsactl monitor")?
+            .stdout
+            .error("Failed to pipe alsactl monitor output")?;
         Ok(Device {
-            name,
-            device,
+            name: name.unwrap_or_else(|| match device_kind {
+                DeviceKind::Sink => "Master".into(),
+                DeviceKind::Source => "Capture".into(),
+            }),
+            device: device.unwrap_or_else(|| "default".into()),
+            device_kind,
             natural_mapping,
+            commands,
             volume: 0,
             muted: false,
-            monitor: Command::new("alsactl")
-                .arg("monitor")
-                .stdout(Stdio::piped())
-                .spawn()
-                .error("Failed to start alsactl monitor")?
-                .stdout
-                .error("Failed to pipe alsactl monitor output")?,
+            card_name: None,
+            monitor,
         })
     }
+
+    /// Run `amixer` on this device with `args` appended
+    async fn amixer(&self, args: &[&str]) -> Result<String> {
+        let mut command = self.commands.command("amixer");
+        if self.natural_mapping {
+            command.arg("-M");
+        }
+        let output = command
+            .args(["-D", &self.device])
+            .args(args)
+            .output()
+            .await
+            .error("Failed to run amixer")?;
+        String::from_utf8(output.stdout).error("amixer produced non-UTF8 output")
+    }
+
+    /// The direction of the mixer control, which restricts `amixer set` to it
+    fn direction(&self) -> &'static str {
+        match self.device_kind {
+            DeviceKind::Sink => "playback",
+            DeviceKind::Source => "capture",
+        }
+    }
+}
+
+/// Parse the volume and mute state of the playback or capture part of a control from the output
+/// of `amixer get`. Lines of the channels look like
+/// `  Front Left: Playback 65536 [100%] [0.00dB] [on] Capture 0 [0%] [off]`, where the `[on]` or
+/// `[off]` field is missing if the control has no switch.
+fn parse_control(output: &str, device_kind: DeviceKind) -> Result<(u32, bool)> {
+    let direction = match device_kind {
+        DeviceKind::Sink => "Playback",
+        DeviceKind::Source => "Capture",
+    };
+    let mut volumes = Vec::new();
+    let mut switches = Vec::new();
+    for (_channel, fields) in output.lines().filter_map(|l| l.split_once(':')) {
+        let mut in_direction = false;
+        for field in fields.split_whitespace() {
+            match field {
+                "Playback" | "Capture" => in_direction = field == direction,
+                _ if !in_direction || !field.starts_with('[') => (),
+                "[on]" => switches.push(true),
+                "[off]" => switches.push(false),
+                _ => {
+                    if let Some(percents) = field[1..].strip_suffix("%]") {
+                        volumes.push(percents.parse().error("could not parse volume")?);
+                    }
+                }
+            }
+        }
+    }
+    if volumes.is_empty() {
+        return Err(Error::new(format!(
+            "control has no {} volume",
+            direction.to_lowercase()
+        )));
+    }
+    let volume = (volumes.iter().sum::<u32>() as f64 / volumes.len() as f64).round() as u32;
+    // Controls without a switch can't be muted
+    let muted = !switches.is_empty() && switches.iter().all(|on| !on);
+    Ok((volume, muted))
+}
+
+/// Parse the card's name from the output of `amixer info`, whose first line looks like
+/// `Card default 'PCH'/'HDA Intel PCH at 0xf7f10000 irq 32'`
+fn parse_card_name(output: &str) -> Option<String> {
+    let line = output.lines().next()?.strip_prefix("Card ")?;
+    let (_, long_name) = line.split_once("'/'")?;
+    let long_name = long_name.strip_suffix('\'')?;
+    let name = match long_name.find(" at ") {
+        Some(i) => &long_name[..i],
+        None => long_name,
+    };
+    Some(name.trim().to_string()).filter(|n| !n.is_empty())
 }
 
 #[async_trait::async_trait]
@@ -48,8 +141,8 @@ impl SoundDevice for Device {
     }
 
     fn output_description(&self) -> Option<String> {
-        // TODO Does Alsa has something similar like descripitons in Pulse?
-        None
+        let card_name = self.card_name.as_ref()?.as_ref()?;
+        Some(format!("{card_name} {}", self.name))
     }
 
     fn active_port(&self) -> Option<&str> {
@@ -61,34 +154,14 @@ impl SoundDevice for Device {
     }
 
     async fn get_info(&mut self) -> Result<()> {
-        let mut args = Vec::new();
-        if self.natural_mapping {
-            args.push("-M");
-        };
-        args.extend(["-D", &self.device, "get", &self.name]);
-
-        let output: String = Command::new("amixer")
-            .args(&args)
-            .output()
-            .await
-            .map(|o| std::str::from_utf8(&o.stdout).unwrap().trim().into())
-            .error("could not run amixer to get sound info")?;
-
-        let last_line = &output.lines().last().error("could not get sound info")?;
+        if self.card_name.is_none() {
+            let info = self.amixer(&["info"]).await?;
+            self.card_name = Some(parse_card_name(&info));
+        }
 
-        const FILTER: &[char] = &['[', ']', '%'];
-        let mut last = last_line
-            .split_whitespace()
-            .filter(|x| x.starts_with('[') && !x.contains("dB"))
-            .map(|s| s.trim_matches(FILTER));
-
-        self.volume = last
-            .next()
-            .error("could not get volume")?
-            .parse::<u32>()
-            .error("could not parse volume to u32")?;
-
-        self.muted = last.next().map(|muted| muted == "off").unwrap_or(false);
+        let output = self.amixer(&["get", &self.name]).await?;
+        (self.volume, self.muted) = parse_control(&output, self.device_kind)
+            .or_error(|| format!("could not get sound info of '{}'", self.name))?;
 
         Ok(())
     }
@@ -100,16 +173,8 @@ impl SoundDevice for Device {
         } else {
             new_vol
         };
-        let mut args = Vec::new();
-        if self.natural_mapping {
-            args.push("-M");
-        };
         let vol_str = format!("{capped_volume}%");
-        args.extend(["-D", &self.device, "set", &self.name, &vol_str]);
-
-        Command::new("amixer")
-            .args(&args)
-            .output()
+        self.amixer(&["set", &self.name, &vol_str, self.direction()])
             .await
             .error("failed to set volume")?;
 
@@ -119,15 +184,12 @@ impl SoundDevice for Device {
     }
 
     async fn toggle(&mut self) -> Result<()> {
-        let mut args = Vec::new();
-        if self.natural_mapping {
-            args.push("-M");
+        let switch = match (self.device_kind, self.muted) {
+            (DeviceKind::Sink, _) => "toggle",
+            (DeviceKind::Source, true) => "cap",
+            (DeviceKind::Source, false) => "nocap",
         };
-        args.extend(["-D", &self.device, "set", &self.name, "toggle"]);
-
-        Command::new("amixer")
-            .args(&args)
-            .output()
+        self.amixer(&["set", &self.name, switch, self.direction()])
             .await
             .error("failed to toggle mute")?;
 
@@ -136,6 +198,11 @@ impl SoundDevice for Device {
         Ok(())
     }
 
+    async fn set_default_device(&mut self, _name: &str) -> Result<()> {
+        // ALSA has no default device which could be changed at runtime
+        Ok(())
+    }
+
     async fn wait_for_update(&mut self) -> Result<()> {
         let mut buf = [0u8; 1024];
         self.monitor
@@ -145,3 +212,60 @@ impl SoundDevice for Device {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control() {
+        let master = "Simple mixer control 'Master',0
+  Capabilities: pvolume pvolume-joined pswitch pswitch-joined
+  Playback channels: Mono
+  Limits: Playback 0 - 87
+  Mono: Playback 70 [80%] [-13.00dB] [off]
+";
+        assert_eq!(parse_control(master, DeviceKind::Sink).unwrap(), (80, true));
+        assert!(parse_control(master, DeviceKind::Source).is_err());
+
+        // No switch
+        let pcm = "Simple mixer control 'PCM',0
+  Capabilities: pvolume
+  Playback channels: Front Left - Front Right
+  Limits: Playback 0 - 255
+  Front Left: Playback 255 [100%] [0.00dB]
+  Front Right: Playback 204 [80%] [-5.00dB]
+";
+        assert_eq!(parse_control(pcm, DeviceKind::Sink).unwrap(), (90, false));
+
+        let capture = "Simple mixer control 'Capture',0
+  Capabilities: pvolume cvolume pswitch cswitch
+  Playback channels: Front Left - Front Right
+  Capture channels: Front Left - Front Right
+  Limits: Playback 0 - 63 Capture 0 - 63
+  Front Left: Playback 63 [100%] [0.00dB] [on] Capture 25 [40%] [-inf] [off]
+  Front Right: Playback 63 [100%] [0.00dB] [on] Capture 25 [40%] [-inf] [off]
+";
+        assert_eq!(
+            parse_control(capture, DeviceKind::Sink).unwrap(),
+            (100, false)
+        );
+        assert_eq!(
+            parse_control(capture, DeviceKind::Source).unwrap(),
+            (40, true)
+        );
+    }
+
+    #[test]
+    fn card_name() {
+        let info = "Card default 'PCH'/'HDA Intel PCH at 0xf7f10000 irq 32'
+  Mixer name\t: 'Realtek ALC3246'
+";
+        assert_eq!(parse_card_name(info).as_deref(), Some("HDA Intel PCH"));
+        assert_eq!(
+            parse_card_name("Card hw:1 'Pebbles'/'JBL Pebbles'\n").as_deref(),
+            Some("JBL Pebbles")
+        );
+        assert_eq!(parse_card_name(""), None);
+    }
+}
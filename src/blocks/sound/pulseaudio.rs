@@ -5,7 +5,9 @@ use libpulse_binding::context::{
     State as PulseState,
 };
 use libpulse_binding::mainloop::standard::{IterateResult, Mainloop};
+use libpulse_binding::operation::{Operation, State as OperationState};
 use libpulse_binding::proplist::{properties, Proplist};
+use libpulse_binding::time::MicroSeconds;
 use libpulse_binding::volume::{ChannelVolumes, Volume};
 
 use crossbeam_channel::{unbounded, Sender};
@@ -14,6 +16,7 @@ use std::cmp::{max, min};
 use std::convert::{TryFrom, TryInto};
 use std::sync::Mutex;
 use std::thread;
+use std::time::Instant;
 
 use super::super::prelude::*;
 use super::{DeviceKind, SoundDevice};
@@ -21,6 +24,11 @@ use super::{DeviceKind, SoundDevice};
 static CLIENT: Lazy<Result<Client>> = Lazy::new(Client::new);
 static EVENT_LISTENER: Lazy<Mutex<Vec<tokio::sync::mpsc::Sender<()>>>> = Lazy::new(default);
 static DEVICES: Lazy<Mutex<HashMap<(DeviceKind, String), VolInfo>>> = Lazy::new(default);
+/// The error of the last request, if it failed
+static REQUEST_ERROR: Lazy<Mutex<Option<String>>> = Lazy::new(default);
+
+/// How long to wait for pulseaudio to complete a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
 
 // Default device names
 pub(super) static DEFAULT_SOURCE: Lazy<Mutex<String>> =
@@ -37,6 +45,7 @@ pub(super) struct Device {
     volume: Option<ChannelVolumes>,
     volume_avg: u32,
     muted: bool,
+    request_error: Option<String>,
     updates: tokio::sync::mpsc::Receiver<()>,
 }
 
@@ -106,8 +115,10 @@ enum ClientRequest {
     GetDefaultDevice,
     GetInfoByIndex(DeviceKind, u32),
     GetInfoByName(DeviceKind, String),
+    GetInfoList(DeviceKind),
     SetVolumeByName(DeviceKind, String, ChannelVolumes),
     SetMuteByName(DeviceKind, String, bool),
+    SetDefaultDevice(DeviceKind, String),
 }
 
 impl Connection {
@@ -159,6 +170,62 @@ impl Connection {
     }
 }
 
+impl MainloopDriver for Connection {
+    fn iterate_timeout(&mut self, timeout: Duration) -> Result<()> {
+        let timeout = MicroSeconds(timeout.as_micros().try_into().unwrap_or(u64::MAX))
+            .min(MicroSeconds(i32::MAX as u64));
+        self.mainloop
+            .prepare(Some(timeout))
+            .and_then(|()| self.mainloop.poll())
+            .and_then(|_| self.mainloop.dispatch())
+            .map(|_| ())
+            .map_err(|e| Error::new(format!("failed to iterate pulseaudio state: {e}")))
+    }
+}
+
+/// A main loop which can be iterated with a timeout
+trait MainloopDriver {
+    /// Wait at most `timeout` for events and dispatch them
+    fn iterate_timeout(&mut self, timeout: Duration) -> Result<()>;
+}
+
+/// An operation which completes while the main loop is iterated
+trait PendingOperation {
+    fn state(&self) -> OperationState;
+}
+
+impl<C: ?Sized> PendingOperation for Operation<C> {
+    fn state(&self) -> OperationState {
+        self.get_state()
+    }
+}
+
+/// Iterate the main loop until `operation` is done, so that its callback has run.
+///
+/// A slow server may need any number of iterations to reply, so this does not give up before
+/// `timeout` has elapsed.
+fn wait_for_operation(
+    driver: &mut impl MainloopDriver,
+    operation: &dyn PendingOperation,
+    timeout: Duration,
+) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match operation.state() {
+            OperationState::Done => return Ok(()),
+            OperationState::Cancelled => {
+                return Err(Error::new("pulseaudio request was cancelled"))
+            }
+            OperationState::Running => (),
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Error::new("pulseaudio request timed out"));
+        }
+        driver.iterate_timeout(deadline - now)?;
+    }
+}
+
 impl Client {
     fn new() -> Result<Client> {
         let (send_req, recv_req) = unbounded();
@@ -199,47 +266,77 @@ impl Client {
                             use ClientRequest::*;
                             let mut introspector = connection.context.introspect();
 
-                            match req {
-                                GetDefaultDevice => {
-                                    introspector.get_server_info(Client::server_info_callback);
-                                }
-                                GetInfoByIndex(DeviceKind::Sink, index) => {
+                            let operation: Box<dyn PendingOperation> = match req {
+                                GetDefaultDevice => Box::new(
+                                    introspector.get_server_info(Client::server_info_callback),
+                                ),
+                                GetInfoByIndex(DeviceKind::Sink, index) => Box::new(
                                     introspector
-                                        .get_sink_info_by_index(index, Client::sink_info_callback);
-                                }
+                                        .get_sink_info_by_index(index, Client::sink_info_callback),
+                                ),
                                 GetInfoByIndex(DeviceKind::Source, index) => {
-                                    introspector.get_source_info_by_index(
+                                    Box::new(introspector.get_source_info_by_index(
                                         index,
                                         Client::source_info_callback,
-                                    );
+                                    ))
                                 }
-                                GetInfoByName(DeviceKind::Sink, name) => {
+                                GetInfoByName(DeviceKind::Sink, name) => Box::new(
                                     introspector
-                                        .get_sink_info_by_name(&name, Client::sink_info_callback);
-                                }
+                                        .get_sink_info_by_name(&name, Client::sink_info_callback),
+                                ),
                                 GetInfoByName(DeviceKind::Source, name) => {
-                                    introspector.get_source_info_by_name(
+                                    Box::new(introspector.get_source_info_by_name(
                                         &name,
                                         Client::source_info_callback,
-                                    );
-                                }
-                                SetVolumeByName(DeviceKind::Sink, name, volumes) => {
-                                    introspector.set_sink_volume_by_name(&name, &volumes, None);
+                                    ))
                                 }
-                                SetVolumeByName(DeviceKind::Source, name, volumes) => {
-                                    introspector.set_source_volume_by_name(&name, &volumes, None);
+                                GetInfoList(device_kind) => {
+                                    // Forget devices which have been removed since the last list
+                                    DEVICES
+                                        .lock()
+                                        .unwrap()
+                                        .retain(|(kind, _), _| *kind != device_kind);
+                                    match device_kind {
+                                        DeviceKind::Sink => Box::new(
+                                            introspector
+                                                .get_sink_info_list(Client::sink_info_callback),
+                                        ),
+                                        DeviceKind::Source => Box::new(
+                                            introspector
+                                                .get_source_info_list(Client::source_info_callback),
+                                        ),
+                                    }
                                 }
+                                SetVolumeByName(DeviceKind::Sink, name, volumes) => Box::new(
+                                    introspector.set_sink_volume_by_name(&name, &volumes, None),
+                                ),
+                                SetVolumeByName(DeviceKind::Source, name, volumes) => Box::new(
+                                    introspector.set_source_volume_by_name(&name, &volumes, None),
+                                ),
                                 SetMuteByName(DeviceKind::Sink, name, mute) => {
-                                    introspector.set_sink_mute_by_name(&name, mute, None);
+                                    Box::new(introspector.set_sink_mute_by_name(&name, mute, None))
+                                }
+                                SetMuteByName(DeviceKind::Source, name, mute) => Box::new(
+                                    introspector.set_source_mute_by_name(&name, mute, None),
+                                ),
+                                SetDefaultDevice(DeviceKind::Sink, name) => {
+                                    Box::new(connection.context.set_default_sink(&name, |_| {}))
                                 }
-                                SetMuteByName(DeviceKind::Source, name, mute) => {
-                                    introspector.set_source_mute_by_name(&name, mute, None);
+                                SetDefaultDevice(DeviceKind::Source, name) => {
+                                    Box::new(connection.context.set_default_source(&name, |_| {}))
                                 }
                             };
 
-                            // send request and receive response
-                            connection.iterate(true).unwrap();
-                            connection.iterate(true).unwrap();
+                            // send request and wait until the callback has run
+                            let result =
+                                wait_for_operation(&mut connection, &*operation, REQUEST_TIMEOUT);
+                            let error = result.err().map(|e| e.to_string());
+                            let mut request_error = REQUEST_ERROR.lock().unwrap();
+                            if *request_error != error {
+                                *request_error = error;
+                                drop(request_error);
+                                Client::send_update_event();
+                            }
                         }
                     }
                 }
@@ -329,36 +426,37 @@ impl Client {
 
     fn subscribe_callback(
         facility: Option<Facility>,
-        _operation: Option<SubscribeOperation>,
+        operation: Option<SubscribeOperation>,
         index: u32,
     ) {
-        match facility {
-            None => {}
-            Some(facility) => match facility {
-                Facility::Server => {
-                    Client::send(ClientRequest::GetDefaultDevice).ok();
-                }
-                Facility::Sink => {
-                    Client::send(ClientRequest::GetInfoByIndex(DeviceKind::Sink, index)).ok();
-                }
-                Facility::Source => {
-                    Client::send(ClientRequest::GetInfoByIndex(DeviceKind::Source, index)).ok();
-                }
-                _ => {}
-            },
-        }
+        let device_kind = match facility {
+            Some(Facility::Server) => {
+                Client::send(ClientRequest::GetDefaultDevice).ok();
+                return;
+            }
+            Some(Facility::Sink) => DeviceKind::Sink,
+            Some(Facility::Source) => DeviceKind::Source,
+            _ => return,
+        };
+        // The info of a removed device can't be requested, so list the remaining ones instead
+        let request = match operation {
+            Some(SubscribeOperation::Removed) => ClientRequest::GetInfoList(device_kind),
+            _ => ClientRequest::GetInfoByIndex(device_kind, index),
+        };
+        Client::send(request).ok();
     }
 
     fn send_update_event() {
         for tx in &*EVENT_LISTENER.lock().unwrap() {
-            tx.blocking_send(()).unwrap();
+            // A full queue already means that the device will be refreshed
+            let _ = tx.try_send(());
         }
     }
 }
 
 impl Device {
     pub(super) fn new(device_kind: DeviceKind, name: Option<String>) -> Result<Self> {
-        let (tx, rx) = tokio::sync::mpsc::channel(32);
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
         EVENT_LISTENER.lock().unwrap().push(tx);
 
         Client::send(ClientRequest::GetDefaultDevice)?;
@@ -372,10 +470,12 @@ impl Device {
             volume: None,
             volume_avg: 0,
             muted: false,
+            request_error: None,
             updates: rx,
         };
 
         Client::send(ClientRequest::GetInfoByName(device_kind, device.name()))?;
+        Client::send(ClientRequest::GetInfoList(device_kind))?;
 
         Ok(device)
     }
@@ -418,6 +518,20 @@ impl SoundDevice for Device {
         self.active_port.as_deref()
     }
 
+    fn request_error(&self) -> Option<&str> {
+        self.request_error.as_deref()
+    }
+
+    fn device_names(&self) -> Vec<String> {
+        DEVICES
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|(kind, _)| *kind == self.device_kind)
+            .map(|(_, name)| name.clone())
+            .collect()
+    }
+
     async fn get_info(&mut self) -> Result<()> {
         let devices = DEVICES.lock().unwrap();
 
@@ -428,6 +542,7 @@ impl SoundDevice for Device {
             self.active_port = info.active_port.clone();
             self.form_factor = info.form_factor.clone();
         }
+        self.request_error = REQUEST_ERROR.lock().unwrap().clone();
 
         Ok(())
     }
@@ -473,6 +588,13 @@ impl SoundDevice for Device {
         Ok(())
     }
 
+    async fn set_default_device(&mut self, name: &str) -> Result<()> {
+        Client::send(ClientRequest::SetDefaultDevice(
+            self.device_kind,
+            name.into(),
+        ))
+    }
+
     async fn wait_for_update(&mut self) -> Result<()> {
         self.updates
             .recv()
@@ -480,3 +602,70 @@ impl SoundDevice for Device {
             .error("Failed to receive new update")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// An operation whose callback runs after the main loop was iterated `delay` times
+    struct FakeOperation<'a> {
+        iterations: &'a Cell<usize>,
+        delay: Option<usize>,
+    }
+
+    impl PendingOperation for FakeOperation<'_> {
+        fn state(&self) -> OperationState {
+            match self.delay {
+                Some(delay) if self.iterations.get() >= delay => OperationState::Done,
+                _ => OperationState::Running,
+            }
+        }
+    }
+
+    struct FakeMainloop<'a> {
+        iterations: &'a Cell<usize>,
+    }
+
+    impl MainloopDriver for FakeMainloop<'_> {
+        fn iterate_timeout(&mut self, timeout: Duration) -> Result<()> {
+            // Pretend that nothing happened for a while
+            thread::sleep(timeout.min(Duration::from_millis(1)));
+            self.iterations.set(self.iterations.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn delayed_callback() {
+        let iterations = Cell::new(0);
+        let mut mainloop = FakeMainloop {
+            iterations: &iterations,
+        };
+        let operation = FakeOperation {
+            iterations: &iterations,
+            delay: Some(10),
+        };
+        wait_for_operation(&mut mainloop, &operation, Duration::from_secs(5)).unwrap();
+        assert_eq!(iterations.get(), 10);
+    }
+
+    #[test]
+    fn timeout() {
+        let iterations = Cell::new(0);
+        let mut mainloop = FakeMainloop {
+            iterations: &iterations,
+        };
+        let operation = FakeOperation {
+            iterations: &iterations,
+            delay: None,
+        };
+        let error =
+            wait_for_operation(&mut mainloop, &operation, Duration::from_millis(20)).unwrap_err();
+        assert_eq!(
+            error.message.as_deref(),
+            Some("pulseaudio request timed out")
+        );
+        assert!(iterations.get() > 0);
+    }
+}
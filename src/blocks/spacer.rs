@@ -0,0 +1,65 @@
+//! Empty space between blocks
+//!
+//! Separates groups of blocks with a gap. No separators are drawn on either side of a spacer, and
+//! it is left out of the theme's alternating tint. Hidden blocks next to a spacer are skipped, so a
+//! spacer is never separated from the visible blocks around it.
+//!
+//! With `flex = true`, the spacer is given a large `min_width`, which pushes the following blocks
+//! to the right edge on bars which lay out blocks from the left. i3bar and swaybar align the status
+//! line to the right, so there it pushes the preceding blocks out of view instead.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `width` | Width in figure spaces, which are as wide as digits | `1`
+//! `min_width` | Minimum width in pixels | `None`
+//! `flex` | Fill the bar, see above. Overrides `min_width`. | `false`
+//! `transparent` | Show the bar's background instead of the theme's idle background | `true`
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "cpu"
+//!
+//! [[block]]
+//! block = "spacer"
+//! width = 3
+//!
+//! [[block]]
+//! block = "time"
+//! ```
+
+use super::prelude::*;
+use crate::widget::Spacer;
+
+/// Wider than any screen
+const FLEX_MIN_WIDTH: usize = 16384;
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    #[default(1)]
+    width: usize,
+    min_width: Option<usize>,
+    flex: bool,
+    #[default(true)]
+    transparent: bool,
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    let mut widget = Widget::new();
+    widget.set_spacer(Spacer {
+        width: config.width,
+        min_width: if config.flex {
+            Some(FLEX_MIN_WIDTH)
+        } else {
+            config.min_width
+        },
+        transparent: config.transparent,
+    });
+    api.set_widget(&widget).await?;
+    api.event_receiver.close();
+    futures::future::pending().await
+}
@@ -2,18 +2,32 @@
 //!
 //! This block which requires [`speedtest-cli`](https://github.com/sivel/speedtest-cli).
 //!
+//! The measurement runs in the background, and takes a while: meanwhile the results of the last
+//! one are shown along with a spinner. A measurement which does not finish within `timeout` is
+//! stopped, and the block is in warning state until the next one succeeds.
+//!
+//! SIGUSR1, which updates all blocks, does not start a measurement, but the block's own `signal`
+//! does.
+//!
 //! # Configuration
 //!
 //! Key | Values | Default
 //! ----|--------|--------
-//! `format` | A string to customise the output of this block. See below for available placeholders. | `" ^icon_ping $ping ^icon_net_down $speed_down ^icon_net_up $speed_up "`
-//! `interval` | Update interval in seconds | `1800`
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" ^icon_ping {$ping ^icon_net_down $speed_down ^icon_net_up $speed_up\|-}{ $running\|} "`
+//! `interval` | Update interval in seconds. `0` disables automatic measurements, so that they are only started by clicks and the block's `signal`. | `1800`
+//! `run_on_startup` | Whether to measure as soon as the block starts | `true`
+//! `timeout` | How long a measurement may take, in seconds | `60`
 //!
-//! Placeholder  | Value          | Type   | Unit
-//! -------------|----------------|--------|---------------
-//! `ping`       | Ping delay     | Number | Seconds
-//! `speed_down` | Download speed | Number | Bits per second
-//! `speed_up`   | Upload speed   | Number | Bits per second
+//! Placeholder  | Value                                        | Type   | Unit
+//! -------------|----------------------------------------------|--------|---------------
+//! `ping`       | Ping delay. Absent until the first measurement finished. | Number | Seconds
+//! `speed_down` | Download speed. Absent until the first measurement finished. | Number | Bits per second
+//! `speed_up`   | Upload speed. Absent until the first measurement finished. | Number | Bits per second
+//! `running`    | A spinner, present while measuring           | Text   | -
+//!
+//! Action | Description        | Default button
+//! -------|--------------------|---------------
+//! `run`  | Start a measurement | Left
 //!
 //! # Example
 //!
@@ -35,56 +49,129 @@
 //! format = " $speed_down.eng(w:4,u:B) $speed_up(w:4,u:B) "
 //! ```
 //!
+//! Only measure on click, on a metered connection
+//!
+//! ```toml
+//! [[block]]
+//! block = "speedtest"
+//! interval = 0
+//! run_on_startup = false
+//! ```
+//!
 //! # Icons Used
 //! - `ping`
 //! - `net_down`
 //! - `net_up`
 
 use super::prelude::*;
+use futures::stream::FuturesUnordered;
 use tokio::process::Command;
 
+/// The frames of the spinner shown while measuring
+const SPINNER: [&str; 4] = ["\u{25d0}", "\u{25d3}", "\u{25d1}", "\u{25d2}"];
+/// How often the spinner advances
+const SPINNER_INTERVAL: Duration = Duration::from_millis(500);
+
 #[derive(Deserialize, Debug, SmartDefault)]
 #[serde(default)]
 pub struct Config {
     format: FormatConfig,
     #[default(1800.into())]
     interval: Seconds,
+    #[default(true)]
+    run_on_startup: bool,
+    #[default(60.into())]
+    timeout: Seconds<false>,
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
-    let mut widget =
-        Widget::new().with_format(config.format.with_default(
-            " ^icon_ping $ping ^icon_net_down $speed_down ^icon_net_up $speed_up ",
-        )?);
+    api.set_default_actions(&[(MouseButton::Left, None, "run")])
+        .await?;
+    api.ignore_usr1().await?;
 
-    let mut command = Command::new("speedtest-cli");
-    command.arg("--json");
+    let mut widget = Widget::new().with_format(config.format.with_default(
+        " ^icon_ping {$ping ^icon_net_down $speed_down ^icon_net_up $speed_up|-}{ $running|} ",
+    )?);
 
-    loop {
-        let output = command
-            .output()
-            .await
-            .error("failed to run 'speedtest-cli'")?
-            .stdout;
-        let output =
-            std::str::from_utf8(&output).error("'speedtest-cli' produced non-UTF8 outupt")?;
-        let output: SpeedtestCliOutput =
-            serde_json::from_str(output).error("'speedtest-cli' produced wrong JSON")?;
+    let automatic = !config.interval.0.is_zero();
+    // A timer with a zero period would panic, and it is not polled anyway
+    let mut timer = if automatic {
+        config.interval
+    } else {
+        Seconds::new(1800)
+    }
+    .timer();
+    // The running measurement, if any
+    let mut measurements = FuturesUnordered::new();
+    let start = |measurements: &mut FuturesUnordered<_>, api: &CommonApi| {
+        if measurements.is_empty() {
+            let mut command = api.command_context.command("speedtest-cli");
+            command.arg("--json");
+            measurements.push(measure(command, config.timeout.0));
+        }
+    };
+    if config.run_on_startup {
+        start(&mut measurements, &api);
+    }
 
-        widget.set_values(map! {
-            "ping" => Value::seconds(output.ping * 1e-3),
-            "speed_down" => Value::bits(output.download),
-            "speed_up" => Value::bits(output.upload),
-        });
+    let mut last: Option<SpeedtestCliOutput> = None;
+    let mut timed_out = false;
+    let mut frame = 0;
+
+    loop {
+        let mut values = Values::new();
+        if let Some(output) = &last {
+            values.insert("ping".into(), Value::seconds(output.ping * 1e-3));
+            values.insert("speed_down".into(), Value::bits(output.download));
+            values.insert("speed_up".into(), Value::bits(output.upload));
+        }
+        if !measurements.is_empty() {
+            values.insert(
+                "running".into(),
+                Value::text(SPINNER[frame % SPINNER.len()].into()),
+            );
+        }
+        widget.set_values(values);
+        widget.state = if timed_out {
+            State::Warning
+        } else {
+            State::Idle
+        };
         api.set_widget(&widget).await?;
 
         select! {
-            _ = sleep(config.interval.0) => (),
-            _ = api.wait_for_update_request() => (),
+            Some(result) = measurements.next() => match result? {
+                Some(output) => {
+                    last = Some(output);
+                    timed_out = false;
+                }
+                None => timed_out = true,
+            },
+            _ = sleep(SPINNER_INTERVAL), if !measurements.is_empty() => frame += 1,
+            _ = timer.tick(), if automatic => start(&mut measurements, &api),
+            event = api.event() => match event {
+                UpdateRequest => start(&mut measurements, &api),
+                Action(a) if a == "run" => start(&mut measurements, &api),
+                _ => (),
+            }
         }
     }
 }
 
+/// Run `speedtest-cli --json`. Returns `None` if it did not finish in time, in which case it is
+/// killed.
+async fn measure(mut command: Command, timeout: Duration) -> Result<Option<SpeedtestCliOutput>> {
+    let output = command.kill_on_drop(true).output();
+    let output = match tokio::time::timeout(timeout, output).await {
+        Ok(output) => output.error("failed to run 'speedtest-cli'")?.stdout,
+        Err(_) => return Ok(None),
+    };
+    let output = std::str::from_utf8(&output).error("'speedtest-cli' produced non-UTF8 outupt")?;
+    serde_json::from_str(output)
+        .error("'speedtest-cli' produced wrong JSON")
+        .map(Some)
+}
+
 #[derive(Deserialize, Debug, Clone, Copy)]
 struct SpeedtestCliOutput {
     /// Download speed in bits per second
@@ -94,3 +181,32 @@ struct SpeedtestCliOutput {
     /// Ping time in ms
     ping: f64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout() {
+        tokio_test::block_on(async {
+            let mut command = Command::new("sh");
+            command.args([
+                "-c",
+                r#"echo '{"download": 9.5e7, "upload": 2e7, "ping": 12.3}'"#,
+            ]);
+            let output = measure(command, Duration::from_secs(5))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(output.download, 9.5e7);
+            assert_eq!(output.ping, 12.3);
+
+            let mut command = Command::new("sleep");
+            command.arg("10");
+            let start = std::time::Instant::now();
+            let output = measure(command, Duration::from_millis(100)).await.unwrap();
+            assert!(output.is_none());
+            assert!(start.elapsed() < Duration::from_secs(5));
+        });
+    }
+}
@@ -0,0 +1,179 @@
+//! The worst state of all other blocks
+//!
+//! Shows a single character whose state is the worst state among the other blocks: critical if
+//! any block is critical or shows an error, warning if any block is in warning state. This is
+//! useful on narrow screens, where the block in question may be scrolled out of view. Clicking it
+//! shows only the offending blocks (and this one) for `reveal_duration` seconds.
+//!
+//! `summary` blocks never take the state of `summary` blocks into account, including their own,
+//! so that they cannot keep each other in a state.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" ● "`
+//! `reveal_duration` | How long the offending blocks are shown alone after a click, in seconds | `5`
+//!
+//! Placeholder      | Value                                      | Type   | Unit
+//! -----------------|--------------------------------------------|--------|-----
+//! `warning_count`  | Number of blocks in warning state          | Number | -
+//! `critical_count` | Number of blocks in critical state         | Number | -
+//! `error_count`    | Number of blocks which show an error       | Number | -
+//!
+//! Action   | Description                                        | Default button
+//! ---------|----------------------------------------------------|---------------
+//! `reveal` | Show only the offending blocks for a few seconds   | Left
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "summary"
+//! format = " ● $critical_count/$warning_count "
+//! ```
+
+use super::prelude::*;
+use crate::BlockStatus;
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    format: FormatConfig,
+    #[default(5.into())]
+    reveal_duration: Seconds<false>,
+}
+
+/// The states of the blocks which are not `summary` blocks
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Summary {
+    warning: usize,
+    critical: usize,
+    error: usize,
+    /// The IDs of the blocks in warning or critical state or which show an error
+    offending: Vec<usize>,
+}
+
+impl Summary {
+    fn new(statuses: &[(&str, BlockStatus)]) -> Self {
+        let mut summary = Self::default();
+        for (id, (name, status)) in statuses.iter().enumerate() {
+            // Counting summary blocks would let them feed on their own state
+            if *name == "summary" {
+                continue;
+            }
+            match status {
+                BlockStatus::Normal(State::Warning) => summary.warning += 1,
+                BlockStatus::Normal(State::Critical) => summary.critical += 1,
+                BlockStatus::Error => summary.error += 1,
+                _ => continue,
+            }
+            summary.offending.push(id);
+        }
+        summary
+    }
+
+    fn state(&self) -> State {
+        if self.critical > 0 || self.error > 0 {
+            State::Critical
+        } else if self.warning > 0 {
+            State::Warning
+        } else {
+            State::Idle
+        }
+    }
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[(MouseButton::Left, None, "reveal")])
+        .await?;
+
+    let mut widget = Widget::new().with_format(config.format.with_default(" \u{25cf} ")?);
+    let mut statuses = api.block_statuses.clone();
+    let mut last = None;
+
+    loop {
+        let summary = Summary::new(&statuses.borrow_and_update());
+        // Setting the same widget again would only re-render this block
+        if last.as_ref() != Some(&summary) {
+            widget.state = summary.state();
+            widget.set_values(map! {
+                "warning_count" => Value::number(summary.warning),
+                "critical_count" => Value::number(summary.critical),
+                "error_count" => Value::number(summary.error),
+            });
+            api.set_widget(&widget).await?;
+            last = Some(summary);
+        }
+
+        loop {
+            select! {
+                changed = statuses.changed() => {
+                    changed.error("The bar stopped")?;
+                    break;
+                }
+                event = api.event() => match event {
+                    UpdateRequest => break,
+                    Action(a) if a == "reveal" => {
+                        let offending = last.as_ref().map(|s| s.offending.clone()).unwrap_or_default();
+                        if !offending.is_empty() {
+                            let mut blocks = offending;
+                            blocks.push(api.id.get());
+                            blocks.sort_unstable();
+                            api.reveal(blocks, config.reveal_duration.0).await?;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let statuses = [
+            ("cpu", BlockStatus::Normal(State::Idle)),
+            ("battery", BlockStatus::Normal(State::Critical)),
+            ("disk_space", BlockStatus::Normal(State::Warning)),
+            ("weather", BlockStatus::Error),
+            ("net", BlockStatus::Hidden),
+            ("memory", BlockStatus::Normal(State::Warning)),
+        ];
+        let summary = Summary::new(&statuses);
+        assert_eq!(
+            summary,
+            Summary {
+                warning: 2,
+                critical: 1,
+                error: 1,
+                offending: vec![1, 2, 3, 5],
+            }
+        );
+        assert_eq!(summary.state(), State::Critical);
+
+        let summary = Summary::new(&statuses[4..]);
+        assert_eq!(summary.state(), State::Warning);
+        assert_eq!(Summary::new(&[]).state(), State::Idle);
+    }
+
+    #[test]
+    fn no_feedback() {
+        // The summary block is critical because of the battery
+        let mut statuses = vec![
+            ("battery", BlockStatus::Normal(State::Critical)),
+            ("summary", BlockStatus::Normal(State::Critical)),
+            ("summary", BlockStatus::Normal(State::Critical)),
+        ];
+        assert_eq!(Summary::new(&statuses).state(), State::Critical);
+        // Once the battery recovers, the critical summary blocks do not keep each other critical
+        statuses[0].1 = BlockStatus::Normal(State::Good);
+        assert_eq!(Summary::new(&statuses), Summary::default());
+        statuses[1].1 = BlockStatus::Error;
+        assert_eq!(Summary::new(&statuses), Summary::default());
+    }
+}
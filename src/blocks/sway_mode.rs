@@ -0,0 +1,223 @@
+//! The binding mode of i3 or sway
+//!
+//! It is easy to forget that a binding mode such as `resize` is still active. This block shows the
+//! current mode, in critical state by default so it stands out, and is hidden while the mode is
+//! `default`. The mode is followed over the IPC of i3 or sway. If the connection is lost, e.g.
+//! because i3 restarts, the block reconnects with an increasing delay of up to one minute.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $mode "`
+//! `state` | The state of the block, which decides its colors: `"idle"`, `"info"`, `"good"`, `"warning"` or `"critical"` | `"critical"`
+//! `mappings` | Names to show instead of the names of modes, e.g. `{ resize = "RESIZE" }` | `{}`
+//! `icon_per_mode` | Icons to show instead of `binding_mode` for some modes, e.g. `{ resize = "resolution" }` | `{}`
+//!
+//! Placeholder | Value                                   | Type | Unit
+//! ------------|-----------------------------------------|------|-----
+//! `icon`      | The icon of the mode                    | Icon | -
+//! `mode`      | The name of the mode, after `mappings`  | Text | -
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "sway_mode"
+//! mappings = { resize = "RESIZE", passthrough = "PASS" }
+//! icon_per_mode = { resize = "resolution" }
+//! ```
+//!
+//! # Icons Used
+//! - `binding_mode`
+
+use super::prelude::*;
+use crate::BoxedStream;
+use std::future::Future;
+use swayipc_async::{Connection, Event, EventType};
+
+/// The mode in which the block is hidden
+const DEFAULT_MODE: &str = "default";
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    format: FormatConfig,
+    #[default(State::Critical)]
+    state: State,
+    mappings: HashMap<String, String>,
+    icon_per_mode: HashMap<String, String>,
+}
+
+/// The name to show for `mode`, or `None` if the block is hidden
+fn shown_name<'a>(mappings: &'a HashMap<String, String>, mode: &'a str) -> Option<&'a str> {
+    if mode == DEFAULT_MODE {
+        return None;
+    }
+    Some(mappings.get(mode).map_or(mode, String::as_str))
+}
+
+fn icon_name<'a>(icon_per_mode: &'a HashMap<String, String>, mode: &str) -> &'a str {
+    icon_per_mode
+        .get(mode)
+        .map_or("binding_mode", String::as_str)
+}
+
+/// The current mode, followed by its changes
+type Modes = BoxedStream<Result<String>>;
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    let mut widget = Widget::new()
+        .with_format(config.format.with_default(" $icon $mode ")?)
+        .with_state(config.state);
+
+    let mut modes = None;
+    let mut reconnect_delay = MIN_RECONNECT_DELAY;
+    let mut mode = next_mode(&mut modes, connect, &mut reconnect_delay).await;
+
+    loop {
+        match shown_name(&config.mappings, &mode) {
+            Some(name) => {
+                widget.set_values(map! {
+                    "icon" => Value::icon(api.get_icon(icon_name(&config.icon_per_mode, &mode))?),
+                    "mode" => Value::text(name.to_string()),
+                });
+                api.set_widget(&widget).await?;
+            }
+            None => api.hide().await?,
+        }
+
+        select! {
+            new_mode = next_mode(&mut modes, connect, &mut reconnect_delay) => mode = new_mode,
+            _ = api.wait_for_update_request() => (),
+        }
+    }
+}
+
+async fn connect() -> Result<Modes> {
+    // Subscribe first, so that no change is missed between reading the mode and subscribing
+    let events = Connection::new()
+        .await
+        .error("failed to open connection with swayipc")?
+        .subscribe(&[EventType::Mode])
+        .await
+        .error("could not subscribe to mode events")?;
+    let current = Connection::new()
+        .await
+        .error("failed to open connection with swayipc")?
+        .get_binding_state()
+        .await
+        .error("failed to get the binding mode")?;
+    let changes = events.filter_map(|event| async move {
+        match event {
+            Ok(Event::Mode(event)) => Some(Ok(event.change)),
+            Ok(_) => None,
+            Err(error) => Some(Err(error).error("bad event")),
+        }
+    });
+    Ok(futures::stream::once(async { Ok(current) })
+        .chain(changes)
+        .boxed_local())
+}
+
+/// Wait for the next mode, (re)connecting with `connect` if needed. After reconnecting, the
+/// current mode is reported again.
+///
+/// # Cancel safety
+///
+/// This function is cancel safe.
+async fn next_mode<C, F>(
+    modes: &mut Option<Modes>,
+    connect: C,
+    reconnect_delay: &mut Duration,
+) -> String
+where
+    C: Fn() -> F,
+    F: Future<Output = Result<Modes>>,
+{
+    loop {
+        match modes {
+            Some(m) => match m.next().await {
+                Some(Ok(mode)) => {
+                    *reconnect_delay = MIN_RECONNECT_DELAY;
+                    return mode;
+                }
+                Some(Err(error)) => {
+                    log::debug!("Lost the binding mode: {error}");
+                    *modes = None;
+                }
+                None => *modes = None,
+            },
+            None => match connect().await {
+                Ok(m) => *modes = Some(m),
+                Err(error) => {
+                    log::debug!("Lost the binding mode: {error}");
+                    sleep(*reconnect_delay).await;
+                    *reconnect_delay = (*reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn hide_on_default() {
+        let config: Config = toml::from_str(
+            r#"
+            mappings = { resize = "RESIZE" }
+            icon_per_mode = { resize = "resolution" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.state, State::Critical);
+        assert_eq!(shown_name(&config.mappings, "default"), None);
+        assert_eq!(shown_name(&config.mappings, "resize"), Some("RESIZE"));
+        assert_eq!(shown_name(&config.mappings, "launch"), Some("launch"));
+        assert_eq!(icon_name(&config.icon_per_mode, "resize"), "resolution");
+        assert_eq!(icon_name(&config.icon_per_mode, "launch"), "binding_mode");
+    }
+
+    /// Each connection attempt takes the next entry of the script: either an error or the modes
+    /// reported before the connection is lost
+    type Script = RefCell<VecDeque<Result<Vec<Result<String>>>>>;
+
+    fn connection(script: &Script) -> impl Future<Output = Result<Modes>> {
+        let attempt = script.borrow_mut().pop_front().expect("script ended");
+        async move { attempt.map(|modes| futures::stream::iter(modes).boxed_local()) }
+    }
+
+    #[test]
+    fn reconnect() {
+        let script: Script = RefCell::new(VecDeque::from([
+            // i3 is not up yet
+            Err(Error::new("connection refused")),
+            Err(Error::new("connection refused")),
+            Ok(vec![Ok("default".into()), Ok("resize".into())]),
+            // i3 restarts
+            Ok(vec![Ok("default".into()), Err(Error::new("bad event"))]),
+            Ok(vec![Ok("passthrough".into())]),
+        ]));
+        let mut modes = None;
+        let mut delay = Duration::from_millis(1);
+        let mut next = |delay: &mut Duration| {
+            tokio_test::block_on(next_mode(&mut modes, || connection(&script), delay))
+        };
+        // The delay grows while connecting fails, and is reset by the first mode
+        assert_eq!(next(&mut delay), "default");
+        assert_eq!(delay, MIN_RECONNECT_DELAY);
+        assert_eq!(next(&mut delay), "resize");
+        // The current mode is reported again after reconnecting
+        assert_eq!(next(&mut delay), "default");
+        assert_eq!(next(&mut delay), "passthrough");
+        assert!(script.borrow().is_empty());
+    }
+}
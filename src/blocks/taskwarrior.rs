@@ -1,6 +1,7 @@
 //! The number of tasks from the taskwarrior list
 //!
 //! Clicking the right mouse button on the icon cycles the view of the block through the user's filters.
+//! Clicking the left mouse button updates the block.
 //!
 //! # Configuration
 //!
@@ -25,6 +26,7 @@
 //! Action        | Default button
 //! --------------|---------------
 //! `next_filter` | Right
+//! `update`      | Left
 //!
 //! # Example
 //!
@@ -51,7 +53,6 @@
 
 use super::prelude::*;
 use inotify::{Inotify, WatchMask};
-use tokio::process::Command;
 
 #[derive(Deserialize, Debug)]
 #[serde(default)]
@@ -83,8 +84,11 @@ impl Default for Config {
 }
 
 pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
-    api.set_default_actions(&[(MouseButton::Right, None, "next_filter")])
-        .await?;
+    api.set_default_actions(&[
+        (MouseButton::Right, None, "next_filter"),
+        (MouseButton::Left, None, "update"),
+    ])
+    .await?;
 
     let mut widget = Widget::new().with_format(
         config
@@ -104,7 +108,7 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         .error("Failed to create event stream")?;
 
     loop {
-        let number_of_tasks = get_number_of_tasks(&filter.filter).await?;
+        let number_of_tasks = get_number_of_tasks(&api.command_context, &filter.filter).await?;
 
         if number_of_tasks != 0 || !config.hide_when_zero {
             widget.set_values(map! {
@@ -141,13 +145,21 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     }
 }
 
-async fn get_number_of_tasks(filter: &str) -> Result<u32> {
-    let output = Command::new("task")
+async fn get_number_of_tasks(commands: &CommandContext, filter: &str) -> Result<u32> {
+    let output = match commands
+        .command("task")
         .args(["rc.gc=off", filter, "count"])
         .output()
         .await
-        .error("failed to run taskwarrior for getting the number of tasks")?
-        .stdout;
+    {
+        Ok(output) => output.stdout,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::new(
+                "taskwarrior is not installed (`task` was not found in PATH)",
+            ))
+        }
+        Err(e) => return Err(e).error("failed to run taskwarrior for getting the number of tasks"),
+    };
     std::str::from_utf8(&output)
         .error("failed to get the number of tasks from taskwarrior (invalid UTF-8)")?
         .trim()
@@ -36,7 +36,6 @@
 //! - `tea`
 
 use super::prelude::*;
-use crate::subprocess::spawn_shell;
 use chrono::{Duration, Utc};
 
 #[derive(Deserialize, Debug, SmartDefault)]
@@ -73,7 +72,9 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
 
         if !is_timer_active && timer_was_active {
             if let Some(cmd) = &config.done_cmd {
-                spawn_shell(cmd).error("done_cmd error")?;
+                api.command_context
+                    .spawn_shell(cmd)
+                    .error("done_cmd error")?;
             }
         }
         timer_was_active = is_timer_active;
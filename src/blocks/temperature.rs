@@ -1,16 +1,28 @@
 //! The system temperature
 //!
-//! This block displays the system temperature, based on `libsensors` library.
+//! This block displays the system temperature, based on `libsensors` library or on the hwmon
+//! interface in `/sys/class/hwmon`.
 //!
 //! This block has two modes: "collapsed", which uses only color as an indicator, and "expanded",
 //! which shows the content of a `format` string. The average, minimum, and maximum temperatures
-//! are computed using all sensors displayed by `sensors`, or optionally filtered by `chip` and
-//! `inputs`.
+//! are computed using all sensors displayed by `sensors`, or optionally filtered by `chip`,
+//! `inputs` and `ignore_inputs`. Each input is also available as a placeholder named after its
+//! label, with characters other than letters, digits, `_` and `-` replaced by `_`, e.g.
+//! `$Package_id_0` for the input labeled `Package id 0`.
 //!
-//! Requires `libsensors` and appropriate kernel modules for your hardware.
+//! Requires `libsensors` and appropriate kernel modules for your hardware. The `sysfs` driver reads
+//! the kernel's hwmon interface directly, so it works without a `libsensors` configuration, e.g.
+//! on minimal or headless systems.
 //!
 //! Run `sensors` command to list available chips and inputs.
 //!
+//! GPUs are often missing from `libsensors` configurations. With `include_gpu`, the block also reads
+//! the hwmon inputs of `amdgpu` and `radeon` GPUs, labeled `gpu_edge`, `gpu_junction` and
+//! `gpu_mem`, and, if an NVIDIA driver is loaded, the temperature of the first NVIDIA GPU as
+//! `nvidia`. The latter is read through NVML, which is shared with the `nvidia_gpu` blocks that
+//! show the same GPU. GPU inputs count towards the minimum, average and maximum, and can
+//! be filtered by `inputs` and `ignore_inputs` like any other input.
+//!
 //! Note that the colour of the block is always determined by the maximum temperature across all
 //! sensors, not the average. You may need to keep this in mind if you have a misbehaving sensor.
 //!
@@ -18,7 +30,7 @@
 //!
 //! Key | Values | Default
 //! ----|--------|--------
-//! `format` | A string to customise the output of this block. See below for available placeholders | `" $icon $average avg, $max max "`
+//! `format` | A string to customise the output of this block. See below for available placeholders | `" $icon $average avg, $max max "`, with the short text `" $icon $max max "` if `format` is not set
 //! `format_alt` | If set, block will switch between `format` and `format_alt` on every click | `None`
 //! `interval` | Update interval in seconds | `5`
 //! `scale` | Either `"celsius"` or `"fahrenheit"` | `"celsius"`
@@ -26,8 +38,11 @@
 //! `idle` | Maximum temperature to set state to idle | `45` °C (`113` °F)
 //! `info` | Maximum temperature to set state to info | `60` °C (`140` °F)
 //! `warning` | Maximum temperature to set state to warning. Beyond this temperature, state is set to critical | `80` °C (`176` °F)
-//! `chip` | Narrows the results to a given chip name. `*` may be used as a wildcard. | None
-//! `inputs` | Narrows the results to individual inputs reported by each chip. | None
+//! `driver` | Either `"sensors"` (`libsensors`) or `"sysfs"` (`/sys/class/hwmon`) | `"sensors"`
+//! `chip` | Narrows the results to chips whose name matches this pattern, or any of a list of patterns. `*` matches any sequence of characters, e.g. `["coretemp-*", "amdgpu-*"]`. With the `sysfs` driver, patterns are matched against the hwmon device's `name`, e.g. `"coretemp"` | None
+//! `inputs` | Narrows the results to individual inputs reported by each chip. With the `sysfs` driver, inputs without a label are named like `temp1` | None
+//! `ignore_inputs` | A list of inputs to ignore, e.g. bogus sensors like `["SYSTIN"]` | `[]`
+//! `include_gpu` | Also read the temperatures of GPUs, see above | `false`
 //!
 //! Action          | Description                               | Default button
 //! ----------------|-------------------------------------------|---------------
@@ -38,6 +53,7 @@
 //! `min`       | Minimum temperature among all inputs | Number | Degrees
 //! `average`   | Average temperature among all inputs | Number | Degrees
 //! `max`       | Maximum temperature among all inputs | Number | Degrees
+//! `<label>`   | Temperature of the input with this label. If several chips have an input with this label, the first one is used. Absent if there is no such input, so use it in a conditional format like `{$edge|N/A}`. | Number | Degrees
 //!
 //! Note that when block is collapsed, no placeholders are provided.
 //!
@@ -52,19 +68,53 @@
 //! chip = "*-isa-*"
 //! ```
 //!
+//! Show the CPU and GPU temperatures:
+//!
+//! ```toml
+//! [[block]]
+//! block = "temperature"
+//! format = " CPU $Package_id_0 GPU $edge "
+//! chip = ["coretemp-*", "amdgpu-*"]
+//! ```
+//!
+//! Or, without knowing the driver of the GPU:
+//!
+//! ```toml
+//! [[block]]
+//! block = "temperature"
+//! format = " CPU $Package_id_0 GPU {$gpu_edge|}{$nvidia|} "
+//! chip = "coretemp-*"
+//! include_gpu = true
+//! ```
+//!
 //! # Icons Used
 //! - `thermometer`
 
+use super::nvidia_gpu::{Gpu, GpuId, NVIDIA_DRIVER};
 use super::prelude::*;
+use crate::util::{read_file, wildcard_match};
 use sensors::FeatureType::SENSORS_FEATURE_TEMP;
 use sensors::Sensors;
 use sensors::SubfeatureType::SENSORS_SUBFEATURE_TEMP_INPUT;
+use std::path::Path;
+use tokio::fs::read_dir;
+
+const HWMON_DIR: &str = "/sys/class/hwmon";
+
+/// The names of the hwmon devices of GPUs which are read with `include_gpu`
+const GPU_CHIPS: &[&str] = &["amdgpu", "radeon"];
+
+/// Placeholders which are not input labels
+const RESERVED_PLACEHOLDERS: &[&str] = &["icon", "average", "min", "max"];
 
 const DEFAULT_GOOD: f64 = 20.0;
 const DEFAULT_IDLE: f64 = 45.0;
 const DEFAULT_INFO: f64 = 60.0;
 const DEFAULT_WARN: f64 = 80.0;
 
+pub const DEFAULT_FORMAT: &str = " $icon $average avg, $max max ";
+const DEFAULT_SHORT_FORMAT: &str = " $icon $max max ";
+
 #[derive(Deserialize, Debug, SmartDefault)]
 #[serde(default)]
 pub struct Config {
@@ -77,8 +127,39 @@ pub struct Config {
     idle: Option<f64>,
     info: Option<f64>,
     warning: Option<f64>,
-    chip: Option<String>,
+    driver: TemperatureDriver,
+    chip: Option<Chips>,
     inputs: Option<Vec<String>>,
+    ignore_inputs: Vec<String>,
+    include_gpu: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum Chips {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Chips {
+    /// Whether a chip called `name` or `prefix` matches any of the patterns
+    fn matches(&self, name: &str, prefix: &str) -> bool {
+        let patterns = match self {
+            Self::Single(pattern) => std::slice::from_ref(pattern),
+            Self::Multiple(patterns) => patterns.as_slice(),
+        };
+        patterns
+            .iter()
+            .any(|pattern| wildcard_match(pattern, name) || wildcard_match(pattern, prefix))
+    }
+}
+
+#[derive(Deserialize, Debug, SmartDefault, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum TemperatureDriver {
+    #[default]
+    Sensors,
+    Sysfs,
 }
 
 #[derive(Deserialize, Debug, SmartDefault, Clone, Copy, PartialEq, Eq)]
@@ -103,15 +184,25 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     api.set_default_actions(&[(MouseButton::Left, None, "toggle_format")])
         .await?;
 
-    let mut format = config
-        .format
-        .with_default(" $icon $average avg, $max max ")?;
+    // A custom `format` has no short text unless it sets one
+    let default_short = match config.format.full {
+        Some(_) => "",
+        None => DEFAULT_SHORT_FORMAT,
+    };
+    let mut format = config.format.with_defaults(DEFAULT_FORMAT, default_short)?;
     let mut format_alt = match config.format_alt {
         Some(f) => Some(f.with_default("")?),
         None => None,
     };
     let mut widget = Widget::new().with_format(format.clone());
 
+    // Placeholders which refer to inputs by label
+    let mut input_placeholders = format.placeholders();
+    if let Some(format_alt) = &format_alt {
+        input_placeholders.extend(format_alt.placeholders());
+    }
+    input_placeholders.retain(|name| !RESERVED_PLACEHOLDERS.contains(&name.as_str()));
+
     let good = config
         .good
         .unwrap_or_else(|| config.scale.from_celsius(DEFAULT_GOOD));
@@ -125,49 +216,46 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         .warning
         .unwrap_or_else(|| config.scale.from_celsius(DEFAULT_WARN));
 
+    let nvidia = if config.include_gpu && Path::new(NVIDIA_DRIVER).exists() {
+        Some(Gpu::shared(&GpuId::Index(0))?)
+    } else {
+        None
+    };
+
     loop {
-        // Perhaps it's better to just Box::leak() once and don't clone() every time?
-        let chip = config.chip.clone();
-        let inputs = config.inputs.clone();
-        let temp = tokio::task::spawn_blocking(move || {
-            let mut vals = Vec::new();
-            let sensors = Sensors::new();
-            let chips = match &chip {
-                Some(chip) => sensors
-                    .detected_chips(chip)
-                    .error("Failed to create chip iterator")?,
-                None => sensors.into_iter(),
-            };
-            for chip in chips {
-                for feat in chip {
-                    if *feat.feature_type() != SENSORS_FEATURE_TEMP {
-                        continue;
-                    }
-                    if let Some(inputs) = &inputs {
-                        let label = feat.get_label().error("Failed to get input label")?;
-                        if !inputs.contains(&label) {
-                            continue;
-                        }
-                    }
-                    for subfeat in feat {
-                        if *subfeat.subfeature_type() == SENSORS_SUBFEATURE_TEMP_INPUT {
-                            if let Ok(value) = subfeat.get_value() {
-                                if (-100.0..=150.0).contains(&value) {
-                                    vals.push(config.scale.from_celsius(value));
-                                } else {
-                                    eprintln!(
-                                        "Temperature ({value}) outside of range ([-100, 150])"
-                                    );
-                                }
-                            }
-                        }
-                    }
-                }
+        // GPUs are read separately with `include_gpu`, so skip them here to not count them twice
+        let skip_gpus = config.include_gpu;
+        let mut readings = match config.driver {
+            TemperatureDriver::Sensors => {
+                sensors_temperatures(config.chip.clone(), skip_gpus).await?
             }
-            Ok(vals)
-        })
-        .await
-        .error("Failed to join tokio task")??;
+            TemperatureDriver::Sysfs => sysfs_temperatures(config.chip.as_ref(), skip_gpus).await?,
+        };
+        if config.include_gpu {
+            readings.extend(gpu_temperatures().await?);
+        }
+        if let Some(gpu) = &nvidia {
+            readings.push(("nvidia".into(), gpu.temperature()?.into()));
+        }
+        let readings: Vec<(String, f64)> = readings
+            .into_iter()
+            .filter(|(label, _)| {
+                config
+                    .inputs
+                    .as_ref()
+                    .map_or(true, |inputs| inputs.contains(label))
+                    && !config.ignore_inputs.contains(label)
+            })
+            .filter(|(_, value)| {
+                let valid = (-100.0..=150.0).contains(value);
+                if !valid {
+                    eprintln!("Temperature ({value}) outside of range ([-100, 150])");
+                }
+                valid
+            })
+            .map(|(label, value)| (label, config.scale.from_celsius(value)))
+            .collect();
+        let temp: Vec<f64> = readings.iter().map(|(_, value)| *value).collect();
 
         let min_temp = temp
             .iter()
@@ -190,12 +278,21 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         };
 
         'outer: loop {
-            widget.set_values(map! {
+            let mut values = map! {
                 "icon" => Value::icon(api.get_icon("thermometer")?),
                 "average" => Value::degrees(avg_temp),
                 "min" => Value::degrees(min_temp),
                 "max" => Value::degrees(max_temp),
-            });
+            };
+            for name in &input_placeholders {
+                if let Some((_, value)) = readings
+                    .iter()
+                    .find(|(label, _)| placeholder_name(label) == *name)
+                {
+                    values.insert(name.clone().into(), Value::degrees(*value));
+                }
+            }
+            widget.set_values(values);
 
             api.set_widget(&widget).await?;
 
@@ -218,3 +315,173 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         }
     }
 }
+
+/// Labels and temperatures in degrees Celsius of all inputs, read using libsensors. The inputs
+/// of GPUs are left out if `skip_gpus` is set.
+async fn sensors_temperatures(chip: Option<Chips>, skip_gpus: bool) -> Result<Vec<(String, f64)>> {
+    // libsensors reads the files synchronously, so keep it off the executor
+    tokio::task::spawn_blocking(move || {
+        let mut vals = Vec::new();
+        for sensors_chip in Sensors::new() {
+            if skip_gpus && GPU_CHIPS.contains(&sensors_chip.prefix()) {
+                continue;
+            }
+            if let Some(chip) = &chip {
+                let name = sensors_chip.get_name().error("Failed to get chip name")?;
+                if !chip.matches(&name, sensors_chip.prefix()) {
+                    continue;
+                }
+            }
+            for feat in sensors_chip {
+                if *feat.feature_type() != SENSORS_FEATURE_TEMP {
+                    continue;
+                }
+                let label = feat.get_label().error("Failed to get input label")?;
+                for subfeat in feat {
+                    if *subfeat.subfeature_type() == SENSORS_SUBFEATURE_TEMP_INPUT {
+                        if let Ok(value) = subfeat.get_value() {
+                            vals.push((label.clone(), value));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(vals)
+    })
+    .await
+    .error("Failed to join tokio task")?
+}
+
+/// Labels and temperatures in degrees Celsius of all inputs, read from
+/// `/sys/class/hwmon/hwmon*/temp*_input`. The inputs of GPUs are left out if `skip_gpus` is set.
+async fn sysfs_temperatures(chip: Option<&Chips>, skip_gpus: bool) -> Result<Vec<(String, f64)>> {
+    let inputs = hwmon_inputs(|name| {
+        chip.map_or(true, |chip| chip.matches(name, name))
+            && !(skip_gpus && GPU_CHIPS.contains(&name))
+    })
+    .await?;
+    Ok(inputs
+        .into_iter()
+        .map(|input| (input.label.unwrap_or(input.input), input.value))
+        .collect())
+}
+
+/// Normalized labels and temperatures in degrees Celsius of the inputs of all `amdgpu` and
+/// `radeon` GPUs
+async fn gpu_temperatures() -> Result<Vec<(String, f64)>> {
+    let inputs = hwmon_inputs(|name| GPU_CHIPS.contains(&name)).await?;
+    Ok(inputs
+        .into_iter()
+        .map(|input| (gpu_label(input.label.as_deref(), &input.input), input.value))
+        .collect())
+}
+
+/// The label of a GPU input, e.g. `gpu_junction`. `radeon` has a single unlabeled input, which
+/// is the edge temperature.
+fn gpu_label(label: Option<&str>, input: &str) -> String {
+    match label {
+        Some(label) => format!("gpu_{}", placeholder_name(&label.to_lowercase())),
+        None if input == "temp1" => "gpu_edge".into(),
+        None => format!("gpu_{input}"),
+    }
+}
+
+/// A temperature input of a hwmon device
+struct HwmonInput {
+    /// E.g. `temp1`
+    input: String,
+    label: Option<String>,
+    /// In degrees Celsius
+    value: f64,
+}
+
+/// All temperature inputs of the hwmon devices whose name matches `filter`
+async fn hwmon_inputs(filter: impl Fn(&str) -> bool) -> Result<Vec<HwmonInput>> {
+    let mut vals = Vec::new();
+    let mut devices = read_dir(HWMON_DIR)
+        .await
+        .or_error(|| format!("Failed to read {HWMON_DIR}"))?;
+    while let Some(device) = devices
+        .next_entry()
+        .await
+        .or_error(|| format!("Failed to read {HWMON_DIR}"))?
+    {
+        let path = device.path();
+        let name = read_file(path.join("name")).await.unwrap_or_default();
+        if !filter(&name) {
+            continue;
+        }
+        let mut files = match read_dir(&path).await {
+            Ok(files) => files,
+            Err(_) => continue,
+        };
+        while let Ok(Some(file)) = files.next_entry().await {
+            let file_name = file.file_name();
+            let input = match file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix("_input"))
+            {
+                Some(input) if input.starts_with("temp") => input,
+                _ => continue,
+            };
+            // Reading an input fails if the sensor is not ready, libsensors skips those as well
+            if let Ok(value) = read_file(file.path()).await {
+                if let Ok(millidegrees) = value.parse::<f64>() {
+                    let label = read_file(path.join(format!("{input}_label"))).await.ok();
+                    vals.push(HwmonInput {
+                        input: input.into(),
+                        label,
+                        value: millidegrees / 1000.0,
+                    });
+                }
+            }
+        }
+    }
+    Ok(vals)
+}
+
+/// The placeholder of an input labeled `label`
+fn placeholder_name(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || c == '_' || c == '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chips() {
+        let chips: Config = toml::from_str(r#"chip = ["coretemp-*", "amdgpu-*"]"#).unwrap();
+        let chips = chips.chip.unwrap();
+        assert!(chips.matches("coretemp-isa-0000", "coretemp"));
+        assert!(chips.matches("amdgpu-pci-0300", "amdgpu"));
+        assert!(!chips.matches("nvme-pci-0100", "nvme"));
+        let chip: Config = toml::from_str(r#"chip = "coretemp""#).unwrap();
+        assert!(chip.chip.unwrap().matches("coretemp-isa-0000", "coretemp"));
+    }
+
+    #[test]
+    fn placeholder_names() {
+        assert_eq!(placeholder_name("Package id 0"), "Package_id_0");
+        assert_eq!(placeholder_name("edge"), "edge");
+        assert_eq!(placeholder_name("Core 0"), "Core_0");
+        assert_eq!(placeholder_name("temp1+cpu"), "temp1_cpu");
+    }
+
+    #[test]
+    fn gpu_labels() {
+        assert_eq!(gpu_label(Some("edge"), "temp1"), "gpu_edge");
+        assert_eq!(gpu_label(Some("junction"), "temp2"), "gpu_junction");
+        assert_eq!(gpu_label(Some("mem"), "temp3"), "gpu_mem");
+        assert_eq!(gpu_label(Some("Hot Spot"), "temp2"), "gpu_hot_spot");
+        // radeon
+        assert_eq!(gpu_label(None, "temp1"), "gpu_edge");
+        assert_eq!(gpu_label(None, "temp2"), "gpu_temp2");
+    }
+}
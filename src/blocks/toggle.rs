@@ -2,10 +2,11 @@
 //!
 //! You can add commands to be executed to disable the toggle (`command_off`), and to enable it
 //! (`command_on`). If these command exit with a non-zero status, the block will not be toggled and
-//! the block state will be changed to give a visual warning of the failure. You also need to
-//! specify a command to determine the state of the toggle (`command_state`). When the command outputs
-//! nothing, the toggle is disabled, otherwise enabled. By specifying the interval property you can
-//! let the command_state be executed continuously.
+//! the block will be critical for a few seconds to give a visual warning of the failure. You also
+//! need to specify a command to determine the state of the toggle (`command_state`). When the
+//! command exits successfully and outputs something, the toggle is enabled, otherwise disabled.
+//! `command_state` is run again right after every successful toggle. By specifying the interval
+//! property you can let the command_state be executed continuously.
 //!
 //! To run those commands, the shell form `$SHELL` environment variable is used. If such variable
 //! is not presented, `sh` is used.
@@ -14,10 +15,11 @@
 //!
 //! Key | Values | Default
 //! ----|--------|--------
-//! `format` | A string to customise the output of this block. See below for available placeholders | `" $icon "`
+//! `format` | A string to customise the output of this block. See below for available placeholders | `" $icon{ $text\|} "`
+//! `text` | A static text, available as the `text` placeholder | None
 //! `command_on` | Shell command to enable the toggle | Yes | N/A
 //! `command_off` | Shell command to disable the toggle | Yes | N/A
-//! `command_state` | Shell command to determine the state. Empty output => No, otherwise => Yes. | **Required**
+//! `command_state` | Shell command to determine the state. Non-empty output and a zero exit status => Yes, otherwise => No. | **Required**
 //! `icon_on` | Icon override for the toggle button while on | `"toggle_on"`
 //! `icon_off` | Icon override for the toggle button while off | `"toggle_off"`
 //! `interval` | Update interval in seconds. If not set, `command_state` will run only on click. | None
@@ -25,6 +27,7 @@
 //! Placeholder   | Value                                       | Type   | Unit
 //! --------------|---------------------------------------------|--------|-----
 //! `icon`        | Icon based on toggle's state                | Icon   | -
+//! `text`        | The value of `text`. Absent if it is not set. | Text   | -
 //!
 //! Action   | Default button
 //! ---------|---------------
@@ -37,7 +40,7 @@
 //! ```toml
 //! [[block]]
 //! block = "toggle"
-//! format = " $icon 4k "
+//! text = "4k"
 //! command_state = "xrandr | grep 'DP1 connected 38' | grep -v eDP1"
 //! command_on = "~/.screenlayout/4kmon_default.sh"
 //! command_off = "~/.screenlayout/builtin.sh"
@@ -48,17 +51,24 @@
 //! - `toggle_off`
 //! - `toggle_on`
 
-use super::prelude::*;
 use std::env;
-use tokio::process::Command;
+use std::process::Output;
+
+use super::prelude::*;
+
+/// How long the block stays critical after a failed toggle
+const FAILURE_DURATION: Duration = Duration::from_secs(5);
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
+    #[serde(default)]
     format: FormatConfig,
     command_on: String,
     command_off: String,
     command_state: String,
     #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
     icon_on: Option<String>,
     #[serde(default)]
     icon_off: Option<String>,
@@ -71,7 +81,7 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         .await?;
 
     let interval = config.interval.map(Duration::from_secs);
-    let mut widget = Widget::new().with_format(config.format.with_default(" $icon ")?);
+    let mut widget = Widget::new().with_format(config.format.with_default(" $icon{ $text|} ")?);
 
     let icon_on = config.icon_on.unwrap_or_else(|| "toggle_on".into());
     let icon_off = config.icon_off.unwrap_or_else(|| "toggle_off".into());
@@ -80,57 +90,37 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     // 1) `SHELL` environment varialble
     // 2) `"sh"`
     let shell = env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+    let commands = api.command_context.clone();
+    let run_shell = |cmd: &str| commands.command(&shell).args(["-c", cmd]).output();
+
+    // When the block stops being critical after a failed toggle
+    let mut failed_until = None;
 
     loop {
-        // Check state
-        let output = Command::new(&shell)
-            .args(["-c", &config.command_state])
-            .output()
+        let output = run_shell(&config.command_state)
             .await
             .error("Failed to run command_state")?;
-        let is_toggled = !std::str::from_utf8(&output.stdout)
-            .error("The output of command_state is invalid UTF-8")?
-            .trim()
-            .is_empty();
-
-        widget.set_values(map!(
-            "icon" => Value::icon(
-                api.get_icon(if is_toggled { &icon_on } else { &icon_off })?
-            )
-        ));
-        api.set_widget(&widget).await?;
-
-        // TODO: try not to duplicate code
+        let is_toggled = is_on(&output)?;
+
         loop {
-            match interval {
-                Some(interval) => {
-                    select! {
-                        _ = sleep(interval) => break,
-                        event = api.event() => match event {
-                            UpdateRequest => break,
-                            Action(a) if a == "toggle" => {
-                                let cmd = if is_toggled {
-                                    &config.command_off
-                                } else {
-                                    &config.command_on
-                                };
-                                let output = Command::new(&shell)
-                                    .args(["-c", cmd])
-                                    .output()
-                                    .await
-                                    .error("Failed to run command")?;
-                                if output.status.success() {
-                                    widget.state = State::Idle;
-                                    break;
-                                } else {
-                                    widget.state = State::Critical;
-                                }
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-                None => match api.event().await {
+            let mut values = map! {
+                "icon" => Value::icon(api.get_icon(if is_toggled { &icon_on } else { &icon_off })?),
+            };
+            if let Some(text) = &config.text {
+                values.insert("text".into(), Value::text(text.clone()));
+            }
+            widget.set_values(values);
+            widget.state = if failed_until.is_some() {
+                State::Critical
+            } else {
+                State::Idle
+            };
+            api.set_widget(&widget).await?;
+
+            select! {
+                _ = sleep_or_pending(interval) => break,
+                _ = sleep_until_or_pending(failed_until) => failed_until = None,
+                event = api.event() => match event {
                     UpdateRequest => break,
                     Action(a) if a == "toggle" => {
                         let cmd = if is_toggled {
@@ -138,21 +128,61 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                         } else {
                             &config.command_on
                         };
-                        let output = Command::new(&shell)
-                            .args(["-c", cmd])
-                            .output()
-                            .await
-                            .error("Failed to run command")?;
+                        let output = run_shell(cmd).await.error("Failed to run command")?;
                         if output.status.success() {
-                            widget.state = State::Idle;
+                            failed_until = None;
+                            // Check the state which the command has set
                             break;
-                        } else {
-                            widget.state = State::Critical;
                         }
+                        // Keep the icon, since nothing was toggled
+                        failed_until = Some(tokio::time::Instant::now() + FAILURE_DURATION);
                     }
                     _ => (),
-                },
+                }
             }
         }
     }
 }
+
+/// Whether the output of `command_state` means that the toggle is enabled
+fn is_on(output: &Output) -> Result<bool> {
+    let stdout = std::str::from_utf8(&output.stdout)
+        .error("The output of command_state is invalid UTF-8")?;
+    Ok(output.status.success() && !stdout.trim().is_empty())
+}
+
+async fn sleep_or_pending(duration: Option<Duration>) {
+    match duration {
+        Some(duration) => sleep(duration).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn sleep_until_or_pending(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(cmd: &str) -> bool {
+        let output = std::process::Command::new("sh")
+            .args(["-c", cmd])
+            .output()
+            .unwrap();
+        is_on(&output).unwrap()
+    }
+
+    #[test]
+    fn command_state() {
+        assert!(state("echo up"));
+        assert!(!state("true"));
+        assert!(!state("echo '  '"));
+        // Output of a failing command does not count
+        assert!(!state("echo 'Error: not connected'; exit 1"));
+    }
+}
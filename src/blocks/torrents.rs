@@ -0,0 +1,198 @@
+//! Torrent client status
+//!
+//! Shows the number of active torrents and aggregate transfer rates of a
+//! [Transmission](https://transmissionbt.com/) daemon or a [qBittorrent](https://www.qbittorrent.org/)
+//! instance with WebUI enabled.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `driver` | One of `"transmission"` or `"qbittorrent"` | `"transmission"`
+//! `url` | URL of the Transmission RPC endpoint or of the qBittorrent WebUI | `"http://localhost:9091/transmission/rpc"` for Transmission, `"http://localhost:8080"` for qBittorrent
+//! `username` | Username used to authenticate | `None`
+//! `password` | Password used to authenticate | `None`
+//! `password_command` | Shell command which outputs the password (used if `password` is not set) | `None`
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $active.eng(w:1) ^icon_net_down $speed_down.eng(prefix:K) ^icon_net_up $speed_up.eng(prefix:K) "`
+//! `interval` | Update interval in seconds | `10`
+//! `hide_when_idle` | Hide the block if nothing is being downloaded or uploaded | `false`
+//!
+//! Placeholder  | Value                                          | Type   | Unit
+//! -------------|------------------------------------------------|--------|-----
+//! `icon`       | Icon based on the alternative speed mode       | Icon   | -
+//! `active`     | Number of downloading torrents                 | Number | -
+//! `seeding`    | Number of completed torrents which are seeding | Number | -
+//! `errors`     | Number of torrents with an error               | Number | -
+//! `speed_down` | Total download rate                            | Number | Bytes per second
+//! `speed_up`   | Total upload rate                              | Number | Bytes per second
+//! `alt_speed`  | Present if alternative speed limits are on     | Flag   | -
+//!
+//! The block's state is set to warning if any torrent has an error. If the client rejects the
+//! credentials, the block displays "auth" in warning state.
+//!
+//! Action             | Description                                                   | Default button
+//! -------------------|---------------------------------------------------------------|---------------
+//! `toggle_alt_speed` | Toggle alternative speed limits ("turtle mode" in Transmission) | Left
+//!
+//! # Examples
+//!
+//! ```toml
+//! [[block]]
+//! block = "torrents"
+//! username = "admin"
+//! password_command = "pass show transmission"
+//! hide_when_idle = true
+//! ```
+//!
+//! ```toml
+//! [[block]]
+//! block = "torrents"
+//! driver = "qbittorrent"
+//! url = "http://localhost:8080"
+//! format = " $icon $active/$seeding "
+//! ```
+//!
+//! # Icons Used
+//! - `torrent`
+//! - `torrent_alt_speed`
+//! - `net_down`
+//! - `net_up`
+
+mod qbittorrent;
+mod transmission;
+
+use super::prelude::*;
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    driver: Driver,
+    url: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    password_command: Option<String>,
+    format: FormatConfig,
+    #[default(10.into())]
+    interval: Seconds,
+    hide_when_idle: bool,
+}
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(rename_all = "lowercase")]
+enum Driver {
+    #[default]
+    Transmission,
+    Qbittorrent,
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[(MouseButton::Left, None, "toggle_alt_speed")])
+        .await?;
+
+    let format = config.format.with_default(
+        " $icon $active.eng(w:1) ^icon_net_down $speed_down.eng(prefix:K) ^icon_net_up $speed_up.eng(prefix:K) ",
+    )?;
+    let auth_format = FormatConfig::default().with_default(" $icon auth ")?;
+    let mut widget = Widget::new();
+
+    let password = match (config.password, config.password_command) {
+        (Some(password), _) => Some(password),
+        (None, Some(cmd)) => Some(run_password_command(&api.command_context, &cmd).await?),
+        (None, None) => None,
+    };
+    let credentials = config.username.map(|u| (u, password.unwrap_or_default()));
+
+    let client: Box<dyn TorrentClient + Send + Sync> = match config.driver {
+        Driver::Transmission => Box::new(transmission::Client::new(config.url, credentials)),
+        Driver::Qbittorrent => Box::new(qbittorrent::Client::new(config.url, credentials)),
+    };
+
+    loop {
+        match api.recoverable(|| client.status()).await? {
+            Some(status) => {
+                if config.hide_when_idle && status.is_idle() {
+                    api.hide().await?;
+                } else {
+                    let icon = if status.alt_speed {
+                        "torrent_alt_speed"
+                    } else {
+                        "torrent"
+                    };
+                    widget.set_format(format.clone());
+                    widget.state = if status.errors > 0 {
+                        State::Warning
+                    } else {
+                        State::Idle
+                    };
+                    widget.set_values(map! {
+                        "icon" => Value::icon(api.get_icon(icon)?),
+                        "active" => Value::number(status.downloading),
+                        "seeding" => Value::number(status.seeding),
+                        "errors" => Value::number(status.errors),
+                        "speed_down" => Value::bytes(status.speed_down),
+                        "speed_up" => Value::bytes(status.speed_up),
+                        [if status.alt_speed] "alt_speed" => Value::flag(),
+                    });
+                    api.set_widget(&widget).await?;
+                }
+            }
+            None => {
+                widget.set_format(auth_format.clone());
+                widget.state = State::Warning;
+                widget.set_values(map!("icon" => Value::icon(api.get_icon("torrent")?)));
+                api.set_widget(&widget).await?;
+            }
+        }
+
+        select! {
+            _ = sleep(config.interval.0) => (),
+            event = api.event() => match event {
+                Action(a) if a == "toggle_alt_speed" => {
+                    api.recoverable(|| client.toggle_alt_speed()).await?;
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+async fn run_password_command(commands: &CommandContext, cmd: &str) -> Result<String> {
+    let output = commands
+        .shell(cmd)
+        .output()
+        .await
+        .error("Failed to run password_command")?;
+    if !output.status.success() {
+        return Err(Error::new("password_command failed"));
+    }
+    let password =
+        String::from_utf8(output.stdout).error("password_command produced non-UTF8 output")?;
+    Ok(password.trim_end_matches('\n').to_string())
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Status {
+    downloading: usize,
+    seeding: usize,
+    errors: usize,
+    /// Bytes per second
+    speed_down: f64,
+    /// Bytes per second
+    speed_up: f64,
+    alt_speed: bool,
+}
+
+impl Status {
+    fn is_idle(&self) -> bool {
+        self.downloading == 0 && self.speed_down == 0.0 && self.speed_up == 0.0
+    }
+}
+
+#[async_trait]
+trait TorrentClient {
+    /// Returns `None` if the client rejected the credentials
+    async fn status(&self) -> Result<Option<Status>>;
+
+    /// Toggle alternative speed limits. Does nothing if the client rejected the credentials.
+    async fn toggle_alt_speed(&self) -> Result<()>;
+}
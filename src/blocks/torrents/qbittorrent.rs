@@ -0,0 +1,189 @@
+//! qBittorrent WebUI API client
+//!
+//! See <https://github.com/qbittorrent/qBittorrent/wiki/WebUI-API-(qBittorrent-4.1)>
+
+use super::{Status, TorrentClient};
+use crate::blocks::prelude::*;
+use reqwest::header::{COOKIE, SET_COOKIE};
+use reqwest::{Method, StatusCode};
+use std::sync::Mutex;
+
+const DEFAULT_URL: &str = "http://localhost:8080";
+
+pub(super) struct Client {
+    url: String,
+    credentials: Option<(String, String)>,
+    sid: Mutex<Option<String>>,
+}
+
+impl Client {
+    pub(super) fn new(url: Option<String>, credentials: Option<(String, String)>) -> Self {
+        let url = url.unwrap_or_else(|| DEFAULT_URL.into());
+        Self {
+            url: url.trim_end_matches('/').into(),
+            credentials,
+            sid: Mutex::new(None),
+        }
+    }
+
+    /// Log in and remember the session cookie. Returns `false` if the credentials were rejected.
+    async fn login(&self) -> Result<bool> {
+        let (username, password) = match &self.credentials {
+            Some((username, password)) => (username.as_str(), password.as_str()),
+            None => ("", ""),
+        };
+        let response = REQWEST_CLIENT
+            .post(format!("{}/api/v2/auth/login", self.url))
+            // qBittorrent rejects login requests without a matching Referer
+            .header(reqwest::header::REFERER, &self.url)
+            .form(&[("username", username), ("password", password)])
+            .send()
+            .await
+            .error("Failed to send login request to qBittorrent")?;
+        let sid = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|cookie| cookie.to_str().ok())
+            .find_map(sid_from_cookie);
+        let text = response
+            .text()
+            .await
+            .error("Failed to read qBittorrent response")?;
+        if text.trim() == "Fails." {
+            return Ok(false);
+        }
+        *self.sid.lock().unwrap() = sid;
+        Ok(true)
+    }
+
+    /// Send an API request. Returns `None` if the credentials were rejected.
+    async fn request(&self, method: Method, path: &str) -> Result<Option<String>> {
+        // The session may have expired, in which case log in again and retry once
+        for _ in 0..2 {
+            let mut request = REQWEST_CLIENT.request(method.clone(), format!("{}{path}", self.url));
+            if let Some(sid) = self.sid.lock().unwrap().as_deref() {
+                request = request.header(COOKIE, format!("SID={sid}"));
+            }
+            let response = request
+                .send()
+                .await
+                .error("Failed to send request to qBittorrent")?;
+            match response.status() {
+                StatusCode::FORBIDDEN => {
+                    if !self.login().await? {
+                        return Ok(None);
+                    }
+                }
+                status if status.is_success() => {
+                    return response
+                        .text()
+                        .await
+                        .map(Some)
+                        .error("Failed to read qBittorrent response");
+                }
+                status => {
+                    return Err(Error::new(format!(
+                        "qBittorrent returned unexpected HTTP status {status}"
+                    )))
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl TorrentClient for Client {
+    async fn status(&self) -> Result<Option<Status>> {
+        let torrents = match self.request(Method::GET, "/api/v2/torrents/info").await? {
+            Some(torrents) => torrents,
+            None => return Ok(None),
+        };
+        let torrents: Vec<Torrent> =
+            serde_json::from_str(&torrents).error("Failed to parse qBittorrent torrent list")?;
+        let mode = self
+            .request(Method::GET, "/api/v2/transfer/speedLimitsMode")
+            .await?;
+        Ok(mode.map(|mode| status_from_torrents(&torrents, mode.trim() == "1")))
+    }
+
+    async fn toggle_alt_speed(&self) -> Result<()> {
+        self.request(Method::POST, "/api/v2/transfer/toggleSpeedLimitsMode")
+            .await?;
+        Ok(())
+    }
+}
+
+/// Extract the session ID from a `Set-Cookie` header value
+fn sid_from_cookie(cookie: &str) -> Option<String> {
+    let (name, value) = cookie.split(';').next()?.split_once('=')?;
+    (name.trim() == "SID").then(|| value.trim().to_string())
+}
+
+#[derive(Deserialize, Debug)]
+struct Torrent {
+    state: String,
+    /// Bytes per second
+    dlspeed: f64,
+    /// Bytes per second
+    upspeed: f64,
+}
+
+fn status_from_torrents(torrents: &[Torrent], alt_speed: bool) -> Status {
+    let mut status = Status {
+        alt_speed,
+        ..Default::default()
+    };
+    for torrent in torrents {
+        match torrent.state.as_str() {
+            "downloading" | "forcedDL" | "stalledDL" | "metaDL" | "queuedDL" | "checkingDL"
+            | "allocating" => status.downloading += 1,
+            "uploading" | "stalledUP" | "forcedUP" | "queuedUP" => status.seeding += 1,
+            "error" | "missingFiles" => status.errors += 1,
+            _ => (),
+        }
+        status.speed_down += torrent.dlspeed;
+        status.speed_up += torrent.upspeed;
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cookie() {
+        assert_eq!(
+            sid_from_cookie("SID=hBc7TxF76ERhvIw0jQQ4LZ7Z1jQUV0tQ; HttpOnly; path=/"),
+            Some("hBc7TxF76ERhvIw0jQQ4LZ7Z1jQUV0tQ".into())
+        );
+        assert_eq!(sid_from_cookie("SID=abc"), Some("abc".into()));
+        assert_eq!(sid_from_cookie("other=abc; path=/"), None);
+        assert_eq!(sid_from_cookie("garbage"), None);
+    }
+
+    #[test]
+    fn torrents_info() {
+        let text = r#"[
+            {"name": "a", "state": "downloading", "dlspeed": 1000, "upspeed": 10},
+            {"name": "b", "state": "stalledUP", "dlspeed": 0, "upspeed": 200},
+            {"name": "c", "state": "missingFiles", "dlspeed": 0, "upspeed": 0},
+            {"name": "d", "state": "pausedUP", "dlspeed": 0, "upspeed": 0},
+            {"name": "e", "state": "metaDL", "dlspeed": 5, "upspeed": 0}
+        ]"#;
+        let torrents: Vec<Torrent> = serde_json::from_str(text).unwrap();
+        assert_eq!(
+            status_from_torrents(&torrents, false),
+            Status {
+                downloading: 2,
+                seeding: 1,
+                errors: 1,
+                speed_down: 1005.0,
+                speed_up: 210.0,
+                alt_speed: false,
+            }
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Transmission RPC client
+//!
+//! See <https://github.com/transmission/transmission/blob/main/docs/rpc-spec.md>
+
+use super::{Status, TorrentClient};
+use crate::blocks::prelude::*;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use std::sync::Mutex;
+
+const DEFAULT_URL: &str = "http://localhost:9091/transmission/rpc";
+const SESSION_ID_HEADER: &str = "X-Transmission-Session-Id";
+
+pub(super) struct Client {
+    url: String,
+    credentials: Option<(String, String)>,
+    session_id: Mutex<Option<String>>,
+}
+
+impl Client {
+    pub(super) fn new(url: Option<String>, credentials: Option<(String, String)>) -> Self {
+        Self {
+            url: url.unwrap_or_else(|| DEFAULT_URL.into()),
+            credentials,
+            session_id: Mutex::new(None),
+        }
+    }
+
+    /// Call an RPC method. Returns `None` if the credentials were rejected.
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        arguments: serde_json::Value,
+    ) -> Result<Option<T>> {
+        let body = json!({ "method": method, "arguments": arguments });
+
+        // The first request may be rejected with a new session ID
+        for _ in 0..2 {
+            let mut request = REQWEST_CLIENT.post(&self.url).json(&body);
+            if let Some(session_id) = self.session_id.lock().unwrap().as_deref() {
+                request = request.header(SESSION_ID_HEADER, session_id);
+            }
+            if let Some((username, password)) = &self.credentials {
+                request = request.basic_auth(username, Some(password));
+            }
+
+            let response = request
+                .send()
+                .await
+                .error("Failed to send request to Transmission")?;
+            let status = response.status().as_u16();
+            let session_id = response
+                .headers()
+                .get(SESSION_ID_HEADER)
+                .and_then(|id| id.to_str().ok())
+                .map(String::from);
+            let text = response
+                .text()
+                .await
+                .error("Failed to read Transmission response")?;
+
+            match parse_reply(status, session_id, &text)? {
+                Reply::Ok(arguments) => return Ok(Some(arguments)),
+                Reply::NewSessionId(id) => *self.session_id.lock().unwrap() = Some(id),
+                Reply::AuthFailed => return Ok(None),
+            }
+        }
+
+        Err(Error::new("Transmission rejected the session ID"))
+    }
+}
+
+#[async_trait]
+impl TorrentClient for Client {
+    async fn status(&self) -> Result<Option<Status>> {
+        let torrents: Option<TorrentGet> = self
+            .call(
+                "torrent-get",
+                json!({ "fields": ["status", "error", "percentDone", "rateDownload", "rateUpload"] }),
+            )
+            .await?;
+        let torrents = match torrents {
+            Some(torrents) => torrents,
+            None => return Ok(None),
+        };
+        let session: Option<SessionGet> = self
+            .call("session-get", json!({ "fields": ["alt-speed-enabled"] }))
+            .await?;
+        Ok(session.map(|session| status_from_torrents(&torrents.torrents, session.alt_speed)))
+    }
+
+    async fn toggle_alt_speed(&self) -> Result<()> {
+        let session: Option<SessionGet> = self
+            .call("session-get", json!({ "fields": ["alt-speed-enabled"] }))
+            .await?;
+        if let Some(session) = session {
+            self.call::<serde_json::Value>(
+                "session-set",
+                json!({ "alt-speed-enabled": !session.alt_speed }),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Reply<T> {
+    Ok(T),
+    /// The server requires a (new) session ID, retry the request with it
+    NewSessionId(String),
+    AuthFailed,
+}
+
+#[derive(Deserialize, Debug)]
+struct RpcResponse<T> {
+    result: String,
+    arguments: Option<T>,
+}
+
+fn parse_reply<T: DeserializeOwned>(
+    status: u16,
+    session_id: Option<String>,
+    text: &str,
+) -> Result<Reply<T>> {
+    match status {
+        401 | 403 => Ok(Reply::AuthFailed),
+        409 => session_id
+            .map(Reply::NewSessionId)
+            .error("Transmission did not send a session ID"),
+        200 => {
+            let response: RpcResponse<T> =
+                serde_json::from_str(text).error("Failed to parse Transmission response")?;
+            if response.result != "success" {
+                return Err(Error::new(format!(
+                    "Transmission returned an error: {}",
+                    response.result
+                )));
+            }
+            response
+                .arguments
+                .map(Reply::Ok)
+                .error("Transmission response has no arguments")
+        }
+        other => Err(Error::new(format!(
+            "Transmission returned unexpected HTTP status {other}"
+        ))),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TorrentGet {
+    torrents: Vec<Torrent>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SessionGet {
+    #[serde(rename = "alt-speed-enabled")]
+    alt_speed: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Torrent {
+    /// 0: stopped, 1: queued to verify, 2: verifying, 3: queued to download, 4: downloading,
+    /// 5: queued to seed, 6: seeding
+    status: u8,
+    error: u8,
+    percent_done: f64,
+    rate_download: f64,
+    rate_upload: f64,
+}
+
+fn status_from_torrents(torrents: &[Torrent], alt_speed: bool) -> Status {
+    let mut status = Status {
+        alt_speed,
+        ..Default::default()
+    };
+    for torrent in torrents {
+        match torrent.status {
+            3 | 4 => status.downloading += 1,
+            5 | 6 if torrent.percent_done >= 1.0 => status.seeding += 1,
+            _ => (),
+        }
+        if torrent.error != 0 {
+            status.errors += 1;
+        }
+        status.speed_down += torrent.rate_download;
+        status.speed_up += torrent.rate_upload;
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TORRENT_GET: &str = r#"{
+        "arguments": {
+            "torrents": [
+                {"error": 0, "percentDone": 0.5, "rateDownload": 1000, "rateUpload": 10, "status": 4},
+                {"error": 0, "percentDone": 1, "rateDownload": 0, "rateUpload": 200, "status": 6},
+                {"error": 3, "percentDone": 0.1, "rateDownload": 0, "rateUpload": 0, "status": 0},
+                {"error": 0, "percentDone": 1, "rateDownload": 0, "rateUpload": 0, "status": 0}
+            ]
+        },
+        "result": "success"
+    }"#;
+
+    #[test]
+    fn session_id_handshake() {
+        let reply = parse_reply::<serde_json::Value>(409, Some("abc".into()), "").unwrap();
+        assert_eq!(reply, Reply::NewSessionId("abc".into()));
+        assert!(parse_reply::<serde_json::Value>(409, None, "").is_err());
+    }
+
+    #[test]
+    fn auth_failed() {
+        let reply = parse_reply::<serde_json::Value>(401, None, "Unauthorized").unwrap();
+        assert_eq!(reply, Reply::AuthFailed);
+    }
+
+    #[test]
+    fn rpc_error() {
+        let text = r#"{"arguments": {}, "result": "no such method"}"#;
+        assert!(parse_reply::<serde_json::Value>(200, None, text).is_err());
+    }
+
+    #[test]
+    fn torrent_get() {
+        let torrents = match parse_reply::<TorrentGet>(200, None, TORRENT_GET).unwrap() {
+            Reply::Ok(torrents) => torrents,
+            other => panic!("unexpected reply: {other:?}"),
+        };
+        let status = status_from_torrents(&torrents.torrents, true);
+        assert_eq!(
+            status,
+            Status {
+                downloading: 1,
+                seeding: 1,
+                errors: 1,
+                speed_down: 1000.0,
+                speed_up: 210.0,
+                alt_speed: true,
+            }
+        );
+    }
+
+    #[test]
+    fn session_get() {
+        let text = r#"{"arguments": {"alt-speed-enabled": true}, "result": "success"}"#;
+        match parse_reply::<SessionGet>(200, None, text).unwrap() {
+            Reply::Ok(session) => assert!(session.alt_speed),
+            other => panic!("unexpected reply: {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,469 @@
+//! Time-based one-time passwords
+//!
+//! Shows the current [TOTP](https://www.rfc-editor.org/rfc/rfc6238) code of one or more accounts.
+//!
+//! **Showing login codes in the bar is a security tradeoff:** anyone who can see your screen (or
+//! read the bar's output) can use them. Only use this block for low-risk accounts. It is not
+//! compiled in by default; build with `--features totp` to enable it.
+//!
+//! Secrets are never part of the configuration. Instead, `secret_command` is run each time a new
+//! code is needed, and must print the base32-encoded secret or an `otpauth://` URI on its first
+//! line. The secret is wiped from memory right after computing the code. Codes are hidden until
+//! the block is clicked, and hidden again after `reveal_duration`.
+//!
+//! # Configuration
+//!
+//! Key | Values | Default
+//! ----|--------|--------
+//! `accounts` | A list of accounts, see below. At least one is required. | `[]`
+//! `format` | A string to customise the output of this block. See below for available placeholders. | `" $icon $name $code{ $remaining.eng(w:2)\|} "`
+//! `reveal_duration` | Hide the code again this many seconds after it was revealed | `15`
+//!
+//! Account key | Values | Default
+//! ------------|--------|--------
+//! `name` | Name of the account | **Required**
+//! `secret_command` | Shell command which prints the secret, e.g. `"pass show otp/github \| head -1"` | **Required**
+//! `algorithm` | One of `"sha1"` or `"sha256"` | `"sha1"`
+//! `digits` | Number of digits of the code (6 to 9) | `6`
+//! `period` | How long a code is valid, in seconds | `30`
+//!
+//! Placeholder | Value                                                       | Type   | Unit
+//! ------------|-------------------------------------------------------------|--------|-----
+//! `icon`      | A static icon                                               | Icon   | -
+//! `name`      | Name of the shown account                                   | Text   | -
+//! `code`      | The current code, or `*` characters while hidden            | Text   | -
+//! `remaining` | Seconds until the code changes. Absent while hidden         | Number | s
+//! `revealed`  | Present while the code is shown                             | Flag   | -
+//!
+//! The block's state is set to warning during the last five seconds of a revealed code.
+//!
+//! Action   | Description                          | Default button
+//! ---------|--------------------------------------|---------------
+//! `reveal` | Show or hide the code                | Left
+//! `next`   | Switch to the next account           | Wheel Down
+//! `prev`   | Switch to the previous account       | Wheel Up
+//!
+//! # Example
+//!
+//! ```toml
+//! [[block]]
+//! block = "totp"
+//! reveal_duration = 30
+//! [[block.accounts]]
+//! name = "GitHub"
+//! secret_command = "pass show otp/github | head -1"
+//! [[block.accounts]]
+//! name = "Forum"
+//! secret_command = "secret-tool lookup totp forum"
+//! algorithm = "sha256"
+//! digits = 8
+//! ```
+//!
+//! # Icons Used
+//! - `totp`
+
+mod sha256;
+
+use super::prelude::*;
+use sha1::{Digest, Sha1};
+use std::sync::atomic::{compiler_fence, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    accounts: Vec<Account>,
+    format: FormatConfig,
+    #[default(15.into())]
+    reveal_duration: Seconds,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Account {
+    name: String,
+    secret_command: String,
+    #[serde(default)]
+    algorithm: Algorithm,
+    #[serde(default = "default_digits")]
+    digits: u32,
+    #[serde(default = "default_period")]
+    period: u64,
+}
+
+fn default_digits() -> u32 {
+    6
+}
+
+fn default_period() -> u64 {
+    30
+}
+
+#[derive(Deserialize, Debug, SmartDefault, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
+    api.set_default_actions(&[
+        (MouseButton::Left, None, "reveal"),
+        (MouseButton::WheelDown, None, "next"),
+        (MouseButton::WheelUp, None, "prev"),
+    ])
+    .await?;
+
+    let mut widget = Widget::new().with_format(
+        config
+            .format
+            .with_default(" $icon $name $code{ $remaining.eng(w:2)|} ")?,
+    );
+
+    if config.accounts.is_empty() {
+        return Err(Error::new("At least one account is required"));
+    }
+    let commands = api.command_context.clone();
+    for account in &config.accounts {
+        if !(6..=9).contains(&account.digits) {
+            return Err(Error::new(format!(
+                "{}: digits must be between 6 and 9",
+                account.name
+            )));
+        }
+        if account.period == 0 {
+            return Err(Error::new(format!(
+                "{}: period must not be 0",
+                account.name
+            )));
+        }
+    }
+
+    let mut current = 0;
+    let mut reveal = Reveal::default();
+    // The code shown last, with its account and time step, so the secret is only read once per
+    // code
+    let mut shown: Option<(usize, u64, String)> = None;
+
+    loop {
+        let account = &config.accounts[current];
+        let unix_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .error("The system clock is before 1970")?
+            .as_secs();
+        let step = unix_time / account.period;
+        let remaining = account.period - unix_time % account.period;
+        let revealed = reveal.update(Instant::now());
+
+        if !revealed {
+            shown = None;
+        } else if !matches!(&shown, Some((a, s, _)) if *a == current && *s == step) {
+            let mut secret = api.recoverable(|| account.secret(&commands)).await?;
+            let code = hotp(&secret, step, account.digits, account.algorithm);
+            secret.clear();
+            shown = Some((current, step, code));
+        }
+
+        widget.state = if revealed && remaining <= 5 {
+            State::Warning
+        } else {
+            State::Idle
+        };
+        widget.set_values(map! {
+            "icon" => Value::icon(api.get_icon("totp")?),
+            "name" => Value::text(account.name.clone()),
+            "code" => Value::text(match &shown {
+                Some((_, _, code)) => code.clone(),
+                None => "*".repeat(account.digits as usize),
+            }),
+            [if revealed] "remaining" => Value::seconds(remaining),
+            [if revealed] "revealed" => Value::flag(),
+        });
+        api.set_widget(&widget).await?;
+
+        // Count down every second while revealed, otherwise wake up when the code changes
+        let timeout = if revealed {
+            Duration::from_secs(1)
+        } else {
+            Duration::from_secs(remaining)
+        };
+        select! {
+            _ = sleep(timeout) => (),
+            event = api.event() => match event {
+                Action(a) if a == "reveal" => reveal.toggle(Instant::now(), config.reveal_duration.0),
+                Action(a) if a == "next" => current = (current + 1) % config.accounts.len(),
+                Action(a) if a == "prev" => {
+                    current = (current + config.accounts.len() - 1) % config.accounts.len();
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Account {
+    async fn secret(&self, commands: &CommandContext) -> Result<Secret> {
+        let mut output = commands
+            .shell(&self.secret_command)
+            .output()
+            .await
+            .or_error(|| format!("{}: failed to run secret_command", self.name))?;
+        if !output.status.success() {
+            zeroize(&mut output.stdout);
+            return Err(Error::new(format!(
+                "{}: secret_command exited with {}",
+                self.name, output.status
+            )));
+        }
+        Secret::from_output(&mut output.stdout)
+            .or_error(|| format!("{}: invalid secret", self.name))
+    }
+}
+
+/// Whether the code is shown
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Reveal {
+    #[default]
+    Hidden,
+    Revealed {
+        until: Instant,
+    },
+}
+
+impl Reveal {
+    fn toggle(&mut self, now: Instant, duration: Duration) {
+        *self = match self {
+            Self::Hidden => Self::Revealed {
+                until: now + duration,
+            },
+            Self::Revealed { .. } => Self::Hidden,
+        };
+    }
+
+    /// Hide the code if the reveal timed out. Returns whether the code is shown.
+    fn update(&mut self, now: Instant) -> bool {
+        match *self {
+            Self::Revealed { until } if now >= until => {
+                *self = Self::Hidden;
+                false
+            }
+            Self::Revealed { .. } => true,
+            Self::Hidden => false,
+        }
+    }
+}
+
+/// Overwrite a buffer with zeros in a way the compiler does not optimize away
+fn zeroize(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        // SAFETY: `byte` is a valid, aligned reference
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// A decoded secret, which is wiped when dropped
+struct Secret(Vec<u8>);
+
+impl Secret {
+    /// Decode the output of `secret_command`: a base32 secret or an `otpauth://` URI on the first
+    /// line. `output` is wiped, also if decoding fails.
+    fn from_output(output: &mut [u8]) -> Result<Self> {
+        let result = Self::decode(output);
+        zeroize(output);
+        result
+    }
+
+    fn decode(output: &[u8]) -> Result<Self> {
+        let line = output.split(|&b| b == b'\n').next().unwrap_or_default();
+        let encoded = match line.strip_prefix(b"otpauth://") {
+            Some(uri) => {
+                let query = uri.splitn(2, |&b| b == b'?').nth(1).unwrap_or_default();
+                query
+                    .split(|&b| b == b'&')
+                    .find_map(|param| param.strip_prefix(b"secret="))
+                    .error("the URI has no secret")?
+            }
+            None => line,
+        };
+        let mut normalized: Vec<u8> = encoded
+            .iter()
+            .filter(|b| !b.is_ascii_whitespace() && **b != b'=')
+            .map(u8::to_ascii_uppercase)
+            .collect();
+        let decoded = data_encoding::BASE32_NOPAD.decode(&normalized);
+        zeroize(&mut normalized);
+        let secret = decoded.ok().error("the secret is not valid base32")?;
+        if secret.is_empty() {
+            return Err(Error::new("the secret is empty"));
+        }
+        Ok(Self(secret))
+    }
+
+    fn clear(&mut self) {
+        zeroize(&mut self.0);
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl std::ops::Deref for Secret {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Algorithm {
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha1 => Sha1::digest(data).to_vec(),
+            Self::Sha256 => sha256::digest(data).to_vec(),
+        }
+    }
+}
+
+/// HMAC (RFC 2104). Both SHA-1 and SHA-256 use 64 byte blocks.
+fn hmac(algorithm: Algorithm, key: &[u8], message: &[u8]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut key_block = [0; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        let mut hashed = algorithm.digest(key);
+        key_block[..hashed.len()].copy_from_slice(&hashed);
+        zeroize(&mut hashed);
+    } else {
+        key_block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner: Vec<u8> = key_block.iter().map(|b| b ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let inner_hash = algorithm.digest(&inner);
+
+    let mut outer: Vec<u8> = key_block.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend_from_slice(&inner_hash);
+    let result = algorithm.digest(&outer);
+
+    zeroize(&mut key_block);
+    zeroize(&mut inner);
+    zeroize(&mut outer);
+    result
+}
+
+/// The HOTP code (RFC 4226) for `counter`, which is the time step for TOTP
+fn hotp(key: &[u8], counter: u64, digits: u32, algorithm: Algorithm) -> String {
+    let mac = hmac(algorithm, key, &counter.to_be_bytes());
+    let offset = (mac[mac.len() - 1] & 0xf) as usize;
+    let truncated = u32::from_be_bytes(mac[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    let code = truncated % 10u32.pow(digits);
+    format!("{code:0width$}", width = digits as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hotp_rfc4226() {
+        let key = b"12345678901234567890";
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(hotp(key, counter as u64, 6, Algorithm::Sha1), *code);
+        }
+    }
+
+    #[test]
+    fn totp_rfc6238() {
+        let sha1_key = b"12345678901234567890";
+        let sha256_key = b"12345678901234567890123456789012";
+        let vectors = [
+            (59, "94287082", "46119246"),
+            (1111111109, "07081804", "68084774"),
+            (1111111111, "14050471", "67062674"),
+            (1234567890, "89005924", "91819424"),
+            (2000000000, "69279037", "90698825"),
+            (20000000000, "65353130", "77737706"),
+        ];
+        for (time, sha1_code, sha256_code) in vectors {
+            assert_eq!(hotp(sha1_key, time / 30, 8, Algorithm::Sha1), sha1_code);
+            assert_eq!(
+                hotp(sha256_key, time / 30, 8, Algorithm::Sha256),
+                sha256_code
+            );
+        }
+    }
+
+    #[test]
+    fn long_hmac_key() {
+        // RFC 4231 test case 6: the key is longer than a block and is hashed first
+        let key = [0xaa; 131];
+        let mac = hmac(
+            Algorithm::Sha256,
+            &key,
+            b"Test Using Larger Than Block-Size Key - Hash Key First",
+        );
+        let hex: String = mac.iter().map(|b| format!("{b:02x}")).collect();
+        assert_eq!(
+            hex,
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn secrets() {
+        // "12345678901234567890" in base32
+        let mut output = b"GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\nsecond line\n".to_vec();
+        let secret = Secret::from_output(&mut output).unwrap();
+        assert_eq!(&*secret, b"12345678901234567890");
+        assert!(output.iter().all(|&b| b == 0));
+
+        let mut output = b"otpauth://totp/GitHub:me?secret=gezdgnbv&issuer=GitHub".to_vec();
+        assert_eq!(&*Secret::from_output(&mut output).unwrap(), b"12345");
+        assert!(output.iter().all(|&b| b == 0));
+
+        let mut output = b"not base32!".to_vec();
+        assert!(Secret::from_output(&mut output).is_err());
+        assert!(output.iter().all(|&b| b == 0));
+        assert!(Secret::from_output(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn zeroization() {
+        let mut buf = b"secret".to_vec();
+        zeroize(&mut buf);
+        assert_eq!(buf, [0; 6]);
+
+        let mut secret = Secret(b"secret".to_vec());
+        secret.clear();
+        assert_eq!(&*secret, [0; 6]);
+    }
+
+    #[test]
+    fn reveal() {
+        let start = Instant::now();
+        let duration = Duration::from_secs(15);
+        let mut reveal = Reveal::default();
+        assert!(!reveal.update(start));
+
+        reveal.toggle(start, duration);
+        assert!(reveal.update(start));
+        assert!(reveal.update(start + Duration::from_secs(14)));
+        // Hidden again after the timeout
+        assert!(!reveal.update(start + duration));
+        assert_eq!(reveal, Reveal::Hidden);
+
+        // Clicking again hides it right away
+        reveal.toggle(start, duration);
+        reveal.toggle(start + Duration::from_secs(1), duration);
+        assert!(!reveal.update(start + Duration::from_secs(1)));
+    }
+}
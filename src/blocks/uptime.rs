@@ -30,6 +30,7 @@
 //! - Add `time` or `dur` formatter to `src/formatting/formatter.rs`
 
 use super::prelude::*;
+use crate::localization::Text;
 use tokio::fs::read_to_string;
 
 #[derive(Deserialize, Debug, SmartDefault)]
@@ -62,14 +63,22 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         let minutes = seconds / 60;
         seconds %= 60;
 
+        let l = &api.shared_config.localization;
+        let (w, d, h, m, s) = (
+            l.get(Text::WeeksShort),
+            l.get(Text::DaysShort),
+            l.get(Text::HoursShort),
+            l.get(Text::MinutesShort),
+            l.get(Text::SecondsShort),
+        );
         let text = if weeks > 0 {
-            format!("{weeks}w {days}d")
+            format!("{weeks}{w} {days}{d}")
         } else if days > 0 {
-            format!("{days}d {hours}h")
+            format!("{days}{d} {hours}{h}")
         } else if hours > 0 {
-            format!("{hours}h {minutes}m")
+            format!("{hours}{h} {minutes}{m}")
         } else {
-            format!("{minutes}m {seconds}s")
+            format!("{minutes}{m} {seconds}{s}")
         };
 
         widget.set_values(map! {
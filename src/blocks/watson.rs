@@ -39,6 +39,7 @@ use std::path::PathBuf;
 use tokio::fs::read_to_string;
 
 use super::prelude::*;
+use crate::localization::{Localization, Text, TimeUnit};
 
 #[derive(Deserialize, Debug, SmartDefault)]
 #[serde(default)]
@@ -95,7 +96,7 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             state @ WatsonState::Active { .. } => {
                 widget.state = State::Good;
                 widget.set_values(map!(
-                  "text" => Value::text(state.format(show_time, "started", format_delta_past))
+                  "text" => Value::text(state.format(show_time, "started", format_delta_past, &api.shared_config.localization))
                 ));
                 prev_state = Some(state);
             }
@@ -105,7 +106,7 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                     // tracking. This means that we could show some statistics.
                     widget.state = State::Idle;
                     widget.set_values(map!(
-                      "text" => Value::text(prev.format(true, "stopped", format_delta_after))
+                      "text" => Value::text(prev.format(true, "stopped", format_delta_after, &api.shared_config.localization))
                     ));
                 } else {
                     // File is empty which means that there is currently no active time tracking,
@@ -142,36 +143,36 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     }
 }
 
-fn format_delta_past(delta: &chrono::Duration) -> String {
+fn format_delta_past(delta: &chrono::Duration, localization: &Localization) -> String {
     let spans = &[
-        ("week", delta.num_weeks()),
-        ("day", delta.num_days()),
-        ("hour", delta.num_hours()),
-        ("minute", delta.num_minutes()),
+        (TimeUnit::Week, delta.num_weeks()),
+        (TimeUnit::Day, delta.num_days()),
+        (TimeUnit::Hour, delta.num_hours()),
+        (TimeUnit::Minute, delta.num_minutes()),
     ];
 
     spans
         .iter()
         .filter(|&(_, n)| *n != 0)
-        .map(|&(label, n)| format!("{n} {label}{} ago", if n > 1 { "s" } else { "" }))
+        .map(|&(unit, n)| localization.relative(Text::Ago, &localization.amount(n, unit)))
         .next()
-        .unwrap_or_else(|| "now".into())
+        .unwrap_or_else(|| localization.get(Text::Now).into())
 }
 
-fn format_delta_after(delta: &chrono::Duration) -> String {
+fn format_delta_after(delta: &chrono::Duration, localization: &Localization) -> String {
     let spans = &[
-        ("week", delta.num_weeks()),
-        ("day", delta.num_days()),
-        ("hour", delta.num_hours()),
-        ("minute", delta.num_minutes()),
-        ("second", delta.num_seconds()),
+        (TimeUnit::Week, delta.num_weeks()),
+        (TimeUnit::Day, delta.num_days()),
+        (TimeUnit::Hour, delta.num_hours()),
+        (TimeUnit::Minute, delta.num_minutes()),
+        (TimeUnit::Second, delta.num_seconds()),
     ];
 
     spans
         .iter()
         .find(|&(_, n)| *n != 0)
-        .map(|&(label, n)| format!("after {n} {label}{}", if n > 1 { "s" } else { "" }))
-        .unwrap_or_else(|| "now".into())
+        .map(|&(unit, n)| localization.relative(Text::After, &localization.amount(n, unit)))
+        .unwrap_or_else(|| localization.get(Text::Now).into())
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -188,7 +189,13 @@ enum WatsonState {
 }
 
 impl WatsonState {
-    fn format(&self, show_time: bool, verb: &str, f: fn(&chrono::Duration) -> String) -> String {
+    fn format(
+        &self,
+        show_time: bool,
+        verb: &str,
+        f: fn(&chrono::Duration, &Localization) -> String,
+        localization: &Localization,
+    ) -> String {
         if let WatsonState::Active {
             project,
             start,
@@ -210,7 +217,7 @@ impl WatsonState {
                 s.push_str(verb);
                 let delta = Local::now() - *start;
                 s.push(' ');
-                s.push_str(&f(&delta));
+                s.push_str(&f(&delta, localization));
             }
             s
         } else {
@@ -64,6 +64,10 @@
 //! `wind`            | Wind speed                                                         | Number | -
 //! `wind_kmh`        | Wind speed. The wind speed in km/h                                 | Number | -
 //! `direction`       | Wind direction, e.g. "NE"                                          | Text   | -
+//! `stale`           | Present if the last update failed and the displayed data is old    | Flag   | -
+//!
+//! If an update fails after the weather has been displayed once, the block keeps showing the old
+//! data (with `stale` set) and retries periodically, instead of showing an error.
 //!
 //! # Example
 //!
@@ -199,10 +203,11 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         if autolocate_interval == config.interval {
             // In the case where `autolocate_interval` matches `interval` merge both actions.
             loop {
-                let location = api.recoverable(find_ip_location).await?;
-                let data = api
-                    .recoverable(|| provider.get_weather(Some(location)))
-                    .await?;
+                let location = recoverable_stale(&mut api, &mut widget, find_ip_location).await?;
+                let data = recoverable_stale(&mut api, &mut widget, || {
+                    provider.get_weather(Some(location))
+                })
+                .await?;
                 widget.set_values(data.into_values(&api)?);
                 api.set_widget(&widget).await?;
 
@@ -217,10 +222,11 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
             let mut autolocate_interval = autolocate_interval.timer();
 
             // Initial pass
-            let mut location = api.recoverable(find_ip_location).await?;
-            let data = api
-                .recoverable(|| provider.get_weather(Some(location)))
-                .await?;
+            let mut location = recoverable_stale(&mut api, &mut widget, find_ip_location).await?;
+            let data = recoverable_stale(&mut api, &mut widget, || {
+                provider.get_weather(Some(location))
+            })
+            .await?;
             widget.set_values(data.into_values(&api)?);
             api.set_widget(&widget).await?;
 
@@ -228,22 +234,24 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                 select! {
                     biased; // if both timers `tick()` autolocate should run first
                     _ = autolocate_interval.tick() => {
-                        location = api.recoverable(find_ip_location).await?;
+                        location = recoverable_stale(&mut api, &mut widget, find_ip_location).await?;
                     }
                     _ = interval.tick() => {
-                        let data = api
-                            .recoverable(|| provider.get_weather(Some(location)))
-                            .await?;
+                        let data = recoverable_stale(&mut api, &mut widget, || {
+                            provider.get_weather(Some(location))
+                        })
+                        .await?;
                         widget.set_values(data.into_values(&api)?);
                         api.set_widget(&widget).await?;
                     },
                     // On update request autolocate and update the block.
                     _ = api.wait_for_update_request() => {
-                        location = api.recoverable(find_ip_location).await?;
+                        location = recoverable_stale(&mut api, &mut widget, find_ip_location).await?;
 
-                        let data = api
-                            .recoverable(|| provider.get_weather(Some(location)))
-                            .await?;
+                        let data = recoverable_stale(&mut api, &mut widget, || {
+                            provider.get_weather(Some(location))
+                        })
+                        .await?;
                         widget.set_values(data.into_values(&api)?);
                         api.set_widget(&widget).await?;
 
@@ -256,7 +264,8 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
         }
     } else {
         loop {
-            let data = api.recoverable(|| provider.get_weather(None)).await?;
+            let data =
+                recoverable_stale(&mut api, &mut widget, || provider.get_weather(None)).await?;
             widget.set_values(data.into_values(&api)?);
             api.set_widget(&widget).await?;
 
@@ -268,6 +277,38 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
     }
 }
 
+/// Like [`CommonApi::recoverable`], but once some weather has been displayed, failures (e.g. a lost
+/// network connection) do not replace it with an error. Instead, the last values are kept with the
+/// `stale` flag set and the request is retried after `error_interval`.
+async fn recoverable_stale<F, Fut, T>(
+    api: &mut CommonApi,
+    widget: &mut Widget,
+    mut f: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T>>,
+{
+    let mut values = match widget.values() {
+        Some(values) => values.clone(),
+        None => return api.recoverable(f).await,
+    };
+    loop {
+        match f().await {
+            Ok(res) => return Ok(res),
+            Err(_) => {
+                values.insert("stale".into(), Value::flag());
+                widget.set_values(values.clone());
+                api.set_widget(widget).await?;
+                select! {
+                    _ = sleep(api.error_interval) => (),
+                    _ = api.wait_for_update_request() => (),
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, SmartDefault)]
 #[serde(rename_all = "lowercase")]
 enum UnitSystem {
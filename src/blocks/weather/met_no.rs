@@ -134,11 +134,10 @@ impl WeatherProvider for Service {
             .or_else(|| coordinates.clone())
             .error("No location given")?;
 
-        let querystr: HashMap<&str, String> = map! {
-            "lat" => lat,
-            "lon" => lon,
-            [if let Some(alt) = altitude] "altitude" => alt,
-        };
+        let mut querystr = vec![("lat", lat), ("lon", lon)];
+        if let Some(alt) = altitude {
+            querystr.push(("altitude", alt.clone()));
+        }
 
         let data: ForecastResponse = REQWEST_CLIENT
             .get(FORECAST_URL)
@@ -41,9 +41,7 @@
 //! - `resolution`
 
 use super::prelude::*;
-use crate::subprocess::spawn_shell;
 use regex::RegexSet;
-use tokio::process::Command;
 
 #[derive(Deserialize, Debug, SmartDefault)]
 #[serde(default)]
@@ -71,9 +69,10 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
 
     let mut cur_indx = 0;
     let mut timer = config.interval.timer();
+    let commands = api.command_context.clone();
 
     loop {
-        let mut monitors = get_monitors().await?;
+        let mut monitors = get_monitors(&commands).await?;
         if cur_indx > monitors.len() {
             cur_indx = 0;
         }
@@ -104,13 +103,13 @@ pub async fn run(config: Config, mut api: CommonApi) -> Result<()> {
                     Action(a) if a == "brightness_up" => {
                         if let Some(monitor) = monitors.get_mut(cur_indx) {
                             let bright = (monitor.brightness + config.step_width).min(100);
-                            monitor.set_brightness(bright);
+                            monitor.set_brightness(&commands, bright);
                         }
                     }
                     Action(a) if a == "brightness_down" => {
                         if let Some(monitor) = monitors.get_mut(cur_indx) {
                             let bright = monitor.brightness.saturating_sub(config.step_width);
-                            monitor.set_brightness(bright);
+                            monitor.set_brightness(&commands, bright);
                         }
                     }
                     _ => (),
@@ -127,8 +126,8 @@ struct Monitor {
 }
 
 impl Monitor {
-    fn set_brightness(&mut self, brightness: u32) {
-        let _ = spawn_shell(&format!(
+    fn set_brightness(&mut self, commands: &CommandContext, brightness: u32) {
+        let _ = commands.spawn_shell(&format!(
             "xrandr --output {} --brightness  {}",
             self.name,
             brightness as f64 / 100.0
@@ -146,10 +145,11 @@ macro_rules! unwrap_or_break {
     };
 }
 
-async fn get_monitors() -> Result<Vec<Monitor>> {
+async fn get_monitors(commands: &CommandContext) -> Result<Vec<Monitor>> {
     let mut monitors = Vec::new();
 
-    let active_monitors = Command::new("xrandr")
+    let active_monitors = commands
+        .command("xrandr")
         .arg("--listactivemonitors")
         .output()
         .await
@@ -165,7 +165,8 @@ async fn get_monitors() -> Result<Vec<Monitor>> {
         .chain(Some("Brightness:".into()));
     let regex = RegexSet::new(regex).error("Failed to create RegexSet")?;
 
-    let monitors_info = Command::new("xrandr")
+    let monitors_info = commands
+        .command("xrandr")
         .arg("--verbose")
         .output()
         .await
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::fmt;
 
 use serde::de::{self, Deserializer, Visitor};
@@ -5,14 +7,15 @@ use serde::Deserialize;
 
 use crate::errors::{Result, ResultExt};
 use crate::protocol::i3bar_event::I3BarEvent;
-use crate::subprocess::{spawn_shell, spawn_shell_sync};
+use crate::subprocess::{CommandContext, Reaper};
+use crate::util::wildcard_match;
 
-/// Can be one of `left`, `middle`, `right`, `wheel_up`, `wheel_down`, `forward`, `back`, or
-/// `double_left`.
+/// Can be one of `left`, `middle`, `right`, `up` (or `wheel_up`), `down` (or `wheel_down`),
+/// `forward`, `back`, `double_left`, `double_middle` or `double_right`.
 ///
 /// Note that in order for double clicks to be registered, you have to set `double_click_delay` to a
 /// non-zero value. `200` might be a good choice. Note that enabling this functionality will
-/// make left clicks less responsive and feel a bit laggy.
+/// make left, middle and right clicks less responsive and feel a bit laggy.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MouseButton {
     Left,
@@ -24,47 +27,297 @@ pub enum MouseButton {
     Back,
     Unknown,
     DoubleLeft,
+    DoubleMiddle,
+    DoubleRight,
 }
 
-#[derive(Debug, Clone)]
+/// The actions which blocks perform when a button is clicked, and to which widget they apply
+pub type DefaultActions = &'static [(MouseButton, Option<&'static str>, &'static str)];
+
+impl MouseButton {
+    /// The button called `name` in the configuration, e.g. `wheel_up`
+    pub fn from_name(name: &str) -> Self {
+        use MouseButton::*;
+        match name {
+            "left" => Left,
+            "middle" => Middle,
+            "right" => Right,
+            "up" | "wheel_up" => WheelUp,
+            "down" | "wheel_down" => WheelDown,
+            "forward" => Forward,
+            "back" => Back,
+            // Experemental
+            "double_left" => DoubleLeft,
+            "double_middle" => DoubleMiddle,
+            "double_right" => DoubleRight,
+            _ => Unknown,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        use MouseButton::*;
+        match self {
+            Left => "left",
+            Middle => "middle",
+            Right => "right",
+            WheelUp => "wheel_up",
+            WheelDown => "wheel_down",
+            Forward => "forward",
+            Back => "back",
+            Unknown => "unknown",
+            DoubleLeft => "double_left",
+            DoubleMiddle => "double_middle",
+            DoubleRight => "double_right",
+        }
+    }
+
+    /// The double click variant of this button, if it has one
+    pub fn double_click(self) -> Option<Self> {
+        match self {
+            Self::Left => Some(Self::DoubleLeft),
+            Self::Middle => Some(Self::DoubleMiddle),
+            Self::Right => Some(Self::DoubleRight),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct PostActions {
-    pub action: Option<String>,
+    pub action: Option<Cow<'static, str>>,
+    /// How many times to trigger `action`, see [`I3BarEvent::count`]
+    pub count: u32,
     pub update: bool,
+    /// A command with `sync = true`. It is not started by [`ResolvedClick::execute`], the caller
+    /// must run it and perform the other post actions once it has exited.
+    pub sync_cmd: Option<String>,
+}
+
+/// How a `click` entry matched the clicked widget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match {
+    /// `widget` is the widget
+    Exact,
+    /// `widget` is a pattern matching the widget
+    Pattern,
+    /// The entry has no `widget`
+    Any,
+}
+
+/// What a click does, decided before anything runs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolvedClick {
+    /// The index of the `click` entry which applies, and how it matched
+    pub entry: Option<(usize, Match)>,
+    pub cmd: Option<String>,
+    pub sync: bool,
+    /// The block action to trigger: the entry's `action`, or the block's default action for the
+    /// button if the entry neither runs a command nor triggers an action
+    pub action: Option<Cow<'static, str>>,
+    /// Whether `action` is the block's default action
+    pub default_action: bool,
+    pub update: bool,
+}
+
+impl ResolvedClick {
+    /// Run the command of the click once for each click of `event`, unless it runs synchronously.
+    /// It is waited for by `reaper`. `commands` should come from [`click_context`].
+    pub fn execute(
+        self,
+        event: &I3BarEvent,
+        reaper: &mut Reaper,
+        commands: &CommandContext,
+    ) -> Result<PostActions> {
+        let mut sync_cmd = None;
+        if let Some(cmd) = self.cmd {
+            if self.sync {
+                sync_cmd = Some(cmd);
+            } else {
+                for _ in 0..event.count {
+                    reaper.spawn_shell(&cmd, event.id, commands).or_error(|| {
+                        format!("'{:?}' button handler: Failed to run '{cmd}", event.button)
+                    })?;
+                }
+            }
+        }
+        Ok(PostActions {
+            action: self.action,
+            count: event.count,
+            update: self.update,
+            sync_cmd,
+        })
+    }
+}
+
+/// The clicks on a block which wait for the command of an earlier click with `sync = true`, so
+/// that clicks never overtake each other
+#[derive(Debug, Default)]
+pub struct ClickQueue {
+    in_progress: bool,
+    queued: VecDeque<I3BarEvent>,
+}
+
+impl ClickQueue {
+    /// Whether a command with `sync = true` is running
+    pub fn in_progress(&self) -> bool {
+        self.in_progress
+    }
+
+    /// Handle `event` once the running command and the clicks queued before it are done
+    pub fn push(&mut self, event: I3BarEvent) {
+        self.queued.push_back(event);
+    }
+
+    /// A command with `sync = true` was started
+    pub fn start(&mut self) {
+        self.in_progress = true;
+    }
+
+    /// The running command exited. If it failed, the block fails along with it, so the clicks
+    /// which waited for it are dropped.
+    pub fn finish(&mut self, succeeded: bool) {
+        self.in_progress = false;
+        if !succeeded {
+            self.clear();
+        }
+    }
+
+    /// Drop the queued clicks, e.g. because the block failed
+    pub fn clear(&mut self) {
+        self.queued.clear();
+    }
+
+    /// The next queued click to handle, unless a command is running
+    pub fn next(&mut self) -> Option<I3BarEvent> {
+        if self.in_progress {
+            return None;
+        }
+        self.queued.pop_front()
+    }
 }
 
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct ClickHandler(Vec<ClickConfigEntry>);
 
 impl ClickHandler {
-    pub async fn handle(&self, event: &I3BarEvent) -> Result<PostActions> {
-        Ok(
-            match self
-                .0
+    /// Decide what a click does, given the block's default actions
+    pub fn resolve(&self, event: &I3BarEvent, default_actions: DefaultActions) -> ResolvedClick {
+        let instance = event.instance.as_deref();
+        let mut resolved = match self.find(event.button, instance) {
+            Some((index, how)) => {
+                let entry = &self.0[index];
+                ResolvedClick {
+                    entry: Some((index, how)),
+                    cmd: entry.cmd.clone(),
+                    sync: entry.sync,
+                    action: entry.action.clone().map(Cow::Owned),
+                    default_action: false,
+                    update: entry.update,
+                }
+            }
+            None => ResolvedClick::default(),
+        };
+        if resolved.cmd.is_none() && resolved.action.is_none() {
+            // Default actions without a widget also apply to widgets which have no default action
+            // of their own
+            let find = |widget: Option<&str>| {
+                default_actions
+                    .iter()
+                    .find(|(button, w, _)| *button == event.button && *w == widget)
+            };
+            if let Some((_, _, action)) = find(instance).or_else(|| find(None)) {
+                resolved.action = Some(Cow::Borrowed(action));
+                resolved.default_action = true;
+            }
+        }
+        resolved
+    }
+
+    /// Describe what a click resolved to, along with the variables set for its command by
+    /// `commands`
+    pub fn explain(&self, resolved: &ResolvedClick, commands: &CommandContext) -> String {
+        let mut parts = Vec::new();
+        match resolved.entry {
+            Some((index, how)) => {
+                let entry = &self.0[index];
+                let widget = match (how, &entry.widget) {
+                    (Match::Exact, Some(widget)) => format!(" for widget '{widget}'"),
+                    (Match::Pattern, Some(widget)) => format!(" for widgets '{widget}'"),
+                    _ => String::new(),
+                };
+                parts.push(format!("click[{index}] ({}{widget})", entry.button.name()));
+            }
+            None => parts.push("no click entry".into()),
+        }
+        if let Some(cmd) = &resolved.cmd {
+            let mut env: Vec<_> = commands.env().iter().collect();
+            env.sort();
+            let env: Vec<String> = env.iter().map(|(k, v)| format!("{k}={v}")).collect();
+            parts.push(format!(
+                "runs '{cmd}'{} with {}",
+                if resolved.sync { " and waits" } else { "" },
+                env.join(" ")
+            ));
+        }
+        if let Some(action) = &resolved.action {
+            let kind = if resolved.default_action {
+                "default action"
+            } else {
+                "action"
+            };
+            parts.push(format!("{kind} '{action}'"));
+        }
+        if resolved.update {
+            parts.push("updates the block".into());
+        }
+        if parts.len() == 1 {
+            parts.push("does nothing".into());
+        }
+        parts.join(", ")
+    }
+
+    /// The index of the entry for a click on the widget `instance`. An entry whose `widget` is
+    /// exactly `instance` comes first, then one whose `widget` pattern matches it, then one
+    /// without `widget`. Among those, the first one in the configuration is used.
+    fn find(&self, button: MouseButton, instance: Option<&str>) -> Option<(usize, Match)> {
+        let entries = || {
+            self.0
                 .iter()
-                .find(|e| e.button == event.button && e.widget == event.instance)
-            {
-                Some(entry) => {
-                    if let Some(cmd) = &entry.cmd {
-                        if entry.sync {
-                            spawn_shell_sync(cmd).await
-                        } else {
-                            spawn_shell(cmd)
-                        }
-                        .or_error(|| {
-                            format!("'{:?}' button handler: Failed to run '{cmd}", event.button)
-                        })?;
-                    }
-                    PostActions {
-                        action: entry.action.clone(),
-                        update: entry.update,
-                    }
+                .enumerate()
+                .filter(move |(_, e)| e.button == button)
+        };
+        let found = |how: Match, matches: &dyn Fn(&ClickConfigEntry) -> bool| {
+            entries()
+                .find(|(_, e)| matches(e))
+                .map(|(index, _)| (index, how))
+        };
+        found(Match::Exact, &|e| {
+            e.widget.is_some() && e.widget.as_deref() == instance
+        })
+        .or_else(|| {
+            found(Match::Pattern, &|e| match (&e.widget, instance) {
+                (Some(pattern), Some(instance)) => {
+                    pattern.contains('*') && wildcard_match(pattern, instance)
                 }
-                None => PostActions {
-                    action: None,
-                    update: false,
-                },
-            },
-        )
+                _ => false,
+            })
+        })
+        .or_else(|| found(Match::Any, &|e| e.widget.is_none()))
+    }
+}
+
+/// The context of the commands run by a click on a block called `block_name`. `$BLOCK_NAME` is
+/// set to the name of the block and `$BLOCK_INSTANCE` to the widget which was clicked, if any,
+/// unless `command_env` sets them.
+pub fn click_context(
+    commands: &CommandContext,
+    block_name: &str,
+    event: &I3BarEvent,
+) -> CommandContext {
+    let commands = commands.with_default_env("BLOCK_NAME", block_name);
+    match &event.instance {
+        Some(instance) => commands.with_default_env("BLOCK_INSTANCE", instance),
+        None => commands,
     }
 }
 
@@ -73,7 +326,7 @@ impl ClickHandler {
 pub struct ClickConfigEntry {
     /// Which button to handle
     button: MouseButton,
-    /// To which part of the block this entry applies
+    /// To which part of the block this entry applies. `*` matches any sequence of characters.
     #[serde(default)]
     widget: Option<String>,
     /// Which command to run
@@ -111,19 +364,7 @@ impl<'de> Deserialize<'de> for MouseButton {
             where
                 E: de::Error,
             {
-                use MouseButton::*;
-                Ok(match name {
-                    "left" => Left,
-                    "middle" => Middle,
-                    "right" => Right,
-                    "up" => WheelUp,
-                    "down" => WheelDown,
-                    "forward" => Forward,
-                    "back" => Back,
-                    // Experemental
-                    "double_left" => DoubleLeft,
-                    _ => Unknown,
-                })
+                Ok(MouseButton::from_name(name))
             }
 
             // ```toml
@@ -156,3 +397,214 @@ impl<'de> Deserialize<'de> for MouseButton {
         deserializer.deserialize_any(MouseButtonVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handler(entries: &str) -> ClickHandler {
+        #[derive(Deserialize)]
+        struct Block {
+            click: ClickHandler,
+        }
+        toml::from_str::<Block>(entries).unwrap().click
+    }
+
+    fn found<'a>(
+        handler: &'a ClickHandler,
+        button: MouseButton,
+        instance: Option<&str>,
+    ) -> Option<&'a str> {
+        let (index, _) = handler.find(button, instance)?;
+        handler.0[index].cmd.as_deref()
+    }
+
+    #[test]
+    fn precedence() {
+        let handler = handler(
+            r#"
+            click = [
+                { button = "left", cmd = "bare" },
+                { button = "left", widget = "workspace-*", cmd = "glob" },
+                { button = "left", widget = "workspace-2", cmd = "exact" },
+                { button = "left", widget = "*-2", cmd = "other glob" },
+                { button = "right", widget = "workspace-*", cmd = "right" },
+            ]
+            "#,
+        );
+        use MouseButton::*;
+        assert_eq!(found(&handler, Left, Some("workspace-2")), Some("exact"));
+        // The first matching pattern is used
+        assert_eq!(found(&handler, Left, Some("workspace-1")), Some("glob"));
+        assert_eq!(found(&handler, Left, Some("output-2")), Some("other glob"));
+        assert_eq!(found(&handler, Left, Some("output-1")), Some("bare"));
+        assert_eq!(found(&handler, Left, None), Some("bare"));
+        assert_eq!(found(&handler, Right, Some("workspace-1")), Some("right"));
+        assert_eq!(found(&handler, Right, Some("output-1")), None);
+        assert_eq!(found(&handler, Right, None), None);
+        assert_eq!(found(&handler, Middle, Some("workspace-1")), None);
+
+        // A widget without `*` is not a pattern
+        let handler = self::handler(r#"click = [{ button = "left", widget = "a", cmd = "a" }]"#);
+        assert_eq!(found(&handler, Left, Some("a")), Some("a"));
+        assert_eq!(found(&handler, Left, Some("ab")), None);
+        assert_eq!(found(&handler, Left, None), None);
+    }
+
+    #[test]
+    fn resolve() {
+        let handler = handler(
+            r#"
+            click = [
+                { button = "left", widget = "a", cmd = "run a", sync = true },
+                { button = "left", widget = "b*", update = true },
+                { button = "middle", action = "mine" },
+                { button = "right", cmd = "run right", action = "after" },
+            ]
+            "#,
+        );
+        const DEFAULTS: DefaultActions = &[
+            (MouseButton::Left, Some("b2"), "b2 default"),
+            (MouseButton::Left, None, "default"),
+            (MouseButton::Middle, None, "middle default"),
+        ];
+        let resolve = |button, instance: Option<&str>| {
+            let event = I3BarEvent {
+                id: 0,
+                instance: instance.map(Into::into),
+                button,
+                count: 1,
+            };
+            handler.resolve(&event, DEFAULTS)
+        };
+        use MouseButton::*;
+        // A command replaces the default action
+        assert_eq!(
+            resolve(Left, Some("a")),
+            ResolvedClick {
+                entry: Some((0, Match::Exact)),
+                cmd: Some("run a".into()),
+                sync: true,
+                ..Default::default()
+            }
+        );
+        // An entry which only updates keeps the default action, of the widget if it has one
+        assert_eq!(
+            resolve(Left, Some("b2")),
+            ResolvedClick {
+                entry: Some((1, Match::Pattern)),
+                action: Some("b2 default".into()),
+                default_action: true,
+                update: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(resolve(Left, Some("b1")).action.unwrap(), "default");
+        // Without an entry
+        assert_eq!(
+            resolve(Left, Some("c")),
+            ResolvedClick {
+                action: Some("default".into()),
+                default_action: true,
+                ..Default::default()
+            }
+        );
+        // A configured action replaces the default one
+        assert_eq!(
+            resolve(Middle, None),
+            ResolvedClick {
+                entry: Some((2, Match::Any)),
+                action: Some("mine".into()),
+                ..Default::default()
+            }
+        );
+        let right = resolve(Right, None);
+        assert_eq!(right.cmd.as_deref(), Some("run right"));
+        assert_eq!(right.action.as_deref(), Some("after"));
+        assert_eq!(resolve(WheelUp, None), ResolvedClick::default());
+
+        let commands = CommandContext::new([("X".into(), "1".into())].into(), None)
+            .with_default_env("BLOCK_NAME", "time");
+        assert_eq!(
+            handler.explain(&resolve(Left, Some("a")), &commands),
+            "click[0] (left for widget 'a'), runs 'run a' and waits with BLOCK_NAME=time X=1"
+        );
+        assert_eq!(
+            handler.explain(&resolve(Left, Some("b2")), &commands),
+            "click[1] (left for widgets 'b*'), default action 'b2 default', updates the block"
+        );
+        assert_eq!(
+            handler.explain(&resolve(WheelUp, None), &commands),
+            "no click entry, does nothing"
+        );
+    }
+
+    #[test]
+    fn environment() {
+        let event = |instance: Option<&str>| I3BarEvent {
+            id: 0,
+            instance: instance.map(Into::into),
+            button: MouseButton::Left,
+            count: 1,
+        };
+        let output = |context: CommandContext| {
+            tokio_test::block_on(async {
+                let output = context
+                    .shell(r#"printf '%s|%s' "$BLOCK_NAME" "${BLOCK_INSTANCE-unset}""#)
+                    .output()
+                    .await
+                    .unwrap();
+                String::from_utf8(output.stdout).unwrap()
+            })
+        };
+        let commands = CommandContext::default();
+        // The instance is not interpreted by the shell
+        let context = click_context(&commands, "sway_workspaces", &event(Some("$(exit 1); 'x")));
+        assert_eq!(output(context), "sway_workspaces|$(exit 1); 'x");
+        let context = click_context(&commands, "time", &event(None));
+        assert_eq!(output(context), "time|unset");
+        // `command_env` takes precedence
+        let commands = CommandContext::new([("BLOCK_NAME".into(), "mine".into())].into(), None);
+        let context = click_context(&commands, "time", &event(Some("a")));
+        assert_eq!(output(context), "mine|a");
+    }
+
+    fn click(id: usize, button: MouseButton) -> I3BarEvent {
+        I3BarEvent {
+            id,
+            instance: None,
+            button,
+            count: 1,
+        }
+    }
+
+    #[test]
+    fn click_queue() {
+        let mut queue = ClickQueue::default();
+        assert!(!queue.in_progress());
+        assert_eq!(queue.next(), None);
+
+        // The left click runs a command with `sync = true`, the others wait for it
+        queue.start();
+        queue.push(click(0, MouseButton::Middle));
+        queue.push(click(0, MouseButton::Right));
+        assert!(queue.in_progress());
+        assert_eq!(queue.next(), None);
+
+        // Once it is done, they are handled in order. The middle click starts another command.
+        queue.finish(true);
+        assert_eq!(queue.next(), Some(click(0, MouseButton::Middle)));
+        queue.start();
+        queue.push(click(0, MouseButton::WheelUp));
+        assert_eq!(queue.next(), None);
+
+        // It fails, so the clicks which waited for it are dropped along with the block
+        queue.finish(false);
+        assert_eq!(queue.next(), None);
+
+        // Later clicks do not wait for anything
+        assert!(!queue.in_progress());
+        queue.push(click(0, MouseButton::Left));
+        assert_eq!(queue.next(), Some(click(0, MouseButton::Left)));
+    }
+}
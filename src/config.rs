@@ -2,13 +2,27 @@ use serde::{Deserialize, Deserializer};
 use smart_default::SmartDefault;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::alerts::Config as AlertsConfig;
 use crate::blocks::BlockConfig;
 use crate::click::ClickHandler;
 use crate::errors::*;
 use crate::formatting::config::Config as FormatConfig;
-use crate::icons::Icons;
-use crate::themes::{Theme, ThemeOverrides, ThemeUserConfig};
+use crate::formatting::marquee::Marquee;
+use crate::heartbeat::Config as HeartbeatConfig;
+use crate::icons::{Icon, IconPosition, Icons};
+use crate::localization::Localization;
+use crate::power_warnings::Config as PowerWarningsConfig;
+use crate::presentation::Config as PresentationConfig;
+use crate::protocol::sway::Compositor;
+use crate::recording::Config as RecordingConfig;
+use crate::refresh_groups::Config as RefreshGroupConfig;
+use crate::theme_rules::Rule as ThemeRule;
+use crate::themes::color::Color;
+use crate::themes::{BlockColors, Theme, ThemeOverrides, ThemeUserConfig};
+use crate::widget::{Markup, State, StatePrefixes};
+use crate::wrappers::{Seconds, ShellString};
 
 #[derive(Deserialize, Debug, SmartDefault)]
 #[serde(default)]
@@ -22,15 +36,132 @@ pub struct Config {
     /// The maximum delay (ms) between two clicks that are considered as doulble click
     pub double_click_delay: u64,
 
+    /// The delay (ms) within which scrolling over a block is merged into one event
+    #[default(50)]
+    pub scroll_coalesce_delay: u64,
+
     #[default(" {$short_error_message|X} ".parse().unwrap())]
     pub error_format: FormatConfig,
     #[default(" $full_error_message ".parse().unwrap())]
     pub error_fullscreen_format: FormatConfig,
 
+    /// Record values of some blocks to a file
+    pub recording: Option<RecordingConfig>,
+
+    /// Tell a server that this machine is up
+    pub heartbeat: Option<HeartbeatConfig>,
+
+    /// Warn about a low battery in front of all blocks
+    pub power_warnings: Option<PowerWarningsConfig>,
+
+    /// Stop the whole bar if a block fails instead of showing the error in the block
+    pub error_halts_bar: bool,
+
+    /// Skip the theme's transitions if their frames would be printed more often than this
+    pub max_fps: Option<f64>,
+
+    /// Flash the bar when blocks enter some states
+    pub alerts: AlertsConfig,
+
+    /// Whether the order of the blocks set over DBus survives restarts
+    pub presentation: PresentationConfig,
+
+    /// Change the theme of the whole bar depending on values of blocks
+    pub theme_rules: Vec<ThemeRule>,
+
+    /// Groups of blocks which a signal refreshes in the same frame
+    pub refresh_groups: Vec<RefreshGroupConfig>,
+
+    /// Which compositor the bar runs under, which decides how it is paused while hidden
+    pub compositor: Compositor,
+
+    /// Refuse to start if two blocks control the same resource, instead of only warning
+    pub strict_conflicts: bool,
+
+    /// How the configuration is reloaded on `SIGUSR2`
+    pub reload: Reload,
+
+    /// Shrink blocks while the bar would be wider than this many characters
+    pub width_budget: Option<usize>,
+
     #[serde(rename = "block")]
     pub blocks: Vec<BlockConfigEntry>,
 }
 
+/// How the configuration is reloaded on `SIGUSR2`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Reload {
+    /// Replace only the blocks whose configuration changed, and restart the whole bar if anything
+    /// else changed
+    #[default]
+    InProcess,
+    /// Always restart the whole bar
+    Exec,
+}
+
+impl Config {
+    /// Warn about blocks which control the same resource, or fail under `strict_conflicts`
+    pub fn check_conflicts(&self) -> Result<()> {
+        for (resource, blocks) in conflicts(&self.blocks) {
+            let message = format!("Blocks {} all control '{resource}'", blocks.join(", "));
+            if self.strict_conflicts {
+                return Err(Error::new(message));
+            }
+            log::warn!("{message}");
+        }
+        Ok(())
+    }
+}
+
+/// The resources which are controlled by more than one block, with the identifiers (e.g.
+/// `sound-1`) of those blocks
+fn conflicts(blocks: &[BlockConfigEntry]) -> Vec<(String, Vec<String>)> {
+    let mut counts = HashMap::new();
+    let mut conflicts: Vec<(String, Vec<String>)> = Vec::new();
+    for block in blocks {
+        let name = block.config.name();
+        let index = counts.entry(name).or_insert(0);
+        let identifier = format!("{name}-{index}");
+        *index += 1;
+        let resource = match block.config.resource() {
+            Some(resource) => resource,
+            None => continue,
+        };
+        match conflicts.iter_mut().find(|(r, _)| *r == resource) {
+            Some((_, identifiers)) => identifiers.push(identifier),
+            None => conflicts.push((resource, vec![identifier])),
+        }
+    }
+    conflicts.retain(|(_, identifiers)| identifiers.len() > 1);
+    conflicts
+}
+
+/// The block entries of the configuration file, used to restart blocks after errors, and the other
+/// settings, used to tell which parts of the configuration changed when it is reloaded
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct RawConfig {
+    #[serde(rename = "block")]
+    pub blocks: Vec<toml::Value>,
+    #[serde(flatten)]
+    pub settings: toml::value::Table,
+}
+
+/// Match the block entries of a reloaded configuration with the entries of the running blocks.
+/// Returns, for each new entry, the index of an old entry which is exactly the same and can be
+/// kept. Each old entry is kept at most once, and identical entries are matched in order.
+pub fn match_blocks(old: &[&toml::Value], new: &[toml::Value]) -> Vec<Option<usize>> {
+    let mut kept = vec![false; old.len()];
+    new.iter()
+        .map(|entry| {
+            let i = (0..old.len()).find(|&i| !kept[i] && old[i] == entry)?;
+            kept[i] = true;
+            Some(i)
+        })
+        .collect()
+}
+
 #[derive(Deserialize, Debug, Clone, SmartDefault)]
 #[serde(default)]
 pub struct SharedConfig {
@@ -39,14 +170,41 @@ pub struct SharedConfig {
     pub icons: Arc<Icons>,
     #[default(Arc::new("{icon}".into()))]
     pub icons_format: Arc<String>,
+    pub icon_position: IconPosition,
+    pub state_prefixes: Arc<StatePrefixes>,
+    pub localization: Arc<Localization>,
+    pub markup: Markup,
+    /// Limits the width of text values of a block, see `max_width` of [`CommonBlockConfig`]
+    #[serde(skip)]
+    pub marquee: Option<Arc<Marquee>>,
+    /// The colors set in the table of a block, see [`CommonBlockConfig`]
+    #[serde(skip)]
+    pub colors: Arc<BlockColors>,
 }
 
 impl SharedConfig {
+    /// The background and foreground colors in `state`
+    pub fn get_colors(&self, state: State) -> (Color, Color) {
+        self.colors.apply(state, self.theme.get_colors(state))
+    }
+
     pub fn get_icon(&self, icon: &str) -> Option<String> {
+        self.get_stateful_icon(icon)
+            .map(|icon| icon.text().to_owned())
+    }
+
+    /// Like [`SharedConfig::get_icon`], but with the variants of an icon which depends on the
+    /// state of the widget showing it
+    pub fn get_stateful_icon(&self, icon: &str) -> Option<Icon> {
         if icon.is_empty() {
-            Some(String::new())
+            Some(Icon::Plain(String::new()))
         } else {
-            Some(self.icons_format.replace("{icon}", self.icons.0.get(icon)?))
+            let icon = self.icons.0.get(icon)?;
+            if self.icons_format.as_str() == "{icon}" {
+                Some(icon.clone())
+            } else {
+                Some(icon.map(|icon| self.icons_format.replace("{icon}", icon)))
+            }
         }
     }
 }
@@ -65,16 +223,73 @@ pub struct CommonBlockConfig {
     pub click: ClickHandler,
     pub signal: Option<i32>,
     pub icons_format: Option<String>,
+    pub icon_position: Option<IconPosition>,
     pub theme_overrides: Option<ThemeOverrides>,
-    pub icons_overrides: Option<HashMap<String, String>>,
+    /// Colors which take precedence over the theme, see [`BlockColors`]. They are not flattened
+    /// into a struct, since the keys of nested flattened structs would not be consumed.
+    pub background: Option<Color>,
+    pub foreground: Option<Color>,
+    pub idle_bg: Option<Color>,
+    pub idle_fg: Option<Color>,
+    pub info_bg: Option<Color>,
+    pub info_fg: Option<Color>,
+    pub good_bg: Option<Color>,
+    pub good_fg: Option<Color>,
+    pub warning_bg: Option<Color>,
+    pub warning_fg: Option<Color>,
+    pub critical_bg: Option<Color>,
+    pub critical_fg: Option<Color>,
+    pub icons_overrides: Option<HashMap<String, Icon>>,
+    pub localization_overrides: Option<Localization>,
+    pub markup: Option<Markup>,
     pub merge_with_next: bool,
+    pub import_values: HashMap<String, String>,
+    pub click_feedback: bool,
+
+    /// The maximum width of text values which are shown without a formatter
+    pub max_width: Option<usize>,
+    /// Whether to rotate texts which are wider than `max_width` instead of truncating them
+    pub marquee: bool,
+    #[default(Seconds(Duration::from_millis(500)))]
+    pub marquee_interval: Seconds<false>,
+    /// Blocks with a higher priority shrink first if the bar exceeds `width_budget`
+    pub shrink_priority: i32,
 
     #[default(5)]
     pub error_interval: u64,
     pub error_format: FormatConfig,
     pub error_fullscreen_format: FormatConfig,
 
+    /// Restart the block if it is busy for longer, e.g. because an update hangs
+    pub update_timeout: Option<Seconds<false>>,
+
     pub if_command: Option<String>,
+    /// How often to run `if_command` again, showing or hiding the block depending on its result
+    pub if_command_interval: Option<Seconds<false>>,
+
+    /// Environment variables for the commands run by the block
+    pub command_env: HashMap<String, String>,
+    /// Working directory of the commands run by the block
+    pub command_dir: Option<ShellString>,
+}
+
+impl CommonBlockConfig {
+    pub fn colors(&self) -> BlockColors {
+        BlockColors {
+            background: self.background,
+            foreground: self.foreground,
+            idle_bg: self.idle_bg,
+            idle_fg: self.idle_fg,
+            info_bg: self.info_bg,
+            info_fg: self.info_fg,
+            good_bg: self.good_bg,
+            good_fg: self.good_fg,
+            warning_bg: self.warning_bg,
+            warning_fg: self.warning_fg,
+            critical_bg: self.critical_bg,
+            critical_fg: self.critical_fg,
+        }
+    }
 }
 
 fn deserialize_theme_config<'de, D>(deserializer: D) -> Result<Arc<Theme>, D::Error>
@@ -85,3 +300,143 @@ where
     let theme = Theme::try_from(theme_config).serde_error()?;
     Ok(Arc::new(theme))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resource_conflicts() {
+        let config: Config = toml::from_str(
+            r#"
+            [[block]]
+            block = "memory"
+            format = " $mem_used "
+            [[block]]
+            block = "memory"
+            format = " $swap_used "
+            [[block]]
+            block = "sound"
+            driver = "alsa"
+            [[block]]
+            block = "backlight"
+            device = "intel_backlight"
+            [[block]]
+            block = "sound"
+            driver = "alsa"
+            device_kind = "source"
+            [[block]]
+            block = "backlight"
+            device = "acpi_video0"
+            [[block]]
+            block = "sound"
+            driver = "alsa"
+            name = "Master"
+            [[block]]
+            block = "hueshift"
+            hue_shifter = "sct"
+            [[block]]
+            block = "hueshift"
+            hue_shifter = "sct"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            conflicts(&config.blocks),
+            [
+                (
+                    "alsa:default:Master".to_string(),
+                    vec!["sound-0".to_string(), "sound-2".to_string()]
+                ),
+                (
+                    "hueshift:sct".to_string(),
+                    vec!["hueshift-0".to_string(), "hueshift-1".to_string()]
+                ),
+            ]
+        );
+        assert!(config.check_conflicts().is_ok());
+
+        let strict = Config {
+            strict_conflicts: true,
+            ..config
+        };
+        assert!(strict.check_conflicts().is_err());
+
+        let benign = Config {
+            blocks: strict.blocks.into_iter().take(6).collect(),
+            strict_conflicts: true,
+            ..Default::default()
+        };
+        assert!(benign.check_conflicts().is_ok());
+    }
+
+    #[test]
+    fn block_colors() {
+        let config: Config = toml::from_str(
+            r##"
+            [[block]]
+            block = "time"
+            background = "#0000ff"
+            critical_fg = "auto"
+            "##,
+        )
+        .unwrap();
+        let colors = config.blocks[0].common.colors();
+        let (bg, _) = colors.apply(State::Idle, (Color::None, Color::None));
+        assert_eq!(bg, "#0000ff".parse().unwrap());
+
+        // Malformed colors are rejected, naming the block
+        for color in ["#00f", "0000ffff", "#0000fg", "#0000ff0"] {
+            let contents = format!("[[block]]\nblock = \"time\"\nwarning_bg = \"{color}\"\n");
+            let error =
+                crate::util::deserialize_toml::<Config>(&contents, std::path::Path::new("a.toml"))
+                    .unwrap_err()
+                    .to_string();
+            assert!(error.contains("in `block[0].time.warning_bg`"), "{error}");
+            assert!(
+                error.contains(&format!("'{color}' is not a valid RGBA color")),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn reload_matching() {
+        let raw = |s: &str| toml::from_str::<RawConfig>(s).unwrap();
+        let old = raw(r#"
+            theme = "plain"
+            [[block]]
+            block = "time"
+            [[block]]
+            block = "cpu"
+            [[block]]
+            block = "time"
+            [[block]]
+            block = "memory"
+            format = " $mem_used "
+            "#);
+        let new = raw(r#"
+            theme = "plain"
+            [[block]]
+            block = "load"
+            [[block]]
+            block = "time"
+            [[block]]
+            block = "memory"
+            format = " $mem_avail "
+            [[block]]
+            block = "cpu"
+            [[block]]
+            block = "time"
+            [[block]]
+            block = "time"
+            "#);
+        assert_eq!(old.settings, new.settings);
+        let old_blocks: Vec<_> = old.blocks.iter().collect();
+        assert_eq!(
+            match_blocks(&old_blocks, &new.blocks),
+            [None, Some(0), None, Some(1), Some(2), None]
+        );
+        assert_ne!(old.settings, raw(r#"theme = "slick""#).settings);
+    }
+}
@@ -0,0 +1,239 @@
+//! Controlling the bar over DBus
+//!
+//! The bar owns the `rs.i3status` name on the session bus, which is also used by the
+//! `custom_dbus` block. The object `/` implements the `rs.i3status.bar` interface, so that scripts
+//! and key bindings can control the bar without signals.
+//!
+//! The name is only requested if it is set explicitly (see below) or once a `custom_dbus` block
+//! starts, so that a bar without such blocks does not take `rs.i3status` from another bar which
+//! has them. To control a bar without `custom_dbus` blocks, give it a name.
+//!
+//!
+//! Method          | Arguments             | Result | Effect
+//! ----------------|-----------------------|--------|-------
+//! `UpdateBlock`   | `s` block             | -      | Asks the block to update, like its `signal`
+//! `SetVisibility` | `s` block, `b` show   | -      | Hides or shows the block. It keeps running while hidden.
+//! `GetBlocks`     | -                     | `as`   | The identifier and the current text of each block, e.g. `cpu-0: 12%`
+//! `Reload`        | -                     | -      | Reloads the config file, like SIGUSR2
+//! `ExplainClick`  | `s` block, `s` button | `as`   | What clicking each block with the button (e.g. `wheel_up`) does: which `click` entry applies, which command would run with which variables, and which action is triggered
+//! `SetOrder`      | `as` identifiers      | -      | Shows the listed blocks first, in this order, see [`presentation`](crate::presentation)
+//! `ResetOrder`    | -                     | -      | Shows the blocks in their configured order again
+//! `GetStatus`     | -                     | `a{sv}` | `order`: the identifiers of the blocks in the order in which they are shown, `reordered`: whether this order was set with `SetOrder`
+//! `RefreshGroup`  | `s` group             | -      | Refreshes a group of `refresh_groups`, given by its `name` or signal number, like its signal
+//! `SetHeartbeat`  | `b` enabled           | -      | Pauses or resumes the heartbeats of the `[heartbeat]` table. Resuming sends one right away.
+//!
+//! A block is given by its name (e.g. `sound`), which refers to all blocks of this type, by its
+//! identifier (e.g. `sound-1`, see [`imports`](crate::imports)) or by its position in the config
+//! file, starting at `0`.
+//!
+//! Like with `custom_dbus`, several bars can be controlled by setting a different name for each
+//! of them with `--dbus-name <name>` or the env var `I3RS_DBUS_NAME`: the bar then owns
+//! `rs.i3status.<name>` instead.
+//!
+//! # Example
+//!
+//! ```sh
+//! busctl --user call rs.i3status / rs.i3status.bar UpdateBlock s sound
+//! busctl --user call rs.i3status / rs.i3status.bar SetVisibility sb cpu-0 false
+//! busctl --user call rs.i3status / rs.i3status.bar GetBlocks
+//! busctl --user call rs.i3status / rs.i3status.bar SetOrder as 2 time-0 sound-0
+//! ```
+
+use std::collections::HashMap;
+use std::env;
+
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use tokio::sync::{mpsc, oneshot, Notify};
+use zbus::zvariant::OwnedValue;
+use zbus::{dbus_interface, fdo};
+
+use crate::errors::*;
+use crate::util::new_dbus_connection;
+use crate::BoxedStream;
+
+const DBUS_NAME: &str = "rs.i3status";
+
+// Shared by the bar and all `custom_dbus` blocks, since only one connection can own the name
+static DBUS_CONNECTION: async_once_cell::OnceCell<Result<zbus::Connection>> =
+    async_once_cell::OnceCell::new();
+
+/// Notified once the connection owns the name
+static CONNECTED: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// The connection owning the bar's name on the session bus
+pub async fn dbus_connection() -> Result<&'static zbus::Connection> {
+    DBUS_CONNECTION
+        .get_or_init(connect())
+        .await
+        .as_ref()
+        .map_err(Clone::clone)
+}
+
+async fn connect() -> Result<zbus::Connection> {
+    let dbus_interface_name = match env::var("I3RS_DBUS_NAME") {
+        Ok(v) => format!("{DBUS_NAME}.{v}"),
+        Err(_) => DBUS_NAME.to_string(),
+    };
+
+    let conn = new_dbus_connection().await?;
+    conn.request_name(dbus_interface_name)
+        .await
+        .error("Failed to request DBus name")?;
+    // Only `commands()` waits for this, so the permit is kept until it does
+    CONNECTED.notify_one();
+    Ok(conn)
+}
+
+/// A method call, handled by the bar
+#[derive(Debug)]
+pub enum Command {
+    UpdateBlock(String, oneshot::Sender<Result<()>>),
+    SetVisibility(String, bool, oneshot::Sender<Result<()>>),
+    GetBlocks(oneshot::Sender<Vec<String>>),
+    Reload,
+    ExplainClick(String, String, oneshot::Sender<Result<Vec<String>>>),
+    SetOrder(Option<Vec<String>>, oneshot::Sender<()>),
+    GetStatus(oneshot::Sender<HashMap<String, OwnedValue>>),
+    RefreshGroup(String, oneshot::Sender<Result<()>>),
+    SetHeartbeat(bool, oneshot::Sender<Result<()>>),
+}
+
+struct Bar {
+    commands: mpsc::Sender<Command>,
+}
+
+impl Bar {
+    async fn call<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> Result<T> {
+        let (sender, receiver) = oneshot::channel();
+        self.commands
+            .send(command(sender))
+            .await
+            .error("The bar stopped")?;
+        receiver.await.error("The bar stopped")
+    }
+}
+
+#[dbus_interface(name = "rs.i3status.bar")]
+impl Bar {
+    async fn update_block(&self, block: String) -> fdo::Result<()> {
+        self.call(|reply| Command::UpdateBlock(block, reply))
+            .await??;
+        Ok(())
+    }
+
+    async fn set_visibility(&self, block: String, show: bool) -> fdo::Result<()> {
+        self.call(|reply| Command::SetVisibility(block, show, reply))
+            .await??;
+        Ok(())
+    }
+
+    async fn get_blocks(&self) -> fdo::Result<Vec<String>> {
+        Ok(self.call(Command::GetBlocks).await?)
+    }
+
+    async fn explain_click(&self, block: String, button: String) -> fdo::Result<Vec<String>> {
+        Ok(self
+            .call(|reply| Command::ExplainClick(block, button, reply))
+            .await??)
+    }
+
+    async fn set_order(&self, order: Vec<String>) -> fdo::Result<()> {
+        Ok(self
+            .call(|reply| Command::SetOrder(Some(order), reply))
+            .await?)
+    }
+
+    async fn reset_order(&self) -> fdo::Result<()> {
+        Ok(self.call(|reply| Command::SetOrder(None, reply)).await?)
+    }
+
+    async fn get_status(&self) -> fdo::Result<HashMap<String, OwnedValue>> {
+        Ok(self.call(Command::GetStatus).await?)
+    }
+
+    async fn refresh_group(&self, group: String) -> fdo::Result<()> {
+        self.call(|reply| Command::RefreshGroup(group, reply))
+            .await??;
+        Ok(())
+    }
+
+    async fn set_heartbeat(&self, enabled: bool) -> fdo::Result<()> {
+        self.call(|reply| Command::SetHeartbeat(enabled, reply))
+            .await??;
+        Ok(())
+    }
+
+    async fn reload(&self) -> fdo::Result<()> {
+        // The bar may restart in place, so don't wait for it
+        self.commands
+            .send(Command::Reload)
+            .await
+            .error("The bar stopped")?;
+        Ok(())
+    }
+}
+
+/// Serve the `rs.i3status.bar` interface, right away if the name is set explicitly and otherwise
+/// once a `custom_dbus` block requested it. Failures are logged, after which the stream ends.
+pub fn commands() -> BoxedStream<Command> {
+    let (sender, receiver) = mpsc::channel(8);
+    tokio::spawn(async move {
+        if env::var_os("I3RS_DBUS_NAME").is_none() {
+            CONNECTED.notified().await;
+        }
+        let result = async {
+            dbus_connection()
+                .await?
+                .object_server()
+                .at("/", Bar { commands: sender })
+                .await
+                .error("Failed to setup DBus server")
+        };
+        if let Err(error) = result.await {
+            log::warn!("Cannot be controlled over DBus: {error}");
+        }
+    });
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        let command = receiver.recv().await?;
+        Some((command, receiver))
+    })
+    .boxed_local()
+}
+
+/// The IDs of the blocks referenced by `reference`: a name (e.g. `sound`), an identifier (e.g.
+/// `sound-1`) or a position in the config file
+pub fn resolve(reference: &str, names: &[&str], block_ids: &HashMap<String, usize>) -> Vec<usize> {
+    if let Some(id) = block_ids.get(reference) {
+        return vec![*id];
+    }
+    if let Ok(id) = reference.parse::<usize>() {
+        return if id < names.len() {
+            vec![id]
+        } else {
+            Vec::new()
+        };
+    }
+    (0..names.len())
+        .filter(|id| names[*id] == reference)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references() {
+        let names = ["sound", "cpu", "sound"];
+        let block_ids = crate::imports::block_ids(&names);
+        let resolve = |reference| resolve(reference, &names, &block_ids);
+        assert_eq!(resolve("sound"), [0, 2]);
+        assert_eq!(resolve("sound-1"), [2]);
+        assert_eq!(resolve("cpu"), [1]);
+        assert_eq!(resolve("1"), [1]);
+        assert!(resolve("3").is_empty());
+        assert!(resolve("sound-2").is_empty());
+        assert!(resolve("time").is_empty());
+    }
+}
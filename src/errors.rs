@@ -172,12 +172,15 @@ impl fmt::Display for Error {
                     write!(f, ". (Cause: {cause})")?;
                 }
             }
-            None => {
-                f.write_str(self.message.as_deref().unwrap_or("Error"))?;
-                if let Some(cause) = &self.cause {
-                    write!(f, ". (Cause: {cause})")?;
+            None => match (&self.message, &self.cause) {
+                (None, Some(cause)) => write!(f, "{cause}")?,
+                (message, cause) => {
+                    f.write_str(message.as_deref().unwrap_or("Error"))?;
+                    if let Some(cause) = cause {
+                        write!(f, ". (Cause: {cause})")?;
+                    }
                 }
-            }
+            },
         }
 
         Ok(())
@@ -32,6 +32,48 @@ impl<I: Iterator<Item = char>> CollectEscaped for I {
     }
 }
 
+/// `text` with the tags of pango markup removed and entities replaced by their characters
+pub fn strip_pango(text: &str) -> String {
+    let mut stripped = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find(['<', '&']) {
+        stripped.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with('<') {
+            // Drop the tag
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+            continue;
+        }
+        let entity = rest.find(';').map(|end| (&rest[1..end], &rest[end + 1..]));
+        let unescaped = entity.and_then(|(name, after)| {
+            let c = match name {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                _ => name
+                    .strip_prefix('#')
+                    .and_then(|code| code.parse().ok())
+                    .and_then(char::from_u32)?,
+            };
+            Some((c, after))
+        });
+        match unescaped {
+            Some((c, after)) => {
+                stripped.push(c);
+                rest = after;
+            }
+            None => {
+                stripped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,5 +83,7 @@ mod tests {
         let orig = "&my 'text' <>";
         let escaped: String = orig.chars().collect_pango_escaped();
         assert_eq!(escaped, "&amp;my &#39;text&#39; &lt;&gt;");
+        assert_eq!(strip_pango(&escaped), orig);
+        assert_eq!(strip_pango("<b>1 &lt; 2</b> & <i>3</i>"), "1 < 2 & 3");
     }
 }
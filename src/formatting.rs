@@ -35,6 +35,9 @@
 //! `max_width` or `max_w` | if text is longer it will be truncated            | Infinity
 //! `rot_interval`         | if text is longer than `max_width` it will be rotated every `rot_interval` seconds | `0.5`
 //!
+//! A rotating text starts from its beginning whenever it changes. The `max_width` and `marquee`
+//! options of a block apply to all text placeholders of the block which have no formatter.
+//!
 //! ## `eng` - Format numbers using engineering notation
 //!
 //! Argument        | Description                                                                                      |Default value
@@ -55,10 +58,15 @@
 //! `width` or `w`         | the width of the bar (in characters)                                            | `5`
 //! `max_value`            | which value is treated as "full". For example, for battery level `100` is full. | `100`
 //!
-//! ## `pango-str` - Just display the text without pango markup escaping
+//! ## `pango-str` or `raw` - Just display the text without pango markup escaping
 //!
 //! No arguments.
 //!
+//! Text values are escaped by the other formatters, so that e.g. a song title with `&` does not
+//! break the bar's markup. Pango markup written in the format itself, such as
+//! `<span foreground='#ff0000'>$text</span>`, is kept. See `markup` in the README to show all
+//! text as is instead.
+//!
 //! # Handling missing placeholders and incorrect types
 //!
 //! Some blocks allow missing placeholders, for example [bluetooth](crate::blocks::bluetooth)'s
@@ -71,6 +79,22 @@
 //! always displayed, followed by the actual percentage or "N/A" in case percentage is not
 //! available. This example does exactly the same thing as `Percentage: $percentage|Percentage: N/A`
 //!
+//! # Fallback chains
+//!
+//! `${a|b|'text'}` shows the first of the placeholders `a` and `b` which is set and, if it is
+//! text, not empty. If none of them is, the quoted text at the end is shown. For example,
+//! `${ssid|device|'wired'}` shows the SSID, else the device, else "wired". In more detail:
+//!
+//! - Placeholders are written without `$`, and each one can have its own formatter:
+//!   `${title.str(max_w:20)|file}`. Spaces around `|` are allowed.
+//! - The quoted text is optional and must come last. In it, `|`, `{` and `}` need no escaping, but
+//!   `'` and `\` are written `\'` and `\\`.
+//! - Without quoted text, a chain with no placeholder set fails like a missing placeholder, so
+//!   the format moves on to its next alternative: in `{${ssid|device}|offline}`, "offline" is shown
+//!   if neither is set.
+//! - A chain is used like a placeholder, also inside `{}`. Its `|` never separates alternatives of
+//!   the surrounding format.
+//!
 //! # How to use flags
 //!
 //! Some blocks provide flags, which can be used to change the format based on some critera. For
@@ -83,6 +107,7 @@
 
 pub mod config;
 pub mod formatter;
+pub mod marquee;
 pub mod parse;
 pub mod prefix;
 pub mod scheduling;
@@ -91,7 +116,6 @@ pub mod unit;
 pub mod value;
 
 use std::borrow::Cow;
-use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::config::SharedConfig;
@@ -99,7 +123,88 @@ use crate::errors::*;
 use template::FormatTemplate;
 use value::Value;
 
-pub type Values = HashMap<Cow<'static, str>, Value>;
+/// The values of a block's placeholders
+///
+/// Blocks provide at most a few dozen values, so they are kept in a `Vec` and looked up by
+/// scanning it, which is faster than hashing the keys. The API mirrors the parts of `HashMap`
+/// which blocks use.
+#[derive(Debug, Clone, Default)]
+pub struct Values(Vec<(Cow<'static, str>, Value)>);
+
+impl Values {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.0.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Insert a value, returning the previous value of `key`
+    pub fn insert(&mut self, key: Cow<'static, str>, value: Value) -> Option<Value> {
+        match self.get_mut(&key) {
+            Some(old) => Some(std::mem::replace(old, value)),
+            None => {
+                self.0.push((key, value));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let index = self.0.iter().position(|(k, _)| k == key)?;
+        Some(self.0.swap_remove(index).1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Cow<'static, str>, &Value)> {
+        self.0.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Cow<'static, str>> {
+        self.0.iter().map(|(k, _)| k)
+    }
+}
+
+impl Extend<(Cow<'static, str>, Value)> for Values {
+    fn extend<T: IntoIterator<Item = (Cow<'static, str>, Value)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl FromIterator<(Cow<'static, str>, Value)> for Values {
+    fn from_iter<T: IntoIterator<Item = (Cow<'static, str>, Value)>>(iter: T) -> Self {
+        let mut values = Self::new();
+        values.extend(iter);
+        values
+    }
+}
+
+impl IntoIterator for Values {
+    type Item = (Cow<'static, str>, Value);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Format {
@@ -113,6 +218,27 @@ impl Format {
         self.full.contains_key(key) || self.short.contains_key(key)
     }
 
+    /// Whether the placeholder is used other than in a fallback chain
+    pub fn contains_plain_key(&self, key: &str) -> bool {
+        self.full.contains_plain_key(key) || self.short.contains_plain_key(key)
+    }
+
+    /// The names of all placeholders used in the full and short templates
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for name in self
+            .full
+            .placeholders()
+            .into_iter()
+            .chain(self.short.placeholders())
+        {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.into());
+            }
+        }
+        names
+    }
+
     pub fn intervals(&self) -> Vec<u64> {
         self.intervals.clone()
     }
@@ -132,6 +258,11 @@ impl Format {
             .error("Failed to render short text")?;
         Ok((full, short))
     }
+
+    /// The icons which the full text shows, in order
+    pub fn icons(&self, values: &Values, config: &SharedConfig) -> Vec<String> {
+        self.full.icons(values, config)
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -172,3 +303,77 @@ impl Metadata {
         *self == Default::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values() {
+        let mut values = map! {
+            "a" => Value::number(1),
+            "b" => Value::number(2),
+        };
+        assert!(values.insert("a".into(), Value::number(3)).is_some());
+        assert!(values
+            .insert(String::from("c").into(), Value::flag())
+            .is_none());
+        assert_eq!(values.len(), 3);
+        assert!(matches!(
+            values.get("a").unwrap().inner,
+            value::ValueInner::Number { val, .. } if val == 3.
+        ));
+        assert!(values.remove("b").is_some());
+        assert!(values.remove("b").is_none());
+        assert!(!values.contains_key("b"));
+        assert_eq!(values.keys().count(), 2);
+    }
+
+    #[test]
+    fn placeholders() {
+        let format: config::Config = "$a {$b.eng(w:2)|$c} $a".parse().unwrap();
+        let format = format.with_default("").unwrap();
+        assert_eq!(format.placeholders(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn fallback() {
+        let render = |format: &str, values: &Values| -> Result<String> {
+            let format: config::Config = format.parse()?;
+            let (full, _) = format
+                .with_default("")?
+                .render(values, &SharedConfig::default())?;
+            Ok(full.iter().map(|f| f.text.as_str()).collect())
+        };
+        let values = map! {
+            "empty" => Value::text(String::new()),
+            "device" => Value::text("eth0".into()),
+            "speed" => Value::number(12),
+            "up" => Value::flag(),
+        };
+        assert_eq!(render(" ${ssid|device} ", &values).unwrap(), " eth0 ");
+        // Empty text is skipped like missing placeholders
+        assert_eq!(
+            render("${empty|speed.eng(w:3)|'x'}", &values).unwrap(),
+            " 12"
+        );
+        assert_eq!(render("${ssid|empty|'wired'}", &values).unwrap(), "wired");
+        // Without a default, the chain fails like a missing placeholder
+        assert!(render("${ssid|empty}", &values).is_err());
+        assert_eq!(render("{${ssid}|none}", &values).unwrap(), "none");
+        // Flags, as in `${b|c}{b or c is set}`
+        assert_eq!(
+            render("${down|up}{up or down}|neither", &values).unwrap(),
+            "up or down"
+        );
+        assert_eq!(
+            render("${down|ssid}{up or down}|neither", &values).unwrap(),
+            "neither"
+        );
+        let format: config::Config = "${a|b.eng(w:2)} $c".parse().unwrap();
+        assert_eq!(
+            format.with_default("").unwrap().placeholders(),
+            ["a", "b", "c"]
+        );
+    }
+}
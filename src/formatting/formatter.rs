@@ -1,7 +1,8 @@
 use std::fmt::Debug;
 use std::iter::repeat;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
+use super::marquee::Marquee;
 use super::parse::Arg;
 use super::prefix::Prefix;
 use super::unit::Unit;
@@ -21,8 +22,7 @@ const DEFAULT_NUMBER_WIDTH: usize = 2;
 pub const DEFAULT_STRING_FORMATTER: StrFormatter = StrFormatter {
     min_width: DEFAULT_STR_MIN_WIDTH,
     max_width: DEFAULT_STR_MAX_WIDTH,
-    rot_interval_ms: None,
-    init_time: None,
+    marquee: None,
 };
 
 // TODO: split those defaults
@@ -86,11 +86,11 @@ pub fn new_formatter(name: &str, args: &[Arg]) -> Result<Box<dyn Formatter>> {
             Ok(Box::new(StrFormatter {
                 min_width,
                 max_width,
-                rot_interval_ms: rot_interval.map(|x| (x * 1e3) as u64),
-                init_time: Some(Instant::now()),
+                marquee: rot_interval
+                    .map(|x| Marquee::new(max_width, Some(Duration::from_secs_f64(x)))),
             }))
         }
-        "pango-str" => {
+        "pango-str" | "raw" => {
             #[allow(clippy::never_loop)]
             for arg in args {
                 return Err(Error::new(format!(
@@ -128,8 +128,8 @@ pub fn new_formatter(name: &str, args: &[Arg]) -> Result<Box<dyn Formatter>> {
 pub struct StrFormatter {
     min_width: usize,
     max_width: usize,
-    rot_interval_ms: Option<u64>,
-    init_time: Option<Instant>,
+    /// Rotates texts which are longer than `max_width`
+    marquee: Option<Marquee>,
 }
 
 impl Formatter for StrFormatter {
@@ -137,20 +137,9 @@ impl Formatter for StrFormatter {
         match val {
             Value::Text(text) => {
                 let width = text.chars().count();
-                Ok(match (self.rot_interval_ms, self.init_time) {
-                    (Some(rot_interval_ms), Some(init_time)) if width > self.max_width => {
-                        let width = width + 1; // Now we include '|' at the end
-                        let step = (init_time.elapsed().as_millis() as u64 / rot_interval_ms)
-                            as usize
-                            % width;
-                        let w1 = self.max_width.min(width - step);
-                        text.chars()
-                            .chain(Some('|'))
-                            .skip(step)
-                            .take(w1)
-                            .chain(text.chars())
-                            .take(self.max_width)
-                            .collect_pango_escaped()
+                Ok(match &self.marquee {
+                    Some(marquee) if width > self.max_width => {
+                        marquee.visible(text).chars().collect_pango_escaped()
                     }
                     _ => text
                         .chars()
@@ -159,7 +148,7 @@ impl Formatter for StrFormatter {
                         .collect_pango_escaped(),
                 })
             }
-            Value::Icon(icon) => Ok(icon.clone()), // No escaping
+            Value::Icon(icon) => Ok(icon.text().to_owned()), // No escaping
             Value::Number { .. } => Err(Error::new_format(
                 "A number cannot be formatted with 'str' formatter",
             )),
@@ -170,7 +159,7 @@ impl Formatter for StrFormatter {
     }
 
     fn interval(&self) -> Option<Duration> {
-        self.rot_interval_ms.map(Duration::from_millis)
+        self.marquee.as_ref().and_then(|marquee| marquee.interval)
     }
 }
 
@@ -180,7 +169,8 @@ pub struct PangoStrFormatter;
 impl Formatter for PangoStrFormatter {
     fn format(&self, val: &Value) -> Result<String> {
         match val {
-            Value::Text(x) | Value::Icon(x) => Ok(x.clone()), // No escaping
+            Value::Text(x) => Ok(x.clone()), // No escaping
+            Value::Icon(icon) => Ok(icon.text().to_owned()),
             Value::Number { .. } => Err(Error::new_format(
                 "A number cannot be formatted with 'str' formatter",
             )),
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits the width of texts. Texts which are wider are truncated or, if `interval` is set,
+/// rotated by one character every `interval`. The rotation of a text starts at its beginning
+/// when it is first shown, so a new text (e.g. the next song) is shown from the start.
+#[derive(Debug)]
+pub struct Marquee {
+    pub max_width: usize,
+    pub interval: Option<Duration>,
+    /// When each of the texts which are being rotated was first and last shown
+    shown: Mutex<HashMap<String, (Instant, Instant)>>,
+}
+
+impl Marquee {
+    pub fn new(max_width: usize, interval: Option<Duration>) -> Self {
+        Self {
+            max_width,
+            interval,
+            shown: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The part of `text` which is visible now
+    pub fn visible(&self, text: &str) -> String {
+        self.visible_at(text, Instant::now())
+    }
+
+    fn visible_at(&self, text: &str, now: Instant) -> String {
+        let width = text.chars().count();
+        let interval = match self.interval {
+            Some(interval) if width > self.max_width => interval,
+            _ => return text.chars().take(self.max_width).collect(),
+        };
+        let mut shown = self.shown.lock().unwrap();
+        // A rotating text is shown at every interval, so texts which were not shown for a while
+        // have been replaced
+        shown.retain(|_, (_, last)| now.duration_since(*last) < interval * 10);
+        let (first, last) = shown.entry(text.into()).or_insert((now, now));
+        *last = now;
+        let step = now.duration_since(*first).as_millis() / interval.as_millis().max(1);
+        rotate(text, self.max_width, step as usize)
+    }
+}
+
+/// `max_width` characters of `text`, starting at `step`. The end of the text is separated from
+/// its start by `|`.
+fn rotate(text: &str, max_width: usize, step: usize) -> String {
+    let step = step % (text.chars().count() + 1);
+    text.chars()
+        .chain(Some('|'))
+        .chain(text.chars())
+        .skip(step)
+        .take(max_width)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation() {
+        assert_eq!(rotate("abcdef", 4, 0), "abcd");
+        assert_eq!(rotate("abcdef", 4, 4), "ef|a");
+        assert_eq!(rotate("abcdef", 4, 7), "abcd");
+
+        let marquee = Marquee::new(4, Some(Duration::from_millis(500)));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(marquee.visible_at("abcdef", at(0)), "abcd");
+        assert_eq!(marquee.visible_at("abcdef", at(500)), "bcde");
+        assert_eq!(marquee.visible_at("abcdef", at(1200)), "cdef");
+        // Texts which fit are not rotated
+        assert_eq!(marquee.visible_at("abc", at(1200)), "abc");
+        // A new text starts from its beginning
+        assert_eq!(marquee.visible_at("uvwxyz", at(1500)), "uvwx");
+        assert_eq!(marquee.visible_at("uvwxyz", at(2000)), "vwxy");
+        assert_eq!(marquee.visible_at("abcdef", at(2000)), "ef|a");
+        // So does a text which was replaced for a while
+        assert_eq!(marquee.visible_at("abcdef", at(10000)), "abcd");
+    }
+
+    #[test]
+    fn truncation() {
+        let marquee = Marquee::new(4, None);
+        assert_eq!(marquee.visible("abcdef"), "abcd");
+        assert_eq!(marquee.visible("äöü"), "äöü");
+        assert!(marquee.shown.lock().unwrap().is_empty());
+    }
+}
@@ -3,7 +3,7 @@ use nom::{
     bytes::complete::{escaped_transform, tag, take_while, take_while1},
     character::complete::{anychar, char},
     combinator::{cut, eof, map, not, opt},
-    multi::{many0, separated_list0},
+    multi::{many0, separated_list0, separated_list1},
     sequence::{preceded, separated_pair, terminated, tuple},
     IResult, Parser,
 };
@@ -28,10 +28,18 @@ pub struct Placeholder<'a> {
     pub formatter: Option<Formatter<'a>>,
 }
 
+/// `${a|b|'default'}`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Fallback<'a> {
+    pub placeholders: Vec<Placeholder<'a>>,
+    pub default: Option<String>,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Token<'a> {
     Text(String),
     Placeholder(Placeholder<'a>),
+    Fallback(Fallback<'a>),
     Icon(&'a str),
     Recursive(FormatTemplate<'a>),
 }
@@ -122,6 +130,45 @@ fn parse_placeholder(i: &str) -> IResult<&str, Placeholder, PError> {
         .parse(i)
 }
 
+// `'quoted \' text'`
+fn parse_literal(i: &str) -> IResult<&str, String, PError<'_>> {
+    preceded(
+        char('\''),
+        cut(terminated(
+            map(
+                opt(escaped_transform(
+                    take_while1(|x| x != '\'' && x != '\\'),
+                    '\\',
+                    anychar,
+                )),
+                Option::unwrap_or_default,
+            ),
+            char('\''),
+        )),
+    )(i)
+}
+
+// `${var|key.eng(w:2)}`
+// `${ var | key | 'default' }`
+fn parse_fallback(i: &str) -> IResult<&str, Fallback<'_>, PError<'_>> {
+    let separator = || preceded(spaces, char('|'));
+    let placeholder = preceded(spaces, tuple((alphanum1, opt(parse_formatter))))
+        .map(|(name, formatter)| Placeholder { name, formatter });
+    let default = preceded(separator(), preceded(spaces, parse_literal));
+    preceded(
+        tag("${"),
+        cut(terminated(
+            tuple((separated_list1(separator(), placeholder), opt(default))),
+            preceded(spaces, char('}')),
+        )),
+    )
+    .map(|(placeholders, default)| Fallback {
+        placeholders,
+        default,
+    })
+    .parse(i)
+}
+
 // `just escaped \| text`
 fn parse_string(i: &str) -> IResult<&str, String, PError> {
     preceded(
@@ -148,6 +195,8 @@ fn parse_token_list(i: &str) -> IResult<&str, TokenList, PError> {
     map(
         many0(alt((
             map(parse_string, Token::Text),
+            // Before placeholders, which also start with `$`
+            map(parse_fallback, Token::Fallback),
             map(parse_placeholder, Token::Placeholder),
             map(parse_icon, Token::Icon),
             map(parse_recursive_template, Token::Recursive),
@@ -322,6 +371,67 @@ mod tests {
         assert!(parse_icon("^2").is_err());
     }
 
+    #[test]
+    fn fallback() {
+        let placeholder = |name| Placeholder {
+            name,
+            formatter: None,
+        };
+        assert_eq!(
+            parse_fallback("${ssid|device|'wired'}"),
+            Ok((
+                "",
+                Fallback {
+                    placeholders: vec![placeholder("ssid"), placeholder("device")],
+                    default: Some("wired".into()),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_fallback("${ a.str(max_w:3) | b }"),
+            Ok((
+                "",
+                Fallback {
+                    placeholders: vec![
+                        Placeholder {
+                            name: "a",
+                            formatter: Some(Formatter {
+                                name: "str",
+                                args: vec![Arg {
+                                    key: "max_w",
+                                    val: "3"
+                                }]
+                            })
+                        },
+                        placeholder("b")
+                    ],
+                    default: None,
+                }
+            ))
+        );
+        // Pipes and braces need no escaping in the default, quotes and backslashes do
+        assert_eq!(
+            parse_fallback(r"${a|'x|{y}\'\\'}"),
+            Ok((
+                "",
+                Fallback {
+                    placeholders: vec![placeholder("a")],
+                    default: Some(r"x|{y}'\".into()),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_fallback("${a|''}").unwrap().1.default,
+            Some(String::new())
+        );
+        // At least one placeholder, and the default comes last
+        assert!(parse_fallback("${'x'}").is_err());
+        assert!(parse_fallback("${a|'x'|b}").is_err());
+        assert!(parse_fallback("${a|'x}").is_err());
+        assert!(parse_fallback("${a").is_err());
+        assert!(parse_fallback("$a").is_err());
+    }
+
     #[test]
     fn token_list() {
         assert_eq!(
@@ -378,6 +488,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn nested_fallback() {
+        let fallback = |name, default: &str| {
+            Token::Fallback(Fallback {
+                placeholders: vec![Placeholder {
+                    name,
+                    formatter: None,
+                }],
+                default: Some(default.into()),
+            })
+        };
+        // The pipes of a fallback chain don't separate the alternatives around it, escaped
+        // pipes are text
+        assert_eq!(
+            parse_format_template(r"{${a|'-'} \| $b|${c|'|'}}"),
+            Ok((
+                "",
+                FormatTemplate(vec![TokenList(vec![Token::Recursive(FormatTemplate(
+                    vec![
+                        TokenList(vec![
+                            fallback("a", "-"),
+                            Token::Text(" | ".into()),
+                            Token::Placeholder(Placeholder {
+                                name: "b",
+                                formatter: None,
+                            }),
+                        ]),
+                        TokenList(vec![fallback("c", "|")]),
+                    ]
+                ))])])
+            ))
+        );
+    }
+
     #[test]
     fn full() {
         assert_eq!(
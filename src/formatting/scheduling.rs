@@ -67,6 +67,8 @@ pub fn manage_widgets_updates() -> (UnboundedSender<(usize, Vec<u64>)>, BoxedStr
 
 fn single_block_next_update(intervals: &[u64], time: u64, last_update: u64) -> u64 {
     fn next_update(time: u64, interval: u64) -> u64 {
+        // Intervals shorter than a millisecond are rounded down to zero
+        let interval = interval.max(1);
         time + interval - time % interval
     }
     let mut time_to_next = u64::MAX;
@@ -98,5 +100,7 @@ mod tests {
         assert_eq!(single_block_next_update(inntervals, 300, 290), 0);
         assert_eq!(single_block_next_update(inntervals, 300, 300), 100);
         assert_eq!(single_block_next_update(inntervals, 800, 300), 0);
+        // A zero interval counts as one millisecond
+        assert_eq!(single_block_next_update(&[0], 800, 800), 1);
     }
 }
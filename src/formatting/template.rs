@@ -1,10 +1,16 @@
 use super::formatter::{new_formatter, Formatter};
 use super::parse;
+use super::value::{Value, ValueInner};
 use super::{Fragment, Values};
 use crate::config::SharedConfig;
 use crate::errors::*;
+use crate::escape::CollectEscaped;
+use crate::icons::IconPosition;
 
+use once_cell::sync::OnceCell;
+use std::borrow::Cow;
 use std::str::FromStr;
+use std::sync::Arc;
 
 #[derive(Debug, Default)]
 pub struct FormatTemplate(pub Vec<TokenList>);
@@ -20,34 +26,108 @@ pub enum Token {
         name: String,
         formatter: Option<Box<dyn Formatter>>,
     },
+    /// The first placeholder which is set, else the default text
+    Fallback {
+        placeholders: Vec<(String, Option<Box<dyn Formatter>>)>,
+        default: Option<String>,
+    },
     Icon {
         name: String,
+        /// The icon resolved with the first config it was rendered with
+        cache: IconCache,
     },
 }
 
+/// Caches an icon lookup, since the icon set of a block does not change
+#[derive(Debug, Default)]
+pub struct IconCache(OnceCell<(usize, usize, Option<String>)>);
+
+impl IconCache {
+    fn get(&self, name: &str, config: &SharedConfig) -> Option<Cow<'_, str>> {
+        // Identify the config by its icon set and icons format
+        let key = (
+            Arc::as_ptr(&config.icons) as usize,
+            Arc::as_ptr(&config.icons_format) as usize,
+        );
+        let (icons, format, icon) = self.0.get_or_init(|| (key.0, key.1, config.get_icon(name)));
+        if (*icons, *format) == key {
+            icon.as_deref().map(Cow::Borrowed)
+        } else {
+            config.get_icon(name).map(Cow::Owned)
+        }
+    }
+}
+
 impl FormatTemplate {
     pub fn contains_key(&self, key: &str) -> bool {
         self.0.iter().any(|token_list| {
             token_list.0.iter().any(|token| match token {
                 Token::Placeholder { name, .. } => name == key,
+                Token::Fallback { placeholders, .. } => {
+                    placeholders.iter().any(|(name, _)| name == key)
+                }
                 Token::Recursive(rec) => rec.contains_key(key),
                 _ => false,
             })
         })
     }
 
+    /// Whether the placeholder is used other than in a fallback chain
+    pub fn contains_plain_key(&self, key: &str) -> bool {
+        self.0.iter().any(|token_list| {
+            token_list.0.iter().any(|token| match token {
+                Token::Placeholder { name, .. } => name == key,
+                Token::Recursive(rec) => rec.contains_plain_key(key),
+                _ => false,
+            })
+        })
+    }
+
+    /// The names of all placeholders used in the template
+    pub fn placeholders(&self) -> Vec<&str> {
+        self.0.iter().flat_map(TokenList::placeholders).collect()
+    }
+
     pub fn render(&self, values: &Values, config: &SharedConfig) -> Result<Vec<Fragment>> {
+        let mut icons = Vec::new();
+        let mut retval = self.render_moving_icons(values, config, &mut icons)?;
+        if !icons.is_empty() {
+            push_icons(&mut retval, icons);
+        }
+        Ok(retval)
+    }
+
+    /// Like [`FormatTemplate::render`], but icons which are shown after the text, see
+    /// [`IconPosition`], are collected in `icons`
+    fn render_moving_icons(
+        &self,
+        values: &Values,
+        config: &SharedConfig,
+        icons: &mut Vec<Fragment>,
+    ) -> Result<Vec<Fragment>> {
         for (i, token_list) in self.0.iter().enumerate() {
-            match token_list.render(values, config) {
+            let moved = icons.len();
+            match token_list.render(values, config, icons) {
                 Ok(res) => return Ok(res),
                 Err(e) if e.kind != ErrorKind::Format => return Err(e),
                 Err(e) if i == self.0.len() - 1 => return Err(e),
-                _ => (),
+                _ => icons.truncate(moved),
             }
         }
         Ok(Vec::new())
     }
 
+    /// The icons which [`FormatTemplate::render`] shows, in order
+    pub fn icons(&self, values: &Values, config: &SharedConfig) -> Vec<String> {
+        if config.icon_position == IconPosition::Hidden {
+            return Vec::new();
+        }
+        self.0
+            .iter()
+            .find(|token_list| token_list.render(values, config, &mut Vec::new()).is_ok())
+            .map_or_else(Vec::new, |token_list| token_list.icons(values, config))
+    }
+
     pub fn init_intervals(&self, intervals: &mut Vec<u64>) {
         for tl in &self.0 {
             for t in &tl.0 {
@@ -60,6 +140,13 @@ impl FormatTemplate {
                             intervals.push(i.as_millis() as u64);
                         }
                     }
+                    Token::Fallback { placeholders, .. } => {
+                        for (_, f) in placeholders {
+                            if let Some(i) = f.as_ref().and_then(|f| f.interval()) {
+                                intervals.push(i.as_millis() as u64);
+                            }
+                        }
+                    }
                     _ => (),
                 }
             }
@@ -68,52 +155,128 @@ impl FormatTemplate {
 }
 
 impl TokenList {
-    pub fn render(&self, values: &Values, config: &SharedConfig) -> Result<Vec<Fragment>> {
+    fn placeholders(&self) -> Vec<&str> {
+        self.0
+            .iter()
+            .flat_map(|token| match token {
+                Token::Placeholder { name, .. } => vec![name.as_str()],
+                Token::Fallback { placeholders, .. } => {
+                    placeholders.iter().map(|(name, _)| name.as_str()).collect()
+                }
+                Token::Recursive(rec) => rec.placeholders(),
+                _ => Vec::new(),
+            })
+            .collect()
+    }
+
+    fn icons(&self, values: &Values, config: &SharedConfig) -> Vec<String> {
+        let mut icons = Vec::new();
+        for token in &self.0 {
+            match token {
+                Token::Icon { name, cache } => {
+                    icons.extend(cache.get(name, config).map(Cow::into_owned));
+                }
+                Token::Placeholder { name, .. } => {
+                    if let Some(ValueInner::Icon(icon)) = values.get(name).map(|v| &v.inner) {
+                        icons.push(icon.text().to_owned());
+                    }
+                }
+                Token::Fallback { placeholders, .. } => {
+                    if let Some((value, _)) = first_set(placeholders, values) {
+                        if let ValueInner::Icon(icon) = &value.inner {
+                            icons.push(icon.text().to_owned());
+                        }
+                    }
+                }
+                Token::Recursive(rec) => icons.extend(rec.icons(values, config)),
+                Token::Text(_) => (),
+            }
+        }
+        icons
+    }
+
+    /// Icons which are moved after the text are collected in `icons`. The whitespace after a
+    /// moved or hidden icon, or before it if it is last, is left out, since it separated the icon
+    /// from the text.
+    fn render(
+        &self,
+        values: &Values,
+        config: &SharedConfig,
+        icons: &mut Vec<Fragment>,
+    ) -> Result<Vec<Fragment>> {
         let mut retval = Vec::new();
         let mut cur = Fragment::default();
+        let mut after_icon = false;
         for token in &self.0 {
-            match token {
-                Token::Text(text) => {
-                    if cur.metadata.is_default() {
-                        cur.text.push_str(text);
-                    } else {
-                        if !cur.text.is_empty() {
-                            retval.push(cur);
+            if config.icon_position != IconPosition::Left {
+                let icon = match token {
+                    Token::Icon { name, cache } => {
+                        let icon = cache
+                            .get(name, config)
+                            .or_format_error(|| format!("Icon '{name}' not found"))?;
+                        Some(icon.into_owned().into())
+                    }
+                    Token::Placeholder { name, formatter } => match values.get(name) {
+                        Some(value) if is_icon(value) => {
+                            Some(render_value(value, formatter, config)?)
                         }
-                        cur = text.clone().into();
+                        _ => None,
+                    },
+                    Token::Fallback { placeholders, .. } => match first_set(placeholders, values) {
+                        Some((value, formatter)) if is_icon(value) => {
+                            Some(render_value(value, formatter, config)?)
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if let Some(icon) = icon {
+                    if config.icon_position == IconPosition::Right {
+                        icons.push(icon);
                     }
+                    after_icon = true;
+                    continue;
                 }
+            }
+            match token {
+                Token::Text(text) if std::mem::take(&mut after_icon) => {
+                    push_text(&mut retval, &mut cur, text.trim_start());
+                }
+                Token::Text(text) => push_text(&mut retval, &mut cur, text),
                 Token::Recursive(rec) => {
+                    after_icon = false;
                     if !cur.text.is_empty() {
                         retval.push(cur);
                     }
-                    retval.extend(rec.render(values, config)?);
+                    retval.extend(rec.render_moving_icons(values, config, icons)?);
                     cur = retval.pop().unwrap_or_default();
                 }
                 Token::Placeholder { name, formatter } => {
                     let value = values
                         .get(name.as_str())
                         .or_format_error(|| format!("Placeholder '{name}' not found"))?;
-                    let formatter = formatter
-                        .as_ref()
-                        .map(Box::as_ref)
-                        .unwrap_or_else(|| value.default_formatter());
-                    let formatted = formatter.format(&value.inner)?;
-                    if value.metadata == cur.metadata {
-                        cur.text.push_str(&formatted);
-                    } else {
-                        if !cur.text.is_empty() {
-                            retval.push(cur);
-                        }
-                        cur = Fragment {
-                            text: formatted,
-                            metadata: value.metadata,
-                        };
-                    }
+                    push_value(&mut retval, &mut cur, value, formatter, config)?;
                 }
-                Token::Icon { name } => {
-                    let icon = config
-                        .get_icon(name)
+                Token::Fallback {
+                    placeholders,
+                    default,
+                } => match (first_set(placeholders, values), default) {
+                    (Some((value, formatter)), _) => {
+                        push_value(&mut retval, &mut cur, value, formatter, config)?;
+                    }
+                    (None, Some(default)) => push_text(&mut retval, &mut cur, default),
+                    (None, None) => {
+                        let names: Vec<&str> =
+                            placeholders.iter().map(|(name, _)| name.as_str()).collect();
+                        return Err(Error::new_format(format!(
+                            "None of the placeholders '{}' found",
+                            names.join("', '")
+                        )));
+                    }
+                },
+                Token::Icon { name, cache } => {
+                    let icon = cache
+                        .get(name, config)
                         .or_format_error(|| format!("Icon '{name}' not found"))?;
                     if cur.metadata.is_default() {
                         cur.text.push_str(&icon);
@@ -121,12 +284,17 @@ impl TokenList {
                         if !cur.text.is_empty() {
                             retval.push(cur);
                         }
-                        cur = icon.into();
+                        cur = icon.into_owned().into();
                     }
                 }
             }
         }
 
+        if after_icon {
+            // Nothing follows the icon, so the whitespace before it separated it from the text
+            let len = cur.text.trim_end().len();
+            cur.text.truncate(len);
+        }
         if !cur.text.is_empty() {
             retval.push(cur);
         }
@@ -135,6 +303,103 @@ impl TokenList {
     }
 }
 
+fn render_value(
+    value: &Value,
+    formatter: &Option<Box<dyn Formatter>>,
+    config: &SharedConfig,
+) -> Result<Fragment> {
+    let mut cur = Fragment::default();
+    push_value(&mut Vec::new(), &mut cur, value, formatter, config)?;
+    Ok(cur)
+}
+
+fn is_icon(value: &Value) -> bool {
+    matches!(value.inner, ValueInner::Icon(_))
+}
+
+/// Append `icons` to the rendered text, before its trailing whitespace, separated by spaces
+fn push_icons(retval: &mut Vec<Fragment>, icons: Vec<Fragment>) {
+    let trailing = match retval.last_mut() {
+        Some(last) if last.text.trim_end().is_empty() => std::mem::take(&mut last.text),
+        Some(last) => last.text.split_off(last.text.trim_end().len()),
+        None => String::new(),
+    };
+    for icon in icons {
+        if retval
+            .last()
+            .is_some_and(|last| !last.text.ends_with(char::is_whitespace))
+        {
+            push_fragment(retval, " ".to_string().into());
+        }
+        push_fragment(retval, icon);
+    }
+    if !trailing.is_empty() {
+        push_fragment(retval, trailing.into());
+    }
+}
+
+fn push_fragment(retval: &mut Vec<Fragment>, fragment: Fragment) {
+    match retval.last_mut() {
+        Some(last) if last.metadata == fragment.metadata => last.text.push_str(&fragment.text),
+        _ => retval.push(fragment),
+    }
+}
+
+/// The value of the first placeholder of a fallback chain which is set and, if it is text, not
+/// empty
+fn first_set<'a, 'b>(
+    placeholders: &'a [(String, Option<Box<dyn Formatter>>)],
+    values: &'b Values,
+) -> Option<(&'b Value, &'a Option<Box<dyn Formatter>>)> {
+    placeholders.iter().find_map(|(name, formatter)| {
+        let value = values.get(name)?;
+        let empty = matches!(&value.inner, ValueInner::Text(text) if text.is_empty());
+        (!empty).then_some((value, formatter))
+    })
+}
+
+fn push_text(retval: &mut Vec<Fragment>, cur: &mut Fragment, text: &str) {
+    if cur.metadata.is_default() {
+        cur.text.push_str(text);
+    } else {
+        if !cur.text.is_empty() {
+            retval.push(std::mem::take(cur));
+        }
+        *cur = text.to_owned().into();
+    }
+}
+
+fn push_value(
+    retval: &mut Vec<Fragment>,
+    cur: &mut Fragment,
+    value: &Value,
+    formatter: &Option<Box<dyn Formatter>>,
+    config: &SharedConfig,
+) -> Result<()> {
+    let formatted = match (formatter, &value.inner, &config.marquee) {
+        (None, ValueInner::Text(text), Some(marquee)) => {
+            marquee.visible(text).chars().collect_pango_escaped()
+        }
+        (formatter, ..) => formatter
+            .as_ref()
+            .map(Box::as_ref)
+            .unwrap_or_else(|| value.default_formatter())
+            .format(&value.inner)?,
+    };
+    if value.metadata == cur.metadata {
+        cur.text.push_str(&formatted);
+    } else {
+        if !cur.text.is_empty() {
+            retval.push(std::mem::take(cur));
+        }
+        *cur = Fragment {
+            text: formatted,
+            metadata: value.metadata,
+        };
+    }
+    Ok(())
+}
+
 impl FromStr for FormatTemplate {
     type Err = Error;
 
@@ -184,8 +449,23 @@ impl TryFrom<parse::Token<'_>> for Token {
                     .map(|fmt| new_formatter(fmt.name, &fmt.args))
                     .transpose()?,
             },
+            parse::Token::Fallback(fallback) => Self::Fallback {
+                placeholders: fallback
+                    .placeholders
+                    .into_iter()
+                    .map(|placeholder| {
+                        let formatter = placeholder
+                            .formatter
+                            .map(|fmt| new_formatter(fmt.name, &fmt.args))
+                            .transpose()?;
+                        Ok((placeholder.name.to_owned(), formatter))
+                    })
+                    .collect::<Result<_>>()?,
+                default: fallback.default,
+            },
             parse::Token::Icon(icon) => Self::Icon {
                 name: icon.to_owned(),
+                cache: IconCache::default(),
             },
             parse::Token::Recursive(rec) => Self::Recursive(rec.try_into()?),
         })
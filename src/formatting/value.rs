@@ -1,6 +1,7 @@
 use super::formatter;
 use super::unit::Unit;
 use super::Metadata;
+use crate::icons::Icon;
 
 #[derive(Debug, Clone)]
 pub struct Value {
@@ -11,7 +12,7 @@ pub struct Value {
 #[derive(Debug, Clone)]
 pub enum ValueInner {
     Text(String),
-    Icon(String),
+    Icon(Icon),
     Number { val: f64, unit: Unit },
     Flag,
 }
@@ -46,8 +47,8 @@ impl Value {
         Self::new(ValueInner::Flag)
     }
 
-    pub fn icon(icon: String) -> Self {
-        Self::new(ValueInner::Icon(icon))
+    pub fn icon(icon: impl Into<Icon>) -> Self {
+        Self::new(ValueInner::Icon(icon.into()))
     }
 
     pub fn text(text: String) -> Self {
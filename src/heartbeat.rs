@@ -0,0 +1,255 @@
+//! Heartbeats which tell a server that the machine is up
+//!
+//! When the top-level `[heartbeat]` table is present, the bar POSTs a small JSON document to `url`
+//! every `interval` seconds, e.g. for presence detection on a home server. Heartbeats are sent by
+//! a separate task, so they never delay the bar. Failures are logged at debug level, and after
+//! each consecutive failure the delay until the next attempt doubles, up to an hour (or
+//! `interval` if it is longer). Heartbeats can be paused and resumed with the `SetHeartbeat`
+//! method over DBus, see [`control`](crate::control).
+//!
+//! The document has the following fields:
+//!
+//! Field       | Value
+//! ------------|------
+//! `hostname`  | The host name of the machine
+//! `uptime`    | Seconds since the machine booted
+//! `version`   | The version of i3status-rs
+//! `timestamp` | UNIX timestamp of the heartbeat
+//!
+//! For example: `{"hostname":"laptop","uptime":8215.32,"version":"0.32.3","timestamp":1700000000}`
+//!
+//! # Configuration
+//!
+//! Key        | Values                                                     | Default
+//! -----------|------------------------------------------------------------|--------
+//! `url`      | The URL to POST heartbeats to                              | Required
+//! `token`    | Sent as a bearer token in the `Authorization` header       | None
+//! `interval` | How often to send a heartbeat, in seconds. Must be positive. | `60`
+//!
+//! # Example
+//!
+//! ```toml
+//! [heartbeat]
+//! url = "https://home.example.com/api/presence"
+//! token = "..."
+//! interval = 120
+//! ```
+
+use std::time::Duration;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::sync::watch;
+
+use crate::errors::*;
+use crate::util::read_file;
+use crate::wrappers::Seconds;
+use crate::REQWEST_CLIENT;
+
+/// The longest delay after failures, unless `interval` is longer
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub url: String,
+    pub token: Option<String>,
+    #[serde(
+        default = "default_interval",
+        deserialize_with = "deserialize_interval"
+    )]
+    pub interval: Seconds<false>,
+}
+
+fn default_interval() -> Seconds<false> {
+    60.into()
+}
+
+fn deserialize_interval<'de, D>(deserializer: D) -> Result<Seconds<false>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let interval = Seconds::deserialize(deserializer)?;
+    if interval.0.is_zero() {
+        return Err(D::Error::custom("'interval' must be positive"));
+    }
+    Ok(interval)
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Payload {
+    hostname: String,
+    uptime: f64,
+    version: &'static str,
+    timestamp: i64,
+}
+
+impl Payload {
+    async fn new() -> Result<Self> {
+        let hostname = read_file("/proc/sys/kernel/hostname")
+            .await
+            .error("Failed to read the host name")?;
+        let uptime = read_file("/proc/uptime")
+            .await
+            .error("Failed to read /proc/uptime")?;
+        let uptime = uptime
+            .split_whitespace()
+            .next()
+            .and_then(|uptime| uptime.parse().ok())
+            .error("Failed to parse /proc/uptime")?;
+        Ok(Self {
+            hostname: hostname.trim().into(),
+            uptime,
+            version: env!("CARGO_PKG_VERSION"),
+            timestamp: chrono::Utc::now().timestamp(),
+        })
+    }
+}
+
+/// Spawn the heartbeat task. Must be called from within the tokio runtime. Heartbeats are sent
+/// while the returned value is `true`, and the task stops once it is dropped.
+pub fn spawn(config: Config) -> watch::Sender<bool> {
+    let (enabled_tx, enabled) = watch::channel(true);
+    tokio::spawn(heartbeat_loop(config, enabled));
+    enabled_tx
+}
+
+async fn heartbeat_loop(config: Config, mut enabled: watch::Receiver<bool>) {
+    let mut failures = 0;
+    loop {
+        while !*enabled.borrow_and_update() {
+            if enabled.changed().await.is_err() {
+                return;
+            }
+        }
+        match send(&config).await {
+            Ok(()) => failures = 0,
+            Err(error) => {
+                failures += 1;
+                log::debug!("Failed to send heartbeat ({failures} in a row): {error}");
+            }
+        }
+        // Resuming heartbeats sends one right away
+        tokio::select! {
+            _ = tokio::time::sleep(retry_delay(config.interval.0, failures)) => (),
+            result = enabled.changed() => {
+                if result.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// The delay until the next heartbeat after `failures` consecutive failures
+fn retry_delay(interval: Duration, failures: u32) -> Duration {
+    let max = interval.max(MAX_RETRY_DELAY);
+    interval
+        .checked_mul(2u32.saturating_pow(failures))
+        .map_or(max, |delay| delay.min(max))
+}
+
+async fn send(config: &Config) -> Result<()> {
+    let payload = Payload::new().await?;
+    let mut request = REQWEST_CLIENT.post(&config.url).json(&payload);
+    if let Some(token) = &config.token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await.error("Failed to send request")?;
+    if !response.status().is_success() {
+        return Err(Error::new(format!("Server error: {}", response.status())));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Accept one request, answer it with `status` and return it
+    async fn serve_once(listener: &TcpListener, status: &str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|l| l.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break;
+                }
+            }
+        }
+        let response =
+            format!("HTTP/1.1 {status}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+        stream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8(request).unwrap()
+    }
+
+    #[test]
+    fn payload() {
+        tokio_test::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let config = Config {
+                url: format!("http://{}/presence", listener.local_addr().unwrap()),
+                token: Some("secret".into()),
+                interval: default_interval(),
+            };
+            let (request, result) =
+                tokio::join!(serve_once(&listener, "204 No Content"), send(&config));
+            result.unwrap();
+
+            let (head, body) = request.split_once("\r\n\r\n").unwrap();
+            assert!(head.starts_with("POST /presence HTTP/1.1"));
+            assert!(head
+                .lines()
+                .any(|line| line.eq_ignore_ascii_case("authorization: Bearer secret")));
+            let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+            let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap();
+            assert_eq!(payload["hostname"], hostname.trim());
+            assert!(payload["uptime"].as_f64().unwrap() > 0.0);
+            assert_eq!(payload["version"], env!("CARGO_PKG_VERSION"));
+            assert!(payload["timestamp"].as_i64().unwrap() > 1_600_000_000);
+
+            // Errors of the server are failures
+            let (_, result) = tokio::join!(
+                serve_once(&listener, "500 Internal Server Error"),
+                send(&config)
+            );
+            assert!(result.is_err());
+        });
+    }
+
+    #[test]
+    fn interval() {
+        let config: Config = toml::from_str(r#"url = "http://localhost""#).unwrap();
+        assert_eq!(config.interval.0, Duration::from_secs(60));
+        let config: Config = toml::from_str("url = \"http://localhost\"\ninterval = 5").unwrap();
+        assert_eq!(config.interval.0, Duration::from_secs(5));
+        assert!(toml::from_str::<Config>("url = \"http://localhost\"\ninterval = 0").is_err());
+    }
+
+    #[test]
+    fn backoff() {
+        let minute = Duration::from_secs(60);
+        assert_eq!(retry_delay(minute, 0), minute);
+        assert_eq!(retry_delay(minute, 1), minute * 2);
+        assert_eq!(retry_delay(minute, 5), minute * 32);
+        assert_eq!(retry_delay(minute, 6), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(minute, 100), MAX_RETRY_DELAY);
+        // An interval longer than the maximum is kept
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(retry_delay(day, 3), day);
+    }
+}
@@ -1,111 +1,210 @@
 use crate::errors::*;
 use crate::util;
+use crate::widget::State;
 use serde::Deserialize;
 use std::collections::HashMap;
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(try_from = "IconsConfigRaw")]
-pub struct Icons(pub HashMap<String, String>);
+pub struct Icons(pub HashMap<String, Icon>);
+
+/// Where the icons of a block are shown, relative to its text
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IconPosition {
+    /// Where the format puts them
+    #[default]
+    Left,
+    /// After the text, in the order the format puts them
+    Right,
+    Hidden,
+}
+
+/// An icon, which may look different depending on the state of the widget showing it, e.g.
+/// `{ idle = "BAT", critical = "BAT!" }`. States without an icon of their own show `idle`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Icon {
+    Plain(String),
+    Stateful(Box<StatefulIcon>),
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct StatefulIcon {
+    idle: String,
+    info: Option<String>,
+    good: Option<String>,
+    warning: Option<String>,
+    critical: Option<String>,
+}
+
+impl Icon {
+    /// The icon shown in the idle state
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Plain(icon) => icon,
+            Self::Stateful(icon) => &icon.idle,
+        }
+    }
+
+    pub fn in_state(&self, state: State) -> &str {
+        let icon = match self {
+            Self::Plain(icon) => return icon,
+            Self::Stateful(icon) => icon,
+        };
+        let variant = match state {
+            State::Idle => None,
+            State::Info => icon.info.as_ref(),
+            State::Good => icon.good.as_ref(),
+            State::Warning => icon.warning.as_ref(),
+            State::Critical => icon.critical.as_ref(),
+        };
+        variant.unwrap_or(&icon.idle)
+    }
+
+    pub fn is_stateful(&self) -> bool {
+        matches!(self, Self::Stateful(_))
+    }
+
+    /// Apply `f`, e.g. the icons format, to every variant
+    pub fn map(&self, f: impl Fn(&str) -> String) -> Self {
+        match self {
+            Self::Plain(icon) => Self::Plain(f(icon)),
+            Self::Stateful(icon) => Self::Stateful(Box::new(StatefulIcon {
+                idle: f(&icon.idle),
+                info: icon.info.as_deref().map(&f),
+                good: icon.good.as_deref().map(&f),
+                warning: icon.warning.as_deref().map(&f),
+                critical: icon.critical.as_deref().map(&f),
+            })),
+        }
+    }
+}
+
+impl From<String> for Icon {
+    fn from(icon: String) -> Self {
+        Self::Plain(icon)
+    }
+}
 
 impl Default for Icons {
     fn default() -> Self {
         // "none" icon set
-        Self(map! {
-            "backlight_empty" => "BRIGHT",
-            "backlight_full" => "BRIGHT",
-            "backlight_1" =>  "BRIGHT",
-            "backlight_2" =>  "BRIGHT",
-            "backlight_3" =>  "BRIGHT",
-            "backlight_4" =>  "BRIGHT",
-            "backlight_5" =>  "BRIGHT",
-            "backlight_6" =>  "BRIGHT",
-            "backlight_7" =>  "BRIGHT",
-            "backlight_8" =>  "BRIGHT",
-            "backlight_9" =>  "BRIGHT",
-            "backlight_10" => "BRIGHT",
-            "backlight_11" => "BRIGHT",
-            "backlight_12" => "BRIGHT",
-            "backlight_13" => "BRIGHT",
-            "bat_10" => "BAT",
-            "bat_20" => "BAT",
-            "bat_30" => "BAT",
-            "bat_40" => "BAT",
-            "bat_50" => "BAT",
-            "bat_60" => "BAT",
-            "bat_70" => "BAT",
-            "bat_80" => "BAT",
-            "bat_90" => "BAT",
-            "bat_charging" => "CHG",
-            "bat_discharging" => "DCG",
-            "bat_empty" => "EMP",
-            "bat_full" => "FULL",
-            "bat_not_available" => "BAT N/A",
-            "bell" => "ON",
-            "bell-slash" => "OFF",
-            "bluetooth" => "BT",
-            "calendar" => "CAL",
-            "cogs" => "LOAD",
-            "cpu" => "CPU",
-            "cpu_boost_on" => "BOOST ON",
-            "cpu_boost_off" => "BOOST OFF",
-            "disk_drive" => "DISK",
-            "docker" => "DOCKER",
-            "github" => "GITHUB",
-            "gpu" => "GPU",
-            "headphones" => "HEAD",
-            "joystick" => "JOY",
-            "keyboard" => "KBD",
-            "mail" => "MAIL",
-            "memory_mem" => "MEM",
-            "memory_swap" => "SWAP",
-            "mouse" => "MOUSE",
-            "music" => "MUSIC",
-            "music_next" => ">",
-            "music_pause" => "||",
-            "music_play" => ">",
-            "music_prev" => "<",
-            "net_bridge" => "BRIDGE",
-            "net_down" => "DOWN",
-            "net_loopback" => "LO",
-            "net_modem" => "MODEM",
-            "net_up" => "UP ",
-            "net_vpn" => "VPN",
-            "net_wired" => "ETH",
-            "net_wireless" => "WLAN",
-            "notification" => "NOTIF",
-            "phone" => "PHONE",
-            "phone_disconnected" => "PHONE",
-            "ping" => "PING",
-            "pomodoro" => "POMODORO",
-            "pomodoro_break" => "BREAK",
-            "pomodoro_paused" => "PAUSED",
-            "pomodoro_started" => "STARTED",
-            "pomodoro_stopped" => "STOPPED",
-            "resolution" => "RES",
-            "tasks" => "TSK",
-            "thermometer" => "TEMP",
-            "time" => "TIME",
-            "toggle_off" => "OFF",
-            "toggle_on" => "ON",
-            "unknown" => "??",
-            "update" => "UPD",
-            "uptime" => "UP",
-            "volume_empty" => "VOL",
-            "volume_full" => "VOL",
-            "volume_half" => "VOL",
-            "volume_muted" => "VOL MUTED",
-            "microphone_empty" => "MIC ",
-            "microphone_full" => "MIC",
-            "microphone_half" => "MIC",
-            "microphone_muted" => "MIC MUTED",
-            "weather_clouds" => "CLOUDY",
-            "weather_default" => "WEATHER",
-            "weather_rain" => "RAIN",
-            "weather_snow" => "SNOW",
-            "weather_sun" => "SUNNY",
-            "weather_thunder" => "STORM",
-            "xrandr" => "SCREEN"
-        })
+        Self(
+            [
+                ("ac_offline", "BAT"),
+                ("ac_online", "AC"),
+                ("backlight_empty", "BRIGHT"),
+                ("backlight_full", "BRIGHT"),
+                ("backlight_1", "BRIGHT"),
+                ("backlight_2", "BRIGHT"),
+                ("backlight_3", "BRIGHT"),
+                ("backlight_4", "BRIGHT"),
+                ("backlight_5", "BRIGHT"),
+                ("backlight_6", "BRIGHT"),
+                ("backlight_7", "BRIGHT"),
+                ("backlight_8", "BRIGHT"),
+                ("backlight_9", "BRIGHT"),
+                ("backlight_10", "BRIGHT"),
+                ("backlight_11", "BRIGHT"),
+                ("backlight_12", "BRIGHT"),
+                ("backlight_13", "BRIGHT"),
+                ("bat_10", "BAT"),
+                ("bat_20", "BAT"),
+                ("bat_30", "BAT"),
+                ("bat_40", "BAT"),
+                ("bat_50", "BAT"),
+                ("bat_60", "BAT"),
+                ("bat_70", "BAT"),
+                ("bat_80", "BAT"),
+                ("bat_90", "BAT"),
+                ("bat_charging", "CHG"),
+                ("bat_discharging", "DCG"),
+                ("bat_empty", "EMP"),
+                ("bat_full", "FULL"),
+                ("bat_not_available", "BAT N/A"),
+                ("bell", "ON"),
+                ("bell-slash", "OFF"),
+                ("binding_mode", "MODE"),
+                ("bluetooth", "BT"),
+                ("bluetooth_absent", "BT OFF"),
+                ("calendar", "CAL"),
+                ("clipboard", "CLIP"),
+                ("cogs", "LOAD"),
+                ("cpu", "CPU"),
+                ("cpu_boost_on", "BOOST ON"),
+                ("cpu_boost_off", "BOOST OFF"),
+                ("disk_drive", "DISK"),
+                ("dns_blocker", "DNS"),
+                ("docker", "DOCKER"),
+                ("github", "GITHUB"),
+                ("gpu", "GPU"),
+                ("headphones", "HEAD"),
+                ("inhibitor", "INHIBIT"),
+                ("joystick", "JOY"),
+                ("keyboard", "KBD"),
+                ("lid_closed", "LID CLOSED"),
+                ("lid_open", "LID"),
+                ("mail", "MAIL"),
+                ("memory_mem", "MEM"),
+                ("memory_swap", "SWAP"),
+                ("mouse", "MOUSE"),
+                ("music", "MUSIC"),
+                ("music_next", ">"),
+                ("music_pause", "||"),
+                ("music_play", ">"),
+                ("music_prev", "<"),
+                ("net_bridge", "BRIDGE"),
+                ("net_down", "DOWN"),
+                ("net_loopback", "LO"),
+                ("net_modem", "MODEM"),
+                ("net_up", "UP "),
+                ("net_vpn", "VPN"),
+                ("net_wired", "ETH"),
+                ("net_wireless", "WLAN"),
+                ("notification", "NOTIF"),
+                ("phone", "PHONE"),
+                ("phone_disconnected", "PHONE"),
+                ("ping", "PING"),
+                ("pomodoro", "POMODORO"),
+                ("pomodoro_break", "BREAK"),
+                ("pomodoro_paused", "PAUSED"),
+                ("pomodoro_started", "STARTED"),
+                ("pomodoro_stopped", "STOPPED"),
+                ("resolution", "RES"),
+                ("scratchpad", "SCRATCH"),
+                ("tasks", "TSK"),
+                ("thermometer", "TEMP"),
+                ("time", "TIME"),
+                ("totp", "OTP"),
+                ("toggle_off", "OFF"),
+                ("toggle_on", "ON"),
+                ("torrent", "TORRENT"),
+                ("torrent_alt_speed", "TORRENT SLOW"),
+                ("unknown", "??"),
+                ("update", "UPD"),
+                ("uptime", "UP"),
+                ("volume_empty", "VOL"),
+                ("volume_full", "VOL"),
+                ("volume_half", "VOL"),
+                ("volume_muted", "VOL MUTED"),
+                ("microphone_empty", "MIC "),
+                ("microphone_full", "MIC"),
+                ("microphone_half", "MIC"),
+                ("microphone_muted", "MIC MUTED"),
+                ("weather_clouds", "CLOUDY"),
+                ("weather_default", "WEATHER"),
+                ("weather_rain", "RAIN"),
+                ("weather_snow", "SNOW"),
+                ("weather_sun", "SUNNY"),
+                ("weather_thunder", "STORM"),
+                ("xrandr", "SCREEN"),
+            ]
+            .into_iter()
+            .map(|(icon, value)| (icon.into(), Icon::Plain(value.into())))
+            .collect(),
+        )
     }
 }
 
@@ -120,7 +219,7 @@ impl Icons {
         }
     }
 
-    pub fn apply_overrides(&mut self, overrides: HashMap<String, String>) {
+    pub fn apply_overrides(&mut self, overrides: HashMap<String, Icon>) {
         self.0.extend(overrides);
     }
 }
@@ -129,7 +228,7 @@ impl Icons {
 #[serde(deny_unknown_fields, default)]
 struct IconsConfigRaw {
     icons: Option<String>,
-    overrides: Option<HashMap<String, String>>,
+    overrides: Option<HashMap<String, Icon>>,
 }
 
 impl TryFrom<IconsConfigRaw> for Icons {
@@ -145,3 +244,34 @@ impl TryFrom<IconsConfigRaw> for Icons {
         Ok(icons)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stateful() {
+        let icons: Icons = toml::from_str(
+            r#"
+            [overrides]
+            bat_10 = { idle = "B", warning = "B?", critical = "B!" }
+            bat_20 = "b"
+            "#,
+        )
+        .unwrap();
+        let icon = &icons.0["bat_10"];
+        assert!(icon.is_stateful());
+        assert_eq!(icon.text(), "B");
+        assert_eq!(icon.in_state(State::Critical), "B!");
+        // States without an icon of their own fall back to idle
+        assert_eq!(icon.in_state(State::Good), "B");
+        assert_eq!(icons.0["bat_20"].in_state(State::Critical), "b");
+        assert_eq!(
+            icon.map(|i| format!(" {i} ")).in_state(State::Warning),
+            " B? "
+        );
+
+        // A table without `idle` is not an icon
+        assert!(toml::from_str::<Icons>(r#"overrides = { bat = { critical = "B!" } }"#).is_err());
+    }
+}
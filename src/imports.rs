@@ -0,0 +1,286 @@
+//! Importing values of other blocks
+//!
+//! A block can display values of other blocks with the `import_values` option, which maps a
+//! placeholder of this block to a value of another block. Other blocks are referenced by the
+//! block's name and its index among the blocks of the same type, e.g. `temperature-0` is the
+//! first `temperature` block. The imported value is inserted just before the block is rendered,
+//! and the block is re-rendered whenever the source block updates, so composite layouts do not
+//! need to read the same sensor several times.
+//!
+//! Cycles (e.g. two blocks importing from each other) are rejected at startup.
+//!
+//! # Example
+//!
+//! Show the temperature inside the `cpu` block:
+//!
+//! ```toml
+//! [[block]]
+//! block = "temperature"
+//! format = " $icon $max "
+//!
+//! [[block]]
+//! block = "cpu"
+//! format = " $icon $utilization $temp "
+//! import_values = { temp = "temperature-0.max" }
+//! ```
+
+use std::collections::HashMap;
+
+use crate::errors::*;
+use crate::formatting::Values;
+
+/// A value imported from another block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    /// The placeholder of the importing block
+    pub placeholder: String,
+    /// The ID of the source block
+    pub source: usize,
+    /// The placeholder of the source block
+    pub key: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Imports {
+    /// The values imported by each block
+    imports: Vec<Vec<Import>>,
+    /// All blocks, sorted so that each block comes after the blocks it imports from
+    order: Vec<usize>,
+    /// Whether other blocks import from this block
+    is_source: Vec<bool>,
+}
+
+impl Imports {
+    /// Resolve the `import_values` options of all blocks. `names` are the names of the blocks
+    /// (e.g. `"cpu"`) in order of their IDs.
+    pub fn new(names: &[&str], import_values: &[&HashMap<String, String>]) -> Result<Self> {
+        let ids = block_ids(names);
+
+        let mut imports = Vec::with_capacity(import_values.len());
+        let mut is_source = vec![false; names.len()];
+        for config in import_values {
+            let mut block_imports = Vec::with_capacity(config.len());
+            for (placeholder, reference) in *config {
+                let (block, key) = reference.split_once('.').or_error(|| {
+                    format!("import_values: '{reference}' is not of the form '<block>-<n>.<key>'")
+                })?;
+                let source = *ids
+                    .get(block)
+                    .or_error(|| format!("import_values: block '{block}' not found"))?;
+                is_source[source] = true;
+                block_imports.push(Import {
+                    placeholder: placeholder.clone(),
+                    source,
+                    key: key.into(),
+                });
+            }
+            // Keep the order deterministic
+            block_imports.sort_unstable_by(|a, b| a.placeholder.cmp(&b.placeholder));
+            imports.push(block_imports);
+        }
+
+        let order = match topological_order(&imports) {
+            Some(order) => order,
+            None => {
+                let id = find_cycle(&imports).unwrap_or_default();
+                let index = names[..id].iter().filter(|n| **n == names[id]).count();
+                return Err(Error::new(format!(
+                    "import_values: block '{}-{index}' imports its own values (directly or through other blocks)",
+                    names[id]
+                )));
+            }
+        };
+
+        Ok(Self {
+            imports,
+            order,
+            is_source,
+        })
+    }
+
+    /// The values imported by `block`
+    pub fn of(&self, block: usize) -> &[Import] {
+        self.imports.get(block).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether other blocks import values from `block`
+    pub fn is_source(&self, block: usize) -> bool {
+        self.is_source.get(block).copied().unwrap_or(false)
+    }
+
+    /// The blocks which have to be re-rendered when `block` updates, in the order in which they
+    /// must be rendered. Does not include `block` itself.
+    pub fn dependents(&self, block: usize) -> Vec<usize> {
+        let mut dirty = vec![false; self.imports.len()];
+        if let Some(dirty) = dirty.get_mut(block) {
+            *dirty = true;
+        }
+        let mut dependents = Vec::new();
+        // A block comes after all of its sources, so a single pass is enough
+        for &id in &self.order {
+            if id != block && self.imports[id].iter().any(|i| dirty[i.source]) {
+                dirty[id] = true;
+                dependents.push(id);
+            }
+        }
+        dependents
+    }
+
+    /// Insert the values imported by `block` into `values`
+    pub fn apply(&self, block: usize, values: &mut Values, published: &[Option<Values>]) {
+        for import in self.of(block) {
+            let value = published
+                .get(import.source)
+                .and_then(Option::as_ref)
+                .and_then(|v| v.get(import.key.as_str()));
+            match value {
+                Some(value) => {
+                    values.insert(import.placeholder.clone().into(), value.clone());
+                }
+                None => {
+                    values.remove(import.placeholder.as_str());
+                }
+            }
+        }
+    }
+}
+
+/// Map the identifiers of the blocks (e.g. `cpu-0`) to their IDs. `names` are the names of the
+/// blocks in order of their IDs.
+pub fn block_ids(names: &[&str]) -> HashMap<String, usize> {
+    let mut ids = HashMap::new();
+    let mut counts = HashMap::new();
+    for (id, name) in names.iter().enumerate() {
+        let index = counts.entry(*name).or_insert(0);
+        ids.insert(format!("{name}-{index}"), id);
+        *index += 1;
+    }
+    ids
+}
+
+/// Sort the blocks so that every block comes after the blocks it imports from. Returns `None` if
+/// there is a cycle.
+fn topological_order(imports: &[Vec<Import>]) -> Option<Vec<usize>> {
+    // Number of distinct sources which are not sorted yet
+    let mut pending: Vec<usize> = imports.iter().map(|i| distinct_sources(i).len()).collect();
+    let mut order: Vec<usize> = (0..imports.len()).filter(|&id| pending[id] == 0).collect();
+    let mut next = 0;
+    while let Some(&id) = order.get(next) {
+        next += 1;
+        for (dependent, dependent_imports) in imports.iter().enumerate() {
+            if distinct_sources(dependent_imports).contains(&id) {
+                pending[dependent] -= 1;
+                if pending[dependent] == 0 {
+                    order.push(dependent);
+                }
+            }
+        }
+    }
+    (order.len() == imports.len()).then_some(order)
+}
+
+/// Find a block which is part of a cycle
+fn find_cycle(imports: &[Vec<Import>]) -> Option<usize> {
+    (0..imports.len()).find(|&start| {
+        let mut stack: Vec<usize> = imports[start].iter().map(|i| i.source).collect();
+        let mut visited = vec![false; imports.len()];
+        while let Some(id) = stack.pop() {
+            if id == start {
+                return true;
+            }
+            if !std::mem::replace(&mut visited[id], true) {
+                stack.extend(imports[id].iter().map(|i| i.source));
+            }
+        }
+        false
+    })
+}
+
+fn distinct_sources(imports: &[Import]) -> Vec<usize> {
+    let mut sources: Vec<usize> = imports.iter().map(|i| i.source).collect();
+    sources.sort_unstable();
+    sources.dedup();
+    sources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatting::value::Value;
+
+    fn config(imports: &[(&str, &str)]) -> HashMap<String, String> {
+        imports
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn references() {
+        let none = config(&[]);
+        let cpu = config(&[("temp", "temperature-1.max")]);
+        let imports = Imports::new(
+            &["temperature", "temperature", "cpu"],
+            &[&none, &none, &cpu],
+        )
+        .unwrap();
+        assert_eq!(
+            imports.of(2),
+            &[Import {
+                placeholder: "temp".into(),
+                source: 1,
+                key: "max".into(),
+            }]
+        );
+        assert!(imports.is_source(1));
+        assert!(!imports.is_source(0));
+
+        let missing = config(&[("temp", "temperature-2.max")]);
+        assert!(Imports::new(&["temperature", "cpu"], &[&none, &missing]).is_err());
+        let invalid = config(&[("temp", "temperature-0")]);
+        assert!(Imports::new(&["temperature", "cpu"], &[&none, &invalid]).is_err());
+    }
+
+    #[test]
+    fn propagation_order() {
+        // time-0 <- cpu-0 <- custom-0, and custom-0 also imports from time-0 directly. The block
+        // importing from a block must always be rendered after it.
+        let none = config(&[]);
+        let custom = config(&[("a", "time-0.time"), ("b", "cpu-0.temp")]);
+        let cpu = config(&[("temp", "time-0.time")]);
+        let imports = Imports::new(&["custom", "cpu", "time"], &[&custom, &cpu, &none]).unwrap();
+        assert_eq!(imports.dependents(2), vec![1, 0]);
+        assert_eq!(imports.dependents(1), vec![0]);
+        assert_eq!(imports.dependents(0), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn cycles() {
+        let a = config(&[("x", "custom-1.x")]);
+        let b = config(&[("x", "custom-2.x")]);
+        let c = config(&[("x", "custom-0.x")]);
+        let err = Imports::new(&["custom", "custom", "custom"], &[&a, &b, &c]).unwrap_err();
+        assert!(err.to_string().contains("custom-0"));
+
+        let own = config(&[("x", "custom-0.y")]);
+        assert!(Imports::new(&["custom"], &[&own]).is_err());
+    }
+
+    #[test]
+    fn apply() {
+        let none = config(&[]);
+        let cpu = config(&[("temp", "temperature-0.max")]);
+        let imports = Imports::new(&["temperature", "cpu"], &[&none, &cpu]).unwrap();
+
+        let mut published = vec![Some(map!("max" => Value::degrees(50))), None];
+        let mut values = map!("utilization" => Value::percents(10));
+        imports.apply(1, &mut values, &published);
+        assert_eq!(values.len(), 2);
+        assert!(values.contains_key("temp"));
+
+        // The source block failed, so the value is gone
+        published[0] = None;
+        imports.apply(1, &mut values, &published);
+        assert!(!values.contains_key("temp"));
+    }
+}
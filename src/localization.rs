@@ -0,0 +1,199 @@
+//! Translations of the texts which are built into i3status-rs
+//!
+//! The top-level `[localization]` table translates the words and abbreviations which blocks use
+//! to show durations and relative times, as well as the state names which are appended to the
+//! short text (see `short_text_state` of `[state_prefixes]`). Each block can override single
+//! texts with `localization_overrides`. Texts which are not translated stay in English. Unknown
+//! keys are an error.
+//!
+//! Key | English
+//! ----|--------
+//! `weeks_short` | `w`
+//! `days_short` | `d`
+//! `hours_short` | `h`
+//! `minutes_short` | `m`
+//! `seconds_short` | `s`
+//! `week`, `weeks` | `week`, `weeks`
+//! `day`, `days` | `day`, `days`
+//! `hour`, `hours` | `hour`, `hours`
+//! `minute`, `minutes` | `minute`, `minutes`
+//! `second`, `seconds` | `second`, `seconds`
+//! `now` | `now`
+//! `ago` | `{} ago`
+//! `in` | `in {}`
+//! `after` | `after {}`
+//! `not_available` | `N/A`
+//! `idle`, `info`, `good`, `warning`, `critical` | The names of the states
+//!
+//! In `ago`, `in` and `after`, `{}` is replaced with an amount of time, e.g. `5 minutes`.
+//!
+//! # Example
+//!
+//! ```toml
+//! [localization]
+//! days_short = "T"
+//! hours_short = "Std"
+//! ago = "vor {}"
+//! minute = "Minute"
+//! minutes = "Minuten"
+//! critical = "kritisch"
+//! ```
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::widget::State;
+
+/// A text which can be translated
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Text {
+    WeeksShort,
+    DaysShort,
+    HoursShort,
+    MinutesShort,
+    SecondsShort,
+    Week,
+    Weeks,
+    Day,
+    Days,
+    Hour,
+    Hours,
+    Minute,
+    Minutes,
+    Second,
+    Seconds,
+    Now,
+    Ago,
+    In,
+    After,
+    NotAvailable,
+    Idle,
+    Info,
+    Good,
+    Warning,
+    Critical,
+}
+
+impl Text {
+    pub fn english(self) -> &'static str {
+        match self {
+            Self::WeeksShort => "w",
+            Self::DaysShort => "d",
+            Self::HoursShort => "h",
+            Self::MinutesShort => "m",
+            Self::SecondsShort => "s",
+            Self::Week => "week",
+            Self::Weeks => "weeks",
+            Self::Day => "day",
+            Self::Days => "days",
+            Self::Hour => "hour",
+            Self::Hours => "hours",
+            Self::Minute => "minute",
+            Self::Minutes => "minutes",
+            Self::Second => "second",
+            Self::Seconds => "seconds",
+            Self::Now => "now",
+            Self::Ago => "{} ago",
+            Self::In => "in {}",
+            Self::After => "after {}",
+            Self::NotAvailable => "N/A",
+            Self::Idle => State::Idle.name(),
+            Self::Info => State::Info.name(),
+            Self::Good => State::Good.name(),
+            Self::Warning => State::Warning.name(),
+            Self::Critical => State::Critical.name(),
+        }
+    }
+}
+
+/// A unit of time in relative times, such as `5 minutes ago`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(transparent)]
+pub struct Localization(HashMap<Text, String>);
+
+impl Localization {
+    /// The translation of `text`, or the English text if there is none
+    pub fn get(&self, text: Text) -> &str {
+        self.0.get(&text).map_or(text.english(), String::as_str)
+    }
+
+    pub fn apply_overrides(&mut self, overrides: Self) {
+        self.0.extend(overrides.0);
+    }
+
+    pub fn state(&self, state: State) -> &str {
+        self.get(match state {
+            State::Idle => Text::Idle,
+            State::Info => Text::Info,
+            State::Good => Text::Good,
+            State::Warning => Text::Warning,
+            State::Critical => Text::Critical,
+        })
+    }
+
+    /// An amount of time, such as `1 minute` or `5 minutes`
+    pub fn amount(&self, n: i64, unit: TimeUnit) -> String {
+        let (one, many) = match unit {
+            TimeUnit::Week => (Text::Week, Text::Weeks),
+            TimeUnit::Day => (Text::Day, Text::Days),
+            TimeUnit::Hour => (Text::Hour, Text::Hours),
+            TimeUnit::Minute => (Text::Minute, Text::Minutes),
+            TimeUnit::Second => (Text::Second, Text::Seconds),
+        };
+        let unit = self.get(if n.abs() == 1 { one } else { many });
+        format!("{n} {unit}")
+    }
+
+    /// Fill `{}` of the `ago`, `in` or `after` text with `amount`
+    pub fn relative(&self, text: Text, amount: &str) -> String {
+        self.get(text).replacen("{}", amount, 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback() {
+        let localization: Localization = toml::from_str(
+            r#"
+            days_short = "T"
+            ago = "vor {}"
+            minutes = "Minuten"
+            critical = "kritisch"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(localization.get(Text::DaysShort), "T");
+        assert_eq!(localization.get(Text::HoursShort), "h");
+        assert_eq!(localization.state(State::Critical), "kritisch");
+        assert_eq!(localization.state(State::Warning), "warning");
+        // Each key falls back on its own, even if the other form is translated
+        assert_eq!(localization.amount(5, TimeUnit::Minute), "5 Minuten");
+        assert_eq!(localization.amount(1, TimeUnit::Minute), "1 minute");
+        assert_eq!(
+            localization.relative(Text::Ago, &localization.amount(2, TimeUnit::Minute)),
+            "vor 2 Minuten"
+        );
+        assert_eq!(localization.relative(Text::In, "5m"), "in 5m");
+
+        let mut overridden = localization.clone();
+        overridden.apply_overrides(toml::from_str(r#"days_short = "Tg""#).unwrap());
+        assert_eq!(overridden.get(Text::DaysShort), "Tg");
+        assert_eq!(overridden.get(Text::Ago), "vor {}");
+
+        assert!(toml::from_str::<Localization>(r#"hour_short = "Std""#).is_err());
+    }
+}
@@ -4,22 +4,38 @@
 
 #[macro_use]
 mod util;
+mod alerts;
+mod animation;
 mod blocks;
 mod click;
 mod config;
+mod control;
 mod errors;
 mod escape;
 mod formatting;
+mod heartbeat;
 mod icons;
+mod imports;
+mod localization;
+mod migrate;
 mod netlink;
+mod power_warnings;
+mod presentation;
 mod protocol;
+mod recording;
+mod refresh_groups;
 mod signals;
 mod subprocess;
+mod theme_rules;
 mod themes;
+mod toml_error;
 mod widget;
+mod width_budget;
 mod wrappers;
 
 use clap::Parser;
+use formatting::config::Config as FormatConfig;
+use formatting::marquee::Marquee;
 use formatting::value::Value;
 use futures::future::{abortable, FutureExt};
 use futures::stream::futures_unordered::FuturesUnordered;
@@ -27,37 +43,75 @@ use futures::stream::{AbortHandle, Stream, StreamExt};
 use once_cell::sync::Lazy;
 use protocol::i3bar_block::I3BarBlock;
 use protocol::i3bar_event::I3BarEvent;
+use protocol::xroot::{plain_text, XRoot, XSetRoot};
+use protocol::{Output, OutputFormat};
+use serde::Deserialize;
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::process::Command;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
+use zbus::zvariant;
 
-use blocks::{BlockEvent, BlockFuture, CommonApi};
-use click::{ClickHandler, MouseButton};
+use alerts::Alerts;
+use animation::Animations;
+use blocks::{Activity, BlockConfig, BlockFuture, BlockId, CommonApi, EventSender};
+use click::{ClickHandler, ClickQueue, DefaultActions, MouseButton, PostActions};
 use config::SharedConfig;
-use config::{BlockConfigEntry, Config};
+use config::{BlockConfigEntry, CommonBlockConfig, Config, RawConfig, Reload};
 use errors::*;
 use escape::CollectEscaped;
-use formatting::{scheduling, Format};
+use formatting::{scheduling, Format, Values};
+use imports::Imports;
+use power_warnings::PowerWarnings;
 use protocol::i3bar_event::events_stream;
+use recording::Recorder;
+use refresh_groups::RefreshGroups;
 use signals::{signals_stream, Signal};
-use widget::{State, Widget};
+use subprocess::{CommandContext, Reaper};
+use theme_rules::ThemeRules;
+use themes::{BlockColors, Theme};
+use widget::{PartsCache, State, Widget, WidgetsDiff};
+use width_budget::WidthBudget;
 
 pub type BoxedFuture<T> = Pin<Box<dyn Future<Output = T>>>;
 pub type BoxedStream<T> = Pin<Box<dyn Stream<Item = T>>>;
 
-pub static REQWEST_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+/// The maximum delay before restarting a block which keeps failing
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// How often to look for blocks which are busy for longer than their `update_timeout`
+const HUNG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a block is shown in warning state after a command started by clicking it failed
+const CLICK_FEEDBACK_DURATION: Duration = Duration::from_secs(2);
+
+/// How long a block shows what a click would have run, with `--dry-run-clicks`
+const CLICK_EXPLANATION_DURATION: Duration = Duration::from_secs(5);
+
+pub static REQWEST_CLIENT: Lazy<reqwest::Client> =
+    Lazy::new(|| reqwest_client_builder().build().unwrap());
+
+/// Like [`REQWEST_CLIENT`], but connects only over IPv4
+pub static REQWEST_CLIENT_IPV4: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest_client_builder()
+        .local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+        .build()
+        .unwrap()
+});
+
+fn reqwest_client_builder() -> reqwest::ClientBuilder {
     const APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
     const REQWEST_TIMEOUT: Duration = Duration::from_secs(10);
     reqwest::Client::builder()
         .user_agent(APP_USER_AGENT)
         .timeout(REQWEST_TIMEOUT)
-        .build()
-        .unwrap()
-});
+}
 
 #[derive(Debug, Parser)]
 #[clap(author, about, version = env!("VERSION"))]
@@ -71,45 +125,185 @@ struct CliArgs {
     /// Do not send the init sequence
     #[clap(long = "no-init")]
     no_init: bool,
+    /// Do not ask the bar for click events
+    #[clap(long = "no-click-events")]
+    no_click_events: bool,
+    /// The version of i3bar's protocol in the header
+    #[clap(long = "protocol-version", value_name = "VERSION", default_value = "1")]
+    protocol_version: u32,
+    /// Fields to add to the header of i3bar's protocol, as a JSON object, e.g. for extensions of
+    /// the bar. They override the fields set by other options.
+    #[clap(long = "header-json", value_name = "JSON", value_parser = parse_header_json)]
+    header_json: Option<serde_json::Map<String, serde_json::Value>>,
     /// The maximum number of blocking threads spawned by tokio
     #[clap(long = "threads", short = 'j', default_value = "2")]
     blocking_threads: usize,
+    /// Do not output any colors or borders
+    #[clap(long = "no-color")]
+    no_color: bool,
+    /// Where to print the blocks. `xroot` sets the name of the X root window (e.g. for dwm)
+    /// instead, without colors nor clicks.
+    #[clap(long = "output-format", value_enum, default_value_t = OutputFormat::I3bar)]
+    output_format: OutputFormat,
+    /// Print a summary of the values recorded to a history file and exit
+    #[clap(long = "dump-history", value_name = "PATH")]
+    dump_history: Option<String>,
+    /// Only summarize values of this block (used with `--dump-history`)
+    #[clap(long = "block", requires = "dump_history")]
+    block: Option<String>,
+    /// Only summarize values recorded within this period, e.g. `24h` (used with `--dump-history`)
+    #[clap(long = "last", requires = "dump_history")]
+    last: Option<String>,
+    /// Rewrite old options in a config file (by default the one given as `config`) and exit
+    #[clap(long = "migrate-config", value_name = "PATH")]
+    migrate_config: Option<Option<String>>,
+    /// Write the migrated config to this file instead of replacing the original, which is
+    /// otherwise kept as `<PATH>.bak` (used with `--migrate-config`)
+    #[clap(long = "output", value_name = "PATH", requires = "migrate_config")]
+    output: Option<String>,
+    /// Own `rs.i3status.<NAME>` on DBus instead of `rs.i3status`, like `$I3RS_DBUS_NAME`
+    #[clap(long = "dbus-name", value_name = "NAME")]
+    dbus_name: Option<String>,
+    /// Do not run the commands of clicks, but show on the clicked block what would run
+    #[clap(long = "dry-run-clicks")]
+    dry_run_clicks: bool,
+}
+
+fn parse_header_json(json: &str) -> Result<serde_json::Map<String, serde_json::Value>> {
+    serde_json::from_str(json).error("Header must be a JSON object")
 }
 
 fn main() {
+    // Before any thread is started, and even with `--no-init`, so that the marker does not leak
+    // into the commands run by blocks
+    protocol::take_restart_marker();
     env_logger::init();
     let args = CliArgs::parse();
     let blocking_threads = args.blocking_threads;
+    let no_color = args.no_color;
+    let never_pause = args.never_pause;
+    let dry_run_clicks = args.dry_run_clicks;
+    let output_format = args.output_format;
+    // The root window does not need the header of i3bar's protocol
+    let no_init = args.no_init || output_format == OutputFormat::Xroot;
+    let header = protocol::Header {
+        version: args.protocol_version,
+        click_events: !args.no_click_events,
+        never_pause,
+        extra: args.header_json.clone().unwrap_or_default(),
+    };
 
-    if !args.no_init {
-        protocol::init(args.never_pause);
+    if let Some(path) = &args.dump_history {
+        if let Err(error) =
+            recording::dump_history(path, args.block.as_deref(), args.last.as_deref())
+        {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+        return;
     }
 
-    let result = tokio::runtime::Builder::new_current_thread()
+    if let Some(path) = &args.migrate_config {
+        let path = path.as_deref().unwrap_or(&args.config);
+        let result = util::find_file(path, None, Some("toml"))
+            .or_error(|| format!("Configuration file '{path}' not found"))
+            .and_then(|path| migrate::migrate_config(&path, args.output.as_deref().map(Path::new)));
+        if let Err(error) = result {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Read by `custom_dbus` blocks as well, and kept when restarting in place
+    if let Some(name) = &args.dbus_name {
+        std::env::set_var("I3RS_DBUS_NAME", name);
+    }
+
+    let output = match output_format {
+        OutputFormat::I3bar => Output::I3bar {
+            no_color,
+            click_events: header.click_events,
+        },
+        OutputFormat::Xroot => match XRoot::new() {
+            Ok(xroot) => Output::XRoot(xroot),
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
         .max_blocking_threads(blocking_threads)
         .enable_all()
         .build()
-        .unwrap()
-        .block_on(async move {
-            let config_path = util::find_file(&args.config, None, Some("toml"))
-                .or_error(|| format!("Configuration file '{}' not found", args.config))?;
-            let mut config: Config = util::deserialize_toml_file(&config_path)?;
-            let blocks = std::mem::take(&mut config.blocks);
-            let mut bar = BarState::new(config);
-            for block_config in blocks {
-                bar.spawn_block(block_config).await?;
-            }
-            bar.run_event_loop().await
-        });
+        .unwrap();
+    let startup_header = header.clone();
+    let result = runtime.block_on(async move {
+        let config_path = util::find_file(&args.config, None, Some("toml"))
+            .or_error(|| format!("Configuration file '{}' not found", args.config))?;
+        let (mut config, raw_config) = load_config(&config_path)?;
+        let blocks = std::mem::take(&mut config.blocks);
+
+        // Under sway, follow the bar's visibility over IPC instead of being stopped
+        let swaysock = std::env::var_os("SWAYSOCK");
+        let visibility = if !never_pause
+            && matches!(output, Output::I3bar { .. })
+            && config.compositor.is_sway(swaysock.as_deref())
+        {
+            match protocol::sway::visibility_stream().await {
+                Ok(stream) => Some(stream),
+                Err(error) => {
+                    log::warn!("Cannot follow the visibility of the bar: {error}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        if !no_init {
+            protocol::init(&protocol::Header {
+                never_pause: never_pause || visibility.is_some(),
+                ..startup_header
+            });
+        }
+
+        let mut bar = BarState::new(config, config_path, raw_config.settings, output)?;
+        if let Some(visibility) = visibility {
+            bar.visibility_stream = visibility;
+        }
+        if bar.config.presentation.persist {
+            bar.order = presentation::load_order().await;
+        }
+        bar.dry_run_clicks = dry_run_clicks;
+        bar.load_blocks(blocks, raw_config.blocks).await?;
+        bar.run_event_loop().await
+    });
+    // Do not wait for the blocking read of stdin, which only returns on the next click
+    runtime.shutdown_background();
     if let Err(error) = result {
-        let error_widget = Widget::new()
-            .with_text(error.to_string().chars().collect_pango_escaped())
-            .with_state(State::Critical);
+        // The lines after the first one show where in the config file the error is
+        let message = error.to_string();
+        let summary = message.lines().next().unwrap_or_default();
+        if output_format == OutputFormat::Xroot {
+            if let Err(error) = XSetRoot::set_name_blocking(summary) {
+                eprintln!("{error}");
+            }
+        } else {
+            if !no_init {
+                protocol::init(&header);
+            }
+            let error_widget = Widget::new()
+                .with_text(summary.chars().collect_pango_escaped())
+                .with_state(State::Critical);
 
-        println!(
-            "{},",
-            serde_json::to_string(&error_widget.get_data(&Default::default(), 0).unwrap()).unwrap()
-        );
+            let mut error_blocks = error_widget.get_data(&Default::default(), 0).unwrap();
+            if no_color {
+                error_blocks.iter_mut().for_each(I3BarBlock::strip_colors);
+            }
+            println!("{},", serde_json::to_string(&error_blocks).unwrap());
+        }
         eprintln!("\n\n{error}\n\n");
         dbg!(error);
 
@@ -123,18 +317,81 @@ fn main() {
     }
 }
 
+/// Read the configuration file and check it
+fn load_config(path: &Path) -> Result<(Config, RawConfig)> {
+    let contents = migrate::read_config(path)?;
+    let config: Config = util::deserialize_toml(&contents, path)?;
+    let raw_config: RawConfig = util::deserialize_toml(&contents, path)?;
+    config.check_conflicts()?;
+    Ok((config, raw_config))
+}
+
+/// The context of the commands run by a block
+fn block_command_context(common: &CommonBlockConfig) -> Result<CommandContext> {
+    let command_dir = match &common.command_dir {
+        Some(dir) => Some(PathBuf::from(&*dir.expand()?)),
+        None => None,
+    };
+    Ok(CommandContext::new(common.command_env.clone(), command_dir))
+}
+
+/// A block of the configuration file with its command context and whether it is visible
+type BlockEntry = (BlockConfigEntry, toml::Value, CommandContext, bool);
+
+/// Whether a block's `if_command` succeeds
+async fn if_command_succeeds(command_context: &CommandContext, cmd: &str) -> Result<bool> {
+    Ok(command_context
+        .shell(cmd)
+        .output()
+        .await
+        .error("failed to run if_command")?
+        .status
+        .success())
+}
+
 #[derive(Debug)]
 pub struct Block {
-    id: usize,
+    id: BlockId,
 
-    event_sender: Option<mpsc::Sender<BlockEvent>>,
+    event_sender: Option<EventSender>,
     widget_updates_sender: mpsc::UnboundedSender<(usize, Vec<u64>)>,
     abort_handle: AbortHandle,
+    raw_config: toml::Value,
+    error_interval: Duration,
+    /// How long to wait before restarting the block after its next error
+    restart_delay: Duration,
 
     click_handler: ClickHandler,
-    default_actions: &'static [(MouseButton, Option<&'static str>, &'static str)],
+    /// Clicks which arrived while a command run with `sync = true` by clicking this block is still
+    /// running
+    clicks: ClickQueue,
+    default_actions: DefaultActions,
     signal: Option<i32>,
+    /// Whether SIGUSR1 asks the block to update, see [`CommonApi::ignore_usr1`]
+    update_on_usr1: bool,
+    import_values: HashMap<String, String>,
+    click_feedback: bool,
+    /// Whether a command started by clicking this block failed recently
+    click_failed: bool,
+    /// What the last click would have run with `--dry-run-clicks`, shown until the given instant
+    click_explanation: Option<(String, tokio::time::Instant)>,
     shared_config: SharedConfig,
+    /// The theme of the block without the overrides of `theme_rules`
+    base_theme: Arc<Theme>,
+    command_context: CommandContext,
+    /// The `if_command` of the block along with how often to run it again, if it is set
+    if_command: Option<(String, Duration)>,
+    /// Whether the block's `if_command` is running, so that it is not run again meanwhile
+    if_command_running: bool,
+    /// Whether the block's `if_command` succeeded when it was last run. Blocks whose command
+    /// fails keep running, but show nothing and receive no clicks.
+    visible: bool,
+    /// Whether the block was hidden over DBus. It keeps running as well.
+    hidden: bool,
+    /// How long the block may be busy before it is restarted
+    update_timeout: Option<Duration>,
+    /// The rendered parts of the block's widget, if it has parts
+    parts_cache: PartsCache,
 
     error_format: Format,
     error_fullscreen_format: Format,
@@ -147,6 +404,7 @@ impl Block {
         self.abort_handle.abort();
         self.event_sender = None;
         self.state = BlockState::None;
+        self.clicks.clear();
     }
 
     fn notify_intervals(&self) {
@@ -154,9 +412,25 @@ impl Block {
             BlockState::None => return,
             BlockState::Normal { widget } | BlockState::Error { widget } => widget,
         };
-        let _ = self
-            .widget_updates_sender
-            .send((self.id, widget.intervals()));
+        let mut intervals = widget.intervals();
+        // Rotate long texts without asking the block for an update
+        if let Some(interval) = self.shared_config.marquee.as_ref().and_then(|m| m.interval) {
+            intervals.push(interval.as_millis() as u64);
+        }
+        let _ = self.widget_updates_sender.send((self.id.get(), intervals));
+    }
+
+    fn perform_post_actions(&self, post_actions: PostActions) {
+        let sender = match &self.event_sender {
+            Some(sender) => sender,
+            None => return,
+        };
+        if let Some(action) = post_actions.action {
+            sender.send_action(action, post_actions.count);
+        }
+        if post_actions.update {
+            sender.request_update();
+        }
     }
 
     fn set_error(&mut self, fullscreen: bool, error: Error) {
@@ -194,16 +468,36 @@ pub enum RequestCmd {
     UnsetWidget,
     SetError(Error),
     SetDefaultActions(&'static [(MouseButton, Option<&'static str>, &'static str)]),
+    IgnoreUsr1,
+    UpdateBlocks(Vec<String>),
+    Reveal(Vec<usize>, Duration),
 }
 
-#[derive(Debug, Clone)]
+/// The status of a rendered block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockStatus {
+    Hidden,
+    Normal(State),
+    Error,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct RenderedBlock {
     segments: Vec<I3BarBlock>,
+    /// The segments showing only the icons of the block, if `width_budget` is set
+    icon_segments: Vec<I3BarBlock>,
     merge_with_next: bool,
+    /// Empty space, which is neither tinted nor separated from its neighbours
+    spacer: bool,
+    shrink_priority: i32,
 }
 
 struct BarState {
     config: Config,
+    config_path: PathBuf,
+    /// The settings of the configuration file other than the blocks, to tell whether a reload
+    /// changes them
+    settings: toml::value::Table,
 
     blocks: Vec<(Block, &'static str)>,
     fullscreen_block: Option<usize>,
@@ -212,19 +506,96 @@ struct BarState {
     widget_updates_stream: BoxedStream<Vec<usize>>,
     widget_updates_sender: mpsc::UnboundedSender<(usize, Vec<u64>)>,
     blocks_render_cache: Vec<RenderedBlock>,
+    /// Transitions of blocks which appeared or disappeared, and flashes of the bar
+    animations: Animations,
+    width_budget: Option<WidthBudget>,
+    alerts: Alerts,
+    theme_rules: ThemeRules,
+    /// The theme of the bar without the overrides of `theme_rules`
+    base_theme: Arc<Theme>,
+    /// The status of every block, shared with blocks such as `summary`
+    block_statuses: watch::Sender<Vec<(&'static str, BlockStatus)>>,
+    /// Blocks shown alone until the given instant, see [`CommonApi::reveal`]
+    revealed: Option<(Vec<usize>, tokio::time::Instant)>,
+    /// The block identifiers set over DBus to show first, see [`presentation`]
+    order: Option<Vec<String>>,
+    /// The IDs of the blocks in the order in which they are shown
+    display_order: Vec<usize>,
+    /// Holds the bar back while the blocks of a refresh group update
+    refresh_groups: RefreshGroups,
+
+    imports: Imports,
+    /// Maps block identifiers (e.g. `cpu-0`) to block IDs
+    block_ids: HashMap<String, usize>,
+    /// Values of blocks which other blocks import from, including their own imported values
+    published_values: Vec<Option<Values>>,
 
     request_sender: mpsc::Sender<Request>,
     request_receiver: mpsc::Receiver<Request>,
 
     signals_stream: BoxedStream<Signal>,
     events_stream: BoxedStream<I3BarEvent>,
+    /// Whether the bar is hidden, if it is followed over sway's IPC
+    visibility_stream: BoxedStream<bool>,
+    /// Nothing is printed while the bar is hidden
+    hidden: bool,
+    /// Method calls over DBus
+    control_stream: BoxedStream<control::Command>,
+
+    /// Restart blocks which failed
+    restart_timers: FuturesUnordered<BoxedFuture<usize>>,
+    /// Looks for blocks which are busy for longer than their `update_timeout`
+    hung_check: tokio::time::Interval,
+
+    /// Waits for the commands started by clicks
+    reaper: Reaper,
+    /// Commands with `sync = true` started by clicks, along with the clicks' post actions
+    pending_clicks: FuturesUnordered<BoxedFuture<(I3BarEvent, PostActions, Result<()>)>>,
+    /// Ends the warning state of blocks whose click commands failed
+    click_feedback_timers: FuturesUnordered<BoxedFuture<usize>>,
+    /// Whether clicks only show what they would run, see `--dry-run-clicks`
+    dry_run_clicks: bool,
+    /// Ends the explanations of clicks with `--dry-run-clicks`
+    click_explanation_timers: FuturesUnordered<BoxedFuture<usize>>,
+
+    /// Schedules the `if_command` of blocks which set `if_command_interval`
+    if_command_sender: mpsc::UnboundedSender<(usize, Vec<u64>)>,
+    if_command_stream: BoxedStream<Vec<usize>>,
+    /// The `if_command` of blocks which are running, along with the result
+    if_command_checks: FuturesUnordered<BoxedFuture<(BlockId, Result<bool>)>>,
+
+    recorder: Option<Recorder>,
+    /// Whether heartbeats are sent, if `[heartbeat]` is set
+    heartbeat: Option<watch::Sender<bool>>,
+
+    /// Decides when to show the warning of `power_warnings`, if it is set
+    power_warnings: Option<PowerWarnings>,
+    power_warnings_stream: BoxedStream<power_warnings::Update>,
+    /// The warning shown in front of all blocks
+    power_warning: Option<RenderedBlock>,
+
+    output: Output,
 }
 
 impl BarState {
-    fn new(config: Config) -> Self {
+    fn new(
+        mut config: Config,
+        config_path: PathBuf,
+        settings: toml::value::Table,
+        output: Output,
+    ) -> Result<Self> {
         let (request_sender, request_receiver) = mpsc::channel(64);
         let (widget_updates_sender, widget_updates_stream) = scheduling::manage_widgets_updates();
-        Self {
+        let (if_command_sender, if_command_stream) = scheduling::manage_widgets_updates();
+        let recorder = config.recording.take().map(Recorder::spawn).transpose()?;
+        let heartbeat = config.heartbeat.take().map(heartbeat::spawn);
+        let power_warnings = config.power_warnings.as_ref().map(PowerWarnings::new);
+        let power_warnings_stream = if power_warnings.is_some() {
+            power_warnings::updates()
+        } else {
+            futures::stream::pending().boxed_local()
+        };
+        Ok(Self {
             blocks: Vec::new(),
             fullscreen_block: None,
             running_blocks: FuturesUnordered::new(),
@@ -232,58 +603,187 @@ impl BarState {
             widget_updates_stream,
             widget_updates_sender,
             blocks_render_cache: Vec::new(),
+            animations: Animations::new(config.shared.theme.transition, config.max_fps),
+            width_budget: config.width_budget.map(WidthBudget::new),
+            alerts: Alerts::default(),
+            theme_rules: ThemeRules::default(),
+            base_theme: config.shared.theme.clone(),
+            block_statuses: watch::channel(Vec::new()).0,
+            revealed: None,
+            order: None,
+            display_order: Vec::new(),
+            refresh_groups: RefreshGroups::default(),
+
+            imports: Imports::default(),
+            block_ids: HashMap::new(),
+            published_values: Vec::new(),
 
             request_sender,
             request_receiver,
 
             signals_stream: signals_stream(),
-            events_stream: events_stream(
-                config.invert_scrolling,
-                Duration::from_millis(config.double_click_delay),
-            ),
+            events_stream: if output.has_clicks() {
+                events_stream(
+                    config.invert_scrolling,
+                    Duration::from_millis(config.double_click_delay),
+                    Duration::from_millis(config.scroll_coalesce_delay),
+                )
+            } else {
+                futures::stream::pending().boxed_local()
+            },
+            visibility_stream: futures::stream::pending().boxed_local(),
+            hidden: false,
+            control_stream: control::commands(),
+
+            restart_timers: FuturesUnordered::new(),
+            hung_check: tokio::time::interval(HUNG_CHECK_INTERVAL),
+
+            reaper: Reaper::default(),
+            pending_clicks: FuturesUnordered::new(),
+            click_feedback_timers: FuturesUnordered::new(),
+            dry_run_clicks: false,
+            click_explanation_timers: FuturesUnordered::new(),
+
+            if_command_sender,
+            if_command_stream,
+            if_command_checks: FuturesUnordered::new(),
+
+            recorder,
+            heartbeat,
+
+            power_warnings,
+            power_warnings_stream,
+            power_warning: None,
+
+            output,
 
             config,
-        }
+            config_path,
+            settings,
+        })
     }
 
-    async fn spawn_block(&mut self, block_config: BlockConfigEntry) -> Result<()> {
-        if let Some(cmd) = &block_config.common.if_command {
-            if !Command::new("sh")
-                .args(["-c", cmd])
-                .output()
-                .await
-                .error("failed to run if_command")?
-                .status
-                .success()
-            {
-                return Ok(());
-            }
-        }
+    /// Start the future of a block. Returns the sender of the block's events and the handle to
+    /// abort it.
+    fn run_block(
+        &mut self,
+        id: BlockId,
+        config: BlockConfig,
+        shared_config: SharedConfig,
+        error_interval: Duration,
+        command_context: CommandContext,
+    ) -> (EventSender, AbortHandle) {
+        let (event_sender, event_receiver) = mpsc::unbounded_channel();
+        let update_pending = Arc::new(AtomicBool::new(false));
+        let activity = Activity::default();
+
+        let api = CommonApi {
+            id,
+            shared_config,
+            event_receiver,
+            update_pending: update_pending.clone(),
+            activity: activity.clone(),
+            action_count: 1,
+            coalesce_actions: false,
+            repeated_action: None,
+
+            request_sender: self.request_sender.clone(),
+            block_statuses: self.block_statuses.subscribe(),
+
+            error_interval,
+            command_context,
+        };
+
+        let (block_fut, abort_handle) = abortable(config.run(api));
+        self.running_blocks
+            .push(Box::pin(block_fut.map(|res| match res {
+                Ok(res) => res,
+                Err(_aborted) => Ok(()),
+            })));
+        (
+            EventSender::new(event_sender, update_pending, activity),
+            abort_handle,
+        )
+    }
+
+    /// Restart a block which failed. Its error is shown until it sets a widget.
+    fn restart_block(&mut self, id: usize) -> Result<()> {
+        let (block, block_type) = match self.blocks.get(id) {
+            Some(block) => block,
+            // The block was removed by a reload
+            None => return Ok(()),
+        };
+        let config = BlockConfigEntry::deserialize(block.raw_config.clone())
+            .or_error(|| format!("Failed to restart block '{block_type}'"))?
+            .config;
+        let block_id = block.id.clone();
+        let shared_config = block.shared_config.clone();
+        let error_interval = block.error_interval;
+        let command_context = block.command_context.clone();
+        let (event_sender, abort_handle) = self.run_block(
+            block_id,
+            config,
+            shared_config,
+            error_interval,
+            command_context,
+        );
+        let block = &mut self.blocks[id].0;
+        block.event_sender = Some(event_sender);
+        block.abort_handle = abort_handle;
+        Ok(())
+    }
 
+    /// `raw_config` is the block's entry of the configuration file, used to restart the block
+    /// after an error.
+    fn spawn_block(
+        &mut self,
+        block_config: BlockConfigEntry,
+        raw_config: toml::Value,
+        command_context: CommandContext,
+        visible: bool,
+    ) -> Result<()> {
+        self.check_block(&block_config.common)?;
         let mut shared_config = self.config.shared.clone();
+        // The theme without the overrides of a theme rule which may be in effect
+        shared_config.theme = self.base_theme.clone();
 
         // Overrides
+        let colors = block_config.common.colors();
         if let Some(icons_format) = block_config.common.icons_format {
             shared_config.icons_format = Arc::new(icons_format);
         }
+        if let Some(icon_position) = block_config.common.icon_position {
+            shared_config.icon_position = icon_position;
+        }
         if let Some(theme_overrides) = block_config.common.theme_overrides {
             Arc::make_mut(&mut shared_config.theme).apply_overrides(theme_overrides)?;
         }
+        if colors != BlockColors::default() {
+            shared_config.colors = Arc::new(colors);
+        }
         if let Some(icons_overrides) = block_config.common.icons_overrides {
             Arc::make_mut(&mut shared_config.icons).apply_overrides(icons_overrides);
         }
-
-        let (event_sender, event_receiver) = mpsc::channel(64);
-
-        let api = CommonApi {
-            id: self.blocks.len(),
-            shared_config: shared_config.clone(),
-            event_receiver,
-
-            request_sender: self.request_sender.clone(),
-
-            error_interval: Duration::from_secs(block_config.common.error_interval),
-        };
+        if let Some(localization_overrides) = block_config.common.localization_overrides {
+            Arc::make_mut(&mut shared_config.localization).apply_overrides(localization_overrides);
+        }
+        if let Some(markup) = block_config.common.markup {
+            shared_config.markup = markup;
+        }
+        if let Some(max_width) = block_config.common.max_width {
+            // The marquee is not redrawn more often than `max_fps` allows
+            let frame_interval = self
+                .config
+                .max_fps
+                .and_then(|fps| Duration::try_from_secs_f64(fps.recip()).ok())
+                .unwrap_or_default();
+            let interval = block_config
+                .common
+                .marquee
+                .then_some(block_config.common.marquee_interval.0.max(frame_interval));
+            shared_config.marquee = Some(Arc::new(Marquee::new(max_width, interval)));
+        }
+        let update_timeout = block_config.common.update_timeout.map(|timeout| timeout.0);
 
         let error_format = block_config
             .common
@@ -294,20 +794,49 @@ impl BarState {
             .error_fullscreen_format
             .with_default_config(&self.config.error_fullscreen_format);
 
+        let id = BlockId::new(self.blocks.len());
         let block_name = block_config.config.name();
-        let (block_fut, abort_handle) = abortable(block_config.config.run(api));
+        let error_interval = Duration::from_secs(block_config.common.error_interval);
+        let (event_sender, abort_handle) = self.run_block(
+            id.clone(),
+            block_config.config,
+            shared_config.clone(),
+            error_interval,
+            command_context.clone(),
+        );
 
         let block = Block {
-            id: self.blocks.len(),
+            id,
 
             event_sender: Some(event_sender),
             widget_updates_sender: self.widget_updates_sender.clone(),
             abort_handle,
+            raw_config,
+            error_interval,
+            restart_delay: error_interval,
 
             click_handler: block_config.common.click,
+            clicks: ClickQueue::default(),
             default_actions: &[],
+            update_on_usr1: true,
             signal: block_config.common.signal,
+            import_values: block_config.common.import_values,
+            click_feedback: block_config.common.click_feedback,
+            click_failed: false,
+            click_explanation: None,
+            base_theme: shared_config.theme.clone(),
             shared_config,
+            command_context,
+            if_command: block_config
+                .common
+                .if_command
+                .zip(block_config.common.if_command_interval)
+                .map(|(cmd, interval)| (cmd, interval.0)),
+            if_command_running: false,
+            visible,
+            hidden: false,
+            update_timeout,
+            parts_cache: PartsCache::default(),
 
             error_format,
             error_fullscreen_format,
@@ -315,70 +844,825 @@ impl BarState {
             state: BlockState::None,
         };
 
-        self.running_blocks
-            .push(Box::pin(block_fut.map(|res| match res {
-                Ok(res) => res,
-                Err(_aborted) => Ok(()),
-            })));
         self.blocks.push((block, block_name));
         self.blocks_render_cache.push(RenderedBlock {
             segments: Vec::new(),
+            icon_segments: Vec::new(),
             merge_with_next: block_config.common.merge_with_next,
+            spacer: false,
+            shrink_priority: block_config.common.shrink_priority,
         });
+        self.published_values.push(None);
+        self.block_statuses
+            .send_modify(|statuses| statuses.push((block_name, BlockStatus::Hidden)));
+
+        Ok(())
+    }
+
+    /// Check the common configuration of a block, see [`BarState::block_entries`]
+    fn check_block(&self, common: &CommonBlockConfig) -> Result<()> {
+        let mut theme = Theme::clone(&self.base_theme);
+        if let Some(theme_overrides) = &common.theme_overrides {
+            theme.apply_overrides(theme_overrides.clone())?;
+        }
+        for rule in &self.config.theme_rules {
+            Theme::clone(&theme).apply_overrides(rule.theme_overrides.clone())?;
+        }
+        if common.marquee {
+            if common.max_width.is_none() {
+                return Err(Error::new("'marquee' requires 'max_width'"));
+            }
+            if common.marquee_interval.0.as_millis() == 0 {
+                return Err(Error::new("'marquee_interval' must be at least 1ms"));
+            }
+        }
+        if matches!(&common.if_command_interval, Some(interval) if interval.0.is_zero()) {
+            return Err(Error::new("'if_command_interval' must be positive"));
+        }
+        if matches!(&common.update_timeout, Some(timeout) if timeout.0.is_zero()) {
+            return Err(Error::new("'update_timeout' must be positive"));
+        }
+        Ok(())
+    }
+
+    /// Replace the running blocks with the blocks of the configuration file. Blocks whose entry
+    /// of the configuration file did not change keep running along with their widgets, even if
+    /// they move. The others are stopped or spawned.
+    async fn load_blocks(
+        &mut self,
+        blocks: Vec<BlockConfigEntry>,
+        raw_blocks: Vec<toml::Value>,
+    ) -> Result<()> {
+        let entries = self.block_entries(blocks, raw_blocks).await?;
+        self.replace_blocks(entries)
+    }
+
+    /// Check the blocks of the configuration file and run their `if_command`s, without touching
+    /// the running blocks, so that an invalid configuration can be rejected on reload. Returns
+    /// the blocks to run with their command contexts and whether they are visible.
+    async fn block_entries(
+        &self,
+        blocks: Vec<BlockConfigEntry>,
+        raw_blocks: Vec<toml::Value>,
+    ) -> Result<Vec<BlockEntry>> {
+        let mut entries = Vec::new();
+        for (block_config, raw_config) in blocks.into_iter().zip(raw_blocks) {
+            self.check_block(&block_config.common)?;
+            let command_context = block_command_context(&block_config.common)?;
+            let visible = match &block_config.common.if_command {
+                Some(cmd) => if_command_succeeds(&command_context, cmd).await?,
+                None => true,
+            };
+            // Blocks whose `if_command` is not run again are never shown, so don't run them
+            if visible || block_config.common.if_command_interval.is_some() {
+                entries.push((block_config, raw_config, command_context, visible));
+            }
+        }
+
+        // Report invalid `import_values` and `refresh_groups` now rather than in
+        // `resolve_imports`
+        let names: Vec<&str> = entries
+            .iter()
+            .map(|(block_config, ..)| block_config.config.name())
+            .collect();
+        let import_values: Vec<_> = entries
+            .iter()
+            .map(|(block_config, ..)| &block_config.common.import_values)
+            .collect();
+        Imports::new(&names, &import_values)?;
+        RefreshGroups::new(&self.config.refresh_groups, &imports::block_ids(&names))?;
+        Ok(entries)
+    }
+
+    /// Replace the running blocks with `entries`, see [`BarState::load_blocks`]
+    fn replace_blocks(&mut self, entries: Vec<BlockEntry>) -> Result<()> {
+        // Blocks may have sent requests with their old IDs in the meantime
+        while let Ok(request) = self.request_receiver.try_recv() {
+            self.process_request(request);
+        }
+
+        let old_raw: Vec<_> = self.blocks.iter().map(|(b, _)| &b.raw_config).collect();
+        let new_raw: Vec<_> = entries.iter().map(|(_, raw, _, _)| raw.clone()).collect();
+        let kept = config::match_blocks(&old_raw, &new_raw);
+
+        let old_len = self.blocks.len();
+        let mut old_blocks: Vec<_> = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let old_render_cache = std::mem::take(&mut self.blocks_render_cache);
+        let mut old_values = std::mem::take(&mut self.published_values);
+        let old_statuses = self.block_statuses.send_replace(Vec::new());
+
+        for ((block_config, raw_config, command_context, visible), kept) in
+            entries.into_iter().zip(kept)
+        {
+            match kept.and_then(|i| Some((i, old_blocks[i].take()?))) {
+                Some((i, (mut block, block_name))) => {
+                    block.id.set(self.blocks.len());
+                    block.visible = visible;
+                    self.blocks.push((block, block_name));
+                    self.blocks_render_cache.push(old_render_cache[i].clone());
+                    self.published_values.push(old_values[i].take());
+                    self.block_statuses
+                        .send_modify(|statuses| statuses.push(old_statuses[i]));
+                }
+                None => self.spawn_block(block_config, raw_config, command_context, visible)?,
+            }
+        }
+        for (mut block, _) in old_blocks.into_iter().flatten() {
+            block.abort();
+            block.id.remove();
+        }
+
+        // Schedule the updates of the widgets and the `if_command`s by their new IDs
+        for id in 0..old_len.max(self.blocks.len()) {
+            let _ = self.widget_updates_sender.send((id, Vec::new()));
+            let _ = self.if_command_sender.send((id, Vec::new()));
+        }
+        for (id, (block, _)) in self.blocks.iter().enumerate() {
+            block.notify_intervals();
+            if let Some((_, interval)) = &block.if_command {
+                let interval = (interval.as_millis() as u64).max(1);
+                let _ = self.if_command_sender.send((id, vec![interval]));
+            }
+        }
+
+        self.fullscreen_block = None;
+        self.revealed = None;
+        self.animations = Animations::new(self.base_theme.transition, self.config.max_fps);
+        self.width_budget = self.config.width_budget.map(WidthBudget::new);
+        self.resolve_imports()?;
+        // Renders all blocks as well
+        self.apply_theme_rules()
+    }
+
+    /// Stop the subprocesses and write the recorded history before the process is replaced
+    async fn shutdown(&mut self) {
+        self.reaper.shutdown();
+        if let Some(recorder) = &self.recorder {
+            recorder.flush().await;
+        }
+    }
+
+    /// Read the configuration file again and replace the blocks which changed. The whole bar is
+    /// restarted if other settings changed, or if `reload = "exec"` is set.
+    async fn reload(&mut self) -> Result<()> {
+        if self.config.reload == Reload::Exec {
+            self.shutdown().await;
+            restart();
+        }
+        let (mut config, raw_config) = match load_config(&self.config_path) {
+            Ok(config) => config,
+            Err(error) => {
+                log::error!("Cannot reload the configuration: {error}");
+                return Ok(());
+            }
+        };
+        if raw_config.settings != self.settings {
+            log::info!("Settings other than blocks changed, restarting");
+            self.shutdown().await;
+            restart();
+        }
+        let blocks = std::mem::take(&mut config.blocks);
+        // Keep the running blocks if the new ones are invalid
+        let entries = match self.block_entries(blocks, raw_config.blocks).await {
+            Ok(entries) => entries,
+            Err(error) => {
+                log::error!("Cannot reload the configuration: {error}");
+                return Ok(());
+            }
+        };
+        self.replace_blocks(entries)?;
+        self.render();
+        Ok(())
+    }
+
+    /// Resolve `import_values` of all blocks and the identifiers used to reference blocks. Must be
+    /// called after all blocks are spawned.
+    fn resolve_imports(&mut self) -> Result<()> {
+        let names: Vec<&str> = self.blocks.iter().map(|(_, name)| *name).collect();
+        self.block_ids = imports::block_ids(&names);
+        let import_values: Vec<_> = self
+            .blocks
+            .iter()
+            .map(|(block, _)| &block.import_values)
+            .collect();
+        self.imports = Imports::new(&names, &import_values)?;
+        self.alerts = Alerts::new(&self.config.alerts, &names, &self.block_ids);
+        for rule in &self.config.theme_rules {
+            // Report invalid overrides now rather than when the rule takes effect
+            Theme::clone(&self.base_theme).apply_overrides(rule.theme_overrides.clone())?;
+        }
+        self.theme_rules = ThemeRules::new(&self.config.theme_rules, &self.block_ids);
+        self.display_order = presentation::display_order(
+            self.order.as_deref().unwrap_or_default(),
+            &self.block_ids,
+            names.len(),
+        );
+        self.refresh_groups = RefreshGroups::new(&self.config.refresh_groups, &self.block_ids)?;
+        Ok(())
+    }
+
+    /// Send update requests to the blocks referenced by name (e.g. `sound`) or by identifier (e.g.
+    /// `sound-0`)
+    fn update_blocks(&self, references: &[String]) {
+        for reference in references {
+            let ids = self.find_blocks(reference);
+            if ids.is_empty() {
+                log::warn!("Cannot update block '{reference}': no such block");
+            }
+            for id in ids {
+                if let Some(sender) = &self.blocks[id].0.event_sender {
+                    sender.request_update();
+                }
+            }
+        }
+    }
 
+    /// The IDs of the blocks referenced by name, identifier or position, see
+    /// [`control::resolve`]
+    fn find_blocks(&self, reference: &str) -> Vec<usize> {
+        let names: Vec<&str> = self.blocks.iter().map(|(_, name)| *name).collect();
+        control::resolve(reference, &names, &self.block_ids)
+    }
+
+    /// Handle a method call over DBus
+    async fn process_command(&mut self, command: control::Command) -> Result<()> {
+        let no_such_block = |reference: &str| Error::new(format!("No such block '{reference}'"));
+        match command {
+            control::Command::UpdateBlock(reference, reply) => {
+                let result = if self.find_blocks(&reference).is_empty() {
+                    Err(no_such_block(&reference))
+                } else {
+                    self.update_blocks(&[reference]);
+                    Ok(())
+                };
+                let _ = reply.send(result);
+            }
+            control::Command::SetVisibility(reference, show, reply) => {
+                let ids = self.find_blocks(&reference);
+                let _ = reply.send(if ids.is_empty() {
+                    Err(no_such_block(&reference))
+                } else {
+                    Ok(())
+                });
+                let mut changed = false;
+                for id in ids {
+                    let block = &mut self.blocks[id].0;
+                    if block.hidden == show {
+                        block.hidden = !show;
+                        changed |= self.render_block(id)?;
+                    }
+                }
+                if changed {
+                    self.render();
+                }
+            }
+            control::Command::GetBlocks(reply) => {
+                let blocks = self
+                    .identifiers()
+                    .iter()
+                    .zip(&self.blocks_render_cache)
+                    .map(|(identifier, block)| {
+                        let text: String = block.segments.iter().map(plain_text).collect();
+                        format!("{identifier}: {}", text.trim())
+                    })
+                    .collect();
+                let _ = reply.send(blocks);
+            }
+            control::Command::Reload => self.reload().await?,
+            control::Command::SetOrder(order, reply) => {
+                self.display_order = presentation::display_order(
+                    order.as_deref().unwrap_or_default(),
+                    &self.block_ids,
+                    self.blocks.len(),
+                );
+                if self.config.presentation.persist {
+                    presentation::save_order(order.as_deref()).await;
+                }
+                self.order = order;
+                let _ = reply.send(());
+                self.render();
+            }
+            control::Command::GetStatus(reply) => {
+                let identifiers = self.identifiers();
+                let order: Vec<&str> = self
+                    .display_order
+                    .iter()
+                    .map(|&id| identifiers[id])
+                    .collect();
+                let _ = reply.send(HashMap::from([
+                    ("order".into(), zvariant::Value::from(order).into()),
+                    (
+                        "reordered".into(),
+                        zvariant::Value::from(self.order.is_some()).into(),
+                    ),
+                ]));
+            }
+            control::Command::RefreshGroup(group, reply) => {
+                match self
+                    .refresh_groups
+                    .trigger_name(&group, tokio::time::Instant::now())
+                {
+                    Some(ids) => {
+                        let _ = reply.send(Ok(()));
+                        for id in ids {
+                            if let Some(sender) = &self.blocks[id].0.event_sender {
+                                sender.request_update();
+                            }
+                        }
+                    }
+                    None => {
+                        let error = format!("No such refresh group '{group}'");
+                        let _ = reply.send(Err(Error::new(error)));
+                    }
+                }
+            }
+            control::Command::SetHeartbeat(enabled, reply) => {
+                let _ = reply.send(match &self.heartbeat {
+                    Some(heartbeat) => {
+                        heartbeat.send_replace(enabled);
+                        Ok(())
+                    }
+                    None => Err(Error::new("No [heartbeat] is configured")),
+                });
+            }
+            control::Command::ExplainClick(reference, button, reply) => {
+                let ids = self.find_blocks(&reference);
+                let button = MouseButton::from_name(&button);
+                let result = if ids.is_empty() {
+                    Err(no_such_block(&reference))
+                } else if button == MouseButton::Unknown {
+                    Err(Error::new("Unknown button"))
+                } else {
+                    let identifiers = self.identifiers();
+                    Ok(ids
+                        .into_iter()
+                        .map(|id| {
+                            let (block, name) = &self.blocks[id];
+                            let event = I3BarEvent {
+                                id,
+                                instance: None,
+                                button,
+                                count: 1,
+                            };
+                            let commands =
+                                click::click_context(&block.command_context, name, &event);
+                            let resolved =
+                                block.click_handler.resolve(&event, block.default_actions);
+                            let explanation = block.click_handler.explain(&resolved, &commands);
+                            format!("{}: {explanation}", identifiers[id])
+                        })
+                        .collect())
+                };
+                let _ = reply.send(result);
+            }
+        }
         Ok(())
     }
 
-    fn process_request(&mut self, request: Request) {
-        let block = &mut self.blocks[request.block_id].0;
+    /// The identifiers of the blocks (e.g. `cpu-0`), by block ID
+    fn identifiers(&self) -> Vec<&str> {
+        let mut identifiers = vec![""; self.blocks.len()];
+        for (identifier, id) in &self.block_ids {
+            identifiers[*id] = identifier;
+        }
+        identifiers
+    }
+
+    /// Returns `false` if the request was dropped because its block does not exist (anymore)
+    fn process_request(&mut self, request: Request) -> bool {
+        let id = request.block_id;
+        // Removed blocks may still send requests until they are aborted
+        let (block, block_name) = match self.blocks.get_mut(id) {
+            Some(block) => block,
+            None => {
+                log::debug!("Dropping a request of the removed block {id}");
+                return false;
+            }
+        };
         match request.cmd {
             RequestCmd::SetWidget(widget) => {
+                if let (Some(recorder), Some(values)) = (&self.recorder, widget.values()) {
+                    recorder.record(block_name, values);
+                }
+                block
+                    .parts_cache
+                    .changed(widget.parts_diff().unwrap_or(&WidgetsDiff::All));
                 block.state = BlockState::Normal { widget };
-                if self.fullscreen_block == Some(request.block_id) {
+                block.notify_intervals();
+                if self.fullscreen_block == Some(id) {
                     self.fullscreen_block = None;
                 }
             }
             RequestCmd::UnsetWidget => {
                 block.state = BlockState::None;
-                if self.fullscreen_block == Some(request.block_id) {
+                block.notify_intervals();
+                if self.fullscreen_block == Some(id) {
                     self.fullscreen_block = None;
                 }
             }
             RequestCmd::SetError(error) => {
-                block.set_error(self.fullscreen_block == Some(request.block_id), error);
+                block.set_error(self.fullscreen_block == Some(id), error);
+                block.notify_intervals();
             }
             RequestCmd::SetDefaultActions(actions) => {
                 block.default_actions = actions;
             }
+            RequestCmd::IgnoreUsr1 => {
+                block.update_on_usr1 = false;
+            }
+            RequestCmd::UpdateBlocks(references) => {
+                self.update_blocks(&references);
+            }
+            RequestCmd::Reveal(blocks, duration) => {
+                self.revealed = Some((blocks, tokio::time::Instant::now() + duration));
+                self.render();
+            }
         }
-        block.notify_intervals();
+        true
     }
 
-    fn render_block(&mut self, id: usize) -> Result<()> {
+    /// Render a block into the render cache. Returns whether its output changed.
+    fn render_block(&mut self, id: usize) -> Result<bool> {
         let (block, block_type) = &mut self.blocks[id];
-        let data = &mut self.blocks_render_cache[id].segments;
-        match &block.state {
+        let new_data = match &block.state {
+            BlockState::Normal { .. } if block.click_explanation.is_some() => {
+                let (explanation, _) = block.click_explanation.as_ref().unwrap();
+                Widget::new()
+                    .with_text(explanation.chars().collect_pango_escaped())
+                    .with_state(State::Info)
+                    .get_data(&block.shared_config, id)
+                    .in_block(block_type, id)?
+            }
             BlockState::None => {
-                data.clear();
+                self.published_values[id] = None;
+                Vec::new()
             }
-            BlockState::Normal { widget } | BlockState::Error { widget, .. } => {
-                *data = widget
+            BlockState::Error { widget, .. } => {
+                self.published_values[id] = None;
+                widget
                     .get_data(&block.shared_config, id)
-                    .in_block(block_type, id)?;
+                    .in_block(block_type, id)?
+            }
+            BlockState::Normal { widget } => {
+                let imports = self.imports.of(id);
+                let data =
+                    if imports.is_empty() && !self.imports.is_source(id) && !block.click_failed {
+                        block
+                            .parts_cache
+                            .get_data(widget, &block.shared_config, id)
+                            .in_block(block_type, id)?
+                    } else {
+                        let mut widget = widget.clone();
+                        if block.click_failed {
+                            widget.state = State::Warning;
+                        }
+                        if !imports.is_empty() || self.imports.is_source(id) {
+                            let mut values = widget.values().cloned().unwrap_or_default();
+                            self.imports.apply(id, &mut values, &self.published_values);
+                            widget.set_values(values.clone());
+                            if self.imports.is_source(id) {
+                                self.published_values[id] = Some(values);
+                            }
+                        }
+                        widget
+                            .get_data(&block.shared_config, id)
+                            .in_block(block_type, id)?
+                    };
+                // The block works again, so restart it quickly after its next error
+                block.restart_delay = block.error_interval;
+                data
+            }
+        };
+        let icon_segments = match (&block.state, &self.width_budget) {
+            (BlockState::Normal { widget }, Some(_)) => {
+                widget.get_icon_data(&block.shared_config, id)
+            }
+            _ => Vec::new(),
+        };
+        // Blocks hidden over DBus or by their `if_command` keep running, but show nothing
+        let (new_data, icon_segments) = if block.hidden || !block.visible {
+            (Vec::new(), Vec::new())
+        } else {
+            (new_data, icon_segments)
+        };
+        let status = match &block.state {
+            _ if !block.visible => BlockStatus::Hidden,
+            BlockState::None => BlockStatus::Hidden,
+            BlockState::Normal { widget } => BlockStatus::Normal(widget.state),
+            BlockState::Error { .. } => BlockStatus::Error,
+        };
+        self.blocks_render_cache[id].spacer =
+            matches!(&block.state, BlockState::Normal { widget } if widget.is_spacer());
+        let state = match status {
+            BlockStatus::Normal(state) => Some(state),
+            _ => None,
+        };
+        let values = match &block.state {
+            BlockState::Normal { widget } => widget.values(),
+            _ => None,
+        };
+        let theme_changed = self.theme_rules.block_rendered(id, values);
+        if self.alerts.block_rendered(id, state) {
+            self.alert(id);
+        }
+        // Only notify the blocks watching the statuses if this one changed
+        self.block_statuses.send_if_modified(|statuses| {
+            let changed = statuses[id].1 != status;
+            statuses[id].1 = status;
+            changed
+        });
+        if theme_changed {
+            // Renders this block as well
+            self.apply_theme_rules()?;
+            return Ok(true);
+        }
+        self.blocks_render_cache[id].icon_segments = icon_segments;
+        // Printing the whole bar is the expensive part, so skip it if nothing changed
+        let data = &mut self.blocks_render_cache[id].segments;
+        if *data == new_data {
+            return Ok(false);
+        }
+        self.animations
+            .block_changed(id, data, &new_data, self.config.shared.theme.idle_bg);
+        *data = new_data;
+        Ok(true)
+    }
+
+    /// Layer the overrides of the theme rule in effect, if any, onto the configured themes of the
+    /// bar and of all blocks, and render all blocks again
+    fn apply_theme_rules(&mut self) -> Result<()> {
+        let overrides = self.theme_rules.active_overrides().cloned();
+        let themed = |base: &Arc<Theme>| -> Result<Arc<Theme>> {
+            match &overrides {
+                Some(overrides) => {
+                    let mut theme = Theme::clone(base);
+                    theme.apply_overrides(overrides.clone())?;
+                    Ok(Arc::new(theme))
+                }
+                None => Ok(base.clone()),
             }
+        };
+        self.config.shared.theme = themed(&self.base_theme)?;
+        for (block, _) in &mut self.blocks {
+            block.shared_config.theme = themed(&block.base_theme)?;
         }
-        Ok(())
+        for id in 0..self.blocks.len() {
+            self.render_block(id)?;
+        }
+        self.render_power_warning()
+    }
+
+    /// Flash the bar and run the alert command, because block `id` entered a state listed in
+    /// `alerts.flash_on`
+    fn alert(&mut self, id: usize) {
+        self.animations.flash(self.config.alerts.flashes);
+        if let Some(cmd) = &self.config.alerts.command {
+            let identifier = self
+                .block_ids
+                .iter()
+                .find(|(_, block_id)| **block_id == id)
+                .map_or("", |(identifier, _)| identifier.as_str());
+            if let Err(e) = CommandContext::default()
+                .with_default_env("I3RS_ALERT_BLOCK", identifier)
+                .spawn_shell(cmd)
+            {
+                log::warn!("Failed to run alert command '{cmd}': {e}");
+            }
+        }
+    }
+
+    /// Render a block and the blocks importing its values. Returns whether any output changed.
+    fn render_block_and_dependents(&mut self, id: usize) -> Result<bool> {
+        let mut changed = self.render_block(id)?;
+        if self.imports.is_source(id) {
+            for dependent in self.imports.dependents(id) {
+                changed |= self.render_block(dependent)?;
+            }
+        }
+        Ok(changed)
     }
 
     fn render(&self) {
+        if self.hidden || self.refresh_groups.is_waiting() {
+            return;
+        }
         if let Some(id) = self.fullscreen_block {
-            protocol::print_blocks(&[&self.blocks_render_cache[id]], &self.config.shared);
+            self.output
+                .print(&[&self.blocks_render_cache[id]], &self.config.shared);
+        } else if let Some((revealed, _)) = &self.revealed {
+            let blocks: Vec<_> = revealed
+                .iter()
+                .filter_map(|id| self.blocks_render_cache.get(*id))
+                .collect();
+            self.output.print(&blocks, &self.config.shared);
+        } else if self.animations.is_running() {
+            let inverted = self.animations.is_inverted();
+            let theme = &self.config.shared.theme;
+            let blocks: Vec<_> = self
+                .blocks_render_cache
+                .iter()
+                .enumerate()
+                .map(|(id, block)| {
+                    let mut block = match self.animations.frame(id) {
+                        Some(frame) => Cow::Owned(RenderedBlock {
+                            segments: frame.to_vec(),
+                            ..block.clone()
+                        }),
+                        None => Cow::Borrowed(block),
+                    };
+                    if inverted {
+                        for segment in &mut block.to_mut().segments {
+                            animation::invert(segment, theme.idle_fg, theme.idle_bg);
+                        }
+                    }
+                    block
+                })
+                .collect();
+            let blocks = self.in_display_order(self.fit_width(blocks));
+            self.output
+                .print(&self.with_power_warning(blocks), &self.config.shared);
         } else {
-            protocol::print_blocks(&self.blocks_render_cache, &self.config.shared);
+            let blocks = self.blocks_render_cache.iter().map(Cow::Borrowed).collect();
+            let blocks = self.in_display_order(self.fit_width(blocks));
+            self.output
+                .print(&self.with_power_warning(blocks), &self.config.shared);
+        }
+    }
+
+    /// Put the warning of `power_warnings` in front of the blocks, if it is shown
+    fn with_power_warning<'a>(
+        &'a self,
+        mut blocks: Vec<Cow<'a, RenderedBlock>>,
+    ) -> Vec<Cow<'a, RenderedBlock>> {
+        if let Some(warning) = &self.power_warning {
+            blocks.insert(0, Cow::Borrowed(warning));
+        }
+        blocks
+    }
+
+    /// Render the warning of `power_warnings`, using the error format of the bar
+    fn render_power_warning(&mut self) -> Result<()> {
+        let widget = self.power_warnings.as_ref().and_then(|warnings| {
+            warnings.widget(FormatConfig::default().with_default_config(&self.config.error_format))
+        });
+        self.power_warning = match widget {
+            Some(widget) => Some(RenderedBlock {
+                segments: widget.get_data(&self.config.shared, power_warnings::BLOCK_ID)?,
+                ..Default::default()
+            }),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Shrink blocks so that the bar fits into `width_budget`, if it is set
+    fn fit_width<'a>(&self, blocks: Vec<Cow<'a, RenderedBlock>>) -> Vec<Cow<'a, RenderedBlock>> {
+        match &self.width_budget {
+            Some(budget) => budget.fit(blocks, &self.config.shared),
+            None => blocks,
+        }
+    }
+
+    /// Reorder the blocks, given in order of their IDs, if another order was set over DBus
+    fn in_display_order<T>(&self, blocks: Vec<T>) -> Vec<T> {
+        if self.order.is_none() {
+            return blocks;
+        }
+        let mut blocks: Vec<Option<T>> = blocks.into_iter().map(Some).collect();
+        self.display_order
+            .iter()
+            .filter_map(|&id| blocks.get_mut(id)?.take())
+            .collect()
+    }
+
+    /// Show or hide a block depending on the result of its `if_command`. A block which appears is
+    /// asked to update.
+    fn if_command_finished(&mut self, id: usize, result: Result<bool>) -> Result<()> {
+        let (block, block_type) = match self.blocks.get_mut(id) {
+            Some(block) => block,
+            // The block was removed by a reload
+            None => return Ok(()),
+        };
+        block.if_command_running = false;
+        let visible = match result {
+            Ok(visible) => visible,
+            Err(error) => {
+                log::warn!("Block '{block_type}': {error}");
+                return Ok(());
+            }
+        };
+        if block.visible == visible {
+            return Ok(());
+        }
+        block.visible = visible;
+        if visible {
+            if let Some(sender) = &block.event_sender {
+                sender.request_update();
+            }
+        }
+        if self.render_block(id)? {
+            self.render();
         }
+        Ok(())
+    }
+
+    fn process_click(&mut self, event: I3BarEvent) -> Result<()> {
+        if event.id == power_warnings::BLOCK_ID {
+            if let Some(warnings) = &mut self.power_warnings {
+                if warnings.dismiss() {
+                    self.render_power_warning()?;
+                    self.render();
+                }
+            }
+            return Ok(());
+        }
+        let (block, block_type) = match self.blocks.get_mut(event.id) {
+            Some(block) => block,
+            // The click was on a block which a reload removed
+            None => return Ok(()),
+        };
+        // The click was on a block which its `if_command` hid meanwhile
+        if !block.visible {
+            return Ok(());
+        }
+        match &mut block.state {
+            BlockState::None => (),
+            BlockState::Normal { .. } if block.clicks.in_progress() => {
+                // Clicks must not overtake the click whose command is still running
+                block.clicks.push(event);
+            }
+            BlockState::Normal { .. } => {
+                let command_context =
+                    click::click_context(&block.command_context, block_type, &event);
+                let resolved = block.click_handler.resolve(&event, block.default_actions);
+                if self.dry_run_clicks && resolved.cmd.is_some() {
+                    let explanation = block.click_handler.explain(&resolved, &command_context);
+                    log::info!(
+                        "Click on {block_type} ({:?}): {explanation}",
+                        event.instance
+                    );
+                    let until = tokio::time::Instant::now() + CLICK_EXPLANATION_DURATION;
+                    block.click_explanation = Some((explanation, until));
+                    let block_id = block.id.clone();
+                    self.click_explanation_timers.push(Box::pin(
+                        tokio::time::sleep_until(until).map(move |()| block_id.get()),
+                    ));
+                    if self.render_block(event.id)? {
+                        self.render();
+                    }
+                    return Ok(());
+                }
+                let mut post_actions = resolved
+                    .execute(&event, &mut self.reaper, &command_context)
+                    .in_block(block_type, event.id)?;
+                match post_actions.sync_cmd.take() {
+                    Some(cmd) => {
+                        // Keep the bar responsive while the command runs
+                        block.clicks.start();
+                        let block_id = block.id.clone();
+                        self.pending_clicks.push(Box::pin(async move {
+                            let mut event = event;
+                            // Once for each click of a burst, one after the other
+                            let result = async {
+                                for _ in 0..event.count {
+                                    command_context.spawn_shell_sync(&cmd).await?;
+                                }
+                                std::io::Result::Ok(())
+                            };
+                            let result = result.await.or_error(|| {
+                                format!("'{:?}' button handler: Failed to run '{cmd}", event.button)
+                            });
+                            // A reload may have moved the block in the meantime
+                            event.id = block_id.get();
+                            (event, post_actions, result)
+                        }));
+                    }
+                    None => block.perform_post_actions(post_actions),
+                }
+            }
+            BlockState::Error { widget } => {
+                if self.fullscreen_block == Some(event.id) {
+                    self.fullscreen_block = None;
+                    widget.set_format(block.error_format.clone());
+                } else {
+                    self.fullscreen_block = Some(event.id);
+                    widget.set_format(block.error_fullscreen_format.clone());
+                }
+                block.notify_intervals();
+                if self.render_block(event.id)? {
+                    self.render();
+                }
+            }
+        }
+        Ok(())
     }
 
     async fn process_event(&mut self) -> Result<()> {
+        let reveal_until = self.revealed.as_ref().map(|(_, until)| *until);
+        let refresh_deadline = self.refresh_groups.deadline();
         tokio::select! {
             // Handle blocks' errors
             Some(block_result) = self.running_blocks.next() => {
@@ -387,70 +1671,214 @@ impl BarState {
             // Receive messages from blocks
             Some(request) = self.request_receiver.recv() => {
                 let id = request.block_id;
-                self.process_request(request);
-                self.render_block(id)?;
-                self.render();
+                let sets_widget = matches!(
+                    request.cmd,
+                    RequestCmd::SetWidget(_) | RequestCmd::UnsetWidget | RequestCmd::SetError(_)
+                );
+                if self.process_request(request) {
+                    // The last block of a refresh group releases the frame which was held back
+                    let updated = sets_widget && self.refresh_groups.block_updated(id);
+                    if self.render_block_and_dependents(id)? || updated {
+                        self.render();
+                    }
+                }
                 Ok(())
             }
             // Handle scheduled updates
             Some(ids) = self.widget_updates_stream.next() => {
+                let mut changed = false;
+                // Updates which were scheduled before a reload may refer to removed blocks
+                let len = self.blocks.len();
+                for id in ids.into_iter().filter(|id| *id < len) {
+                    // Animated formats change every part
+                    self.blocks[id].0.parts_cache.changed(&WidgetsDiff::All);
+                    changed |= self.render_block(id)?;
+                }
+                if changed {
+                    self.render();
+                }
+                Ok(())
+            }
+            // Run the `if_command` of blocks again
+            Some(ids) = self.if_command_stream.next() => {
+                // Runs which were scheduled before a reload may refer to removed blocks
                 for id in ids {
-                    self.render_block(id)?;
+                    let block = match self.blocks.get_mut(id) {
+                        Some((block, _)) => block,
+                        None => continue,
+                    };
+                    // A slow command is not run again before it finished
+                    if block.if_command_running {
+                        continue;
+                    }
+                    if let Some((cmd, _)) = &block.if_command {
+                        block.if_command_running = true;
+                        let block_id = block.id.clone();
+                        let command_context = block.command_context.clone();
+                        let cmd = cmd.clone();
+                        self.if_command_checks.push(Box::pin(async move {
+                            (block_id, if_command_succeeds(&command_context, &cmd).await)
+                        }));
+                    }
                 }
-                self.render();
                 Ok(())
             }
+            // Show or hide blocks whose `if_command` finished
+            Some((block_id, result)) = self.if_command_checks.next() => {
+                self.if_command_finished(block_id.get(), result)
+            }
             // Handle clicks
             Some(event) = self.events_stream.next() => {
-                let (block, block_type) = self.blocks.get_mut(event.id).error("Events receiver: ID out of bounds")?;
-                match &mut block.state {
-                    BlockState::None => (),
-                    BlockState::Normal { .. } => {
-                        let post_actions = block.click_handler.handle(&event).await.in_block(block_type, event.id)?;
-                        if let Some(sender) = &block.event_sender {
-                            if let Some(action) = post_actions.action {
-                                let _ = sender.send(BlockEvent::Action(Cow::Owned(action))).await;
-                            } else if let Some((_, _, action)) = block.default_actions
-                                .iter()
-                                .find(|(btn, widget, _)| *btn == event.button && *widget == event.instance.as_deref()) {
-                                let _ = sender.send(BlockEvent::Action(Cow::Borrowed(action))).await;
-                            }
-                            if post_actions.update {
-                                let _ = sender.send(BlockEvent::UpdateRequest).await;
-                            }
+                self.process_click(event)
+            }
+            // Handle clicks which ran a command with `sync = true`
+            Some((event, post_actions, result)) = self.pending_clicks.next() => {
+                let (block, block_type) = match self.blocks.get_mut(event.id) {
+                    Some(block) => block,
+                    None => return Ok(()),
+                };
+                block.clicks.finish(result.is_ok());
+                result.in_block(block_type, event.id)?;
+                if matches!(block.state, BlockState::Normal { .. }) {
+                    block.perform_post_actions(post_actions);
+                }
+                // Handle the clicks which arrived in the meantime, in order
+                while let Some(queued) = self.blocks[event.id].0.clicks.next() {
+                    self.process_click(queued)?;
+                }
+                Ok(())
+            }
+            // Show or hide the warning of `power_warnings`
+            Some(update) = self.power_warnings_stream.next() => {
+                let changed = self
+                    .power_warnings
+                    .as_mut()
+                    .is_some_and(|warnings| warnings.update(update));
+                if changed {
+                    self.render_power_warning()?;
+                    self.render();
+                }
+                Ok(())
+            }
+            // Show the next frame of the running transitions
+            () = self.animations.next_frame(), if self.animations.is_running() => {
+                self.render();
+                Ok(())
+            }
+            // Restart failed blocks
+            Some(id) = self.restart_timers.next() => {
+                self.restart_block(id)
+            }
+            // Fail blocks which hang, so that they are restarted
+            _ = self.hung_check.tick(), if self.blocks.iter().any(|(block, _)| block.update_timeout.is_some()) => {
+                for (id, (block, block_type)) in self.blocks.iter().enumerate() {
+                    if let (Some(timeout), Some(sender)) = (block.update_timeout, &block.event_sender) {
+                        if sender.busy_for().is_some_and(|busy| busy > timeout) {
+                            return Err(Error::new(format!(
+                                "Not done updating after {}s",
+                                timeout.as_secs_f64()
+                            ))
+                            .in_block(block_type, id));
                         }
                     }
-                    BlockState::Error { widget } => {
-                        if self.fullscreen_block == Some(event.id) {
-                            self.fullscreen_block = None;
-                            widget.set_format(block.error_format.clone());
-                        } else {
-                            self.fullscreen_block = Some(event.id);
-                            widget.set_format(block.error_fullscreen_format.clone());
-                        }
-                        block.notify_intervals();
-                        self.render_block(event.id)?;
+                }
+                Ok(())
+            }
+            // Handle failed click commands
+            failure = self.reaper.next_failure() => {
+                match failure.status {
+                    Some(status) => log::warn!("'{}' exited with {status}", failure.cmd),
+                    None => log::warn!("Failed to wait for '{}'", failure.cmd),
+                }
+                let block = match self.blocks.get_mut(failure.block_id) {
+                    Some((block, _)) => block,
+                    None => return Ok(()),
+                };
+                if block.click_feedback && !block.click_failed {
+                    block.click_failed = true;
+                    let block_id = block.id.clone();
+                    self.click_feedback_timers.push(Box::pin(
+                        tokio::time::sleep(CLICK_FEEDBACK_DURATION).map(move |()| block_id.get()),
+                    ));
+                    if self.render_block(failure.block_id)? {
                         self.render();
                     }
                 }
                 Ok(())
             }
+            Some(id) = self.click_feedback_timers.next() => {
+                match self.blocks.get_mut(id) {
+                    Some((block, _)) => block.click_failed = false,
+                    None => return Ok(()),
+                }
+                if self.render_block(id)? {
+                    self.render();
+                }
+                Ok(())
+            }
+            Some(id) = self.click_explanation_timers.next() => {
+                let block = match self.blocks.get_mut(id) {
+                    Some((block, _)) => block,
+                    None => return Ok(()),
+                };
+                // A later click may have replaced the explanation
+                match &block.click_explanation {
+                    Some((_, until)) if *until <= tokio::time::Instant::now() => {
+                        block.click_explanation = None;
+                    }
+                    _ => return Ok(()),
+                }
+                if self.render_block(id)? {
+                    self.render();
+                }
+                Ok(())
+            }
+            // Print the bar although not all blocks of a refresh group updated
+            () = tokio::time::sleep_until(refresh_deadline.unwrap_or_else(tokio::time::Instant::now)), if refresh_deadline.is_some() => {
+                self.refresh_groups.time_out();
+                self.render();
+                Ok(())
+            }
+            // Show all blocks again after some were revealed
+            () = tokio::time::sleep_until(reveal_until.unwrap_or_else(tokio::time::Instant::now)), if reveal_until.is_some() => {
+                self.revealed = None;
+                self.render();
+                Ok(())
+            }
+            // Handle method calls over DBus
+            Some(command) = self.control_stream.next() => {
+                self.process_command(command).await
+            }
+            // Print the latest state when the bar is shown again
+            Some(hidden) = self.visibility_stream.next() => {
+                let shown = self.hidden && !hidden;
+                self.hidden = hidden;
+                if shown {
+                    self.render();
+                }
+                Ok(())
+            }
             // Handle signals
             Some(signal) = self.signals_stream.next() => match signal {
                 Signal::Usr1 => {
-                    for (block, _) in &self.blocks {
+                    for (block, _) in self.blocks.iter().filter(|(block, _)| block.update_on_usr1) {
                         if let Some(sender) = &block.event_sender {
-                            let _ = sender.send(BlockEvent::UpdateRequest).await;
+                            sender.request_update();
                         }
                     }
                     Ok(())
                 }
-                Signal::Usr2 => restart(),
+                Signal::Usr2 => self.reload().await,
                 Signal::Custom(signal) => {
+                    for id in self.refresh_groups.trigger(signal, tokio::time::Instant::now()) {
+                        if let Some(sender) = &self.blocks[id].0.event_sender {
+                            sender.request_update();
+                        }
+                    }
                     for (block, _) in &self.blocks {
                         if let Some(sender) = &block.event_sender {
                             if block.signal == Some(signal) {
-                                let _ = sender.send(BlockEvent::UpdateRequest).await;
+                                sender.request_update();
                             }
                         }
                     }
@@ -465,20 +1893,37 @@ impl BarState {
             if let Err(error) = self.process_event().await {
                 match error.block {
                     Some((_, id)) => {
+                        if self.config.error_halts_bar {
+                            return Err(error);
+                        }
+
+                        // A failed block does not hold back its refresh group
+                        let updated = self.refresh_groups.block_updated(id);
                         let block = &mut self.blocks[id].0;
 
-                        if matches!(block.state, BlockState::Error { .. }) {
+                        if matches!(block.state, BlockState::Error { .. })
+                            && block.event_sender.is_none()
+                        {
                             // This should never happen. If this code runs, it cound mean that we
                             // got an error while trying to display and error. We better stop here.
                             return Err(error);
                         }
 
+                        log::error!("{error}");
                         block.abort();
                         block.set_error(self.fullscreen_block == Some(id), error);
                         block.notify_intervals();
 
-                        self.render_block(id)?;
-                        self.render();
+                        let delay = block.restart_delay;
+                        block.restart_delay = (delay * 2).min(MAX_RESTART_DELAY);
+                        let block_id = block.id.clone();
+                        self.restart_timers.push(Box::pin(
+                            tokio::time::sleep(delay).map(move |()| block_id.get()),
+                        ));
+
+                        if self.render_block_and_dependents(id)? || updated {
+                            self.render();
+                        }
                     }
                     None => return Err(error),
                 }
@@ -508,6 +1953,6 @@ fn restart() -> ! {
     }
 
     // Restart
-    nix::unistd::execvp(&exe, &arg).unwrap();
+    nix::unistd::execve(&exe, &arg, &protocol::restart_env()).unwrap();
     unreachable!();
 }
@@ -0,0 +1,516 @@
+//! Migration of configuration files written for older versions
+//!
+//! Options which were renamed or replaced are listed in [`DEPRECATIONS`]. When the bar starts, it
+//! applies them to the configuration in memory and logs a warning for each old option it finds.
+//! `i3status-rs --migrate-config [path]` applies them to the file itself, keeping its formatting
+//! and comments, and prints what changed. Options which cannot be migrated automatically are left
+//! as they are and reported.
+
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml_edit::{value, Array, Document, InlineTable, Item, TableLike, Value};
+
+use crate::errors::*;
+
+/// Where a deprecated option is found
+#[derive(Debug, Clone, Copy)]
+enum Scope {
+    /// A top-level table, e.g. `[theme]`
+    Table(&'static str),
+    /// The blocks of one type
+    Block(&'static str),
+    /// All blocks
+    AnyBlock,
+}
+
+#[derive(Clone, Copy)]
+enum Fix {
+    /// The option was renamed
+    Rename(&'static str),
+    /// The option has no effect anymore
+    Remove,
+    /// The option is replaced by other options of the same table. The function removes the
+    /// option and describes the change, or returns why the option must be migrated by hand.
+    Replace(fn(&mut dyn TableLike) -> Result<String, String>),
+}
+
+struct Deprecation {
+    scope: Scope,
+    key: &'static str,
+    fix: Fix,
+}
+
+/// All renamed and replaced options, in the order they are applied
+const DEPRECATIONS: &[Deprecation] = &[
+    Deprecation {
+        scope: Scope::Table("theme"),
+        key: "name",
+        fix: Fix::Rename("theme"),
+    },
+    Deprecation {
+        scope: Scope::Table("icons"),
+        key: "name",
+        fix: Fix::Rename("icons"),
+    },
+    Deprecation {
+        scope: Scope::AnyBlock,
+        key: "on_click",
+        fix: Fix::Replace(on_click),
+    },
+    // Before `format_mem`, which becomes `format`
+    Deprecation {
+        scope: Scope::Block("memory"),
+        key: "format_swap",
+        fix: Fix::Replace(format_swap),
+    },
+    Deprecation {
+        scope: Scope::Block("memory"),
+        key: "format_mem",
+        fix: Fix::Replace(format_mem),
+    },
+    Deprecation {
+        scope: Scope::Block("memory"),
+        key: "display_type",
+        fix: Fix::Replace(display_type),
+    },
+    Deprecation {
+        scope: Scope::Block("memory"),
+        key: "icons",
+        fix: Fix::Remove,
+    },
+    Deprecation {
+        scope: Scope::Block("memory"),
+        key: "clickable",
+        fix: Fix::Remove,
+    },
+    Deprecation {
+        scope: Scope::Block("temperature"),
+        key: "collapsed",
+        fix: Fix::Replace(collapsed),
+    },
+    Deprecation {
+        scope: Scope::Block("pomodoro"),
+        key: "blocking_cmd",
+        fix: Fix::Remove,
+    },
+];
+
+/// A migrated option, or one which must be migrated by hand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The table of the option, e.g. `[theme]` or `block 2 (memory)`
+    location: String,
+    key: &'static str,
+    description: String,
+    migrated: bool,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: `{}` {}", self.location, self.key, self.description)?;
+        if !self.migrated {
+            f.write_str(" (migrate it by hand)")?;
+        }
+        Ok(())
+    }
+}
+
+impl Deprecation {
+    fn applies_to(&self, block: &str) -> bool {
+        match self.scope {
+            Scope::Table(_) => false,
+            Scope::Block(name) => name == block,
+            Scope::AnyBlock => true,
+        }
+    }
+
+    fn apply(&self, table: &mut dyn TableLike, location: &str) -> Option<Change> {
+        if !table.contains_key(self.key) {
+            return None;
+        }
+        let result = match self.fix {
+            Fix::Rename(new) => rename(table, self.key, new),
+            Fix::Remove => {
+                table.remove(self.key);
+                Ok("has no effect anymore and was removed".into())
+            }
+            Fix::Replace(replace) => replace(table),
+        };
+        Some(Change {
+            location: location.into(),
+            key: self.key,
+            migrated: result.is_ok(),
+            description: result.unwrap_or_else(|reason| reason),
+        })
+    }
+}
+
+/// Apply all deprecations to `doc`
+fn migrate(doc: &mut Document) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    for deprecation in DEPRECATIONS {
+        if let Scope::Table(name) = deprecation.scope {
+            if let Some(table) = doc.get_mut(name).and_then(Item::as_table_like_mut) {
+                changes.extend(deprecation.apply(table, &format!("[{name}]")));
+            }
+        }
+    }
+
+    let blocks: Vec<&mut dyn TableLike> = match doc.get_mut("block") {
+        Some(Item::ArrayOfTables(blocks)) => blocks
+            .iter_mut()
+            .map(|block| block as &mut dyn TableLike)
+            .collect(),
+        Some(Item::Value(Value::Array(blocks))) => blocks
+            .iter_mut()
+            .filter_map(Value::as_inline_table_mut)
+            .map(|block| block as &mut dyn TableLike)
+            .collect(),
+        _ => Vec::new(),
+    };
+    for (i, block) in blocks.into_iter().enumerate() {
+        let name = match block.get("block").and_then(Item::as_str) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let location = format!("block {} ({name})", i + 1);
+        for deprecation in DEPRECATIONS.iter().filter(|d| d.applies_to(&name)) {
+            changes.extend(deprecation.apply(block, &location));
+        }
+    }
+
+    changes
+}
+
+fn rename(table: &mut dyn TableLike, old: &str, new: &str) -> Result<String, String> {
+    if table.contains_key(new) {
+        return Err(format!("was renamed to `{new}`, which is set as well"));
+    }
+    let decor = table.key_decor(old).cloned();
+    let item = table.remove(old).unwrap();
+    table.insert(new, item);
+    if let (Some(decor), Some(new_decor)) = (decor, table.key_decor_mut(new)) {
+        *new_decor = decor;
+    }
+    Ok(format!("was renamed to `{new}`"))
+}
+
+/// `on_click = "<command>"` ran a command on left click
+fn on_click(block: &mut dyn TableLike) -> Result<String, String> {
+    let cmd = block
+        .get("on_click")
+        .and_then(Item::as_str)
+        .ok_or("is not a string")?
+        .to_string();
+    let mut entry = InlineTable::new();
+    entry.insert("button", "left".into());
+    entry.insert("cmd", cmd.into());
+    match block.get_mut("click") {
+        None => {
+            let mut click = Array::new();
+            click.push(entry);
+            block.insert("click", value(click));
+        }
+        Some(Item::ArrayOfTables(click)) => click.push(entry.into_table()),
+        Some(Item::Value(Value::Array(click))) => click.push(entry),
+        Some(_) => return Err("could not be added to `click`".into()),
+    }
+    block.remove("on_click");
+    Ok("was replaced by a `click` entry for the left button".into())
+}
+
+/// `format_mem` was shown first, `format_swap` after a click
+fn format_mem(block: &mut dyn TableLike) -> Result<String, String> {
+    memory_format(block, "format_mem", "format")
+}
+
+fn format_swap(block: &mut dyn TableLike) -> Result<String, String> {
+    memory_format(block, "format_swap", "format_alt")
+}
+
+/// A `format` next to the old options shows memory and swap together, so they are ignored
+fn memory_format(block: &mut dyn TableLike, old: &str, new: &str) -> Result<String, String> {
+    if block.contains_key("format") {
+        return Err(
+            "is deprecated and ignored, since `format` is set as well and may show memory and swap together"
+                .into(),
+        );
+    }
+    rename(block, old, new)
+}
+
+/// `display_type = "swap"` showed swap usage first, which is `format_alt` now
+fn display_type(block: &mut dyn TableLike) -> Result<String, String> {
+    match block.get("display_type").and_then(Item::as_str) {
+        Some("memory") => {
+            block.remove("display_type");
+            Ok("has no effect anymore and was removed".into())
+        }
+        Some("swap") if block.contains_key("format") && block.contains_key("format_alt") => {
+            let format = block.remove("format").unwrap();
+            let format_alt = block.remove("format_alt").unwrap();
+            block.insert("format", format_alt);
+            block.insert("format_alt", format);
+            block.remove("display_type");
+            Ok("was removed and `format` and `format_alt` were swapped".into())
+        }
+        Some("swap") => Err("was removed, set a `format` which shows swap usage instead".into()),
+        _ => Err("is not \"memory\" or \"swap\"".into()),
+    }
+}
+
+/// `collapsed = true` only showed the icon until the block was clicked
+fn collapsed(block: &mut dyn TableLike) -> Result<String, String> {
+    let collapsed = block
+        .get("collapsed")
+        .and_then(Item::as_bool)
+        .ok_or("is not a boolean")?;
+    if block.contains_key("format_alt") {
+        return Err("was replaced by `format_alt`, which is set as well".into());
+    }
+    let description = if collapsed {
+        let format = block
+            .remove("format")
+            .unwrap_or_else(|| value(crate::blocks::temperature::DEFAULT_FORMAT));
+        block.insert("format", value(" $icon "));
+        block.insert("format_alt", format);
+        "was replaced by `format = \" $icon \"`, the previous format is now `format_alt`"
+    } else {
+        block.insert("format_alt", value(" $icon "));
+        "was replaced by `format_alt = \" $icon \"`"
+    };
+    block.remove("collapsed");
+    Ok(description.into())
+}
+
+/// Read a configuration file and migrate old options in memory, logging a warning for each
+pub fn read_config(path: &Path) -> Result<String> {
+    let contents =
+        fs::read_to_string(path).or_error(|| format!("Failed to read file: {}", path.display()))?;
+    // Syntax errors are reported when the configuration is deserialized
+    let Ok(mut doc) = contents.parse::<Document>() else {
+        return Ok(contents);
+    };
+    let changes = migrate(&mut doc);
+    if changes.is_empty() {
+        return Ok(contents);
+    }
+    for change in &changes {
+        log::warn!("Deprecated option in {}: {change}", path.display());
+    }
+    log::warn!(
+        "Run `i3status-rs --migrate-config {}` to update the configuration file",
+        path.display()
+    );
+    Ok(doc.to_string())
+}
+
+/// Migrate the configuration file at `path`. The result is written to `output`, or to `path`
+/// after the original file is copied to `<path>.bak`.
+pub fn migrate_config(path: &Path, output: Option<&Path>) -> Result<()> {
+    let contents =
+        fs::read_to_string(path).or_error(|| format!("Failed to read file: {}", path.display()))?;
+    let mut doc: Document = contents
+        .parse()
+        .or_error(|| format!("Failed to parse {}", path.display()))?;
+    let changes = migrate(&mut doc);
+    if changes.is_empty() {
+        println!("{} does not use any old options", path.display());
+        return Ok(());
+    }
+    let migrated = doc.to_string();
+
+    if migrated != contents {
+        let output = match output {
+            Some(output) => output.to_path_buf(),
+            None => {
+                let mut backup = PathBuf::from(path).into_os_string();
+                backup.push(".bak");
+                fs::copy(path, &backup)
+                    .or_error(|| format!("Failed to create backup {backup:?}"))?;
+                path.to_path_buf()
+            }
+        };
+        fs::write(&output, &migrated)
+            .or_error(|| format!("Failed to write {}", output.display()))?;
+        print!("{}", diff(&contents, &migrated));
+        println!("\nWrote {}", output.display());
+    }
+    for change in &changes {
+        println!("{change}");
+    }
+    Ok(())
+}
+
+/// A line based diff which shows removed lines with `-` and added lines with `+`
+fn diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // `common[i][j]` is the length of the longest common subsequence of `old[i..]` and `new[j..]`
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    let mut in_hunk = false;
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+            in_hunk = false;
+            continue;
+        }
+        if !in_hunk {
+            let _ = writeln!(diff, "@@ line {} @@", i + 1);
+            in_hunk = true;
+        }
+        if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            let _ = writeln!(diff, "-{}", old[i]);
+            i += 1;
+        } else {
+            let _ = writeln!(diff, "+{}", new[j]);
+            j += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    const OLD_CONFIG: &str = r#"
+[theme]
+# The theme
+name = "files/themes/plain"
+
+[icons]
+name = "files/icons/awesome"
+
+[[block]]
+block = "memory"
+format_mem = " $mem_used "
+format_swap = " $swap_used "
+display_type = "swap"
+icons = true
+clickable = false
+
+[[block]]
+block = "temperature"
+collapsed = true
+format = " $max "
+
+[[block]]
+block = "time"
+on_click = "gnome-calendar"
+"#;
+
+    fn migrated(config: &str) -> (Document, Vec<Change>) {
+        let mut doc: Document = config.parse().unwrap();
+        let changes = migrate(&mut doc);
+        (doc, changes)
+    }
+
+    #[test]
+    fn old_config() {
+        let (doc, changes) = migrated(OLD_CONFIG);
+        assert_eq!(changes.len(), 9);
+        assert!(changes.iter().all(|c| c.migrated), "{changes:#?}");
+
+        let migrated = doc.to_string();
+        assert!(migrated.contains("# The theme\ntheme = \"files/themes/plain\""));
+        assert_eq!(doc["block"][0]["format"].as_str(), Some(" $swap_used "));
+        assert_eq!(doc["block"][0]["format_alt"].as_str(), Some(" $mem_used "));
+        assert!(doc["block"][0].get("icons").is_none());
+        assert_eq!(doc["block"][1]["format"].as_str(), Some(" $icon "));
+        assert_eq!(doc["block"][1]["format_alt"].as_str(), Some(" $max "));
+        assert_eq!(
+            doc["block"][2]["click"][0]["cmd"].as_str(),
+            Some("gnome-calendar")
+        );
+
+        // The result is a current configuration
+        toml::from_str::<Config>(&migrated).unwrap();
+        let (_, changes) = self::migrated(&migrated);
+        assert_eq!(changes, []);
+    }
+
+    #[test]
+    fn manual() {
+        let config = r#"
+[[block]]
+block = "memory"
+display_type = "swap"
+format_mem = " $mem_used "
+format = " $mem_avail "
+"#;
+        let (doc, changes) = migrated(config);
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|c| !c.migrated));
+        assert_eq!(doc.to_string(), config);
+        assert_eq!(
+            changes[0].to_string(),
+            "block 1 (memory): `format_mem` is deprecated and ignored, since `format` is set as well and may show memory and swap together (migrate it by hand)"
+        );
+
+        // Only `format_swap` next to `format` is ignored as well
+        let (_, changes) = migrated(
+            r#"
+[[block]]
+block = "memory"
+format = " $mem_used_percents $swap_used_percents "
+format_swap = " $swap_used "
+"#,
+        );
+        assert_eq!(changes.len(), 1);
+        assert!(!changes[0].migrated);
+    }
+
+    #[test]
+    fn on_click_with_click() {
+        let (doc, changes) = migrated(
+            r#"
+[[block]]
+block = "time"
+on_click = "gnome-calendar"
+[[block.click]]
+button = "right"
+cmd = "date | xclip"
+"#,
+        );
+        assert!(changes[0].migrated);
+        let click = doc["block"][0]["click"].as_array_of_tables().unwrap();
+        assert_eq!(click.len(), 2);
+        assert_eq!(click.get(1).unwrap()["button"].as_str(), Some("left"));
+
+        let (doc, _) = migrated(
+            r#"block = [{ block = "time", on_click = "cal", click = [{ button = "right", cmd = "x" }] }]"#,
+        );
+        assert_eq!(
+            doc.to_string(),
+            r#"block = [{ block = "time", click = [{ button = "right", cmd = "x" }, { button = "left", cmd = "cal" }] }]
+"#
+        );
+    }
+
+    #[test]
+    fn line_diff() {
+        assert_eq!(diff("a\nb\nc\n", "a\nb\nc\n"), "");
+        assert_eq!(
+            diff("a\nname = 1\nc\nd\n", "a\nc\nd\ntheme = 1\n"),
+            "@@ line 2 @@\n-name = 1\n@@ line 5 @@\n+theme = 1\n"
+        );
+    }
+}
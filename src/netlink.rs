@@ -10,6 +10,9 @@ use regex::Regex;
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::ops;
 use std::path::Path;
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
 
 use crate::errors::*;
 use crate::util;
@@ -257,6 +260,86 @@ macro_rules! recv_until_done {
     };
 }
 
+/// Notifications of changes of IP addresses and default routes
+pub struct AddressChanges {
+    sock: NlSocket,
+    buf: Vec<u8>,
+}
+
+impl AddressChanges {
+    pub fn new() -> Result<Self> {
+        let groups = [
+            libc::RTNLGRP_IPV4_IFADDR,
+            libc::RTNLGRP_IPV6_IFADDR,
+            libc::RTNLGRP_IPV4_ROUTE,
+            libc::RTNLGRP_IPV6_ROUTE,
+        ];
+        let sock = NlSocket::new(
+            NlSocketHandle::connect(NlFamily::Route, None, &groups).error("Socket error")?,
+        )
+        .error("Socket error")?;
+        Ok(Self {
+            sock,
+            buf: vec![0; 64 * 1024],
+        })
+    }
+
+    /// Wait for a change, and then until there were no more changes for `quiet`, since a new
+    /// connection changes addresses and routes several times
+    pub async fn settled(&mut self, quiet: Duration) -> Result<()> {
+        self.changed().await?;
+        loop {
+            match tokio::time::timeout(quiet, self.changed()).await {
+                Ok(changed) => changed?,
+                Err(_elapsed) => return Ok(()),
+            }
+        }
+    }
+
+    /// Wait for the next change
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancellation safe.
+    pub async fn changed(&mut self) -> Result<()> {
+        loop {
+            let len = self
+                .sock
+                .read(&mut self.buf)
+                .await
+                .error("Failed to read from netlink socket")?;
+            if is_address_change(&self.buf[..len]) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Whether the netlink messages in `buf` add or remove an address or a default route. Other
+/// routes change often, e.g. when containers start, and do not change the way out.
+fn is_address_change(mut buf: &[u8]) -> bool {
+    // struct nlmsghdr
+    const HEADER_LEN: usize = 16;
+    while buf.len() >= HEADER_LEN {
+        let len = u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize;
+        let kind = u16::from_ne_bytes(buf[4..6].try_into().unwrap());
+        if len < HEADER_LEN || len > buf.len() {
+            break;
+        }
+        match kind {
+            libc::RTM_NEWADDR | libc::RTM_DELADDR => return true,
+            // The second byte of struct rtmsg is the length of the destination prefix
+            libc::RTM_NEWROUTE | libc::RTM_DELROUTE if buf.get(HEADER_LEN + 1) == Some(&0) => {
+                return true
+            }
+            _ => (),
+        }
+        // Messages are aligned to 4 bytes
+        buf = buf.get((len + 3) & !3..).unwrap_or_default();
+    }
+    false
+}
+
 async fn get_interfaces(
     sock: &mut NlSocket,
 ) -> Result<Vec<Interface>, Box<dyn StdError + Send + Sync + 'static>> {
@@ -412,3 +495,40 @@ async fn ipv6(sock: &mut NlSocket, ifa_index: i32) -> Result<Option<Ipv6Addr>> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A netlink message of `kind` with `payload`, padded to 4 bytes
+    fn message(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let len = 16 + payload.len();
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&(len as u32).to_ne_bytes());
+        msg.extend_from_slice(&kind.to_ne_bytes());
+        msg.extend_from_slice(&[0; 10]);
+        msg.extend_from_slice(payload);
+        msg.resize((len + 3) & !3, 0);
+        msg
+    }
+
+    #[test]
+    fn address_changes() {
+        assert!(is_address_change(&message(libc::RTM_NEWADDR, &[2, 24])));
+        assert!(is_address_change(&message(libc::RTM_DELADDR, &[])));
+        // Default routes, but not other routes
+        assert!(is_address_change(&message(libc::RTM_NEWROUTE, &[2, 0, 0])));
+        assert!(!is_address_change(&message(
+            libc::RTM_DELROUTE,
+            &[2, 24, 0]
+        )));
+        assert!(!is_address_change(&message(libc::RTM_NEWLINK, &[0; 16])));
+        // Any message of a batch
+        let mut batch = message(libc::RTM_NEWROUTE, &[10, 64, 0]);
+        batch.extend(message(libc::RTM_NEWADDR, &[10, 64, 0]));
+        assert!(is_address_change(&batch));
+        // Truncated messages are ignored
+        assert!(!is_address_change(&batch[..10]));
+        assert!(!is_address_change(&[]));
+    }
+}
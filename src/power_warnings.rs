@@ -0,0 +1,343 @@
+//! Warnings about a low battery, shown in front of all blocks
+//!
+//! When the top-level `[power_warnings]` table is present, the bar follows the warning level which
+//! UPower computes for the battery of the machine (its "display device"), whether or not there is
+//! a `battery` block. While the level is low or critical and the machine runs on battery, a
+//! warning with the charge of the battery is shown in front of all blocks. It is shown like the
+//! error of a block, using `error_format`, in the critical state.
+//!
+//! A click on the warning dismisses it. A dismissed warning is shown again if the level goes from
+//! low to critical, and once the level clears, e.g. when AC is plugged in, the next warning is
+//! shown as usual.
+//!
+//! The warning is not counted in `width_budget`.
+//!
+//! # Configuration
+//!
+//! Key             | Values                                                 | Default
+//! ----------------|--------------------------------------------------------|--------
+//! `only_critical` | Whether to only warn once the level is critical        | `false`
+//!
+//! The message of the warning has the following placeholders of `error_format`:
+//!
+//! Placeholder           | Value
+//! ----------------------|------
+//! `short_error_message` | The charge of the battery, e.g. `Battery 8%`
+//! `full_error_message`  | The level and the charge, e.g. `Battery critical: 8%`
+//!
+//! # Example
+//!
+//! ```toml
+//! [power_warnings]
+//! only_critical = true
+//! ```
+
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::blocks::battery::upower::{DeviceProxy, UPowerProxy, DISPLAY_DEVICE};
+use crate::errors::*;
+use crate::formatting::value::Value;
+use crate::formatting::Format;
+use crate::util::new_system_dbus_connection;
+use crate::widget::{State, Widget};
+use crate::BoxedStream;
+
+/// The block ID of the warning in the instances of its segments. No block of the configuration
+/// file has this ID, so clicks on the warning can be told apart.
+pub const BLOCK_ID: usize = usize::MAX;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub only_critical: bool,
+}
+
+/// The warning level of a battery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    None,
+    Low,
+    Critical,
+}
+
+impl Level {
+    /// https://upower.freedesktop.org/docs/Device.html#Device:WarningLevel
+    fn from_upower(level: u32) -> Self {
+        match level {
+            3 => Self::Low,
+            // Critical and "action", when the machine is about to shut down
+            4 | 5 => Self::Critical,
+            _ => Self::None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Low => "low",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+/// A change of one of the properties of UPower
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Update {
+    Level(Level),
+    Percentage(f64),
+    OnBattery(bool),
+}
+
+/// Decides when the warning is shown
+#[derive(Debug)]
+pub struct PowerWarnings {
+    threshold: Level,
+    level: Level,
+    percentage: f64,
+    on_battery: bool,
+    /// The level of the warning which was dismissed, or `Level::None`
+    dismissed: Level,
+}
+
+impl PowerWarnings {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            threshold: if config.only_critical {
+                Level::Critical
+            } else {
+                Level::Low
+            },
+            level: Level::None,
+            percentage: 0.0,
+            // Until UPower tells otherwise, trust its warning level
+            on_battery: true,
+            dismissed: Level::None,
+        }
+    }
+
+    /// Apply an update. Returns whether the warning changed.
+    pub fn update(&mut self, update: Update) -> bool {
+        let before = self.warning();
+        match update {
+            Update::Level(level) => self.level = level,
+            Update::Percentage(percentage) => self.percentage = percentage,
+            Update::OnBattery(on_battery) => self.on_battery = on_battery,
+        }
+        if self.active_level() == Level::None {
+            self.dismissed = Level::None;
+        }
+        self.warning() != before
+    }
+
+    /// Hide the warning until the level rises or clears. Returns whether the warning changed.
+    pub fn dismiss(&mut self) -> bool {
+        let before = self.warning();
+        self.dismissed = self.active_level();
+        self.warning() != before
+    }
+
+    /// The level and percentage of the warning to show, if any
+    pub fn warning(&self) -> Option<(Level, f64)> {
+        let level = self.active_level();
+        (level > self.dismissed).then_some((level, self.percentage))
+    }
+
+    /// The widget showing the warning, if any, in the format of errors
+    pub fn widget(&self, error_format: Format) -> Option<Widget> {
+        let (level, percentage) = self.warning()?;
+        let mut widget = Widget::new()
+            .with_state(State::Critical)
+            .with_format(error_format);
+        widget.set_values(map! {
+            "full_error_message" => Value::text(format!("Battery {}: {percentage:.0}%", level.name())),
+            "short_error_message" => Value::text(format!("Battery {percentage:.0}%")),
+        });
+        Some(widget)
+    }
+
+    fn active_level(&self) -> Level {
+        if self.on_battery && self.level >= self.threshold {
+            self.level
+        } else {
+            Level::None
+        }
+    }
+}
+
+/// Follow UPower's display device. Failures are logged, after which the stream ends.
+pub fn updates() -> BoxedStream<Update> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        if let Err(error) = follow_upower(&sender).await {
+            log::warn!("Cannot follow the battery's warning level: {error}");
+        }
+    });
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        let update = receiver.recv().await?;
+        Some((update, receiver))
+    })
+    .boxed_local()
+}
+
+async fn follow_upower(sender: &mpsc::UnboundedSender<Update>) -> Result<()> {
+    let dbus_conn = new_system_dbus_connection().await?;
+    let device = DeviceProxy::builder(&dbus_conn)
+        .path(DISPLAY_DEVICE)
+        .unwrap()
+        .build()
+        .await
+        .error("Failed to create DeviceProxy")?;
+    let upower = UPowerProxy::new(&dbus_conn)
+        .await
+        .error("Failed to create UPowerProxy")?;
+
+    let mut levels = device.receive_warning_level_changed().await;
+    let mut percentages = device.receive_percentage_changed().await;
+    let mut on_battery = upower.receive_on_battery_changed().await;
+
+    let initial = [
+        Update::Level(Level::from_upower(
+            device
+                .warning_level()
+                .await
+                .error("Failed to get warning level")?,
+        )),
+        Update::Percentage(
+            device
+                .percentage()
+                .await
+                .error("Failed to get percentage")?,
+        ),
+        Update::OnBattery(
+            upower
+                .on_battery()
+                .await
+                .error("Failed to get whether on battery")?,
+        ),
+    ];
+    for update in initial {
+        if sender.send(update).is_err() {
+            return Ok(());
+        }
+    }
+
+    loop {
+        let update = tokio::select! {
+            Some(change) = levels.next() => {
+                let level = change.get().await.error("Failed to get warning level")?;
+                Update::Level(Level::from_upower(level))
+            }
+            Some(change) = percentages.next() => {
+                Update::Percentage(change.get().await.error("Failed to get percentage")?)
+            }
+            Some(change) = on_battery.next() => {
+                Update::OnBattery(change.get().await.error("Failed to get whether on battery")?)
+            }
+            else => return Err(Error::new("UPower went away")),
+        };
+        // The bar stopped
+        if sender.send(update).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warnings(config: Config, updates: &[Update]) -> PowerWarnings {
+        let mut warnings = PowerWarnings::new(&config);
+        for update in updates {
+            warnings.update(*update);
+        }
+        warnings
+    }
+
+    #[test]
+    fn appear() {
+        let mut w = warnings(Config::default(), &[Update::Percentage(30.0)]);
+        assert_eq!(w.warning(), None);
+        assert!(w.update(Update::Level(Level::Low)));
+        assert_eq!(w.warning(), Some((Level::Low, 30.0)));
+        assert!(w.update(Update::Percentage(29.0)));
+        assert!(w.update(Update::Level(Level::Critical)));
+        assert_eq!(w.warning(), Some((Level::Critical, 29.0)));
+        // UPower's codes
+        assert!(!w.update(Update::Level(Level::from_upower(5))));
+
+        let mut w = warnings(
+            Config {
+                only_critical: true,
+            },
+            &[Update::Percentage(12.0), Update::Level(Level::Low)],
+        );
+        assert_eq!(w.warning(), None);
+        assert!(w.update(Update::Level(Level::from_upower(4))));
+        assert_eq!(w.warning(), Some((Level::Critical, 12.0)));
+    }
+
+    #[test]
+    fn dismiss() {
+        let mut w = warnings(
+            Config::default(),
+            &[Update::Percentage(10.0), Update::Level(Level::Low)],
+        );
+        assert!(w.dismiss());
+        assert_eq!(w.warning(), None);
+        // It stays dismissed while the level stays
+        assert!(!w.update(Update::Percentage(9.0)));
+        assert!(!w.dismiss());
+        // But not once the level rises
+        assert!(w.update(Update::Level(Level::Critical)));
+        assert_eq!(w.warning(), Some((Level::Critical, 9.0)));
+        assert!(w.dismiss());
+        assert!(!w.update(Update::Level(Level::Low)));
+        assert_eq!(w.warning(), None);
+    }
+
+    #[test]
+    fn clear() {
+        let mut w = warnings(
+            Config::default(),
+            &[Update::Percentage(10.0), Update::Level(Level::Low)],
+        );
+        assert!(w.update(Update::Level(Level::None)));
+        assert_eq!(w.warning(), None);
+
+        // AC attaches
+        assert!(w.update(Update::Level(Level::Low)));
+        assert!(w.update(Update::OnBattery(false)));
+        assert_eq!(w.warning(), None);
+        assert!(w.update(Update::OnBattery(true)));
+
+        // A dismissed warning is shown again after the level cleared
+        w.dismiss();
+        assert!(!w.update(Update::OnBattery(false)));
+        assert!(w.update(Update::OnBattery(true)));
+        assert_eq!(w.warning(), Some((Level::Low, 10.0)));
+        w.dismiss();
+        w.update(Update::Level(Level::None));
+        assert!(w.update(Update::Level(Level::Low)));
+    }
+
+    #[test]
+    fn widget() {
+        let w = warnings(
+            Config::default(),
+            &[Update::Percentage(7.6), Update::Level(Level::Critical)],
+        );
+        let format = crate::formatting::config::Config::default()
+            .with_default(" $full_error_message ")
+            .unwrap();
+        let widget = w.widget(format).unwrap();
+        assert_eq!(widget.state, State::Critical);
+        let data = widget
+            .get_data(&crate::config::SharedConfig::default(), BLOCK_ID)
+            .unwrap();
+        assert_eq!(data[0].full_text, " Battery critical: 8% ");
+        assert_eq!(data[0].instance, format!("{BLOCK_ID}:"));
+    }
+}
@@ -0,0 +1,144 @@
+//! Showing the blocks in another order, e.g. while presenting
+//!
+//! The order is set over DBus (see [`control`](crate::control)) with `SetOrder`, which takes a list
+//! of block identifiers (e.g. `time-0`). The listed blocks come first, in that order, followed by
+//! the other blocks in their configured order. Unknown identifiers are ignored with a warning.
+//! `ResetOrder` restores the configured order, and `GetStatus` includes the current one. Clicks
+//! are not affected, since they are routed to blocks by their IDs and not by their positions.
+//!
+//! If `persist` is set, the order is kept in `$XDG_STATE_HOME/i3status-rust/order` (or
+//! `order.<name>` with `--dbus-name <name>`), so that it survives restarts.
+//!
+//! # Configuration
+//!
+//! Key       | Values                                            | Default
+//! ----------|---------------------------------------------------|--------
+//! `persist` | Whether the order set over DBus survives restarts | `false`
+//!
+//! # Example
+//!
+//! ```toml
+//! [presentation]
+//! persist = true
+//! ```
+//!
+//! Then `busctl --user call rs.i3status / rs.i3status.bar SetOrder as 2 time-0 battery-0` moves
+//! the time and the battery to the left, until `ResetOrder` is called.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub persist: bool,
+}
+
+/// The file which keeps the order, one identifier per line
+fn state_file() -> Option<PathBuf> {
+    let mut path = dirs::state_dir()?;
+    path.push("i3status-rust");
+    path.push(match std::env::var("I3RS_DBUS_NAME") {
+        Ok(name) => format!("order.{name}"),
+        Err(_) => "order".into(),
+    });
+    Some(path)
+}
+
+/// The order which was saved by [`save_order`], if any
+pub async fn load_order() -> Option<Vec<String>> {
+    read(&state_file()?).await
+}
+
+/// Keep `order` for the next start, or forget the saved order if it is `None`
+pub async fn save_order(order: Option<&[String]>) {
+    let path = match state_file() {
+        Some(path) => path,
+        None => {
+            log::warn!("Cannot save the order of the blocks: no state directory");
+            return;
+        }
+    };
+    let result = match order {
+        Some(order) => write(&path, order).await,
+        None => match tokio::fs::remove_file(&path).await {
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        },
+    };
+    if let Err(error) = result {
+        log::warn!("Cannot save the order of the blocks to {path:?}: {error}");
+    }
+}
+
+async fn read(path: &Path) -> Option<Vec<String>> {
+    let contents = tokio::fs::read_to_string(path).await.ok()?;
+    Some(contents.lines().map(Into::into).collect())
+}
+
+async fn write(path: &Path, order: &[String]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    tokio::fs::write(path, order.join("\n")).await
+}
+
+/// The IDs of the blocks in the order in which they are shown. `order` are block identifiers,
+/// `block_ids` maps all identifiers to block IDs, and there are `len` blocks.
+pub fn display_order(
+    order: &[String],
+    block_ids: &HashMap<String, usize>,
+    len: usize,
+) -> Vec<usize> {
+    let mut ids = Vec::with_capacity(len);
+    for identifier in order {
+        match block_ids.get(identifier) {
+            Some(id) if !ids.contains(id) => ids.push(*id),
+            Some(_) => (),
+            None => log::warn!("Cannot reorder block '{identifier}': no such block"),
+        }
+    }
+    let listed = ids.clone();
+    ids.extend((0..len).filter(|id| !listed.contains(id)));
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order() {
+        let names = ["cpu", "sound", "time", "sound", "battery"];
+        let block_ids = crate::imports::block_ids(&names);
+        let order = |order: &[&str]| {
+            let order: Vec<String> = order.iter().map(|x| x.to_string()).collect();
+            display_order(&order, &block_ids, names.len())
+        };
+        assert_eq!(order(&[]), [0, 1, 2, 3, 4]);
+        assert_eq!(order(&["time-0", "sound-1"]), [2, 3, 0, 1, 4]);
+        // Unknown and repeated identifiers are ignored
+        assert_eq!(
+            order(&["battery-0", "time-1", "battery-0"]),
+            [4, 0, 1, 2, 3]
+        );
+        // Only identifiers, not names
+        assert_eq!(order(&["sound"]), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn persisted_order() {
+        let path = std::env::temp_dir()
+            .join(format!("i3rs-order-{}", std::process::id()))
+            .join("order");
+        let order = vec!["time-0".to_string(), "sound-1".to_string()];
+        tokio_test::block_on(async {
+            write(&path, &order).await.unwrap();
+            assert_eq!(read(&path).await, Some(order));
+            std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+            assert_eq!(read(&path).await, None);
+        });
+    }
+}
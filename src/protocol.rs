@@ -1,7 +1,14 @@
 pub mod i3bar_block;
 pub mod i3bar_event;
+pub mod sway;
+pub mod xroot;
 
 use std::borrow::Borrow;
+use std::ffi::{CString, OsStr, OsString};
+use std::os::unix::ffi::OsStringExt;
+use std::sync::Once;
+
+use once_cell::sync::Lazy;
 
 use crate::config::SharedConfig;
 use crate::themes::color::Color;
@@ -9,43 +16,210 @@ use crate::themes::separator::Separator;
 use crate::RenderedBlock;
 
 use i3bar_block::I3BarBlock;
+use xroot::XRoot;
+
+/// The value of `--output-format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// The JSON protocol of i3bar and swaybar on stdout
+    I3bar,
+    /// The name of the X root window, e.g. for dwm
+    Xroot,
+}
+
+/// Where the blocks are printed
+#[derive(Debug)]
+pub enum Output {
+    I3bar {
+        /// Remove all colors and borders, leaving only the text
+        no_color: bool,
+        /// Whether the bar is asked for click events, see [`Header::click_events`]
+        click_events: bool,
+    },
+    XRoot(XRoot),
+}
+
+impl Output {
+    pub fn print<B>(&self, blocks: &[B], config: &SharedConfig)
+    where
+        B: Borrow<RenderedBlock>,
+    {
+        match self {
+            Self::I3bar { no_color, .. } => print_blocks(blocks, config, *no_color),
+            Self::XRoot(xroot) => xroot.print(blocks, config),
+        }
+    }
+
+    /// Whether click events are read from stdin
+    pub fn has_clicks(&self) -> bool {
+        matches!(self, Self::I3bar { click_events, .. } if *click_events)
+    }
+}
+
+/// Set when restarting in place, since the header was already printed to the same stdout
+const RESTART_MARKER: &str = "I3RS_HEADER_PRINTED";
+
+/// The header of i3bar's protocol, see
+/// https://i3wm.org/docs/i3bar-protocol.html#_header_in_detail
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub version: u32,
+    /// Whether the bar sends click events on stdin
+    pub click_events: bool,
+    /// Ask the bar not to stop the process when the bar is hidden
+    pub never_pause: bool,
+    /// Fields for extensions of bars, set with `--header-json`. They override the other fields.
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self {
+            version: 1,
+            click_events: true,
+            never_pause: false,
+            extra: serde_json::Map::new(),
+        }
+    }
+}
+
+impl Header {
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut header = serde_json::Map::new();
+        header.insert("version".into(), self.version.into());
+        if self.click_events {
+            header.insert("click_events".into(), true.into());
+        }
+        if self.never_pause {
+            header.insert("stop_signal".into(), 0.into());
+        }
+        header.extend(self.extra.clone());
+        header.into()
+    }
+}
+
+/// Print the header, unless it was already printed, by this process or by the process which
+/// restarted in place into this one
+pub fn init(header: &Header) {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        if !take_restart_marker() {
+            println!("{}\n[", header.to_json());
+        }
+    });
+}
+
+/// Whether this process was restarted in place, after the header was printed. The marker is
+/// removed, so that commands started by blocks do not inherit it.
+pub fn take_restart_marker() -> bool {
+    static RESTARTED: Lazy<bool> = Lazy::new(|| {
+        let restarted = is_restart_marker(std::env::var_os(RESTART_MARKER).as_deref());
+        std::env::remove_var(RESTART_MARKER);
+        restarted
+    });
+    *RESTARTED
+}
 
-pub fn init(never_pause: bool) {
-    if never_pause {
-        println!("{{\"version\": 1, \"click_events\": true, \"stop_signal\": 0}}\n[");
-    } else {
-        println!("{{\"version\": 1, \"click_events\": true}}\n[");
+/// Whether `value` of the variable `RESTART_MARKER` marks a restart in place
+fn is_restart_marker(value: Option<&OsStr>) -> bool {
+    value.is_some_and(|value| value == "1")
+}
+
+/// The environment of the process which replaces this one when restarting in place. It has the
+/// restart marker, so that the header is not printed again even if `--no-init` is lost. The
+/// environment of this process is not changed, since other threads may read it.
+pub fn restart_env() -> Vec<CString> {
+    with_restart_marker(std::env::vars_os())
+}
+
+fn with_restart_marker(vars: impl Iterator<Item = (OsString, OsString)>) -> Vec<CString> {
+    vars.filter(|(key, _)| key != RESTART_MARKER)
+        .chain([(RESTART_MARKER.into(), "1".into())])
+        .filter_map(|(key, value)| {
+            let mut var = key.into_vec();
+            var.push(b'=');
+            var.extend(value.into_vec());
+            CString::new(var).ok()
+        })
+        .collect()
+}
+
+/// Print the blocks. If `no_color` is set, all colors and borders are removed, leaving only the
+/// text.
+pub fn print_blocks<B>(blocks: &[B], config: &SharedConfig, no_color: bool)
+where
+    B: Borrow<RenderedBlock>,
+{
+    let mut rendered_blocks = render_blocks(blocks, config);
+    if no_color {
+        rendered_blocks
+            .iter_mut()
+            .for_each(I3BarBlock::strip_colors);
     }
+    println!("{},", serde_json::to_string(&rendered_blocks).unwrap());
 }
 
-pub fn print_blocks<B>(blocks: &[B], config: &SharedConfig)
+/// Apply alternating tints and add separators. Spacers are left out of both, and no separator is
+/// added on either side of them. A block with `merge_with_next` is not separated from the next
+/// visible block, and both are tinted alike. If there is no such block, or it is a spacer, the
+/// block is separated as usual.
+fn render_blocks<B>(blocks: &[B], config: &SharedConfig) -> Vec<I3BarBlock>
 where
     B: Borrow<RenderedBlock>,
 {
     let mut last_bg = Color::None;
-    let mut rendered_blocks = vec![];
+    let mut rendered_blocks: Vec<I3BarBlock> = vec![];
 
-    // The right most block should never be alternated
-    let mut alt = blocks
+    let visible: Vec<&RenderedBlock> = blocks
         .iter()
         .map(|x| x.borrow())
-        .filter(|x| !x.segments.is_empty() && !x.merge_with_next)
+        .filter(|x| !x.segments.is_empty())
+        .collect();
+    let merges: Vec<bool> = visible
+        .iter()
+        .enumerate()
+        .map(|(i, block)| {
+            block.merge_with_next
+                && !block.spacer
+                && visible.get(i + 1).is_some_and(|next| !next.spacer)
+        })
+        .collect();
+
+    // The right most block should never be alternated
+    let mut alt = visible
+        .iter()
+        .zip(&merges)
+        .filter(|(x, merge_with_next)| !**merge_with_next && !x.spacer)
         .count()
         % 2
         == 0;
 
     let mut logical_block_i = 0;
+    // Whether the previous visible block is a spacer
+    let mut after_spacer = false;
+    // Whether the previous visible block is merged with this one
+    let mut merge_with_previous = false;
 
-    for widgets in blocks
-        .iter()
-        .map(|x| x.borrow())
-        .filter(|x| !x.segments.is_empty())
-        .cloned()
-    {
-        let RenderedBlock {
-            mut segments,
-            merge_with_next,
-        } = widgets;
+    for (block, merge_with_next) in visible.into_iter().zip(merges) {
+        let mut segments = block.segments.clone();
+
+        if block.spacer {
+            for segment in &mut segments {
+                segment.name = Some(logical_block_i.to_string());
+            }
+            // Remove the native separator of the previous block
+            if let Some(previous) = rendered_blocks.last_mut() {
+                if config.theme.separator == Separator::Native {
+                    previous.separator = Some(false);
+                    previous.separator_block_width = Some(0);
+                }
+            }
+            rendered_blocks.extend(segments);
+            logical_block_i += 1;
+            after_spacer = true;
+            merge_with_previous = false;
+            continue;
+        }
 
         for segment in &mut segments {
             segment.name = Some(logical_block_i.to_string());
@@ -62,8 +236,9 @@ where
             alt = !alt;
         }
 
-        if !merge_with_next {
-            if let Separator::Custom(separator) = &config.theme.separator {
+        if let Separator::Custom(separator) = &config.theme.separator {
+            // Custom separators are put in front of blocks
+            if !merge_with_previous && !after_spacer {
                 // The first widget's BG is used to get the FG color for the current separator
                 let sep_fg = if config.theme.separator_fg == Color::Auto {
                     segments.first().unwrap().background
@@ -78,21 +253,24 @@ where
                     config.theme.separator_bg
                 };
 
-                // The last widget's BG is used to get the BG color for the next separator
-                last_bg = segments.last().unwrap().background;
-
-                let separator = I3BarBlock {
+                rendered_blocks.push(I3BarBlock {
                     full_text: separator.clone(),
                     background: sep_bg,
                     color: sep_fg,
                     ..Default::default()
-                };
-
-                rendered_blocks.push(separator);
+                });
+            }
+            // The last widget's BG is used to get the BG color for the next separator
+            last_bg = segments.last().unwrap().background;
+        } else {
+            let last = segments.last_mut().unwrap();
+            if merge_with_next {
+                last.separator = Some(false);
+                last.separator_block_width = Some(0);
             } else {
                 // Re-add native separator on last widget for native theme
-                segments.last_mut().unwrap().separator = None;
-                segments.last_mut().unwrap().separator_block_width = None;
+                last.separator = None;
+                last.separator_block_width = None;
             }
         }
 
@@ -101,6 +279,8 @@ where
         if !merge_with_next {
             logical_block_i += 1;
         }
+        after_spacer = false;
+        merge_with_previous = merge_with_next;
     }
 
     if let Separator::Custom(end_separator) = &config.theme.end_separator {
@@ -112,5 +292,304 @@ where
         });
     }
 
-    println!("{},", serde_json::to_string(&rendered_blocks).unwrap());
+    rendered_blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::themes::color::Rgba;
+    use std::sync::Arc;
+
+    #[test]
+    fn restart_marker() {
+        assert!(is_restart_marker(Some(OsStr::new("1"))));
+        assert!(!is_restart_marker(Some(OsStr::new(""))));
+        assert!(!is_restart_marker(None));
+
+        let env = with_restart_marker(
+            [
+                ("PATH".into(), "/bin".into()),
+                (RESTART_MARKER.into(), "0".into()),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(
+            env,
+            [
+                CString::new("PATH=/bin").unwrap(),
+                CString::new(format!("{RESTART_MARKER}=1")).unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn header() {
+        use serde_json::json;
+        assert_eq!(
+            Header::default().to_json(),
+            json!({"version": 1, "click_events": true})
+        );
+        let header = Header {
+            version: 2,
+            click_events: false,
+            never_pause: true,
+            ..Default::default()
+        };
+        assert_eq!(header.to_json(), json!({"version": 2, "stop_signal": 0}));
+        let extra = serde_json::from_str(r#"{"cont_signal": 18, "version": 3}"#).unwrap();
+        let header = Header {
+            extra,
+            ..Default::default()
+        };
+        // User fields override the others
+        assert_eq!(
+            header.to_json(),
+            json!({"version": 3, "click_events": true, "cont_signal": 18})
+        );
+    }
+
+    #[test]
+    fn strip_colors() {
+        let mut config = SharedConfig::default();
+        let theme = Arc::make_mut(&mut config.theme);
+        theme.separator = Separator::Custom("<".into());
+        theme.alternating_tint_bg = Color::Rgba(Rgba::new(16, 16, 16, 0));
+        let block = |text: &str, background: Color| RenderedBlock {
+            segments: vec![I3BarBlock {
+                full_text: text.into(),
+                background,
+                color: Color::Rgba(Rgba::new(255, 255, 255, 255)),
+                border: Some("#ff0000".into()),
+                border_top: Some(1),
+                urgent: Some(true),
+                ..Default::default()
+            }],
+            merge_with_next: false,
+            spacer: false,
+            ..Default::default()
+        };
+        let frame = [
+            block("a", Color::Rgba(Rgba::new(255, 0, 0, 255))),
+            block("b", Color::None),
+        ];
+
+        let normal = render_blocks(&frame, &config);
+        let mut stripped = normal.clone();
+        stripped.iter_mut().for_each(I3BarBlock::strip_colors);
+
+        let normal = serde_json::to_value(&normal).unwrap();
+        let stripped = serde_json::to_value(&stripped).unwrap();
+        let normal = normal.as_array().unwrap();
+        let stripped = stripped.as_array().unwrap();
+        assert_eq!(normal.len(), stripped.len());
+        for (normal, stripped) in normal.iter().zip(stripped) {
+            let mut expected = normal.as_object().unwrap().clone();
+            for key in [
+                "color",
+                "background",
+                "border",
+                "border_top",
+                "border_right",
+                "border_bottom",
+                "border_left",
+            ] {
+                expected.remove(key);
+            }
+            assert_eq!(stripped.as_object().unwrap(), &expected);
+        }
+        assert!(normal.iter().any(|block| block.get("background").is_some()));
+    }
+
+    fn rendered(text: &str, spacer: bool) -> RenderedBlock {
+        RenderedBlock {
+            segments: vec![I3BarBlock {
+                full_text: text.into(),
+                background: Color::Rgba(Rgba::new(0, 0, 255, 255)),
+                separator: if spacer { Some(false) } else { None },
+                ..Default::default()
+            }],
+            merge_with_next: false,
+            spacer,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn spacers_native() {
+        let config = SharedConfig::default();
+        let frame = [
+            rendered("a", false),
+            rendered(" ", true),
+            rendered("b", false),
+            rendered("c", false),
+        ];
+        let blocks = render_blocks(&frame, &config);
+        let separators: Vec<_> = blocks.iter().map(|b| b.separator).collect();
+        // Only the block which is not next to the spacer keeps its native separator
+        assert_eq!(separators, [Some(false), Some(false), None, None]);
+        assert_eq!(blocks[0].separator_block_width, Some(0));
+    }
+
+    #[test]
+    fn spacers_custom() {
+        let mut config = SharedConfig::default();
+        let theme = Arc::make_mut(&mut config.theme);
+        theme.separator = Separator::Custom("<".into());
+        theme.alternating_tint_bg = Color::Rgba(Rgba::new(16, 16, 16, 0));
+        let frame = [
+            rendered("a", false),
+            rendered(" ", true),
+            rendered("b", false),
+            rendered("c", false),
+        ];
+        let texts: Vec<_> = render_blocks(&frame, &config)
+            .into_iter()
+            .map(|b| b.full_text)
+            .collect();
+        assert_eq!(texts, ["<", "a", " ", "b", "<", "c"]);
+
+        // The spacer is neither tinted nor counted when alternating, and the last block is never tinted
+        let blocks = render_blocks(&frame, &config);
+        let tinted = Color::Rgba(Rgba::new(16, 16, 255, 255));
+        let plain = Color::Rgba(Rgba::new(0, 0, 255, 255));
+        let backgrounds: Vec<_> = blocks
+            .iter()
+            .filter(|b| b.full_text != "<")
+            .map(|b| b.background)
+            .collect();
+        assert_eq!(backgrounds, [plain, plain, tinted, plain]);
+
+        // Hidden neighbours are skipped
+        let frame = [
+            rendered("a", false),
+            RenderedBlock {
+                segments: Vec::new(),
+                merge_with_next: false,
+                spacer: false,
+                ..Default::default()
+            },
+            rendered(" ", true),
+            RenderedBlock {
+                segments: Vec::new(),
+                merge_with_next: false,
+                spacer: false,
+                ..Default::default()
+            },
+            rendered("b", false),
+        ];
+        let texts: Vec<_> = render_blocks(&frame, &config)
+            .into_iter()
+            .map(|b| b.full_text)
+            .collect();
+        assert_eq!(texts, ["<", "a", " ", "b"]);
+    }
+
+    fn merged(text: &str) -> RenderedBlock {
+        RenderedBlock {
+            merge_with_next: true,
+            ..rendered(text, false)
+        }
+    }
+
+    #[test]
+    fn merged_native() {
+        let config = SharedConfig::default();
+        let frame = [merged("a"), rendered("b", false), rendered("c", false)];
+        let blocks = render_blocks(&frame, &config);
+        let separators: Vec<_> = blocks
+            .iter()
+            .map(|b| (b.separator, b.separator_block_width))
+            .collect();
+        assert_eq!(
+            separators,
+            [(Some(false), Some(0)), (None, None), (None, None)]
+        );
+        let names: Vec<_> = blocks.iter().map(|b| b.name.clone().unwrap()).collect();
+        assert_eq!(names, ["0", "0", "1"]);
+
+        // A hidden block in between is skipped, and the last block has nothing to merge with
+        let hidden = RenderedBlock::default();
+        let frame = [
+            merged("a"),
+            hidden.clone(),
+            rendered("b", false),
+            merged("c"),
+            hidden,
+        ];
+        let separators: Vec<_> = render_blocks(&frame, &config)
+            .iter()
+            .map(|b| b.separator)
+            .collect();
+        assert_eq!(separators, [Some(false), None, None]);
+    }
+
+    #[test]
+    fn merged_custom() {
+        let mut config = SharedConfig::default();
+        let theme = Arc::make_mut(&mut config.theme);
+        theme.separator = Separator::Custom("<".into());
+        theme.separator_bg = Color::Auto;
+        theme.alternating_tint_bg = Color::Rgba(Rgba::new(16, 16, 16, 0));
+        let frame = [
+            rendered("a", false),
+            merged("b"),
+            RenderedBlock::default(),
+            rendered("c", false),
+            merged("d"),
+            rendered(" ", true),
+            merged("e"),
+        ];
+        let blocks = render_blocks(&frame, &config);
+        let texts: Vec<_> = blocks.iter().map(|b| b.full_text.as_str()).collect();
+        // No separator between the merged blocks, and `d` is not merged across the spacer
+        assert_eq!(texts, ["<", "a", "<", "b", "c", "<", "d", " ", "e"]);
+
+        // Merged blocks are tinted alike, and the last group is never tinted
+        let tinted = Color::Rgba(Rgba::new(16, 16, 255, 255));
+        let plain = Color::Rgba(Rgba::new(0, 0, 255, 255));
+        let backgrounds: Vec<_> = blocks
+            .iter()
+            .filter(|b| b.full_text != "<" && b.full_text != " ")
+            .map(|b| b.background)
+            .collect();
+        assert_eq!(backgrounds, [tinted, plain, plain, tinted, plain]);
+    }
+
+    #[test]
+    fn reordered_clicks() {
+        let config = SharedConfig::default();
+        let frame: Vec<RenderedBlock> = (0..3)
+            .map(|id| RenderedBlock {
+                segments: crate::widget::Widget::new()
+                    .with_text(format!("block {id}"))
+                    .get_data(&config, id)
+                    .unwrap(),
+                merge_with_next: false,
+                spacer: false,
+                ..Default::default()
+            })
+            .collect();
+        let block_ids = crate::imports::block_ids(&["cpu", "time", "sound"]);
+        let order = crate::presentation::display_order(
+            &["sound-0".into(), "cpu-0".into()],
+            &block_ids,
+            frame.len(),
+        );
+        assert_eq!(order, [2, 0, 1]);
+        let reordered: Vec<&RenderedBlock> = order.iter().map(|&id| &frame[id]).collect();
+
+        let rendered = render_blocks(&reordered, &config);
+        assert_eq!(rendered[0].full_text, "block 2");
+        // A click on each block still reaches that block
+        for segment in rendered {
+            let event = serde_json::json!({
+                "name": segment.name,
+                "instance": segment.instance,
+                "button": 1,
+            });
+            let event = i3bar_event::parse_event(&event.to_string(), false).unwrap();
+            assert_eq!(segment.full_text, format!("block {}", event.id));
+        }
+    }
 }
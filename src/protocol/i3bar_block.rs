@@ -2,7 +2,7 @@ use crate::themes::color::Color;
 use serde::Serialize;
 
 /// Represent block as described in <https://i3wm.org/docs/i3bar-protocol.html>
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Debug, Clone, PartialEq)]
 pub struct I3BarBlock {
     pub full_text: String,
     #[serde(skip_serializing_if = "String::is_empty")]
@@ -74,7 +74,20 @@ impl Default for I3BarBlock {
     }
 }
 
-#[derive(Serialize, Debug, Clone, Copy)]
+impl I3BarBlock {
+    /// Remove colors and borders, leaving the text and everything else untouched
+    pub fn strip_colors(&mut self) {
+        self.color = Color::None;
+        self.background = Color::None;
+        self.border = None;
+        self.border_top = None;
+        self.border_right = None;
+        self.border_bottom = None;
+        self.border_left = None;
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(dead_code)]
 #[serde(rename_all = "lowercase")]
 pub enum I3BarBlockAlign {
@@ -83,7 +96,7 @@ pub enum I3BarBlockAlign {
     Left,
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 #[serde(untagged)]
 pub enum I3BarBlockMinWidth {
@@ -7,6 +7,7 @@ use futures::StreamExt;
 use tokio::fs::File;
 use tokio::io::{AsyncBufReadExt, BufReader};
 
+use super::sway::button_from_event_code;
 use crate::click::MouseButton;
 use crate::BoxedStream;
 
@@ -15,6 +16,8 @@ pub struct I3BarEvent {
     pub id: usize,
     pub instance: Option<String>,
     pub button: MouseButton,
+    /// How many clicks this event stands for. Bursts of scrolling are merged into one event.
+    pub count: u32,
 }
 
 fn unprocessed_events_stream(invert_scrolling: bool) -> BoxedStream<I3BarEvent> {
@@ -25,59 +28,123 @@ fn unprocessed_events_stream(invert_scrolling: bool) -> BoxedStream<I3BarEvent>
 
     futures::stream::unfold(lines, move |mut lines| async move {
         loop {
-            // Take only the valid JSON object betweem curly braces (cut off leading bracket, commas and whitespace)
             let line = lines.next_line().await.ok().flatten()?;
-            let line = line.trim_start_matches(|c| c != '{');
-            let line = line.trim_end_matches(|c| c != '}');
-
-            if line.is_empty() {
-                continue;
-            }
-
-            #[derive(Deserialize)]
-            struct I3BarEventRaw {
-                instance: Option<String>,
-                button: MouseButton,
+            if let Some(event) = parse_event(&line, invert_scrolling) {
+                break Some((event, lines));
             }
-
-            let event: I3BarEventRaw = serde_json::from_str(line).unwrap();
-            let (id, instance) = match event.instance {
-                Some(name) => {
-                    let (id, instance) = name.split_once(':').unwrap();
-                    let instance = if instance.is_empty() {
-                        None
-                    } else {
-                        Some(instance.to_owned())
-                    };
-                    (id.parse().unwrap(), instance)
-                }
-                None => continue,
-            };
-
-            use MouseButton::*;
-            let button = match (event.button, invert_scrolling) {
-                (WheelUp, false) | (WheelDown, true) => WheelUp,
-                (WheelUp, true) | (WheelDown, false) => WheelDown,
-                (other, _) => other,
-            };
-
-            let event = I3BarEvent {
-                id,
-                instance,
-                button,
-            };
-
-            break Some((event, lines));
         }
     })
     .boxed_local()
 }
 
+/// Parse a line of the click events sent by i3bar or swaybar. Returns `None` for lines which are
+/// not events and for clicks on segments which do not belong to a block, e.g. separators.
+pub(super) fn parse_event(line: &str, invert_scrolling: bool) -> Option<I3BarEvent> {
+    // Take only the valid JSON object betweem curly braces (cut off leading bracket, commas and whitespace)
+    let line = line.trim_start_matches(|c| c != '{');
+    let line = line.trim_end_matches(|c| c != '}');
+
+    if line.is_empty() {
+        return None;
+    }
+
+    #[derive(Deserialize)]
+    struct I3BarEventRaw {
+        instance: Option<String>,
+        button: MouseButton,
+        /// The Linux input event code of the button, sent by swaybar
+        event: Option<u32>,
+    }
+
+    let event: I3BarEventRaw = serde_json::from_str(line).unwrap();
+    let (id, instance) = event.instance?.split_once(':').map(|(id, instance)| {
+        let instance = if instance.is_empty() {
+            None
+        } else {
+            Some(instance.to_owned())
+        };
+        (id.parse().unwrap(), instance)
+    })?;
+
+    use MouseButton::*;
+    let button = match (event.button, event.event) {
+        // swaybar sends button 0 for buttons which have no X11 number, e.g. BTN_BACK
+        (Unknown, Some(code)) => button_from_event_code(code),
+        (button, _) => button,
+    };
+    let button = match (button, invert_scrolling) {
+        (WheelUp, false) | (WheelDown, true) => WheelUp,
+        (WheelUp, true) | (WheelDown, false) => WheelDown,
+        (other, _) => other,
+    };
+
+    Some(I3BarEvent {
+        id,
+        instance,
+        button,
+        count: 1,
+    })
+}
+
 pub fn events_stream(
     invert_scrolling: bool,
     double_click_delay: Duration,
+    scroll_coalesce_delay: Duration,
+) -> BoxedStream<I3BarEvent> {
+    let mut events = unprocessed_events_stream(invert_scrolling);
+    if !scroll_coalesce_delay.is_zero() {
+        events = coalesce_scrolling(events, scroll_coalesce_delay);
+    }
+    if double_click_delay.is_zero() {
+        events
+    } else {
+        coalesce_double_clicks(events, double_click_delay)
+    }
+}
+
+fn is_wheel(button: MouseButton) -> bool {
+    matches!(button, MouseButton::WheelUp | MouseButton::WheelDown)
+}
+
+/// Merge scrolling in the same direction on the same widget within `delay` of the first step into
+/// one event, whose `count` is the number of steps. Scrolling is delayed by at most `delay`.
+fn coalesce_scrolling(events: BoxedStream<I3BarEvent>, delay: Duration) -> BoxedStream<I3BarEvent> {
+    // The state holds the stream, unless it ended while a burst was pending
+    futures::stream::unfold((Some(events), None), move |(events, pending)| async move {
+        let mut events = events?;
+        let mut event = match pending {
+            Some(pending) => pending,
+            None => events.next().await?,
+        };
+
+        if is_wheel(event.button) {
+            let deadline = tokio::time::Instant::now() + delay;
+            while let Ok(new_event) = tokio::time::timeout_at(deadline, events.next()).await {
+                match new_event {
+                    None => return Some((event, (None, None))),
+                    Some(new_event)
+                        if new_event.id == event.id
+                            && new_event.instance == event.instance
+                            && new_event.button == event.button =>
+                    {
+                        event.count += new_event.count;
+                    }
+                    Some(new_event) => return Some((event, (Some(events), Some(new_event)))),
+                }
+            }
+        }
+
+        Some((event, (Some(events), None)))
+    })
+    .boxed_local()
+}
+
+/// Turn two clicks of the same button on the same widget within `delay` into one double click.
+/// Clicks of buttons which support double clicks are delayed by at most `delay`.
+fn coalesce_double_clicks(
+    events: BoxedStream<I3BarEvent>,
+    delay: Duration,
 ) -> BoxedStream<I3BarEvent> {
-    let events = unprocessed_events_stream(invert_scrolling);
     futures::stream::unfold((events, None), move |(mut events, pending)| async move {
         if let Some(pending) = pending {
             return Some((pending, (events, None)));
@@ -85,12 +152,11 @@ pub fn events_stream(
 
         let mut event = events.next().await?;
 
-        // Handle double clicks (for now only left)
-        if event.button == MouseButton::Left && !double_click_delay.is_zero() {
-            if let Ok(new_event) = tokio::time::timeout(double_click_delay, events.next()).await {
+        if let Some(double) = event.button.double_click() {
+            if let Ok(new_event) = tokio::time::timeout(delay, events.next()).await {
                 let new_event = new_event?;
                 if event == new_event {
-                    event.button = MouseButton::DoubleLeft;
+                    event.button = double;
                 } else {
                     return Some((event, (events, Some(new_event))));
                 }
@@ -101,3 +167,170 @@ pub fn events_stream(
     })
     .boxed_local()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(id: usize, button: MouseButton) -> I3BarEvent {
+        I3BarEvent {
+            id,
+            instance: None,
+            button,
+            count: 1,
+        }
+    }
+
+    /// A stream of events, each delivered after the given delay
+    fn delayed_events(events: Vec<(u64, I3BarEvent)>) -> BoxedStream<I3BarEvent> {
+        futures::stream::iter(events)
+            .then(|(delay, event)| async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                event
+            })
+            .boxed_local()
+    }
+
+    fn collect(events: BoxedStream<I3BarEvent>) -> Vec<I3BarEvent> {
+        tokio_test::block_on(
+            coalesce_double_clicks(events, Duration::from_millis(100)).collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn events() {
+        use MouseButton::*;
+        // Captured from i3bar
+        assert_eq!(
+            parse_event(
+                r#"[{"name":"","instance":"2:","button":1,"modifiers":[],"x":1650,"y":10,"relative_x":22,"relative_y":10,"output_x":1650,"output_y":10,"width":60,"height":20}"#,
+                false
+            ),
+            Some(click(2, Left))
+        );
+        // Captured from swaybar, which sends the Linux event code along with the X11 button
+        assert_eq!(
+            parse_event(
+                r#",{ "name": "", "instance": "0:vol", "button": 5, "event": 769, "x": 1820, "y": 12, "relative_x": 8, "relative_y": 12, "width": 54, "height": 24, "scale": 1 }"#,
+                true
+            ),
+            Some(I3BarEvent {
+                id: 0,
+                instance: Some("vol".into()),
+                button: WheelUp,
+                count: 1,
+            })
+        );
+        // BTN_BACK has no X11 button number
+        assert_eq!(
+            parse_event(
+                r#",{ "name": "", "instance": "1:", "button": 0, "event": 278, "x": 1, "y": 1, "relative_x": 1, "relative_y": 1, "width": 54, "height": 24, "scale": 2 }"#,
+                false
+            ),
+            Some(click(1, Back))
+        );
+        // Separators have no instance
+        assert_eq!(
+            parse_event(r#",{"name":"","button":1,"event":272}"#, false),
+            None
+        );
+        assert_eq!(parse_event("[", false), None);
+    }
+
+    #[test]
+    fn double_clicks() {
+        use MouseButton::*;
+        let events = delayed_events(vec![
+            (0, click(0, Left)),
+            (0, click(0, Left)),
+            (0, click(0, Right)),
+            (0, click(0, Right)),
+            (0, click(1, Middle)),
+            (0, click(1, Middle)),
+        ]);
+        assert_eq!(
+            collect(events),
+            vec![
+                click(0, DoubleLeft),
+                click(0, DoubleRight),
+                click(1, DoubleMiddle)
+            ]
+        );
+    }
+
+    #[test]
+    fn single_clicks() {
+        use MouseButton::*;
+        let events = delayed_events(vec![
+            // Different blocks
+            (0, click(0, Left)),
+            (0, click(1, Left)),
+            // Too slow
+            (200, click(0, Left)),
+            (200, click(0, Left)),
+            // Scrolling is never delayed or coalesced
+            (0, click(0, WheelUp)),
+            (0, click(0, WheelUp)),
+        ]);
+        assert_eq!(
+            collect(events),
+            vec![
+                click(0, Left),
+                click(1, Left),
+                click(0, Left),
+                click(0, Left),
+                click(0, WheelUp),
+                click(0, WheelUp),
+            ]
+        );
+    }
+
+    fn scroll(id: usize, button: MouseButton, count: u32) -> I3BarEvent {
+        I3BarEvent {
+            count,
+            ..click(id, button)
+        }
+    }
+
+    fn collect_scrolling(events: BoxedStream<I3BarEvent>) -> Vec<I3BarEvent> {
+        tokio_test::block_on(
+            coalesce_scrolling(events, Duration::from_millis(100)).collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn scrolling() {
+        use MouseButton::*;
+        let events = delayed_events(vec![
+            (0, click(0, WheelUp)),
+            (30, click(0, WheelUp)),
+            (30, click(0, WheelUp)),
+            // The direction changes
+            (0, click(0, WheelDown)),
+            (0, click(0, WheelDown)),
+            // Another block
+            (0, click(1, WheelDown)),
+            // Other buttons are not delayed
+            (0, click(1, Left)),
+            (0, click(1, Left)),
+            // The window starts at the first step of a burst
+            (0, click(0, WheelUp)),
+            (60, click(0, WheelUp)),
+            (60, click(0, WheelUp)),
+            // The stream ends during a burst
+            (0, click(0, WheelUp)),
+        ]);
+        assert_eq!(
+            collect_scrolling(events),
+            vec![
+                scroll(0, WheelUp, 3),
+                scroll(0, WheelDown, 2),
+                scroll(1, WheelDown, 1),
+                click(1, Left),
+                click(1, Left),
+                scroll(0, WheelUp, 2),
+                scroll(0, WheelUp, 2),
+            ]
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Integration with swaybar
+//!
+//! swaybar sends `stop_signal` (by default `SIGSTOP`) to the status command while the bar is
+//! hidden, which freezes every block. When running under sway, the bar instead asks swaybar not to
+//! stop it and follows the visibility of its bar over sway's IPC: nothing is printed while the bar
+//! is hidden, but blocks keep running and the latest state is printed as soon as it is shown.
+
+use std::ffi::OsStr;
+use std::time::Duration;
+
+use futures::StreamExt;
+use serde::Deserialize;
+use swayipc_async::{BarConfig, BarMode, Connection, Event, EventType};
+
+use crate::click::MouseButton;
+use crate::errors::*;
+use crate::BoxedStream;
+
+/// Which compositor the bar runs under
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Compositor {
+    /// Sway if `$SWAYSOCK` is set
+    #[default]
+    Auto,
+    I3,
+    Sway,
+}
+
+impl Compositor {
+    /// Whether the bar runs under sway. `swaysock` is the value of `$SWAYSOCK`.
+    pub fn is_sway(self, swaysock: Option<&OsStr>) -> bool {
+        match self {
+            Self::Auto => swaysock.is_some_and(|sock| !sock.is_empty()),
+            Self::I3 => false,
+            Self::Sway => true,
+        }
+    }
+}
+
+/// The button of a click from the Linux input event code which swaybar sends along with the X11
+/// button number. swaybar sends button `0` for buttons which have no X11 number.
+pub fn button_from_event_code(code: u32) -> MouseButton {
+    // See linux/input-event-codes.h and swaybar/input.h
+    const BTN_LEFT: u32 = 0x110;
+    const BTN_RIGHT: u32 = 0x111;
+    const BTN_MIDDLE: u32 = 0x112;
+    const BTN_SIDE: u32 = 0x113;
+    const BTN_EXTRA: u32 = 0x114;
+    const BTN_FORWARD: u32 = 0x115;
+    const BTN_BACK: u32 = 0x116;
+    const SWAY_SCROLL_UP: u32 = 0x300;
+    const SWAY_SCROLL_DOWN: u32 = 0x301;
+    match code {
+        BTN_LEFT => MouseButton::Left,
+        BTN_RIGHT => MouseButton::Right,
+        BTN_MIDDLE => MouseButton::Middle,
+        BTN_SIDE | BTN_BACK => MouseButton::Back,
+        BTN_EXTRA | BTN_FORWARD => MouseButton::Forward,
+        SWAY_SCROLL_UP => MouseButton::WheelUp,
+        SWAY_SCROLL_DOWN => MouseButton::WheelDown,
+        _ => MouseButton::Unknown,
+    }
+}
+
+/// The visibility of a bar, as far as the status command is concerned
+#[derive(Debug, Clone, Copy)]
+struct Visibility {
+    mode: BarMode,
+    visible_by_modifier: bool,
+}
+
+impl Visibility {
+    fn new(config: &BarConfig) -> Self {
+        Self {
+            mode: config.mode,
+            visible_by_modifier: false,
+        }
+    }
+
+    fn is_hidden(self) -> bool {
+        match self.mode {
+            BarMode::Hide => !self.visible_by_modifier,
+            BarMode::Invisible => true,
+            _ => false,
+        }
+    }
+
+    /// Apply an IPC event. Events of other bars are ignored.
+    fn apply(&mut self, bar_id: &str, event: &Event) {
+        match event {
+            Event::BarConfigUpdate(config) if config.id == bar_id => self.mode = config.mode,
+            Event::BarStateUpdate(state) if state.id == bar_id => {
+                self.visible_by_modifier = state.visible_by_modifier;
+            }
+            _ => (),
+        }
+    }
+}
+
+/// The ID of the bar given to swaybar with `-b` or `--bar_id`, from its NUL separated command line
+fn bar_id(cmdline: &[u8]) -> Option<String> {
+    let cmdline = String::from_utf8_lossy(cmdline);
+    let mut args = cmdline.split('\0');
+    let program = args.next()?;
+    if !program.ends_with("swaybar") {
+        return None;
+    }
+    while let Some(arg) = args.next() {
+        if let Some(id) = arg.strip_prefix("--bar_id=") {
+            return Some(id.into());
+        }
+        if arg == "-b" || arg == "--bar_id" {
+            return args.next().map(Into::into);
+        }
+    }
+    None
+}
+
+/// Find the swaybar process among the ancestors of this process, which may be run through a shell
+fn find_bar_id() -> Option<String> {
+    let mut pid = std::os::unix::process::parent_id();
+    for _ in 0..3 {
+        if let Some(id) = std::fs::read(format!("/proc/{pid}/cmdline"))
+            .ok()
+            .and_then(|cmdline| bar_id(&cmdline))
+        {
+            return Some(id);
+        }
+        let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+        pid = status
+            .lines()
+            .find_map(|line| line.strip_prefix("PPid:"))?
+            .trim()
+            .parse()
+            .ok()?;
+    }
+    None
+}
+
+/// How long to wait before connecting to sway's IPC again after the connection was lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Returns a stream of whether the bar is hidden, starting with its current visibility. If the
+/// connection to sway is lost, the bar counts as shown until it is connected again.
+pub async fn visibility_stream() -> Result<BoxedStream<bool>> {
+    let first = connect_visibility().await?;
+    let reconnects = futures::stream::unfold((), |()| async {
+        loop {
+            tokio::time::sleep(RECONNECT_DELAY).await;
+            match connect_visibility().await {
+                Ok(stream) => return Some((stream, ())),
+                Err(error) => log::debug!("Cannot follow the visibility of the bar: {error}"),
+            }
+        }
+    });
+    Ok(futures::stream::once(async { first })
+        .chain(reconnects)
+        .flat_map(|stream| {
+            stream.chain(futures::stream::once(async {
+                log::warn!("Lost the connection to sway, showing the bar");
+                false
+            }))
+        })
+        .boxed_local())
+}
+
+async fn connect_visibility() -> Result<BoxedStream<bool>> {
+    let mut connection = Connection::new()
+        .await
+        .error("Failed to open swayipc connection")?;
+    let bar_id = match find_bar_id() {
+        Some(id) => id,
+        None => {
+            let ids = connection
+                .get_bar_ids()
+                .await
+                .error("Failed to get bar IDs")?;
+            match <[String; 1]>::try_from(ids) {
+                Ok([id]) => id,
+                Err(_) => return Err(Error::new("Cannot tell which sway bar this is")),
+            }
+        }
+    };
+    let config = connection
+        .get_bar_config(&bar_id)
+        .await
+        .error("Failed to get bar config")?;
+    let visibility = Visibility::new(&config);
+    let events = Connection::new()
+        .await
+        .error("Failed to open swayipc connection")?
+        .subscribe([EventType::BarConfigUpdate, EventType::BarStateUpdate])
+        .await
+        .error("Failed to subscribe to bar events")?;
+
+    let initial = futures::stream::once(async move { visibility.is_hidden() });
+    let updates = events.filter_map(|event| async move { event.ok() }).scan(
+        visibility,
+        move |visibility, event| {
+            visibility.apply(&bar_id, &event);
+            futures::future::ready(Some(visibility.is_hidden()))
+        },
+    );
+    Ok(initial.chain(updates).boxed_local())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swayipc_async::BarStateUpdateEvent;
+
+    /// Captured with `swaymsg -t get_bar_config bar-0 -r`
+    fn bar_config(id: &str, mode: &str) -> BarConfig {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "mode": mode,
+            "hidden_state": "hide",
+            "position": "top",
+            "status_command": "i3status-rs",
+            "font": "monospace 10",
+            "gaps": { "top": 0, "right": 0, "bottom": 0, "left": 0 },
+            "bar_height": 0,
+            "status_padding": 1,
+            "status_edge_padding": 3,
+            "wrap_scroll": false,
+            "workspace_buttons": true,
+            "strip_workspace_numbers": false,
+            "strip_workspace_name": false,
+            "workspace_min_width": 0,
+            "binding_mode_indicator": true,
+            "verbose": false,
+            "pango_markup": true,
+            "colors": {
+                "background": "#000000ff",
+                "statusline": "#ffffffff",
+                "separator": "#666666ff",
+                "focused_background": "#000000ff",
+                "focused_statusline": "#ffffffff",
+                "focused_separator": "#666666ff",
+                "focused_workspace_border": "#4c7899ff",
+                "focused_workspace_bg": "#285577ff",
+                "focused_workspace_text": "#ffffffff",
+                "inactive_workspace_border": "#333333ff",
+                "inactive_workspace_bg": "#222222ff",
+                "inactive_workspace_text": "#888888ff",
+                "active_workspace_border": "#333333ff",
+                "active_workspace_bg": "#5f676aff",
+                "active_workspace_text": "#ffffffff",
+                "urgent_workspace_border": "#2f343aff",
+                "urgent_workspace_bg": "#900000ff",
+                "urgent_workspace_text": "#ffffffff",
+                "binding_mode_border": "#2f343aff",
+                "binding_mode_bg": "#900000ff",
+                "binding_mode_text": "#ffffffff"
+            },
+            "tray_padding": 2
+        }))
+        .unwrap()
+    }
+
+    /// Captured with `swaymsg -t subscribe -m '["bar_state_update"]'`
+    fn bar_state_update(payload: &str) -> Event {
+        Event::BarStateUpdate(serde_json::from_str::<BarStateUpdateEvent>(payload).unwrap())
+    }
+
+    #[test]
+    fn detection() {
+        let sock = OsStr::new("/run/user/1000/sway-ipc.1000.1234.sock");
+        assert!(Compositor::Auto.is_sway(Some(sock)));
+        assert!(!Compositor::Auto.is_sway(Some(OsStr::new(""))));
+        assert!(!Compositor::Auto.is_sway(None));
+        assert!(!Compositor::I3.is_sway(Some(sock)));
+        assert!(Compositor::Sway.is_sway(None));
+
+        assert_eq!(bar_id(b"swaybar\0-b\0bar-0\0").as_deref(), Some("bar-0"));
+        assert_eq!(
+            bar_id(b"/usr/bin/swaybar\0--bar_id=bar-1\0").as_deref(),
+            Some("bar-1")
+        );
+        assert_eq!(bar_id(b"swaybar\0").as_deref(), None);
+        assert_eq!(bar_id(b"sh\0-c\0i3status-rs\0").as_deref(), None);
+    }
+
+    #[test]
+    fn event_codes() {
+        assert_eq!(button_from_event_code(0x110), MouseButton::Left);
+        assert_eq!(button_from_event_code(0x116), MouseButton::Back);
+        assert_eq!(button_from_event_code(0x115), MouseButton::Forward);
+        assert_eq!(button_from_event_code(0x301), MouseButton::WheelDown);
+        assert_eq!(button_from_event_code(0x14a), MouseButton::Unknown);
+    }
+
+    #[test]
+    fn visibility() {
+        let mut visibility = Visibility::new(&bar_config("bar-0", "dock"));
+        assert!(!visibility.is_hidden());
+
+        // The bar is switched to hide mode
+        visibility.apply(
+            "bar-0",
+            &Event::BarConfigUpdate(Box::new(bar_config("bar-0", "hide"))),
+        );
+        assert!(visibility.is_hidden());
+
+        // The modifier is pressed
+        visibility.apply(
+            "bar-0",
+            &bar_state_update(r#"{"id":"bar-0","visible_by_modifier":true}"#),
+        );
+        assert!(!visibility.is_hidden());
+
+        // Events of other bars are ignored
+        visibility.apply(
+            "bar-0",
+            &bar_state_update(r#"{"id":"bar-1","visible_by_modifier":false}"#),
+        );
+        visibility.apply(
+            "bar-0",
+            &Event::BarConfigUpdate(Box::new(bar_config("bar-1", "invisible"))),
+        );
+        assert!(!visibility.is_hidden());
+
+        visibility.apply(
+            "bar-0",
+            &bar_state_update(r#"{"id":"bar-0","visible_by_modifier":false}"#),
+        );
+        assert!(visibility.is_hidden());
+
+        let mut visibility = Visibility::new(&bar_config("bar-0", "invisible"));
+        visibility.apply(
+            "bar-0",
+            &bar_state_update(r#"{"id":"bar-0","visible_by_modifier":true}"#),
+        );
+        assert!(visibility.is_hidden());
+    }
+}
@@ -0,0 +1,276 @@
+//! Output to the name of the X root window
+//!
+//! Some window managers, e.g. dwm, show the name of the root window as their status instead of
+//! running a status command. With `--output-format xroot`, the text of the blocks is joined with
+//! the theme's separator and set as that name with `xsetroot -name`. Colors, icon fonts and
+//! other markup are dropped, and clicks are not available.
+//!
+//! `xsetroot` runs in the background, so that a slow X server does not hold the bar back. While
+//! it runs, only the latest text is kept to be set next.
+
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::process::{Command, Stdio};
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use crate::config::SharedConfig;
+use crate::errors::*;
+use crate::escape::strip_pango;
+use crate::themes::separator::Separator;
+use crate::RenderedBlock;
+
+use super::i3bar_block::I3BarBlock;
+
+/// Separates the blocks if the theme uses i3bar's native separator
+const NATIVE_SEPARATOR: &str = " | ";
+
+/// The X root window, or a mock of it in tests
+#[async_trait]
+pub trait RootWindow: Send + 'static {
+    async fn set_name(&self, name: &str) -> Result<()>;
+}
+
+/// Sets the name of the root window of `$DISPLAY` with `xsetroot`
+#[derive(Debug)]
+pub struct XSetRoot;
+
+impl XSetRoot {
+    fn command(name: &str) -> Command {
+        let mut command = Command::new("xsetroot");
+        command.args(["-name", name]).stdin(Stdio::null());
+        command
+    }
+
+    /// Like [`RootWindow::set_name`], but blocks until it is set, e.g. when the bar exits
+    pub fn set_name_blocking(name: &str) -> Result<()> {
+        let status = Self::command(name)
+            .status()
+            .error("Failed to run xsetroot")?;
+        check_status(status)
+    }
+}
+
+#[async_trait]
+impl RootWindow for XSetRoot {
+    async fn set_name(&self, name: &str) -> Result<()> {
+        let status = tokio::process::Command::from(Self::command(name))
+            .status()
+            .await
+            .error("Failed to run xsetroot")?;
+        check_status(status)
+    }
+}
+
+fn check_status(status: std::process::ExitStatus) -> Result<()> {
+    if !status.success() {
+        return Err(Error::new(format!("xsetroot exited with {status}")));
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct XRoot<W = XSetRoot> {
+    /// The name which was set last, to not set the same name again
+    last: RefCell<Option<String>>,
+    names: watch::Sender<String>,
+    /// The window and the names to set, until they are handed to [`set_names`] on the first print
+    setter: RefCell<Option<(W, watch::Receiver<String>)>>,
+}
+
+impl XRoot {
+    /// Fails if there is no X server to talk to
+    pub fn new() -> Result<Self> {
+        check_display(std::env::var_os("DISPLAY").as_deref())?;
+        Ok(Self::with_window(XSetRoot))
+    }
+}
+
+impl<W: RootWindow> XRoot<W> {
+    fn with_window(window: W) -> Self {
+        let (names, receiver) = watch::channel(String::new());
+        Self {
+            last: RefCell::new(None),
+            names,
+            setter: RefCell::new(Some((window, receiver))),
+        }
+    }
+
+    pub fn print<B>(&self, blocks: &[B], config: &SharedConfig)
+    where
+        B: Borrow<RenderedBlock>,
+    {
+        let text = status_text(blocks, config);
+        if self.last.borrow().as_ref() == Some(&text) {
+            return;
+        }
+        let _ = self.names.send(text.clone());
+        *self.last.borrow_mut() = Some(text);
+        if let Some((window, names)) = self.setter.borrow_mut().take() {
+            tokio::spawn(set_names(window, names));
+        }
+    }
+}
+
+/// Sets the name of the root window to each of `names`. Names which are sent while the previous
+/// one is being set are skipped, except for the latest one.
+async fn set_names<W: RootWindow>(window: W, mut names: watch::Receiver<String>) {
+    while names.changed().await.is_ok() {
+        let name = names.borrow_and_update().clone();
+        if let Err(error) = window.set_name(&name).await {
+            log::warn!("Cannot set the name of the root window: {error}");
+        }
+    }
+}
+
+fn check_display(display: Option<&OsStr>) -> Result<()> {
+    match display {
+        Some(display) if !display.is_empty() => Ok(()),
+        _ => Err(Error::new(
+            "DISPLAY is not set, but --output-format xroot needs an X server",
+        )),
+    }
+}
+
+/// The text of the blocks, joined with the separator of the theme like i3bar would. No separator
+/// is added after a block which is merged with the next one, nor on either side of a spacer.
+fn status_text<B>(blocks: &[B], config: &SharedConfig) -> String
+where
+    B: Borrow<RenderedBlock>,
+{
+    let separator = match &config.theme.separator {
+        Separator::Custom(separator) => separator.as_str(),
+        Separator::Native => NATIVE_SEPARATOR,
+    };
+    let mut text = String::new();
+    // Whether a separator goes before the next block
+    let mut separate = false;
+    for block in blocks
+        .iter()
+        .map(|x| x.borrow())
+        .filter(|x| !x.segments.is_empty())
+    {
+        if separate && !block.spacer {
+            text.push_str(separator);
+        }
+        for segment in &block.segments {
+            text.push_str(&plain_text(segment));
+        }
+        separate = !block.merge_with_next && !block.spacer;
+    }
+    text
+}
+
+/// The text of a segment without markup
+pub fn plain_text(segment: &I3BarBlock) -> String {
+    if segment.markup.as_deref() != Some("pango") {
+        return segment.full_text.clone();
+    }
+    strip_pango(&segment.full_text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[derive(Default, Clone)]
+    struct MockWindow(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl RootWindow for MockWindow {
+        async fn set_name(&self, name: &str) -> Result<()> {
+            self.0.lock().unwrap().push(name.into());
+            Ok(())
+        }
+    }
+
+    fn block(texts: &[&str], merge_with_next: bool, spacer: bool) -> RenderedBlock {
+        RenderedBlock {
+            segments: texts
+                .iter()
+                .map(|text| I3BarBlock {
+                    full_text: text.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            merge_with_next,
+            spacer,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn text() {
+        let frame = [
+            block(
+                &[" <span font_family='Icons'>X</span> ", "a&amp;b "],
+                false,
+                false,
+            ),
+            block(&[], false, false),
+            block(&[" 50&#39;C "], true, false),
+            block(&[" 40% "], false, false),
+            block(&["   "], false, true),
+            block(&[" 12:00 "], false, false),
+        ];
+        let mut config = SharedConfig::default();
+        assert_eq!(
+            status_text(&frame, &config),
+            " X a&b  |  50'C  40%     12:00 "
+        );
+        Arc::make_mut(&mut config.theme).separator = Separator::Custom("/".into());
+        assert_eq!(
+            status_text(&frame, &config),
+            " X a&b / 50'C  40%     12:00 "
+        );
+
+        assert_eq!(
+            plain_text(&I3BarBlock {
+                full_text: "<b>1 &lt; 2</b> & <i>3</i>".into(),
+                ..Default::default()
+            }),
+            "1 < 2 & 3"
+        );
+        assert_eq!(
+            plain_text(&I3BarBlock {
+                full_text: "<b>as is</b>".into(),
+                markup: None,
+                ..Default::default()
+            }),
+            "<b>as is</b>"
+        );
+    }
+
+    #[test]
+    fn set_names() {
+        let window = MockWindow::default();
+        let xroot = XRoot::with_window(window.clone());
+        let config = SharedConfig::default();
+        let settle = || tokio::time::sleep(Duration::from_millis(10));
+        tokio_test::block_on(async {
+            xroot.print(&[block(&["a"], false, false)], &config);
+            settle().await;
+            // Unchanged text is not set again
+            xroot.print(&[block(&["a"], false, false)], &config);
+            settle().await;
+            assert_eq!(*window.0.lock().unwrap(), ["a"]);
+
+            // Only the latest of the names printed in the meantime is set
+            xroot.print(&[block(&["b"], false, false)], &config);
+            xroot.print(&[block(&["c"], false, false)], &config);
+            settle().await;
+            assert_eq!(*window.0.lock().unwrap(), ["a", "c"]);
+        });
+    }
+
+    #[test]
+    fn no_display() {
+        assert!(check_display(None).is_err());
+        assert!(check_display(Some(OsStr::new(""))).is_err());
+        assert!(check_display(Some(OsStr::new(":0"))).is_ok());
+    }
+}
@@ -0,0 +1,533 @@
+//! Recording of block values over time
+//!
+//! When the top-level `[recording]` table is present, numeric values of the listed blocks are
+//! appended to a CSV file every `every` seconds. Each row has the form
+//! `timestamp,block,key,value`, where `timestamp` is a UNIX timestamp and `key` is the name of a
+//! placeholder. Rows are written in batches from a blocking thread, so the bar never waits for the
+//! disk. A batch is also written every `flush_every` seconds even if it is not full, and before the
+//! bar exits or restarts. When the file grows beyond `max_size` bytes, it is renamed to `<path>.1`
+//! (overwriting the previous one) and a new file is started.
+//!
+//! Recorded history can be summarized with `i3status-rs --dump-history <path> [--block <name>]
+//! [--last <duration>]`.
+//!
+//! # Configuration
+//!
+//! Key           | Values                                                        | Default
+//! --------------|---------------------------------------------------------------|--------
+//! `path`        | Path to the CSV file                                          | `"~/.local/share/i3status-rust/history.csv"`
+//! `blocks`      | List of block names to record                                 | `[]`
+//! `every`       | How often to record a row for each value, in seconds          | `60`
+//! `max_size`    | File size in bytes after which the file is rotated            | `10485760`
+//! `batch_size`  | Number of rows to collect before writing them to the file     | `64`
+//! `flush_every` | How often to write a batch which is not full, in seconds      | `600`
+//!
+//! # Example
+//!
+//! ```toml
+//! [recording]
+//! path = "~/.local/share/i3status-rust/history.csv"
+//! blocks = ["temperature", "memory"]
+//! every = 60
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use smart_default::SmartDefault;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::errors::*;
+use crate::formatting::value::ValueInner;
+use crate::formatting::Values;
+use crate::wrappers::{Seconds, ShellString};
+
+const CSV_HEADER: &str = "timestamp,block,key,value";
+
+#[derive(Deserialize, Debug, SmartDefault)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[default(ShellString::new("~/.local/share/i3status-rust/history.csv"))]
+    pub path: ShellString,
+    pub blocks: Vec<String>,
+    #[default(60.into())]
+    pub every: Seconds<false>,
+    #[default(10 * 1024 * 1024)]
+    pub max_size: u64,
+    #[default(64)]
+    pub batch_size: usize,
+    #[default(600.into())]
+    pub flush_every: Seconds<false>,
+}
+
+#[derive(Debug)]
+enum Message {
+    Values(&'static str, Vec<(String, f64)>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// A handle to the recorder task
+#[derive(Debug)]
+pub struct Recorder {
+    blocks: Vec<String>,
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl Recorder {
+    /// Spawn the recorder task. Must be called from within the tokio runtime.
+    pub fn spawn(config: Config) -> Result<Self> {
+        let path = PathBuf::from(config.path.expand()?.as_ref());
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(record_loop(
+            path,
+            config.every,
+            config.flush_every,
+            config.max_size,
+            Batch::new(config.batch_size),
+            receiver,
+        ));
+        Ok(Self {
+            blocks: config.blocks,
+            sender,
+        })
+    }
+
+    /// Remember the latest numeric values of a block. Values of blocks which are not listed in
+    /// `blocks` are ignored.
+    pub fn record(&self, block: &'static str, values: &Values) {
+        if !self.blocks.iter().any(|b| b == block) {
+            return;
+        }
+        let numbers = values
+            .iter()
+            .filter_map(|(key, value)| match value.inner {
+                ValueInner::Number { val, .. } => Some((key.to_string(), val)),
+                _ => None,
+            })
+            .collect();
+        let _ = self.sender.send(Message::Values(block, numbers));
+    }
+
+    /// Write the rows collected so far and wait until they are written
+    pub async fn flush(&self) {
+        let (sender, receiver) = oneshot::channel();
+        if self.sender.send(Message::Flush(sender)).is_ok() {
+            let _ = receiver.await;
+        }
+    }
+}
+
+async fn record_loop(
+    path: PathBuf,
+    every: Seconds<false>,
+    flush_every: Seconds<false>,
+    max_size: u64,
+    mut batch: Batch,
+    mut receiver: mpsc::UnboundedReceiver<Message>,
+) {
+    let mut latest: HashMap<(&'static str, String), f64> = HashMap::new();
+    let mut timer = every.timer();
+    let mut flush_timer = flush_every.timer();
+
+    loop {
+        tokio::select! {
+            msg = receiver.recv() => match msg {
+                Some(Message::Values(block, values)) => {
+                    for (key, value) in values {
+                        latest.insert((block, key), value);
+                    }
+                }
+                Some(Message::Flush(done)) => {
+                    write_batch(&path, &mut batch, max_size).await;
+                    let _ = done.send(());
+                }
+                None => {
+                    write_batch(&path, &mut batch, max_size).await;
+                    break;
+                }
+            },
+            _ = flush_timer.tick() => write_batch(&path, &mut batch, max_size).await,
+            _ = timer.tick() => {
+                let timestamp = chrono::Utc::now().timestamp();
+                for ((block, key), &value) in &latest {
+                    let row = Row {
+                        timestamp,
+                        block: block.to_string(),
+                        key: key.clone(),
+                        value,
+                    };
+                    if batch.push(row) {
+                        write_batch(&path, &mut batch, max_size).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Write the rows of `batch`, if there are any, from a blocking thread
+async fn write_batch(path: &Path, batch: &mut Batch, max_size: u64) {
+    if batch.rows.is_empty() {
+        return;
+    }
+    let rows = batch.take();
+    let path = path.to_path_buf();
+    let res = tokio::task::spawn_blocking(move || write_rows(&path, &rows, max_size)).await;
+    match res {
+        Ok(Ok(())) => (),
+        Ok(Err(err)) => log::warn!("Failed to write history: {err}"),
+        Err(err) => log::warn!("Failed to write history: {err}"),
+    }
+}
+
+/// A single recorded value
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    timestamp: i64,
+    block: String,
+    key: String,
+    value: f64,
+}
+
+impl fmt::Display for Row {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.timestamp, self.block, self.key, self.value
+        )
+    }
+}
+
+impl Row {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(4, ',');
+        Some(Self {
+            timestamp: parts.next()?.parse().ok()?,
+            block: parts.next()?.into(),
+            key: parts.next()?.into(),
+            value: parts.next()?.parse().ok()?,
+        })
+    }
+}
+
+/// Rows waiting to be written
+#[derive(Debug)]
+struct Batch {
+    rows: Vec<Row>,
+    capacity: usize,
+}
+
+impl Batch {
+    fn new(capacity: usize) -> Self {
+        Self {
+            rows: Vec::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Add a row. Returns `true` if the batch is full and should be written.
+    fn push(&mut self, row: Row) -> bool {
+        self.rows.push(row);
+        self.rows.len() >= self.capacity
+    }
+
+    fn take(&mut self) -> Vec<Row> {
+        std::mem::replace(&mut self.rows, Vec::with_capacity(self.capacity))
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    rotated.into()
+}
+
+/// Move the file to `<path>.1` if it is not smaller than `max_size`.
+fn rotate(path: &Path, max_size: u64) -> io::Result<()> {
+    match fs::metadata(path) {
+        Ok(meta) if meta.len() >= max_size => fs::rename(path, rotated_path(path)),
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+fn write_rows(path: &Path, rows: &[Row], max_size: u64) -> io::Result<()> {
+    rotate(path, max_size)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let is_new = file.metadata()?.len() == 0;
+    let mut file = BufWriter::new(file);
+    if is_new {
+        writeln!(file, "{CSV_HEADER}")?;
+    }
+    for row in rows {
+        writeln!(file, "{row}")?;
+    }
+    file.flush()
+}
+
+/// Minimum, average and maximum of a series of values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub min: f64,
+    pub max: f64,
+    pub sum: f64,
+    pub count: usize,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Summary {
+    fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn avg(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
+
+/// Summarize rows, grouped by block and key.
+fn summarize(
+    reader: impl BufRead,
+    block: Option<&str>,
+    since: Option<i64>,
+) -> io::Result<BTreeMap<(String, String), Summary>> {
+    let mut summaries: BTreeMap<(String, String), Summary> = BTreeMap::new();
+    for line in reader.lines() {
+        let row = match Row::parse(&line?) {
+            Some(row) => row,
+            None => continue,
+        };
+        if matches!(block, Some(b) if b != row.block)
+            || matches!(since, Some(s) if row.timestamp < s)
+        {
+            continue;
+        }
+        summaries
+            .entry((row.block, row.key))
+            .or_default()
+            .add(row.value);
+    }
+    Ok(summaries)
+}
+
+/// Parse durations like `90s`, `30m`, `24h` or `7d`. A number without a suffix means seconds.
+fn parse_duration(s: &str) -> Result<Duration> {
+    let (num, multiplier) = match s.as_bytes().last() {
+        Some(b's') => (&s[..s.len() - 1], 1),
+        Some(b'm') => (&s[..s.len() - 1], 60),
+        Some(b'h') => (&s[..s.len() - 1], 60 * 60),
+        Some(b'd') => (&s[..s.len() - 1], 60 * 60 * 24),
+        _ => (s, 1),
+    };
+    let num: u64 = num
+        .parse()
+        .or_error(|| format!("'{s}' is not a valid duration"))?;
+    Ok(Duration::from_secs(num * multiplier))
+}
+
+/// Print a summary of the recorded history to stdout
+pub fn dump_history(path: &str, block: Option<&str>, last: Option<&str>) -> Result<()> {
+    let path = PathBuf::from(ShellString::new(path.to_string()).expand()?.as_ref());
+    let since = last
+        .map(parse_duration)
+        .transpose()?
+        .map(|last| chrono::Utc::now().timestamp() - last.as_secs() as i64);
+
+    let mut summaries = BTreeMap::new();
+    for file in [rotated_path(&path), path.clone()] {
+        let file = match File::open(&file) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).or_error(|| format!("Failed to open {}", file.display())),
+        };
+        let part = summarize(BufReader::new(file), block, since)
+            .or_error(|| format!("Failed to read {}", path.display()))?;
+        for (key, summary) in part {
+            let total: &mut Summary = summaries.entry(key).or_default();
+            total.min = total.min.min(summary.min);
+            total.max = total.max.max(summary.max);
+            total.sum += summary.sum;
+            total.count += summary.count;
+        }
+    }
+
+    if summaries.is_empty() {
+        println!("No values recorded");
+    }
+    for ((block, key), summary) in summaries {
+        println!(
+            "{block}.{key}: min {:.2}, avg {:.2}, max {:.2} ({} samples)",
+            summary.min,
+            summary.avg(),
+            summary.max,
+            summary.count
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatting::value::Value;
+
+    fn row(timestamp: i64, block: &str, key: &str, value: f64) -> Row {
+        Row {
+            timestamp,
+            block: block.into(),
+            key: key.into(),
+            value,
+        }
+    }
+
+    #[test]
+    fn batching() {
+        let mut batch = Batch::new(3);
+        assert!(!batch.push(row(1, "memory", "mem_used", 1.0)));
+        assert!(!batch.push(row(2, "memory", "mem_used", 2.0)));
+        assert!(batch.push(row(3, "memory", "mem_used", 3.0)));
+        let rows = batch.take();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2], row(3, "memory", "mem_used", 3.0));
+        assert!(batch.rows.is_empty());
+        assert!(!batch.push(row(4, "memory", "mem_used", 4.0)));
+    }
+
+    #[test]
+    fn flushing() {
+        let dir = std::env::temp_dir().join(format!("i3status-rs-flushing-{}", std::process::id()));
+        let path = dir.join("history.csv");
+        let _ = fs::remove_dir_all(&dir);
+        let rows = || fs::read_to_string(&path).unwrap().lines().count() - 1;
+        let every = Seconds(Duration::from_millis(10));
+
+        tokio_test::block_on(async {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let task = tokio::spawn(record_loop(
+                path.clone(),
+                every,
+                Seconds(Duration::from_secs(3600)),
+                1024,
+                Batch::new(64),
+                receiver,
+            ));
+            let recorder = Recorder {
+                blocks: vec!["memory".into()],
+                sender,
+            };
+            recorder.record("memory", &map! { "mem_used" => Value::number(1) });
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            // The batch is not full yet
+            assert!(!path.exists());
+
+            recorder.flush().await;
+            let flushed = rows();
+            assert!(flushed > 0);
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(recorder);
+            task.await.unwrap();
+            assert!(rows() > flushed);
+        });
+        fs::remove_dir_all(&dir).unwrap();
+
+        tokio_test::block_on(async {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tokio::spawn(record_loop(
+                path.clone(),
+                every,
+                Seconds(Duration::from_millis(50)),
+                1024,
+                Batch::new(64),
+                receiver,
+            ));
+            sender
+                .send(Message::Values("memory", vec![("mem_used".into(), 1.0)]))
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(120)).await;
+            assert!(rows() > 0);
+        });
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = std::env::temp_dir().join(format!("i3status-rs-rotation-{}", std::process::id()));
+        let path = dir.join("history.csv");
+        let _ = fs::remove_dir_all(&dir);
+
+        write_rows(&path, &[row(1, "memory", "mem_used", 1.0)], 64).unwrap();
+        assert!(!rotated_path(&path).exists());
+        write_rows(&path, &[row(2, "memory", "mem_used", 2.0)], 64).unwrap();
+        assert!(!rotated_path(&path).exists());
+        // The file is now larger than 64 bytes
+        write_rows(&path, &[row(3, "memory", "mem_used", 3.0)], 64).unwrap();
+        assert!(rotated_path(&path).exists());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{CSV_HEADER}\n3,memory,mem_used,3\n")
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn summary_math() {
+        let csv = "\
+timestamp,block,key,value
+100,temperature,average,40
+200,temperature,average,50
+300,temperature,average,60
+300,temperature,max,70
+300,memory,mem_used,1000
+";
+        let all = summarize(csv.as_bytes(), None, None).unwrap();
+        assert_eq!(all.len(), 3);
+        let avg = all[&("temperature".into(), "average".into())];
+        assert_eq!((avg.min, avg.avg(), avg.max, avg.count), (40., 50., 60., 3));
+
+        let recent = summarize(csv.as_bytes(), Some("temperature"), Some(200)).unwrap();
+        assert_eq!(recent.len(), 2);
+        let avg = recent[&("temperature".into(), "average".into())];
+        assert_eq!((avg.min, avg.avg(), avg.max, avg.count), (50., 55., 60., 2));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(
+            parse_duration("24h").unwrap(),
+            Duration::from_secs(24 * 3600)
+        );
+        assert_eq!(
+            parse_duration("7d").unwrap(),
+            Duration::from_secs(7 * 86400)
+        );
+        assert!(parse_duration("h").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! Refreshing several blocks at once
+//!
+//! A script which changes several things at once, e.g. the audio output and the brightness, can
+//! refresh the blocks showing them with a single signal. Sending `SIGRTMIN+<signal>` asks all
+//! blocks of a group to update, and the bar is not printed until each of them has updated, so it
+//! never shows some of them updated and others not. A block which takes longer than `max_wait`
+//! does not hold up the bar any longer: it is printed anyway, and the block shows its update
+//! later.
+//!
+//! A block counts as updated once it sends its widget, hides or fails.
+//!
+//! A group can also be refreshed with the `RefreshGroup` method over DBus (see
+//! [`control`](crate::control)), given its `name` or its signal number.
+//!
+//! # Configuration
+//!
+//! Key        | Values                                                            | Default
+//! -----------|-------------------------------------------------------------------|--------
+//! `blocks`   | List of block identifiers (e.g. `"sound-0"`) to refresh together  | Required
+//! `signal`   | Signal number which refreshes the group                           | Required
+//! `name`     | Name of the group for the `RefreshGroup` DBus method              | None
+//! `max_wait` | How long to hold the bar back at most, in seconds                 | `0.1`
+//!
+//! # Example
+//!
+//! ```toml
+//! [[refresh_groups]]
+//! blocks = ["sound-0", "backlight-0"]
+//! signal = 6
+//! name = "output"
+//! ```
+//!
+//! Then `pkill -SIGRTMIN+6 i3status-rs` or
+//! `busctl --user call rs.i3status / rs.i3status.bar RefreshGroup s output` refreshes both blocks
+//! in the same frame.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::errors::*;
+use crate::wrappers::Seconds;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub blocks: Vec<String>,
+    pub signal: i32,
+    pub name: Option<String>,
+    #[serde(default = "default_max_wait")]
+    pub max_wait: Seconds<false>,
+}
+
+fn default_max_wait() -> Seconds<false> {
+    Seconds(Duration::from_millis(100))
+}
+
+#[derive(Debug)]
+struct Group {
+    signal: i32,
+    name: Option<String>,
+    ids: Vec<usize>,
+    max_wait: Duration,
+}
+
+/// The groups, and the blocks whose updates the bar is waiting for
+#[derive(Debug, Default)]
+pub struct RefreshGroups {
+    groups: Vec<Group>,
+    pending: HashSet<usize>,
+    /// When the bar is printed anyway, if it is waiting
+    deadline: Option<Instant>,
+}
+
+impl RefreshGroups {
+    /// `block_ids` maps block identifiers to block IDs. Unknown identifiers are an error.
+    pub fn new(configs: &[Config], block_ids: &HashMap<String, usize>) -> Result<Self> {
+        let mut groups = Vec::new();
+        for config in configs {
+            let mut ids = Vec::new();
+            for identifier in &config.blocks {
+                let id = block_ids.get(identifier).or_error(|| {
+                    format!(
+                        "Refresh group of signal {}: no such block '{identifier}'",
+                        config.signal
+                    )
+                })?;
+                if !ids.contains(id) {
+                    ids.push(*id);
+                }
+            }
+            groups.push(Group {
+                signal: config.signal,
+                name: config.name.clone(),
+                ids,
+                max_wait: config.max_wait.0,
+            });
+        }
+        Ok(Self {
+            groups,
+            ..Default::default()
+        })
+    }
+
+    /// Start waiting for the blocks of the groups of `signal`. Returns the IDs of the blocks to
+    /// ask for an update, which is empty if no group has this signal.
+    pub fn trigger(&mut self, signal: i32, now: Instant) -> Vec<usize> {
+        self.trigger_groups(|group| group.signal == signal, now)
+    }
+
+    /// Like [`RefreshGroups::trigger`], for the groups named `name` or, if `name` is a number,
+    /// of this signal. Returns `None` if there is no such group.
+    pub fn trigger_name(&mut self, name: &str, now: Instant) -> Option<Vec<usize>> {
+        let signal = name.parse::<i32>().ok();
+        let matches =
+            |group: &Group| group.name.as_deref() == Some(name) || Some(group.signal) == signal;
+        if !self.groups.iter().any(matches) {
+            return None;
+        }
+        Some(self.trigger_groups(matches, now))
+    }
+
+    fn trigger_groups(&mut self, matches: impl Fn(&Group) -> bool, now: Instant) -> Vec<usize> {
+        let mut triggered = Vec::new();
+        for group in self.groups.iter().filter(|group| matches(group)) {
+            for &id in &group.ids {
+                self.pending.insert(id);
+                if !triggered.contains(&id) {
+                    triggered.push(id);
+                }
+            }
+            let deadline = now + group.max_wait;
+            self.deadline = Some(self.deadline.map_or(deadline, |d| d.max(deadline)));
+        }
+        if self.pending.is_empty() {
+            self.deadline = None;
+        }
+        triggered
+    }
+
+    /// Block `id` updated. Returns whether that was the last update the bar was waiting for.
+    pub fn block_updated(&mut self, id: usize) -> bool {
+        if !self.pending.remove(&id) || !self.pending.is_empty() {
+            return false;
+        }
+        self.deadline = None;
+        true
+    }
+
+    /// Whether the bar must not be printed, because it is waiting for updates
+    pub fn is_waiting(&self) -> bool {
+        self.deadline.is_some()
+    }
+
+    /// When to stop waiting, if the bar is waiting
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Stop waiting, because the deadline passed
+    pub fn time_out(&mut self) {
+        if !self.pending.is_empty() {
+            log::debug!(
+                "Refresh group timed out waiting for blocks {:?}",
+                self.pending
+            );
+        }
+        self.pending.clear();
+        self.deadline = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups() -> RefreshGroups {
+        let block_ids = crate::imports::block_ids(&["sound", "backlight", "time", "sound"]);
+        let configs: Vec<Config> = toml::from_str::<HashMap<String, Vec<Config>>>(
+            r#"
+            refresh_groups = [
+                { blocks = ["sound-0", "backlight-0", "sound-0"], signal = 6 },
+                { blocks = ["sound-1"], signal = 7, max_wait = 0.3 },
+                { blocks = ["time-0"], signal = 7, name = "clock" },
+            ]
+            "#,
+        )
+        .unwrap()
+        .remove("refresh_groups")
+        .unwrap();
+        RefreshGroups::new(&configs, &block_ids).unwrap()
+    }
+
+    #[test]
+    fn validation() {
+        let block_ids = crate::imports::block_ids(&["sound", "time"]);
+        let config = |blocks: &[&str]| Config {
+            blocks: blocks.iter().map(|x| x.to_string()).collect(),
+            signal: 1,
+            name: None,
+            max_wait: default_max_wait(),
+        };
+        assert!(RefreshGroups::new(&[config(&["sound-0", "time-0"])], &block_ids).is_ok());
+        let error = RefreshGroups::new(&[config(&["sound-0", "sound-1"])], &block_ids)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("no such block 'sound-1'"), "{error}");
+        // Only identifiers, not names
+        assert!(RefreshGroups::new(&[config(&["sound"])], &block_ids).is_err());
+    }
+
+    #[test]
+    fn barrier() {
+        let mut groups = groups();
+        let now = Instant::now();
+        assert!(groups.trigger(1, now).is_empty());
+        assert!(!groups.is_waiting());
+
+        assert_eq!(groups.trigger(6, now), [0, 1]);
+        assert!(groups.is_waiting());
+        assert_eq!(groups.deadline(), Some(now + Duration::from_millis(100)));
+        // Blocks outside the group don't count
+        assert!(!groups.block_updated(2));
+        assert!(!groups.block_updated(1));
+        assert!(!groups.block_updated(1));
+        assert!(groups.is_waiting());
+        assert!(groups.block_updated(0));
+        assert!(!groups.is_waiting());
+        // Once the frame is out, updates are not waited for
+        assert!(!groups.block_updated(0));
+
+        // Both groups of a signal, waiting as long as the longest
+        assert_eq!(groups.trigger(7, now), [3, 2]);
+        assert_eq!(groups.deadline(), Some(now + Duration::from_millis(300)));
+        // A group triggered meanwhile joins the wait
+        assert_eq!(groups.trigger(6, now), [0, 1]);
+        assert_eq!(groups.deadline(), Some(now + Duration::from_millis(300)));
+        for id in [3, 2, 0] {
+            assert!(!groups.block_updated(id));
+        }
+        assert!(groups.block_updated(1));
+    }
+
+    #[test]
+    fn names() {
+        let mut groups = groups();
+        let now = Instant::now();
+        assert_eq!(groups.trigger_name("clock", now), Some(vec![2]));
+        assert!(groups.block_updated(2));
+        assert_eq!(groups.trigger_name("7", now), Some(vec![3, 2]));
+        assert_eq!(groups.trigger_name("sound", now), None);
+        assert_eq!(groups.trigger_name("8", now), None);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut groups = groups();
+        let now = Instant::now();
+        groups.trigger(6, now);
+        assert!(!groups.block_updated(0));
+        // The backlight block never answers, so the frame is emitted anyway
+        groups.time_out();
+        assert!(!groups.is_waiting());
+        assert_eq!(groups.deadline(), None);
+        // Its late update is shown as usual
+        assert!(!groups.block_updated(1));
+        assert!(!groups.is_waiting());
+    }
+}
@@ -1,39 +1,253 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::future::pending;
 use std::io;
 use std::os::unix::process::CommandExt;
-use std::process::{Command, Stdio};
-
-/// Spawn a new detached process
-pub fn spawn_process(cmd: &str, args: &[&str]) -> io::Result<()> {
-    let mut proc = Command::new(cmd);
-    proc.args(args);
-    proc.stdin(Stdio::null());
-    proc.stdout(Stdio::null());
-    // Safety: libc::daemon() is async-signal-safe
-    unsafe {
-        proc.pre_exec(|| match libc::daemon(0, 0) {
-            -1 => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Failed to detach new process",
-            )),
-            _ => Ok(()),
-        });
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+
+use crate::errors::*;
+
+use tokio::task::JoinSet;
+
+/// The environment and working directory of the commands run by a block, set by its
+/// `command_env` and `command_dir` options
+#[derive(Debug, Clone, Default)]
+pub struct CommandContext {
+    env: HashMap<String, String>,
+    dir: Option<PathBuf>,
+}
+
+impl CommandContext {
+    pub fn new(env: HashMap<String, String>, dir: Option<PathBuf>) -> Self {
+        Self { env, dir }
+    }
+
+    /// A copy which also sets the variable `key` to `value`, unless it is set by `command_env`
+    pub fn with_default_env(&self, key: &str, value: &str) -> Self {
+        let mut context = self.clone();
+        context
+            .env
+            .entry(key.into())
+            .or_insert_with(|| value.into());
+        context
+    }
+
+    /// The variables set for the commands
+    pub fn env(&self) -> &HashMap<String, String> {
+        &self.env
+    }
+
+    /// A command which runs `program` in this context
+    pub fn command(&self, program: impl AsRef<OsStr>) -> tokio::process::Command {
+        let mut cmd = tokio::process::Command::new(program);
+        cmd.envs(&self.env);
+        if let Some(dir) = &self.dir {
+            cmd.current_dir(dir);
+        }
+        cmd
     }
-    proc.spawn()?.wait()?;
-    Ok(())
+
+    /// A command which runs `cmd` with `sh -c` in this context
+    pub fn shell(&self, cmd: &str) -> tokio::process::Command {
+        let mut proc = self.command("sh");
+        proc.args(["-c", cmd]);
+        proc
+    }
+
+    /// Spawn a new detached process
+    pub fn spawn_process(&self, cmd: &str, args: &[&str]) -> io::Result<()> {
+        let mut proc = Command::new(cmd);
+        proc.args(args);
+        proc.envs(&self.env);
+        if let Some(dir) = &self.dir {
+            proc.current_dir(dir);
+        }
+        proc.stdin(Stdio::null());
+        proc.stdout(Stdio::null());
+        // Stay in `command_dir` if it is set
+        let nochdir = self.dir.is_some() as libc::c_int;
+        // Safety: libc::daemon() is async-signal-safe
+        unsafe {
+            proc.pre_exec(move || match libc::daemon(nochdir, 0) {
+                -1 => Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "Failed to detach new process",
+                )),
+                _ => Ok(()),
+            });
+        }
+        proc.spawn()?.wait()?;
+        Ok(())
+    }
+
+    /// Spawn a new detached shell
+    pub fn spawn_shell(&self, cmd: &str) -> io::Result<()> {
+        self.spawn_process("sh", &["-c", cmd])
+    }
+
+    pub async fn spawn_shell_sync(&self, cmd: &str) -> io::Result<()> {
+        self.shell(cmd)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()?
+            .wait()
+            .await?;
+        Ok(())
+    }
+
+    /// Whether `command` is found in this context's `PATH`
+    pub async fn has_command(&self, command: &str) -> Result<bool> {
+        self.shell(&format!("command -v {command} >/dev/null 2>&1"))
+            .status()
+            .await
+            .or_error(|| format!("Failed to check {command} presence"))
+            .map(|status| status.success())
+    }
+}
+
+/// A command spawned with [`Reaper::spawn_shell`] which exited unsuccessfully
+#[derive(Debug)]
+pub struct Failure {
+    pub block_id: usize,
+    pub cmd: String,
+    /// `None` if waiting for the command failed
+    pub status: Option<ExitStatus>,
 }
 
-/// Spawn a new detached shell
-pub fn spawn_shell(cmd: &str) -> io::Result<()> {
-    spawn_process("sh", &["-c", cmd])
+/// Spawns shell commands and waits for them in the background, so that their exit status can be
+/// reported.
+///
+/// The commands run in a new session, so they are not affected by signals sent to the bar (e.g.
+/// when i3bar stops it while hidden). They are not killed when the reaper is dropped.
+#[derive(Debug, Default)]
+pub struct Reaper {
+    children: JoinSet<Option<Failure>>,
 }
 
-pub async fn spawn_shell_sync(cmd: &str) -> io::Result<()> {
-    tokio::process::Command::new("sh")
-        .args(["-c", cmd])
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .spawn()?
-        .wait()
-        .await?;
-    Ok(())
+impl Reaper {
+    pub fn spawn_shell(
+        &mut self,
+        cmd: &str,
+        block_id: usize,
+        context: &CommandContext,
+    ) -> io::Result<()> {
+        let mut proc = context.shell(cmd);
+        proc.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .kill_on_drop(false);
+        // Safety: setsid() is async-signal-safe
+        unsafe {
+            proc.pre_exec(|| match libc::setsid() {
+                -1 => Err(io::Error::last_os_error()),
+                _ => Ok(()),
+            });
+        }
+        let mut child = proc.spawn()?;
+        let cmd = cmd.to_owned();
+        self.children.spawn(async move {
+            let status = child.wait().await.ok();
+            match status {
+                Some(status) if status.success() => None,
+                status => Some(Failure {
+                    block_id,
+                    cmd,
+                    status,
+                }),
+            }
+        });
+        Ok(())
+    }
+
+    /// Wait for the next command which fails. Never returns if no commands are running.
+    pub async fn next_failure(&mut self) -> Failure {
+        loop {
+            match self.children.join_next().await {
+                Some(Ok(Some(failure))) => return failure,
+                Some(_) => (),
+                None => pending().await,
+            }
+        }
+    }
+
+    /// Stop waiting for the running commands. The commands keep running.
+    pub fn shutdown(&mut self) {
+        self.children.abort_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_has_command_ok() {
+        // we assume sh is always available
+        let context = CommandContext::default();
+        assert!(tokio_test::block_on(context.has_command("sh")).unwrap());
+    }
+
+    #[test]
+    fn test_has_command_err() {
+        // we assume thequickbrownfoxjumpsoverthelazydog command does not exist
+        let context = CommandContext::default();
+        assert!(
+            !tokio_test::block_on(context.has_command("thequickbrownfoxjumpsoverthelazydog"))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn context() {
+        let context = CommandContext::new(
+            HashMap::from([("GREETING".into(), "hello".into())]),
+            Some("/".into()),
+        )
+        .with_default_env("GREETING", "ignored")
+        .with_default_env("LC_ALL", "C");
+        let output = tokio_test::block_on(async {
+            context
+                .shell("echo $GREETING $LC_ALL; pwd")
+                .output()
+                .await
+                .unwrap()
+        });
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello C\n/\n");
+    }
+
+    #[test]
+    fn reaper() {
+        tokio_test::block_on(async {
+            let context = CommandContext::default();
+            let mut reaper = Reaper::default();
+            reaper.spawn_shell("true", 0, &context).unwrap();
+            reaper.spawn_shell("exit 3", 1, &context).unwrap();
+            reaper.spawn_shell("./does-not-exist", 2, &context).unwrap();
+
+            let mut failures = Vec::new();
+            for _ in 0..2 {
+                let failure = reaper.next_failure().await;
+                failures.push((failure.block_id, failure.status.and_then(|s| s.code())));
+            }
+            failures.sort_unstable();
+            assert_eq!(failures, [(1, Some(3)), (2, Some(127))]);
+
+            // The successful command is not reported
+            let next = tokio::time::timeout(Duration::from_millis(100), reaper.next_failure());
+            assert!(next.await.is_err());
+        });
+    }
+
+    #[test]
+    fn reaper_shutdown() {
+        tokio_test::block_on(async {
+            let context = CommandContext::default();
+            let mut reaper = Reaper::default();
+            reaper.spawn_shell("sleep 2; exit 1", 0, &context).unwrap();
+            reaper.shutdown();
+            let next = tokio::time::timeout(Duration::from_millis(100), reaper.next_failure());
+            assert!(next.await.is_err());
+        });
+    }
 }
@@ -0,0 +1,331 @@
+//! Changing the theme of the whole bar depending on values of blocks
+//!
+//! Each entry of the top-level `[[theme_rules]]` list has a condition on a value of a block and
+//! theme overrides. Whenever a block which is referenced by a rule is rendered, the rules are
+//! evaluated in order, and the overrides of the first rule which holds are applied on top of the
+//! theme of every block. Once no rule holds, the configured themes are restored.
+//!
+//! A condition has the form `<block>.<placeholder> <comparator> <literal>`, where `<block>` is a
+//! block identifier (e.g. `battery-0`), `<comparator>` is one of `<`, `<=`, `>`, `>=`, `==` and
+//! `!=`, and `<literal>` is a number or a text. Numbers are compared with numeric values, texts
+//! with text values. A condition on a value which the block does not have does not hold.
+//!
+//! Values which hover around the limit of a comparison would switch the theme back and forth. To
+//! avoid this, a rule which is in effect stays in effect until its comparison fails by more than
+//! `margin`.
+//!
+//! # Configuration
+//!
+//! Key               | Values                                                        | Default
+//! ------------------|---------------------------------------------------------------|--------
+//! `when`            | The condition, e.g. `"battery-0.percentage < 20"`             | Required
+//! `theme_overrides` | Theme overrides, like the `theme_overrides` of `[theme]`     | Required
+//! `margin`          | How far a number must get past the limit to end the rule      | `0`
+//!
+//! # Example
+//!
+//! ```toml
+//! [[theme_rules]]
+//! when = "battery-0.percentage < 20"
+//! margin = 2
+//! theme_overrides = { idle_bg = "#200000" }
+//!
+//! [[theme_rules]]
+//! when = "custom-0.text == REC"
+//! theme_overrides = { idle_bg = "#400000", idle_fg = "#ffcccc" }
+//! ```
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::errors::*;
+use crate::formatting::value::{Value, ValueInner};
+use crate::formatting::Values;
+use crate::themes::ThemeOverrides;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    when: Condition,
+    pub theme_overrides: ThemeOverrides,
+    #[serde(default)]
+    margin: f64,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+struct Condition {
+    block: String,
+    key: String,
+    comparator: Comparator,
+    literal: Literal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparator {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Number(f64),
+    Text(String),
+}
+
+impl FromStr for Condition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // Two-character comparators first, so that `<=` is not taken for `<`
+        const COMPARATORS: [(&str, Comparator); 6] = [
+            ("<=", Comparator::LessOrEqual),
+            (">=", Comparator::GreaterOrEqual),
+            ("==", Comparator::Equal),
+            ("!=", Comparator::NotEqual),
+            ("<", Comparator::Less),
+            (">", Comparator::Greater),
+        ];
+        let (i, op, comparator) = COMPARATORS
+            .iter()
+            .filter_map(|&(op, comparator)| s.find(op).map(|i| (i, op, comparator)))
+            .min_by_key(|&(i, ..)| i)
+            .or_error(|| format!("theme_rules: '{s}' has no comparator"))?;
+        let (reference, literal) = (s[..i].trim(), s[i + op.len()..].trim());
+        let (block, key) = reference.split_once('.').or_error(|| {
+            format!("theme_rules: '{reference}' is not of the form '<block>-<n>.<placeholder>'")
+        })?;
+        if literal.is_empty() {
+            return Err(Error::new(format!("theme_rules: '{s}' has no value")));
+        }
+        let literal = match literal.parse() {
+            Ok(number) => Literal::Number(number),
+            Err(_) => Literal::Text(literal.trim_matches(['"', '\'']).into()),
+        };
+        Ok(Self {
+            block: block.into(),
+            key: key.into(),
+            comparator,
+            literal,
+        })
+    }
+}
+
+impl TryFrom<String> for Condition {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl Condition {
+    /// Whether the condition holds for `value`. The limit of numeric comparisons is moved by
+    /// `margin` in favour of the condition.
+    fn holds(&self, value: Option<&Value>, margin: f64) -> bool {
+        let value = match value {
+            Some(value) => &value.inner,
+            None => return false,
+        };
+        match (value, &self.literal) {
+            (ValueInner::Number { val, .. }, Literal::Number(limit)) => {
+                compare_numbers(*val, self.comparator, *limit, margin)
+            }
+            (ValueInner::Text(text), Literal::Number(limit)) => text
+                .trim()
+                .parse()
+                .is_ok_and(|val| compare_numbers(val, self.comparator, *limit, margin)),
+            (ValueInner::Text(text), Literal::Text(literal)) => match self.comparator {
+                Comparator::Equal => text == literal,
+                Comparator::NotEqual => text != literal,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+fn compare_numbers(val: f64, comparator: Comparator, limit: f64, margin: f64) -> bool {
+    match comparator {
+        Comparator::Less => val < limit + margin,
+        Comparator::LessOrEqual => val <= limit + margin,
+        Comparator::Greater => val > limit - margin,
+        Comparator::GreaterOrEqual => val >= limit - margin,
+        Comparator::Equal => val == limit,
+        Comparator::NotEqual => val != limit,
+    }
+}
+
+/// Keeps track of the rule which is in effect
+#[derive(Debug, Default)]
+pub struct ThemeRules {
+    /// The ID of the block referenced by each rule, the rule and the latest value of the block
+    rules: Vec<(Option<usize>, Rule, Option<Value>)>,
+    active: Option<usize>,
+}
+
+impl ThemeRules {
+    /// `block_ids` maps the identifiers of all blocks to block IDs
+    pub fn new(rules: &[Rule], block_ids: &HashMap<String, usize>) -> Self {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let id = block_ids.get(&rule.when.block).copied();
+                if id.is_none() {
+                    log::warn!(
+                        "Cannot evaluate theme rule on block '{}': no such block",
+                        rule.when.block
+                    );
+                }
+                (id, rule.clone(), None)
+            })
+            .collect();
+        Self {
+            rules,
+            active: None,
+        }
+    }
+
+    /// The theme overrides of the rule which is in effect
+    pub fn active_overrides(&self) -> Option<&ThemeOverrides> {
+        self.active.map(|i| &self.rules[i].1.theme_overrides)
+    }
+
+    /// Handle the values of block `id` after it was rendered, `None` if it is hidden or shows an
+    /// error. Returns whether another rule is in effect now. Blocks which are not referenced by
+    /// any rule are ignored.
+    pub fn block_rendered(&mut self, id: usize, values: Option<&Values>) -> bool {
+        let mut referenced = false;
+        for (block, rule, value) in &mut self.rules {
+            if *block == Some(id) {
+                referenced = true;
+                *value = values.and_then(|v| v.get(rule.when.key.as_str())).cloned();
+            }
+        }
+        if !referenced {
+            return false;
+        }
+        let active = self.active;
+        let new_active = self
+            .rules
+            .iter()
+            .enumerate()
+            .position(|(i, (_, rule, value))| {
+                let margin = if active == Some(i) { rule.margin } else { 0. };
+                rule.when.holds(value.as_ref(), margin)
+            });
+        self.active = new_active;
+        new_active != active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &str) -> ThemeRules {
+        #[derive(Deserialize)]
+        struct Config {
+            theme_rules: Vec<Rule>,
+        }
+        let config: Config = toml::from_str(rules).unwrap();
+        let names = ["battery", "custom"];
+        ThemeRules::new(&config.theme_rules, &crate::imports::block_ids(&names))
+    }
+
+    fn number(key: &str, val: f64) -> Values {
+        let mut values = Values::new();
+        values.insert(key.to_string().into(), Value::number(val));
+        values
+    }
+
+    #[test]
+    fn parse() {
+        let condition: Condition = "battery-0.percentage<=20.5".parse().unwrap();
+        assert_eq!(condition.block, "battery-0");
+        assert_eq!(condition.key, "percentage");
+        assert_eq!(condition.comparator, Comparator::LessOrEqual);
+        assert_eq!(condition.literal, Literal::Number(20.5));
+        let condition: Condition = "custom-0.text != \"REC\"".parse().unwrap();
+        assert_eq!(condition.comparator, Comparator::NotEqual);
+        assert_eq!(condition.literal, Literal::Text("REC".into()));
+        assert!("battery-0.percentage".parse::<Condition>().is_err());
+        assert!("battery-0 < 20".parse::<Condition>().is_err());
+        assert!("battery-0.percentage <".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        let mut rules = rules(
+            r##"
+            [[theme_rules]]
+            when = "custom-0.text == REC"
+            theme_overrides = { idle_bg = "#400000" }
+            [[theme_rules]]
+            when = "battery-0.percentage < 20"
+            theme_overrides = { idle_bg = "#200000" }
+            "##,
+        );
+        assert!(rules.block_rendered(0, Some(&number("percentage", 15.))));
+        assert_eq!(rules.active, Some(1));
+        // The first rule which holds wins
+        let rec = map! { "text" => Value::text("REC".into()) };
+        assert!(rules.block_rendered(1, Some(&rec)));
+        assert_eq!(rules.active, Some(0));
+        assert!(!rules.block_rendered(0, Some(&number("percentage", 10.))));
+        // Blocks without rules are ignored
+        assert!(!rules.block_rendered(5, None));
+    }
+
+    #[test]
+    fn removal() {
+        let mut rules = rules(
+            r##"
+            [[theme_rules]]
+            when = "battery-0.percentage < 20"
+            theme_overrides = { idle_bg = "#200000" }
+            "##,
+        );
+        assert!(!rules.block_rendered(0, Some(&number("percentage", 50.))));
+        assert!(rules.block_rendered(0, Some(&number("percentage", 19.))));
+        assert!(rules.active_overrides().is_some());
+        assert!(rules.block_rendered(0, Some(&number("percentage", 20.))));
+        assert_eq!(rules.active, None);
+        // A hidden block has no values
+        assert!(rules.block_rendered(0, Some(&number("percentage", 19.))));
+        assert!(rules.block_rendered(0, None));
+        assert_eq!(rules.active, None);
+    }
+
+    #[test]
+    fn flap_guard() {
+        let mut rules = rules(
+            r##"
+            [[theme_rules]]
+            when = "battery-0.percentage < 20"
+            margin = 2
+            theme_overrides = { idle_bg = "#200000" }
+            [[theme_rules]]
+            when = "battery-0.percentage < 50"
+            theme_overrides = { idle_bg = "#101010" }
+            "##,
+        );
+        assert!(rules.block_rendered(0, Some(&number("percentage", 21.))));
+        assert_eq!(rules.active, Some(1));
+        // The margin does not apply to a rule which is not in effect
+        assert!(!rules.block_rendered(0, Some(&number("percentage", 20.))));
+        assert!(rules.block_rendered(0, Some(&number("percentage", 19.))));
+        assert_eq!(rules.active, Some(0));
+        // Hovering around the limit keeps the rule
+        assert!(!rules.block_rendered(0, Some(&number("percentage", 20.))));
+        assert!(!rules.block_rendered(0, Some(&number("percentage", 21.9))));
+        assert!(rules.block_rendered(0, Some(&number("percentage", 22.))));
+        assert_eq!(rules.active, Some(1));
+    }
+}
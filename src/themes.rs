@@ -3,6 +3,7 @@ pub mod separator;
 
 use serde::Deserialize;
 
+use crate::animation::Transition;
 use crate::errors::*;
 use crate::util;
 use crate::widget::State;
@@ -28,6 +29,7 @@ pub struct Theme {
     pub alternating_tint_bg: Color,
     pub alternating_tint_fg: Color,
     pub end_separator: Separator,
+    pub transition: Transition,
 }
 
 impl Theme {
@@ -77,11 +79,54 @@ impl Theme {
     }
 }
 
+/// Colors of a single block, set directly in its table, which take precedence over the colors of
+/// its theme. `background` and `foreground` apply in every state, and `<state>_bg` and
+/// `<state>_fg` in one state, before the former. `"auto"` keeps the color of the theme.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockColors {
+    pub background: Option<Color>,
+    pub foreground: Option<Color>,
+    pub idle_bg: Option<Color>,
+    pub idle_fg: Option<Color>,
+    pub info_bg: Option<Color>,
+    pub info_fg: Option<Color>,
+    pub good_bg: Option<Color>,
+    pub good_fg: Option<Color>,
+    pub warning_bg: Option<Color>,
+    pub warning_fg: Option<Color>,
+    pub critical_bg: Option<Color>,
+    pub critical_fg: Option<Color>,
+}
+
+impl BlockColors {
+    /// The background and foreground colors in `state`, given those of the theme
+    pub fn apply(&self, state: State, (theme_bg, theme_fg): (Color, Color)) -> (Color, Color) {
+        let (bg, fg) = match state {
+            State::Idle => (self.idle_bg, self.idle_fg),
+            State::Info => (self.info_bg, self.info_fg),
+            State::Good => (self.good_bg, self.good_fg),
+            State::Warning => (self.warning_bg, self.warning_fg),
+            State::Critical => (self.critical_bg, self.critical_fg),
+        };
+        let pick = |specific: Option<Color>, general: Option<Color>, theme: Color| match specific
+            .or(general)
+        {
+            Some(Color::Auto) | None => theme,
+            Some(color) => color,
+        };
+        (
+            pick(bg, self.background, theme_bg),
+            pick(fg, self.foreground, theme_fg),
+        )
+    }
+}
+
 #[derive(Deserialize, Default)]
 #[serde(deny_unknown_fields, default)]
 pub struct ThemeUserConfig {
     theme: Option<String>,
     overrides: Option<ThemeOverrides>,
+    transition: Option<Transition>,
 }
 
 #[derive(Deserialize, Debug, Clone, Default)]
@@ -115,6 +160,9 @@ impl TryFrom<ThemeUserConfig> for Theme {
         if let Some(overrides) = user_config.overrides {
             theme.apply_overrides(overrides)?;
         }
+        if let Some(transition) = user_config.transition {
+            theme.transition = transition;
+        }
         Ok(theme)
     }
 }
@@ -150,3 +198,31 @@ impl ColorOrLink {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_colors() {
+        let blue: Color = "#0000ff".parse().unwrap();
+        let yellow: Color = "#ffff00".parse().unwrap();
+        let colors = BlockColors {
+            background: Some(blue),
+            warning_bg: Some(yellow),
+            critical_bg: Some(Color::Auto),
+            good_fg: Some(Color::None),
+            ..Default::default()
+        };
+        let theme_bg: Color = "#111111".parse().unwrap();
+        let theme_fg: Color = "#eeeeee".parse().unwrap();
+        let theme = (theme_bg, theme_fg);
+        assert_eq!(colors.apply(State::Idle, theme), (blue, theme_fg));
+        // The color of the state takes precedence
+        assert_eq!(colors.apply(State::Warning, theme), (yellow, theme_fg));
+        // Unless it keeps the theme's color
+        assert_eq!(colors.apply(State::Critical, theme), theme);
+        assert_eq!(colors.apply(State::Good, theme), (blue, Color::None));
+        assert_eq!(BlockColors::default().apply(State::Info, theme), theme);
+    }
+}
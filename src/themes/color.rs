@@ -178,6 +178,26 @@ impl Color {
     pub fn skip_ser(&self) -> bool {
         matches!(self, Self::None | Self::Auto)
     }
+
+    /// The color `t` (0 to 1) of the way from `self` to `other`. If either color is `None` or
+    /// `Auto`, `other` is returned.
+    pub fn blend(self, other: Self, t: f64) -> Self {
+        let rgba = |color| match color {
+            Self::Rgba(rgba) => Some(rgba),
+            Self::Hsva(hsva) => Some(Rgba::from(hsva)),
+            Self::None | Self::Auto => None,
+        };
+        let (Some(from), Some(to)) = (rgba(self), rgba(other)) else {
+            return other;
+        };
+        let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        Self::Rgba(Rgba::new(
+            mix(from.r, to.r),
+            mix(from.g, to.g),
+            mix(from.b, to.b),
+            mix(from.a, to.a),
+        ))
+    }
 }
 
 impl Add for Color {
@@ -208,7 +228,7 @@ impl FromStr for Color {
         } else if color == "auto" {
             Color::Auto
         } else if color.starts_with("hsv:") {
-            let err_msg = || format!("'{color}' is not a vaild HSVA color");
+            let err_msg = || format!("'{color}' is not a valid HSVA color");
             let color = color.split_at(4).1;
             let mut components = color.split(':').map(|x| x.parse::<f64>().or_error(err_msg));
             let h = components.next().or_error(err_msg)??;
@@ -217,7 +237,10 @@ impl FromStr for Color {
             let a = components.next().unwrap_or(Ok(100.))?;
             Color::Hsva(Hsva::new(h, s / 100., v / 100., (a / 100. * 255.) as u8))
         } else {
-            let err_msg = || format!("'{color}' is not a vaild RGBA color");
+            let err_msg = || format!("'{color}' is not a valid RGBA color");
+            if !color.starts_with('#') || !matches!(color.len(), 7 | 9) {
+                return Err(Error::new(err_msg()));
+            }
             let rgb = color.get(1..7).or_error(err_msg)?;
             let a = color.get(7..9).unwrap_or("FF");
             Color::Rgba(Rgba::from_hex(
@@ -0,0 +1,239 @@
+//! Errors in TOML files which point at the offending line
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::errors::StdError;
+
+/// An error in a TOML file, with its position, the key of the offending value and the lines
+/// around it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TomlError {
+    pub path: PathBuf,
+    pub message: String,
+    /// The line and column of the error, starting at 1
+    pub position: Option<(usize, usize)>,
+    /// The key of the offending value, e.g. `block[3].sound.driver`
+    pub key: Option<String>,
+    /// The line of the error and the line before it, with their numbers
+    pub context: Vec<(usize, String)>,
+    /// The number of characters to underline, starting at the column of the error
+    width: usize,
+}
+
+impl TomlError {
+    pub fn new(error: &toml::de::Error, contents: &str, path: &Path) -> Self {
+        // Messages of syntax errors span several lines
+        let message = error.message().lines().collect::<Vec<_>>().join(", ");
+        let mut this = Self {
+            path: path.to_owned(),
+            message,
+            position: None,
+            key: None,
+            context: Vec::new(),
+            width: 0,
+        };
+        let span = match error.span() {
+            Some(span) if span.start <= contents.len() => span,
+            _ => return this,
+        };
+        let span = narrow(contents, span, error.message());
+
+        let line_start = contents[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = contents[span.start..]
+            .find('\n')
+            .map_or(contents.len(), |i| span.start + i);
+        let line = contents[..line_start].matches('\n').count() + 1;
+        let column = contents[line_start..span.start].chars().count() + 1;
+        this.position = Some((line, column));
+        this.key = key_at(contents, line);
+        let lines: Vec<&str> = contents.split('\n').collect();
+        this.context = (line.saturating_sub(1).max(1)..=line)
+            .filter_map(|n| Some((n, lines.get(n - 1)?.trim_end().to_owned())))
+            .collect();
+        this.width = contents[span.start..span.end.min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+        this
+    }
+}
+
+impl fmt::Display for TomlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to deserialize TOML file {}", self.path.display())?;
+        if let Some((line, column)) = self.position {
+            write!(f, " at line {line}, column {column}")?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, ", in `{key}`")?;
+        }
+        write!(f, ": {}", self.message)?;
+        let gutter = self.context.last().map_or(0, |(n, _)| n.to_string().len());
+        for (n, line) in &self.context {
+            write!(f, "\n{n:>gutter$} | {line}")?;
+        }
+        if let Some((_, column)) = self.position {
+            if !self.context.is_empty() {
+                let indent = " ".repeat(column - 1);
+                let caret = "^".repeat(self.width);
+                write!(f, "\n{:gutter$} | {indent}{caret}", "")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StdError for TomlError {}
+
+/// Errors in flattened tables, such as the options of a block, point at the whole table. Point
+/// at the line with the key or value which the message quotes instead, if there is one.
+fn narrow(contents: &str, span: Range<usize>, message: &str) -> Range<usize> {
+    let text = match contents.get(span.clone()) {
+        Some(text) if text.trim_end().contains('\n') => text,
+        _ => return span,
+    };
+    let quoted = match quoted(message) {
+        Some(quoted) => quoted,
+        None => return span,
+    };
+    let mut offset = span.start;
+    for line in text.split_inclusive('\n') {
+        if let Some((key, value)) = line.split_once('=') {
+            let key_start = offset + key.len() - key.trim_start().len();
+            if key.trim() == quoted {
+                return key_start..key_start + key.trim().len();
+            }
+            if value.contains(quoted) {
+                let value_start = offset + key.len() + 1 + value.len() - value.trim_start().len();
+                return value_start..value_start + value.trim().len();
+            }
+        }
+        offset += line.len();
+    }
+    span
+}
+
+/// The first text in backticks or single quotes, e.g. `nope` in "unknown variant `nope`"
+fn quoted(message: &str) -> Option<&str> {
+    let start = message.find(['`', '\''])?;
+    let quote = message[start..].chars().next()?;
+    let rest = &message[start + 1..];
+    let quoted = &rest[..rest.find(quote)?];
+    (!quoted.is_empty()).then_some(quoted)
+}
+
+/// The key on `line` (starting at 1), including the tables it is in, e.g.
+/// `block[3].sound.driver`. Elements of the `block` array are named after their type.
+fn key_at(contents: &str, line: usize) -> Option<String> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let mut table: Option<(String, usize)> = None;
+    let mut counts = HashMap::new();
+    for (i, text) in lines.iter().enumerate().take(line) {
+        let text = text.trim();
+        if let Some(name) = text.strip_prefix("[[").and_then(|t| t.strip_suffix("]]")) {
+            let count = counts.entry(name.trim()).or_insert(0);
+            table = Some((format!("{}[{count}]", name.trim()), i));
+            *count += 1;
+        } else if let Some(name) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            table = Some((name.trim().to_owned(), i));
+        }
+    }
+
+    let mut path = Vec::new();
+    if let Some((name, header)) = &table {
+        path.push(name.clone());
+        if name.starts_with("block[") {
+            // The type of the block, which may be set after the offending line
+            let block_type = lines[header + 1..]
+                .iter()
+                .take_while(|text| !text.trim_start().starts_with('['))
+                .filter_map(|text| text.split_once('='))
+                .find(|(key, _)| key.trim() == "block")
+                .map(|(_, value)| value.trim().trim_matches(['"', '\'']));
+            if let Some(block_type) = block_type {
+                path.push(block_type.to_owned());
+            }
+        }
+    }
+    let text = lines.get(line - 1)?.trim();
+    if !text.starts_with('[') {
+        if let Some((key, _)) = text.split_once('=') {
+            path.push(key.trim().to_owned());
+        }
+    }
+    (!path.is_empty()).then(|| path.join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::util::deserialize_toml;
+
+    fn error(contents: &str) -> (TomlError, String) {
+        let error = toml::from_str::<Config>(contents).unwrap_err();
+        let error = TomlError::new(&error, contents, Path::new("config.toml"));
+        let rendered = error.to_string();
+        (error, rendered)
+    }
+
+    #[test]
+    fn syntax_error() {
+        let (error, rendered) = error("icons_format = \"{icon}\"\ntheme = \n");
+        assert_eq!(error.position, Some((2, 9)));
+        assert_eq!(error.key.as_deref(), Some("theme"));
+        assert_eq!(
+            rendered,
+            "Failed to deserialize TOML file config.toml at line 2, column 9, in `theme`: \
+             invalid string, expected `\"`, `'`\n\
+             1 | icons_format = \"{icon}\"\n\
+             2 | theme =\n\
+            \x20 |         ^"
+        );
+    }
+
+    #[test]
+    fn wrong_type() {
+        let (error, rendered) = error("[alerts]\nflashes = \"many\"\n");
+        assert_eq!(error.position, Some((2, 11)));
+        assert_eq!(error.key.as_deref(), Some("alerts.flashes"));
+        assert!(rendered.ends_with("2 | flashes = \"many\"\n  |           ^^^^^^"));
+    }
+
+    #[test]
+    fn bad_enum() {
+        let (error, rendered) = error(
+            "[[block]]\nblock = \"time\"\n\n[[block]]\nblock = \"sound\"\ndriver = \"nope\"\n",
+        );
+        // The error of a flattened table is narrowed down to the quoted value
+        assert_eq!(error.position, Some((6, 10)));
+        assert_eq!(error.key.as_deref(), Some("block[1].sound.driver"));
+        assert!(rendered.contains("unknown variant `nope`"));
+        assert!(
+            rendered.ends_with("5 | block = \"sound\"\n6 | driver = \"nope\"\n  |          ^^^^^^")
+        );
+    }
+
+    #[test]
+    fn unknown_field() {
+        let (error, rendered) = error("[alerts]\nflashes = 3\nbogus = 1\n");
+        assert_eq!(error.position, Some((3, 1)));
+        assert_eq!(error.key.as_deref(), Some("alerts.bogus"));
+        assert!(rendered.contains("at line 3, column 1, in `alerts.bogus`: unknown field"));
+        assert!(rendered.ends_with("3 | bogus = 1\n  | ^^^^^"));
+    }
+
+    #[test]
+    fn structured() {
+        let result = deserialize_toml::<Config>("invert_scrolling = 3\n", Path::new("a.toml"));
+        let error = result.unwrap_err();
+        let cause = error.cause.as_deref().unwrap();
+        let toml_error = cause.downcast_ref::<TomlError>().unwrap();
+        assert_eq!(toml_error.path, Path::new("a.toml"));
+        assert_eq!(toml_error.key.as_deref(), Some("invert_scrolling"));
+        assert_eq!(error.to_string(), toml_error.to_string());
+    }
+}
@@ -1,13 +1,17 @@
 use std::fs::File;
 use std::io::{self, BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use dirs::{config_dir, data_dir};
+use nix::sys::statvfs::{statvfs, Statvfs};
 use serde::de::DeserializeOwned;
 use tokio::io::AsyncReadExt;
-use tokio::process::Command;
 
 use crate::errors::*;
+use crate::localization::{Localization, Text};
+use crate::toml_error::TomlError;
 
 /// Tries to find a file in standard locations:
 /// - Fist try to find a file by full path
@@ -105,18 +109,17 @@ where
     BufReader::new(file)
         .read_to_string(&mut contents)
         .or_error(|| format!("Failed to read file: {}", path.display()))?;
-    toml::from_str(&contents).map_err(|err| {
-        #[allow(deprecated)]
-        let location_msg = err
-            .line_col()
-            .map(|(line, _col)| format!(" at line {}", line + 1))
-            .unwrap_or_default();
-        Error::new(format!(
-            "Failed to deserialize TOML file {}{}: {}",
-            path.display(),
-            location_msg,
-            err.message()
-        ))
+    deserialize_toml(&contents, path)
+}
+
+/// Deserialize the contents of the TOML file at `path`. The cause of the error is a
+/// [`TomlError`], which points at the offending line.
+pub fn deserialize_toml<T: DeserializeOwned>(contents: &str, path: &Path) -> Result<T> {
+    toml::from_str(contents).map_err(|err| Error {
+        kind: ErrorKind::Config,
+        message: None,
+        cause: Some(Arc::new(TomlError::new(&err, contents, path))),
+        block: None,
     })
 }
 
@@ -127,32 +130,22 @@ pub async fn read_file(path: impl AsRef<Path>) -> io::Result<String> {
     Ok(content.trim_end().to_string())
 }
 
-pub async fn has_command(command: &str) -> Result<bool> {
-    Command::new("sh")
-        .args([
-            "-c",
-            format!("command -v {command} >/dev/null 2>&1").as_ref(),
-        ])
-        .status()
-        .await
-        .or_error(|| format!("Failed to check {command} presence"))
-        .map(|status| status.success())
-}
-
+/// Build the [`Values`](crate::formatting::Values) of a block
+///
 /// # Example
 ///
 /// ```
 /// let opt = Some(1);
-/// let map: HashMap<&'static str, String> = map! {
-///     "key" => "value",
-///     [if true] "hello" => "world",
-///     [if let Some(x) = opt] "opt" => x.to_string(),
+/// let values = map! {
+///     "key" => Value::text("value".into()),
+///     [if true] "hello" => Value::text("world".into()),
+///     [if let Some(x) = opt] "opt" => Value::number(x),
 /// };
 /// ```
 macro_rules! map {
     ($( $([$($cond_tokens:tt)*])? $key:literal => $value:expr ),* $(,)?) => {{
         #[allow(unused_mut)]
-        let mut m = ::std::collections::HashMap::new();
+        let mut m = $crate::formatting::Values::new();
         $(
         map!(@insert m, $key, $value $(,$($cond_tokens)*)?);
         )*
@@ -235,6 +228,55 @@ pub fn country_flag_from_iso_code(country_code: &str) -> String {
     String::from_utf8(vec![0xf0, 0x9f, 0x87, b1, 0xf0, 0x9f, 0x87, b2]).unwrap()
 }
 
+/// Format a number of seconds like `2h 05m` or `45m`, with the units of `localization`. Negative
+/// durations are shown as `0m`.
+pub fn format_duration(seconds: f64, localization: &Localization) -> String {
+    let (h, m) = (
+        localization.get(Text::HoursShort),
+        localization.get(Text::MinutesShort),
+    );
+    let minutes = (seconds.max(0.) / 60.) as u64;
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{minutes}{m}"),
+        (hours, minutes) => format!("{hours}{h} {minutes:02}{m}"),
+    }
+}
+
+/// Run the blocking `f` on a separate thread, but give up waiting for it after `timeout`, in
+/// which case `None` is returned. The thread keeps running until `f` returns, e.g. a call on a
+/// stale network filesystem may never return.
+pub async fn spawn_blocking_timeout<T, F>(timeout: Duration, f: F) -> Result<Option<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::time::timeout(timeout, tokio::task::spawn_blocking(f)).await {
+        Ok(result) => result.error("Failed to join tokio task").map(Some),
+        Err(_elapsed) => Ok(None),
+    }
+}
+
+/// [`statvfs`] which gives up after `timeout`, see [`spawn_blocking_timeout`]
+pub async fn statvfs_timeout(path: PathBuf, timeout: Duration) -> Result<Option<Statvfs>> {
+    match spawn_blocking_timeout(timeout, move || statvfs(&path)).await? {
+        Some(result) => result.error("failed to retrieve statvfs").map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Whether `text` matches `pattern`, in which `*` matches any sequence of characters
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => match text.strip_prefix(prefix) {
+            Some(text) => (0..=text.len())
+                .filter(|&i| text.is_char_boundary(i))
+                .any(|i| wildcard_match(rest, &text[i..])),
+            None => false,
+        },
+    }
+}
+
 /// A shorcut for `Default::default()`
 /// See <https://github.com/rust-lang/rust/issues/73014>
 #[inline]
@@ -247,21 +289,50 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_has_command_ok() {
-        // we assume sh is always available
-        assert!(tokio_test::block_on(has_command("sh")).unwrap());
+    fn test_flags() {
+        assert!(country_flag_from_iso_code("ES") == "🇪🇸");
+        assert!(country_flag_from_iso_code("US") == "🇺🇸");
+        assert!(country_flag_from_iso_code("USA") == "USA");
     }
 
     #[test]
-    fn test_has_command_err() {
-        // we assume thequickbrownfoxjumpsoverthelazydog command does not exist
-        assert!(!tokio_test::block_on(has_command("thequickbrownfoxjumpsoverthelazydog")).unwrap());
+    fn test_format_duration() {
+        let english = Localization::default();
+        assert_eq!(format_duration(0., &english), "0m");
+        assert_eq!(format_duration(45. * 60. + 59., &english), "45m");
+        assert_eq!(format_duration(2. * 3600. + 5. * 60., &english), "2h 05m");
+        assert_eq!(format_duration(-100., &english), "0m");
+        let german: Localization = toml::from_str(r#"hours_short = "Std""#).unwrap();
+        assert_eq!(format_duration(2. * 3600. + 5. * 60., &german), "2Std 05m");
+    }
+    #[test]
+    fn blocking_timeout() {
+        tokio_test::block_on(async {
+            let fast = spawn_blocking_timeout(Duration::from_secs(5), || 42).await;
+            assert_eq!(fast.unwrap(), Some(42));
+            let slow = spawn_blocking_timeout(Duration::from_millis(10), || {
+                std::thread::sleep(Duration::from_millis(500));
+                42
+            })
+            .await;
+            assert_eq!(slow.unwrap(), None);
+            assert!(statvfs_timeout("/".into(), Duration::from_secs(5))
+                .await
+                .unwrap()
+                .is_some());
+        });
     }
 
     #[test]
-    fn test_flags() {
-        assert!(country_flag_from_iso_code("ES") == "🇪🇸");
-        assert!(country_flag_from_iso_code("US") == "🇺🇸");
-        assert!(country_flag_from_iso_code("USA") == "USA");
+    fn wildcard() {
+        assert!(wildcard_match("coretemp", "coretemp"));
+        assert!(!wildcard_match("coretemp", "coretemp2"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("core*", "coretemp"));
+        assert!(wildcard_match("*temp", "coretemp"));
+        assert!(wildcard_match("*-isa-*", "coretemp-isa-0000"));
+        assert!(wildcard_match("n*e*", "nvme"));
+        assert!(!wildcard_match("*-isa-*", "nvme-pci-0100"));
+        assert!(!wildcard_match("k10temp", "coretemp"));
     }
 }
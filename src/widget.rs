@@ -1,9 +1,15 @@
 use crate::config::SharedConfig;
 use crate::errors::*;
+use crate::escape::{strip_pango, CollectEscaped};
+use crate::formatting::value::{Value, ValueInner};
 use crate::formatting::{Format, Fragment, Values};
-use crate::protocol::i3bar_block::I3BarBlock;
+use crate::icons::Icon;
+use crate::protocol::i3bar_block::{I3BarBlock, I3BarBlockMinWidth};
+use crate::themes::Theme;
 use serde::Deserialize;
 use smart_default::SmartDefault;
+use std::borrow::Cow;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Default)]
 pub struct Widget {
@@ -49,85 +55,455 @@ impl Widget {
 
     pub fn set_format(&mut self, format: Format) {
         match &mut self.source {
-            Source::Format(old, _) => *old = format,
+            Source::Format(old, _) | Source::Parts(old, _, _) => *old = format,
             _ => self.source = Source::Format(format, None),
         }
     }
 
+    /// Render the format once for each part, with the part's own state and values, instead of
+    /// once with the values set by [`Widget::set_values`]. Must be called after the format is set.
+    pub fn set_parts(&mut self, parts: Vec<WidgetPart>) {
+        self.set_parts_diff(parts, WidgetsDiff::All);
+    }
+
+    /// Like [`Widget::set_parts`], but only the parts which changed according to
+    /// [`PartsView::view_diff`] are rendered again
+    pub fn set_view(&mut self, view: &impl PartsView) {
+        self.set_parts_diff(view.view(), view.view_diff());
+    }
+
+    fn set_parts_diff(&mut self, parts: Vec<WidgetPart>, diff: WidgetsDiff) {
+        match std::mem::take(&mut self.source) {
+            Source::Format(format, _) | Source::Parts(format, _, _) => {
+                self.source = Source::Parts(format, parts, diff);
+            }
+            source => self.source = source,
+        }
+    }
+
+    /// Which parts changed since the block last sent this widget, if it has parts
+    pub fn parts_diff(&self) -> Option<&WidgetsDiff> {
+        match &self.source {
+            Source::Parts(_, _, diff) => Some(diff),
+            _ => None,
+        }
+    }
+
+    /// Show empty space instead of text
+    pub fn set_spacer(&mut self, spacer: Spacer) {
+        self.source = Source::Spacer(spacer);
+    }
+
+    /// Whether this widget is empty space, which is never separated from its neighbours
+    pub fn is_spacer(&self) -> bool {
+        matches!(self.source, Source::Spacer(_))
+    }
+
     pub fn set_values(&mut self, new_values: Values) {
-        if let Source::Format(_, values) = &mut self.source {
-            *values = Some(new_values);
+        match &mut self.source {
+            Source::Format(_, values) => *values = Some(new_values),
+            Source::Parts(format, _, _) => {
+                self.source = Source::Format(format.clone(), Some(new_values));
+            }
+            _ => (),
+        }
+    }
+
+    pub fn values(&self) -> Option<&Values> {
+        match &self.source {
+            Source::Format(_, values) => values.as_ref(),
+            _ => None,
         }
     }
 
     pub fn intervals(&self) -> Vec<u64> {
         match &self.source {
-            Source::Format(f, _) => f.intervals(),
+            Source::Format(f, _) | Source::Parts(f, _, _) => f.intervals(),
             _ => Vec::new(),
         }
     }
 
     /// Constuct `I3BarBlock` from this widget
     pub fn get_data(&self, shared_config: &SharedConfig, id: usize) -> Result<Vec<I3BarBlock>> {
-        // Create a "template" block
-        let (key_bg, key_fg) = shared_config.theme.get_colors(self.state);
-        let (full, short) = self.source.render(shared_config)?;
-        let mut template = I3BarBlock {
-            instance: format!("{id}:"),
-            background: key_bg,
-            color: key_fg,
-            ..I3BarBlock::default()
+        match &self.source {
+            Source::Parts(format, parts, _) => {
+                let mut data = Vec::new();
+                for part in parts {
+                    data.extend(part.get_data(format, shared_config, id)?);
+                }
+                Ok(data)
+            }
+            Source::Spacer(spacer) => {
+                let (background, color) = if spacer.transparent {
+                    Default::default()
+                } else {
+                    shared_config.get_colors(self.state)
+                };
+                Ok(vec![I3BarBlock {
+                    // Figure spaces are as wide as digits
+                    full_text: "\u{2007}".repeat(spacer.width),
+                    background,
+                    color,
+                    min_width: spacer.min_width.map(I3BarBlockMinWidth::Pixels),
+                    instance: format!("{id}:"),
+                    ..I3BarBlock::default()
+                }])
+            }
+            source => {
+                let (full, short) = source.render(self.state, shared_config)?;
+                Ok(segments(
+                    self.state,
+                    full,
+                    short,
+                    shared_config,
+                    format!("{id}:"),
+                ))
+            }
+        }
+    }
+
+    /// Construct `I3BarBlock`s which only show the icons of this widget, for a bar which is too
+    /// narrow to show all of its text. Empty if the widget has no icons.
+    pub fn get_icon_data(&self, shared_config: &SharedConfig, id: usize) -> Vec<I3BarBlock> {
+        let icons = |format: &Format, values: &Values, state, instance| {
+            let icons = format.icons(&icons_in_state(values, state), shared_config);
+            if icons.is_empty() {
+                return Vec::new();
+            }
+            let icons: Vec<&str> = icons.iter().map(|icon| icon.trim()).collect();
+            let text = format!(" {} ", icons.join(" "));
+            segments(state, vec![text.into()], vec![], shared_config, instance)
         };
+        match &self.source {
+            Source::Format(format, Some(values)) => {
+                icons(format, values, self.state, format!("{id}:"))
+            }
+            Source::Parts(format, parts, _) => parts
+                .iter()
+                .flat_map(|part| {
+                    let instance = format!("{id}:{}", part.name);
+                    icons(format, &part.values, part.state, instance)
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
 
-        // Collect all the pieces into "parts"
-        let mut parts = Vec::new();
+/// A part of a widget, see [`Widget::set_parts`]
+#[derive(Debug, Clone)]
+pub struct WidgetPart {
+    /// Set as the instance of the part's segments, so that clicks on them can be told apart
+    pub name: String,
+    pub state: State,
+    pub values: Values,
+}
 
-        if full.is_empty() {
-            return Ok(parts);
-        }
+impl WidgetPart {
+    fn get_data(
+        &self,
+        format: &Format,
+        shared_config: &SharedConfig,
+        id: usize,
+    ) -> Result<Vec<I3BarBlock>> {
+        let values = icons_in_state(&self.values, self.state);
+        let (full, short) = format.render(&values, shared_config)?;
+        let instance = format!("{id}:{}", self.name);
+        Ok(segments(self.state, full, short, shared_config, instance))
+    }
+}
+
+/// Which parts of a widget changed since the block last sent it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum WidgetsDiff {
+    /// Any part may have changed
+    #[default]
+    All,
+    /// Only the parts with these indices changed
+    Changed(Vec<usize>),
+}
 
-        // If short text is available, it's necessary to hide all full blocks. `swaybar`/`i3bar`
-        // will switch a block to "short mode" only if it's "short_text" is set to a non-empty
-        // string "<span/>" is a non-empty string and it doesn't display anything. It's kinda hacky,
-        // but it works.
-        if !short.is_empty() {
-            template.short_text = "<span/>".into();
+impl WidgetsDiff {
+    /// Add the changes of `other`, which happened after these
+    pub fn merge(&mut self, other: &Self) {
+        match (&mut *self, other) {
+            (Self::All, _) => (),
+            (_, Self::All) => *self = Self::All,
+            (Self::Changed(changed), Self::Changed(other)) => changed.extend(other),
         }
+    }
+}
+
+/// The state of a block which is shown as one part per item, e.g. one per host. See
+/// [`Widget::set_view`].
+pub trait PartsView {
+    /// The parts to show, in order
+    fn view(&self) -> Vec<WidgetPart>;
+
+    /// Which parts changed since the parts were last shown. Only those are rendered again.
+    fn view_diff(&self) -> WidgetsDiff {
+        WidgetsDiff::All
+    }
+}
+
+/// The segments of each part of a block's widget, so that parts which did not change are not
+/// rendered again
+#[derive(Debug, Default)]
+pub struct PartsCache {
+    parts: Vec<Vec<I3BarBlock>>,
+    /// The block ID and theme which the parts were rendered with
+    rendered_with: Option<(usize, Arc<Theme>)>,
+    /// The parts which changed since they were rendered
+    changed: WidgetsDiff,
+}
+
+impl PartsCache {
+    /// The parts of the next widget changed, e.g. because the block sent a new one
+    pub fn changed(&mut self, diff: &WidgetsDiff) {
+        self.changed.merge(diff);
+    }
 
-        parts.extend(full.into_iter().map(|w| {
-            let mut data = template.clone();
-            data.full_text = w.formated_text();
-            if let Some(i) = &w.metadata.instance {
-                data.instance.push_str(i);
+    /// Like [`Widget::get_data`], but parts which did not change are not rendered again
+    pub fn get_data(
+        &mut self,
+        widget: &Widget,
+        shared_config: &SharedConfig,
+        id: usize,
+    ) -> Result<Vec<I3BarBlock>> {
+        let (format, parts) = match &widget.source {
+            Source::Parts(format, parts, _) => (format, parts),
+            _ => {
+                self.parts.clear();
+                return widget.get_data(shared_config, id);
             }
-            data
-        }));
-
-        template.full_text = "<span/>".into();
-        parts.extend(short.into_iter().map(|w| {
-            let mut data = template.clone();
-            data.short_text = w.formated_text();
-            if let Some(i) = &w.metadata.instance {
-                data.instance.push_str(i);
+        };
+        let up_to_date = self.parts.len() == parts.len()
+            && matches!(&self.rendered_with, Some((rendered_id, theme))
+                if *rendered_id == id && Arc::ptr_eq(theme, &shared_config.theme));
+        let changed = match std::mem::take(&mut self.changed) {
+            WidgetsDiff::Changed(changed) if up_to_date => changed,
+            _ => {
+                self.parts = vec![Vec::new(); parts.len()];
+                (0..parts.len()).collect()
+            }
+        };
+        self.changed = WidgetsDiff::Changed(Vec::new());
+        for index in changed {
+            if let Some(part) = parts.get(index) {
+                self.parts[index] = part.get_data(format, shared_config, id)?;
             }
-            data
-        }));
+        }
+        self.rendered_with = Some((id, shared_config.theme.clone()));
+        Ok(self.parts.concat())
+    }
+}
+
+/// Empty space, see [`Widget::set_spacer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spacer {
+    /// In figure spaces
+    pub width: usize,
+    /// In pixels
+    pub min_width: Option<usize>,
+    /// Whether the bar's background shows through
+    pub transparent: bool,
+}
+
+/// `values` with every icon which depends on the state replaced by its variant for `state`
+fn icons_in_state(values: &Values, state: State) -> Cow<'_, Values> {
+    let is_stateful =
+        |value: &Value| matches!(&value.inner, ValueInner::Icon(icon) if icon.is_stateful());
+    if !values.iter().any(|(_, value)| is_stateful(value)) {
+        return Cow::Borrowed(values);
+    }
+    let values = values.iter().map(|(key, value)| {
+        let mut value = value.clone();
+        if let ValueInner::Icon(icon) = &value.inner {
+            value.inner = ValueInner::Icon(Icon::Plain(icon.in_state(state).to_owned()));
+        }
+        (key.clone(), value)
+    });
+    Cow::Owned(values.collect())
+}
+
+/// Construct the `I3BarBlock`s of rendered text
+fn segments(
+    state: State,
+    full: Vec<Fragment>,
+    short: Vec<Fragment>,
+    shared_config: &SharedConfig,
+    instance: String,
+) -> Vec<I3BarBlock> {
+    // Create a "template" block
+    let (key_bg, key_fg) = shared_config.get_colors(state);
+    let mut template = I3BarBlock {
+        instance,
+        background: key_bg,
+        color: key_fg,
+        ..I3BarBlock::default()
+    };
+
+    // Collect all the pieces into "parts"
+    let mut parts = Vec::new();
+
+    if full.is_empty() {
+        return parts;
+    }
+
+    // If short text is available, it's necessary to hide all full blocks. `swaybar`/`i3bar`
+    // will switch a block to "short mode" only if it's "short_text" is set to a non-empty
+    // string "<span/>" is a non-empty string and it doesn't display anything. It's kinda hacky,
+    // but it works.
+    let markup = shared_config.markup;
+    template.markup = Some(markup.name().into());
+    if !short.is_empty() {
+        template.short_text = markup.hidden_text().into();
+    }
 
-        Ok(parts)
+    parts.extend(full.into_iter().map(|w| {
+        let mut data = template.clone();
+        data.full_text = markup.text(&w);
+        if let Some(i) = &w.metadata.instance {
+            data.instance.push_str(i);
+        }
+        data
+    }));
+
+    let prefix = shared_config.state_prefixes.get(state);
+    if !prefix.is_empty() {
+        let prefix = markup.escape(prefix);
+        let text = &mut parts[0].full_text;
+        text.insert_str(text.len() - text.trim_start().len(), &prefix);
+    }
+    let short_parts_start = parts.len();
+
+    template.full_text = markup.hidden_text().into();
+    parts.extend(short.into_iter().map(|w| {
+        let mut data = template.clone();
+        data.short_text = markup.text(&w);
+        if let Some(i) = &w.metadata.instance {
+            data.instance.push_str(i);
+        }
+        data
+    }));
+
+    if shared_config.state_prefixes.short_text_state
+        && state != State::Idle
+        && parts.len() > short_parts_start
+    {
+        let text = &mut parts.last_mut().unwrap().short_text;
+        let name = markup.escape(shared_config.localization.state(state));
+        text.insert_str(text.trim_end().len(), &format!(" {name}"));
     }
+
+    parts
 }
 
 /// State of the widget. Affects the theming.
 #[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, SmartDefault)]
 pub enum State {
     #[default]
+    #[serde(alias = "idle")]
     Idle,
+    #[serde(alias = "info")]
     Info,
+    #[serde(alias = "good")]
     Good,
+    #[serde(alias = "warning")]
     Warning,
+    #[serde(alias = "critical")]
     Critical,
 }
 
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Info => "info",
+            Self::Good => "good",
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+/// How i3bar interprets the text of a block
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Markup {
+    /// Literal text in formats and icons may contain pango markup, and values are escaped
+    #[default]
+    Pango,
+    /// All text is shown as is. Markup in formats and icons is removed.
+    None,
+}
+
+impl Markup {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pango => "pango",
+            Self::None => "none",
+        }
+    }
+
+    /// A text which is not empty, but shows nothing. It hides the full text of a segment in
+    /// short mode, or its short text in full mode.
+    pub fn hidden_text(self) -> &'static str {
+        match self {
+            Self::Pango => "<span/>",
+            // A zero width space
+            Self::None => "\u{200b}",
+        }
+    }
+
+    /// Whether `text` is the [`Markup::hidden_text`] of any markup
+    pub fn is_hidden_text(text: &str) -> bool {
+        [Self::Pango, Self::None]
+            .iter()
+            .any(|markup| markup.hidden_text() == text)
+    }
+
+    /// The text of a rendered fragment, which has escaped values
+    fn text(self, fragment: &Fragment) -> String {
+        match self {
+            Self::Pango => fragment.formated_text(),
+            Self::None => strip_pango(&fragment.text),
+        }
+    }
+
+    fn escape(self, text: &str) -> String {
+        match self {
+            Self::Pango => text.chars().collect_pango_escaped(),
+            Self::None => text.into(),
+        }
+    }
+}
+
+/// Text-only indication of the widget's state, which does not rely on colors
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct StatePrefixes {
+    pub idle: String,
+    pub info: String,
+    pub good: String,
+    pub warning: String,
+    pub critical: String,
+    /// Append the state's name to the short text
+    pub short_text_state: bool,
+}
+
+impl StatePrefixes {
+    pub fn get(&self, state: State) -> &str {
+        match state {
+            State::Idle => &self.idle,
+            State::Info => &self.info,
+            State::Good => &self.good,
+            State::Warning => &self.warning,
+            State::Critical => &self.critical,
+        }
+    }
+}
+
 /// The source of text for widget
 #[derive(Debug, Clone, SmartDefault)]
 enum Source {
@@ -138,14 +514,530 @@ enum Source {
     Text(String),
     /// A format template
     Format(Format, Option<Values>),
+    /// A format template rendered once for each part
+    Parts(Format, Vec<WidgetPart>, WidgetsDiff),
+    /// Empty space
+    Spacer(Spacer),
 }
 
 impl Source {
-    fn render(&self, config: &SharedConfig) -> Result<(Vec<Fragment>, Vec<Fragment>)> {
+    fn render(
+        &self,
+        state: State,
+        config: &SharedConfig,
+    ) -> Result<(Vec<Fragment>, Vec<Fragment>)> {
         match self {
             Self::Text(text) => Ok((vec![text.clone().into()], vec![])),
-            Self::Format(format, Some(values)) => format.render(values, config),
-            Self::None | Self::Format(_, None) => Ok((vec![], vec![])),
+            Self::Format(format, Some(values)) => {
+                format.render(&icons_in_state(values, state), config)
+            }
+            Self::None | Self::Format(_, None) | Self::Parts(..) | Self::Spacer(_) => {
+                Ok((vec![], vec![]))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatting::config::Config as FormatConfig;
+    use crate::formatting::marquee::Marquee;
+    use crate::icons::IconPosition;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const STATES: [State; 5] = [
+        State::Idle,
+        State::Info,
+        State::Good,
+        State::Warning,
+        State::Critical,
+    ];
+
+    fn config(prefixes: StatePrefixes) -> SharedConfig {
+        SharedConfig {
+            state_prefixes: Arc::new(prefixes),
+            ..Default::default()
+        }
+    }
+
+    fn widget(state: State) -> Widget {
+        let format = FormatConfig::default()
+            .with_defaults(" $text ", " $short ")
+            .unwrap();
+        let mut widget = Widget::new().with_format(format).with_state(state);
+        widget.set_values(map! {
+            "text" => Value::text("full".into()),
+            "short" => Value::text("short".into()),
+        });
+        widget
+    }
+
+    fn prefixes() -> StatePrefixes {
+        StatePrefixes {
+            idle: String::new(),
+            info: "i ".into(),
+            good: "+ ".into(),
+            warning: "! ".into(),
+            critical: "!! ".into(),
+            short_text_state: false,
+        }
+    }
+
+    #[test]
+    fn no_prefixes() {
+        let config = config(StatePrefixes::default());
+        for state in STATES {
+            let data = widget(state).get_data(&config, 0).unwrap();
+            assert_eq!(data[0].full_text, " full ");
+            assert_eq!(data[1].short_text, " short ");
+        }
+    }
+
+    #[test]
+    fn parts() {
+        let config = config(prefixes());
+        let mut widget = widget(State::Idle);
+        let part = |name: &str, state, text: &str| WidgetPart {
+            name: name.into(),
+            state,
+            values: map! {
+                "text" => Value::text(text.into()),
+                "short" => Value::text(String::new()),
+            },
+        };
+        widget.set_parts(vec![
+            part("/", State::Idle, "root"),
+            part("/home", State::Critical, "home"),
+        ]);
+        let data = widget.get_data(&config, 3).unwrap();
+        let full: Vec<_> = data
+            .iter()
+            .filter(|d| d.short_text.is_empty() || Markup::is_hidden_text(&d.short_text))
+            .map(|d| (d.instance.as_str(), d.full_text.as_str()))
+            .collect();
+        assert_eq!(full, [("3:/", " root "), ("3:/home", " !! home ")]);
+
+        // Values replace the parts
+        widget.set_values(map! {
+            "text" => Value::text("full".into()),
+            "short" => Value::text("short".into()),
+        });
+        assert_eq!(widget.get_data(&config, 3).unwrap()[0].instance, "3:");
+    }
+
+    #[test]
+    fn with_prefixes() {
+        let config = config(prefixes());
+        for state in STATES {
+            let data = widget(state).get_data(&config, 0).unwrap();
+            let expected = format!(" {}full ", config.state_prefixes.get(state));
+            assert_eq!(data[0].full_text, expected);
+            assert_eq!(data[1].short_text, " short ");
+        }
+    }
+
+    #[test]
+    fn short_text_state() {
+        let config = config(StatePrefixes {
+            short_text_state: true,
+            ..prefixes()
+        });
+        for state in STATES {
+            let data = widget(state).get_data(&config, 0).unwrap();
+            let expected = match state {
+                State::Idle => " short ".to_string(),
+                _ => format!(" short {} ", state.name()),
+            };
+            assert_eq!(data[1].short_text, expected);
+        }
+    }
+
+    #[test]
+    fn prefixes_with_markup() {
+        let config = config(StatePrefixes {
+            warning: "<!> ".into(),
+            ..Default::default()
+        });
+        let mut widget = Widget::new()
+            .with_format(FormatConfig::default().with_default(" $text ").unwrap())
+            .with_state(State::Warning);
+        widget.set_values(map! {
+            "text" => Value::text("a&b".into()).italic(true),
+        });
+        let full_text =
+            |data: Vec<I3BarBlock>| data.into_iter().map(|d| d.full_text).collect::<String>();
+
+        let data = widget.get_data(&config, 0).unwrap();
+        assert_eq!(full_text(data), " &lt;!&gt; <i>a&amp;b</i> ");
+
+        let data = widget
+            .with_state(State::Critical)
+            .get_data(&config, 0)
+            .unwrap();
+        assert_eq!(full_text(data), " <i>a&amp;b</i> ");
+    }
+
+    #[test]
+    fn markup() {
+        let mut config = config(StatePrefixes {
+            warning: "<!> ".into(),
+            short_text_state: true,
+            ..Default::default()
+        });
+        Arc::make_mut(&mut config.localization)
+            .apply_overrides(toml::from_str(r#"warning = "w&w""#).unwrap());
+        let mut widget = Widget::new()
+            .with_format(
+                FormatConfig::default()
+                    .with_defaults(" <b>$text</b> $text.raw() ", " $text ")
+                    .unwrap(),
+            )
+            .with_state(State::Warning);
+        widget.set_values(map! { "text" => Value::text("a&b".into()) });
+        let texts = |config: &SharedConfig| -> Vec<(String, String, Option<String>)> {
+            widget
+                .get_data(config, 0)
+                .unwrap()
+                .into_iter()
+                .map(|d| (d.full_text, d.short_text, d.markup))
+                .collect()
+        };
+        let pango = Some("pango".to_string());
+        assert_eq!(
+            texts(&config),
+            [
+                (
+                    " &lt;!&gt; <b>a&amp;b</b> a&b ".into(),
+                    "<span/>".into(),
+                    pango.clone()
+                ),
+                ("<span/>".into(), " a&amp;b w&amp;w ".into(), pango),
+            ]
+        );
+
+        config.markup = Markup::None;
+        let none = Some("none".to_string());
+        assert_eq!(
+            texts(&config),
+            [
+                (" <!> a&b a&b ".into(), "\u{200b}".into(), none.clone()),
+                ("\u{200b}".into(), " a&b w&w ".into(), none),
+            ]
+        );
+    }
+
+    #[test]
+    fn stateful_icons() {
+        let mut config = SharedConfig::default();
+        Arc::make_mut(&mut config.icons).apply_overrides(
+            toml::from_str(r#"bat_10 = { idle = "B", critical = "B!" }"#).unwrap(),
+        );
+        config.icons_format = Arc::new("{icon}".into());
+        let icon = config.get_stateful_icon("bat_10").unwrap();
+        let mut widget =
+            Widget::new().with_format(FormatConfig::default().with_default("$icon").unwrap());
+        widget.set_values(map! { "icon" => Value::icon(icon.clone()) });
+        let full_text = |widget: &Widget| widget.get_data(&config, 0).unwrap()[0].full_text.clone();
+
+        assert_eq!(full_text(&widget), "B");
+        widget.state = State::Critical;
+        assert_eq!(full_text(&widget), "B!");
+        // Falls back to the idle icon
+        widget.state = State::Warning;
+        assert_eq!(full_text(&widget), "B");
+
+        // Each part uses its own state
+        widget.set_parts(vec![WidgetPart {
+            name: "a".into(),
+            state: State::Critical,
+            values: map! { "icon" => Value::icon(icon) },
+        }]);
+        assert_eq!(full_text(&widget), "B!");
+    }
+
+    #[test]
+    fn icon_position() {
+        let mut config = SharedConfig::default();
+        Arc::make_mut(&mut config.icons).apply_overrides(toml::from_str(r#"ping = "P""#).unwrap());
+        let render = |config: &SharedConfig, format: &str, text: &str| -> String {
+            let mut widget =
+                Widget::new().with_format(FormatConfig::default().with_default(format).unwrap());
+            widget.set_values(map! {
+                "icon" => Value::icon(String::from("I")),
+                "text" => Value::text(text.into()),
+            });
+            widget
+                .get_data(config, 0)
+                .unwrap()
+                .into_iter()
+                .map(|d| d.full_text)
+                .collect()
+        };
+
+        let cases = [
+            (IconPosition::Left, " I t ", " I  ", " P t I "),
+            (IconPosition::Right, " t I ", " I  ", " t P I "),
+            (IconPosition::Hidden, " t ", "  ", " t "),
+        ];
+        for (position, with_text, without_text, nested) in cases {
+            config.icon_position = position;
+            assert_eq!(render(&config, " $icon $text ", "t"), with_text);
+            assert_eq!(render(&config, " $icon $text ", ""), without_text);
+            // Icons of nested groups are moved after the whole text
+            assert_eq!(render(&config, " ^icon_ping $text{ $icon|} ", "t"), nested);
+        }
+
+        // An icon after the text keeps its place
+        config.icon_position = IconPosition::Right;
+        assert_eq!(render(&config, " $text $icon ", "t"), " t I ");
+        config.icon_position = IconPosition::Hidden;
+        assert_eq!(render(&config, " $text $icon ", "t"), " t ");
+
+        // Only icons
+        config.icon_position = IconPosition::Right;
+        assert_eq!(render(&config, "$icon", ""), "I");
+        config.icon_position = IconPosition::Hidden;
+        assert_eq!(render(&config, "$icon", ""), "");
+    }
+
+    #[test]
+    fn icon_data() {
+        let config = SharedConfig::default();
+        let mut widget = Widget::new().with_format(
+            FormatConfig::default()
+                .with_default(" $icon $text{ $missing|} ")
+                .unwrap(),
+        );
+        widget.set_values(map! {
+            "icon" => Value::icon(String::from(" I ")),
+            "text" => Value::text("text".into()),
+        });
+        let texts = |widget: &Widget| -> Vec<(String, String)> {
+            widget
+                .get_icon_data(&config, 2)
+                .into_iter()
+                .map(|d| (d.full_text, d.instance))
+                .collect()
+        };
+        assert_eq!(texts(&widget), [(" I ".into(), "2:".into())]);
+
+        widget.set_parts(vec![
+            WidgetPart {
+                name: "a".into(),
+                state: State::Idle,
+                values: map! { "text" => Value::text("a".into()) },
+            },
+            WidgetPart {
+                name: "b".into(),
+                state: State::Idle,
+                values: map! { "icon" => Value::icon(String::from("J")), "text" => Value::text("b".into()) },
+            },
+        ]);
+        // Parts without icons are left out
+        assert_eq!(texts(&widget), [(" J ".into(), "2:b".into())]);
+        assert!(Widget::new()
+            .with_text("text".into())
+            .get_icon_data(&config, 2)
+            .is_empty());
+    }
+
+    #[test]
+    fn marquee() {
+        let config = SharedConfig {
+            marquee: Some(Arc::new(Marquee::new(4, Some(Duration::from_secs(60))))),
+            ..Default::default()
+        };
+        let mut widget = Widget::new().with_format(
+            FormatConfig::default()
+                .with_default(" $title $title.str(max_w:2) ")
+                .unwrap(),
+        );
+        widget.set_values(map! { "title" => Value::text("a<b>cdef".into()) });
+        let data = widget.get_data(&config, 3).unwrap();
+        // Only text values without a formatter are limited, before they are escaped
+        assert_eq!(data[0].full_text, " a&lt;b&gt; a&lt; ");
+
+        // Parts keep their instances, so that clicks on them can still be told apart
+        widget.set_parts(vec![
+            WidgetPart {
+                name: "x".into(),
+                state: State::Idle,
+                values: map! { "title" => Value::text("abcdef".into()) },
+            },
+            WidgetPart {
+                name: "y".into(),
+                state: State::Idle,
+                values: map! { "title" => Value::text("ab".into()) },
+            },
+        ]);
+        let data = widget.get_data(&config, 3).unwrap();
+        let texts: Vec<_> = data
+            .iter()
+            .map(|d| (d.full_text.as_str(), d.instance.as_str()))
+            .collect();
+        assert_eq!(texts, [(" abcd ab ", "3:x"), (" ab ab ", "3:y")]);
+    }
+
+    #[test]
+    fn short_text_json() {
+        #[derive(serde::Deserialize)]
+        struct BlockConfig {
+            format: FormatConfig,
+        }
+        let json = |toml: &str| {
+            let config: BlockConfig = toml::from_str(toml).unwrap();
+            let mut widget =
+                Widget::new().with_format(config.format.with_default(" $text ").unwrap());
+            widget.set_values(map! {
+                "text" => Value::text("full".into()),
+                "short" => Value::text("short".into()),
+            });
+            let data = widget.get_data(&SharedConfig::default(), 0).unwrap();
+            serde_json::to_value(&data).unwrap()
+        };
+
+        // The full and the short text are separate segments, each hiding the other text
+        let both = json(r#"format = { full = " $text ", short = " $short " }"#);
+        assert_eq!(both[0]["full_text"], " full ");
+        assert_eq!(both[0]["short_text"], "<span/>");
+        assert_eq!(both[1]["full_text"], "<span/>");
+        assert_eq!(both[1]["short_text"], " short ");
+        // A plain string is the full text, and i3bar falls back to it
+        let plain = json(r#"format = " $text $short ""#);
+        assert_eq!(plain.as_array().unwrap().len(), 1);
+        assert_eq!(plain[0]["full_text"], " full short ");
+        assert!(plain[0].get("short_text").is_none());
+    }
+
+    struct TextsView {
+        texts: Vec<&'static str>,
+        diff: WidgetsDiff,
+    }
+
+    impl PartsView for TextsView {
+        fn view(&self) -> Vec<WidgetPart> {
+            self.texts
+                .iter()
+                .enumerate()
+                .map(|(index, text)| WidgetPart {
+                    name: index.to_string(),
+                    state: State::Idle,
+                    values: map! { "text" => Value::text(text.to_string()) },
+                })
+                .collect()
+        }
+
+        fn view_diff(&self) -> WidgetsDiff {
+            self.diff.clone()
+        }
+    }
+
+    #[test]
+    fn parts_cache() {
+        let config = SharedConfig::default();
+        let mut widget =
+            Widget::new().with_format(FormatConfig::default().with_default(" $text ").unwrap());
+        let mut cache = PartsCache::default();
+        let render = |widget: &Widget, cache: &mut PartsCache| {
+            cache.changed(widget.parts_diff().unwrap());
+            cache
+                .get_data(widget, &config, 0)
+                .unwrap()
+                .into_iter()
+                .map(|segment| segment.full_text)
+                .collect::<Vec<_>>()
+        };
+
+        let mut view = TextsView {
+            texts: vec!["a", "b"],
+            diff: WidgetsDiff::All,
+        };
+        widget.set_view(&view);
+        assert_eq!(render(&widget, &mut cache), [" a ", " b "]);
+
+        // Parts which did not change are not rendered again, so changing them behind the cache's
+        // back does not show
+        view.texts = vec!["c", "d"];
+        view.diff = WidgetsDiff::Changed(vec![0]);
+        widget.set_view(&view);
+        assert_eq!(render(&widget, &mut cache), [" c ", " b "]);
+        view.diff = WidgetsDiff::Changed(vec![]);
+        widget.set_view(&view);
+        assert_eq!(render(&widget, &mut cache), [" c ", " b "]);
+
+        // A different number of parts renders all of them
+        view.texts = vec!["e", "f", "g"];
+        widget.set_view(&view);
+        assert_eq!(render(&widget, &mut cache), [" e ", " f ", " g "]);
+
+        // So does a widget which does not report its changes
+        view.texts = vec!["h", "i", "j"];
+        widget.set_parts(view.view());
+        assert_eq!(render(&widget, &mut cache), [" h ", " i ", " j "]);
+
+        // So does a different theme
+        view.texts = vec!["k", "l", "m"];
+        widget.set_view(&view);
+        let config = SharedConfig::default();
+        cache.changed(widget.parts_diff().unwrap());
+        let texts: Vec<_> = cache
+            .get_data(&widget, &config, 0)
+            .unwrap()
+            .into_iter()
+            .map(|segment| segment.full_text)
+            .collect();
+        assert_eq!(texts, [" k ", " l ", " m "]);
+    }
+
+    /// Render the `memory` block's default format like one update does. Run with
+    /// `cargo test --release render_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn render_benchmark() {
+        const ITERATIONS: u32 = 100_000;
+        let config = SharedConfig::default();
+        let format = FormatConfig::default()
+            .with_default(
+                " $icon $mem_avail.eng(prefix:M)/$mem_total.eng(prefix:M)($mem_total_used_percents.eng(w:2)) ",
+            )
+            .unwrap();
+        let mut widget = Widget::new().with_format(format);
+
+        let start = std::time::Instant::now();
+        for i in 0..ITERATIONS {
+            let used = (i % 1000) as f64 * 1e6;
+            let total = 16e9;
+            widget.set_values(map! {
+                "icon" => Value::icon(config.get_icon("memory_mem").unwrap()),
+                "icon_swap" => Value::icon(config.get_icon("memory_swap").unwrap()),
+                "mem_total" => Value::bytes(total),
+                "mem_free" => Value::bytes(total - used),
+                "mem_free_percents" => Value::percents((total - used) / total * 100.),
+                "mem_total_used" => Value::bytes(used),
+                "mem_total_used_percents" => Value::percents(used / total * 100.),
+                "mem_used" => Value::bytes(used),
+                "mem_used_percents" => Value::percents(used / total * 100.),
+                "mem_avail" => Value::bytes(total - used),
+                "mem_avail_percents" => Value::percents((total - used) / total * 100.),
+                "swap_total" => Value::bytes(0.),
+                "swap_free" => Value::bytes(0.),
+                "swap_free_percents" => Value::percents(0.),
+                "swap_used" => Value::bytes(0.),
+                "swap_used_percents" => Value::percents(0.),
+                "buffers" => Value::bytes(0.),
+                "buffers_percent" => Value::percents(0.),
+                "cached" => Value::bytes(0.),
+                "cached_percent" => Value::percents(0.),
+            });
+            let data = widget.get_data(&config, 0).unwrap();
+            assert!(!data.is_empty());
         }
+        let elapsed = start.elapsed();
+        println!(
+            "{ITERATIONS} renders in {elapsed:?} ({:?} per render)",
+            elapsed / ITERATIONS
+        );
     }
 }
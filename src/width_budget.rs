@@ -0,0 +1,343 @@
+//! Fitting the bar into a limited width
+//!
+//! i3bar cuts off a bar which is too wide for the screen. With the top-level `width_budget`, the
+//! bar shrinks blocks itself whenever its text would be wider than that many characters. Blocks
+//! shrink in three stages, until the bar fits: first blocks show their short text instead of
+//! their full text, then only their icons, and finally they are hidden. Blocks without a short
+//! text or without icons skip that stage.
+//!
+//! Within each stage, blocks with a higher `shrink_priority` shrink first. Among blocks with the
+//! same priority, those which come first in the configuration file shrink first. Blocks grow back
+//! once there is space again.
+//!
+//! The width of the bar is estimated by counting characters, including those of separators. This
+//! is exact for monospace fonts only; leave some room with proportional fonts or icon fonts.
+//!
+//! Near the limit, a block could shrink and grow back with every update. To avoid this, blocks
+//! only grow back once the bar would still be at least four characters narrower than the budget.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::config::SharedConfig;
+use crate::protocol::i3bar_block::I3BarBlock;
+use crate::protocol::xroot::plain_text;
+use crate::themes::separator::Separator;
+use crate::widget::Markup;
+use crate::RenderedBlock;
+
+/// How much narrower than the budget the bar must get before blocks grow back
+const HYSTERESIS: usize = 4;
+
+/// How far a block is shrunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    Full,
+    Short,
+    Icons,
+    Hidden,
+}
+
+const SHRINKING_STAGES: [Stage; 3] = [Stage::Short, Stage::Icons, Stage::Hidden];
+
+/// The width of a block in each stage. A stage which the block does not have is as wide as the
+/// one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Widths {
+    priority: i32,
+    full: usize,
+    short: usize,
+    icons: usize,
+}
+
+impl Widths {
+    fn get(&self, stage: Stage) -> usize {
+        match stage {
+            Stage::Full => self.full,
+            Stage::Short => self.short,
+            Stage::Icons => self.icons,
+            Stage::Hidden => 0,
+        }
+    }
+}
+
+/// Keeps track of how far each block is shrunk
+#[derive(Debug)]
+pub struct WidthBudget {
+    width: usize,
+    stages: RefCell<Vec<Stage>>,
+}
+
+impl WidthBudget {
+    pub fn new(width: usize) -> Self {
+        Self {
+            width,
+            stages: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Shrink `blocks`, given in order of their IDs, so that the bar fits into the budget
+    pub fn fit<'a>(
+        &self,
+        blocks: Vec<Cow<'a, RenderedBlock>>,
+        config: &SharedConfig,
+    ) -> Vec<Cow<'a, RenderedBlock>> {
+        let separator = match &config.theme.separator {
+            Separator::Custom(separator) => plain_text(&I3BarBlock {
+                full_text: separator.clone(),
+                ..Default::default()
+            })
+            .chars()
+            .count(),
+            // About as wide as a character
+            Separator::Native => 1,
+        };
+        let widths: Vec<Widths> = blocks
+            .iter()
+            .map(|block| {
+                let separated = |width| match width {
+                    0 => 0,
+                    _ if block.merge_with_next || block.spacer => width,
+                    _ => width + separator,
+                };
+                let full = separated(text_width(&block.segments));
+                let short = short_segments(&block.segments)
+                    .map_or(full, |short| separated(text_width(&short)).min(full));
+                let icons = if block.icon_segments.is_empty() {
+                    short
+                } else {
+                    separated(text_width(&block.icon_segments)).min(short)
+                };
+                Widths {
+                    priority: block.shrink_priority,
+                    full,
+                    short,
+                    icons,
+                }
+            })
+            .collect();
+
+        let mut stages = self.stages.borrow_mut();
+        *stages = next_stages(&stages, &widths, self.width);
+        blocks
+            .into_iter()
+            .zip(stages.iter())
+            .map(|(block, stage)| shrink(block, *stage))
+            .collect()
+    }
+}
+
+/// The number of characters which `segments` show
+fn text_width(segments: &[I3BarBlock]) -> usize {
+    segments
+        .iter()
+        .map(|segment| plain_text(segment).chars().count())
+        .sum()
+}
+
+/// The segments of a block with a short text, showing the short text as their full text
+fn short_segments(segments: &[I3BarBlock]) -> Option<Vec<I3BarBlock>> {
+    let short: Vec<I3BarBlock> = segments
+        .iter()
+        .filter(|segment| {
+            !segment.short_text.is_empty() && !Markup::is_hidden_text(&segment.short_text)
+        })
+        .map(|segment| I3BarBlock {
+            full_text: segment.short_text.clone(),
+            short_text: String::new(),
+            ..segment.clone()
+        })
+        .collect();
+    (!short.is_empty()).then_some(short)
+}
+
+fn shrink(block: Cow<'_, RenderedBlock>, stage: Stage) -> Cow<'_, RenderedBlock> {
+    let segments = match stage {
+        Stage::Full => return block,
+        Stage::Short => short_segments(&block.segments),
+        Stage::Icons if block.icon_segments.is_empty() => short_segments(&block.segments),
+        Stage::Icons => Some(block.icon_segments.clone()),
+        Stage::Hidden => Some(Vec::new()),
+    };
+    match segments {
+        Some(segments) => Cow::Owned(RenderedBlock {
+            segments,
+            ..block.into_owned()
+        }),
+        None => block,
+    }
+}
+
+/// The stages which make the blocks fit into `budget`, from scratch
+fn solve(widths: &[Widths], budget: usize) -> Vec<Stage> {
+    let mut stages = vec![Stage::Full; widths.len()];
+    let mut total: usize = widths.iter().map(|w| w.full).sum();
+    let mut order: Vec<usize> = (0..widths.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(widths[i].priority));
+    for stage in SHRINKING_STAGES {
+        for &i in &order {
+            if total <= budget {
+                return stages;
+            }
+            let (old, new) = (widths[i].get(stages[i]), widths[i].get(stage));
+            if new < old {
+                total -= old - new;
+                stages[i] = stage;
+            }
+        }
+    }
+    stages
+}
+
+/// The stages of the next frame, given those of the last one
+fn next_stages(last: &[Stage], widths: &[Widths], budget: usize) -> Vec<Stage> {
+    let total: usize = last.iter().zip(widths).map(|(s, w)| w.get(*s)).sum();
+    if last.len() != widths.len() || total > budget {
+        return solve(widths, budget);
+    }
+    // Only grow if there is some room left afterwards
+    let grown = solve(widths, budget.saturating_sub(HYSTERESIS));
+    let shrinking = |stages: &[Stage]| stages.iter().map(|s| *s as usize).sum::<usize>();
+    if shrinking(&grown) < shrinking(last) {
+        grown
+    } else {
+        last.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Stage::*;
+
+    fn widths(priority: i32, full: usize, short: usize, icons: usize) -> Widths {
+        Widths {
+            priority,
+            full,
+            short,
+            icons,
+        }
+    }
+
+    #[test]
+    fn stages() {
+        let frame = [
+            widths(0, 20, 10, 3),
+            widths(1, 30, 30, 5),
+            widths(0, 10, 5, 5),
+            widths(-1, 15, 8, 2),
+        ];
+        // 75 characters in total
+        assert_eq!(solve(&frame, 75), [Full, Full, Full, Full]);
+        // The first block of the highest priority shrinks first, but it has no short text
+        assert_eq!(solve(&frame, 74), [Short, Full, Full, Full]);
+        assert_eq!(solve(&frame, 60), [Short, Full, Short, Full]);
+        assert_eq!(solve(&frame, 55), [Short, Full, Short, Short]);
+        // All short texts are shown before the first block is reduced to its icons
+        assert_eq!(solve(&frame, 52), [Short, Icons, Short, Short]);
+        assert_eq!(solve(&frame, 21), [Icons, Icons, Short, Short]);
+        // The third block has no icons
+        assert_eq!(solve(&frame, 20), [Icons, Icons, Short, Icons]);
+        // Then blocks are hidden in the same order
+        assert_eq!(solve(&frame, 12), [Icons, Hidden, Short, Icons]);
+        assert_eq!(solve(&frame, 7), [Hidden, Hidden, Short, Icons]);
+        assert_eq!(solve(&frame, 0), [Hidden, Hidden, Hidden, Hidden]);
+    }
+
+    #[test]
+    fn hysteresis() {
+        let frame = |first| [widths(1, first, 4, 4), widths(0, 50, 50, 50)];
+        let mut stages = next_stages(&[], &frame(40), 90);
+        assert_eq!(stages, [Full, Full]);
+        stages = next_stages(&stages, &frame(41), 90);
+        assert_eq!(stages, [Short, Full]);
+        // The block does not grow back as long as it would barely fit
+        for width in [40, 37, 40, 38] {
+            stages = next_stages(&stages, &frame(width), 90);
+            assert_eq!(stages, [Short, Full], "width {width}");
+        }
+        stages = next_stages(&stages, &frame(36), 90);
+        assert_eq!(stages, [Full, Full]);
+        // But it stays grown while it fits
+        stages = next_stages(&stages, &frame(40), 90);
+        assert_eq!(stages, [Full, Full]);
+        // The number of blocks changed, e.g. with a reload
+        assert_eq!(next_stages(&stages, &frame(40)[..1], 20), [Short]);
+    }
+
+    fn segment(full_text: &str, short_text: &str, instance: &str) -> I3BarBlock {
+        I3BarBlock {
+            full_text: full_text.into(),
+            short_text: short_text.into(),
+            instance: instance.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fit() {
+        let block = |segments, icon_segments, shrink_priority| RenderedBlock {
+            segments,
+            icon_segments,
+            merge_with_next: false,
+            spacer: false,
+            shrink_priority,
+        };
+        let blocks = [
+            block(
+                vec![
+                    segment(" <b>long</b> text ", "<span/>", "0:"),
+                    segment("<span/>", " short ", "0:"),
+                ],
+                vec![segment(" X ", "", "0:")],
+                1,
+            ),
+            block(
+                vec![segment(" a ", "", "1:a"), segment(" b ", "", "1:b")],
+                vec![segment(" A ", "", "1:a")],
+                0,
+            ),
+        ];
+        let config = SharedConfig::default();
+        let texts = |budget: &WidthBudget| -> Vec<Vec<(String, String)>> {
+            let frame = blocks.iter().map(Cow::Borrowed).collect();
+            budget
+                .fit(frame, &config)
+                .iter()
+                .map(|block| {
+                    block
+                        .segments
+                        .iter()
+                        .map(|s| (s.full_text.clone(), s.instance.clone()))
+                        .collect()
+                })
+                .collect()
+        };
+        let pairs = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(text, instance)| (text.to_string(), instance.to_string()))
+                .collect()
+        };
+
+        // 11 + 6 characters and two separators
+        assert_eq!(texts(&WidthBudget::new(19))[0].len(), 2);
+        // The short text of the first block
+        assert_eq!(
+            texts(&WidthBudget::new(18)),
+            [
+                pairs(&[(" short ", "0:")]),
+                pairs(&[(" a ", "1:a"), (" b ", "1:b")])
+            ]
+        );
+        // The second block keeps the instance of the part with the icon
+        assert_eq!(
+            texts(&WidthBudget::new(8)),
+            [pairs(&[(" X ", "0:")]), pairs(&[(" A ", "1:a")])]
+        );
+        assert_eq!(
+            texts(&WidthBudget::new(4)),
+            [pairs(&[]), pairs(&[(" A ", "1:a")])]
+        );
+    }
+}
@@ -1,6 +1,7 @@
-use crate::errors::{Result, ResultExt};
+use crate::errors::{Error, OptionExt, Result, ResultExt};
 use serde::de::{self, Deserialize, Deserializer};
 use std::borrow::Cow;
+use std::str::FromStr;
 use std::time::Duration;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,10 +23,6 @@ impl<const ALLOW_ONCE: bool> Seconds<ALLOW_ONCE> {
         timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
         timer
     }
-
-    pub fn seconds(self) -> u64 {
-        self.0.as_secs()
-    }
 }
 
 impl<'de, const ALLOW_ONCE: bool> Deserialize<'de> for Seconds<ALLOW_ONCE> {
@@ -119,3 +116,214 @@ impl ShellString {
         shellexpand::full(&self.0).error("Failed to expand string")
     }
 }
+
+/// A threshold which may be given either as a bare number or as a humanized string with a unit,
+/// e.g. `"80%"`, `"500MiB"`, `"2.5GB"` or `"1500ms"`. Units are case-insensitive.
+///
+/// The meaning of a bare number depends on the block, which keeps old configs working.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    Number(f64),
+    Percents(f64),
+    Bytes(f64),
+    Seconds(f64),
+}
+
+impl Threshold {
+    /// The value in the canonical unit (percents, bytes or seconds)
+    pub fn value(self) -> f64 {
+        match self {
+            Self::Number(x) | Self::Percents(x) | Self::Bytes(x) | Self::Seconds(x) => x,
+        }
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let err = || format!("'{s}' is not a valid threshold");
+        let (number, unit) = s.split_at(
+            s.find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+')))
+                .unwrap_or(s.len()),
+        );
+        let number: f64 = number.parse().or_error(err)?;
+        let unit = unit.trim().to_lowercase();
+        Ok(match unit.as_str() {
+            "" => Self::Number(number),
+            "%" => Self::Percents(number),
+            "ms" => Self::Seconds(number * 1e-3),
+            "s" => Self::Seconds(number),
+            "min" => Self::Seconds(number * 60.),
+            "h" => Self::Seconds(number * 3600.),
+            unit => {
+                let multiplier = match unit.strip_suffix('b').or_error(err)? {
+                    "" => 1.,
+                    "k" => 1e3,
+                    "ki" => 1024.,
+                    "m" => 1e6,
+                    "mi" => 1024. * 1024.,
+                    "g" => 1e9,
+                    "gi" => 1024. * 1024. * 1024.,
+                    "t" => 1e12,
+                    "ti" => 1024. * 1024. * 1024. * 1024.,
+                    _ => return Err(Error::new(err())),
+                };
+                Self::Bytes(number * multiplier)
+            }
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Threshold {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ThresholdVisitor;
+
+        impl<'de> de::Visitor<'de> for ThresholdVisitor {
+            type Value = Threshold;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a number or a string such as \"80%\" or \"2GiB\"")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(Threshold::Number(v as f64))
+            }
+
+            fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(Threshold::Number(v))
+            }
+        }
+
+        deserializer.deserialize_any(ThresholdVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threshold_bare_numbers() {
+        assert_eq!("80".parse::<Threshold>().unwrap(), Threshold::Number(80.));
+        assert_eq!(
+            " 2.5 ".parse::<Threshold>().unwrap(),
+            Threshold::Number(2.5)
+        );
+        assert_eq!("-1".parse::<Threshold>().unwrap(), Threshold::Number(-1.));
+    }
+
+    #[test]
+    fn threshold_percents() {
+        assert_eq!(
+            "80%".parse::<Threshold>().unwrap(),
+            Threshold::Percents(80.)
+        );
+        assert_eq!(
+            "12.5 %".parse::<Threshold>().unwrap(),
+            Threshold::Percents(12.5)
+        );
+    }
+
+    #[test]
+    fn threshold_bytes() {
+        assert_eq!("100B".parse::<Threshold>().unwrap(), Threshold::Bytes(100.));
+        assert_eq!(
+            "2.5GB".parse::<Threshold>().unwrap(),
+            Threshold::Bytes(2.5e9)
+        );
+        assert_eq!("1KB".parse::<Threshold>().unwrap(), Threshold::Bytes(1e3));
+        assert_eq!("1TB".parse::<Threshold>().unwrap(), Threshold::Bytes(1e12));
+        assert_eq!(
+            "500MiB".parse::<Threshold>().unwrap(),
+            Threshold::Bytes(500. * 1024. * 1024.)
+        );
+        assert_eq!(
+            "2GiB".parse::<Threshold>().unwrap(),
+            Threshold::Bytes(2. * 1024. * 1024. * 1024.)
+        );
+        assert_eq!(
+            "1KiB".parse::<Threshold>().unwrap(),
+            Threshold::Bytes(1024.)
+        );
+        assert_eq!(
+            "1TiB".parse::<Threshold>().unwrap(),
+            Threshold::Bytes(1024f64.powi(4))
+        );
+    }
+
+    #[test]
+    fn threshold_case_insensitive() {
+        assert_eq!(
+            "2gib".parse::<Threshold>().unwrap(),
+            "2GiB".parse().unwrap()
+        );
+        assert_eq!(
+            "2GIB".parse::<Threshold>().unwrap(),
+            "2GiB".parse().unwrap()
+        );
+        assert_eq!("3kb".parse::<Threshold>().unwrap(), Threshold::Bytes(3e3));
+        assert_eq!(
+            "1500MS".parse::<Threshold>().unwrap(),
+            Threshold::Seconds(1.5)
+        );
+    }
+
+    #[test]
+    fn threshold_durations() {
+        assert_eq!(
+            "1500ms".parse::<Threshold>().unwrap(),
+            Threshold::Seconds(1.5)
+        );
+        assert_eq!("3s".parse::<Threshold>().unwrap(), Threshold::Seconds(3.));
+        assert_eq!(
+            "2min".parse::<Threshold>().unwrap(),
+            Threshold::Seconds(120.)
+        );
+        assert_eq!(
+            "1h".parse::<Threshold>().unwrap(),
+            Threshold::Seconds(3600.)
+        );
+    }
+
+    #[test]
+    fn threshold_invalid() {
+        assert!("".parse::<Threshold>().is_err());
+        assert!("GB".parse::<Threshold>().is_err());
+        assert!("10 parsecs".parse::<Threshold>().is_err());
+        assert!("10XB".parse::<Threshold>().is_err());
+        assert!("1.2.3%".parse::<Threshold>().is_err());
+    }
+
+    #[test]
+    fn threshold_deserialize() {
+        #[derive(serde::Deserialize)]
+        struct Config {
+            a: Threshold,
+            b: Threshold,
+            c: Threshold,
+        }
+        let config: Config = toml::from_str("a = 10\nb = 2.5\nc = \"2GiB\"").unwrap();
+        assert_eq!(config.a, Threshold::Number(10.));
+        assert_eq!(config.b, Threshold::Number(2.5));
+        assert_eq!(config.c, Threshold::Bytes(2. * 1024. * 1024. * 1024.));
+        assert!(toml::from_str::<Config>("a = 1\nb = 2\nc = \"2 apples\"").is_err());
+    }
+}